@@ -6,13 +6,10 @@ use std::{
     ffi::CStr,
     io::{self, Read, Write},
     mem::{self, MaybeUninit},
-    os::unix::io::RawFd,
+    os::unix::io::{AsRawFd, RawFd},
     ptr,
 };
 
-#[cfg(feature = "tokio-support")]
-use mio::{event::Evented, unix::EventedFd, Poll, PollOpt, Ready, Token};
-
 /// Represents a link-local address.
 /// At this time, it's not particularly useful.
 pub struct Addr {
@@ -261,30 +258,9 @@ impl Write for BoundSocket {
     }
 }
 
-#[cfg(feature = "tokio-support")]
-impl Evented for BoundSocket {
-    fn register(
-        &self,
-        poll: &Poll,
-        token: Token,
-        interest: Ready,
-        opts: PollOpt,
-    ) -> io::Result<()> {
-        EventedFd(&self.fd).register(poll, token, interest, opts)
-    }
-
-    fn reregister(
-        &self,
-        poll: &Poll,
-        token: Token,
-        interest: Ready,
-        opts: PollOpt,
-    ) -> io::Result<()> {
-        EventedFd(&self.fd).reregister(poll, token, interest, opts)
-    }
-
-    fn deregister(&self, poll: &Poll) -> io::Result<()> {
-        EventedFd(&self.fd).deregister(poll)
+impl AsRawFd for BoundSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
     }
 }
 
@@ -1,9 +1,9 @@
 use crate::sockets;
 use std::{ffi::CStr, io};
-use tokio::io::{AsyncReadExt, AsyncWriteExt, PollEvented};
+use tokio::io::unix::AsyncFd;
 
 pub struct AsyncBoundSocket {
-    sock: PollEvented<sockets::BoundSocket>,
+    sock: AsyncFd<sockets::BoundSocket>,
 }
 
 impl AsyncBoundSocket {
@@ -12,7 +12,7 @@ impl AsyncBoundSocket {
         sock.set_nonblocking(true)?;
         let sock = sock.bind(iface)?;
         Ok(Self {
-            sock: PollEvented::new(sock)?,
+            sock: AsyncFd::new(sock)?,
         })
     }
 
@@ -21,10 +21,20 @@ impl AsyncBoundSocket {
     }
 
     pub async fn send(&mut self, frame: &[u8]) -> io::Result<usize> {
-        self.sock.write(frame).await
+        loop {
+            let mut guard = self.sock.writable_mut().await?;
+            if let Ok(result) = guard.try_io(|sock| sock.get_mut().send(frame)) {
+                return result;
+            }
+        }
     }
 
     pub async fn recv(&mut self, frame: &mut [u8]) -> io::Result<usize> {
-        self.sock.read(frame).await
+        loop {
+            let mut guard = self.sock.readable_mut().await?;
+            if let Ok(result) = guard.try_io(|sock| sock.get_mut().recv(frame)) {
+                return result.map(|(len, _)| len);
+            }
+        }
     }
 }
@@ -13,7 +13,7 @@ fn layer2_loopback() {
     // If this takes more than a second to occur, something's definitely wrong.
     let timeout = Duration::from_secs(1);
 
-    let mut rt = runtime::Runtime::new().unwrap();
+    let rt = runtime::Runtime::new().unwrap();
 
     rt.block_on(async {
         let mut rng = rand::thread_rng();
@@ -21,7 +21,7 @@ fn layer2_loopback() {
 
         let mut side_a = afpacket::AsyncBoundSocket::from_interface(&iface_name).unwrap();
 
-        let (mut tx, mut rx) = mpsc::channel(1);
+        let (tx, mut rx) = mpsc::channel(1);
 
         let task_b = tokio::spawn(async move {
             let mut side_b = afpacket::AsyncBoundSocket::from_interface(&iface_name).unwrap();
@@ -68,8 +68,7 @@ impl route_rs_runtime::pipeline::Runner for Pipeline {
             .build_link();
         all_runnables.append(&mut runnables_6);
 
-        let mut rt = runtime::Builder::new()
-            .threaded_scheduler()
+        let rt = runtime::Builder::new_multi_thread()
             .enable_all()
             .build()
             .unwrap();
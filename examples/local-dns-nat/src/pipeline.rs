@@ -1,12 +1,12 @@
 use crate::interface::*;
 #[allow(unused_imports)]
 use crate::processors::*;
-use futures::lazy;
 #[allow(unused_imports)]
 use route_rs_runtime::link::*;
 #[allow(unused_imports)]
 use route_rs_runtime::pipeline::{InputChannelLink, OutputChannelLink};
 use smoltcp::wire::*;
+use tokio::runtime;
 
 pub struct Pipeline {}
 
@@ -18,6 +18,10 @@ impl route_rs_runtime::pipeline::Runner for Pipeline {
         _input_channel: crossbeam::Receiver<Self::Input>,
         _output_channel: crossbeam::Sender<Self::Output>,
     ) {
-        tokio::run(lazy(move || Ok(())));
+        let rt = runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {});
     }
 }
@@ -139,11 +139,11 @@ mod tests {
         let data_unknown: Vec<u8> = vec![
             0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0xff, 0xff,
         ];
-        let frame1 = EthernetFrame::from_buffer(data_v4, 0).unwrap();
-        let frame2 = EthernetFrame::from_buffer(data_v6, 0).unwrap();
-        let frame3 = EthernetFrame::from_buffer(data_unknown, 0).unwrap();
+        let frame1 = EthernetFrame::from_buffer(data_v4.into(), 0).unwrap();
+        let frame2 = EthernetFrame::from_buffer(data_v6.into(), 0).unwrap();
+        let frame3 = EthernetFrame::from_buffer(data_unknown.into(), 0).unwrap();
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let packets = vec![frame1.clone(), frame2.clone(), frame3.clone()];
 
@@ -171,12 +171,15 @@ mod tests {
             0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0x08, 00, 0x45, 0, 0, 20, 0, 0,
             0, 0, 64, 17, 0, 0, 192, 178, 128, 0, 10, 0, 0, 1,
         ];
-        let mut packet_interface0 = Ipv4Packet::from_buffer(data_v4.clone(), Some(0), 14).unwrap();
-        let mut packet_interface1 = Ipv4Packet::from_buffer(data_v4.clone(), Some(0), 14).unwrap();
-        let mut packet_interface2 = Ipv4Packet::from_buffer(data_v4.clone(), Some(0), 14).unwrap();
-        let mut packet_default = Ipv4Packet::from_buffer(data_v4, Some(0), 14).unwrap();
+        let mut packet_interface0 =
+            Ipv4Packet::from_buffer(data_v4.clone().into(), Some(0), 14).unwrap();
+        let mut packet_interface1 =
+            Ipv4Packet::from_buffer(data_v4.clone().into(), Some(0), 14).unwrap();
+        let mut packet_interface2 =
+            Ipv4Packet::from_buffer(data_v4.clone().into(), Some(0), 14).unwrap();
+        let mut packet_default = Ipv4Packet::from_buffer(data_v4.into(), Some(0), 14).unwrap();
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             packet_interface0.set_dest_addr(Ipv4Addr::new(0, 0, 0, 0));
             packet_interface1.set_dest_addr(Ipv4Addr::new(10, 0, 0, 14));
@@ -219,12 +222,15 @@ mod tests {
             0xad, 0xbe, 0xef, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 0xa, 0xb, 0xc,
             0xd,
         ];
-        let mut packet_interface0 = Ipv6Packet::from_buffer(data_v6.clone(), Some(0), 14).unwrap();
-        let mut packet_interface1 = Ipv6Packet::from_buffer(data_v6.clone(), Some(0), 14).unwrap();
-        let mut packet_interface2 = Ipv6Packet::from_buffer(data_v6.clone(), Some(0), 14).unwrap();
-        let mut packet_default = Ipv6Packet::from_buffer(data_v6, Some(0), 14).unwrap();
+        let mut packet_interface0 =
+            Ipv6Packet::from_buffer(data_v6.clone().into(), Some(0), 14).unwrap();
+        let mut packet_interface1 =
+            Ipv6Packet::from_buffer(data_v6.clone().into(), Some(0), 14).unwrap();
+        let mut packet_interface2 =
+            Ipv6Packet::from_buffer(data_v6.clone().into(), Some(0), 14).unwrap();
+        let mut packet_default = Ipv6Packet::from_buffer(data_v6.into(), Some(0), 14).unwrap();
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             packet_interface0.set_dest_addr(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0));
             packet_interface1.set_dest_addr(Ipv6Addr::new(0x2001, 0xdb8, 0xdead, 1, 2, 3, 4, 5));
@@ -78,10 +78,10 @@ mod tests {
             0, 0, 64, 17, 0, 0, 192, 178, 128, 0, 10, 0, 0, 1,
         ];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
-            let frame = EthernetFrame::from_buffer(data.clone(), 0).unwrap();
-            let frame_invalid_ip = EthernetFrame::from_buffer(data2, 0).unwrap();
+            let frame = EthernetFrame::from_buffer(data.clone().into(), 0).unwrap();
+            let frame_invalid_ip = EthernetFrame::from_buffer(data2.into(), 0).unwrap();
             let packets = vec![frame, frame_invalid_ip];
 
             let link = ProcessLink::new()
@@ -92,7 +92,7 @@ mod tests {
             run_link(link).await
         });
 
-        let test_packet = Ipv4Packet::from_buffer(data, Some(0), 14).unwrap();
+        let test_packet = Ipv4Packet::from_buffer(data.into(), Some(0), 14).unwrap();
         assert_eq!(results[0][0], test_packet);
         assert_eq!(results[0].len(), 1, "Error didn't drop second packet");
     }
@@ -104,9 +104,9 @@ mod tests {
             0, 0, 64, 17, 0, 0, 192, 178, 128, 0, 10, 0, 0, 1,
         ];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
-            let packet = Ipv4Packet::from_buffer(data.clone(), Some(0), 14).unwrap();
+            let packet = Ipv4Packet::from_buffer(data.clone().into(), Some(0), 14).unwrap();
             let packets = vec![packet];
 
             let link = ProcessLink::new()
@@ -117,7 +117,7 @@ mod tests {
             run_link(link).await
         });
 
-        let test_frame = EthernetFrame::from_buffer(data, 0).unwrap();
+        let test_frame = EthernetFrame::from_buffer(data.into(), 0).unwrap();
         assert_eq!(results[0][0], test_frame);
     }
 
@@ -136,10 +136,10 @@ mod tests {
             0xd,
         ];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
-            let frame = EthernetFrame::from_buffer(data.clone(), 0).unwrap();
-            let frame_invalid_ip = EthernetFrame::from_buffer(data2, 0).unwrap();
+            let frame = EthernetFrame::from_buffer(data.clone().into(), 0).unwrap();
+            let frame_invalid_ip = EthernetFrame::from_buffer(data2.into(), 0).unwrap();
             let packets = vec![frame, frame_invalid_ip];
 
             let link = ProcessLink::new()
@@ -150,7 +150,7 @@ mod tests {
             run_link(link).await
         });
 
-        let test_packet = Ipv6Packet::from_buffer(data, Some(0), 14).unwrap();
+        let test_packet = Ipv6Packet::from_buffer(data.into(), Some(0), 14).unwrap();
         assert_eq!(results[0][0], test_packet);
         assert_eq!(
             results[0].len(),
@@ -168,9 +168,9 @@ mod tests {
             0xd,
         ];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
-            let packet = Ipv6Packet::from_buffer(data.clone(), Some(0), 14).unwrap();
+            let packet = Ipv6Packet::from_buffer(data.clone().into(), Some(0), 14).unwrap();
             let packets = vec![packet];
 
             let link = ProcessLink::new()
@@ -181,7 +181,7 @@ mod tests {
             run_link(link).await
         });
 
-        let test_frame = EthernetFrame::from_buffer(data, 0).unwrap();
+        let test_frame = EthernetFrame::from_buffer(data.into(), 0).unwrap();
         assert_eq!(results[0][0], test_frame);
     }
 }
@@ -343,19 +343,18 @@ fn gen_tokio_run() -> Vec<syn::Stmt> {
                         path: codegen::path(vec![
                             (codegen::ident("runtime"), None),
                             (codegen::ident("Builder"), None),
-                            (codegen::ident("new"), None),
+                            (codegen::ident("new_multi_thread"), None),
                         ]),
                     }),
                     vec![],
                 ),
                 vec![
-                    ("threaded_scheduler", vec![]),
                     ("enable_all", vec![]),
                     ("build", vec![]),
                     ("unwrap", vec![]),
                 ],
             ),
-            true,
+            false,
         )),
         codegen::stmt_expr_semi(codegen::call_function(
             codegen::expr_field(codegen::expr_path_ident("rt"), "block_on"),
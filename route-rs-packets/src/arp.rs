@@ -2,11 +2,13 @@ use crate::{EthernetFrame, MacAddr, ARP_ETHER_TYPE};
 use std::convert::{TryFrom, TryInto};
 use std::net::IpAddr;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ArpOp {
     Request = 1,
     Reply = 2,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ArpHardwareType {
     Ethernet = 1,
 }
@@ -29,7 +31,7 @@ pub struct ArpFrame {
 impl ArpFrame {
     ///
     /// Constructs a new, empty packet with a payload big enough for all ARP fields,
-    /// given some hardware/protocol address lengths.
+    /// given some hardware/protocol address lengths, in a frame with the ARP ether type.
     ///
     pub fn new(hardware_addr_len: u8, protocol_addr_len: u8) -> Self {
         let payload_len = 8 + (2 * hardware_addr_len as usize) + (2 * protocol_addr_len as usize);
@@ -37,6 +39,7 @@ impl ArpFrame {
 
         let mut frame = EthernetFrame::empty();
         frame.set_payload(payload.as_slice());
+        frame.set_ether_type(ARP_ETHER_TYPE);
 
         let mut arp_frame = ArpFrame { frame };
         arp_frame.set_hardware_addr_len(hardware_addr_len);
@@ -208,7 +211,8 @@ impl TryFrom<EthernetFrame> for ArpFrame {
     /// Decorates the given EthernetFrame with ArpFrame getters/setters.
     /// Validates
     /// - The frame has an ARP ether type
-    /// - The frame has a reasonable payload size given the hardware/protocol address lengths
+    /// - The frame has a reasonable payload size given the hardware/protocol address lengths,
+    ///   allowing for the padding of frames shorter than Ethernet's minimum
     ///
     fn try_from(frame: EthernetFrame) -> Result<Self, Self::Error> {
         if frame.ether_type() != ARP_ETHER_TYPE {
@@ -225,7 +229,7 @@ impl TryFrom<EthernetFrame> for ArpFrame {
         let hlen = arp_frame.hardware_addr_len() as usize;
         let plen = arp_frame.protocol_addr_len() as usize;
 
-        if payload_len != (8 + (2 * hlen) + (2 * plen)) {
+        if payload_len < (8 + (2 * hlen) + (2 * plen)) {
             return Err("Frame payload doesn't match address length fields");
         }
 
@@ -236,6 +240,8 @@ impl TryFrom<EthernetFrame> for ArpFrame {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::IPV4_ETHER_TYPE;
+    use std::net::Ipv4Addr;
 
     #[test]
     fn generate_empty_arp_frame() {
@@ -290,11 +296,118 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "Frame does not have ARP ether type")]
+    fn arp_frame_with_padding() {
+        let mut arp_payload: Vec<u8> = vec![
+            0x00, 0x01, 0x08, 0x00, 0x06, 0x04, 0x00, 0x02, 1, 2, 3, 4, 5, 6, 10, 0, 0, 1, 10, 9,
+            8, 7, 6, 5, 10, 0, 0, 2,
+        ];
+        arp_payload.resize(46, 0);
+        let mut ethernet_frame = EthernetFrame::empty();
+        ethernet_frame.set_payload(&arp_payload);
+        ethernet_frame.set_ether_type(ARP_ETHER_TYPE);
+
+        let arp_frame = ArpFrame::try_from(ethernet_frame).unwrap();
+        assert_eq!(arp_frame.opcode(), ArpOp::Reply as u16);
+        assert_eq!(arp_frame.target_protocol_addr(), [10, 0, 0, 2]);
+    }
+
+    /// A request, as captured, from 00:07:0d:af:f4:54 at 24.166.172.1 for 24.166.173.159, padded
+    /// to Ethernet's minimum.
+    const CAPTURED_REQUEST: [u8; 60] = [
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0x07, 0x0d, 0xaf, 0xf4, 0x54, 0x08, 0x06, 0x00,
+        0x01, 0x08, 0x00, 0x06, 0x04, 0x00, 0x01, 0x00, 0x07, 0x0d, 0xaf, 0xf4, 0x54, 0x18, 0xa6,
+        0xac, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0xa6, 0xad, 0x9f, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    /// The reply to CAPTURED_REQUEST, from 00:0c:29:34:0b:de, unpadded.
+    const CAPTURED_REPLY: [u8; 42] = [
+        0x00, 0x07, 0x0d, 0xaf, 0xf4, 0x54, 0x00, 0x0c, 0x29, 0x34, 0x0b, 0xde, 0x08, 0x06, 0x00,
+        0x01, 0x08, 0x00, 0x06, 0x04, 0x00, 0x02, 0x00, 0x0c, 0x29, 0x34, 0x0b, 0xde, 0x18, 0xa6,
+        0xad, 0x9f, 0x00, 0x07, 0x0d, 0xaf, 0xf4, 0x54, 0x18, 0xa6, 0xac, 0x01,
+    ];
+
+    fn requester() -> (MacAddr, Ipv4Addr) {
+        (
+            MacAddr::new([0x00, 0x07, 0x0d, 0xaf, 0xf4, 0x54]),
+            Ipv4Addr::new(24, 166, 172, 1),
+        )
+    }
+
+    fn replier() -> (MacAddr, Ipv4Addr) {
+        (
+            MacAddr::new([0x00, 0x0c, 0x29, 0x34, 0x0b, 0xde]),
+            Ipv4Addr::new(24, 166, 173, 159),
+        )
+    }
+
+    #[test]
+    fn parse_captured_frames() {
+        let request = ArpFrame::try_from(
+            EthernetFrame::from_buffer(CAPTURED_REQUEST.to_vec().into(), 0).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(request.hardware_type(), ArpHardwareType::Ethernet as u16);
+        assert_eq!(request.protocol_type(), IPV4_ETHER_TYPE);
+        assert_eq!(request.hardware_addr_len(), 6);
+        assert_eq!(request.protocol_addr_len(), 4);
+        assert_eq!(request.opcode(), ArpOp::Request as u16);
+        assert_eq!(request.sender_hardware_addr(), requester().0.bytes);
+        assert_eq!(request.sender_protocol_addr(), requester().1.octets());
+        assert_eq!(request.target_hardware_addr(), [0; 6]);
+        assert_eq!(request.target_protocol_addr(), replier().1.octets());
+
+        let reply = ArpFrame::try_from(
+            EthernetFrame::from_buffer(CAPTURED_REPLY.to_vec().into(), 0).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(reply.opcode(), ArpOp::Reply as u16);
+        assert_eq!(reply.sender_hardware_addr(), replier().0.bytes);
+        assert_eq!(reply.sender_protocol_addr(), replier().1.octets());
+        assert_eq!(reply.target_hardware_addr(), requester().0.bytes);
+        assert_eq!(reply.target_protocol_addr(), requester().1.octets());
+    }
+
+    #[test]
+    fn serialize_captured_frames() {
+        let mut request = ArpFrame::new(6, 4);
+        request
+            .set_hardware_type(ArpHardwareType::Ethernet as u16)
+            .set_protocol_type(IPV4_ETHER_TYPE)
+            .set_opcode(ArpOp::Request as u16)
+            .set_sender_hardware_addr(requester().0)
+            .set_sender_protocol_addr(requester().1.into())
+            .set_target_hardware_addr(MacAddr::new([0; 6]))
+            .set_target_protocol_addr(replier().1.into());
+        let mut frame = request.frame();
+        frame.set_dest_mac(MacAddr::new([0xff; 6]));
+        frame.set_src_mac(requester().0);
+        // Padding is left to the interface.
+        assert_eq!(frame.data, CAPTURED_REQUEST[..42]);
+
+        let mut reply = ArpFrame::new(6, 4);
+        reply
+            .set_hardware_type(ArpHardwareType::Ethernet as u16)
+            .set_protocol_type(IPV4_ETHER_TYPE)
+            .set_opcode(ArpOp::Reply as u16)
+            .set_sender_hardware_addr(replier().0)
+            .set_sender_protocol_addr(replier().1.into())
+            .set_target_hardware_addr(requester().0)
+            .set_target_protocol_addr(requester().1.into());
+        let mut frame = reply.frame();
+        frame.set_dest_mac(requester().0);
+        frame.set_src_mac(replier().0);
+        assert_eq!(frame.data, CAPTURED_REPLY);
+    }
+
+    #[test]
     fn try_from_non_arp_ether_type() {
         let mut ethernet_frame = EthernetFrame::empty();
         ethernet_frame.set_ether_type(ARP_ETHER_TYPE + 1);
-        ArpFrame::try_from(ethernet_frame).unwrap();
+        assert_eq!(
+            ArpFrame::try_from(ethernet_frame).err(),
+            Some("Frame does not have ARP ether type")
+        );
     }
 
     #[test]
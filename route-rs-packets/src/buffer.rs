@@ -0,0 +1,446 @@
+use bytes::{Bytes, BytesMut};
+use std::borrow::Borrow;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
+use std::ops::{Bound, Deref, DerefMut, RangeBounds};
+
+#[derive(Clone)]
+enum Buffer {
+    /// Data that may be shared with other PacketData, by clones or slices, and so is made unique
+    /// before it's changed.
+    Shared(Bytes),
+    /// Data that's being changed, which no other PacketData shares.
+    Unique(BytesMut),
+}
+
+/// The common datatype that all packet structures share to represent their data. It's a view
+/// over a reference counted buffer, so cloning and slicing it are O(1), sharing the buffer, and
+/// the data is only copied when it's changed while shared. It has the methods of `Vec<u8>` that
+/// packets use to change their data, and derefs to a slice for the rest.
+///
+/// Changing the data makes it unique, without copying it if no other PacketData shares it, and
+/// it stays unique until it's frozen. Clones and slices of unique data are copies, so call
+/// `freeze`, which doesn't copy, when done changing data that will be cloned, such as before a
+/// ForkLink.
+#[derive(Clone)]
+pub struct PacketData {
+    buffer: Buffer,
+}
+
+impl PacketData {
+    pub fn new() -> PacketData {
+        PacketData::from(Bytes::new())
+    }
+
+    pub fn with_capacity(capacity: usize) -> PacketData {
+        PacketData::from(BytesMut::with_capacity(capacity))
+    }
+
+    /// Data viewing a static buffer, which is never copied unless changed.
+    pub fn from_static(data: &'static [u8]) -> PacketData {
+        PacketData::from(Bytes::from_static(data))
+    }
+
+    /// Data viewing a buffer `owner` holds, which is dropped once the data, and every clone and
+    /// slice of it, are dropped or changed. Changing the data copies it out of `owner`'s buffer.
+    pub fn from_owner<T>(owner: T) -> PacketData
+    where
+        T: AsRef<[u8]> + Send + 'static,
+    {
+        PacketData::from(Bytes::from_owner(owner))
+    }
+
+    /// Makes the data shareable, so clones and slices of it share its buffer, rather than copying
+    /// it. Does not copy the data.
+    pub fn freeze(&mut self) {
+        if let Buffer::Unique(data) = &mut self.buffer {
+            self.buffer = Buffer::Shared(std::mem::take(data).freeze());
+        }
+    }
+
+    /// Returns the data in `range`, sharing the buffer if the data is frozen, and otherwise
+    /// copying it.
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> PacketData {
+        let (start, end) = self.bounds(range);
+        match &self.buffer {
+            Buffer::Shared(data) => PacketData::from(data.slice(start..end)),
+            Buffer::Unique(data) => PacketData::from(&data[start..end]),
+        }
+    }
+
+    /// Splits the data in two at `at`, returning the data after it. The halves share the buffer.
+    pub fn split_off(&mut self, at: usize) -> PacketData {
+        match &mut self.buffer {
+            Buffer::Shared(data) => PacketData::from(data.split_off(at)),
+            Buffer::Unique(data) => PacketData::from(data.split_off(at)),
+        }
+    }
+
+    /// Splits the data in two at `at`, returning the data before it, such as the headers a
+    /// decapsulated packet is carried in. The halves share the buffer.
+    pub fn split_to(&mut self, at: usize) -> PacketData {
+        match &mut self.buffer {
+            Buffer::Shared(data) => PacketData::from(data.split_to(at)),
+            Buffer::Unique(data) => PacketData::from(data.split_to(at)),
+        }
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        match &mut self.buffer {
+            Buffer::Shared(data) => data.truncate(len),
+            Buffer::Unique(data) => data.truncate(len),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    pub fn reserve_exact(&mut self, additional: usize) {
+        self.make_mut().reserve(additional);
+    }
+
+    pub fn push(&mut self, value: u8) {
+        self.make_mut().extend_from_slice(&[value]);
+    }
+
+    pub fn extend_from_slice(&mut self, other: &[u8]) {
+        self.make_mut().extend_from_slice(other);
+    }
+
+    pub fn resize(&mut self, new_len: usize, value: u8) {
+        self.make_mut().resize(new_len, value);
+    }
+
+    pub fn insert(&mut self, index: usize, value: u8) {
+        self.splice(index..index, Some(value));
+    }
+
+    /// Replaces the data in `range` with `replace_with`.
+    pub fn splice<I>(&mut self, range: impl RangeBounds<usize>, replace_with: I)
+    where
+        I: IntoIterator<Item = u8>,
+    {
+        let (start, end) = self.bounds(range);
+        let data = self.make_mut();
+        let tail = data.split_off(end);
+        data.truncate(start);
+        data.extend(replace_with);
+        data.unsplit(tail);
+    }
+
+    /// Removes the data in `range`, returning an iterator over it.
+    pub fn drain(&mut self, range: impl RangeBounds<usize>) -> std::vec::IntoIter<u8> {
+        let (start, end) = self.bounds(range);
+        let removed = self[start..end].to_vec();
+        self.splice(start..end, None);
+        removed.into_iter()
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        match self.buffer {
+            Buffer::Shared(data) => Vec::from(data),
+            Buffer::Unique(data) => Vec::from(data),
+        }
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        match self.buffer {
+            Buffer::Shared(data) => data,
+            Buffer::Unique(data) => data.freeze(),
+        }
+    }
+
+    /// Makes the data unique, so it can be changed. Takes the buffer back without copying it if
+    /// no other PacketData shares it, and copies it otherwise, or if it's a buffer that isn't
+    /// ours to change, such as a static one or one an owner holds.
+    fn make_mut(&mut self) -> &mut BytesMut {
+        if let Buffer::Shared(data) = &mut self.buffer {
+            let data = match std::mem::take(data).try_into_mut() {
+                Ok(data) => data,
+                Err(shared) => BytesMut::from(&shared[..]),
+            };
+            self.buffer = Buffer::Unique(data);
+        }
+        match &mut self.buffer {
+            Buffer::Unique(data) => data,
+            Buffer::Shared(_) => unreachable!(),
+        }
+    }
+
+    fn bounds(&self, range: impl RangeBounds<usize>) -> (usize, usize) {
+        let start = match range.start_bound() {
+            Bound::Included(start) => *start,
+            Bound::Excluded(start) => start + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => end + 1,
+            Bound::Excluded(end) => *end,
+            Bound::Unbounded => self.len(),
+        };
+        assert!(
+            start <= end && end <= self.len(),
+            "PacketData range: {}..{}, out of bounds of length {}",
+            start,
+            end,
+            self.len()
+        );
+        (start, end)
+    }
+}
+
+impl Default for PacketData {
+    fn default() -> Self {
+        PacketData::new()
+    }
+}
+
+impl Deref for PacketData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.buffer {
+            Buffer::Shared(data) => data,
+            Buffer::Unique(data) => data,
+        }
+    }
+}
+
+impl DerefMut for PacketData {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.make_mut()
+    }
+}
+
+impl AsRef<[u8]> for PacketData {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl AsMut<[u8]> for PacketData {
+    fn as_mut(&mut self) -> &mut [u8] {
+        self
+    }
+}
+
+impl Borrow<[u8]> for PacketData {
+    fn borrow(&self) -> &[u8] {
+        self
+    }
+}
+
+impl fmt::Debug for PacketData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl Hash for PacketData {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl PartialEq for PacketData {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for PacketData {}
+
+impl PartialEq<[u8]> for PacketData {
+    fn eq(&self, other: &[u8]) -> bool {
+        **self == *other
+    }
+}
+
+impl PartialEq<&[u8]> for PacketData {
+    fn eq(&self, other: &&[u8]) -> bool {
+        **self == **other
+    }
+}
+
+impl<const N: usize> PartialEq<[u8; N]> for PacketData {
+    fn eq(&self, other: &[u8; N]) -> bool {
+        **self == other[..]
+    }
+}
+
+impl PartialEq<Vec<u8>> for PacketData {
+    fn eq(&self, other: &Vec<u8>) -> bool {
+        **self == other[..]
+    }
+}
+
+impl PartialEq<PacketData> for Vec<u8> {
+    fn eq(&self, other: &PacketData) -> bool {
+        self[..] == **other
+    }
+}
+
+impl PartialEq<PacketData> for [u8] {
+    fn eq(&self, other: &PacketData) -> bool {
+        *self == **other
+    }
+}
+
+impl From<Vec<u8>> for PacketData {
+    /// Takes the vector's buffer, without copying it.
+    fn from(data: Vec<u8>) -> Self {
+        PacketData::from(Bytes::from(data))
+    }
+}
+
+impl From<&[u8]> for PacketData {
+    fn from(data: &[u8]) -> Self {
+        PacketData::from(Bytes::copy_from_slice(data))
+    }
+}
+
+impl<const N: usize> From<[u8; N]> for PacketData {
+    fn from(data: [u8; N]) -> Self {
+        PacketData::from(&data[..])
+    }
+}
+
+impl From<Bytes> for PacketData {
+    fn from(data: Bytes) -> Self {
+        PacketData {
+            buffer: Buffer::Shared(data),
+        }
+    }
+}
+
+impl From<BytesMut> for PacketData {
+    fn from(data: BytesMut) -> Self {
+        PacketData {
+            buffer: Buffer::Unique(data),
+        }
+    }
+}
+
+impl From<PacketData> for Vec<u8> {
+    fn from(data: PacketData) -> Self {
+        data.into_vec()
+    }
+}
+
+impl From<PacketData> for Bytes {
+    fn from(data: PacketData) -> Self {
+        data.into_bytes()
+    }
+}
+
+impl FromIterator<u8> for PacketData {
+    fn from_iter<I: IntoIterator<Item = u8>>(iter: I) -> Self {
+        PacketData::from(Vec::from_iter(iter))
+    }
+}
+
+impl Extend<u8> for PacketData {
+    fn extend<I: IntoIterator<Item = u8>>(&mut self, iter: I) {
+        self.make_mut().extend(iter);
+    }
+}
+
+impl<'a> Extend<&'a u8> for PacketData {
+    fn extend<I: IntoIterator<Item = &'a u8>>(&mut self, iter: I) {
+        self.make_mut().extend(iter);
+    }
+}
+
+impl<'a> IntoIterator for &'a PacketData {
+    type Item = &'a u8;
+    type IntoIter = std::slice::Iter<'a, u8>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl IntoIterator for PacketData {
+    type Item = u8;
+    type IntoIter = std::vec::IntoIter<u8>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_vec().into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_until_changed() {
+        let data = PacketData::from(vec![1, 2, 3, 4]);
+        let mut clone = data.clone();
+        assert_eq!(clone.as_ptr(), data.as_ptr());
+
+        clone[0] = 9;
+        assert_ne!(clone.as_ptr(), data.as_ptr());
+        assert_eq!(data, [1, 2, 3, 4]);
+        assert_eq!(clone, [9, 2, 3, 4]);
+
+        // Unique data is changed in place, and copied by clones until it's frozen.
+        let ptr = clone.as_ptr();
+        clone[1] = 8;
+        assert_eq!(clone.as_ptr(), ptr);
+        assert_ne!(clone.clone().as_ptr(), ptr);
+        clone.extend_from_slice(&[5, 6]);
+        clone.freeze();
+        let copy = clone.clone();
+        assert_eq!(copy.as_ptr(), clone.as_ptr());
+        assert_eq!(copy, [9, 8, 3, 4, 5, 6]);
+
+        // Frozen data no other PacketData shares is changed without copying.
+        drop(copy);
+        let ptr = clone.as_ptr();
+        clone[0] = 7;
+        assert_eq!(clone.as_ptr(), ptr);
+
+        // Static data isn't ours to change, so is copied first.
+        let mut data = PacketData::from_static(&[1, 2]);
+        data[0] = 3;
+        assert_eq!(data, [3, 2]);
+    }
+
+    #[test]
+    fn slices_share() {
+        let mut data = PacketData::from(vec![1, 2, 3, 4, 5, 6]);
+        let inner = data.slice(2..);
+        assert_eq!(inner, [3, 4, 5, 6]);
+        assert_eq!(inner.as_ptr(), data[2..].as_ptr());
+
+        let headers = data.split_to(2);
+        assert_eq!(headers, [1, 2]);
+        assert_eq!(data.as_ptr(), inner.as_ptr());
+        let tail = data.split_off(2);
+        assert_eq!(data, [3, 4]);
+        assert_eq!(tail, vec![5, 6]);
+    }
+
+    #[test]
+    fn vec_methods() {
+        let mut data = PacketData::from_static(&[1, 2, 3]);
+        data.push(4);
+        data.extend_from_slice(&[5, 6]);
+        data.extend(vec![7u8]);
+        data.truncate(6);
+        assert_eq!(data, [1, 2, 3, 4, 5, 6]);
+
+        data.splice(1..3, vec![9, 9, 9]);
+        assert_eq!(data, [1, 9, 9, 9, 4, 5, 6]);
+        let removed: Vec<u8> = data.drain(1..4).collect();
+        assert_eq!(removed, vec![9, 9, 9]);
+        data.insert(0, 0);
+        data.resize(8, 7);
+        assert_eq!(data, [0, 1, 4, 5, 6, 7, 7, 7]);
+        assert_eq!(data.iter().map(|b| u32::from(*b)).sum::<u32>(), 37);
+        assert_eq!(Vec::from(data), vec![0, 1, 4, 5, 6, 7, 7, 7]);
+    }
+}
@@ -0,0 +1,487 @@
+use crate::*;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Builds an `EthernetFrame` layer by layer, for tests and for synthesizing packets, such as
+/// replies. Each layer's builder is handed to a closure, and lengths and checksums are filled in
+/// when the frame is built:
+///
+/// ```
+/// # use route_rs_packets::*;
+/// # use std::convert::TryFrom;
+/// # use std::net::Ipv4Addr;
+/// let frame = EthernetFrame::builder()
+///     .src(MacAddr::new([0, 1, 2, 3, 4, 5]))
+///     .dst(MacAddr::new([6, 7, 8, 9, 10, 11]))
+///     .ipv4(|ip| {
+///         ip.src(Ipv4Addr::new(10, 0, 0, 1))
+///             .dst(Ipv4Addr::new(10, 0, 0, 2))
+///             .udp(|udp| udp.sport(5353).dport(53).payload(b"query"))
+///     })
+///     .build();
+/// assert_eq!(UdpSegment::try_from(frame).unwrap().payload(), &b"query"[..]);
+/// ```
+pub struct EthernetBuilder {
+    frame: EthernetFrame,
+}
+
+impl EthernetFrame {
+    pub fn builder() -> EthernetBuilder {
+        EthernetBuilder {
+            frame: EthernetFrame::empty(),
+        }
+    }
+}
+
+impl EthernetBuilder {
+    pub fn src(mut self, mac: MacAddr) -> Self {
+        self.frame.set_src_mac(mac);
+        self
+    }
+
+    pub fn dst(mut self, mac: MacAddr) -> Self {
+        self.frame.set_dest_mac(mac);
+        self
+    }
+
+    /// Tags the frame with an 802.1Q tag, replacing any tag it already has.
+    pub fn vlan(mut self, tag: VlanTag) -> Self {
+        if self.frame.set_vlan_tag(tag).is_err() {
+            self.frame.push_vlan_tag(tag).unwrap();
+        }
+        self
+    }
+
+    /// Carries the IPv4 packet `f` builds.
+    pub fn ipv4(self, f: impl FnOnce(Ipv4Builder) -> Ipv4Builder) -> Self {
+        let packet = f(Ipv4Packet::builder()).build();
+        self.payload(IPV4_ETHER_TYPE, &packet.data)
+    }
+
+    /// Carries the IPv6 packet `f` builds.
+    pub fn ipv6(self, f: impl FnOnce(Ipv6Builder) -> Ipv6Builder) -> Self {
+        let packet = f(Ipv6Packet::builder()).build();
+        self.payload(IPV6_ETHER_TYPE, &packet.data)
+    }
+
+    /// Carries `payload` as a protocol of `ether_type`, such as an ARP frame.
+    pub fn payload(mut self, ether_type: u16, payload: &[u8]) -> Self {
+        self.frame.set_ether_type(ether_type);
+        self.frame.set_payload(payload);
+        self
+    }
+
+    pub fn build(self) -> EthernetFrame {
+        self.frame
+    }
+}
+
+/// Where the checksum of an upper layer that covers an IP pseudo-header sits, from the start of
+/// the IP payload, and whether it's a UDP checksum, where zero means none.
+#[derive(Clone, Copy)]
+struct PseudoHeaderChecksum {
+    offset: usize,
+    udp: bool,
+}
+
+const TCP_CHECKSUM: PseudoHeaderChecksum = PseudoHeaderChecksum {
+    offset: 16,
+    udp: false,
+};
+const UDP_CHECKSUM: PseudoHeaderChecksum = PseudoHeaderChecksum {
+    offset: 6,
+    udp: true,
+};
+const ICMPV6_CHECKSUM: PseudoHeaderChecksum = PseudoHeaderChecksum {
+    offset: 2,
+    udp: false,
+};
+
+/// Writes the checksum of the upper layer `segment` of `protocol`, carried from `src` to `dest`.
+/// A UDP checksum that comes to zero is sent as 0xFFFF, since zero means none.
+fn write_checksum<A: Into<IpAddr>>(
+    segment: &mut PacketData,
+    at: PseudoHeaderChecksum,
+    src: A,
+    dest: A,
+    protocol: u8,
+) {
+    segment[at.offset..at.offset + 2].copy_from_slice(&[0, 0]);
+    let checksum = match pseudo_header_checksum(src, dest, protocol, segment) {
+        0 if at.udp => 0xFFFF,
+        checksum => checksum,
+    };
+    segment[at.offset..at.offset + 2].copy_from_slice(&checksum.to_be_bytes());
+}
+
+/// Builds an `Ipv4Packet`, with a TTL of 64 unless set. The total length and header checksum, and
+/// the checksum of a TCP or UDP segment it carries, are filled in by `build`.
+pub struct Ipv4Builder {
+    packet: Ipv4Packet,
+    checksum: Option<PseudoHeaderChecksum>,
+}
+
+impl Ipv4Packet {
+    pub fn builder() -> Ipv4Builder {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_ttl(64);
+        Ipv4Builder {
+            packet,
+            checksum: None,
+        }
+    }
+}
+
+impl Ipv4Builder {
+    pub fn src(mut self, addr: Ipv4Addr) -> Self {
+        self.packet.set_src_addr(addr);
+        self
+    }
+
+    pub fn dst(mut self, addr: Ipv4Addr) -> Self {
+        self.packet.set_dest_addr(addr);
+        self
+    }
+
+    pub fn ttl(mut self, ttl: u8) -> Self {
+        self.packet.set_ttl(ttl);
+        self
+    }
+
+    pub fn dscp(mut self, dscp: u8) -> Self {
+        self.packet.set_dscp(dscp);
+        self
+    }
+
+    pub fn identification(mut self, identification: u16) -> Self {
+        self.packet.set_identification(identification);
+        self
+    }
+
+    /// Sets the Don't Fragment flag.
+    pub fn dont_fragment(mut self) -> Self {
+        self.packet.set_flags(true, false);
+        self
+    }
+
+    /// Carries the UDP segment `f` builds.
+    pub fn udp(self, f: impl FnOnce(UdpBuilder) -> UdpBuilder) -> Self {
+        let segment = f(UdpSegment::builder()).build();
+        self.upper_layer(17, &segment.data, Some(UDP_CHECKSUM))
+    }
+
+    /// Carries the TCP segment `f` builds.
+    pub fn tcp(self, f: impl FnOnce(TcpBuilder) -> TcpBuilder) -> Self {
+        let segment = f(TcpSegment::builder()).build();
+        self.upper_layer(6, &segment.data, Some(TCP_CHECKSUM))
+    }
+
+    pub fn icmp(self, message: &IcmpMessage) -> Self {
+        let packet = IcmpPacket::new(message);
+        self.upper_layer(1, &packet.data, None)
+    }
+
+    /// Carries `payload` as `protocol`, as it is.
+    pub fn payload(self, protocol: u8, payload: &[u8]) -> Self {
+        self.upper_layer(protocol, payload, None)
+    }
+
+    fn upper_layer(
+        mut self,
+        protocol: u8,
+        payload: &[u8],
+        checksum: Option<PseudoHeaderChecksum>,
+    ) -> Self {
+        self.packet.set_protocol(protocol);
+        self.packet.set_payload(payload);
+        self.checksum = checksum;
+        self
+    }
+
+    pub fn build(mut self) -> Ipv4Packet {
+        if let Some(checksum) = self.checksum {
+            let mut payload = self.packet.data.split_off(self.packet.payload_offset);
+            let protocol = self.packet.data[self.packet.layer3_offset + 9];
+            let (src, dest) = (self.packet.src_addr(), self.packet.dest_addr());
+            write_checksum(&mut payload, checksum, src, dest, protocol);
+            self.packet.data.extend_from_slice(&payload);
+        }
+        self.packet.set_checksum();
+        self.packet
+    }
+}
+
+/// Builds an `Ipv6Packet`, with a hop limit of 64 unless set. The payload length, and the
+/// checksum of a TCP segment, UDP segment or ICMPv6 packet it carries, are filled in by `build`.
+pub struct Ipv6Builder {
+    packet: Ipv6Packet,
+    checksum: Option<PseudoHeaderChecksum>,
+}
+
+impl Ipv6Packet {
+    pub fn builder() -> Ipv6Builder {
+        let mut packet = Ipv6Packet::empty();
+        packet.set_hop_limit(64);
+        Ipv6Builder {
+            packet,
+            checksum: None,
+        }
+    }
+}
+
+impl Ipv6Builder {
+    pub fn src(mut self, addr: Ipv6Addr) -> Self {
+        self.packet.set_src_addr(addr);
+        self
+    }
+
+    pub fn dst(mut self, addr: Ipv6Addr) -> Self {
+        self.packet.set_dest_addr(addr);
+        self
+    }
+
+    pub fn hop_limit(mut self, hop_limit: u8) -> Self {
+        self.packet.set_hop_limit(hop_limit);
+        self
+    }
+
+    pub fn traffic_class(mut self, traffic_class: u8) -> Self {
+        self.packet.set_traffic_class(traffic_class);
+        self
+    }
+
+    pub fn flow_label(mut self, flow_label: u32) -> Self {
+        self.packet.set_flow_label(flow_label);
+        self
+    }
+
+    /// Carries the UDP segment `f` builds.
+    pub fn udp(self, f: impl FnOnce(UdpBuilder) -> UdpBuilder) -> Self {
+        let segment = f(UdpSegment::builder()).build();
+        self.upper_layer(17, &segment.data, Some(UDP_CHECKSUM))
+    }
+
+    /// Carries the TCP segment `f` builds.
+    pub fn tcp(self, f: impl FnOnce(TcpBuilder) -> TcpBuilder) -> Self {
+        let segment = f(TcpSegment::builder()).build();
+        self.upper_layer(6, &segment.data, Some(TCP_CHECKSUM))
+    }
+
+    pub fn icmpv6(self, message: &Icmpv6Message) -> Self {
+        let packet = Icmpv6Packet::new(Ipv6Addr::UNSPECIFIED, Ipv6Addr::UNSPECIFIED, message);
+        self.upper_layer(58, &packet.data, Some(ICMPV6_CHECKSUM))
+    }
+
+    /// Carries `payload` as `next_header`, as it is.
+    pub fn payload(self, next_header: u8, payload: &[u8]) -> Self {
+        self.upper_layer(next_header, payload, None)
+    }
+
+    fn upper_layer(
+        mut self,
+        next_header: u8,
+        payload: &[u8],
+        checksum: Option<PseudoHeaderChecksum>,
+    ) -> Self {
+        self.packet.set_next_header(next_header);
+        self.packet.set_payload(payload);
+        self.checksum = checksum;
+        self
+    }
+
+    pub fn build(mut self) -> Ipv6Packet {
+        if let Some(checksum) = self.checksum {
+            let mut payload = self.packet.data.split_off(self.packet.payload_offset);
+            let next_header = self.packet.data[self.packet.layer3_offset + 6];
+            let (src, dest) = (self.packet.src_addr(), self.packet.dest_addr());
+            write_checksum(&mut payload, checksum, src, dest, next_header);
+            self.packet.data.extend_from_slice(&payload);
+        }
+        self.packet
+    }
+}
+
+/// Builds a `UdpSegment` with no IP header, filling in its length. Its checksum covers the IP
+/// pseudo-header, so is left for the IP builder carrying it.
+pub struct UdpBuilder {
+    segment: UdpSegment,
+}
+
+impl UdpSegment {
+    pub fn builder() -> UdpBuilder {
+        UdpBuilder {
+            segment: UdpSegment::empty(),
+        }
+    }
+}
+
+impl UdpBuilder {
+    pub fn sport(mut self, port: u16) -> Self {
+        self.segment.set_src_port(port);
+        self
+    }
+
+    pub fn dport(mut self, port: u16) -> Self {
+        self.segment.set_dest_port(port);
+        self
+    }
+
+    pub fn payload(mut self, payload: &[u8]) -> Self {
+        self.segment.set_payload(payload);
+        self
+    }
+
+    pub fn build(mut self) -> UdpSegment {
+        let length = self.segment.data.len();
+        assert!(
+            length <= usize::from(u16::MAX),
+            "UdpSegment length: {}, must be <= 65535",
+            length
+        );
+        let length = length as u16;
+        self.segment.data[4..6].copy_from_slice(&length.to_be_bytes());
+        self.segment
+    }
+}
+
+/// Builds a `TcpSegment` with no IP header, with a window of 65535 unless set. Its checksum covers
+/// the IP pseudo-header, so is left for the IP builder carrying it.
+pub struct TcpBuilder {
+    segment: TcpSegment,
+}
+
+impl TcpSegment {
+    pub fn builder() -> TcpBuilder {
+        let mut segment = TcpSegment::empty();
+        segment.set_window_size(0xFFFF);
+        TcpBuilder { segment }
+    }
+}
+
+impl TcpBuilder {
+    pub fn sport(mut self, port: u16) -> Self {
+        self.segment.set_src_port(port);
+        self
+    }
+
+    pub fn dport(mut self, port: u16) -> Self {
+        self.segment.set_dest_port(port);
+        self
+    }
+
+    pub fn seq(mut self, sequence_number: u32) -> Self {
+        self.segment.set_sequence_number(sequence_number);
+        self
+    }
+
+    pub fn ack(mut self, acknowledgment_number: u32) -> Self {
+        self.segment
+            .set_acknowledgment_number(acknowledgment_number);
+        self
+    }
+
+    /// Sets the control bits, such as 0x02 for SYN or 0x12 for SYN-ACK.
+    pub fn flags(mut self, control_bits: u16) -> Self {
+        self.segment.set_control_bits(control_bits);
+        self
+    }
+
+    pub fn window(mut self, window_size: u16) -> Self {
+        self.segment.set_window_size(window_size);
+        self
+    }
+
+    /// Sets the options, which are padded to a multiple of 4 bytes.
+    pub fn options(mut self, options: &[TcpOption]) -> Self {
+        self.segment.set_tcp_options(options).unwrap();
+        self
+    }
+
+    pub fn payload(mut self, payload: &[u8]) -> Self {
+        self.segment.set_payload(payload);
+        self
+    }
+
+    pub fn build(self) -> TcpSegment {
+        self.segment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    fn verify(checksum_data: &[&[u8]]) {
+        assert_eq!(internet_checksum(checksum_data), 0);
+    }
+
+    #[test]
+    fn ipv4_udp() {
+        let src = Ipv4Addr::new(192, 168, 1, 10);
+        let dst = Ipv4Addr::new(8, 8, 8, 8);
+        let frame = EthernetFrame::builder()
+            .src(MacAddr::new([2, 0, 0, 0, 0, 1]))
+            .dst(MacAddr::new([2, 0, 0, 0, 0, 2]))
+            .vlan(VlanTag::new(100))
+            .ipv4(|ip| {
+                ip.src(src)
+                    .dst(dst)
+                    .ttl(32)
+                    .udp(|udp| udp.sport(40000).dport(53).payload(&[1, 2, 3]))
+            })
+            .build();
+        assert_eq!(frame.vlan_tag().unwrap().vid, 100);
+        assert_eq!(frame.src_mac(), MacAddr::new([2, 0, 0, 0, 0, 1]));
+
+        let packet = Ipv4Packet::try_from(frame).unwrap();
+        assert_eq!(packet.ttl(), 32);
+        assert_eq!(packet.total_len(), 20 + 8 + 3);
+        verify(&[&packet.data[packet.layer3_offset..packet.payload_offset]]);
+
+        let segment = UdpSegment::try_from(packet.clone()).unwrap();
+        assert_eq!(segment.src_port(), 40000);
+        assert_eq!(segment.dest_port(), 53);
+        assert_eq!(segment.length(), 11);
+        assert_eq!(segment.payload(), &[1, 2, 3][..]);
+        verify(&[
+            &src.octets(),
+            &dst.octets(),
+            &[0, 17, 0, 11],
+            &packet.payload(),
+        ]);
+    }
+
+    #[test]
+    fn ipv6_tcp_and_icmpv6() {
+        let src: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let dst: Ipv6Addr = "2001:db8::2".parse().unwrap();
+        let packet = Ipv6Packet::builder()
+            .src(src)
+            .dst(dst)
+            .tcp(|tcp| tcp.sport(1234).dport(80).seq(7).flags(0x02).payload(b"hi"))
+            .build();
+        assert_eq!(packet.payload_length(), 22);
+        assert_eq!(packet.hop_limit(), 64);
+        let segment = TcpSegment::try_from(packet.clone()).unwrap();
+        assert_eq!(segment.sequence_number(), 7);
+        assert_eq!(segment.window_size(), 0xFFFF);
+        verify(&[
+            &src.octets(),
+            &dst.octets(),
+            &[0, 0, 0, 22, 0, 0, 0, 6],
+            &packet.payload(),
+        ]);
+
+        let packet = Ipv6Packet::builder()
+            .src(src)
+            .dst(dst)
+            .icmpv6(&Icmpv6Message::EchoRequest {
+                identifier: 1,
+                sequence_number: 2,
+                data: &[],
+            })
+            .build();
+        let icmp = Icmpv6Packet::try_from(packet.clone()).unwrap();
+        assert!(icmp.validate_checksum(src, dst));
+    }
+}
@@ -0,0 +1,606 @@
+use crate::*;
+use std::convert::{TryFrom, TryInto};
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+pub const DHCP_SERVER_PORT: u16 = 67;
+pub const DHCP_CLIENT_PORT: u16 = 68;
+
+/// Option codes, per RFC 2132.
+pub const DHCP_OPTION_PAD: u8 = 0;
+pub const DHCP_OPTION_SUBNET_MASK: u8 = 1;
+pub const DHCP_OPTION_ROUTER: u8 = 3;
+pub const DHCP_OPTION_DNS_SERVER: u8 = 6;
+pub const DHCP_OPTION_REQUESTED_ADDR: u8 = 50;
+pub const DHCP_OPTION_LEASE_TIME: u8 = 51;
+pub const DHCP_OPTION_MESSAGE_TYPE: u8 = 53;
+pub const DHCP_OPTION_SERVER_ID: u8 = 54;
+pub const DHCP_OPTION_PARAMETER_REQUEST_LIST: u8 = 55;
+pub const DHCP_OPTION_RENEWAL_TIME: u8 = 58;
+pub const DHCP_OPTION_REBINDING_TIME: u8 = 59;
+pub const DHCP_OPTION_CLIENT_ID: u8 = 61;
+pub const DHCP_OPTION_END: u8 = 255;
+
+/// The BOOTP op of messages from clients, and from servers.
+pub const BOOTREQUEST: u8 = 1;
+pub const BOOTREPLY: u8 = 2;
+
+/// Set in the flags field by clients that can't receive unicast before they have an address.
+const BROADCAST_FLAG: u16 = 0x8000;
+
+/// The options follow the fixed fields and the magic cookie.
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const OPTIONS_OFFSET: usize = 240;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DhcpMessageType {
+    Discover = 1,
+    Offer = 2,
+    Request = 3,
+    Decline = 4,
+    Ack = 5,
+    Nak = 6,
+    Release = 7,
+    Inform = 8,
+}
+
+impl DhcpMessageType {
+    pub fn from_u8(message_type: u8) -> Option<DhcpMessageType> {
+        match message_type {
+            1 => Some(DhcpMessageType::Discover),
+            2 => Some(DhcpMessageType::Offer),
+            3 => Some(DhcpMessageType::Request),
+            4 => Some(DhcpMessageType::Decline),
+            5 => Some(DhcpMessageType::Ack),
+            6 => Some(DhcpMessageType::Nak),
+            7 => Some(DhcpMessageType::Release),
+            8 => Some(DhcpMessageType::Inform),
+            _ => None,
+        }
+    }
+}
+
+/// A DHCP message, per RFC 2131, as carried in the payload of a UDP segment. The data is the
+/// message alone, without the headers of the segment it came in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DhcpMessage {
+    pub data: PacketData,
+}
+
+impl DhcpMessage {
+    pub fn from_buffer(data: PacketData) -> Result<DhcpMessage, &'static str> {
+        if data.len() < OPTIONS_OFFSET {
+            return Err("Data is too short to be a DhcpMessage");
+        }
+        if data[OPTIONS_OFFSET - 4..OPTIONS_OFFSET] != MAGIC_COOKIE {
+            return Err("DhcpMessage does not have the DHCP magic cookie");
+        }
+        Ok(DhcpMessage { data })
+    }
+
+    /// Make an empty DhcpMessage, for a client with an Ethernet address, with no options.
+    pub fn empty() -> DhcpMessage {
+        let mut data = vec![0; OPTIONS_OFFSET];
+        data[1] = 1;
+        data[2] = 6;
+        data[OPTIONS_OFFSET - 4..].copy_from_slice(&MAGIC_COOKIE);
+        data.push(DHCP_OPTION_END);
+        DhcpMessage::from_buffer(data.into()).unwrap()
+    }
+
+    /// BOOTREQUEST or BOOTREPLY.
+    pub fn op(&self) -> u8 {
+        self.data[0]
+    }
+
+    pub fn set_op(&mut self, op: u8) {
+        self.data[0] = op;
+    }
+
+    /// The transaction ID, chosen by the client, and copied into replies.
+    pub fn xid(&self) -> u32 {
+        u32::from_be_bytes(self.data[4..8].try_into().unwrap())
+    }
+
+    pub fn set_xid(&mut self, xid: u32) {
+        self.data[4..8].copy_from_slice(&xid.to_be_bytes());
+    }
+
+    pub fn flags(&self) -> u16 {
+        u16::from_be_bytes(self.data[10..12].try_into().unwrap())
+    }
+
+    pub fn set_flags(&mut self, flags: u16) {
+        self.data[10..12].copy_from_slice(&flags.to_be_bytes());
+    }
+
+    /// Whether the client asked for replies to be broadcast.
+    pub fn broadcast(&self) -> bool {
+        self.flags() & BROADCAST_FLAG != 0
+    }
+
+    /// The client's address, if it has one it can receive unicast on.
+    pub fn ciaddr(&self) -> Ipv4Addr {
+        self.addr(12)
+    }
+
+    pub fn set_ciaddr(&mut self, addr: Ipv4Addr) {
+        self.set_addr(12, addr);
+    }
+
+    /// The address the server gives the client.
+    pub fn yiaddr(&self) -> Ipv4Addr {
+        self.addr(16)
+    }
+
+    pub fn set_yiaddr(&mut self, addr: Ipv4Addr) {
+        self.set_addr(16, addr);
+    }
+
+    /// The address of the next server to bootstrap from.
+    pub fn siaddr(&self) -> Ipv4Addr {
+        self.addr(20)
+    }
+
+    pub fn set_siaddr(&mut self, addr: Ipv4Addr) {
+        self.set_addr(20, addr);
+    }
+
+    /// The address of the relay agent the message passed through, if any.
+    pub fn giaddr(&self) -> Ipv4Addr {
+        self.addr(24)
+    }
+
+    pub fn set_giaddr(&mut self, addr: Ipv4Addr) {
+        self.set_addr(24, addr);
+    }
+
+    /// The client's hardware address.
+    pub fn chaddr(&self) -> MacAddr {
+        MacAddr::new(self.data[28..34].try_into().unwrap())
+    }
+
+    pub fn set_chaddr(&mut self, mac: MacAddr) {
+        self.data[28..34].copy_from_slice(&mac.bytes);
+    }
+
+    /// Returns the options, as codes and their data, up to the end option. Pad options are
+    /// skipped, and an option cut short by the end of the message ends them.
+    pub fn options(&self) -> Vec<(u8, &[u8])> {
+        let mut options = vec![];
+        let mut offset = OPTIONS_OFFSET;
+        while offset < self.data.len() {
+            let code = self.data[offset];
+            match code {
+                DHCP_OPTION_PAD => offset += 1,
+                DHCP_OPTION_END => break,
+                _ => {
+                    let len = match self.data.get(offset + 1) {
+                        Some(len) => usize::from(*len),
+                        None => break,
+                    };
+                    match self.data.get(offset + 2..offset + 2 + len) {
+                        Some(data) => options.push((code, data)),
+                        None => break,
+                    }
+                    offset += 2 + len;
+                }
+            }
+        }
+        options
+    }
+
+    /// Returns the data of the first option with `code`.
+    pub fn option(&self, code: u8) -> Option<&[u8]> {
+        self.options()
+            .into_iter()
+            .find(|(option, _)| *option == code)
+            .map(|(_, data)| data)
+    }
+
+    /// Replaces the options with `options`, followed by the end option.
+    pub fn set_options(&mut self, options: &[(u8, &[u8])]) {
+        self.data.truncate(OPTIONS_OFFSET);
+        for (code, data) in options {
+            self.data.push(*code);
+            self.data.push(data.len() as u8);
+            self.data.extend_from_slice(data);
+        }
+        self.data.push(DHCP_OPTION_END);
+    }
+
+    pub fn message_type(&self) -> Option<DhcpMessageType> {
+        match self.option(DHCP_OPTION_MESSAGE_TYPE)? {
+            [message_type] => DhcpMessageType::from_u8(*message_type),
+            _ => None,
+        }
+    }
+
+    /// Returns the option with `code` as an address, if it's one address long.
+    pub fn addr_option(&self, code: u8) -> Option<Ipv4Addr> {
+        let addr: [u8; 4] = self.option(code)?.try_into().ok()?;
+        Some(Ipv4Addr::from(addr))
+    }
+
+    /// Returns the option with `code` as a list of addresses, empty if there isn't one, or it
+    /// isn't a whole number of addresses long.
+    pub fn addrs_option(&self, code: u8) -> Vec<Ipv4Addr> {
+        match self.option(code) {
+            Some(data) if data.len() % 4 == 0 => data
+                .chunks_exact(4)
+                .map(|addr| Ipv4Addr::new(addr[0], addr[1], addr[2], addr[3]))
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Returns the option with `code` as a time in seconds, if it's 4 bytes long.
+    pub fn time_option(&self, code: u8) -> Option<Duration> {
+        let seconds: [u8; 4] = self.option(code)?.try_into().ok()?;
+        Some(Duration::from_secs(u64::from(u32::from_be_bytes(seconds))))
+    }
+
+    /// The address the client asks for, in a discover or a request.
+    pub fn requested_addr(&self) -> Option<Ipv4Addr> {
+        self.addr_option(DHCP_OPTION_REQUESTED_ADDR)
+    }
+
+    /// The address of the server that sent, or is meant to receive, the message.
+    pub fn server_id(&self) -> Option<Ipv4Addr> {
+        self.addr_option(DHCP_OPTION_SERVER_ID)
+    }
+
+    pub fn subnet_mask(&self) -> Option<Ipv4Addr> {
+        self.addr_option(DHCP_OPTION_SUBNET_MASK)
+    }
+
+    /// The routers on the client's subnet, in order of preference.
+    pub fn routers(&self) -> Vec<Ipv4Addr> {
+        self.addrs_option(DHCP_OPTION_ROUTER)
+    }
+
+    /// The DNS servers, in order of preference.
+    pub fn dns_servers(&self) -> Vec<Ipv4Addr> {
+        self.addrs_option(DHCP_OPTION_DNS_SERVER)
+    }
+
+    pub fn lease_time(&self) -> Option<Duration> {
+        self.time_option(DHCP_OPTION_LEASE_TIME)
+    }
+
+    /// How long into the lease the client starts renewing it.
+    pub fn renewal_time(&self) -> Option<Duration> {
+        self.time_option(DHCP_OPTION_RENEWAL_TIME)
+    }
+
+    /// How long into the lease the client starts asking any server to extend it.
+    pub fn rebinding_time(&self) -> Option<Duration> {
+        self.time_option(DHCP_OPTION_REBINDING_TIME)
+    }
+
+    /// The identifier the client chose, instead of its hardware address, to be known by. It's
+    /// usually a hardware type followed by a hardware address, per RFC 2132.
+    pub fn client_id(&self) -> Option<&[u8]> {
+        self.option(DHCP_OPTION_CLIENT_ID)
+    }
+
+    fn addr(&self, offset: usize) -> Ipv4Addr {
+        let addr: [u8; 4] = self.data[offset..offset + 4].try_into().unwrap();
+        Ipv4Addr::from(addr)
+    }
+
+    fn set_addr(&mut self, offset: usize, addr: Ipv4Addr) {
+        self.data[offset..offset + 4].copy_from_slice(&addr.octets());
+    }
+}
+
+/// Builds a server's reply to a request: an offer, ack or nak. The reply starts with the
+/// request's transaction ID, flags, relay agent and client hardware address, and, for an ack,
+/// its client address, as per RFC 2131, and with the message type as its first option. Options
+/// follow in the order they're set, and setting one again replaces it.
+pub struct DhcpReplyBuilder {
+    message: DhcpMessage,
+    options: Vec<(u8, Vec<u8>)>,
+}
+
+impl DhcpReplyBuilder {
+    pub fn new(request: &DhcpMessage, message_type: DhcpMessageType) -> Self {
+        let mut message = DhcpMessage::empty();
+        message.set_op(BOOTREPLY);
+        message.set_xid(request.xid());
+        message.set_flags(request.flags());
+        message.set_giaddr(request.giaddr());
+        message.set_chaddr(request.chaddr());
+        if message_type == DhcpMessageType::Ack {
+            message.set_ciaddr(request.ciaddr());
+        }
+        DhcpReplyBuilder {
+            message,
+            options: vec![(DHCP_OPTION_MESSAGE_TYPE, vec![message_type as u8])],
+        }
+    }
+
+    /// The address given to the client.
+    pub fn yiaddr(mut self, addr: Ipv4Addr) -> Self {
+        self.message.set_yiaddr(addr);
+        self
+    }
+
+    /// The address of the next server to bootstrap from.
+    pub fn siaddr(mut self, addr: Ipv4Addr) -> Self {
+        self.message.set_siaddr(addr);
+        self
+    }
+
+    pub fn option(mut self, code: u8, data: &[u8]) -> Self {
+        match self.options.iter_mut().find(|(option, _)| *option == code) {
+            Some((_, existing)) => *existing = data.to_vec(),
+            None => self.options.push((code, data.to_vec())),
+        }
+        self
+    }
+
+    pub fn server_id(self, addr: Ipv4Addr) -> Self {
+        self.option(DHCP_OPTION_SERVER_ID, &addr.octets())
+    }
+
+    pub fn subnet_mask(self, mask: Ipv4Addr) -> Self {
+        self.option(DHCP_OPTION_SUBNET_MASK, &mask.octets())
+    }
+
+    /// The routers on the client's subnet, in order of preference. Left out if empty.
+    pub fn routers(self, routers: &[Ipv4Addr]) -> Self {
+        self.addrs_option(DHCP_OPTION_ROUTER, routers)
+    }
+
+    /// The DNS servers, in order of preference. Left out if empty.
+    pub fn dns_servers(self, servers: &[Ipv4Addr]) -> Self {
+        self.addrs_option(DHCP_OPTION_DNS_SERVER, servers)
+    }
+
+    /// Times are sent in whole seconds. Ones too long to send are sent as infinite.
+    pub fn lease_time(self, lease_time: Duration) -> Self {
+        self.time_option(DHCP_OPTION_LEASE_TIME, lease_time)
+    }
+
+    pub fn renewal_time(self, renewal_time: Duration) -> Self {
+        self.time_option(DHCP_OPTION_RENEWAL_TIME, renewal_time)
+    }
+
+    pub fn rebinding_time(self, rebinding_time: Duration) -> Self {
+        self.time_option(DHCP_OPTION_REBINDING_TIME, rebinding_time)
+    }
+
+    pub fn client_id(self, client_id: &[u8]) -> Self {
+        self.option(DHCP_OPTION_CLIENT_ID, client_id)
+    }
+
+    pub fn build(mut self) -> DhcpMessage {
+        let options: Vec<(u8, &[u8])> = self
+            .options
+            .iter()
+            .map(|(code, data)| (*code, &data[..]))
+            .collect();
+        self.message.set_options(&options);
+        self.message
+    }
+
+    fn addrs_option(self, code: u8, addrs: &[Ipv4Addr]) -> Self {
+        if addrs.is_empty() {
+            return self;
+        }
+        let data: Vec<u8> = addrs
+            .iter()
+            .flat_map(|addr| addr.octets().to_vec())
+            .collect();
+        self.option(code, &data)
+    }
+
+    fn time_option(self, code: u8, time: Duration) -> Self {
+        let seconds = u32::try_from(time.as_secs()).unwrap_or(u32::MAX);
+        self.option(code, &seconds.to_be_bytes())
+    }
+}
+
+impl TryFrom<UdpSegment> for DhcpMessage {
+    type Error = &'static str;
+
+    fn try_from(mut segment: UdpSegment) -> Result<Self, Self::Error> {
+        DhcpMessage::from_buffer(segment.data.split_off(segment.payload_offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dhcp_message() {
+        let mut message = DhcpMessage::empty();
+        message.set_op(BOOTREQUEST);
+        message.set_xid(0xdead_beef);
+        message.set_flags(0x8000);
+        message.set_ciaddr(Ipv4Addr::new(10, 0, 0, 5));
+        message.set_chaddr(MacAddr::new([2, 0, 0, 0, 0, 1]));
+        message.set_options(&[
+            (DHCP_OPTION_MESSAGE_TYPE, &[DhcpMessageType::Request as u8]),
+            (DHCP_OPTION_REQUESTED_ADDR, &[10, 0, 0, 5]),
+        ]);
+
+        let message = DhcpMessage::from_buffer(message.data).unwrap();
+        assert_eq!(message.op(), BOOTREQUEST);
+        assert_eq!(message.xid(), 0xdead_beef);
+        assert!(message.broadcast());
+        assert_eq!(message.ciaddr(), Ipv4Addr::new(10, 0, 0, 5));
+        assert_eq!(message.yiaddr(), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(message.chaddr(), MacAddr::new([2, 0, 0, 0, 0, 1]));
+        assert_eq!(message.message_type(), Some(DhcpMessageType::Request));
+        assert_eq!(
+            message.addr_option(DHCP_OPTION_REQUESTED_ADDR),
+            Some(Ipv4Addr::new(10, 0, 0, 5))
+        );
+        assert_eq!(message.option(DHCP_OPTION_ROUTER), None);
+    }
+
+    #[test]
+    fn options_skip_pad_and_stop_at_end() {
+        let mut message = DhcpMessage::empty();
+        message.data.truncate(OPTIONS_OFFSET);
+        message
+            .data
+            .extend_from_slice(&[0, 0, 53, 1, 1, 0, 255, 3, 4, 10, 0, 0, 1]);
+        assert_eq!(message.options(), vec![(53, &[1][..])]);
+
+        // A truncated option ends the options.
+        message.data.truncate(OPTIONS_OFFSET);
+        message.data.extend_from_slice(&[53, 1, 1, 3, 4, 10]);
+        assert_eq!(message.options(), vec![(53, &[1][..])]);
+    }
+
+    const CLIENT_MAC: [u8; 6] = [0x00, 0x0b, 0x82, 0x01, 0xfc, 0x42];
+    const CLIENT_ID: [u8; 7] = [0x01, 0x00, 0x0b, 0x82, 0x01, 0xfc, 0x42];
+
+    /// A message from a captured exchange, as sent, padded with zeros to `len`.
+    fn capture(op: u8, yiaddr: [u8; 4], siaddr: [u8; 4], options: &[u8], len: usize) -> Vec<u8> {
+        let mut data = vec![op, 1, 6, 0, 0x00, 0x00, 0x3d, 0x1d, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.extend_from_slice(&yiaddr);
+        data.extend_from_slice(&siaddr);
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&CLIENT_MAC);
+        data.resize(OPTIONS_OFFSET - 4, 0);
+        data.extend_from_slice(&MAGIC_COOKIE);
+        data.extend_from_slice(options);
+        data.resize(len, 0);
+        data
+    }
+
+    fn discover() -> Vec<u8> {
+        let mut options = vec![53, 1, 1, 61, 7];
+        options.extend_from_slice(&CLIENT_ID);
+        options.extend_from_slice(&[50, 4, 0, 0, 0, 0, 55, 4, 1, 3, 6, 42, 255]);
+        capture(BOOTREQUEST, [0; 4], [0; 4], &options, 272)
+    }
+
+    fn offer() -> Vec<u8> {
+        let options = [
+            53, 1, 2, 1, 4, 255, 255, 255, 0, 58, 4, 0, 0, 0x07, 0x08, 59, 4, 0, 0, 0x0c, 0x4e, 51,
+            4, 0, 0, 0x0e, 0x10, 54, 4, 192, 168, 0, 1, 255,
+        ];
+        capture(
+            BOOTREPLY,
+            [192, 168, 0, 10],
+            [192, 168, 0, 1],
+            &options,
+            300,
+        )
+    }
+
+    #[test]
+    fn parses_captures() {
+        let discover = DhcpMessage::from_buffer(discover().into()).unwrap();
+        assert_eq!(discover.op(), BOOTREQUEST);
+        assert_eq!(discover.xid(), 0x3d1d);
+        assert_eq!(discover.chaddr(), MacAddr::new(CLIENT_MAC));
+        assert_eq!(discover.message_type(), Some(DhcpMessageType::Discover));
+        assert_eq!(discover.client_id(), Some(&CLIENT_ID[..]));
+        assert_eq!(discover.requested_addr(), Some(Ipv4Addr::UNSPECIFIED));
+        assert_eq!(
+            discover.option(DHCP_OPTION_PARAMETER_REQUEST_LIST),
+            Some(&[1, 3, 6, 42][..])
+        );
+        assert_eq!(discover.server_id(), None);
+        assert_eq!(discover.lease_time(), None);
+
+        let offer = DhcpMessage::from_buffer(offer().into()).unwrap();
+        assert_eq!(offer.message_type(), Some(DhcpMessageType::Offer));
+        assert_eq!(offer.yiaddr(), Ipv4Addr::new(192, 168, 0, 10));
+        assert_eq!(offer.siaddr(), Ipv4Addr::new(192, 168, 0, 1));
+        assert_eq!(offer.subnet_mask(), Some(Ipv4Addr::new(255, 255, 255, 0)));
+        assert_eq!(offer.renewal_time(), Some(Duration::from_secs(1800)));
+        assert_eq!(offer.rebinding_time(), Some(Duration::from_secs(3150)));
+        assert_eq!(offer.lease_time(), Some(Duration::from_secs(3600)));
+        assert_eq!(offer.server_id(), Some(Ipv4Addr::new(192, 168, 0, 1)));
+        assert!(offer.routers().is_empty());
+        assert!(offer.dns_servers().is_empty());
+    }
+
+    #[test]
+    fn builds_captured_offer() {
+        let discover = DhcpMessage::from_buffer(discover().into()).unwrap();
+        let built = DhcpReplyBuilder::new(&discover, DhcpMessageType::Offer)
+            .yiaddr(Ipv4Addr::new(192, 168, 0, 10))
+            .siaddr(Ipv4Addr::new(192, 168, 0, 1))
+            .subnet_mask(Ipv4Addr::new(255, 255, 255, 0))
+            .renewal_time(Duration::from_secs(1800))
+            .rebinding_time(Duration::from_secs(3150))
+            .lease_time(Duration::from_secs(3600))
+            .server_id(Ipv4Addr::new(192, 168, 0, 1))
+            .build();
+
+        // The capture is padded past the end option.
+        let captured = offer();
+        assert_eq!(built.data[..], captured[..built.data.len()]);
+        assert!(captured[built.data.len()..].iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn builds_ack() {
+        let mut request = DhcpMessage::from_buffer(discover().into()).unwrap();
+        request.set_ciaddr(Ipv4Addr::new(192, 168, 0, 10));
+        request.set_giaddr(Ipv4Addr::new(10, 0, 0, 1));
+        request.set_flags(0x8000);
+
+        let routers = [Ipv4Addr::new(192, 168, 0, 1), Ipv4Addr::new(192, 168, 0, 2)];
+        let ack = DhcpReplyBuilder::new(&request, DhcpMessageType::Ack)
+            .yiaddr(Ipv4Addr::new(192, 168, 0, 10))
+            .lease_time(Duration::from_secs(60))
+            .routers(&routers)
+            .dns_servers(&[])
+            .client_id(request.client_id().unwrap())
+            .lease_time(Duration::from_secs(3600))
+            .build();
+        assert_eq!(ack.op(), BOOTREPLY);
+        assert_eq!(ack.xid(), request.xid());
+        assert!(ack.broadcast());
+        assert_eq!(ack.ciaddr(), Ipv4Addr::new(192, 168, 0, 10));
+        assert_eq!(ack.giaddr(), Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(ack.chaddr(), MacAddr::new(CLIENT_MAC));
+        assert_eq!(ack.routers(), routers);
+        assert!(ack.dns_servers().is_empty());
+        assert_eq!(ack.client_id(), Some(&CLIENT_ID[..]));
+        // Setting the lease time again replaces it, where it was.
+        let codes: Vec<u8> = ack.options().iter().map(|(code, _)| *code).collect();
+        assert_eq!(
+            codes,
+            vec![
+                DHCP_OPTION_MESSAGE_TYPE,
+                DHCP_OPTION_LEASE_TIME,
+                DHCP_OPTION_ROUTER,
+                DHCP_OPTION_CLIENT_ID
+            ]
+        );
+        assert_eq!(ack.lease_time(), Some(Duration::from_secs(3600)));
+
+        // Offers and naks don't carry the client's address.
+        let nak = DhcpReplyBuilder::new(&request, DhcpMessageType::Nak).build();
+        assert_eq!(nak.ciaddr(), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(nak.message_type(), Some(DhcpMessageType::Nak));
+        assert_eq!(nak.options().len(), 1);
+
+        let infinite = DhcpReplyBuilder::new(&request, DhcpMessageType::Ack)
+            .lease_time(Duration::from_secs(u64::MAX))
+            .build();
+        assert_eq!(
+            infinite.option(DHCP_OPTION_LEASE_TIME),
+            Some(&[0xff, 0xff, 0xff, 0xff][..])
+        );
+    }
+
+    #[test]
+    fn rejects_bootp() {
+        let mut data = DhcpMessage::empty().data;
+        data[OPTIONS_OFFSET - 1] = 0;
+        assert!(DhcpMessage::from_buffer(data).is_err());
+        assert!(DhcpMessage::from_buffer(vec![0; 100].into()).is_err());
+    }
+}
@@ -0,0 +1,577 @@
+use crate::*;
+use std::convert::TryInto;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::ops::Range;
+
+/// How a field's value is shown.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Decimal,
+    Hex,
+    Mac,
+    Ipv4,
+    Ipv6,
+    Bytes,
+}
+
+/// A named field of a packet's headers, or its payload, and where it is in the packet's buffer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Field {
+    pub name: &'static str,
+    pub range: Range<usize>,
+    format: Format,
+}
+
+impl Field {
+    /// Shows the value of the field in `data`, as much of it as there is: a number for the short
+    /// fields, an address for addresses, and bytes in hex for the rest.
+    pub fn value(&self, data: &[u8]) -> String {
+        let bytes = match data.get(self.range.clone()) {
+            Some(bytes) => bytes,
+            None => data.get(self.range.start..).unwrap_or(&[]),
+        };
+        let int = || {
+            bytes
+                .iter()
+                .fold(0u64, |int, byte| int << 8 | u64::from(*byte))
+        };
+        match self.format {
+            Format::Decimal if bytes.len() <= 8 => int().to_string(),
+            Format::Hex if bytes.len() <= 8 => {
+                format!("{:#0width$x}", int(), width = 2 + 2 * bytes.len())
+            }
+            Format::Mac if bytes.len() == 6 => MacAddr::new(bytes.try_into().unwrap()).to_string(),
+            Format::Ipv4 if bytes.len() == 4 => {
+                let octets: [u8; 4] = bytes.try_into().unwrap();
+                Ipv4Addr::from(octets).to_string()
+            }
+            Format::Ipv6 if bytes.len() == 16 => {
+                let octets: [u8; 16] = bytes.try_into().unwrap();
+                Ipv6Addr::from(octets).to_string()
+            }
+            _ => hex(bytes),
+        }
+    }
+}
+
+/// Packets whose headers can be laid out field by field, to print them or show where two of them
+/// differ. Packets with an Ethernet header are laid out from it, whatever layer they're a view
+/// of, and otherwise from the outermost header they have.
+pub trait Dissect {
+    /// The packet's whole buffer.
+    fn bytes(&self) -> &[u8];
+
+    /// The fields of the packet's headers, and its payload, in order.
+    fn fields(&self) -> Vec<Field>;
+}
+
+/// The header a packet's layout starts from, and where.
+enum Start {
+    Ethernet(usize),
+    Ip(usize),
+    Upper(u8, usize),
+}
+
+fn start(layer2_offset: Option<usize>, layer3_offset: Option<usize>, upper: Start) -> Start {
+    match (layer2_offset, layer3_offset) {
+        (Some(offset), _) => Start::Ethernet(offset),
+        (None, Some(offset)) => Start::Ip(offset),
+        (None, None) => upper,
+    }
+}
+
+impl Dissect for EthernetFrame {
+    fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn fields(&self) -> Vec<Field> {
+        dissect(&self.data, Start::Ethernet(self.layer2_offset))
+    }
+}
+
+impl Dissect for Ipv4Packet {
+    fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn fields(&self) -> Vec<Field> {
+        let start = start(self.layer2_offset, Some(self.layer3_offset), Start::Ip(0));
+        dissect(&self.data, start)
+    }
+}
+
+impl Dissect for Ipv6Packet {
+    fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn fields(&self) -> Vec<Field> {
+        let start = start(self.layer2_offset, Some(self.layer3_offset), Start::Ip(0));
+        dissect(&self.data, start)
+    }
+}
+
+impl Dissect for UdpSegment {
+    fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn fields(&self) -> Vec<Field> {
+        let upper = Start::Upper(UDP, self.layer4_offset);
+        let start = start(self.layer2_offset, self.layer3_offset, upper);
+        dissect(&self.data, start)
+    }
+}
+
+impl Dissect for TcpSegment {
+    fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn fields(&self) -> Vec<Field> {
+        let upper = Start::Upper(TCP, self.layer4_offset);
+        let start = start(self.layer2_offset, self.layer3_offset, upper);
+        dissect(&self.data, start)
+    }
+}
+
+impl Dissect for IcmpPacket {
+    fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn fields(&self) -> Vec<Field> {
+        let upper = Start::Upper(ICMP, self.layer4_offset);
+        let start = start(self.layer2_offset, self.layer3_offset, upper);
+        dissect(&self.data, start)
+    }
+}
+
+impl Dissect for Icmpv6Packet {
+    fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn fields(&self) -> Vec<Field> {
+        let upper = Start::Upper(ICMPV6, self.layer4_offset);
+        let start = start(self.layer2_offset, self.layer3_offset, upper);
+        dissect(&self.data, start)
+    }
+}
+
+const ICMP: u8 = 1;
+const TCP: u8 = 6;
+const UDP: u8 = 17;
+const ICMPV6: u8 = 58;
+
+fn dissect(data: &[u8], start: Start) -> Vec<Field> {
+    let mut dissector = Dissector {
+        data,
+        fields: vec![],
+    };
+    let offset = match start {
+        Start::Ethernet(offset) | Start::Ip(offset) | Start::Upper(_, offset) => offset,
+    };
+    dissector.field("leading bytes", 0, offset, Format::Bytes);
+    match start {
+        Start::Ethernet(offset) => dissector.ethernet(offset),
+        Start::Ip(offset) => dissector.ip(offset),
+        Start::Upper(protocol, offset) => dissector.upper_layer(protocol, offset, data.len()),
+    }
+    dissector.fields
+}
+
+struct Dissector<'a> {
+    data: &'a [u8],
+    fields: Vec<Field>,
+}
+
+impl<'a> Dissector<'a> {
+    /// Adds the field `len` bytes long at `at`, cut short at the end of the buffer. Empty fields,
+    /// and fields past the end, are left out.
+    fn field(&mut self, name: &'static str, at: usize, len: usize, format: Format) {
+        let end = (at + len).min(self.data.len());
+        if at < end {
+            self.fields.push(Field {
+                name,
+                range: at..end,
+                format,
+            });
+        }
+    }
+
+    fn u16_at(&self, at: usize) -> Option<u16> {
+        let bytes = self.data.get(at..at + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn ethernet(&mut self, at: usize) {
+        self.field("destination MAC", at, 6, Format::Mac);
+        self.field("source MAC", at + 6, 6, Format::Mac);
+        let mut at = at + 12;
+        while let Some(0x8100) | Some(0x88A8) = self.u16_at(at) {
+            self.field("VLAN tag", at, 4, Format::Hex);
+            at += 4;
+        }
+        self.field("EtherType", at, 2, Format::Hex);
+        match self.u16_at(at) {
+            Some(IPV4_ETHER_TYPE) | Some(IPV6_ETHER_TYPE) => self.ip(at + 2),
+            _ => self.field("payload", at + 2, self.data.len(), Format::Bytes),
+        }
+    }
+
+    fn ip(&mut self, at: usize) {
+        match self.data.get(at).map(|byte| byte >> 4) {
+            Some(4) => self.ipv4(at),
+            Some(6) => self.ipv6(at),
+            _ => self.field("payload", at, self.data.len(), Format::Bytes),
+        }
+    }
+
+    fn ipv4(&mut self, at: usize) {
+        self.field("IPv4 version and header length", at, 1, Format::Hex);
+        self.field("DSCP and ECN", at + 1, 1, Format::Hex);
+        self.field("total length", at + 2, 2, Format::Decimal);
+        self.field("identification", at + 4, 2, Format::Decimal);
+        self.field("flags and fragment offset", at + 6, 2, Format::Hex);
+        self.field("TTL", at + 8, 1, Format::Decimal);
+        self.field("protocol", at + 9, 1, Format::Decimal);
+        self.field("IPv4 checksum", at + 10, 2, Format::Hex);
+        self.field("IPv4 source", at + 12, 4, Format::Ipv4);
+        self.field("IPv4 destination", at + 16, 4, Format::Ipv4);
+        if self.data.len() < at + 20 {
+            return;
+        }
+
+        let header_len = usize::from(self.data[at] & 0x0F) * 4;
+        let header_len = header_len.max(20);
+        self.field("IPv4 options", at + 20, header_len - 20, Format::Bytes);
+        let total_len = usize::from(self.u16_at(at + 2).unwrap());
+        let end = if total_len >= header_len {
+            (at + total_len).min(self.data.len())
+        } else {
+            self.data.len()
+        };
+        let first_fragment = self.u16_at(at + 6).unwrap() & 0x1FFF == 0;
+        if first_fragment {
+            self.upper_layer(self.data[at + 9], at + header_len, end);
+        } else {
+            self.field(
+                "payload",
+                at + header_len,
+                end.saturating_sub(at + header_len),
+                Format::Bytes,
+            );
+        }
+        self.field("padding", end, self.data.len() - end, Format::Bytes);
+    }
+
+    fn ipv6(&mut self, at: usize) {
+        self.field(
+            "IPv6 version, traffic class and flow label",
+            at,
+            4,
+            Format::Hex,
+        );
+        self.field("payload length", at + 4, 2, Format::Decimal);
+        self.field("next header", at + 6, 1, Format::Decimal);
+        self.field("hop limit", at + 7, 1, Format::Decimal);
+        self.field("IPv6 source", at + 8, 16, Format::Ipv6);
+        self.field("IPv6 destination", at + 24, 16, Format::Ipv6);
+        if self.data.len() < at + 40 {
+            return;
+        }
+
+        let payload_len = usize::from(self.u16_at(at + 4).unwrap());
+        let end = (at + 40 + payload_len).min(self.data.len());
+        let data = &self.data[..end];
+        let mut headers = Ipv6ExtensionHeaders::new(data, at);
+        let mut first_fragment = true;
+        loop {
+            let offset = headers.offset;
+            match headers.next() {
+                Some(header) => {
+                    if header.header_type == IpProtocol::IPv6_frag {
+                        first_fragment &=
+                            u16::from_be_bytes([header.data[2], header.data[3]]) >> 3 == 0;
+                    }
+                    let len = header.data.len();
+                    self.field("IPv6 extension header", offset, len, Format::Bytes);
+                }
+                None => break,
+            }
+        }
+        let (next_header, offset) = (headers.next_header, headers.offset);
+        if first_fragment {
+            self.upper_layer(next_header, offset, end);
+        } else {
+            self.field("payload", offset, end - offset, Format::Bytes);
+        }
+        self.field("padding", end, self.data.len() - end, Format::Bytes);
+    }
+
+    /// Lays out the upper layer packet of `protocol` at `at`, which ends at `end`.
+    fn upper_layer(&mut self, protocol: u8, at: usize, end: usize) {
+        let header_len = match protocol {
+            UDP => {
+                self.field("UDP source port", at, 2, Format::Decimal);
+                self.field("UDP destination port", at + 2, 2, Format::Decimal);
+                self.field("UDP length", at + 4, 2, Format::Decimal);
+                self.field("UDP checksum", at + 6, 2, Format::Hex);
+                8
+            }
+            TCP => {
+                self.field("TCP source port", at, 2, Format::Decimal);
+                self.field("TCP destination port", at + 2, 2, Format::Decimal);
+                self.field("TCP sequence number", at + 4, 4, Format::Decimal);
+                self.field("TCP acknowledgment number", at + 8, 4, Format::Decimal);
+                self.field("TCP data offset and flags", at + 12, 2, Format::Hex);
+                self.field("TCP window", at + 14, 2, Format::Decimal);
+                self.field("TCP checksum", at + 16, 2, Format::Hex);
+                self.field("TCP urgent pointer", at + 18, 2, Format::Decimal);
+                let data_offset = match self.data.get(at + 12) {
+                    Some(byte) => (usize::from(byte >> 4) * 4).max(20),
+                    None => 20,
+                };
+                self.field("TCP options", at + 20, data_offset - 20, Format::Bytes);
+                data_offset
+            }
+            ICMP | ICMPV6 => {
+                self.field("ICMP type", at, 1, Format::Decimal);
+                self.field("ICMP code", at + 1, 1, Format::Decimal);
+                self.field("ICMP checksum", at + 2, 2, Format::Hex);
+                self.field(
+                    "ICMP body",
+                    at + 4,
+                    end.saturating_sub(at + 4),
+                    Format::Bytes,
+                );
+                return;
+            }
+            _ => 0,
+        };
+        let at = at + header_len;
+        self.field("payload", at, end.saturating_sub(at), Format::Bytes);
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    hex.join(" ")
+}
+
+/// Shows `data` 16 bytes to a line, each line starting with its offset and ending with the bytes
+/// that are printable ASCII, like `hexdump -C`.
+pub fn hex_dump(data: &[u8]) -> String {
+    let mut dump = String::new();
+    for (line, bytes) in data.chunks(16).enumerate() {
+        let ascii: String = bytes
+            .iter()
+            .map(|byte| match byte {
+                0x20..=0x7E => char::from(*byte),
+                _ => '.',
+            })
+            .collect();
+        dump.push_str(&format!(
+            "{:08x}  {:<47}  |{}|\n",
+            line * 16,
+            hex(bytes),
+            ascii
+        ));
+    }
+    dump
+}
+
+/// Shows the packet field by field, a line each, with the offset and value of the field. Payloads
+/// and options show their first 16 bytes.
+pub fn pretty_print<P: Dissect>(packet: &P) -> String {
+    let data = packet.bytes();
+    let mut print = String::new();
+    for field in packet.fields() {
+        let value = if field.format == Format::Bytes && field.range.len() > 16 {
+            let start = field.range.start;
+            format!(
+                "{} ... ({} bytes)",
+                hex(&data[start..start + 16]),
+                field.range.len()
+            )
+        } else {
+            field.value(data)
+        };
+        print.push_str(&format!(
+            "{:>5}  {:<42} {}\n",
+            field.range.start, field.name, value
+        ));
+    }
+    print
+}
+
+/// A field that differs between two packets, with its value in each, and the offset of the first
+/// byte of it that does.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldDiff {
+    pub field: &'static str,
+    pub offset: usize,
+    pub left: String,
+    pub right: String,
+}
+
+impl fmt::Display for FieldDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} differs: {} vs {} at offset {}",
+            self.field, self.left, self.right, self.offset
+        )
+    }
+}
+
+/// Compares two packets field by field, laid out as `left` is, returning the fields that differ,
+/// and their lengths if they do. Long fields, such as payloads, show the first byte that differs.
+pub fn diff_packets<L: Dissect, R: Dissect>(left: &L, right: &R) -> Vec<FieldDiff> {
+    let (left, fields, right) = (left.bytes(), left.fields(), right.bytes());
+    let mut diffs = vec![];
+    for field in fields {
+        let range = field.range.clone();
+        let right_bytes = right.get(range.start..range.end.min(right.len()));
+        if right_bytes == Some(&left[range.clone()]) {
+            continue;
+        }
+        let first_difference = (range.start..range.end)
+            .find(|at| right.get(*at) != Some(&left[*at]))
+            .unwrap_or(range.start);
+        let (left_value, right_value) = if field.format == Format::Bytes && range.len() > 1 {
+            let byte = |data: &[u8]| match data.get(first_difference) {
+                Some(byte) => format!("{:#04x}", byte),
+                None => String::from("nothing"),
+            };
+            (byte(left), byte(right))
+        } else if right.len() < range.end {
+            (field.value(left), String::from("nothing"))
+        } else {
+            (field.value(left), field.value(right))
+        };
+        diffs.push(FieldDiff {
+            field: field.name,
+            offset: first_difference,
+            left: left_value,
+            right: right_value,
+        });
+    }
+    if left.len() != right.len() {
+        diffs.push(FieldDiff {
+            field: "length",
+            offset: left.len().min(right.len()),
+            left: left.len().to_string(),
+            right: right.len().to_string(),
+        });
+    }
+    diffs
+}
+
+/// Asserts two packets are the same byte for byte, like `assert_eq!`, but on failure lists the
+/// fields that differ, such as `TTL differs: 64 vs 63 at offset 22`, rather than the whole
+/// buffers. The packets can be of any types that implement `Dissect`.
+#[macro_export]
+macro_rules! assert_packets_eq {
+    ($left:expr, $right:expr $(,)?) => {
+        match (&$left, &$right) {
+            (left, right) => {
+                let diffs = $crate::diff_packets(left, right);
+                if !diffs.is_empty() {
+                    let diffs: Vec<String> = diffs.iter().map(|diff| diff.to_string()).collect();
+                    panic!("assertion failed: packets differ\n  {}", diffs.join("\n  "));
+                }
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn udp_frame(ttl: u8, payload: &[u8]) -> EthernetFrame {
+        EthernetFrame::builder()
+            .ipv4(|ip| {
+                ip.src(Ipv4Addr::new(10, 0, 0, 1))
+                    .dst(Ipv4Addr::new(10, 0, 0, 2))
+                    .ttl(ttl)
+                    .udp(|udp| udp.sport(1000).dport(53).payload(payload))
+            })
+            .build()
+    }
+
+    #[test]
+    fn diffs_fields() {
+        let left = udp_frame(64, &[1, 2, 3]);
+        assert_packets_eq!(left, left.clone());
+        assert!(diff_packets(&left, &left.clone()).is_empty());
+
+        let right = udp_frame(63, &[1, 2, 4]);
+        let diffs: Vec<String> = diff_packets(&left, &right)
+            .iter()
+            .map(|diff| diff.to_string())
+            .collect();
+        assert_eq!(diffs[0], "TTL differs: 64 vs 63 at offset 22");
+        assert!(diffs[1].starts_with("IPv4 checksum differs"));
+        assert!(diffs[2].starts_with("UDP checksum differs"));
+        assert_eq!(diffs[3], "payload differs: 0x03 vs 0x04 at offset 44");
+        assert_eq!(diffs.len(), 4);
+
+        let short = udp_frame(64, &[1, 2]);
+        let diffs = diff_packets(&left, &short);
+        assert_eq!(
+            diffs.last().unwrap().to_string(),
+            "length differs: 45 vs 44 at offset 44"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "TTL differs: 64 vs 63 at offset 22")]
+    fn assert_packets_eq_panics() {
+        assert_packets_eq!(udp_frame(64, &[]), udp_frame(63, &[]));
+    }
+
+    #[test]
+    fn truncated_later_fragment() {
+        let mut data = vec![0; 14 + 24];
+        data[12..14].copy_from_slice(&[0x08, 0x00]);
+        data[14] = 0x4F;
+        data[16..18].copy_from_slice(&24u16.to_be_bytes());
+        data[20..22].copy_from_slice(&16u16.to_be_bytes());
+        let frame = EthernetFrame::from_buffer(data.into(), 0).unwrap();
+        let fields = frame.fields();
+        let options = fields
+            .iter()
+            .find(|field| field.name == "IPv4 options")
+            .unwrap();
+        assert_eq!(options.range, 34..38);
+        assert!(fields.iter().all(|field| field.name != "payload"));
+        assert!(!pretty_print(&frame).is_empty());
+        assert!(diff_packets(&frame, &frame.clone()).is_empty());
+    }
+
+    #[test]
+    fn prints() {
+        let packet = Ipv6Packet::builder()
+            .src(Ipv6Addr::LOCALHOST)
+            .dst(Ipv6Addr::LOCALHOST)
+            .tcp(|tcp| tcp.sport(1000).dport(80).payload(&[0; 20]))
+            .build();
+        let print = pretty_print(&packet);
+        let lines: Vec<&str> = print.lines().collect();
+        assert!(lines[3].starts_with("    7  hop limit"));
+        assert!(lines[3].ends_with(" 64"));
+        assert!(lines[4].ends_with(" ::1"));
+        assert!(lines[7].ends_with(" 80"));
+        assert!(lines.last().unwrap().ends_with("00 ... (20 bytes)"));
+
+        assert_eq!(
+            hex_dump(b"route-rs\x00\x01"),
+            "00000000  72 6f 75 74 65 2d 72 73 00 01                    |route-rs..|\n"
+        );
+    }
+}
@@ -0,0 +1,731 @@
+use crate::*;
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+pub const DNS_PORT: u16 = 53;
+
+/// Record types, per RFC 1035 and RFC 3596.
+pub const DNS_TYPE_A: u16 = 1;
+pub const DNS_TYPE_NS: u16 = 2;
+pub const DNS_TYPE_CNAME: u16 = 5;
+pub const DNS_TYPE_SOA: u16 = 6;
+pub const DNS_TYPE_PTR: u16 = 12;
+pub const DNS_TYPE_MX: u16 = 15;
+pub const DNS_TYPE_TXT: u16 = 16;
+pub const DNS_TYPE_AAAA: u16 = 28;
+
+pub const DNS_CLASS_IN: u16 = 1;
+
+/// Response codes, per RFC 1035.
+pub const DNS_RCODE_NO_ERROR: u8 = 0;
+pub const DNS_RCODE_FORMAT_ERROR: u8 = 1;
+pub const DNS_RCODE_SERVER_FAILURE: u8 = 2;
+pub const DNS_RCODE_NAME_ERROR: u8 = 3;
+pub const DNS_RCODE_NOT_IMPLEMENTED: u8 = 4;
+pub const DNS_RCODE_REFUSED: u8 = 5;
+
+/// Bits of the flags field.
+const RESPONSE_FLAG: u16 = 0x8000;
+const AUTHORITATIVE_FLAG: u16 = 0x0400;
+const TRUNCATED_FLAG: u16 = 0x0200;
+const RECURSION_DESIRED_FLAG: u16 = 0x0100;
+const RECURSION_AVAILABLE_FLAG: u16 = 0x0080;
+const OPCODE_MASK: u16 = 0x7800;
+const RCODE_MASK: u16 = 0x000F;
+
+const HEADER_LEN: usize = 12;
+const MAX_LABEL_LEN: usize = 63;
+const MAX_NAME_LEN: usize = 255;
+
+/// Compression pointers have their two high bits set, and point at most 14 bits in.
+const POINTER_FLAG: u8 = 0xC0;
+const MAX_POINTER: usize = 0x3FFF;
+
+/// The data of a resource record. Names in the data are decompressed when parsed, and
+/// compressed again against the rest of the message when it's written, so records can be moved
+/// between messages safely. Types whose data isn't parsed are kept as Other.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DnsRecordData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(String),
+    Ns(String),
+    Ptr(String),
+    Mx {
+        preference: u16,
+        exchange: String,
+    },
+    /// The character strings of the record, without their length bytes.
+    Txt(Vec<Vec<u8>>),
+    Soa {
+        mname: String,
+        rname: String,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+    Other {
+        rtype: u16,
+        data: Vec<u8>,
+    },
+}
+
+impl DnsRecordData {
+    pub fn rtype(&self) -> u16 {
+        match self {
+            DnsRecordData::A(_) => DNS_TYPE_A,
+            DnsRecordData::Aaaa(_) => DNS_TYPE_AAAA,
+            DnsRecordData::Cname(_) => DNS_TYPE_CNAME,
+            DnsRecordData::Ns(_) => DNS_TYPE_NS,
+            DnsRecordData::Ptr(_) => DNS_TYPE_PTR,
+            DnsRecordData::Mx { .. } => DNS_TYPE_MX,
+            DnsRecordData::Txt(_) => DNS_TYPE_TXT,
+            DnsRecordData::Soa { .. } => DNS_TYPE_SOA,
+            DnsRecordData::Other { rtype, .. } => *rtype,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnsQuestion {
+    /// The name asked about, as dotted labels without the trailing dot.
+    pub name: String,
+    pub qtype: u16,
+    pub qclass: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnsRecord {
+    /// The name the record is for, as dotted labels without the trailing dot.
+    pub name: String,
+    /// The class, or for OPT records the largest UDP payload the sender accepts.
+    pub rclass: u16,
+    /// The TTL in seconds, or for OPT records the extended flags.
+    pub ttl: u32,
+    pub data: DnsRecordData,
+}
+
+impl DnsRecord {
+    pub fn rtype(&self) -> u16 {
+        self.data.rtype()
+    }
+}
+
+/// A DNS message, per RFC 1035, parsed into its header, questions and resource records. Unlike
+/// the other packet types it isn't a view over its data: `from_buffer` parses a message, and
+/// `to_bytes` writes it out again, compressing its names.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnsMessage {
+    pub id: u16,
+    pub flags: u16,
+    pub questions: Vec<DnsQuestion>,
+    pub answers: Vec<DnsRecord>,
+    pub authorities: Vec<DnsRecord>,
+    pub additionals: Vec<DnsRecord>,
+}
+
+impl DnsMessage {
+    pub fn from_buffer(data: &[u8]) -> Result<DnsMessage, &'static str> {
+        let mut reader = Reader { data, offset: 0 };
+        if data.len() < HEADER_LEN {
+            return Err("Data is too short to be a DnsMessage");
+        }
+        let id = reader.u16()?;
+        let flags = reader.u16()?;
+        let counts = [reader.u16()?, reader.u16()?, reader.u16()?, reader.u16()?];
+
+        let mut questions = vec![];
+        for _ in 0..counts[0] {
+            questions.push(DnsQuestion {
+                name: reader.name()?,
+                qtype: reader.u16()?,
+                qclass: reader.u16()?,
+            });
+        }
+        let mut sections = vec![];
+        for count in &counts[1..] {
+            let mut records = vec![];
+            for _ in 0..*count {
+                records.push(reader.record()?);
+            }
+            sections.push(records);
+        }
+        let additionals = sections.pop().unwrap();
+        let authorities = sections.pop().unwrap();
+        let answers = sections.pop().unwrap();
+
+        Ok(DnsMessage {
+            id,
+            flags,
+            questions,
+            answers,
+            authorities,
+            additionals,
+        })
+    }
+
+    /// Makes a query for `name` and `qtype`, of class IN, asking for recursion.
+    pub fn query(id: u16, name: &str, qtype: u16) -> DnsMessage {
+        DnsMessage {
+            id,
+            flags: RECURSION_DESIRED_FLAG,
+            questions: vec![DnsQuestion {
+                name: name.to_string(),
+                qtype,
+                qclass: DNS_CLASS_IN,
+            }],
+            answers: vec![],
+            authorities: vec![],
+            additionals: vec![],
+        }
+    }
+
+    /// Writes the message out, compressing names against those written before them. Fails if a
+    /// name has an empty label, or one too long, or there are more than 65535 entries in a
+    /// section.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, &'static str> {
+        let mut writer = Writer::default();
+        writer.u16(self.id);
+        writer.u16(self.flags);
+        for len in &[
+            self.questions.len(),
+            self.answers.len(),
+            self.authorities.len(),
+            self.additionals.len(),
+        ] {
+            writer.u16((*len).try_into().map_err(|_| "Too many DNS entries")?);
+        }
+
+        for question in &self.questions {
+            writer.name(&question.name)?;
+            writer.u16(question.qtype);
+            writer.u16(question.qclass);
+        }
+        for record in self
+            .answers
+            .iter()
+            .chain(&self.authorities)
+            .chain(&self.additionals)
+        {
+            writer.record(record)?;
+        }
+        Ok(writer.data)
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.flags & RESPONSE_FLAG != 0
+    }
+
+    pub fn opcode(&self) -> u8 {
+        ((self.flags & OPCODE_MASK) >> 11) as u8
+    }
+
+    pub fn authoritative(&self) -> bool {
+        self.flags & AUTHORITATIVE_FLAG != 0
+    }
+
+    pub fn truncated(&self) -> bool {
+        self.flags & TRUNCATED_FLAG != 0
+    }
+
+    pub fn recursion_desired(&self) -> bool {
+        self.flags & RECURSION_DESIRED_FLAG != 0
+    }
+
+    pub fn recursion_available(&self) -> bool {
+        self.flags & RECURSION_AVAILABLE_FLAG != 0
+    }
+
+    pub fn rcode(&self) -> u8 {
+        (self.flags & RCODE_MASK) as u8
+    }
+
+    fn set_flag(&mut self, flag: u16, set: bool) {
+        if set {
+            self.flags |= flag;
+        } else {
+            self.flags &= !flag;
+        }
+    }
+}
+
+impl TryFrom<UdpSegment> for DnsMessage {
+    type Error = &'static str;
+
+    fn try_from(segment: UdpSegment) -> Result<Self, Self::Error> {
+        DnsMessage::from_buffer(&segment.payload())
+    }
+}
+
+/// Builds a response to a query, starting from its ID, opcode, recursion desired flag and
+/// questions, with no records and a response code of no error.
+pub struct DnsResponseBuilder {
+    message: DnsMessage,
+}
+
+impl DnsResponseBuilder {
+    pub fn new(query: &DnsMessage) -> Self {
+        let flags = RESPONSE_FLAG | (query.flags & (OPCODE_MASK | RECURSION_DESIRED_FLAG));
+        DnsResponseBuilder {
+            message: DnsMessage {
+                id: query.id,
+                flags,
+                questions: query.questions.clone(),
+                answers: vec![],
+                authorities: vec![],
+                additionals: vec![],
+            },
+        }
+    }
+
+    pub fn answer(mut self, record: DnsRecord) -> Self {
+        self.message.answers.push(record);
+        self
+    }
+
+    pub fn authority(mut self, record: DnsRecord) -> Self {
+        self.message.authorities.push(record);
+        self
+    }
+
+    pub fn additional(mut self, record: DnsRecord) -> Self {
+        self.message.additionals.push(record);
+        self
+    }
+
+    pub fn rcode(mut self, rcode: u8) -> Self {
+        self.message.flags = (self.message.flags & !RCODE_MASK) | (u16::from(rcode) & RCODE_MASK);
+        self
+    }
+
+    pub fn authoritative(mut self, authoritative: bool) -> Self {
+        self.message.set_flag(AUTHORITATIVE_FLAG, authoritative);
+        self
+    }
+
+    pub fn recursion_available(mut self, recursion_available: bool) -> Self {
+        self.message
+            .set_flag(RECURSION_AVAILABLE_FLAG, recursion_available);
+        self
+    }
+
+    pub fn build(self) -> DnsMessage {
+        self.message
+    }
+}
+
+/// Reads the fields of a message in order, from `offset`.
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], &'static str> {
+        let bytes = self
+            .data
+            .get(self.offset..self.offset + len)
+            .ok_or("DnsMessage is truncated")?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, &'static str> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, &'static str> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, &'static str> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    /// Reads a name, following compression pointers. Each pointer must point before the one
+    /// that led to it, so a name can't loop.
+    fn name(&mut self) -> Result<String, &'static str> {
+        let mut labels: Vec<String> = vec![];
+        let mut offset = self.offset;
+        let mut end = None;
+        let mut limit = offset;
+        let mut name_len = 1;
+        loop {
+            let len = *self.data.get(offset).ok_or("DnsMessage is truncated")?;
+            match len & POINTER_FLAG {
+                0 if len == 0 => break,
+                0 => {
+                    let len = usize::from(len);
+                    let label = self
+                        .data
+                        .get(offset + 1..offset + 1 + len)
+                        .ok_or("DnsMessage is truncated")?;
+                    name_len += len + 1;
+                    if name_len > MAX_NAME_LEN {
+                        return Err("DNS name is too long");
+                    }
+                    labels.push(String::from_utf8_lossy(label).into_owned());
+                    offset += 1 + len;
+                }
+                POINTER_FLAG => {
+                    let pointer = self
+                        .data
+                        .get(offset..offset + 2)
+                        .ok_or("DnsMessage is truncated")?;
+                    let pointer =
+                        usize::from(u16::from_be_bytes([pointer[0], pointer[1]])) & MAX_POINTER;
+                    if pointer >= limit {
+                        return Err("DNS name has a compression pointer that doesn't point back");
+                    }
+                    end = end.or(Some(offset + 2));
+                    limit = pointer;
+                    offset = pointer;
+                }
+                _ => return Err("DNS name has an unknown label type"),
+            }
+        }
+        self.offset = end.unwrap_or(offset + 1);
+        Ok(labels.join("."))
+    }
+
+    fn record(&mut self) -> Result<DnsRecord, &'static str> {
+        let name = self.name()?;
+        let rtype = self.u16()?;
+        let rclass = self.u16()?;
+        let ttl = self.u32()?;
+        let rdlen = usize::from(self.u16()?);
+        let end = self.offset + rdlen;
+        if end > self.data.len() {
+            return Err("DnsMessage is truncated");
+        }
+
+        let data = match (rtype, rdlen) {
+            (DNS_TYPE_A, 4) => {
+                let addr: [u8; 4] = self.bytes(4)?.try_into().unwrap();
+                DnsRecordData::A(Ipv4Addr::from(addr))
+            }
+            (DNS_TYPE_AAAA, 16) => {
+                let addr: [u8; 16] = self.bytes(16)?.try_into().unwrap();
+                DnsRecordData::Aaaa(Ipv6Addr::from(addr))
+            }
+            (DNS_TYPE_CNAME, _) => DnsRecordData::Cname(self.name()?),
+            (DNS_TYPE_NS, _) => DnsRecordData::Ns(self.name()?),
+            (DNS_TYPE_PTR, _) => DnsRecordData::Ptr(self.name()?),
+            (DNS_TYPE_MX, _) => DnsRecordData::Mx {
+                preference: self.u16()?,
+                exchange: self.name()?,
+            },
+            (DNS_TYPE_TXT, _) => {
+                let mut strings = vec![];
+                while self.offset < end {
+                    let len = usize::from(self.u8()?);
+                    strings.push(self.bytes(len)?.to_vec());
+                }
+                DnsRecordData::Txt(strings)
+            }
+            (DNS_TYPE_SOA, _) => DnsRecordData::Soa {
+                mname: self.name()?,
+                rname: self.name()?,
+                serial: self.u32()?,
+                refresh: self.u32()?,
+                retry: self.u32()?,
+                expire: self.u32()?,
+                minimum: self.u32()?,
+            },
+            _ => DnsRecordData::Other {
+                rtype,
+                data: self.bytes(rdlen)?.to_vec(),
+            },
+        };
+        if self.offset != end {
+            return Err("DNS record data doesn't match its length");
+        }
+
+        Ok(DnsRecord {
+            name,
+            rclass,
+            ttl,
+            data,
+        })
+    }
+}
+
+/// Writes the fields of a message in order, remembering where each name suffix was written so
+/// later names can point at it.
+#[derive(Default)]
+struct Writer {
+    data: Vec<u8>,
+    suffixes: HashMap<String, usize>,
+}
+
+impl Writer {
+    fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    /// Writes `name`, ending with a pointer to the longest suffix already written, if any.
+    /// Suffixes are matched without regard to case.
+    fn name(&mut self, name: &str) -> Result<(), &'static str> {
+        let labels: Vec<&str> = if name.is_empty() {
+            vec![]
+        } else {
+            name.split('.').collect()
+        };
+        if labels.iter().map(|label| label.len() + 1).sum::<usize>() + 1 > MAX_NAME_LEN {
+            return Err("DNS name is too long");
+        }
+
+        for i in 0..labels.len() {
+            let suffix = labels[i..].join(".").to_lowercase();
+            if let Some(offset) = self.suffixes.get(&suffix) {
+                self.u16(((u16::from(POINTER_FLAG)) << 8) | *offset as u16);
+                return Ok(());
+            }
+            let label = labels[i];
+            if label.is_empty() || label.len() > MAX_LABEL_LEN {
+                return Err("DNS name has an empty label, or one too long");
+            }
+            if self.data.len() <= MAX_POINTER {
+                self.suffixes.insert(suffix, self.data.len());
+            }
+            self.data.push(label.len() as u8);
+            self.data.extend_from_slice(label.as_bytes());
+        }
+        self.data.push(0);
+        Ok(())
+    }
+
+    fn record(&mut self, record: &DnsRecord) -> Result<(), &'static str> {
+        self.name(&record.name)?;
+        self.u16(record.rtype());
+        self.u16(record.rclass);
+        self.u32(record.ttl);
+        let rdlen_offset = self.data.len();
+        self.u16(0);
+
+        match &record.data {
+            DnsRecordData::A(addr) => self.data.extend_from_slice(&addr.octets()),
+            DnsRecordData::Aaaa(addr) => self.data.extend_from_slice(&addr.octets()),
+            DnsRecordData::Cname(name) | DnsRecordData::Ns(name) | DnsRecordData::Ptr(name) => {
+                self.name(name)?
+            }
+            DnsRecordData::Mx {
+                preference,
+                exchange,
+            } => {
+                self.u16(*preference);
+                self.name(exchange)?;
+            }
+            DnsRecordData::Txt(strings) => {
+                for string in strings {
+                    let len: u8 = string
+                        .len()
+                        .try_into()
+                        .map_err(|_| "DNS character string is too long")?;
+                    self.data.push(len);
+                    self.data.extend_from_slice(string);
+                }
+            }
+            DnsRecordData::Soa {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => {
+                self.name(mname)?;
+                self.name(rname)?;
+                for value in &[serial, refresh, retry, expire, minimum] {
+                    self.u32(**value);
+                }
+            }
+            DnsRecordData::Other { data, .. } => self.data.extend_from_slice(data),
+        }
+
+        let rdlen: u16 = (self.data.len() - rdlen_offset - 2)
+            .try_into()
+            .map_err(|_| "DNS record data is too long")?;
+        self.data[rdlen_offset..rdlen_offset + 2].copy_from_slice(&rdlen.to_be_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A response to a query for www.example.com's address, which is an alias of example.com,
+    /// with each name after the first compressed, and an EDNS OPT record.
+    const RESPONSE: [u8; 74] = [
+        0xb1, 0x6e, 0x81, 0x80, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, // Header
+        0x03, b'w', b'w', b'w', 0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o',
+        b'm', 0x00, 0x00, 0x01, 0x00, 0x01, // Question
+        0xc0, 0x0c, 0x00, 0x05, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x02, 0xc0,
+        0x10, // CNAME
+        0xc0, 0x10, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01, 0x51, 0x80, 0x00, 0x04, 93, 184, 216,
+        34, // A
+        0x00, 0x00, 0x29, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // OPT
+    ];
+
+    #[test]
+    fn parses_response() {
+        let message = DnsMessage::from_buffer(&RESPONSE).unwrap();
+        assert_eq!(message.id, 0xb16e);
+        assert!(message.is_response());
+        assert!(message.recursion_desired());
+        assert!(message.recursion_available());
+        assert!(!message.authoritative());
+        assert_eq!(message.rcode(), DNS_RCODE_NO_ERROR);
+        assert_eq!(
+            message.questions,
+            vec![DnsQuestion {
+                name: "www.example.com".to_string(),
+                qtype: DNS_TYPE_A,
+                qclass: DNS_CLASS_IN,
+            }]
+        );
+        assert_eq!(
+            message.answers,
+            vec![
+                DnsRecord {
+                    name: "www.example.com".to_string(),
+                    rclass: DNS_CLASS_IN,
+                    ttl: 3600,
+                    data: DnsRecordData::Cname("example.com".to_string()),
+                },
+                DnsRecord {
+                    name: "example.com".to_string(),
+                    rclass: DNS_CLASS_IN,
+                    ttl: 86400,
+                    data: DnsRecordData::A(Ipv4Addr::new(93, 184, 216, 34)),
+                },
+            ]
+        );
+        assert_eq!(message.authorities, vec![]);
+        assert_eq!(
+            message.additionals,
+            vec![DnsRecord {
+                name: "".to_string(),
+                rclass: 4096,
+                ttl: 0,
+                data: DnsRecordData::Other {
+                    rtype: 41,
+                    data: vec![]
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn writes_compressed() {
+        let message = DnsMessage::from_buffer(&RESPONSE).unwrap();
+        assert_eq!(message.to_bytes().unwrap(), RESPONSE.to_vec());
+    }
+
+    #[test]
+    fn builds_response() {
+        let query = DnsMessage::query(7, "gateway.route-rs.local", DNS_TYPE_A);
+        let query = DnsMessage::from_buffer(&query.to_bytes().unwrap()).unwrap();
+        assert!(!query.is_response());
+        assert!(query.recursion_desired());
+
+        let response = DnsResponseBuilder::new(&query)
+            .authoritative(true)
+            .answer(DnsRecord {
+                name: "Gateway.route-rs.local".to_string(),
+                rclass: DNS_CLASS_IN,
+                ttl: 60,
+                data: DnsRecordData::A(Ipv4Addr::new(10, 0, 0, 1)),
+            })
+            .additional(DnsRecord {
+                name: "route-rs.local".to_string(),
+                rclass: DNS_CLASS_IN,
+                ttl: 60,
+                data: DnsRecordData::Txt(vec![b"hello".to_vec(), vec![]]),
+            })
+            .build();
+        let bytes = response.to_bytes().unwrap();
+        // The answer's name points at the question's, whatever its case.
+        assert_eq!(bytes[40..42], [0xc0, 0x0c]);
+
+        let parsed = DnsMessage::from_buffer(&bytes).unwrap();
+        assert_eq!(parsed.id, 7);
+        assert!(parsed.is_response());
+        assert!(parsed.authoritative());
+        assert!(parsed.recursion_desired());
+        assert!(!parsed.recursion_available());
+        assert_eq!(parsed.questions, query.questions);
+        // A name written as a pointer is read back in the case it was first written in.
+        assert_eq!(parsed.answers[0].name, "gateway.route-rs.local");
+        assert_eq!(parsed.answers[0].data, response.answers[0].data);
+        assert_eq!(parsed.additionals, response.additionals);
+
+        let refused = DnsResponseBuilder::new(&query)
+            .rcode(DNS_RCODE_REFUSED)
+            .build();
+        assert_eq!(refused.rcode(), DNS_RCODE_REFUSED);
+        assert!(refused.answers.is_empty());
+    }
+
+    #[test]
+    fn round_trips_records() {
+        let query = DnsMessage::query(1, "example.com", DNS_TYPE_MX);
+        let record = |data| DnsRecord {
+            name: "example.com".to_string(),
+            rclass: DNS_CLASS_IN,
+            ttl: 300,
+            data,
+        };
+        let response = DnsResponseBuilder::new(&query)
+            .answer(record(DnsRecordData::Mx {
+                preference: 10,
+                exchange: "mail.example.com".to_string(),
+            }))
+            .answer(record(DnsRecordData::Aaaa(Ipv6Addr::new(
+                0x2001, 0xdb8, 0, 0, 0, 0, 0, 1,
+            ))))
+            .authority(record(DnsRecordData::Ns("ns.example.com".to_string())))
+            .authority(record(DnsRecordData::Soa {
+                mname: "ns.example.com".to_string(),
+                rname: "hostmaster.example.com".to_string(),
+                serial: 2020010100,
+                refresh: 7200,
+                retry: 3600,
+                expire: 1209600,
+                minimum: 300,
+            }))
+            .additional(record(DnsRecordData::Ptr("example.com".to_string())))
+            .build();
+        let parsed = DnsMessage::from_buffer(&response.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed, response);
+    }
+
+    #[test]
+    fn rejects_malformed() {
+        assert!(DnsMessage::from_buffer(&RESPONSE[..11]).is_err());
+        // Claims an answer more than it has.
+        let mut data = RESPONSE.to_vec();
+        data[7] = 3;
+        assert!(DnsMessage::from_buffer(&data).is_err());
+        // A pointer to itself.
+        let mut data = RESPONSE.to_vec();
+        data[33..35].copy_from_slice(&[0xc0, 33]);
+        assert!(DnsMessage::from_buffer(&data).is_err());
+
+        let mut query = DnsMessage::query(1, "a..b", DNS_TYPE_A);
+        assert!(query.to_bytes().is_err());
+        query.questions[0].name = "a".repeat(64);
+        assert!(query.to_bytes().is_err());
+        query.questions[0].name = "a".repeat(63);
+        assert!(query.to_bytes().is_ok());
+    }
+}
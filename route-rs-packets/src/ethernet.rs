@@ -2,6 +2,50 @@ use crate::*;
 use std::borrow::Cow;
 use std::convert::{TryFrom, TryInto};
 
+/// The MTU of standard Ethernet, the longest payload, such as an IP packet, its frames carry.
+pub const ETHERNET_MTU: usize = 1500;
+
+/// The longest jumbo frame most switches and NICs accept, from the destination MAC to the end of
+/// the payload. Nothing here assumes frames of the standard MTU, so jumbo frames build and parse
+/// like any other.
+pub const MAX_JUMBO_FRAME_LEN: usize = 9216;
+
+/// An 802.1Q tag, identifying the VLAN a frame belongs to on a trunk.
+#[derive(Eq, Clone, Copy, Hash, PartialEq, Debug)]
+pub struct VlanTag {
+    /// Priority code point, the 802.1p class of service, 3 bits.
+    pub pcp: u8,
+    /// Drop eligible indicator.
+    pub dei: bool,
+    /// VLAN identifier, 12 bits.
+    pub vid: u16,
+}
+
+impl VlanTag {
+    /// A tag for VLAN `vid`, with priority 0 and not drop eligible.
+    pub fn new(vid: u16) -> VlanTag {
+        VlanTag {
+            pcp: 0,
+            dei: false,
+            vid,
+        }
+    }
+
+    /// Reads a tag from its tag control information field.
+    pub fn from_tci(tci: u16) -> VlanTag {
+        VlanTag {
+            pcp: (tci >> 13) as u8,
+            dei: tci & 0x1000 != 0,
+            vid: tci & 0x0FFF,
+        }
+    }
+
+    /// Returns the tag control information field of the tag.
+    pub fn tci(&self) -> u16 {
+        (u16::from(self.pcp & 0x07) << 13) | (u16::from(self.dei) << 12) | (self.vid & 0x0FFF)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct EthernetFrame {
     pub data: PacketData,
@@ -19,14 +63,35 @@ impl EthernetFrame {
         // |---6 byte Dest_MAC--|---6 byte Src_MAC---|--2 Byte EtherType---|
         // We could support other formats for the frames, but IP sits atop Ethernet II
 
-        if frame.len() < 14 {
+        // An 802.1Q tag sits between the Src_MAC and EtherType, starting with the 0x8100 TPID.
+        // |---6 byte Dest_MAC--|---6 byte Src_MAC---|--2 byte TPID--|--2 byte TCI--|--2 Byte EtherType---|
+        // Provider bridges (802.1ad, QinQ) stack a service tag, with the 0x88A8 TPID, in front of
+        // it, and tags may be stacked further.
+
+        if frame.len() < layer2_offset + 14 {
             return Err("Frame is less than the minimum of 14 bytes");
         }
 
+        let mut payload_offset = 14 + layer2_offset;
+        loop {
+            let tpid = u16::from_be_bytes(
+                frame[payload_offset - 2..payload_offset]
+                    .try_into()
+                    .unwrap(),
+            );
+            if tpid != VLAN_ETHER_TYPE && tpid != QINQ_ETHER_TYPE {
+                break;
+            }
+            if frame.len() < payload_offset + 4 {
+                return Err("Frame is too short to contain its VLAN tag");
+            }
+            payload_offset += 4;
+        }
+
         Ok(EthernetFrame {
             data: frame,
             layer2_offset,
-            payload_offset: 14 + layer2_offset, // To support 802.1Q VLAN Tagging, this number may be different.
+            payload_offset,
         })
     }
 
@@ -35,7 +100,7 @@ impl EthernetFrame {
     pub fn empty() -> EthernetFrame {
         let mut data = vec![];
         data.resize(14, 0);
-        EthernetFrame::from_buffer(data, 0).unwrap()
+        EthernetFrame::from_buffer(data.into(), 0).unwrap()
     }
 
     pub fn dest_mac(&self) -> MacAddr {
@@ -56,12 +121,93 @@ impl EthernetFrame {
         self.data[6..12].copy_from_slice(&mac.bytes[..6]);
     }
 
+    /// Returns the EtherType of the payload, which follows the VLAN tag of tagged frames.
     pub fn ether_type(&self) -> u16 {
-        u16::from_be_bytes(self.data[12..=13].try_into().unwrap())
+        u16::from_be_bytes(
+            self.data[self.payload_offset - 2..self.payload_offset]
+                .try_into()
+                .unwrap(),
+        )
     }
 
     pub fn set_ether_type(&mut self, ether_type: u16) {
-        self.data[12..=13].copy_from_slice(&ether_type.to_be_bytes());
+        self.data[self.payload_offset - 2..self.payload_offset]
+            .copy_from_slice(&ether_type.to_be_bytes());
+    }
+
+    /// Returns the outermost tag of the frame, if it is tagged: the service tag of a QinQ frame,
+    /// or else its 802.1Q tag.
+    pub fn vlan_tag(&self) -> Option<VlanTag> {
+        if self.payload_offset - self.layer2_offset < 18 {
+            return None;
+        }
+        let tci = u16::from_be_bytes(
+            self.data[self.layer2_offset + 14..=self.layer2_offset + 15]
+                .try_into()
+                .unwrap(),
+        );
+        Some(VlanTag::from_tci(tci))
+    }
+
+    /// Returns the tags of the frame, outermost first, so the customer tag of a QinQ frame is
+    /// last.
+    pub fn vlan_tags(&self) -> Vec<VlanTag> {
+        (self.layer2_offset + 14..self.payload_offset - 2)
+            .step_by(4)
+            .map(|tci_offset| {
+                let tci =
+                    u16::from_be_bytes(self.data[tci_offset..tci_offset + 2].try_into().unwrap());
+                VlanTag::from_tci(tci)
+            })
+            .collect()
+    }
+
+    /// Rewrites the outermost tag of a tagged frame.
+    pub fn set_vlan_tag(&mut self, tag: VlanTag) -> Result<(), &'static str> {
+        if self.vlan_tag().is_none() {
+            return Err("Frame does not have a VLAN tag");
+        }
+        self.data[self.layer2_offset + 14..=self.layer2_offset + 15]
+            .copy_from_slice(&tag.tci().to_be_bytes());
+        Ok(())
+    }
+
+    /// Inserts an 802.1Q tag after the MAC addresses of an untagged frame, moving the payload
+    /// back by 4 bytes.
+    pub fn push_vlan_tag(&mut self, tag: VlanTag) -> Result<(), &'static str> {
+        if self.vlan_tag().is_some() {
+            return Err("Frame already has a VLAN tag");
+        }
+        let mut header = [0; 4];
+        header[..2].copy_from_slice(&VLAN_ETHER_TYPE.to_be_bytes());
+        header[2..].copy_from_slice(&tag.tci().to_be_bytes());
+        let tag_offset = self.layer2_offset + 12;
+        self.data
+            .splice(tag_offset..tag_offset, header.iter().cloned());
+        self.payload_offset += 4;
+        Ok(())
+    }
+
+    /// Inserts an 802.1ad service tag after the MAC addresses, in front of any tags the frame
+    /// already has, as a provider bridge does on entry to its network. The payload moves back
+    /// by 4 bytes.
+    pub fn push_service_tag(&mut self, tag: VlanTag) {
+        let mut header = [0; 4];
+        header[..2].copy_from_slice(&QINQ_ETHER_TYPE.to_be_bytes());
+        header[2..].copy_from_slice(&tag.tci().to_be_bytes());
+        let tag_offset = self.layer2_offset + 12;
+        self.data
+            .splice(tag_offset..tag_offset, header.iter().cloned());
+        self.payload_offset += 4;
+    }
+
+    /// Removes and returns the outermost tag of the frame, if it is tagged.
+    pub fn pop_vlan_tag(&mut self) -> Option<VlanTag> {
+        let tag = self.vlan_tag()?;
+        let tag_offset = self.layer2_offset + 12;
+        self.data.drain(tag_offset..tag_offset + 4);
+        self.payload_offset -= 4;
+        Some(tag)
     }
 
     // This gives you a cow of a slice of the payload.
@@ -70,9 +216,8 @@ impl EthernetFrame {
     }
 
     pub fn set_payload(&mut self, payload: &[u8]) {
-        let payload_len = payload.len() as u16;
         self.data.truncate(self.payload_offset);
-        self.data.reserve_exact(payload_len as usize);
+        self.data.reserve_exact(payload.len());
         self.data.extend(payload);
     }
 
@@ -150,6 +295,78 @@ impl TryFrom<Ipv6Packet> for EthernetFrame {
     }
 }
 
+impl TryFrom<IcmpPacket> for EthernetFrame {
+    type Error = &'static str;
+
+    fn try_from(packet: IcmpPacket) -> Result<Self, Self::Error> {
+        if let Some(layer2_offset) = packet.layer2_offset {
+            EthernetFrame::from_buffer(packet.data, layer2_offset)
+        } else {
+            Err("ICMP Packet does not contain an Ethernet Frame")
+        }
+    }
+}
+
+impl TryFrom<Icmpv6Packet> for EthernetFrame {
+    type Error = &'static str;
+
+    fn try_from(packet: Icmpv6Packet) -> Result<Self, Self::Error> {
+        if let Some(layer2_offset) = packet.layer2_offset {
+            EthernetFrame::from_buffer(packet.data, layer2_offset)
+        } else {
+            Err("ICMPv6 Packet does not contain an Ethernet Frame")
+        }
+    }
+}
+
+impl TryFrom<IgmpPacket> for EthernetFrame {
+    type Error = &'static str;
+
+    fn try_from(packet: IgmpPacket) -> Result<Self, Self::Error> {
+        if let Some(layer2_offset) = packet.layer2_offset {
+            EthernetFrame::from_buffer(packet.data, layer2_offset)
+        } else {
+            Err("IGMP Packet does not contain an Ethernet Frame")
+        }
+    }
+}
+
+impl TryFrom<GrePacket> for EthernetFrame {
+    type Error = &'static str;
+
+    fn try_from(packet: GrePacket) -> Result<Self, Self::Error> {
+        if let Some(layer2_offset) = packet.layer2_offset {
+            EthernetFrame::from_buffer(packet.data, layer2_offset)
+        } else {
+            Err("GRE Packet does not contain an Ethernet Frame")
+        }
+    }
+}
+
+impl TryFrom<SctpPacket> for EthernetFrame {
+    type Error = &'static str;
+
+    fn try_from(packet: SctpPacket) -> Result<Self, Self::Error> {
+        if let Some(layer2_offset) = packet.layer2_offset {
+            EthernetFrame::from_buffer(packet.data, layer2_offset)
+        } else {
+            Err("SCTP Packet does not contain an Ethernet Frame")
+        }
+    }
+}
+
+impl From<ArpFrame> for EthernetFrame {
+    fn from(frame: ArpFrame) -> Self {
+        frame.frame()
+    }
+}
+
+impl From<PppoeFrame> for EthernetFrame {
+    fn from(frame: PppoeFrame) -> Self {
+        frame.frame()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -158,7 +375,7 @@ mod tests {
     #[test]
     fn ethernet_frame() {
         let data: Vec<u8> = vec![0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0, 0];
-        let frame = EthernetFrame::from_buffer(data, 0).unwrap();
+        let frame = EthernetFrame::from_buffer(data.into(), 0).unwrap();
         assert_eq!(
             frame.dest_mac(),
             MacAddr::new([0xde, 0xad, 0xbe, 0xef, 0xff, 0xff])
@@ -171,7 +388,7 @@ mod tests {
     #[test]
     fn set_payload() {
         let data: Vec<u8> = vec![0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0, 0];
-        let mut frame = EthernetFrame::from_buffer(data, 0).unwrap();
+        let mut frame = EthernetFrame::from_buffer(data.into(), 0).unwrap();
         assert_eq!(frame.ether_type(), 0);
         assert_eq!(frame.payload().len(), 0);
 
@@ -185,13 +402,13 @@ mod tests {
     #[should_panic(expected = "Frame is less than the minimum of 14 bytes")]
     fn invalid_data_length() {
         let data: Vec<u8> = vec![0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6];
-        let _frame = EthernetFrame::from_buffer(data, 0).unwrap();
+        let _frame = EthernetFrame::from_buffer(data.into(), 0).unwrap();
     }
 
     #[test]
     fn set_dest_mac() {
         let data: Vec<u8> = vec![0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0, 0];
-        let mut frame = EthernetFrame::from_buffer(data, 0).unwrap();
+        let mut frame = EthernetFrame::from_buffer(data.into(), 0).unwrap();
         let new_dest = MacAddr::new([0x98, 0x88, 0x18, 0x12, 0xb4, 0xdf]);
         frame.set_dest_mac(new_dest);
         assert_eq!(frame.dest_mac(), new_dest);
@@ -200,7 +417,7 @@ mod tests {
     #[test]
     fn set_src_mac() {
         let data: Vec<u8> = vec![0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0, 0];
-        let mut frame = EthernetFrame::from_buffer(data, 0).unwrap();
+        let mut frame = EthernetFrame::from_buffer(data.into(), 0).unwrap();
         let new_src = MacAddr::new([0x98, 0x88, 0x18, 0x12, 0xb4, 0xdf]);
         frame.set_src_mac(new_src);
         assert_eq!(frame.src_mac(), new_src);
@@ -211,10 +428,132 @@ mod tests {
         let data: Vec<u8> = vec![
             0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0xff, 0xff,
         ];
-        let frame = EthernetFrame::from_buffer(data, 0).unwrap();
+        let frame = EthernetFrame::from_buffer(data.into(), 0).unwrap();
         assert_eq!(frame.ether_type(), 0xffff);
     }
 
+    #[test]
+    fn vlan_tag_tci() {
+        let tag = VlanTag {
+            pcp: 5,
+            dei: true,
+            vid: 100,
+        };
+        assert_eq!(tag.tci(), 0xB064);
+        assert_eq!(VlanTag::from_tci(0xB064), tag);
+    }
+
+    #[test]
+    fn tagged_frame() {
+        let data: Vec<u8> = vec![
+            0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0x81, 0x00, 0x20, 0x0A, 0x08,
+            0x00, 0xaa,
+        ];
+        let frame = EthernetFrame::from_buffer(data.into(), 0).unwrap();
+        assert_eq!(frame.payload_offset, 18);
+        assert_eq!(frame.ether_type(), IPV4_ETHER_TYPE);
+        assert_eq!(
+            frame.vlan_tag(),
+            Some(VlanTag {
+                pcp: 1,
+                dei: false,
+                vid: 10
+            })
+        );
+        assert_eq!(frame.payload()[..], [0xaa]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Frame is too short to contain its VLAN tag")]
+    fn truncated_vlan_tag() {
+        let data: Vec<u8> = vec![
+            0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0x81, 0x00, 0x20, 0x0A,
+        ];
+        let _frame = EthernetFrame::from_buffer(data.into(), 0).unwrap();
+    }
+
+    #[test]
+    fn push_set_pop_vlan_tag() {
+        let mut frame = EthernetFrame::encap_ipv4(Ipv4Packet::empty());
+        let untagged = frame.clone();
+        assert_eq!(frame.vlan_tag(), None);
+        assert!(frame.set_vlan_tag(VlanTag::new(20)).is_err());
+
+        frame.push_vlan_tag(VlanTag::new(10)).unwrap();
+        assert_eq!(frame.vlan_tag(), Some(VlanTag::new(10)));
+        assert_eq!(frame.ether_type(), IPV4_ETHER_TYPE);
+        assert_eq!(frame.payload(), untagged.payload());
+        assert!(frame.push_vlan_tag(VlanTag::new(20)).is_err());
+
+        frame.set_vlan_tag(VlanTag::new(20)).unwrap();
+        let reparsed = EthernetFrame::from_buffer(frame.data.clone(), 0).unwrap();
+        assert_eq!(reparsed.vlan_tag(), Some(VlanTag::new(20)));
+        assert!(Ipv4Packet::try_from(reparsed).is_ok());
+
+        assert_eq!(frame.pop_vlan_tag(), Some(VlanTag::new(20)));
+        assert_eq!(frame.pop_vlan_tag(), None);
+        assert_eq!(frame, untagged);
+    }
+
+    #[test]
+    fn qinq_frame() {
+        let data: Vec<u8> = vec![
+            0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0x88, 0xa8, 0x00, 0x64, 0x81,
+            0x00, 0xa0, 0x0A, 0x86, 0xdd, 0xaa,
+        ];
+        let mut frame = EthernetFrame::from_buffer(data.into(), 0).unwrap();
+        assert_eq!(frame.payload_offset, 22);
+        assert_eq!(frame.ether_type(), IPV6_ETHER_TYPE);
+        assert_eq!(frame.vlan_tag(), Some(VlanTag::new(100)));
+        let customer = VlanTag {
+            pcp: 5,
+            dei: false,
+            vid: 10,
+        };
+        assert_eq!(frame.vlan_tags(), vec![VlanTag::new(100), customer]);
+        assert_eq!(frame.payload()[..], [0xaa]);
+
+        // Stripping the service tag leaves the customer's 802.1Q frame.
+        assert_eq!(frame.pop_vlan_tag(), Some(VlanTag::new(100)));
+        assert_eq!(frame.vlan_tags(), vec![customer]);
+        assert_eq!(frame.ether_type(), IPV6_ETHER_TYPE);
+        assert_eq!(frame.payload()[..], [0xaa]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Frame is too short to contain its VLAN tag")]
+    fn truncated_inner_vlan_tag() {
+        let data: Vec<u8> = vec![
+            0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0x88, 0xa8, 0x00, 0x64, 0x81,
+            0x00, 0xa0,
+        ];
+        let _frame = EthernetFrame::from_buffer(data.into(), 0).unwrap();
+    }
+
+    #[test]
+    fn push_pop_service_tag() {
+        let mut frame = EthernetFrame::encap_ipv4(Ipv4Packet::empty());
+        let untagged = frame.clone();
+        frame.push_vlan_tag(VlanTag::new(10)).unwrap();
+        let tagged = frame.clone();
+
+        frame.push_service_tag(VlanTag::new(200));
+        assert_eq!(frame.vlan_tags(), vec![VlanTag::new(200), VlanTag::new(10)]);
+        assert_eq!(frame.data[12..14], QINQ_ETHER_TYPE.to_be_bytes());
+        assert_eq!(frame.ether_type(), IPV4_ETHER_TYPE);
+        assert!(frame.push_vlan_tag(VlanTag::new(20)).is_err());
+        let reparsed = EthernetFrame::from_buffer(frame.data.clone(), 0).unwrap();
+        assert_eq!(reparsed.payload_offset, frame.payload_offset);
+        assert!(Ipv4Packet::try_from(reparsed).is_ok());
+
+        frame.set_vlan_tag(VlanTag::new(201)).unwrap();
+        assert_eq!(frame.pop_vlan_tag(), Some(VlanTag::new(201)));
+        assert_eq!(frame, tagged);
+        assert_eq!(frame.pop_vlan_tag(), Some(VlanTag::new(10)));
+        assert_eq!(frame, untagged);
+        assert_eq!(frame.vlan_tags(), vec![]);
+    }
+
     #[test]
     fn empty() {
         let empty_frame = EthernetFrame::empty();
@@ -222,6 +561,43 @@ mod tests {
         assert_eq!(empty_frame.payload_offset, 14);
     }
 
+    #[test]
+    fn layer_conversions() {
+        let mut segment = UdpSegment::empty();
+        segment.set_payload(&[1, 2, 3]);
+        let mut frame = EthernetFrame::encap_ipv4(Ipv4Packet::encap_udp(segment));
+        frame.data.freeze();
+        let ptr = frame.data.as_ptr();
+
+        // Each layer is a view of the same buffer, going down and back up.
+        let segment = UdpSegment::try_from(frame.clone()).unwrap();
+        assert_eq!(segment.data.as_ptr(), ptr);
+        assert_eq!(segment.payload()[..], [1, 2, 3]);
+        let packet = Ipv4Packet::try_from(segment).unwrap();
+        assert_eq!(packet.protocol(), IpProtocol::UDP);
+        let up = EthernetFrame::try_from(packet).unwrap();
+        assert_eq!(up.data.as_ptr(), ptr);
+        assert_eq!(up, frame);
+
+        // The ether type and protocol fields are checked on the way down.
+        assert!(TcpSegment::try_from(frame.clone()).is_err());
+        assert!(Ipv6Packet::try_from(frame.clone()).is_err());
+        frame.set_ether_type(ARP_ETHER_TYPE);
+        assert!(Ipv4Packet::try_from(frame.clone()).is_err());
+        assert!(UdpSegment::try_from(frame).is_err());
+
+        // A packet made without lower layers has none to go back up to.
+        let echo = IcmpMessage::EchoRequest {
+            identifier: 1,
+            sequence_number: 1,
+            data: &[],
+        };
+        let icmp = IcmpPacket::try_from(Ipv4Packet::encap_icmp(IcmpPacket::new(&echo))).unwrap();
+        assert!(EthernetFrame::try_from(icmp.clone()).is_err());
+        let packet = Ipv4Packet::try_from(icmp).unwrap();
+        assert_eq!(packet.protocol(), IpProtocol::ICMP);
+    }
+
     #[test]
     fn encap_ipv4() {
         let frame = EthernetFrame::encap_ipv4(Ipv4Packet::empty());
@@ -0,0 +1,249 @@
+use crate::*;
+use std::convert::TryInto;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+const ICMP: u8 = 1;
+const TCP: u8 = 6;
+const UDP: u8 = 17;
+const ICMPV6: u8 = 58;
+const SCTP: u8 = 132;
+
+/// Identifies the flow a packet belongs to, by its protocol, addresses and ports, as NAT,
+/// connection tracking and load balancing across flows all need. ICMP and ICMPv6 echo requests
+/// use their identifier as the source port, and replies as the destination port, so queries and
+/// their replies are flows like any other. Other protocols, and IP fragments but the first, which
+/// carry no layer 4 header, have 0 for both ports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FlowKey {
+    pub protocol: u8,
+    pub src: IpAddr,
+    pub src_port: u16,
+    pub dst: IpAddr,
+    pub dst_port: u16,
+}
+
+impl FlowKey {
+    /// Reads the key of the IPv4 or IPv6 packet at `layer3_offset` of `data`, if it's long enough
+    /// to hold the IP header. The length fields of the header aren't checked, so the packet can
+    /// be one quoted by an ICMP error, cut short after the first 8 bytes of its payload.
+    pub fn from_packet(data: &[u8], layer3_offset: usize) -> Option<FlowKey> {
+        match data.get(layer3_offset)? >> 4 {
+            4 => FlowKey::from_ipv4(data, layer3_offset),
+            6 => FlowKey::from_ipv6(data, layer3_offset),
+            _ => None,
+        }
+    }
+
+    fn from_ipv4(data: &[u8], layer3_offset: usize) -> Option<FlowKey> {
+        let header = data.get(layer3_offset..layer3_offset + 20)?;
+        let header_len = usize::from(header[0] & 0x0F) * 4;
+        if header_len < 20 {
+            return None;
+        }
+        let fragment_offset = u16::from_be_bytes([header[6], header[7]]) & 0x1FFF;
+        let src: [u8; 4] = header[12..16].try_into().unwrap();
+        let dst: [u8; 4] = header[16..20].try_into().unwrap();
+        let layer4 = if fragment_offset == 0 {
+            data.get(layer3_offset + header_len..)
+        } else {
+            None
+        };
+        Some(FlowKey::new(
+            header[9],
+            Ipv4Addr::from(src).into(),
+            Ipv4Addr::from(dst).into(),
+            layer4,
+        ))
+    }
+
+    fn from_ipv6(data: &[u8], layer3_offset: usize) -> Option<FlowKey> {
+        let header = data.get(layer3_offset..layer3_offset + 40)?;
+        let src: [u8; 16] = header[8..24].try_into().unwrap();
+        let dst: [u8; 16] = header[24..40].try_into().unwrap();
+
+        let mut headers = Ipv6ExtensionHeaders::new(data, layer3_offset);
+        let mut first_fragment = true;
+        for header in headers.by_ref() {
+            if header.header_type == IpProtocol::IPv6_frag {
+                first_fragment &= u16::from_be_bytes([header.data[2], header.data[3]]) >> 3 == 0;
+            }
+        }
+        let layer4 = if first_fragment {
+            data.get(headers.offset..)
+        } else {
+            None
+        };
+        Some(FlowKey::new(
+            headers.next_header,
+            Ipv6Addr::from(src).into(),
+            Ipv6Addr::from(dst).into(),
+            layer4,
+        ))
+    }
+
+    /// Makes the key of a packet of `protocol`, reading the ports from `layer4`, the start of
+    /// its layer 4 header, if the packet has one.
+    fn new(protocol: u8, src: IpAddr, dst: IpAddr, layer4: Option<&[u8]>) -> FlowKey {
+        let port = |at: usize| {
+            layer4
+                .and_then(|layer4| layer4.get(at..at + 2))
+                .map_or(0, |port| u16::from_be_bytes([port[0], port[1]]))
+        };
+        let (src_port, dst_port) = match protocol {
+            TCP | UDP | SCTP => (port(0), port(2)),
+            ICMP | ICMPV6 => match layer4.and_then(|layer4| layer4.first()) {
+                // Echo, timestamp and information requests, and ICMPv6 echo requests.
+                Some(8) | Some(13) | Some(15) | Some(128) => (port(4), 0),
+                // Their replies.
+                Some(0) | Some(14) | Some(16) | Some(129) => (0, port(4)),
+                _ => (0, 0),
+            },
+            _ => (0, 0),
+        };
+        FlowKey {
+            protocol,
+            src,
+            src_port,
+            dst,
+            dst_port,
+        }
+    }
+
+    /// Returns the key of packets in the other direction of the flow.
+    pub fn reverse(&self) -> FlowKey {
+        FlowKey {
+            protocol: self.protocol,
+            src: self.dst,
+            src_port: self.dst_port,
+            dst: self.src,
+            dst_port: self.src_port,
+        }
+    }
+
+    /// Returns a hash of the key that is the same on every platform, build and run, unlike those
+    /// of `Hash`, so it can pick among egressors or servers consistently, even across restarts
+    /// and between routers.
+    pub fn stable_hash(&self) -> u64 {
+        let mut bytes = [0; 37];
+        bytes[0] = self.protocol;
+        bytes[1..17].copy_from_slice(&ipv6_octets(self.src));
+        bytes[17..19].copy_from_slice(&self.src_port.to_be_bytes());
+        bytes[19..35].copy_from_slice(&ipv6_octets(self.dst));
+        bytes[35..37].copy_from_slice(&self.dst_port.to_be_bytes());
+        fnv1a(&bytes)
+    }
+
+    /// Returns the `stable_hash` of the flow, the same for packets of both directions.
+    pub fn symmetric_hash(&self) -> u64 {
+        let reverse = self.reverse();
+        if (reverse.src, reverse.src_port) < (self.src, self.src_port) {
+            reverse.stable_hash()
+        } else {
+            self.stable_hash()
+        }
+    }
+}
+
+/// IPv4 addresses hash as IPv4-mapped IPv6 addresses.
+fn ipv6_octets(addr: IpAddr) -> [u8; 16] {
+    match addr {
+        IpAddr::V4(addr) => addr.to_ipv6_mapped().octets(),
+        IpAddr::V6(addr) => addr.octets(),
+    }
+}
+
+/// The 64 bit FNV-1a hash of `bytes`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ipv4_and_ipv6() {
+        let src = Ipv4Addr::new(10, 0, 0, 1);
+        let dst = Ipv4Addr::new(10, 0, 0, 2);
+        let packet = Ipv4Packet::builder()
+            .src(src)
+            .dst(dst)
+            .udp(|udp| udp.sport(1000).dport(53))
+            .build();
+        let key = FlowKey::from_packet(&packet.data, 0).unwrap();
+        assert_eq!(
+            key,
+            FlowKey {
+                protocol: 17,
+                src: src.into(),
+                src_port: 1000,
+                dst: dst.into(),
+                dst_port: 53,
+            }
+        );
+
+        // Later fragments have no ports.
+        let mut fragment = packet.clone();
+        fragment.set_fragment_offset(1);
+        let fragment_key = FlowKey::from_packet(&fragment.data, 0).unwrap();
+        assert_eq!((fragment_key.src_port, fragment_key.dst_port), (0, 0));
+
+        let mut packet = Ipv6Packet::builder()
+            .src(Ipv6Addr::LOCALHOST)
+            .dst(Ipv6Addr::UNSPECIFIED)
+            .tcp(|tcp| tcp.sport(2000).dport(80))
+            .build();
+        packet
+            .insert_extension_header(0, 0, &[1, 4, 0, 0, 0, 0])
+            .unwrap();
+        let key = FlowKey::from_packet(&packet.data, 0).unwrap();
+        assert_eq!((key.protocol, key.src_port, key.dst_port), (6, 2000, 80));
+
+        assert_eq!(FlowKey::from_packet(&[0x45; 19], 0), None);
+        assert_eq!(FlowKey::from_packet(&[0; 40], 0), None);
+    }
+
+    #[test]
+    fn icmp_and_other_protocols() {
+        let request = Ipv4Packet::builder()
+            .icmp(&IcmpMessage::EchoRequest {
+                identifier: 7,
+                sequence_number: 1,
+                data: &[],
+            })
+            .build();
+        let key = FlowKey::from_packet(&request.data, 0).unwrap();
+        assert_eq!((key.src_port, key.dst_port), (7, 0));
+
+        let reply = Ipv6Packet::builder()
+            .icmpv6(&Icmpv6Message::EchoReply {
+                identifier: 7,
+                sequence_number: 1,
+                data: &[],
+            })
+            .build();
+        let key = FlowKey::from_packet(&reply.data, 0).unwrap();
+        assert_eq!((key.protocol, key.src_port, key.dst_port), (58, 0, 7));
+
+        let gre = Ipv4Packet::builder().payload(47, &[0; 8]).build();
+        let key = FlowKey::from_packet(&gre.data, 0).unwrap();
+        assert_eq!((key.protocol, key.src_port, key.dst_port), (47, 0, 0));
+    }
+
+    #[test]
+    fn hashes() {
+        let key = FlowKey {
+            protocol: 6,
+            src: Ipv4Addr::new(192, 168, 0, 1).into(),
+            src_port: 40000,
+            dst: Ipv4Addr::new(8, 8, 8, 8).into(),
+            dst_port: 443,
+        };
+        // Fixed, so that it's the same everywhere.
+        assert_eq!(key.stable_hash(), 0xa454_1e65_819d_de12);
+        assert_ne!(key.stable_hash(), key.reverse().stable_hash());
+        assert_eq!(key.symmetric_hash(), key.reverse().symmetric_hash());
+    }
+}
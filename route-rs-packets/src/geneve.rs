@@ -0,0 +1,283 @@
+use crate::*;
+use std::borrow::Cow;
+use std::convert::{TryFrom, TryInto};
+
+/// The UDP port IANA assigned to GENEVE.
+pub const GENEVE_PORT: u16 = 6081;
+
+/// Flag bits in the second byte of the GENEVE header.
+const OAM_FLAG: u8 = 0x80;
+const CRITICAL_FLAG: u8 = 0x40;
+
+/// The bit of an option's type marking it critical: endpoints that don't understand a critical
+/// option must drop the packet.
+const CRITICAL_OPTION: u8 = 0x80;
+
+const HEADER_LEN: usize = 8;
+
+/// A GENEVE option, a TLV with its data a multiple of 4 bytes long, up to 124 bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GeneveOption {
+    pub class: u16,
+    pub option_type: u8,
+    pub data: Vec<u8>,
+}
+
+impl GeneveOption {
+    pub fn critical(&self) -> bool {
+        self.option_type & CRITICAL_OPTION != 0
+    }
+
+    /// The option's bytes, with its data padded with zeros to a multiple of 4 bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let words = self.data.len().div_ceil(4);
+        let mut bytes = self.class.to_be_bytes().to_vec();
+        bytes.push(self.option_type);
+        bytes.push(words as u8 & 0x1F);
+        bytes.extend_from_slice(&self.data);
+        bytes.resize(4 + words * 4, 0);
+        bytes
+    }
+}
+
+/// A GENEVE packet, per RFC 8926, as carried in the payload of a UDP segment: the 8 byte GENEVE
+/// header, its options, then the frame or packet it tunnels, of its protocol type. The data is
+/// the packet alone, without the headers of the segment it came in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GenevePacket {
+    pub data: PacketData,
+    pub payload_offset: usize,
+}
+
+impl GenevePacket {
+    pub fn from_buffer(data: PacketData) -> Result<GenevePacket, &'static str> {
+        if data.len() < HEADER_LEN {
+            return Err("Data is too short to be a GenevePacket");
+        }
+        if data[0] >> 6 != 0 {
+            return Err("GenevePacket has unsupported version");
+        }
+        let payload_offset = HEADER_LEN + usize::from(data[0] & 0x3F) * 4;
+        if data.len() < payload_offset {
+            return Err("Data is too short for the GenevePacket's options");
+        }
+        Ok(GenevePacket {
+            data,
+            payload_offset,
+        })
+    }
+
+    /// Makes a GenevePacket tunneling `payload`, of `protocol_type`, on virtual network `vni`,
+    /// which must be less than 2^24, with no options.
+    pub fn new(vni: u32, protocol_type: u16, payload: &[u8]) -> GenevePacket {
+        let mut packet = GenevePacket::from_buffer(vec![0; HEADER_LEN].into()).unwrap();
+        packet.set_vni(vni);
+        packet.set_protocol_type(protocol_type);
+        packet.set_payload(payload);
+        packet
+    }
+
+    /// Whether the packet is an OAM frame, a control message rather than tunneled data.
+    pub fn oam(&self) -> bool {
+        self.data[1] & OAM_FLAG != 0
+    }
+
+    pub fn set_oam(&mut self, oam: bool) {
+        self.set_flag(OAM_FLAG, oam);
+    }
+
+    /// Whether any of the options are critical. Set by `set_options`.
+    pub fn critical(&self) -> bool {
+        self.data[1] & CRITICAL_FLAG != 0
+    }
+
+    /// The EtherType of the payload.
+    pub fn protocol_type(&self) -> u16 {
+        u16::from_be_bytes(self.data[2..4].try_into().unwrap())
+    }
+
+    pub fn set_protocol_type(&mut self, protocol_type: u16) {
+        self.data[2..4].copy_from_slice(&protocol_type.to_be_bytes());
+    }
+
+    /// The Virtual Network Identifier.
+    pub fn vni(&self) -> u32 {
+        u32::from_be_bytes([0, self.data[4], self.data[5], self.data[6]])
+    }
+
+    pub fn set_vni(&mut self, vni: u32) {
+        assert!(vni < 1 << 24, "GenevePacket vni: {}, must be < 2^24", vni);
+        self.data[4..7].copy_from_slice(&vni.to_be_bytes()[1..]);
+    }
+
+    /// The options, in order. Options running past the end of the option space are left out.
+    pub fn options(&self) -> Vec<GeneveOption> {
+        let mut options = vec![];
+        let mut rest = &self.data[HEADER_LEN..self.payload_offset];
+        while rest.len() >= 4 {
+            let len = 4 + usize::from(rest[3] & 0x1F) * 4;
+            if rest.len() < len {
+                break;
+            }
+            options.push(GeneveOption {
+                class: u16::from_be_bytes([rest[0], rest[1]]),
+                option_type: rest[2],
+                data: rest[4..len].to_vec(),
+            });
+            rest = &rest[len..];
+        }
+        options
+    }
+
+    /// Replaces the options, and sets the critical flag if any of them are critical. Returns an
+    /// error, leaving the packet unchanged, if they don't fit in the 252 bytes of option space.
+    pub fn set_options(&mut self, options: &[GeneveOption]) -> Result<(), &'static str> {
+        let bytes: Vec<u8> = options.iter().flat_map(GeneveOption::to_bytes).collect();
+        if bytes.len() > 0x3F * 4 {
+            return Err("GeneveOptions are too long for the GenevePacket");
+        }
+        self.data
+            .splice(HEADER_LEN..self.payload_offset, bytes.iter().cloned());
+        self.payload_offset = HEADER_LEN + bytes.len();
+        self.data[0] = (bytes.len() / 4) as u8;
+        self.set_flag(CRITICAL_FLAG, options.iter().any(GeneveOption::critical));
+        Ok(())
+    }
+
+    pub fn payload(&self) -> Cow<[u8]> {
+        Cow::from(&self.data[self.payload_offset..])
+    }
+
+    /// Sets the payload. Don't forget to update the length field of the UDP segment that contains
+    /// this.
+    pub fn set_payload(&mut self, payload: &[u8]) {
+        self.data.truncate(self.payload_offset);
+        self.data.reserve_exact(payload.len());
+        self.data.extend(payload);
+    }
+
+    /// The Ethernet frame the packet tunnels, if its protocol type is Transparent Ethernet
+    /// Bridging.
+    pub fn inner_ethernet(&self) -> Result<EthernetFrame, &'static str> {
+        if self.protocol_type() != ETHERNET_BRIDGING_ETHER_TYPE {
+            return Err("GenevePacket does not carry an Ethernet frame");
+        }
+        EthernetFrame::from_buffer(self.data.slice(self.payload_offset..), 0)
+    }
+
+    /// The IPv4 packet the packet tunnels, if its protocol type is IPv4.
+    pub fn inner_ipv4(&self) -> Result<Ipv4Packet, &'static str> {
+        if self.protocol_type() != IPV4_ETHER_TYPE {
+            return Err("GenevePacket does not carry an IPv4 packet");
+        }
+        Ipv4Packet::from_buffer(self.data.slice(self.payload_offset..), None, 0)
+    }
+
+    /// The IPv6 packet the packet tunnels, if its protocol type is IPv6.
+    pub fn inner_ipv6(&self) -> Result<Ipv6Packet, &'static str> {
+        if self.protocol_type() != IPV6_ETHER_TYPE {
+            return Err("GenevePacket does not carry an IPv6 packet");
+        }
+        Ipv6Packet::from_buffer(self.data.slice(self.payload_offset..), None, 0)
+    }
+
+    fn set_flag(&mut self, flag: u8, set: bool) {
+        if set {
+            self.data[1] |= flag;
+        } else {
+            self.data[1] &= !flag;
+        }
+    }
+}
+
+impl TryFrom<UdpSegment> for GenevePacket {
+    type Error = &'static str;
+
+    fn try_from(mut segment: UdpSegment) -> Result<Self, Self::Error> {
+        GenevePacket::from_buffer(segment.data.split_off(segment.payload_offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inner() -> Ipv4Packet {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_payload(&[1, 2, 3]);
+        packet
+    }
+
+    #[test]
+    fn geneve_packet() {
+        let frame = EthernetFrame::encap_ipv4(inner());
+        let packet = GenevePacket::new(0x123456, ETHERNET_BRIDGING_ETHER_TYPE, &frame.data);
+        assert_eq!(packet.data[..8], [0, 0, 0x65, 0x58, 0x12, 0x34, 0x56, 0]);
+        assert_eq!(packet.vni(), 0x123456);
+        assert!(!packet.oam());
+        assert_eq!(packet.options(), vec![]);
+        assert_eq!(packet.inner_ethernet().unwrap(), frame);
+        assert!(packet.inner_ipv4().is_err());
+
+        let mut segment = UdpSegment::empty();
+        segment.set_dest_port(GENEVE_PORT);
+        segment.set_payload(&GenevePacket::new(1, IPV4_ETHER_TYPE, &inner().data).data);
+        let packet = GenevePacket::try_from(segment).unwrap();
+        assert_eq!(packet.inner_ipv4().unwrap(), inner());
+        assert!(packet.inner_ipv6().is_err());
+    }
+
+    #[test]
+    fn options() {
+        let options = vec![
+            GeneveOption {
+                class: 0x0102,
+                option_type: 0x03,
+                data: vec![1, 2, 3, 4],
+            },
+            GeneveOption {
+                class: 0xffff,
+                option_type: 0x80,
+                data: vec![],
+            },
+        ];
+        let mut packet = GenevePacket::new(1, IPV4_ETHER_TYPE, &inner().data);
+        packet.set_options(&options).unwrap();
+        assert_eq!(packet.data[0], 3);
+        assert!(packet.critical());
+        assert_eq!(packet.payload_offset, 20);
+        assert_eq!(packet.options(), options);
+        assert_eq!(packet.inner_ipv4().unwrap(), inner());
+
+        // Data is padded to a multiple of 4 bytes.
+        let padded = GeneveOption {
+            class: 0,
+            option_type: 1,
+            data: vec![9],
+        };
+        packet.set_options(&[padded]).unwrap();
+        assert!(!packet.critical());
+        assert_eq!(packet.options()[0].data, vec![9, 0, 0, 0]);
+        assert_eq!(
+            GenevePacket::from_buffer(packet.data.clone()).unwrap(),
+            packet
+        );
+
+        let too_long = GeneveOption {
+            class: 0,
+            option_type: 1,
+            data: vec![0; 124],
+        };
+        assert!(packet.set_options(&[too_long.clone(), too_long]).is_err());
+        assert_eq!(packet.options().len(), 1);
+    }
+
+    #[test]
+    fn short_packets() {
+        assert!(GenevePacket::from_buffer(vec![0; 7].into()).is_err());
+        // Options running past the end of the data.
+        assert!(GenevePacket::from_buffer(vec![1, 0, 0x08, 0, 0, 0, 1, 0].into()).is_err());
+        // Version 1.
+        assert!(GenevePacket::from_buffer(vec![0x40, 0, 0x08, 0, 0, 0, 1, 0].into()).is_err());
+    }
+}
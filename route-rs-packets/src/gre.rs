@@ -0,0 +1,344 @@
+use crate::*;
+use std::borrow::Cow;
+use std::convert::{TryFrom, TryInto};
+
+/// Flag bits in the first byte of the GRE header, marking which optional fields are present.
+const CHECKSUM_PRESENT: u8 = 0x80;
+const KEY_PRESENT: u8 = 0x20;
+const SEQUENCE_PRESENT: u8 = 0x10;
+
+/// A GRE packet, per RFC 2784 and RFC 2890, with its optional checksum, key and sequence number
+/// fields. The GRE header is at `layer4_offset`, since it follows the delivery IP header.
+#[derive(Clone, Debug)]
+pub struct GrePacket {
+    pub data: PacketData,
+    pub layer2_offset: Option<usize>,
+    pub layer3_offset: Option<usize>,
+    pub layer4_offset: usize,
+    pub payload_offset: usize,
+}
+
+impl GrePacket {
+    pub fn from_buffer(
+        data: PacketData,
+        layer2_offset: Option<usize>,
+        layer3_offset: Option<usize>,
+        layer4_offset: usize,
+    ) -> Result<GrePacket, &'static str> {
+        if data.len() < layer4_offset + 4 {
+            return Err("Data is too short to be a GrePacket");
+        }
+
+        if let Some(layer3_offset) = layer3_offset {
+            let protocol = match data[layer3_offset] >> 4 {
+                4 => get_ipv4_payload_type(&data, layer3_offset)?,
+                6 => get_ipv6_payload_type(&data, layer3_offset)?,
+                _ => return Err("IP Header has invalid version number"),
+            };
+            if protocol != IpProtocol::GREs {
+                return Err("Protocol is incorrect, since it isn't GRE");
+            }
+        }
+
+        // Version 0 is GRE, version 1 is PPTP's enhanced GRE, which we don't support.
+        if data[layer4_offset + 1] & 0x07 != 0 {
+            return Err("GrePacket has unsupported version");
+        }
+
+        let payload_offset = layer4_offset + header_len(data[layer4_offset]);
+        if data.len() < payload_offset {
+            return Err("Data is too short for the GrePacket's optional fields");
+        }
+
+        Ok(GrePacket {
+            data,
+            layer2_offset,
+            layer3_offset,
+            layer4_offset,
+            payload_offset,
+        })
+    }
+
+    /// Make an empty GrePacket, with no optional fields, delivery header nor payload.
+    pub fn empty() -> GrePacket {
+        GrePacket::from_buffer(vec![0; 4].into(), None, None, 0).unwrap()
+    }
+
+    /// The EtherType of the payload.
+    pub fn protocol_type(&self) -> u16 {
+        u16::from_be_bytes(
+            self.data[self.layer4_offset + 2..=self.layer4_offset + 3]
+                .try_into()
+                .unwrap(),
+        )
+    }
+
+    pub fn set_protocol_type(&mut self, protocol_type: u16) {
+        self.data[self.layer4_offset + 2..=self.layer4_offset + 3]
+            .copy_from_slice(&protocol_type.to_be_bytes());
+    }
+
+    pub fn checksum(&self) -> Option<u16> {
+        let offset = self.field_offset(CHECKSUM_PRESENT)?;
+        Some(u16::from_be_bytes(
+            self.data[offset..offset + 2].try_into().unwrap(),
+        ))
+    }
+
+    /// Adds the checksum field, if it isn't present, and sets it to the checksum of the header and
+    /// payload.
+    pub fn set_checksum(&mut self) {
+        self.set_field(CHECKSUM_PRESENT, Some([0; 4]));
+        let checksum = self.calculate_checksum();
+        let offset = self.layer4_offset + 4;
+        self.data[offset..offset + 2].copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    pub fn remove_checksum(&mut self) {
+        self.set_field(CHECKSUM_PRESENT, None);
+    }
+
+    /// Calculates what the checksum should be set to, given the current header and payload.
+    pub fn calculate_checksum(&self) -> u16 {
+        let checksum_offset = self.field_offset(CHECKSUM_PRESENT);
+        let mut sum = self.data[self.layer4_offset..]
+            .chunks(2)
+            .enumerate()
+            .filter(|(i, _)| match checksum_offset {
+                Some(offset) => self.layer4_offset + i * 2 != offset,
+                None => true,
+            })
+            .fold(0, |acc: u32, (_, word)| match word {
+                [high, low] => acc + u32::from(u16::from_be_bytes([*high, *low])),
+                _ => acc + u32::from(u16::from_be_bytes([word[0], 0])),
+            });
+        while sum > 0xFFFF {
+            sum = (sum & 0xFFFF) + (sum >> 16);
+        }
+        !(sum as u16)
+    }
+
+    /// Returns whether the checksum is right, or absent.
+    pub fn validate_checksum(&self) -> bool {
+        match self.checksum() {
+            Some(checksum) => checksum == self.calculate_checksum(),
+            None => true,
+        }
+    }
+
+    pub fn key(&self) -> Option<u32> {
+        let offset = self.field_offset(KEY_PRESENT)?;
+        Some(u32::from_be_bytes(
+            self.data[offset..offset + 4].try_into().unwrap(),
+        ))
+    }
+
+    /// Sets the key, adding or removing the field as needed. Does not update the checksum.
+    pub fn set_key(&mut self, key: Option<u32>) {
+        self.set_field(KEY_PRESENT, key.map(u32::to_be_bytes));
+    }
+
+    pub fn sequence_number(&self) -> Option<u32> {
+        let offset = self.field_offset(SEQUENCE_PRESENT)?;
+        Some(u32::from_be_bytes(
+            self.data[offset..offset + 4].try_into().unwrap(),
+        ))
+    }
+
+    /// Sets the sequence number, adding or removing the field as needed. Does not update the
+    /// checksum.
+    pub fn set_sequence_number(&mut self, sequence_number: Option<u32>) {
+        self.set_field(SEQUENCE_PRESENT, sequence_number.map(u32::to_be_bytes));
+    }
+
+    pub fn payload(&self) -> Cow<'_, [u8]> {
+        Cow::from(&self.data[self.payload_offset..])
+    }
+
+    /// Sets the payload, does not update the checksum.
+    /// Don't forget to update the length field of the IP packet that contains this.
+    pub fn set_payload(&mut self, payload: &[u8]) {
+        self.data.truncate(self.payload_offset);
+        self.data.reserve_exact(payload.len());
+        self.data.extend(payload);
+    }
+
+    /// The Ethernet frame the packet tunnels, if its protocol type is Transparent Ethernet
+    /// Bridging.
+    pub fn inner_ethernet(&self) -> Result<EthernetFrame, &'static str> {
+        if self.protocol_type() != ETHERNET_BRIDGING_ETHER_TYPE {
+            return Err("GrePacket does not carry an Ethernet frame");
+        }
+        EthernetFrame::from_buffer(self.data.slice(self.payload_offset..), 0)
+    }
+
+    /// The IPv4 packet the packet tunnels, if its protocol type is IPv4.
+    pub fn inner_ipv4(&self) -> Result<Ipv4Packet, &'static str> {
+        if self.protocol_type() != IPV4_ETHER_TYPE {
+            return Err("GrePacket does not carry an IPv4 packet");
+        }
+        Ipv4Packet::from_buffer(self.data.slice(self.payload_offset..), None, 0)
+    }
+
+    /// The IPv6 packet the packet tunnels, if its protocol type is IPv6.
+    pub fn inner_ipv6(&self) -> Result<Ipv6Packet, &'static str> {
+        if self.protocol_type() != IPV6_ETHER_TYPE {
+            return Err("GrePacket does not carry an IPv6 packet");
+        }
+        Ipv6Packet::from_buffer(self.data.slice(self.payload_offset..), None, 0)
+    }
+
+    /// Where the optional field marked present by `flag` is, if it is. The fields are always in
+    /// the order checksum, key, then sequence number, each taking 4 bytes.
+    fn field_offset(&self, flag: u8) -> Option<usize> {
+        let flags = self.data[self.layer4_offset];
+        if flags & flag == 0 {
+            return None;
+        }
+        let before = [CHECKSUM_PRESENT, KEY_PRESENT, SEQUENCE_PRESENT]
+            .iter()
+            .take_while(|field| **field != flag)
+            .filter(|field| flags & **field != 0)
+            .count();
+        Some(self.layer4_offset + 4 + before * 4)
+    }
+
+    /// Sets the optional field marked present by `flag`, inserting or removing it as needed.
+    fn set_field(&mut self, flag: u8, value: Option<[u8; 4]>) {
+        match (self.field_offset(flag), value) {
+            (Some(offset), Some(value)) => self.data[offset..offset + 4].copy_from_slice(&value),
+            (Some(offset), None) => {
+                self.data.drain(offset..offset + 4);
+                self.data[self.layer4_offset] &= !flag;
+                self.payload_offset -= 4;
+            }
+            (None, Some(value)) => {
+                self.data[self.layer4_offset] |= flag;
+                let offset = self.field_offset(flag).unwrap();
+                self.data.splice(offset..offset, value.iter().cloned());
+                self.payload_offset += 4;
+            }
+            (None, None) => (),
+        }
+    }
+}
+
+/// The length of a GRE header whose first byte is `flags`.
+fn header_len(flags: u8) -> usize {
+    [CHECKSUM_PRESENT, KEY_PRESENT, SEQUENCE_PRESENT]
+        .iter()
+        .filter(|flag| flags & **flag != 0)
+        .count()
+        * 4
+        + 4
+}
+
+/// GrePackets are considered the same if they have the same data from the GRE header onward.
+/// This function does not consider the data before the start of the GRE header.
+impl PartialEq for GrePacket {
+    fn eq(&self, other: &Self) -> bool {
+        self.data[self.layer4_offset..] == other.data[other.layer4_offset..]
+    }
+}
+
+impl Eq for GrePacket {}
+
+impl TryFrom<Ipv4Packet> for GrePacket {
+    type Error = &'static str;
+
+    fn try_from(packet: Ipv4Packet) -> Result<Self, Self::Error> {
+        GrePacket::from_buffer(
+            packet.data,
+            packet.layer2_offset,
+            Some(packet.layer3_offset),
+            packet.payload_offset,
+        )
+    }
+}
+
+impl TryFrom<Ipv6Packet> for GrePacket {
+    type Error = &'static str;
+
+    fn try_from(packet: Ipv6Packet) -> Result<Self, Self::Error> {
+        GrePacket::from_buffer(
+            packet.data,
+            packet.layer2_offset,
+            Some(packet.layer3_offset),
+            packet.payload_offset,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gre_packet() {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(47);
+        packet.set_payload(&[0x30, 0, 0x08, 0x00, 0, 0, 0, 42, 0, 0, 0, 7, 0xde, 0xad]);
+
+        let gre = GrePacket::try_from(packet).unwrap();
+        assert_eq!(gre.protocol_type(), IPV4_ETHER_TYPE);
+        assert_eq!(gre.checksum(), None);
+        assert_eq!(gre.key(), Some(42));
+        assert_eq!(gre.sequence_number(), Some(7));
+        assert_eq!(gre.payload().as_ref(), &[0xde, 0xad][..]);
+    }
+
+    #[test]
+    fn rejects_other_protocols_and_versions() {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(17);
+        packet.set_payload(&[0; 8]);
+        assert!(GrePacket::try_from(packet).is_err());
+
+        assert!(GrePacket::from_buffer(vec![0, 1, 0x88, 0x0b].into(), None, None, 0).is_err());
+        assert!(GrePacket::from_buffer(vec![0x20, 0, 0x08, 0x00].into(), None, None, 0).is_err());
+    }
+
+    #[test]
+    fn optional_fields() {
+        let mut gre = GrePacket::empty();
+        gre.set_payload(&[1, 2, 3]);
+        gre.set_sequence_number(Some(9));
+        gre.set_key(Some(0xdead_beef));
+        gre.set_checksum();
+
+        assert_eq!(gre.data.len(), 19);
+        assert_eq!(gre.data[0], 0xB0);
+        assert_eq!(gre.key(), Some(0xdead_beef));
+        assert_eq!(gre.sequence_number(), Some(9));
+        assert!(gre.validate_checksum());
+        assert_eq!(gre.payload().as_ref(), &[1, 2, 3][..]);
+
+        gre.data[18] = 4;
+        assert!(!gre.validate_checksum());
+
+        gre.remove_checksum();
+        gre.set_key(None);
+        assert_eq!(gre.data[0], 0x10);
+        assert_eq!(gre.sequence_number(), Some(9));
+        assert_eq!(gre.payload_offset, 8);
+    }
+
+    #[test]
+    fn inner_packets() {
+        let mut inner = Ipv4Packet::empty();
+        inner.set_payload(&[1, 2, 3]);
+
+        let mut gre = GrePacket::empty();
+        gre.set_key(Some(7));
+        gre.set_protocol_type(IPV4_ETHER_TYPE);
+        gre.set_payload(&inner.data);
+        assert_eq!(gre.inner_ipv4().unwrap(), inner);
+        assert!(gre.inner_ipv6().is_err());
+        assert!(gre.inner_ethernet().is_err());
+
+        let frame = EthernetFrame::encap_ipv4(inner);
+        gre.set_protocol_type(ETHERNET_BRIDGING_ETHER_TYPE);
+        gre.set_payload(&frame.data);
+        assert_eq!(gre.inner_ethernet().unwrap(), frame);
+    }
+}
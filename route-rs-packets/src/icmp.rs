@@ -0,0 +1,423 @@
+use crate::*;
+use std::borrow::Cow;
+use std::cmp::min;
+use std::convert::{TryFrom, TryInto};
+use std::net::Ipv4Addr;
+
+/// Message types, per RFC 792.
+pub const ICMP_ECHO_REPLY: u8 = 0;
+pub const ICMP_DESTINATION_UNREACHABLE: u8 = 3;
+pub const ICMP_REDIRECT: u8 = 5;
+pub const ICMP_ECHO_REQUEST: u8 = 8;
+pub const ICMP_TIME_EXCEEDED: u8 = 11;
+
+/// Error messages quote as much of the packet that caused them as fits in the 576 bytes every
+/// host must accept, per RFC 1812.
+const MAX_INVOKING_PACKET_LEN: usize = 576 - 20 - 8;
+
+/// The message an IcmpPacket carries, by its type. The invoking packet of an error message is
+/// the start of the packet that caused it, from its IP header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IcmpMessage<'a> {
+    EchoRequest {
+        identifier: u16,
+        sequence_number: u16,
+        data: &'a [u8],
+    },
+    EchoReply {
+        identifier: u16,
+        sequence_number: u16,
+        data: &'a [u8],
+    },
+    /// The next hop MTU is only set for code 4, fragmentation needed, per RFC 1191.
+    DestinationUnreachable {
+        code: u8,
+        next_hop_mtu: u16,
+        invoking_packet: &'a [u8],
+    },
+    TimeExceeded {
+        code: u8,
+        invoking_packet: &'a [u8],
+    },
+    Redirect {
+        code: u8,
+        gateway: Ipv4Addr,
+        invoking_packet: &'a [u8],
+    },
+    /// Any other message, with everything after its checksum as the body.
+    Other {
+        icmp_type: u8,
+        code: u8,
+        body: &'a [u8],
+    },
+}
+
+/// An ICMP packet, per RFC 792. The ICMP header is at `layer4_offset`, since it follows the IP
+/// header, and the payload follows the 8 byte header.
+#[derive(Clone, Debug)]
+pub struct IcmpPacket {
+    pub data: PacketData,
+    pub layer2_offset: Option<usize>,
+    pub layer3_offset: Option<usize>,
+    pub layer4_offset: usize,
+    pub payload_offset: usize,
+}
+
+impl IcmpPacket {
+    pub fn from_buffer(
+        data: PacketData,
+        layer2_offset: Option<usize>,
+        layer3_offset: Option<usize>,
+        layer4_offset: usize,
+    ) -> Result<IcmpPacket, &'static str> {
+        if data.len() < layer4_offset + 8 {
+            return Err("Data is too short to be an IcmpPacket");
+        }
+
+        if let Some(layer3_offset) = layer3_offset {
+            if get_ipv4_payload_type(&data, layer3_offset)? != IpProtocol::ICMP {
+                return Err("Protocol is incorrect, since it isn't ICMP");
+            }
+        }
+
+        Ok(IcmpPacket {
+            data,
+            layer2_offset,
+            layer3_offset,
+            layer4_offset,
+            payload_offset: layer4_offset + 8,
+        })
+    }
+
+    /// Makes an IcmpPacket carrying `message`, with no IP header, and sets its checksum. The
+    /// invoking packet of an error message is cut to fit in 576 bytes with its IP header.
+    pub fn new(message: &IcmpMessage) -> IcmpPacket {
+        let (icmp_type, code, mut body) = match *message {
+            IcmpMessage::EchoRequest {
+                identifier,
+                sequence_number,
+                data,
+            } => (
+                ICMP_ECHO_REQUEST,
+                0,
+                echo_body(identifier, sequence_number, data),
+            ),
+            IcmpMessage::EchoReply {
+                identifier,
+                sequence_number,
+                data,
+            } => (
+                ICMP_ECHO_REPLY,
+                0,
+                echo_body(identifier, sequence_number, data),
+            ),
+            IcmpMessage::DestinationUnreachable {
+                code,
+                next_hop_mtu,
+                invoking_packet,
+            } => {
+                let mut body = vec![0, 0];
+                body.extend_from_slice(&next_hop_mtu.to_be_bytes());
+                (
+                    ICMP_DESTINATION_UNREACHABLE,
+                    code,
+                    error_body(body, invoking_packet),
+                )
+            }
+            IcmpMessage::TimeExceeded {
+                code,
+                invoking_packet,
+            } => (
+                ICMP_TIME_EXCEEDED,
+                code,
+                error_body(vec![0; 4], invoking_packet),
+            ),
+            IcmpMessage::Redirect {
+                code,
+                gateway,
+                invoking_packet,
+            } => (
+                ICMP_REDIRECT,
+                code,
+                error_body(gateway.octets().to_vec(), invoking_packet),
+            ),
+            IcmpMessage::Other {
+                icmp_type,
+                code,
+                body,
+            } => (icmp_type, code, body.to_vec()),
+        };
+        // Every message has at least the 4 bytes of the header after the checksum.
+        if body.len() < 4 {
+            body.resize(4, 0);
+        }
+
+        let mut data = vec![icmp_type, code, 0, 0];
+        data.extend(body);
+        let mut packet = IcmpPacket::from_buffer(data.into(), None, None, 0).unwrap();
+        packet.set_checksum();
+        packet
+    }
+
+    pub fn icmp_type(&self) -> u8 {
+        self.data[self.layer4_offset]
+    }
+
+    pub fn set_icmp_type(&mut self, icmp_type: u8) {
+        self.data[self.layer4_offset] = icmp_type;
+    }
+
+    pub fn code(&self) -> u8 {
+        self.data[self.layer4_offset + 1]
+    }
+
+    pub fn set_code(&mut self, code: u8) {
+        self.data[self.layer4_offset + 1] = code;
+    }
+
+    pub fn checksum(&self) -> u16 {
+        u16::from_be_bytes(
+            self.data[self.layer4_offset + 2..=self.layer4_offset + 3]
+                .try_into()
+                .unwrap(),
+        )
+    }
+
+    /// Calculates what the checksum should be set to, given the current header and payload.
+    pub fn calculate_checksum(&self) -> u16 {
+        internet_checksum(&[
+            &self.data[self.layer4_offset..self.layer4_offset + 2],
+            &self.data[self.layer4_offset + 4..],
+        ])
+    }
+
+    /// Sets checksum field to valid value
+    pub fn set_checksum(&mut self) {
+        let checksum = self.calculate_checksum();
+        self.data[self.layer4_offset + 2..=self.layer4_offset + 3]
+            .copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    pub fn validate_checksum(&self) -> bool {
+        self.checksum() == self.calculate_checksum()
+    }
+
+    /// The 4 bytes after the checksum, whose meaning depends on the type.
+    pub fn rest_of_header(&self) -> [u8; 4] {
+        self.data[self.layer4_offset + 4..self.payload_offset]
+            .try_into()
+            .unwrap()
+    }
+
+    pub fn set_rest_of_header(&mut self, rest_of_header: [u8; 4]) {
+        self.data[self.layer4_offset + 4..self.payload_offset].copy_from_slice(&rest_of_header);
+    }
+
+    pub fn payload(&self) -> Cow<[u8]> {
+        Cow::from(&self.data[self.payload_offset..])
+    }
+
+    /// Set payload of ICMP packet, does not change checksum.
+    /// Don't forget to update the length field of the IP packet that contains this.
+    pub fn set_payload(&mut self, payload: &[u8]) {
+        self.data.truncate(self.payload_offset);
+        self.data.extend_from_slice(payload);
+    }
+
+    /// Returns the message the packet carries, by its type.
+    pub fn message(&self) -> IcmpMessage<'_> {
+        let rest = self.rest_of_header();
+        let payload = &self.data[self.payload_offset..];
+        let code = self.code();
+        let half = |offset: usize| u16::from_be_bytes([rest[offset], rest[offset + 1]]);
+        match self.icmp_type() {
+            ICMP_ECHO_REQUEST => IcmpMessage::EchoRequest {
+                identifier: half(0),
+                sequence_number: half(2),
+                data: payload,
+            },
+            ICMP_ECHO_REPLY => IcmpMessage::EchoReply {
+                identifier: half(0),
+                sequence_number: half(2),
+                data: payload,
+            },
+            ICMP_DESTINATION_UNREACHABLE => IcmpMessage::DestinationUnreachable {
+                code,
+                next_hop_mtu: half(2),
+                invoking_packet: payload,
+            },
+            ICMP_TIME_EXCEEDED => IcmpMessage::TimeExceeded {
+                code,
+                invoking_packet: payload,
+            },
+            ICMP_REDIRECT => IcmpMessage::Redirect {
+                code,
+                gateway: Ipv4Addr::from(rest),
+                invoking_packet: payload,
+            },
+            icmp_type => IcmpMessage::Other {
+                icmp_type,
+                code,
+                body: &self.data[self.layer4_offset + 4..],
+            },
+        }
+    }
+}
+
+fn echo_body(identifier: u16, sequence_number: u16, data: &[u8]) -> Vec<u8> {
+    let mut body = identifier.to_be_bytes().to_vec();
+    body.extend_from_slice(&sequence_number.to_be_bytes());
+    body.extend_from_slice(data);
+    body
+}
+
+fn error_body(mut body: Vec<u8>, invoking_packet: &[u8]) -> Vec<u8> {
+    body.extend_from_slice(&invoking_packet[..min(invoking_packet.len(), MAX_INVOKING_PACKET_LEN)]);
+    body
+}
+
+/// IcmpPackets are considered the same if they have the same data from the layer 4
+/// header and onward. This function does not consider the data before the start of
+/// the ICMP header.
+impl PartialEq for IcmpPacket {
+    fn eq(&self, other: &Self) -> bool {
+        self.data[self.layer4_offset..] == other.data[other.layer4_offset..]
+    }
+}
+
+impl Eq for IcmpPacket {}
+
+impl TryFrom<Ipv4Packet> for IcmpPacket {
+    type Error = &'static str;
+
+    fn try_from(packet: Ipv4Packet) -> Result<Self, Self::Error> {
+        IcmpPacket::from_buffer(
+            packet.data,
+            packet.layer2_offset,
+            Some(packet.layer3_offset),
+            packet.payload_offset,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An echo request sent by Windows ping, after its Ethernet and IPv4 headers.
+    const PING: [u8; 40] = [
+        0x08, 0x00, 0x4d, 0x56, 0x00, 0x01, 0x00, 0x05, 0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67,
+        0x68, 0x69, 0x6a, 0x6b, 0x6c, 0x6d, 0x6e, 0x6f, 0x70, 0x71, 0x72, 0x73, 0x74, 0x75, 0x76,
+        0x77, 0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    ];
+
+    #[test]
+    fn echo_request() {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(1);
+        packet.set_payload(&PING);
+        let icmp = IcmpPacket::try_from(packet).unwrap();
+
+        assert_eq!(icmp.layer4_offset, 20);
+        assert_eq!(icmp.icmp_type(), ICMP_ECHO_REQUEST);
+        assert_eq!(icmp.code(), 0);
+        assert_eq!(icmp.checksum(), 0x4d56);
+        assert!(icmp.validate_checksum());
+        assert_eq!(
+            icmp.message(),
+            IcmpMessage::EchoRequest {
+                identifier: 1,
+                sequence_number: 5,
+                data: &PING[8..],
+            }
+        );
+
+        let built = IcmpPacket::new(&icmp.message());
+        assert_eq!(built.data[..], PING[..]);
+        assert_eq!(built, icmp);
+
+        let packet = Ipv4Packet::encap_icmp(built);
+        assert_eq!(packet.protocol(), IpProtocol::ICMP);
+        assert_eq!(IcmpPacket::try_from(packet).unwrap(), icmp);
+    }
+
+    #[test]
+    fn echo_reply() {
+        let request = IcmpPacket::from_buffer(PING.to_vec().into(), None, None, 0).unwrap();
+        let reply = IcmpPacket::new(&IcmpMessage::EchoReply {
+            identifier: 1,
+            sequence_number: 5,
+            data: &request.payload(),
+        });
+        assert_eq!(reply.icmp_type(), ICMP_ECHO_REPLY);
+        // Only the type changed, by 8 in the high byte.
+        assert_eq!(reply.checksum(), 0x5556);
+        assert!(reply.validate_checksum());
+        assert_eq!(reply.data[4..], PING[4..]);
+    }
+
+    #[test]
+    fn error_messages() {
+        let mut invoking = Ipv4Packet::empty();
+        invoking.set_protocol(17);
+        invoking.set_payload(&[0; 1000]);
+
+        let unreachable = IcmpPacket::new(&IcmpMessage::DestinationUnreachable {
+            code: 4,
+            next_hop_mtu: 1400,
+            invoking_packet: &invoking.data,
+        });
+        assert!(unreachable.validate_checksum());
+        assert_eq!(unreachable.rest_of_header(), [0, 0, 0x05, 0x78]);
+        assert_eq!(unreachable.data.len(), 576 - 20);
+        assert_eq!(
+            unreachable.message(),
+            IcmpMessage::DestinationUnreachable {
+                code: 4,
+                next_hop_mtu: 1400,
+                invoking_packet: &invoking.data[..548],
+            }
+        );
+
+        let exceeded = IcmpPacket::new(&IcmpMessage::TimeExceeded {
+            code: 0,
+            invoking_packet: &invoking.data[..28],
+        });
+        assert!(exceeded.validate_checksum());
+        assert_eq!(exceeded.icmp_type(), ICMP_TIME_EXCEEDED);
+        assert_eq!(exceeded.payload()[..], invoking.data[..28]);
+
+        let redirect = IcmpMessage::Redirect {
+            code: 1,
+            gateway: Ipv4Addr::new(10, 0, 0, 1),
+            invoking_packet: &invoking.data[..28],
+        };
+        let mut packet = IcmpPacket::new(&redirect);
+        assert!(packet.validate_checksum());
+        assert_eq!(packet.rest_of_header(), [10, 0, 0, 1]);
+        assert_eq!(packet.message(), redirect);
+
+        packet.set_code(0);
+        assert!(!packet.validate_checksum());
+        packet.set_checksum();
+        assert!(packet.validate_checksum());
+    }
+
+    #[test]
+    fn other_messages() {
+        let timestamp = IcmpMessage::Other {
+            icmp_type: 13,
+            code: 0,
+            body: &[0, 1, 0, 2, 0, 0, 0, 9, 0, 0, 0, 0, 0, 0, 0, 0],
+        };
+        let packet = IcmpPacket::new(&timestamp);
+        assert!(packet.validate_checksum());
+        assert_eq!(packet.message(), timestamp);
+
+        assert!(IcmpPacket::from_buffer(vec![8, 0, 0, 0].into(), None, None, 0).is_err());
+        let mut udp = Ipv4Packet::empty();
+        udp.set_protocol(17);
+        udp.set_payload(&PING);
+        assert!(IcmpPacket::try_from(udp).is_err());
+    }
+}
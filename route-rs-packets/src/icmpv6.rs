@@ -0,0 +1,1005 @@
+use crate::*;
+use std::borrow::Cow;
+use std::cmp::min;
+use std::convert::{TryFrom, TryInto};
+use std::net::Ipv6Addr;
+
+/// Message types, per RFC 4443, RFC 4861 and RFC 3810.
+pub const ICMPV6_DESTINATION_UNREACHABLE: u8 = 1;
+pub const ICMPV6_PACKET_TOO_BIG: u8 = 2;
+pub const ICMPV6_TIME_EXCEEDED: u8 = 3;
+pub const ICMPV6_PARAMETER_PROBLEM: u8 = 4;
+pub const ICMPV6_ECHO_REQUEST: u8 = 128;
+pub const ICMPV6_ECHO_REPLY: u8 = 129;
+pub const ICMPV6_MULTICAST_LISTENER_QUERY: u8 = 130;
+pub const ICMPV6_MULTICAST_LISTENER_REPORT: u8 = 131;
+pub const ICMPV6_MULTICAST_LISTENER_DONE: u8 = 132;
+pub const ICMPV6_ROUTER_SOLICITATION: u8 = 133;
+pub const ICMPV6_ROUTER_ADVERTISEMENT: u8 = 134;
+pub const ICMPV6_NEIGHBOR_SOLICITATION: u8 = 135;
+pub const ICMPV6_NEIGHBOR_ADVERTISEMENT: u8 = 136;
+pub const ICMPV6_REDIRECT: u8 = 137;
+pub const ICMPV6_MULTICAST_LISTENER_REPORT_V2: u8 = 143;
+
+/// Neighbor Discovery option types, per RFC 4861.
+pub const NDP_OPTION_SOURCE_LINK_LAYER_ADDR: u8 = 1;
+pub const NDP_OPTION_TARGET_LINK_LAYER_ADDR: u8 = 2;
+pub const NDP_OPTION_PREFIX_INFORMATION: u8 = 3;
+pub const NDP_OPTION_REDIRECTED_HEADER: u8 = 4;
+pub const NDP_OPTION_MTU: u8 = 5;
+
+/// The next header value of ICMPv6, used in the checksum's pseudo header.
+const ICMPV6_NEXT_HEADER: u8 = 58;
+
+/// Error messages quote as much of the packet that caused them as fits in the 1280 byte minimum
+/// MTU, per RFC 4443.
+const MAX_INVOKING_PACKET_LEN: usize = 1280 - 40 - 8;
+
+/// Flag bits of router and neighbor advertisements.
+const MANAGED_FLAG: u8 = 0x80;
+const OTHER_CONFIG_FLAG: u8 = 0x40;
+const ROUTER_FLAG: u8 = 0x80;
+const SOLICITED_FLAG: u8 = 0x40;
+const OVERRIDE_FLAG: u8 = 0x20;
+
+/// Flag bits of the prefix information option.
+const ON_LINK_FLAG: u8 = 0x80;
+const AUTONOMOUS_FLAG: u8 = 0x40;
+
+/// A Neighbor Discovery option. Options of known types but the wrong length are kept as Other.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NdpOption {
+    SourceLinkLayerAddr(MacAddr),
+    TargetLinkLayerAddr(MacAddr),
+    PrefixInformation {
+        prefix_len: u8,
+        on_link: bool,
+        autonomous: bool,
+        valid_lifetime: u32,
+        preferred_lifetime: u32,
+        prefix: Ipv6Addr,
+    },
+    /// The start of the packet being redirected, from its IP header.
+    RedirectedHeader(Vec<u8>),
+    Mtu(u32),
+    /// Any other option, with everything after its type and length bytes as the data.
+    Other {
+        kind: u8,
+        data: Vec<u8>,
+    },
+}
+
+impl NdpOption {
+    /// Parses an option of `kind` with `data` following its type and length bytes.
+    fn parse(kind: u8, data: &[u8]) -> NdpOption {
+        let be_u32 = |bytes: &[u8]| u32::from_be_bytes(bytes.try_into().unwrap());
+        match (kind, data.len()) {
+            (NDP_OPTION_SOURCE_LINK_LAYER_ADDR, 6) => {
+                NdpOption::SourceLinkLayerAddr(MacAddr::new(data.try_into().unwrap()))
+            }
+            (NDP_OPTION_TARGET_LINK_LAYER_ADDR, 6) => {
+                NdpOption::TargetLinkLayerAddr(MacAddr::new(data.try_into().unwrap()))
+            }
+            (NDP_OPTION_PREFIX_INFORMATION, 30) => {
+                let prefix: [u8; 16] = data[14..].try_into().unwrap();
+                NdpOption::PrefixInformation {
+                    prefix_len: data[0],
+                    on_link: data[1] & ON_LINK_FLAG != 0,
+                    autonomous: data[1] & AUTONOMOUS_FLAG != 0,
+                    valid_lifetime: be_u32(&data[2..6]),
+                    preferred_lifetime: be_u32(&data[6..10]),
+                    prefix: Ipv6Addr::from(prefix),
+                }
+            }
+            (NDP_OPTION_REDIRECTED_HEADER, len) if len >= 6 => {
+                NdpOption::RedirectedHeader(data[6..].to_vec())
+            }
+            (NDP_OPTION_MTU, 6) => NdpOption::Mtu(be_u32(&data[2..])),
+            _ => NdpOption::Other {
+                kind,
+                data: data.to_vec(),
+            },
+        }
+    }
+
+    pub fn kind(&self) -> u8 {
+        match self {
+            NdpOption::SourceLinkLayerAddr(_) => NDP_OPTION_SOURCE_LINK_LAYER_ADDR,
+            NdpOption::TargetLinkLayerAddr(_) => NDP_OPTION_TARGET_LINK_LAYER_ADDR,
+            NdpOption::PrefixInformation { .. } => NDP_OPTION_PREFIX_INFORMATION,
+            NdpOption::RedirectedHeader(_) => NDP_OPTION_REDIRECTED_HEADER,
+            NdpOption::Mtu(_) => NDP_OPTION_MTU,
+            NdpOption::Other { kind, .. } => *kind,
+        }
+    }
+
+    /// Returns the option as it's sent, with its type and length bytes, padded with zeros to a
+    /// multiple of 8 bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.kind(), 0];
+        match self {
+            NdpOption::SourceLinkLayerAddr(mac) | NdpOption::TargetLinkLayerAddr(mac) => {
+                bytes.extend_from_slice(&mac.bytes)
+            }
+            NdpOption::PrefixInformation {
+                prefix_len,
+                on_link,
+                autonomous,
+                valid_lifetime,
+                preferred_lifetime,
+                prefix,
+            } => {
+                let mut flags = 0;
+                if *on_link {
+                    flags |= ON_LINK_FLAG;
+                }
+                if *autonomous {
+                    flags |= AUTONOMOUS_FLAG;
+                }
+                bytes.extend_from_slice(&[*prefix_len, flags]);
+                bytes.extend_from_slice(&valid_lifetime.to_be_bytes());
+                bytes.extend_from_slice(&preferred_lifetime.to_be_bytes());
+                bytes.extend_from_slice(&[0; 4]);
+                bytes.extend_from_slice(&prefix.octets());
+            }
+            NdpOption::RedirectedHeader(header) => {
+                bytes.extend_from_slice(&[0; 6]);
+                bytes.extend_from_slice(header);
+            }
+            NdpOption::Mtu(mtu) => {
+                bytes.extend_from_slice(&[0; 2]);
+                bytes.extend_from_slice(&mtu.to_be_bytes());
+            }
+            NdpOption::Other { data, .. } => bytes.extend_from_slice(data),
+        }
+
+        bytes.resize(bytes.len().div_ceil(8) * 8, 0);
+        bytes[1] = (bytes.len() / 8) as u8;
+        bytes
+    }
+}
+
+/// Parses Neighbor Discovery options, until one has a length of 0 or runs past the end.
+fn parse_ndp_options(mut options: &[u8]) -> Vec<NdpOption> {
+    let mut parsed = vec![];
+    while options.len() >= 2 {
+        let len = usize::from(options[1]) * 8;
+        if len == 0 || len > options.len() {
+            break;
+        }
+        parsed.push(NdpOption::parse(options[0], &options[2..len]));
+        options = &options[len..];
+    }
+    parsed
+}
+
+/// The message an Icmpv6Packet carries, by its type. The invoking packet of an error message is
+/// the start of the packet that caused it, from its IP header. A multicast listener query is
+/// MLDv1 if it's 24 bytes long, and MLDv2 if it's longer, as with IGMP queries.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Icmpv6Message<'a> {
+    DestinationUnreachable {
+        code: u8,
+        invoking_packet: &'a [u8],
+    },
+    PacketTooBig {
+        mtu: u32,
+        invoking_packet: &'a [u8],
+    },
+    TimeExceeded {
+        code: u8,
+        invoking_packet: &'a [u8],
+    },
+    ParameterProblem {
+        code: u8,
+        pointer: u32,
+        invoking_packet: &'a [u8],
+    },
+    EchoRequest {
+        identifier: u16,
+        sequence_number: u16,
+        data: &'a [u8],
+    },
+    EchoReply {
+        identifier: u16,
+        sequence_number: u16,
+        data: &'a [u8],
+    },
+    RouterSolicitation {
+        options: Vec<NdpOption>,
+    },
+    RouterAdvertisement {
+        cur_hop_limit: u8,
+        managed: bool,
+        other_config: bool,
+        router_lifetime: u16,
+        reachable_time: u32,
+        retrans_timer: u32,
+        options: Vec<NdpOption>,
+    },
+    NeighborSolicitation {
+        target: Ipv6Addr,
+        options: Vec<NdpOption>,
+    },
+    NeighborAdvertisement {
+        router: bool,
+        solicited: bool,
+        override_entry: bool,
+        target: Ipv6Addr,
+        options: Vec<NdpOption>,
+    },
+    Redirect {
+        target: Ipv6Addr,
+        destination: Ipv6Addr,
+        options: Vec<NdpOption>,
+    },
+    MulticastListenerQuery {
+        max_resp_delay: u16,
+        group: Ipv6Addr,
+    },
+    MulticastListenerQueryV2 {
+        max_resp_code: u16,
+        group: Ipv6Addr,
+        suppress_router_processing: bool,
+        robustness: u8,
+        query_interval_code: u8,
+        sources: Vec<Ipv6Addr>,
+    },
+    MulticastListenerReport {
+        group: Ipv6Addr,
+    },
+    MulticastListenerDone {
+        group: Ipv6Addr,
+    },
+    MulticastListenerReportV2 {
+        records: Vec<MldAddressRecord>,
+    },
+    /// Any other message, or one too short for its type, with everything after its checksum as
+    /// the body.
+    Other {
+        icmp_type: u8,
+        code: u8,
+        body: &'a [u8],
+    },
+}
+
+/// An ICMPv6 packet, per RFC 4443. The ICMPv6 header is at `layer4_offset`, since it follows the
+/// IPv6 header, and the payload follows the 8 byte header. The checksum covers a pseudo header
+/// of the IPv6 addresses, so calculating it takes them.
+#[derive(Clone, Debug)]
+pub struct Icmpv6Packet {
+    pub data: PacketData,
+    pub layer2_offset: Option<usize>,
+    pub layer3_offset: Option<usize>,
+    pub layer4_offset: usize,
+    pub payload_offset: usize,
+}
+
+impl Icmpv6Packet {
+    pub fn from_buffer(
+        data: PacketData,
+        layer2_offset: Option<usize>,
+        layer3_offset: Option<usize>,
+        layer4_offset: usize,
+    ) -> Result<Icmpv6Packet, &'static str> {
+        if data.len() < layer4_offset + 8 {
+            return Err("Data is too short to be an Icmpv6Packet");
+        }
+
+        if let Some(layer3_offset) = layer3_offset {
+            if get_ipv6_payload_type(&data, layer3_offset)? != IpProtocol::IPv6_ICMP {
+                return Err("Protocol is incorrect, since it isn't ICMPv6");
+            }
+        }
+
+        Ok(Icmpv6Packet {
+            data,
+            layer2_offset,
+            layer3_offset,
+            layer4_offset,
+            payload_offset: layer4_offset + 8,
+        })
+    }
+
+    /// Makes an Icmpv6Packet carrying `message`, with no IP header, and sets its checksum for a
+    /// packet from `src` to `dest`. The invoking packet of an error message is cut to fit in the
+    /// minimum MTU with its IP header.
+    pub fn new(src: Ipv6Addr, dest: Ipv6Addr, message: &Icmpv6Message) -> Icmpv6Packet {
+        let (icmp_type, code, mut body) = match message {
+            Icmpv6Message::DestinationUnreachable {
+                code,
+                invoking_packet,
+            } => (
+                ICMPV6_DESTINATION_UNREACHABLE,
+                *code,
+                error_body([0; 4], invoking_packet),
+            ),
+            Icmpv6Message::PacketTooBig {
+                mtu,
+                invoking_packet,
+            } => (
+                ICMPV6_PACKET_TOO_BIG,
+                0,
+                error_body(mtu.to_be_bytes(), invoking_packet),
+            ),
+            Icmpv6Message::TimeExceeded {
+                code,
+                invoking_packet,
+            } => (
+                ICMPV6_TIME_EXCEEDED,
+                *code,
+                error_body([0; 4], invoking_packet),
+            ),
+            Icmpv6Message::ParameterProblem {
+                code,
+                pointer,
+                invoking_packet,
+            } => (
+                ICMPV6_PARAMETER_PROBLEM,
+                *code,
+                error_body(pointer.to_be_bytes(), invoking_packet),
+            ),
+            Icmpv6Message::EchoRequest {
+                identifier,
+                sequence_number,
+                data,
+            } => (
+                ICMPV6_ECHO_REQUEST,
+                0,
+                echo_body(*identifier, *sequence_number, data),
+            ),
+            Icmpv6Message::EchoReply {
+                identifier,
+                sequence_number,
+                data,
+            } => (
+                ICMPV6_ECHO_REPLY,
+                0,
+                echo_body(*identifier, *sequence_number, data),
+            ),
+            Icmpv6Message::RouterSolicitation { options } => {
+                (ICMPV6_ROUTER_SOLICITATION, 0, ndp_body(vec![0; 4], options))
+            }
+            Icmpv6Message::RouterAdvertisement {
+                cur_hop_limit,
+                managed,
+                other_config,
+                router_lifetime,
+                reachable_time,
+                retrans_timer,
+                options,
+            } => {
+                let mut flags = 0;
+                if *managed {
+                    flags |= MANAGED_FLAG;
+                }
+                if *other_config {
+                    flags |= OTHER_CONFIG_FLAG;
+                }
+                let mut body = vec![*cur_hop_limit, flags];
+                body.extend_from_slice(&router_lifetime.to_be_bytes());
+                body.extend_from_slice(&reachable_time.to_be_bytes());
+                body.extend_from_slice(&retrans_timer.to_be_bytes());
+                (ICMPV6_ROUTER_ADVERTISEMENT, 0, ndp_body(body, options))
+            }
+            Icmpv6Message::NeighborSolicitation { target, options } => {
+                let mut body = vec![0; 4];
+                body.extend_from_slice(&target.octets());
+                (ICMPV6_NEIGHBOR_SOLICITATION, 0, ndp_body(body, options))
+            }
+            Icmpv6Message::NeighborAdvertisement {
+                router,
+                solicited,
+                override_entry,
+                target,
+                options,
+            } => {
+                let mut flags = 0;
+                if *router {
+                    flags |= ROUTER_FLAG;
+                }
+                if *solicited {
+                    flags |= SOLICITED_FLAG;
+                }
+                if *override_entry {
+                    flags |= OVERRIDE_FLAG;
+                }
+                let mut body = vec![flags, 0, 0, 0];
+                body.extend_from_slice(&target.octets());
+                (ICMPV6_NEIGHBOR_ADVERTISEMENT, 0, ndp_body(body, options))
+            }
+            Icmpv6Message::Redirect {
+                target,
+                destination,
+                options,
+            } => {
+                let mut body = vec![0; 4];
+                body.extend_from_slice(&target.octets());
+                body.extend_from_slice(&destination.octets());
+                (ICMPV6_REDIRECT, 0, ndp_body(body, options))
+            }
+            Icmpv6Message::MulticastListenerQuery {
+                max_resp_delay,
+                group,
+            } => (
+                ICMPV6_MULTICAST_LISTENER_QUERY,
+                0,
+                mld_body(*max_resp_delay, group),
+            ),
+            Icmpv6Message::MulticastListenerQueryV2 {
+                max_resp_code,
+                group,
+                suppress_router_processing,
+                robustness,
+                query_interval_code,
+                sources,
+            } => {
+                let mut body = mld_body(*max_resp_code, group);
+                body.extend(query_v3_fields(
+                    *suppress_router_processing,
+                    *robustness,
+                    *query_interval_code,
+                    sources,
+                ));
+                (ICMPV6_MULTICAST_LISTENER_QUERY, 0, body)
+            }
+            Icmpv6Message::MulticastListenerReport { group } => {
+                (ICMPV6_MULTICAST_LISTENER_REPORT, 0, mld_body(0, group))
+            }
+            Icmpv6Message::MulticastListenerDone { group } => {
+                (ICMPV6_MULTICAST_LISTENER_DONE, 0, mld_body(0, group))
+            }
+            Icmpv6Message::MulticastListenerReportV2 { records } => {
+                let mut body = vec![0, 0];
+                body.extend_from_slice(&(records.len() as u16).to_be_bytes());
+                body.extend(write_group_records(records));
+                (ICMPV6_MULTICAST_LISTENER_REPORT_V2, 0, body)
+            }
+            Icmpv6Message::Other {
+                icmp_type,
+                code,
+                body,
+            } => (*icmp_type, *code, body.to_vec()),
+        };
+        // Every message has at least the 4 bytes of the header after the checksum.
+        if body.len() < 4 {
+            body.resize(4, 0);
+        }
+
+        let mut data = vec![icmp_type, code, 0, 0];
+        data.extend(body);
+        let mut packet = Icmpv6Packet::from_buffer(data.into(), None, None, 0).unwrap();
+        packet.set_checksum(src, dest);
+        packet
+    }
+
+    pub fn icmp_type(&self) -> u8 {
+        self.data[self.layer4_offset]
+    }
+
+    pub fn set_icmp_type(&mut self, icmp_type: u8) {
+        self.data[self.layer4_offset] = icmp_type;
+    }
+
+    pub fn code(&self) -> u8 {
+        self.data[self.layer4_offset + 1]
+    }
+
+    pub fn set_code(&mut self, code: u8) {
+        self.data[self.layer4_offset + 1] = code;
+    }
+
+    pub fn checksum(&self) -> u16 {
+        u16::from_be_bytes(
+            self.data[self.layer4_offset + 2..=self.layer4_offset + 3]
+                .try_into()
+                .unwrap(),
+        )
+    }
+
+    /// Calculates what the checksum should be set to, given the current header and payload, for
+    /// a packet from `src` to `dest`.
+    pub fn calculate_checksum(&self, src: Ipv6Addr, dest: Ipv6Addr) -> u16 {
+        let len = (self.data.len() - self.layer4_offset) as u32;
+        let mut pseudo_header = [0; 40];
+        pseudo_header[..16].copy_from_slice(&src.octets());
+        pseudo_header[16..32].copy_from_slice(&dest.octets());
+        pseudo_header[32..36].copy_from_slice(&len.to_be_bytes());
+        pseudo_header[39] = ICMPV6_NEXT_HEADER;
+        internet_checksum(&[
+            &pseudo_header,
+            &self.data[self.layer4_offset..self.layer4_offset + 2],
+            &self.data[self.layer4_offset + 4..],
+        ])
+    }
+
+    /// Sets checksum field to valid value, for a packet from `src` to `dest`.
+    pub fn set_checksum(&mut self, src: Ipv6Addr, dest: Ipv6Addr) {
+        let checksum = self.calculate_checksum(src, dest);
+        self.data[self.layer4_offset + 2..=self.layer4_offset + 3]
+            .copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    pub fn validate_checksum(&self, src: Ipv6Addr, dest: Ipv6Addr) -> bool {
+        self.checksum() == self.calculate_checksum(src, dest)
+    }
+
+    /// The 4 bytes after the checksum, whose meaning depends on the type.
+    pub fn rest_of_header(&self) -> [u8; 4] {
+        self.data[self.layer4_offset + 4..self.payload_offset]
+            .try_into()
+            .unwrap()
+    }
+
+    pub fn set_rest_of_header(&mut self, rest_of_header: [u8; 4]) {
+        self.data[self.layer4_offset + 4..self.payload_offset].copy_from_slice(&rest_of_header);
+    }
+
+    pub fn payload(&self) -> Cow<[u8]> {
+        Cow::from(&self.data[self.payload_offset..])
+    }
+
+    /// Set payload of ICMPv6 packet, does not change checksum.
+    /// Don't forget to update the payload length field of the IP packet that contains this.
+    pub fn set_payload(&mut self, payload: &[u8]) {
+        self.data.truncate(self.payload_offset);
+        self.data.extend_from_slice(payload);
+    }
+
+    /// Returns the message the packet carries, by its type.
+    pub fn message(&self) -> Icmpv6Message<'_> {
+        let rest = self.rest_of_header();
+        let payload = &self.data[self.payload_offset..];
+        let code = self.code();
+        let half = |offset: usize| u16::from_be_bytes([rest[offset], rest[offset + 1]]);
+        let addr = |offset: usize| {
+            let addr: [u8; 16] = payload[offset..offset + 16].try_into().unwrap();
+            Ipv6Addr::from(addr)
+        };
+        match (self.icmp_type(), payload.len()) {
+            (ICMPV6_DESTINATION_UNREACHABLE, _) => Icmpv6Message::DestinationUnreachable {
+                code,
+                invoking_packet: payload,
+            },
+            (ICMPV6_PACKET_TOO_BIG, _) => Icmpv6Message::PacketTooBig {
+                mtu: u32::from_be_bytes(rest),
+                invoking_packet: payload,
+            },
+            (ICMPV6_TIME_EXCEEDED, _) => Icmpv6Message::TimeExceeded {
+                code,
+                invoking_packet: payload,
+            },
+            (ICMPV6_PARAMETER_PROBLEM, _) => Icmpv6Message::ParameterProblem {
+                code,
+                pointer: u32::from_be_bytes(rest),
+                invoking_packet: payload,
+            },
+            (ICMPV6_ECHO_REQUEST, _) => Icmpv6Message::EchoRequest {
+                identifier: half(0),
+                sequence_number: half(2),
+                data: payload,
+            },
+            (ICMPV6_ECHO_REPLY, _) => Icmpv6Message::EchoReply {
+                identifier: half(0),
+                sequence_number: half(2),
+                data: payload,
+            },
+            (ICMPV6_ROUTER_SOLICITATION, _) => Icmpv6Message::RouterSolicitation {
+                options: parse_ndp_options(payload),
+            },
+            (ICMPV6_ROUTER_ADVERTISEMENT, len) if len >= 8 => {
+                let be_u32 = |offset: usize| {
+                    u32::from_be_bytes(payload[offset..offset + 4].try_into().unwrap())
+                };
+                Icmpv6Message::RouterAdvertisement {
+                    cur_hop_limit: rest[0],
+                    managed: rest[1] & MANAGED_FLAG != 0,
+                    other_config: rest[1] & OTHER_CONFIG_FLAG != 0,
+                    router_lifetime: half(2),
+                    reachable_time: be_u32(0),
+                    retrans_timer: be_u32(4),
+                    options: parse_ndp_options(&payload[8..]),
+                }
+            }
+            (ICMPV6_NEIGHBOR_SOLICITATION, len) if len >= 16 => {
+                Icmpv6Message::NeighborSolicitation {
+                    target: addr(0),
+                    options: parse_ndp_options(&payload[16..]),
+                }
+            }
+            (ICMPV6_NEIGHBOR_ADVERTISEMENT, len) if len >= 16 => {
+                Icmpv6Message::NeighborAdvertisement {
+                    router: rest[0] & ROUTER_FLAG != 0,
+                    solicited: rest[0] & SOLICITED_FLAG != 0,
+                    override_entry: rest[0] & OVERRIDE_FLAG != 0,
+                    target: addr(0),
+                    options: parse_ndp_options(&payload[16..]),
+                }
+            }
+            (ICMPV6_REDIRECT, len) if len >= 32 => Icmpv6Message::Redirect {
+                target: addr(0),
+                destination: addr(16),
+                options: parse_ndp_options(&payload[32..]),
+            },
+            (ICMPV6_MULTICAST_LISTENER_QUERY, 16) => Icmpv6Message::MulticastListenerQuery {
+                max_resp_delay: half(0),
+                group: addr(0),
+            },
+            (ICMPV6_MULTICAST_LISTENER_QUERY, len) if len >= 20 => {
+                match parse_query_v3(&payload[16..]) {
+                    Some((
+                        suppress_router_processing,
+                        robustness,
+                        query_interval_code,
+                        sources,
+                    )) => Icmpv6Message::MulticastListenerQueryV2 {
+                        max_resp_code: half(0),
+                        group: addr(0),
+                        suppress_router_processing,
+                        robustness,
+                        query_interval_code,
+                        sources,
+                    },
+                    None => self.other(),
+                }
+            }
+            (ICMPV6_MULTICAST_LISTENER_REPORT, len) if len >= 16 => {
+                Icmpv6Message::MulticastListenerReport { group: addr(0) }
+            }
+            (ICMPV6_MULTICAST_LISTENER_DONE, len) if len >= 16 => {
+                Icmpv6Message::MulticastListenerDone { group: addr(0) }
+            }
+            (ICMPV6_MULTICAST_LISTENER_REPORT_V2, _) => match parse_group_records(payload, half(2))
+            {
+                Some(records) => Icmpv6Message::MulticastListenerReportV2 { records },
+                None => self.other(),
+            },
+            _ => self.other(),
+        }
+    }
+
+    fn other(&self) -> Icmpv6Message<'_> {
+        Icmpv6Message::Other {
+            icmp_type: self.icmp_type(),
+            code: self.code(),
+            body: &self.data[self.layer4_offset + 4..],
+        }
+    }
+}
+
+fn echo_body(identifier: u16, sequence_number: u16, data: &[u8]) -> Vec<u8> {
+    let mut body = identifier.to_be_bytes().to_vec();
+    body.extend_from_slice(&sequence_number.to_be_bytes());
+    body.extend_from_slice(data);
+    body
+}
+
+fn error_body(rest_of_header: [u8; 4], invoking_packet: &[u8]) -> Vec<u8> {
+    let mut body = rest_of_header.to_vec();
+    body.extend_from_slice(&invoking_packet[..min(invoking_packet.len(), MAX_INVOKING_PACKET_LEN)]);
+    body
+}
+
+fn ndp_body(mut body: Vec<u8>, options: &[NdpOption]) -> Vec<u8> {
+    body.extend(options.iter().flat_map(NdpOption::to_bytes));
+    body
+}
+
+fn mld_body(max_resp_delay: u16, group: &Ipv6Addr) -> Vec<u8> {
+    let mut body = max_resp_delay.to_be_bytes().to_vec();
+    body.extend_from_slice(&[0, 0]);
+    body.extend_from_slice(&group.octets());
+    body
+}
+
+/// Icmpv6Packets are considered the same if they have the same data from the layer 4
+/// header and onward. This function does not consider the data before the start of
+/// the ICMPv6 header.
+impl PartialEq for Icmpv6Packet {
+    fn eq(&self, other: &Self) -> bool {
+        self.data[self.layer4_offset..] == other.data[other.layer4_offset..]
+    }
+}
+
+impl Eq for Icmpv6Packet {}
+
+impl TryFrom<Ipv6Packet> for Icmpv6Packet {
+    type Error = &'static str;
+
+    fn try_from(packet: Ipv6Packet) -> Result<Self, Self::Error> {
+        Icmpv6Packet::from_buffer(
+            packet.data,
+            packet.layer2_offset,
+            Some(packet.layer3_offset),
+            packet.payload_offset,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOST: Ipv6Addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0x0200, 0, 0, 1);
+    const ROUTER: Ipv6Addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0x0200, 0, 0, 2);
+    const SOLICITED_NODE: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 1, 0xff00, 2);
+
+    /// A neighbor solicitation for ROUTER, from HOST, with its link-layer address, as sent to
+    /// ROUTER's solicited node multicast address.
+    const SOLICITATION: [u8; 32] = [
+        0x87, 0x00, 0x76, 0x97, 0x00, 0x00, 0x00, 0x00, 0xfe, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x01, 0x01, 0x02, 0x00, 0x00, 0x00,
+        0x00, 0x01,
+    ];
+
+    fn solicitation_packet() -> Ipv6Packet {
+        let mut packet = Ipv6Packet::empty();
+        packet.set_next_header(58);
+        packet.set_src_addr(HOST);
+        packet.set_dest_addr(SOLICITED_NODE);
+        packet.set_payload(&SOLICITATION);
+        packet
+    }
+
+    #[test]
+    fn neighbor_solicitation() {
+        let icmp = Icmpv6Packet::try_from(solicitation_packet()).unwrap();
+        assert_eq!(icmp.layer4_offset, 40);
+        assert_eq!(icmp.icmp_type(), ICMPV6_NEIGHBOR_SOLICITATION);
+        assert!(icmp.validate_checksum(HOST, SOLICITED_NODE));
+        assert!(!icmp.validate_checksum(HOST, ROUTER));
+
+        let message = icmp.message();
+        assert_eq!(
+            message,
+            Icmpv6Message::NeighborSolicitation {
+                target: ROUTER,
+                options: vec![NdpOption::SourceLinkLayerAddr(MacAddr::new([
+                    2, 0, 0, 0, 0, 1
+                ]))],
+            }
+        );
+        let built = Icmpv6Packet::new(HOST, SOLICITED_NODE, &message);
+        assert_eq!(built.data[..], SOLICITATION[..]);
+        assert_eq!(built, icmp);
+    }
+
+    #[test]
+    fn neighbor_advertisement() {
+        let message = Icmpv6Message::NeighborAdvertisement {
+            router: true,
+            solicited: true,
+            override_entry: false,
+            target: ROUTER,
+            options: vec![NdpOption::TargetLinkLayerAddr(MacAddr::new([
+                2, 0, 0, 0, 0, 2,
+            ]))],
+        };
+        let packet = Icmpv6Packet::new(ROUTER, HOST, &message);
+        assert!(packet.validate_checksum(ROUTER, HOST));
+        assert_eq!(packet.rest_of_header(), [0xc0, 0, 0, 0]);
+        assert_eq!(packet.data.len(), 32);
+        assert_eq!(packet.message(), message);
+    }
+
+    #[test]
+    fn router_advertisement() {
+        let message = Icmpv6Message::RouterAdvertisement {
+            cur_hop_limit: 64,
+            managed: false,
+            other_config: true,
+            router_lifetime: 1800,
+            reachable_time: 0,
+            retrans_timer: 0,
+            options: vec![
+                NdpOption::SourceLinkLayerAddr(MacAddr::new([2, 0, 0, 0, 0, 2])),
+                NdpOption::Mtu(1500),
+                NdpOption::PrefixInformation {
+                    prefix_len: 64,
+                    on_link: true,
+                    autonomous: true,
+                    valid_lifetime: 86400,
+                    preferred_lifetime: 14400,
+                    prefix: Ipv6Addr::new(0x2001, 0xdb8, 0, 1, 0, 0, 0, 0),
+                },
+            ],
+        };
+        let all_nodes = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+        let packet = Icmpv6Packet::new(ROUTER, all_nodes, &message);
+        assert!(packet.validate_checksum(ROUTER, all_nodes));
+        assert_eq!(packet.data.len(), 16 + 8 + 8 + 32);
+        assert_eq!(packet.data[4..8], [64, 0x40, 0x07, 0x08]);
+        assert_eq!(packet.data[32..36], [3, 4, 64, 0xc0]);
+        assert_eq!(packet.message(), message);
+
+        let solicitation = Icmpv6Message::RouterSolicitation { options: vec![] };
+        let packet = Icmpv6Packet::new(HOST, all_nodes, &solicitation);
+        assert_eq!(packet.data.len(), 8);
+        assert_eq!(packet.message(), solicitation);
+    }
+
+    #[test]
+    fn options() {
+        // A zero length option ends them, and known types of the wrong length are kept as is.
+        assert_eq!(
+            parse_ndp_options(&[
+                1, 1, 2, 0, 0, 0, 0, 1, 5, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
+            ]),
+            vec![
+                NdpOption::SourceLinkLayerAddr(MacAddr::new([2, 0, 0, 0, 0, 1])),
+                NdpOption::Other {
+                    kind: 5,
+                    data: vec![0; 14]
+                },
+            ]
+        );
+        assert_eq!(parse_ndp_options(&[1, 0, 2, 0, 0, 0, 0, 1]), vec![]);
+        assert_eq!(parse_ndp_options(&[1, 2, 2, 0, 0, 0, 0, 1]), vec![]);
+
+        let redirected = NdpOption::RedirectedHeader(vec![0x60; 12]);
+        let bytes = redirected.to_bytes();
+        assert_eq!(bytes.len(), 24);
+        assert_eq!(bytes[..2], [4, 3]);
+        // Padding is kept as part of the header.
+        assert_eq!(
+            parse_ndp_options(&bytes),
+            vec![NdpOption::RedirectedHeader(
+                [vec![0x60; 12], vec![0; 4]].concat()
+            )]
+        );
+    }
+
+    #[test]
+    fn error_messages() {
+        let invoking = solicitation_packet();
+        let too_big = Icmpv6Message::PacketTooBig {
+            mtu: 1280,
+            invoking_packet: &invoking.data,
+        };
+        let packet = Icmpv6Packet::new(ROUTER, HOST, &too_big);
+        assert!(packet.validate_checksum(ROUTER, HOST));
+        assert_eq!(packet.rest_of_header(), [0, 0, 0x05, 0x00]);
+        assert_eq!(packet.message(), too_big);
+
+        let mut long = Ipv6Packet::empty();
+        long.set_payload(&[0; 1500]);
+        let unreachable = Icmpv6Packet::new(
+            ROUTER,
+            HOST,
+            &Icmpv6Message::DestinationUnreachable {
+                code: 3,
+                invoking_packet: &long.data,
+            },
+        );
+        assert_eq!(unreachable.data.len(), 1280 - 40);
+        assert_eq!(unreachable.code(), 3);
+
+        let mut echo = Icmpv6Packet::new(
+            HOST,
+            ROUTER,
+            &Icmpv6Message::EchoRequest {
+                identifier: 7,
+                sequence_number: 1,
+                data: b"ping",
+            },
+        );
+        assert_eq!(echo.data[4..], [0, 7, 0, 1, b'p', b'i', b'n', b'g']);
+        echo.set_icmp_type(ICMPV6_ECHO_REPLY);
+        assert!(!echo.validate_checksum(ROUTER, HOST));
+        echo.set_checksum(ROUTER, HOST);
+        assert!(echo.validate_checksum(ROUTER, HOST));
+        assert_eq!(
+            echo.message(),
+            Icmpv6Message::EchoReply {
+                identifier: 7,
+                sequence_number: 1,
+                data: b"ping",
+            }
+        );
+    }
+
+    #[test]
+    fn short_messages() {
+        // A neighbor solicitation too short for its target is kept as Other.
+        let packet = Icmpv6Packet::from_buffer(
+            vec![
+                ICMPV6_NEIGHBOR_SOLICITATION,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0xfe,
+                0x80,
+            ]
+            .into(),
+            None,
+            None,
+            0,
+        )
+        .unwrap();
+        assert_eq!(
+            packet.message(),
+            Icmpv6Message::Other {
+                icmp_type: ICMPV6_NEIGHBOR_SOLICITATION,
+                code: 0,
+                body: &[0, 0, 0, 0, 0xfe, 0x80],
+            }
+        );
+
+        let mut udp = solicitation_packet();
+        udp.set_next_header(17);
+        assert!(Icmpv6Packet::try_from(udp).is_err());
+    }
+
+    #[test]
+    fn multicast_listeners() {
+        let src = "fe80::1".parse().unwrap();
+        let all_routers = "ff02::16".parse().unwrap();
+        let group: Ipv6Addr = "ff05::1:3".parse().unwrap();
+
+        let report = Icmpv6Message::MulticastListenerReport { group };
+        let packet = Icmpv6Packet::new(src, group, &report);
+        assert_eq!(packet.data.len(), 24);
+        assert!(packet.validate_checksum(src, group));
+        assert_eq!(packet.message(), report);
+
+        let done = Icmpv6Message::MulticastListenerDone { group };
+        assert_eq!(Icmpv6Packet::new(src, all_routers, &done).message(), done);
+
+        let query = Icmpv6Message::MulticastListenerQuery {
+            max_resp_delay: 10000,
+            group: Ipv6Addr::UNSPECIFIED,
+        };
+        let packet = Icmpv6Packet::new(src, "ff02::1".parse().unwrap(), &query);
+        assert_eq!(packet.rest_of_header(), [0x27, 0x10, 0, 0]);
+        assert_eq!(packet.message(), query);
+
+        let query = Icmpv6Message::MulticastListenerQueryV2 {
+            max_resp_code: 10000,
+            group,
+            suppress_router_processing: false,
+            robustness: 2,
+            query_interval_code: 125,
+            sources: vec!["2001:db8::1".parse().unwrap()],
+        };
+        let packet = Icmpv6Packet::new(src, group, &query);
+        assert_eq!(packet.data.len(), 8 + 16 + 4 + 16);
+        assert_eq!(packet.message(), query);
+
+        let report = Icmpv6Message::MulticastListenerReportV2 {
+            records: vec![
+                GroupRecord {
+                    record_type: CHANGE_TO_EXCLUDE_MODE,
+                    group,
+                    sources: vec![],
+                    aux_data: vec![],
+                },
+                GroupRecord {
+                    record_type: BLOCK_OLD_SOURCES,
+                    group: "ff05::1:4".parse().unwrap(),
+                    sources: vec!["2001:db8::1".parse().unwrap()],
+                    aux_data: vec![],
+                },
+            ],
+        };
+        let packet = Icmpv6Packet::new(src, all_routers, &report);
+        assert_eq!(packet.rest_of_header(), [0, 0, 0, 2]);
+        assert_eq!(packet.data.len(), 8 + 20 + 36);
+        assert!(packet.validate_checksum(src, all_routers));
+        assert_eq!(packet.message(), report);
+
+        // A report counting more records than it carries is kept as Other.
+        let mut packet = packet;
+        packet.set_rest_of_header([0, 0, 0, 3]);
+        assert!(matches!(
+            packet.message(),
+            Icmpv6Message::Other {
+                icmp_type: ICMPV6_MULTICAST_LISTENER_REPORT_V2,
+                ..
+            }
+        ));
+    }
+}
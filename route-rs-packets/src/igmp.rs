@@ -0,0 +1,553 @@
+use crate::*;
+use std::borrow::Cow;
+use std::convert::{TryFrom, TryInto};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Message types, per RFC 1112, RFC 2236 and RFC 3376.
+pub const IGMP_MEMBERSHIP_QUERY: u8 = 0x11;
+pub const IGMP_V1_MEMBERSHIP_REPORT: u8 = 0x12;
+pub const IGMP_V2_MEMBERSHIP_REPORT: u8 = 0x16;
+pub const IGMP_LEAVE_GROUP: u8 = 0x17;
+pub const IGMP_V3_MEMBERSHIP_REPORT: u8 = 0x22;
+
+/// Group record types of IGMPv3 and MLDv2 reports, per RFC 3376 and RFC 3810.
+pub const MODE_IS_INCLUDE: u8 = 1;
+pub const MODE_IS_EXCLUDE: u8 = 2;
+pub const CHANGE_TO_INCLUDE_MODE: u8 = 3;
+pub const CHANGE_TO_EXCLUDE_MODE: u8 = 4;
+pub const ALLOW_NEW_SOURCES: u8 = 5;
+pub const BLOCK_OLD_SOURCES: u8 = 6;
+
+/// The Router Alert IPv4 option, which IGMP messages are sent with, per RFC 2113.
+const ROUTER_ALERT: [u8; 4] = [0x94, 0x04, 0, 0];
+
+/// The S flag of v3 queries, telling routers not to lower their timers.
+const SUPPRESS_FLAG: u8 = 0x08;
+
+/// The addresses of group records: IPv4 for IGMP, and IPv6 for MLD.
+pub(crate) trait GroupAddr: Copy {
+    const LEN: usize;
+
+    fn from_slice(bytes: &[u8]) -> Self;
+    fn to_vec(&self) -> Vec<u8>;
+}
+
+impl GroupAddr for Ipv4Addr {
+    const LEN: usize = 4;
+
+    fn from_slice(bytes: &[u8]) -> Self {
+        let addr: [u8; 4] = bytes.try_into().unwrap();
+        Ipv4Addr::from(addr)
+    }
+
+    fn to_vec(&self) -> Vec<u8> {
+        self.octets().to_vec()
+    }
+}
+
+impl GroupAddr for Ipv6Addr {
+    const LEN: usize = 16;
+
+    fn from_slice(bytes: &[u8]) -> Self {
+        let addr: [u8; 16] = bytes.try_into().unwrap();
+        Ipv6Addr::from(addr)
+    }
+
+    fn to_vec(&self) -> Vec<u8> {
+        self.octets().to_vec()
+    }
+}
+
+/// A group record of an IGMPv3 report, or an address record of an MLDv2 report: a change to,
+/// or the state of, the sources a host listens to a multicast group from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GroupRecord<A> {
+    pub record_type: u8,
+    pub group: A,
+    pub sources: Vec<A>,
+    /// Auxiliary data, a multiple of 4 bytes long.
+    pub aux_data: Vec<u8>,
+}
+
+pub type IgmpGroupRecord = GroupRecord<Ipv4Addr>;
+pub type MldAddressRecord = GroupRecord<Ipv6Addr>;
+
+impl<A> GroupRecord<A> {
+    /// Whether the record leaves the group: the host listens to it in include mode, from no
+    /// sources, so it doesn't listen at all.
+    pub fn leaves(&self) -> bool {
+        (self.record_type == MODE_IS_INCLUDE || self.record_type == CHANGE_TO_INCLUDE_MODE)
+            && self.sources.is_empty()
+    }
+}
+
+/// Parses `count` group records, or None if they run past the end of `data`.
+pub(crate) fn parse_group_records<A: GroupAddr>(
+    mut data: &[u8],
+    count: u16,
+) -> Option<Vec<GroupRecord<A>>> {
+    let mut records = vec![];
+    for _ in 0..count {
+        let header = data.get(..4 + A::LEN)?;
+        let aux_len = usize::from(header[1]) * 4;
+        let source_count = usize::from(u16::from_be_bytes([header[2], header[3]]));
+        let len = 4 + A::LEN * (1 + source_count) + aux_len;
+        let record = data.get(..len)?;
+        let sources_end = len - aux_len;
+        records.push(GroupRecord {
+            record_type: record[0],
+            group: A::from_slice(&record[4..4 + A::LEN]),
+            sources: record[4 + A::LEN..sources_end]
+                .chunks_exact(A::LEN)
+                .map(A::from_slice)
+                .collect(),
+            aux_data: record[sources_end..].to_vec(),
+        });
+        data = &data[len..];
+    }
+    Some(records)
+}
+
+/// Writes group records, padding their auxiliary data with zeros to a multiple of 4 bytes.
+pub(crate) fn write_group_records<A: GroupAddr>(records: &[GroupRecord<A>]) -> Vec<u8> {
+    let mut data = vec![];
+    for record in records {
+        let aux_len = record.aux_data.len().div_ceil(4);
+        data.push(record.record_type);
+        data.push(aux_len as u8);
+        data.extend_from_slice(&(record.sources.len() as u16).to_be_bytes());
+        data.extend(record.group.to_vec());
+        for source in &record.sources {
+            data.extend(source.to_vec());
+        }
+        data.extend_from_slice(&record.aux_data);
+        data.resize(data.len() + aux_len * 4 - record.aux_data.len(), 0);
+    }
+    data
+}
+
+/// The message an IgmpPacket carries, by its type. A query is v1 or v2 if it's 8 bytes long,
+/// and v3 if it's longer. Max response codes are in tenths of a second, and encoded as a
+/// floating point number in v3 past 127, as is the query interval code.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IgmpMessage {
+    /// A general query if the group is unspecified, and otherwise a query for the group.
+    Query {
+        max_resp_time: u8,
+        group: Ipv4Addr,
+    },
+    QueryV3 {
+        max_resp_code: u8,
+        group: Ipv4Addr,
+        suppress_router_processing: bool,
+        robustness: u8,
+        query_interval_code: u8,
+        sources: Vec<Ipv4Addr>,
+    },
+    ReportV1 {
+        group: Ipv4Addr,
+    },
+    ReportV2 {
+        group: Ipv4Addr,
+    },
+    Leave {
+        group: Ipv4Addr,
+    },
+    ReportV3 {
+        records: Vec<IgmpGroupRecord>,
+    },
+    /// Any other message, or one too short for its type, with everything after its checksum as
+    /// the body.
+    Other {
+        igmp_type: u8,
+        code: u8,
+        body: Vec<u8>,
+    },
+}
+
+/// An IGMP packet, per RFC 3376. The IGMP header is at `layer4_offset`, since it follows the IP
+/// header, and the payload follows the 8 byte header.
+#[derive(Clone, Debug)]
+pub struct IgmpPacket {
+    pub data: PacketData,
+    pub layer2_offset: Option<usize>,
+    pub layer3_offset: Option<usize>,
+    pub layer4_offset: usize,
+    pub payload_offset: usize,
+}
+
+impl IgmpPacket {
+    pub fn from_buffer(
+        data: PacketData,
+        layer2_offset: Option<usize>,
+        layer3_offset: Option<usize>,
+        layer4_offset: usize,
+    ) -> Result<IgmpPacket, &'static str> {
+        if data.len() < layer4_offset + 8 {
+            return Err("Data is too short to be an IgmpPacket");
+        }
+
+        if let Some(layer3_offset) = layer3_offset {
+            if get_ipv4_payload_type(&data, layer3_offset)? != IpProtocol::IGMP {
+                return Err("Protocol is incorrect, since it isn't IGMP");
+            }
+        }
+
+        Ok(IgmpPacket {
+            data,
+            layer2_offset,
+            layer3_offset,
+            layer4_offset,
+            payload_offset: layer4_offset + 8,
+        })
+    }
+
+    /// Makes an IgmpPacket carrying `message`, with no IP header, and sets its checksum.
+    pub fn new(message: &IgmpMessage) -> IgmpPacket {
+        let (igmp_type, code, mut body) = match message {
+            IgmpMessage::Query {
+                max_resp_time,
+                group,
+            } => (IGMP_MEMBERSHIP_QUERY, *max_resp_time, group.to_vec()),
+            IgmpMessage::QueryV3 {
+                max_resp_code,
+                group,
+                suppress_router_processing,
+                robustness,
+                query_interval_code,
+                sources,
+            } => {
+                let mut body = group.to_vec();
+                body.extend(query_v3_fields(
+                    *suppress_router_processing,
+                    *robustness,
+                    *query_interval_code,
+                    sources,
+                ));
+                (IGMP_MEMBERSHIP_QUERY, *max_resp_code, body)
+            }
+            IgmpMessage::ReportV1 { group } => (IGMP_V1_MEMBERSHIP_REPORT, 0, group.to_vec()),
+            IgmpMessage::ReportV2 { group } => (IGMP_V2_MEMBERSHIP_REPORT, 0, group.to_vec()),
+            IgmpMessage::Leave { group } => (IGMP_LEAVE_GROUP, 0, group.to_vec()),
+            IgmpMessage::ReportV3 { records } => {
+                let mut body = vec![0, 0];
+                body.extend_from_slice(&(records.len() as u16).to_be_bytes());
+                body.extend(write_group_records(records));
+                (IGMP_V3_MEMBERSHIP_REPORT, 0, body)
+            }
+            IgmpMessage::Other {
+                igmp_type,
+                code,
+                body,
+            } => (*igmp_type, *code, body.clone()),
+        };
+        // Every message has at least the 4 bytes of the header after the checksum.
+        if body.len() < 4 {
+            body.resize(4, 0);
+        }
+
+        let mut data = vec![igmp_type, code, 0, 0];
+        data.extend(body);
+        let mut packet = IgmpPacket::from_buffer(data.into(), None, None, 0).unwrap();
+        packet.set_checksum();
+        packet
+    }
+
+    pub fn igmp_type(&self) -> u8 {
+        self.data[self.layer4_offset]
+    }
+
+    /// The max response time of queries, unused by other messages.
+    pub fn code(&self) -> u8 {
+        self.data[self.layer4_offset + 1]
+    }
+
+    pub fn checksum(&self) -> u16 {
+        u16::from_be_bytes(
+            self.data[self.layer4_offset + 2..=self.layer4_offset + 3]
+                .try_into()
+                .unwrap(),
+        )
+    }
+
+    /// Calculates what the checksum should be set to, given the current header and payload.
+    pub fn calculate_checksum(&self) -> u16 {
+        internet_checksum(&[
+            &self.data[self.layer4_offset..self.layer4_offset + 2],
+            &self.data[self.layer4_offset + 4..],
+        ])
+    }
+
+    /// Sets checksum field to valid value
+    pub fn set_checksum(&mut self) {
+        let checksum = self.calculate_checksum();
+        self.data[self.layer4_offset + 2..=self.layer4_offset + 3]
+            .copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    pub fn validate_checksum(&self) -> bool {
+        self.checksum() == self.calculate_checksum()
+    }
+
+    /// The group of the message, or unspecified for v3 reports, which carry a group per record.
+    pub fn group(&self) -> Ipv4Addr {
+        match self.igmp_type() {
+            IGMP_V3_MEMBERSHIP_REPORT => Ipv4Addr::UNSPECIFIED,
+            _ => Ipv4Addr::from_slice(&self.data[self.layer4_offset + 4..self.payload_offset]),
+        }
+    }
+
+    pub fn payload(&self) -> Cow<[u8]> {
+        Cow::from(&self.data[self.payload_offset..])
+    }
+
+    /// Returns the message the packet carries, by its type.
+    pub fn message(&self) -> IgmpMessage {
+        let body = &self.data[self.layer4_offset + 4..];
+        let payload = &self.data[self.payload_offset..];
+        let code = self.code();
+        let group = Ipv4Addr::from_slice(&body[..4]);
+        let message = match self.igmp_type() {
+            IGMP_MEMBERSHIP_QUERY if payload.is_empty() => Some(IgmpMessage::Query {
+                max_resp_time: code,
+                group,
+            }),
+            IGMP_MEMBERSHIP_QUERY => parse_query_v3(payload).map(
+                |(suppress_router_processing, robustness, query_interval_code, sources)| {
+                    IgmpMessage::QueryV3 {
+                        max_resp_code: code,
+                        group,
+                        suppress_router_processing,
+                        robustness,
+                        query_interval_code,
+                        sources,
+                    }
+                },
+            ),
+            IGMP_V1_MEMBERSHIP_REPORT => Some(IgmpMessage::ReportV1 { group }),
+            IGMP_V2_MEMBERSHIP_REPORT => Some(IgmpMessage::ReportV2 { group }),
+            IGMP_LEAVE_GROUP => Some(IgmpMessage::Leave { group }),
+            IGMP_V3_MEMBERSHIP_REPORT => {
+                let count = u16::from_be_bytes([body[2], body[3]]);
+                parse_group_records(payload, count).map(|records| IgmpMessage::ReportV3 { records })
+            }
+            _ => None,
+        };
+        message.unwrap_or_else(|| IgmpMessage::Other {
+            igmp_type: self.igmp_type(),
+            code,
+            body: body.to_vec(),
+        })
+    }
+}
+
+/// Writes the fields of a v3 query after its group: the flags, robustness variable, query
+/// interval code and sources.
+pub(crate) fn query_v3_fields<A: GroupAddr>(
+    suppress_router_processing: bool,
+    robustness: u8,
+    query_interval_code: u8,
+    sources: &[A],
+) -> Vec<u8> {
+    let mut flags = robustness & 0x07;
+    if suppress_router_processing {
+        flags |= SUPPRESS_FLAG;
+    }
+    let mut fields = vec![flags, query_interval_code];
+    fields.extend_from_slice(&(sources.len() as u16).to_be_bytes());
+    for source in sources {
+        fields.extend(source.to_vec());
+    }
+    fields
+}
+
+/// Parses the fields of a v3 query after its group, or None if they're cut short.
+pub(crate) fn parse_query_v3<A: GroupAddr>(fields: &[u8]) -> Option<(bool, u8, u8, Vec<A>)> {
+    let header = fields.get(..4)?;
+    let count = usize::from(u16::from_be_bytes([header[2], header[3]]));
+    let sources = fields.get(4..4 + count * A::LEN)?;
+    Some((
+        header[0] & SUPPRESS_FLAG != 0,
+        header[0] & 0x07,
+        header[1],
+        sources.chunks_exact(A::LEN).map(A::from_slice).collect(),
+    ))
+}
+
+/// IgmpPackets are considered the same if they have the same data from the layer 4
+/// header and onward. This function does not consider the data before the start of
+/// the IGMP header.
+impl PartialEq for IgmpPacket {
+    fn eq(&self, other: &Self) -> bool {
+        self.data[self.layer4_offset..] == other.data[other.layer4_offset..]
+    }
+}
+
+impl Eq for IgmpPacket {}
+
+impl TryFrom<Ipv4Packet> for IgmpPacket {
+    type Error = &'static str;
+
+    fn try_from(packet: Ipv4Packet) -> Result<Self, Self::Error> {
+        IgmpPacket::from_buffer(
+            packet.data,
+            packet.layer2_offset,
+            Some(packet.layer3_offset),
+            packet.payload_offset,
+        )
+    }
+}
+
+impl Ipv4Packet {
+    /// Takes an IgmpPacket, and returns an Ipv4Packet with the packet as payload, a TTL of 1 and
+    /// the Router Alert option, as IGMP messages are sent. Does not set the IP checksum.
+    pub fn encap_igmp(igmp: IgmpPacket) -> Ipv4Packet {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_options(&ROUTER_ALERT);
+        packet.set_payload(&igmp.data[igmp.layer4_offset..]);
+        packet.set_protocol(0x02); //IGMP Header
+        packet.set_ttl(1);
+        packet
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GROUP: Ipv4Addr = Ipv4Addr::new(239, 1, 2, 3);
+
+    #[test]
+    fn v2_messages() {
+        // A v2 report for GROUP, as a host sends it.
+        let report = [0x16, 0x00, 0xf8, 0xfa, 239, 1, 2, 3];
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(2);
+        packet.set_payload(&report);
+        let igmp = IgmpPacket::try_from(packet).unwrap();
+        assert!(igmp.validate_checksum());
+        assert_eq!(igmp.group(), GROUP);
+        assert_eq!(igmp.message(), IgmpMessage::ReportV2 { group: GROUP });
+        assert_eq!(IgmpPacket::new(&igmp.message()).data[..], report);
+
+        let leave = IgmpPacket::new(&IgmpMessage::Leave { group: GROUP });
+        assert!(leave.validate_checksum());
+        assert_eq!(leave.igmp_type(), IGMP_LEAVE_GROUP);
+
+        let query = IgmpMessage::Query {
+            max_resp_time: 100,
+            group: Ipv4Addr::UNSPECIFIED,
+        };
+        let packet = IgmpPacket::new(&query);
+        assert_eq!(packet.data.len(), 8);
+        assert_eq!(packet.code(), 100);
+        assert_eq!(packet.message(), query);
+    }
+
+    #[test]
+    fn v3_query() {
+        let query = IgmpMessage::QueryV3 {
+            max_resp_code: 100,
+            group: GROUP,
+            suppress_router_processing: true,
+            robustness: 2,
+            query_interval_code: 125,
+            sources: vec![Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2)],
+        };
+        let packet = IgmpPacket::new(&query);
+        assert!(packet.validate_checksum());
+        assert_eq!(packet.data.len(), 12 + 8);
+        assert_eq!(packet.data[8..12], [0x0a, 125, 0, 2]);
+        assert_eq!(packet.message(), query);
+
+        // A general v3 query is 12 bytes long, so isn't mistaken for a v2 one.
+        let general = IgmpMessage::QueryV3 {
+            max_resp_code: 100,
+            group: Ipv4Addr::UNSPECIFIED,
+            suppress_router_processing: false,
+            robustness: 2,
+            query_interval_code: 125,
+            sources: vec![],
+        };
+        assert_eq!(IgmpPacket::new(&general).message(), general);
+    }
+
+    #[test]
+    fn v3_report() {
+        // A report joining 239.1.2.3 from any source, and leaving 239.4.5.6, as Linux sends it.
+        let report = [
+            0x22, 0x00, 0xf1, 0xed, 0x00, 0x00, 0x00, 0x02, 0x04, 0x00, 0x00, 0x00, 239, 1, 2, 3,
+            0x03, 0x00, 0x00, 0x00, 239, 4, 5, 6,
+        ];
+        let igmp = IgmpPacket::from_buffer(report.to_vec().into(), None, None, 0).unwrap();
+        assert!(igmp.validate_checksum());
+        assert_eq!(igmp.group(), Ipv4Addr::UNSPECIFIED);
+        let records = match igmp.message() {
+            IgmpMessage::ReportV3 { records } => records,
+            message => panic!("Expected a v3 report, got {:?}", message),
+        };
+        assert_eq!(
+            records,
+            vec![
+                GroupRecord {
+                    record_type: CHANGE_TO_EXCLUDE_MODE,
+                    group: GROUP,
+                    sources: vec![],
+                    aux_data: vec![],
+                },
+                GroupRecord {
+                    record_type: CHANGE_TO_INCLUDE_MODE,
+                    group: Ipv4Addr::new(239, 4, 5, 6),
+                    sources: vec![],
+                    aux_data: vec![],
+                },
+            ]
+        );
+        assert!(!records[0].leaves());
+        assert!(records[1].leaves());
+        assert_eq!(
+            IgmpPacket::new(&IgmpMessage::ReportV3 { records }).data[..],
+            report
+        );
+    }
+
+    #[test]
+    fn group_records() {
+        let records = vec![GroupRecord {
+            record_type: ALLOW_NEW_SOURCES,
+            group: GROUP,
+            sources: vec![Ipv4Addr::new(10, 0, 0, 1)],
+            aux_data: vec![1, 2, 3, 4, 5],
+        }];
+        let data = write_group_records(&records);
+        assert_eq!(data.len(), 8 + 4 + 8);
+        assert_eq!(data[1], 2);
+        let parsed: Vec<IgmpGroupRecord> = parse_group_records(&data, 1).unwrap();
+        assert_eq!(parsed[0].aux_data, vec![1, 2, 3, 4, 5, 0, 0, 0]);
+        assert_eq!(parsed[0].sources, records[0].sources);
+
+        // Counting a record more than there is makes the report unparsed.
+        assert_eq!(parse_group_records::<Ipv4Addr>(&data, 2), None);
+        let mut report = IgmpPacket::new(&IgmpMessage::ReportV3 { records });
+        report.data[7] = 2;
+        assert!(matches!(
+            report.message(),
+            IgmpMessage::Other {
+                igmp_type: 0x22,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn encap() {
+        let packet = Ipv4Packet::encap_igmp(IgmpPacket::new(&IgmpMessage::Leave { group: GROUP }));
+        assert_eq!(packet.protocol(), IpProtocol::IGMP);
+        assert_eq!(packet.ttl(), 1);
+        assert_eq!(packet.options().unwrap()[..], ROUTER_ALERT);
+        assert_eq!(packet.total_len(), 24 + 8);
+        let igmp = IgmpPacket::try_from(packet).unwrap();
+        assert_eq!(igmp.layer4_offset, 24);
+        assert_eq!(igmp.message(), IgmpMessage::Leave { group: GROUP });
+    }
+}
@@ -41,7 +41,13 @@ impl Ipv4Packet {
 
         // This is the header length in 32bit words
         let ihl = (data[layer3_offset] & 0x0F) as usize;
+        if ihl < 5 {
+            return Err("Packet has invalid header length");
+        }
         let payload_offset = layer3_offset + (ihl * 4);
+        if payload_offset > data.len() {
+            return Err("Packet header is longer than the packet");
+        }
 
         Ok(Ipv4Packet {
             data,
@@ -57,7 +63,7 @@ impl Ipv4Packet {
         data.resize(20, 0);
         let total_len: u16 = 20;
         data[2..=3].copy_from_slice(&total_len.to_be_bytes());
-        Ipv4Packet::from_buffer(data, None, 0).unwrap()
+        Ipv4Packet::from_buffer(data.into(), None, 0).unwrap()
     }
 
     pub fn src_addr(&self) -> Ipv4Addr {
@@ -67,7 +73,10 @@ impl Ipv4Packet {
         Ipv4Addr::from(data)
     }
 
+    /// Sets the source address, updating the header checksum, and the checksum of a TCP or UDP
+    /// segment the packet carries, incrementally.
     pub fn set_src_addr(&mut self, addr: Ipv4Addr) {
+        self.update_addr_checksums(self.src_addr(), addr);
         self.data[self.layer3_offset + 12..self.layer3_offset + 16].copy_from_slice(&addr.octets());
     }
 
@@ -78,7 +87,10 @@ impl Ipv4Packet {
         Ipv4Addr::from(data)
     }
 
+    /// Sets the destination address, updating the header checksum, and the checksum of a TCP or
+    /// UDP segment the packet carries, incrementally.
     pub fn set_dest_addr(&mut self, addr: Ipv4Addr) {
+        self.update_addr_checksums(self.dest_addr(), addr);
         self.data[self.layer3_offset + 16..self.layer3_offset + 20].copy_from_slice(&addr.octets());
     }
 
@@ -100,10 +112,16 @@ impl Ipv4Packet {
 
     pub fn set_payload(&mut self, payload: &[u8]) {
         let payload_len = payload.len();
+        let total_len = payload_len + usize::from(self.ihl() * 4);
+        assert!(
+            total_len <= usize::from(u16::MAX),
+            "Ipv4Packet total length: {}, must be <= 65535",
+            total_len
+        );
 
         self.data.truncate(self.payload_offset);
 
-        let total_len = (payload_len as u16 + u16::from(self.ihl() * 4)).to_be_bytes();
+        let total_len = (total_len as u16).to_be_bytes();
         self.data[self.layer3_offset + 2..=self.layer3_offset + 3].copy_from_slice(&total_len);
 
         self.data.reserve_exact(payload_len);
@@ -152,7 +170,13 @@ impl Ipv4Packet {
         self.data[self.layer3_offset + 8]
     }
 
+    /// Sets the TTL, updating the header checksum incrementally.
     pub fn set_ttl(&mut self, ttl: u8) {
+        // The TTL shares its 16 bit word of the header with the protocol.
+        let protocol = self.data[self.layer3_offset + 9];
+        let old = u16::from_be_bytes([self.ttl(), protocol]);
+        let new = u16::from_be_bytes([ttl, protocol]);
+        self.write_checksum(update_u16(self.checksum(), old, new));
         self.data[self.layer3_offset + 8] = ttl;
     }
 
@@ -226,7 +250,7 @@ impl Ipv4Packet {
             (true, false) => bits = 2,
             (true, true) => bits = 3,
         }
-        self.data[self.layer3_offset + 6] &= 0xE0;
+        self.data[self.layer3_offset + 6] &= 0x1F;
         self.data[self.layer3_offset + 6] |= bits << 5;
     }
 
@@ -263,9 +287,35 @@ impl Ipv4Packet {
 
     /// Sets checksum field to valid value
     pub fn set_checksum(&mut self) {
-        let new_checksum = self.caclulate_checksum();
-        self.data[self.layer3_offset + 10] = ((new_checksum & 0xFF00) >> 8) as u8;
-        self.data[self.layer3_offset + 11] = (new_checksum & 0x00FF) as u8;
+        self.write_checksum(self.caclulate_checksum());
+    }
+
+    fn write_checksum(&mut self, checksum: u16) {
+        self.data[self.layer3_offset + 10..self.layer3_offset + 12]
+            .copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    /// Updates the checksums covering an address, for it changing from `old` to `new`: the header
+    /// checksum, and that of the TCP or UDP segment in the first fragment, whose pseudo-header
+    /// includes the addresses.
+    fn update_addr_checksums(&mut self, old: Ipv4Addr, new: Ipv4Addr) {
+        self.write_checksum(update_addr(self.checksum(), old, new));
+        if self.fragment_offset() != 0 {
+            return;
+        }
+        // A header claiming to be longer than the packet leaves no segment to fix up.
+        let layer4_len = match self.data.len().checked_sub(self.payload_offset) {
+            Some(layer4_len) => layer4_len,
+            None => return,
+        };
+        let (checksum_at, udp) = match self.protocol() {
+            IpProtocol::TCP if layer4_len >= 20 => (self.payload_offset + 16, false),
+            IpProtocol::UDP if layer4_len >= 8 => (self.payload_offset + 6, true),
+            _ => return,
+        };
+        update_checksum_at(&mut self.data, checksum_at, udp, |checksum| {
+            update_addr(checksum, old, new)
+        });
     }
 
     /// Takes a UdpSegment, and returns an Ipv6Packet with the
@@ -285,6 +335,24 @@ impl Ipv4Packet {
         packet.set_protocol(0x06); //TCP Header
         packet
     }
+
+    /// Takes an IcmpPacket, and returns an Ipv4Packet with the
+    /// packet as payload.
+    pub fn encap_icmp(icmp: IcmpPacket) -> Ipv4Packet {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_payload(&icmp.data[icmp.layer4_offset..]);
+        packet.set_protocol(0x01); //ICMP Header
+        packet
+    }
+
+    /// Takes an SctpPacket, and returns an Ipv4Packet with the
+    /// packet as payload.
+    pub fn encap_sctp(sctp: SctpPacket) -> Ipv4Packet {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_payload(&sctp.data[sctp.layer4_offset..]);
+        packet.set_protocol(0x84); //SCTP Header
+        packet
+    }
 }
 
 /// Ipv4Packets are considered the same if they have the same data from the layer 4
@@ -316,6 +384,9 @@ impl TryFrom<EthernetFrame> for Ipv4Packet {
     type Error = &'static str;
 
     fn try_from(frame: EthernetFrame) -> Result<Self, Self::Error> {
+        if frame.ether_type() != IPV4_ETHER_TYPE {
+            return Err("Frame does not have IPv4 ether type");
+        }
         Ipv4Packet::from_buffer(frame.data, Some(frame.layer2_offset), frame.payload_offset)
     }
 }
@@ -344,6 +415,54 @@ impl TryFrom<UdpSegment> for Ipv4Packet {
     }
 }
 
+impl TryFrom<IcmpPacket> for Ipv4Packet {
+    type Error = &'static str;
+
+    fn try_from(packet: IcmpPacket) -> Result<Self, Self::Error> {
+        if let Some(layer3_offset) = packet.layer3_offset {
+            Ipv4Packet::from_buffer(packet.data, packet.layer2_offset, layer3_offset)
+        } else {
+            Err("ICMP Packet does not contain an IP Packet")
+        }
+    }
+}
+
+impl TryFrom<IgmpPacket> for Ipv4Packet {
+    type Error = &'static str;
+
+    fn try_from(packet: IgmpPacket) -> Result<Self, Self::Error> {
+        if let Some(layer3_offset) = packet.layer3_offset {
+            Ipv4Packet::from_buffer(packet.data, packet.layer2_offset, layer3_offset)
+        } else {
+            Err("IGMP Packet does not contain an IP Packet")
+        }
+    }
+}
+
+impl TryFrom<GrePacket> for Ipv4Packet {
+    type Error = &'static str;
+
+    fn try_from(packet: GrePacket) -> Result<Self, Self::Error> {
+        if let Some(layer3_offset) = packet.layer3_offset {
+            Ipv4Packet::from_buffer(packet.data, packet.layer2_offset, layer3_offset)
+        } else {
+            Err("GRE Packet does not contain an IP Packet")
+        }
+    }
+}
+
+impl TryFrom<SctpPacket> for Ipv4Packet {
+    type Error = &'static str;
+
+    fn try_from(packet: SctpPacket) -> Result<Self, Self::Error> {
+        if let Some(layer3_offset) = packet.layer3_offset {
+            Ipv4Packet::from_buffer(packet.data, packet.layer2_offset, layer3_offset)
+        } else {
+            Err("SCTP Packet does not contain an IP Packet")
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -351,12 +470,14 @@ mod tests {
 
     #[test]
     fn ipv4_packet() {
-        let mac_data: Vec<u8> = vec![0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0, 0];
+        let mac_data: Vec<u8> = vec![
+            0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0x08, 0x00,
+        ];
         let ip_data: Vec<u8> = vec![
             0x45, 0, 0, 20, 0, 0, 0, 0, 64, 17, 0, 0, 192, 178, 128, 0, 10, 0, 0, 1,
         ];
 
-        let mut frame = EthernetFrame::from_buffer(mac_data, 0).unwrap();
+        let mut frame = EthernetFrame::from_buffer(mac_data.into(), 0).unwrap();
         frame.set_payload(&ip_data);
 
         let packet = Ipv4Packet::try_from(frame).unwrap();
@@ -379,12 +500,14 @@ mod tests {
 
     #[test]
     fn validate_checksum() {
-        let mac_data: Vec<u8> = vec![0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0, 0];
+        let mac_data: Vec<u8> = vec![
+            0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0x08, 0x00,
+        ];
         let invalid_checksum_data: Vec<u8> = vec![
             0x45, 0x00, 0x00, 0x14, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0xb8, 0x61, 0xc0, 0xa8,
             0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7,
         ];
-        let mut frame = EthernetFrame::from_buffer(mac_data, 0).unwrap();
+        let mut frame = EthernetFrame::from_buffer(mac_data.into(), 0).unwrap();
         frame.set_payload(&invalid_checksum_data);
         let mut packet = Ipv4Packet::try_from(frame).unwrap();
         assert!(!packet.validate_checksum());
@@ -401,12 +524,14 @@ mod tests {
 
     #[test]
     fn set_checksum() {
-        let mac_data: Vec<u8> = vec![0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0, 0];
+        let mac_data: Vec<u8> = vec![
+            0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0x08, 0x00,
+        ];
         let ip_data: Vec<u8> = vec![
             0x45, 0x00, 0x00, 0x14, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0xb8, 0x61, 0xc0, 0xa8,
             0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7,
         ];
-        let mut frame = EthernetFrame::from_buffer(mac_data, 0).unwrap();
+        let mut frame = EthernetFrame::from_buffer(mac_data.into(), 0).unwrap();
         frame.set_payload(&ip_data);
         let mut packet = Ipv4Packet::try_from(frame).unwrap();
         assert!(!packet.validate_checksum());
@@ -421,12 +546,23 @@ mod tests {
             64, 17, 0, 0, 192, 178, 128, 0, 10, 0, 0, 1,
         ];
 
-        let mut packet = Ipv4Packet::from_buffer(data, Some(0), 14).unwrap();
+        let mut packet = Ipv4Packet::from_buffer(data.into(), Some(0), 14).unwrap();
         assert_eq!(packet.ihl(), 5);
         packet.set_ihl(24);
         assert_eq!(packet.ihl(), 6);
     }
 
+    #[test]
+    fn set_flags() {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_fragment_offset(100);
+        packet.set_flags(true, true);
+        assert_eq!(packet.flags(), (true, true));
+        packet.set_flags(false, false);
+        assert_eq!(packet.flags(), (false, false));
+        assert_eq!(packet.fragment_offset(), 100);
+    }
+
     #[test]
     fn empty() {
         let empty_packet = Ipv4Packet::empty();
@@ -435,6 +571,66 @@ mod tests {
         assert_eq!(empty_packet.payload_offset, 20);
     }
 
+    #[test]
+    fn incremental_checksums() {
+        let mut segment = UdpSegment::empty();
+        segment.set_payload(&[1, 2, 3]);
+        let mut packet = Ipv4Packet::encap_udp(segment);
+        packet.set_ttl(64);
+        packet.set_src_addr(Ipv4Addr::new(10, 0, 0, 1));
+        packet.set_dest_addr(Ipv4Addr::new(10, 0, 1, 1));
+        packet.set_checksum();
+        let udp_checksum = |packet: &Ipv4Packet| {
+            let src = packet.src_addr().octets();
+            let dest = packet.dest_addr().octets();
+            let len = (packet.payload().len() as u16).to_be_bytes();
+            internet_checksum(&[&src, &dest, &[0, 17], &len, &packet.payload()])
+        };
+        let checksum = udp_checksum(&packet);
+        packet.data[26..28].copy_from_slice(&checksum.to_be_bytes());
+        assert_eq!(udp_checksum(&packet), 0);
+
+        packet.set_ttl(63);
+        packet.set_src_addr(Ipv4Addr::new(203, 0, 113, 7));
+        packet.set_dest_addr(Ipv4Addr::new(198, 51, 100, 9));
+        assert!(packet.validate_checksum());
+        assert_eq!(packet.checksum(), packet.caclulate_checksum());
+        assert_eq!(udp_checksum(&packet), 0);
+
+        // The example of RFC 1624, where updating with the complement of the sum gives 0xFFFF.
+        assert_eq!(update_u16(0xDD2F, 0x5555, 0x3285), 0x0000);
+    }
+
+    #[test]
+    fn header_longer_than_packet() {
+        // An IHL of 15 claims 60 bytes of header in a 20 byte packet.
+        let mut data = vec![0x4F, 0, 0, 20, 0, 0, 0, 0, 64, 6];
+        data.resize(20, 0);
+        assert_eq!(
+            Ipv4Packet::from_buffer(data.clone().into(), None, 0).unwrap_err(),
+            "Packet header is longer than the packet"
+        );
+        let mut short_ihl = data.clone();
+        short_ihl[0] = 0x44;
+        assert_eq!(
+            Ipv4Packet::from_buffer(short_ihl.into(), None, 0).unwrap_err(),
+            "Packet has invalid header length"
+        );
+
+        // Packets built by hand can still claim it.
+        let mut packet = Ipv4Packet {
+            data: data.into(),
+            layer2_offset: None,
+            layer3_offset: 0,
+            payload_offset: 60,
+        };
+        packet.set_src_addr(Ipv4Addr::new(10, 0, 0, 1));
+        packet.set_dest_addr(Ipv4Addr::new(10, 0, 1, 1));
+        packet.set_ttl(63);
+        assert_eq!(packet.src_addr(), Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(packet.ttl(), 63);
+    }
+
     #[test]
     fn encap_udp() {
         let udp = UdpSegment::empty();
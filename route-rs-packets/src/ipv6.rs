@@ -33,8 +33,7 @@ impl Ipv6Packet {
         // Note, there is a special unhandled edge case here, if the payload len is 0, there may
         // be a hop-by-hop extension header, that means we may have a jumbo packet. Not going to
         // handle this edge case for now, but it is needed before shipping.
-        // This may also not be true if there are extension headers, so we just check that we will not
-        // overrun our array trying to access the entire payload.
+        // We just check that we will not overrun our array trying to access the entire payload.
         let payload_len = u16::from_be_bytes(
             data[layer3_offset + 4..=layer3_offset + 5]
                 .try_into()
@@ -45,23 +44,30 @@ impl Ipv6Packet {
             return Err("Packet has invalid payload len field");
         }
 
+        // The payload is the upper layer's, past any extension headers.
+        let mut headers = Ipv6ExtensionHeaders::new(&data, layer3_offset);
+        headers.by_ref().for_each(drop);
+        let payload_offset = headers.offset;
+
         Ok(Ipv6Packet {
             data,
             layer2_offset,
             layer3_offset,
-            payload_offset: packet_len - payload_len,
+            payload_offset,
         })
     }
 
+    /// Returns an empty Ipv6Packet, with no next header.
     pub fn empty() -> Ipv6Packet {
         let mut data = vec![0x60];
         data.resize(40, 0);
-        Ipv6Packet::from_buffer(data, None, 0).unwrap()
+        data[6] = 59;
+        Ipv6Packet::from_buffer(data.into(), None, 0).unwrap()
     }
 
     pub fn traffic_class(&self) -> u8 {
         ((self.data[self.layer3_offset] & 0x0F) << 4)
-            + (self.data[self.layer3_offset + 1] & 0xF0 >> 4)
+            + ((self.data[self.layer3_offset + 1] & 0xF0) >> 4)
     }
 
     pub fn set_traffic_class(&mut self, traffic_class: u8) {
@@ -119,16 +125,12 @@ impl Ipv6Packet {
         Cow::from(&self.data[self.payload_offset..])
     }
 
+    /// Replaces the upper layer's payload, keeping any extension headers.
     pub fn set_payload(&mut self, payload: &[u8]) {
-        let new_payload_len = payload.len();
         self.data.truncate(self.payload_offset);
-
-        let payload_len = (new_payload_len as u16).to_be_bytes();
-        self.data[self.layer3_offset + 4..self.layer3_offset + 6].copy_from_slice(&payload_len);
-
-        self.data.reserve_exact(new_payload_len);
+        self.data.reserve_exact(payload.len());
         self.data.extend(payload);
-        self.payload_offset = self.data.len() - payload.len();
+        self.update_payload_length();
     }
 
     pub fn src_addr(&self) -> Ipv6Addr {
@@ -145,49 +147,61 @@ impl Ipv6Packet {
         Ipv6Addr::from(data)
     }
 
+    /// Sets the source address, updating the checksum of a TCP segment, UDP segment or ICMPv6
+    /// packet the packet carries, whose pseudo-header includes it, incrementally.
     pub fn set_src_addr(&mut self, addr: Ipv6Addr) {
+        self.update_addr_checksum(self.src_addr(), addr);
         self.data[self.layer3_offset + 8..self.layer3_offset + 24].copy_from_slice(&addr.octets());
     }
 
+    /// Sets the destination address, updating the checksum of a TCP segment, UDP segment or
+    /// ICMPv6 packet the packet carries incrementally.
     pub fn set_dest_addr(&mut self, addr: Ipv6Addr) {
+        self.update_addr_checksum(self.dest_addr(), addr);
         self.data[self.layer3_offset + 24..self.layer3_offset + 40].copy_from_slice(&addr.octets());
     }
 
-    // TODO: Test the get and set for extension headers.
-    pub fn extension_headers(&self) -> Vec<Cow<[u8]>> {
-        let mut headers = Vec::<Cow<[u8]>>::new();
-        let mut next_header = self.next_header();
-        let mut header_ext_len;
-        let mut offset = self.layer3_offset + 40; // First byte of first header
-        loop {
-            match next_header {
-                IpProtocol::HOPOPT
-                | IpProtocol::IPv6_Opts
-                | IpProtocol::IPv6_route
-                | IpProtocol::IPv6_frag
-                | IpProtocol::AH
-                | IpProtocol::ESP
-                | IpProtocol::Mobility_Header
-                | IpProtocol::HIP
-                | IpProtocol::Shim6
-                | IpProtocol::Use_for_experimentation_and_testing => {
-                    header_ext_len = self.data[offset + 1];
-                    if header_ext_len == 0 {
-                        // Fragments have the minimum of 8, but it set to zero for some dumb reason
-                        // https://en.wikipedia.org/wiki/IPv6_packet#Fragment
-                        header_ext_len = 8;
-                    }
-                    headers.push(Cow::from(
-                        &self.data[offset..offset + header_ext_len as usize],
-                    ));
-                    next_header = IpProtocol::from(self.data[offset]);
-                    offset += header_ext_len as usize;
-                }
-                _ => {
-                    return headers;
-                }
-            }
+    /// Updates the checksum of the TCP segment, UDP segment or ICMPv6 packet in the first
+    /// fragment, for an address changing from `old` to `new`. Later fragments carry no header.
+    fn update_addr_checksum(&mut self, old: Ipv6Addr, new: Ipv6Addr) {
+        if self.fragment_offset() != 0 {
+            return;
         }
+        let layer4_len = self.data.len() - self.payload_offset;
+        let (checksum_at, udp) = match self.upper_layer_protocol() {
+            IpProtocol::TCP if layer4_len >= 20 => (self.payload_offset + 16, false),
+            IpProtocol::UDP if layer4_len >= 8 => (self.payload_offset + 6, true),
+            IpProtocol::IPv6_ICMP if layer4_len >= 4 => (self.payload_offset + 2, false),
+            _ => return,
+        };
+        update_checksum_at(&mut self.data, checksum_at, udp, |checksum| {
+            update_addr(checksum, old, new)
+        });
+    }
+
+    /// Returns the offset of a fragment in 8 byte units, from its Fragment header, or 0 if the
+    /// packet isn't fragmented.
+    fn fragment_offset(&self) -> u16 {
+        self.extension_headers()
+            .find(|header| header.header_type == IpProtocol::IPv6_frag)
+            .map_or(0, |header| {
+                u16::from_be_bytes([header.data[2], header.data[3]]) >> 3
+            })
+    }
+
+    /// Returns an iterator over the extension headers of the packet, in the order of the chain.
+    /// The chain ends at the first header that isn't an extension header, which is the upper
+    /// layer's, or at a header that runs past the end of the packet.
+    pub fn extension_headers(&self) -> Ipv6ExtensionHeaders<'_> {
+        Ipv6ExtensionHeaders::new(&self.data, self.layer3_offset)
+    }
+
+    /// Returns the protocol of the upper layer, whose header starts at payload_offset, following
+    /// the chain of extension headers from the fixed header's next header field.
+    pub fn upper_layer_protocol(&self) -> IpProtocol {
+        let mut headers = self.extension_headers();
+        headers.by_ref().for_each(drop);
+        IpProtocol::from(headers.next_header)
     }
 
     /// This function sets new extension headers. Because we are inserting into the middle
@@ -195,20 +209,101 @@ impl Ipv6Packet {
     /// both the vector of headers, the type of the first header, as an IpProtocol. The caller is also
     /// required to ensure that the next_header field of their last extension header is a set to the
     /// IpProtocol of the payload.
-    /// If the provided vector does not contain any headers, the header field is cleared, and the
-    /// first_header field should be the IpProtocol of the payload.
+    /// If the provided vector does not contain any headers, the first_header field should be the
+    /// IpProtocol of the payload.
     pub fn set_extension_headers(&mut self, headers: Vec<&[u8]>, first_header: IpProtocol) {
         let payload = self.data.split_off(self.payload_offset);
         self.data.truncate(self.layer3_offset + 40);
         for header in headers.iter() {
             self.data.extend(*header);
         }
+        self.payload_offset = self.data.len();
         self.data.extend(payload);
-        if !headers.is_empty() {
-            self.set_next_header(first_header as u8);
+        self.set_next_header(first_header as u8);
+        self.update_payload_length();
+    }
+
+    /// Inserts an extension header of type `header_type` at `index` in the chain, 0 being first,
+    /// filling in its next header and length fields ahead of `body`, the rest of the header.
+    /// Headers must be a multiple of 8 bytes long, or exactly 8 for fragment headers, so `body`
+    /// is 6 bytes, or 6 more than a multiple of 8.
+    pub fn insert_extension_header(
+        &mut self,
+        index: usize,
+        header_type: u8,
+        body: &[u8],
+    ) -> Result<(), &'static str> {
+        let header_len = body.len() + 2;
+        let len_field = match header_type {
+            IPV6_FRAGMENT if header_len == 8 => 0,
+            IPV6_FRAGMENT => return Err("Fragment headers must be 8 bytes"),
+            _ if extension_header_len(header_type, 0).is_none() => {
+                return Err("Header type is not an extension header")
+            }
+            _ if !header_len.is_multiple_of(8) || header_len > 2048 => {
+                return Err("Extension headers must be a multiple of 8 bytes, at most 2048")
+            }
+            AUTHENTICATION_HEADER => header_len / 4 - 2,
+            _ => header_len / 8 - 1,
+        };
+        if self.data.len() - self.layer3_offset - 40 + header_len > usize::from(u16::MAX) {
+            return Err("Packet would be too long for its payload length field");
+        }
+
+        let offsets: Vec<usize> = self.extension_headers().map(|h| h.offset).collect();
+        let offset = match offsets.get(index) {
+            Some(offset) => *offset,
+            None if index == offsets.len() => self.payload_offset,
+            None => return Err("Index is past the end of the extension headers"),
+        };
+        let next_header_field = self.next_header_field(&offsets, index);
+
+        let mut header = vec![self.data[next_header_field], len_field as u8];
+        header.extend_from_slice(body);
+        self.data[next_header_field] = header_type;
+        self.data.splice(offset..offset, header);
+        self.payload_offset += header_len;
+        self.update_payload_length();
+        Ok(())
+    }
+
+    /// Removes the extension header at `index` in the chain, linking the one before it to the
+    /// one after, and returns its type and bytes, if there is one.
+    pub fn remove_extension_header(&mut self, index: usize) -> Option<(IpProtocol, Vec<u8>)> {
+        let offsets: Vec<usize> = self.extension_headers().map(|h| h.offset).collect();
+        let offset = *offsets.get(index)?;
+        let header_len = offsets.get(index + 1).unwrap_or(&self.payload_offset) - offset;
+        let next_header_field = self.next_header_field(&offsets, index);
+
+        let header_type = IpProtocol::from(self.data[next_header_field]);
+        self.data[next_header_field] = self.data[offset];
+        let header = self.data.drain(offset..offset + header_len).collect();
+        self.payload_offset -= header_len;
+        self.update_payload_length();
+        Some((header_type, header))
+    }
+
+    /// The offset of the next header field naming the header at `index` in the chain whose
+    /// headers start at `offsets`.
+    fn next_header_field(&self, offsets: &[usize], index: usize) -> usize {
+        match index {
+            0 => self.layer3_offset + 6,
+            _ => offsets[index - 1],
         }
     }
 
+    fn update_payload_length(&mut self) {
+        let payload_len = self.data.len() - self.layer3_offset - 40;
+        assert!(
+            payload_len <= usize::from(u16::MAX),
+            "Ipv6Packet payload length: {}, must be <= 65535",
+            payload_len
+        );
+        let payload_len = payload_len as u16;
+        self.data[self.layer3_offset + 4..self.layer3_offset + 6]
+            .copy_from_slice(&payload_len.to_be_bytes());
+    }
+
     /// Takes a UdpSegment, and returns an Ipv6Packet with the
     /// segment as payload. Does not set checksums
     pub fn encap_udp(udp: UdpSegment) -> Ipv6Packet {
@@ -226,6 +321,24 @@ impl Ipv6Packet {
         packet.set_next_header(0x06); //TCP Header
         packet
     }
+
+    /// Takes an Icmpv6Packet, and returns an Ipv6Packet with the
+    /// packet as payload. Set the addresses the checksum was calculated for.
+    pub fn encap_icmpv6(icmp: Icmpv6Packet) -> Ipv6Packet {
+        let mut packet = Ipv6Packet::empty();
+        packet.set_payload(&icmp.data[icmp.layer4_offset..]);
+        packet.set_next_header(0x3A); //ICMPv6 Header
+        packet
+    }
+
+    /// Takes an SctpPacket, and returns an Ipv6Packet with the
+    /// packet as payload.
+    pub fn encap_sctp(sctp: SctpPacket) -> Ipv6Packet {
+        let mut packet = Ipv6Packet::empty();
+        packet.set_payload(&sctp.data[sctp.layer4_offset..]);
+        packet.set_next_header(0x84); //SCTP Header
+        packet
+    }
 }
 
 /// Ipv6Packets are considered the same if they have the same data from the layer 4
@@ -239,56 +352,91 @@ impl PartialEq for Ipv6Packet {
 
 impl Eq for Ipv6Packet {}
 
+const IPV6_FRAGMENT: u8 = 44;
+const AUTHENTICATION_HEADER: u8 = 51;
+
+/// The length of an extension header of type `header_type` with `len_field` in its length
+/// field, or None if the type isn't an extension header whose chain can be followed. ESP
+/// encrypts what follows it, so it ends the chain.
+fn extension_header_len(header_type: u8, len_field: u8) -> Option<usize> {
+    match header_type {
+        // Hop-by-Hop and Destination Options, Routing, Mobility, HIP, Shim6 and experimental
+        // headers count their length in 8 byte units, past the first 8.
+        0 | 43 | 60 | 135 | 139 | 140 | 253 | 254 => Some((usize::from(len_field) + 1) * 8),
+        IPV6_FRAGMENT => Some(8),
+        // AH counts 4 byte units, past the first 8.
+        AUTHENTICATION_HEADER => Some((usize::from(len_field) + 2) * 4),
+        _ => None,
+    }
+}
+
+/// An extension header of an Ipv6Packet.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Ipv6ExtensionHeader<'a> {
+    pub header_type: IpProtocol,
+    /// The whole header, from its next header field.
+    pub data: &'a [u8],
+    offset: usize,
+}
+
+/// Iterates over the chain of extension headers of an Ipv6Packet.
+pub struct Ipv6ExtensionHeaders<'a> {
+    data: &'a [u8],
+    pub(crate) next_header: u8,
+    pub(crate) offset: usize,
+}
+
+impl<'a> Ipv6ExtensionHeaders<'a> {
+    pub(crate) fn new(data: &'a [u8], layer3_offset: usize) -> Self {
+        Ipv6ExtensionHeaders {
+            data,
+            next_header: data[layer3_offset + 6],
+            offset: layer3_offset + 40,
+        }
+    }
+}
+
+impl<'a> Iterator for Ipv6ExtensionHeaders<'a> {
+    type Item = Ipv6ExtensionHeader<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let len_field = *self.data.get(self.offset + 1)?;
+        let header_len = extension_header_len(self.next_header, len_field)?;
+        let data = self.data.get(self.offset..self.offset + header_len)?;
+
+        let header = Ipv6ExtensionHeader {
+            header_type: IpProtocol::from(self.next_header),
+            data,
+            offset: self.offset,
+        };
+        self.next_header = data[0];
+        self.offset += header_len;
+        Some(header)
+    }
+}
+
 /// Returns Ipv6 payload type, reads the header information to get the type
-/// of IpProtocol payload is included. Upon error, returns IpProtocol::Reserved.
+/// of IpProtocol payload is included, past any extension headers.
 pub fn get_ipv6_payload_type(
     data: &[u8],
     layer3_offset: usize,
 ) -> Result<IpProtocol, &'static str> {
     if data.len() < layer3_offset + 40 || data[layer3_offset] & 0xF0 != 0x60 {
-        // In the case of error, we return the reserved as an error.
         return Err("Is not an Ipv6 Packet");
     }
 
-    let mut header = IpProtocol::from(data[layer3_offset + 6]);
-    let mut header_ext_len;
-    let mut offset = layer3_offset + 40; // First byte of first header
-    loop {
-        match header {
-            IpProtocol::HOPOPT
-            | IpProtocol::IPv6_Opts
-            | IpProtocol::IPv6_route
-            | IpProtocol::IPv6_frag
-            | IpProtocol::AH
-            | IpProtocol::ESP
-            | IpProtocol::Mobility_Header
-            | IpProtocol::HIP
-            | IpProtocol::Shim6
-            | IpProtocol::Use_for_experimentation_and_testing => {
-                if data.len() <= offset + 1 {
-                    // Check for length overrun
-                    return Ok(IpProtocol::Reserved);
-                }
-                header_ext_len = data[offset + 1];
-                if header_ext_len == 0 {
-                    // Fragments have the minimum of 8, but it set to zero for some dumb reason
-                    // https://en.wikipedia.org/wiki/IPv6_packet#Fragment
-                    header_ext_len = 8;
-                }
-                header = IpProtocol::from(data[offset]);
-                offset += header_ext_len as usize;
-            }
-            _ => {
-                return Ok(header);
-            }
-        }
-    }
+    let mut headers = Ipv6ExtensionHeaders::new(data, layer3_offset);
+    headers.by_ref().for_each(drop);
+    Ok(IpProtocol::from(headers.next_header))
 }
 
 impl TryFrom<EthernetFrame> for Ipv6Packet {
     type Error = &'static str;
 
     fn try_from(frame: EthernetFrame) -> Result<Self, Self::Error> {
+        if frame.ether_type() != IPV6_ETHER_TYPE {
+            return Err("Frame does not have IPv6 ether type");
+        }
         Ipv6Packet::from_buffer(frame.data, Some(frame.layer2_offset), frame.payload_offset)
     }
 }
@@ -317,6 +465,42 @@ impl TryFrom<UdpSegment> for Ipv6Packet {
     }
 }
 
+impl TryFrom<Icmpv6Packet> for Ipv6Packet {
+    type Error = &'static str;
+
+    fn try_from(packet: Icmpv6Packet) -> Result<Self, Self::Error> {
+        if let Some(layer3_offset) = packet.layer3_offset {
+            Ipv6Packet::from_buffer(packet.data, packet.layer2_offset, layer3_offset)
+        } else {
+            Err("ICMPv6 Packet does not contain an IP Packet")
+        }
+    }
+}
+
+impl TryFrom<GrePacket> for Ipv6Packet {
+    type Error = &'static str;
+
+    fn try_from(packet: GrePacket) -> Result<Self, Self::Error> {
+        if let Some(layer3_offset) = packet.layer3_offset {
+            Ipv6Packet::from_buffer(packet.data, packet.layer2_offset, layer3_offset)
+        } else {
+            Err("GRE Packet does not contain an IP Packet")
+        }
+    }
+}
+
+impl TryFrom<SctpPacket> for Ipv6Packet {
+    type Error = &'static str;
+
+    fn try_from(packet: SctpPacket) -> Result<Self, Self::Error> {
+        if let Some(layer3_offset) = packet.layer3_offset {
+            Ipv6Packet::from_buffer(packet.data, packet.layer2_offset, layer3_offset)
+        } else {
+            Err("SCTP Packet does not contain an IP Packet")
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -324,7 +508,9 @@ mod tests {
 
     #[test]
     fn ipv6_packet() {
-        let mac_data: Vec<u8> = vec![0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0, 0];
+        let mac_data: Vec<u8> = vec![
+            0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0x86, 0xDD,
+        ];
         let ip_data: Vec<u8> = vec![
             0x60, 0, 0, 0, 0, 4, 17, 64, 0xde, 0xad, 0xbe, 0xef, 0xde, 0xad, 0xbe, 0xef, 0xde,
             0xad, 0xbe, 0xef, 0xde, 0xad, 0xbe, 0xef, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13,
@@ -338,7 +524,7 @@ mod tests {
             0x0001, 0x0203, 0x0405, 0x0607, 0x0809, 0x0A0B, 0x0C0D, 0x0E0F,
         );
 
-        let mut frame = EthernetFrame::from_buffer(mac_data, 0).unwrap();
+        let mut frame = EthernetFrame::from_buffer(mac_data.into(), 0).unwrap();
         frame.set_payload(&ip_data);
 
         let packet = Ipv6Packet::try_from(frame).unwrap();
@@ -353,6 +539,16 @@ mod tests {
         assert_eq!(packet.dest_addr(), dest_addr);
     }
 
+    #[test]
+    fn set_traffic_class() {
+        let mut packet = Ipv6Packet::empty();
+        packet.set_flow_label(0xFFFFF);
+        packet.set_traffic_class(0xB9);
+        assert_eq!(packet.traffic_class(), 0xB9);
+        assert_eq!(packet.flow_label(), 0xFFFFF);
+        assert_eq!(packet.data[0] >> 4, 6);
+    }
+
     #[test]
     fn set_src_addr() {
         let data: Vec<u8> = vec![
@@ -369,7 +565,7 @@ mod tests {
             0x0001, 0x0203, 0x0405, 0x0607, 0x0809, 0x0A0B, 0x0C0D, 0x0E0F,
         );
 
-        let mut packet = Ipv6Packet::from_buffer(data, Some(0), 14).unwrap();
+        let mut packet = Ipv6Packet::from_buffer(data.into(), Some(0), 14).unwrap();
 
         assert_eq!(packet.src_addr(), src_addr);
         packet.set_src_addr(new_src_addr);
@@ -392,7 +588,7 @@ mod tests {
             0xdead, 0xbeef, 0xdead, 0xbeef, 0xdead, 0xbeef, 0xdead, 0xbeef,
         );
 
-        let mut packet = Ipv6Packet::from_buffer(data, Some(0), 14).unwrap();
+        let mut packet = Ipv6Packet::from_buffer(data.into(), Some(0), 14).unwrap();
 
         assert_eq!(packet.dest_addr(), dest_addr);
         packet.set_dest_addr(new_dest_addr);
@@ -407,7 +603,7 @@ mod tests {
             0xbe, 0xef, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 0xa, 0xb, 0xc, 0xd,
         ];
 
-        let mut packet = Ipv6Packet::from_buffer(data, Some(0), 14).unwrap();
+        let mut packet = Ipv6Packet::from_buffer(data.into(), Some(0), 14).unwrap();
 
         assert_eq!(packet.data[packet.payload_offset], 0xa);
 
@@ -452,4 +648,165 @@ mod tests {
         assert_eq!(new_segment.layer3_offset, Some(0));
         assert_eq!(new_segment.layer4_offset, 40);
     }
+
+    /// A packet from 2001:db8::1 to 2001:db8::2 carrying a UDP datagram from port 1000 to 2000
+    /// behind Hop-by-Hop Options, Routing, Fragment and Destination Options headers.
+    fn packet_with_extension_headers() -> Vec<u8> {
+        let mut data = vec![0x60, 0, 0, 0, 0, 0, 0, 64];
+        data.extend_from_slice(&Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).octets());
+        data.extend_from_slice(&Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2).octets());
+        // Hop-by-Hop, with a router alert option, then Routing.
+        data.extend_from_slice(&[43, 0, 5, 2, 0, 0, 1, 0]);
+        // Segment routing, with one segment, 24 bytes, then Fragment.
+        data.extend_from_slice(&[44, 2, 4, 0, 0, 0, 0, 0]);
+        data.extend_from_slice(&Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2).octets());
+        // The first fragment, with more to come, then Destination Options.
+        data.extend_from_slice(&[60, 0, 0, 1, 0xde, 0xad, 0xbe, 0xef]);
+        // Padding, then UDP.
+        data.extend_from_slice(&[17, 0, 1, 4, 0, 0, 0, 0]);
+        data.extend_from_slice(&[0x03, 0xe8, 0x07, 0xd0, 0, 10, 0, 0, 0xaa, 0xbb]);
+        let payload_len = (data.len() - 40) as u16;
+        data[4..6].copy_from_slice(&payload_len.to_be_bytes());
+        data[6] = 0;
+        data
+    }
+
+    #[test]
+    fn walks_extension_headers() {
+        let packet =
+            Ipv6Packet::from_buffer(packet_with_extension_headers().into(), None, 0).unwrap();
+        let headers: Vec<_> = packet
+            .extension_headers()
+            .map(|header| (header.header_type, header.data.len()))
+            .collect();
+        assert_eq!(
+            headers,
+            vec![
+                (IpProtocol::HOPOPT, 8),
+                (IpProtocol::IPv6_route, 24),
+                (IpProtocol::IPv6_frag, 8),
+                (IpProtocol::IPv6_Opts, 8),
+            ]
+        );
+        assert_eq!(packet.next_header(), IpProtocol::HOPOPT);
+        assert_eq!(packet.upper_layer_protocol(), IpProtocol::UDP);
+        assert_eq!(get_ipv6_payload_type(&packet.data, 0), Ok(IpProtocol::UDP));
+        assert_eq!(packet.payload_offset, 88);
+        assert_eq!(packet.payload().len(), 10);
+
+        let segment = UdpSegment::try_from(packet).unwrap();
+        assert_eq!(segment.src_port(), 1000);
+        assert_eq!(segment.dest_port(), 2000);
+        assert_eq!(segment.payload()[..], [0xaa, 0xbb]);
+    }
+
+    #[test]
+    fn set_addr_leaves_later_fragments() {
+        let mut first = packet_with_extension_headers();
+        first[94..96].copy_from_slice(&[0x12, 0x34]);
+        let mut later = first.clone();
+        // The fragment at offset 8, whose payload is not a UDP header.
+        later[74..76].copy_from_slice(&[0, 8 << 3 | 1]);
+
+        let mut packet = Ipv6Packet::from_buffer(later.into(), None, 0).unwrap();
+        packet.set_src_addr(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 3));
+        packet.set_dest_addr(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 4));
+        assert_eq!(packet.payload()[..], first[88..]);
+
+        // The first fragment's checksum still follows the addresses.
+        let mut packet = Ipv6Packet::from_buffer(first.clone().into(), None, 0).unwrap();
+        packet.set_src_addr(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 3));
+        assert_ne!(packet.payload()[6..8], first[94..96]);
+    }
+
+    #[test]
+    fn truncated_and_encrypted_chains_end() {
+        // A Hop-by-Hop header claiming more than the packet holds ends the chain where it starts.
+        let mut data = packet_with_extension_headers();
+        data[41] = 200;
+        let packet = Ipv6Packet::from_buffer(data.into(), None, 0).unwrap();
+        assert_eq!(packet.extension_headers().count(), 0);
+        assert_eq!(packet.upper_layer_protocol(), IpProtocol::HOPOPT);
+        assert_eq!(packet.payload_offset, 40);
+
+        // ESP encrypts what follows it, so the chain stops at it.
+        let mut data = packet_with_extension_headers();
+        data[40] = 50;
+        let packet = Ipv6Packet::from_buffer(data.into(), None, 0).unwrap();
+        assert_eq!(packet.extension_headers().count(), 1);
+        assert_eq!(packet.upper_layer_protocol(), IpProtocol::ESP);
+        assert_eq!(packet.payload_offset, 48);
+    }
+
+    #[test]
+    fn insert_and_remove_extension_headers() {
+        let mut segment = UdpSegment::empty();
+        segment.set_payload(&[1, 2, 3]);
+        let original = Ipv6Packet::encap_udp(segment);
+        let mut packet = original.clone();
+
+        packet
+            .insert_extension_header(0, IPV6_FRAGMENT, &[0, 0, 0xde, 0xad, 0xbe, 0xef])
+            .unwrap();
+        packet
+            .insert_extension_header(0, 0, &[1, 4, 0, 0, 0, 0])
+            .unwrap();
+        packet
+            .insert_extension_header(2, 60, &[1, 12, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+            .unwrap();
+        let headers: Vec<_> = packet
+            .extension_headers()
+            .map(|header| header.header_type)
+            .collect();
+        assert_eq!(
+            headers,
+            vec![
+                IpProtocol::HOPOPT,
+                IpProtocol::IPv6_frag,
+                IpProtocol::IPv6_Opts
+            ]
+        );
+        assert_eq!(packet.upper_layer_protocol(), IpProtocol::UDP);
+        assert_eq!(packet.payload_length(), 8 + 8 + 16 + 11);
+        assert_eq!(packet.payload(), original.payload());
+
+        let reparsed = Ipv6Packet::from_buffer(packet.data.clone(), None, 0).unwrap();
+        assert_eq!(reparsed.payload_offset, packet.payload_offset);
+        packet.set_payload(&[9; 11]);
+        assert_eq!(packet.payload_length(), 8 + 8 + 16 + 11);
+        packet.set_payload(original.payload().as_ref());
+
+        let (header_type, header) = packet.remove_extension_header(1).unwrap();
+        assert_eq!(header_type, IpProtocol::IPv6_frag);
+        assert_eq!(header, [60, 0, 0, 0, 0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(packet.remove_extension_header(2), None);
+        assert_eq!(packet.remove_extension_header(1).unwrap().1.len(), 16);
+        assert_eq!(packet.remove_extension_header(0).unwrap().1[..2], [17, 0]);
+        assert_eq!(packet, original);
+    }
+
+    #[test]
+    fn invalid_extension_headers() {
+        let mut packet = Ipv6Packet::empty();
+        assert!(packet.insert_extension_header(0, 17, &[0; 6]).is_err());
+        assert!(packet.insert_extension_header(0, 0, &[0; 7]).is_err());
+        assert!(packet
+            .insert_extension_header(0, IPV6_FRAGMENT, &[0; 14])
+            .is_err());
+        assert!(packet.insert_extension_header(1, 0, &[0; 6]).is_err());
+        assert_eq!(packet, Ipv6Packet::empty());
+    }
+
+    #[test]
+    fn set_extension_headers() {
+        let mut packet = Ipv6Packet::encap_udp(UdpSegment::empty());
+        let hop_by_hop = [17, 0, 1, 4, 0, 0, 0, 0];
+        packet.set_extension_headers(vec![&hop_by_hop], IpProtocol::HOPOPT);
+        assert_eq!(packet.payload_length(), 16);
+        assert_eq!(packet.upper_layer_protocol(), IpProtocol::UDP);
+        assert_eq!(packet.payload().len(), 8);
+
+        packet.set_extension_headers(vec![], IpProtocol::UDP);
+        assert_eq!(packet, Ipv6Packet::encap_udp(UdpSegment::empty()));
+    }
 }
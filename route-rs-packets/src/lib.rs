@@ -1,6 +1,14 @@
+//! Packet types, each a view of a layer of the packet in a shared `PacketData` buffer. Packets
+//! convert down the layers, from an `EthernetFrame` to an `Ipv4Packet` or `Ipv6Packet` and on to
+//! the segment or packet it carries, and back up, with `TryFrom`, keeping the buffer. Going down
+//! checks the ether type or protocol fields; going up fails if the packet was made without the
+//! lower layer.
 mod types;
 pub use self::types::*;
 
+mod buffer;
+pub use self::buffer::*;
+
 mod ethernet;
 pub use self::ethernet::*;
 
@@ -13,8 +21,53 @@ pub use self::ipv6::*;
 mod arp;
 pub use self::arp::*;
 
+mod pppoe;
+pub use self::pppoe::*;
+
 mod udp;
 pub use self::udp::*;
 
 mod tcp;
 pub use self::tcp::*;
+
+mod sctp;
+pub use self::sctp::*;
+
+mod gre;
+pub use self::gre::*;
+
+mod vxlan;
+pub use self::vxlan::*;
+
+mod geneve;
+pub use self::geneve::*;
+
+mod mpls;
+pub use self::mpls::*;
+
+mod dhcp;
+pub use self::dhcp::*;
+
+mod icmp;
+pub use self::icmp::*;
+
+mod icmpv6;
+pub use self::icmpv6::*;
+
+mod igmp;
+pub use self::igmp::*;
+
+mod dns;
+pub use self::dns::*;
+
+mod flow;
+pub use self::flow::*;
+
+mod builder;
+pub use self::builder::*;
+
+mod parse;
+pub use self::parse::*;
+
+mod dissect;
+pub use self::dissect::*;
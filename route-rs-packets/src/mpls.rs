@@ -0,0 +1,173 @@
+use crate::*;
+use std::convert::TryInto;
+
+/// An MPLS label stack entry, per RFC 3032.
+#[derive(Eq, Clone, Copy, Hash, PartialEq, Debug)]
+pub struct MplsLabel {
+    /// The label, 20 bits.
+    pub label: u32,
+    /// Traffic class, 3 bits.
+    pub tc: u8,
+    /// Whether this is the bottom of the stack.
+    pub bos: bool,
+    pub ttl: u8,
+}
+
+impl MplsLabel {
+    /// An entry for `label`, with traffic class 0, not at the bottom of the stack.
+    pub fn new(label: u32, ttl: u8) -> MplsLabel {
+        MplsLabel {
+            label,
+            tc: 0,
+            bos: false,
+            ttl,
+        }
+    }
+
+    pub fn from_bytes(bytes: [u8; 4]) -> MplsLabel {
+        let entry = u32::from_be_bytes(bytes);
+        MplsLabel {
+            label: entry >> 12,
+            tc: ((entry >> 9) & 0x07) as u8,
+            bos: entry & 0x100 != 0,
+            ttl: entry as u8,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 4] {
+        let entry = ((self.label & 0xF_FFFF) << 12)
+            | (u32::from(self.tc & 0x07) << 9)
+            | (u32::from(self.bos) << 8)
+            | u32::from(self.ttl);
+        entry.to_be_bytes()
+    }
+}
+
+/// The label stack sits at the start of the payload of frames with the MPLS EtherType, followed by
+/// the packet it carries.
+impl EthernetFrame {
+    /// Returns the label stack of the frame, top first, or an empty stack if the frame isn't MPLS.
+    /// A stack cut short by the end of the frame is returned as far as it goes.
+    pub fn mpls_labels(&self) -> Vec<MplsLabel> {
+        let mut labels = vec![];
+        if self.ether_type() != MPLS_ETHER_TYPE {
+            return labels;
+        }
+        for entry in self.data[self.payload_offset..].chunks_exact(4) {
+            let label = MplsLabel::from_bytes(entry.try_into().unwrap());
+            labels.push(label);
+            if label.bos {
+                break;
+            }
+        }
+        labels
+    }
+
+    /// Returns the top entry of the label stack, if the frame is MPLS.
+    pub fn mpls_label(&self) -> Option<MplsLabel> {
+        self.mpls_labels().first().copied()
+    }
+
+    /// Rewrites the top entry of the label stack. The bottom of stack flag is kept.
+    pub fn set_mpls_label(&mut self, label: MplsLabel) -> Result<(), &'static str> {
+        let top = self
+            .mpls_label()
+            .ok_or("Frame does not have an MPLS label")?;
+        let label = MplsLabel {
+            bos: top.bos,
+            ..label
+        };
+        self.data[self.payload_offset..self.payload_offset + 4].copy_from_slice(&label.to_bytes());
+        Ok(())
+    }
+
+    /// Pushes an entry onto the label stack, making the frame MPLS if it isn't already. The bottom
+    /// of stack flag is set to whether the frame had no labels.
+    pub fn push_mpls_label(&mut self, label: MplsLabel) {
+        let label = MplsLabel {
+            bos: self.mpls_label().is_none(),
+            ..label
+        };
+        self.data.splice(
+            self.payload_offset..self.payload_offset,
+            label.to_bytes().iter().cloned(),
+        );
+        self.set_ether_type(MPLS_ETHER_TYPE);
+    }
+
+    /// Removes and returns the top entry of the label stack, if the frame is MPLS. Since MPLS
+    /// doesn't say what it carries, popping the bottom entry sets the EtherType by the version of
+    /// the IP packet beneath; if there isn't one, the frame is left unchanged and None returned.
+    pub fn pop_mpls_label(&mut self) -> Option<MplsLabel> {
+        let label = self.mpls_label()?;
+        if label.bos {
+            let ether_type = match self.data.get(self.payload_offset + 4).map(|b| b >> 4) {
+                Some(4) => IPV4_ETHER_TYPE,
+                Some(6) => IPV6_ETHER_TYPE,
+                _ => return None,
+            };
+            self.set_ether_type(ether_type);
+        }
+        self.data
+            .drain(self.payload_offset..self.payload_offset + 4);
+        Some(label)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn label_bytes() {
+        let label = MplsLabel {
+            label: 16,
+            tc: 5,
+            bos: true,
+            ttl: 64,
+        };
+        assert_eq!(label.to_bytes(), [0x00, 0x01, 0x0B, 0x40]);
+        assert_eq!(MplsLabel::from_bytes([0x00, 0x01, 0x0B, 0x40]), label);
+    }
+
+    #[test]
+    fn push_set_pop_mpls_label() {
+        let mut frame = EthernetFrame::encap_ipv4(Ipv4Packet::empty());
+        let unlabelled = frame.clone();
+        assert_eq!(frame.mpls_labels(), vec![]);
+        assert!(frame.set_mpls_label(MplsLabel::new(100, 64)).is_err());
+
+        frame.push_mpls_label(MplsLabel::new(100, 64));
+        frame.push_mpls_label(MplsLabel::new(200, 63));
+        assert_eq!(frame.ether_type(), MPLS_ETHER_TYPE);
+        assert_eq!(
+            frame.mpls_labels(),
+            vec![
+                MplsLabel::new(200, 63),
+                MplsLabel {
+                    bos: true,
+                    ..MplsLabel::new(100, 64)
+                }
+            ]
+        );
+
+        frame.set_mpls_label(MplsLabel::new(300, 62)).unwrap();
+        assert_eq!(frame.mpls_label(), Some(MplsLabel::new(300, 62)));
+
+        assert_eq!(frame.pop_mpls_label(), Some(MplsLabel::new(300, 62)));
+        assert_eq!(frame.pop_mpls_label().map(|label| label.label), Some(100));
+        assert_eq!(frame.pop_mpls_label(), None);
+        assert_eq!(frame, unlabelled);
+        assert!(Ipv4Packet::try_from(frame).is_ok());
+    }
+
+    #[test]
+    fn pop_unknown_payload() {
+        let mut frame = EthernetFrame::empty();
+        frame.push_mpls_label(MplsLabel::new(100, 64));
+        let labelled = frame.clone();
+        assert_eq!(frame.pop_mpls_label(), None);
+        assert_eq!(frame, labelled);
+    }
+}
@@ -0,0 +1,516 @@
+use crate::*;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::net::IpAddr;
+
+/// The shortest Ethernet frame, without its frame check sequence, and without any VLAN tags.
+/// Shorter frames are padded to it, so the IP packet in a frame this short may end before it.
+const MIN_FRAME_LEN: usize = 60;
+
+/// A header of a packet, where parsing it failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Header {
+    Ethernet,
+    Arp,
+    Ipv4,
+    Ipv6,
+    Tcp,
+    Udp,
+}
+
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Header::Ethernet => "Ethernet",
+            Header::Arp => "ARP",
+            Header::Ipv4 => "IPv4",
+            Header::Ipv6 => "IPv6",
+            Header::Tcp => "TCP",
+            Header::Udp => "UDP",
+        };
+        f.write_str(name)
+    }
+}
+
+/// How closely `parse` checks packets against the standards.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ParseMode {
+    /// Checks the checksums of IP headers and of TCP and UDP segments, and rejects data past the
+    /// length the IP header gives, other than the padding of minimum length Ethernet frames.
+    Strict,
+    /// Skips checksums, and drops any data past the length the IP header gives, as most routers
+    /// do.
+    Lenient,
+}
+
+/// Why data off the wire isn't a well formed packet. Unlike the `&'static str` errors of the
+/// `from_buffer` constructors and `TryFrom` conversions, the kinds of error can be told apart, so
+/// malformed packets can be counted by cause, and the header each error is in can be had with
+/// `header`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PacketParseError {
+    /// The data ends before the header does, or before the length the header gives.
+    Truncated(Header),
+    /// The version field isn't the header's.
+    BadVersion(Header),
+    /// A length field is too small for the header, or, in strict mode, the data runs past it.
+    BadLength(Header),
+    /// In strict mode, the checksum doesn't match the data.
+    BadChecksum(Header),
+    /// The layer below carries another protocol, by its ether type or protocol field.
+    WrongType(Header),
+    /// Anything else the header's constructor rejects.
+    Malformed(Header, &'static str),
+}
+
+impl PacketParseError {
+    pub fn header(&self) -> Header {
+        match *self {
+            PacketParseError::Truncated(header)
+            | PacketParseError::BadVersion(header)
+            | PacketParseError::BadLength(header)
+            | PacketParseError::BadChecksum(header)
+            | PacketParseError::WrongType(header)
+            | PacketParseError::Malformed(header, _) => header,
+        }
+    }
+}
+
+impl fmt::Display for PacketParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PacketParseError::Truncated(header) => write!(f, "{} header is truncated", header),
+            PacketParseError::BadVersion(header) => write!(f, "{} header has bad version", header),
+            PacketParseError::BadLength(header) => write!(f, "{} header has bad length", header),
+            PacketParseError::BadChecksum(header) => {
+                write!(f, "{} header has bad checksum", header)
+            }
+            PacketParseError::WrongType(header) => write!(f, "Packet does not carry {}", header),
+            PacketParseError::Malformed(header, reason) => {
+                write!(f, "{} header is malformed: {}", header, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PacketParseError {}
+
+impl EthernetFrame {
+    /// Parses a frame received off the wire, without its frame check sequence.
+    pub fn parse(data: PacketData) -> Result<EthernetFrame, PacketParseError> {
+        EthernetFrame::from_buffer(data, 0)
+            .map_err(|_| PacketParseError::Truncated(Header::Ethernet))
+    }
+}
+
+impl ArpFrame {
+    /// Parses the ARP frame `frame` carries.
+    pub fn parse(frame: EthernetFrame) -> Result<ArpFrame, PacketParseError> {
+        if frame.ether_type() != ARP_ETHER_TYPE {
+            return Err(PacketParseError::WrongType(Header::Arp));
+        }
+        ArpFrame::try_from(frame).map_err(|_| PacketParseError::Truncated(Header::Arp))
+    }
+}
+
+/// Checks the length of the IP packet at the start of `frame`'s payload, `ip_len` bytes long
+/// by its header, and drops any padding after it the mode allows.
+fn trim_ip_packet(
+    frame: &mut EthernetFrame,
+    ip_len: usize,
+    mode: ParseMode,
+    header: Header,
+) -> Result<(), PacketParseError> {
+    let ip_end = frame.payload_offset + ip_len;
+    if frame.data.len() < ip_end {
+        return Err(PacketParseError::Truncated(header));
+    }
+    let tags_len = frame.payload_offset - frame.layer2_offset - 14;
+    let frame_len = frame.data.len() - frame.layer2_offset;
+    if frame.data.len() > ip_end {
+        if mode == ParseMode::Strict && frame_len > MIN_FRAME_LEN + tags_len {
+            return Err(PacketParseError::BadLength(header));
+        }
+        frame.data.truncate(ip_end);
+    }
+    Ok(())
+}
+
+impl Ipv4Packet {
+    /// Parses the IPv4 packet `frame` carries, dropping the padding of a short frame after it.
+    pub fn parse(
+        mut frame: EthernetFrame,
+        mode: ParseMode,
+    ) -> Result<Ipv4Packet, PacketParseError> {
+        if frame.ether_type() != IPV4_ETHER_TYPE {
+            return Err(PacketParseError::WrongType(Header::Ipv4));
+        }
+        let header = &frame.data[frame.payload_offset..];
+        if header.len() < 20 {
+            return Err(PacketParseError::Truncated(Header::Ipv4));
+        }
+        if header[0] >> 4 != 4 {
+            return Err(PacketParseError::BadVersion(Header::Ipv4));
+        }
+        let header_len = usize::from(header[0] & 0x0F) * 4;
+        let total_len = usize::from(u16::from_be_bytes(header[2..4].try_into().unwrap()));
+        if header_len < 20 || total_len < header_len {
+            return Err(PacketParseError::BadLength(Header::Ipv4));
+        }
+        trim_ip_packet(&mut frame, total_len, mode, Header::Ipv4)?;
+
+        let packet = Ipv4Packet::try_from(frame)
+            .map_err(|err| PacketParseError::Malformed(Header::Ipv4, err))?;
+        if mode == ParseMode::Strict
+            && internet_checksum(&[&packet.data[packet.layer3_offset..packet.payload_offset]]) != 0
+        {
+            return Err(PacketParseError::BadChecksum(Header::Ipv4));
+        }
+        Ok(packet)
+    }
+}
+
+impl Ipv6Packet {
+    /// Parses the IPv6 packet `frame` carries, dropping the padding of a short frame after it.
+    pub fn parse(
+        mut frame: EthernetFrame,
+        mode: ParseMode,
+    ) -> Result<Ipv6Packet, PacketParseError> {
+        if frame.ether_type() != IPV6_ETHER_TYPE {
+            return Err(PacketParseError::WrongType(Header::Ipv6));
+        }
+        let header = &frame.data[frame.payload_offset..];
+        if header.len() < 40 {
+            return Err(PacketParseError::Truncated(Header::Ipv6));
+        }
+        if header[0] >> 4 != 6 {
+            return Err(PacketParseError::BadVersion(Header::Ipv6));
+        }
+        let payload_len = usize::from(u16::from_be_bytes(header[4..6].try_into().unwrap()));
+        trim_ip_packet(&mut frame, 40 + payload_len, mode, Header::Ipv6)?;
+
+        Ipv6Packet::try_from(frame).map_err(|err| PacketParseError::Malformed(Header::Ipv6, err))
+    }
+}
+
+/// The IP packet of either version carrying a segment being parsed.
+enum IpPacket {
+    V4(Ipv4Packet),
+    V6(Ipv6Packet),
+}
+
+impl IpPacket {
+    fn parse(
+        frame: EthernetFrame,
+        mode: ParseMode,
+        header: Header,
+    ) -> Result<Self, PacketParseError> {
+        match frame.ether_type() {
+            IPV4_ETHER_TYPE => Ok(IpPacket::V4(Ipv4Packet::parse(frame, mode)?)),
+            IPV6_ETHER_TYPE => Ok(IpPacket::V6(Ipv6Packet::parse(frame, mode)?)),
+            _ => Err(PacketParseError::WrongType(header)),
+        }
+    }
+
+    /// Returns the segment the packet carries, if it's of `protocol`, checking that it starts in
+    /// this packet, rather than an earlier fragment.
+    fn segment(&self, protocol: IpProtocol, header: Header) -> Result<&[u8], PacketParseError> {
+        let (upper_layer_protocol, data, payload_offset) = match self {
+            IpPacket::V4(packet) => (packet.protocol(), &packet.data, packet.payload_offset),
+            IpPacket::V6(packet) => (
+                packet.upper_layer_protocol(),
+                &packet.data,
+                packet.payload_offset,
+            ),
+        };
+        if upper_layer_protocol != protocol {
+            return Err(PacketParseError::WrongType(header));
+        }
+        if self.fragment().0 != 0 {
+            return Err(PacketParseError::Malformed(
+                header,
+                "Packet is not the first fragment",
+            ));
+        }
+        Ok(&data[payload_offset..])
+    }
+
+    /// Returns the fragment offset of the packet, and whether more fragments follow it, from the
+    /// IPv4 header, or from the Fragment header in the chain of IPv6 extension headers.
+    fn fragment(&self) -> (u16, bool) {
+        match self {
+            IpPacket::V4(packet) => (packet.fragment_offset(), packet.flags().1),
+            IpPacket::V6(packet) => packet
+                .extension_headers()
+                .find(|header| header.header_type == IpProtocol::IPv6_frag)
+                .map_or((0, false), |header| {
+                    let field = u16::from_be_bytes([header.data[2], header.data[3]]);
+                    (field >> 3, field & 1 != 0)
+                }),
+        }
+    }
+
+    /// Checks the checksum of `segment`, which covers the pseudo-header. The checksum of a first
+    /// fragment covers the rest of the segment too, so isn't checked.
+    fn checksum_ok(&self, segment: &[u8], protocol: u8) -> bool {
+        if self.fragment().1 {
+            return true;
+        }
+        let (src, dest) = match self {
+            IpPacket::V4(packet) => (
+                IpAddr::V4(packet.src_addr()),
+                IpAddr::V4(packet.dest_addr()),
+            ),
+            IpPacket::V6(packet) => (
+                IpAddr::V6(packet.src_addr()),
+                IpAddr::V6(packet.dest_addr()),
+            ),
+        };
+        pseudo_header_checksum(src, dest, protocol, segment) == 0
+    }
+}
+
+impl UdpSegment {
+    /// Parses the UDP segment the IPv4 or IPv6 packet in `frame` carries. A UDP checksum of zero
+    /// means the sender didn't compute one, which strict mode allows only over IPv4.
+    pub fn parse(frame: EthernetFrame, mode: ParseMode) -> Result<UdpSegment, PacketParseError> {
+        let packet = IpPacket::parse(frame, mode, Header::Udp)?;
+        let segment = packet.segment(IpProtocol::UDP, Header::Udp)?;
+        if segment.len() < 8 {
+            return Err(PacketParseError::Truncated(Header::Udp));
+        }
+        let length = usize::from(u16::from_be_bytes(segment[4..6].try_into().unwrap()));
+        if length < 8 {
+            return Err(PacketParseError::BadLength(Header::Udp));
+        }
+        if segment.len() < length {
+            return Err(PacketParseError::Truncated(Header::Udp));
+        }
+
+        if mode == ParseMode::Strict {
+            if segment.len() > length {
+                return Err(PacketParseError::BadLength(Header::Udp));
+            }
+            let checksum = u16::from_be_bytes(segment[6..8].try_into().unwrap());
+            let checksum_ok = match (checksum, &packet) {
+                (0, IpPacket::V4(_)) => true,
+                (0, IpPacket::V6(_)) => false,
+                _ => packet.checksum_ok(segment, 17),
+            };
+            if !checksum_ok {
+                return Err(PacketParseError::BadChecksum(Header::Udp));
+            }
+        }
+
+        let segment = match packet {
+            IpPacket::V4(packet) => UdpSegment::try_from(packet),
+            IpPacket::V6(packet) => UdpSegment::try_from(packet),
+        };
+        segment.map_err(|err| PacketParseError::Malformed(Header::Udp, err))
+    }
+}
+
+impl TcpSegment {
+    /// Parses the TCP segment the IPv4 or IPv6 packet in `frame` carries.
+    pub fn parse(frame: EthernetFrame, mode: ParseMode) -> Result<TcpSegment, PacketParseError> {
+        let packet = IpPacket::parse(frame, mode, Header::Tcp)?;
+        let segment = packet.segment(IpProtocol::TCP, Header::Tcp)?;
+        if segment.len() < 20 {
+            return Err(PacketParseError::Truncated(Header::Tcp));
+        }
+        let header_len = usize::from(segment[12] >> 4) * 4;
+        if header_len < 20 {
+            return Err(PacketParseError::BadLength(Header::Tcp));
+        }
+        if segment.len() < header_len {
+            return Err(PacketParseError::Truncated(Header::Tcp));
+        }
+        if mode == ParseMode::Strict && !packet.checksum_ok(segment, 6) {
+            return Err(PacketParseError::BadChecksum(Header::Tcp));
+        }
+
+        let segment = match packet {
+            IpPacket::V4(packet) => TcpSegment::try_from(packet),
+            IpPacket::V6(packet) => TcpSegment::try_from(packet),
+        };
+        segment.map_err(|err| PacketParseError::Malformed(Header::Tcp, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn udp_frame() -> EthernetFrame {
+        EthernetFrame::builder()
+            .ipv4(|ip| {
+                ip.src(Ipv4Addr::new(10, 0, 0, 1))
+                    .dst(Ipv4Addr::new(10, 0, 0, 2))
+                    .udp(|udp| udp.sport(1000).dport(2000).payload(&[1, 2, 3, 4]))
+            })
+            .build()
+    }
+
+    fn reparse(frame: &EthernetFrame) -> EthernetFrame {
+        EthernetFrame::parse(frame.data.clone()).unwrap()
+    }
+
+    #[test]
+    fn well_formed() {
+        let frame = udp_frame();
+        for mode in [ParseMode::Strict, ParseMode::Lenient].iter() {
+            let segment = UdpSegment::parse(reparse(&frame), *mode).unwrap();
+            assert_eq!(segment.payload(), &[1, 2, 3, 4][..]);
+            assert_eq!(
+                TcpSegment::parse(reparse(&frame), *mode).unwrap_err(),
+                PacketParseError::WrongType(Header::Tcp)
+            );
+        }
+
+        let frame = EthernetFrame::builder()
+            .ipv6(|ip| {
+                ip.src(Ipv6Addr::LOCALHOST)
+                    .dst(Ipv6Addr::LOCALHOST)
+                    .tcp(|tcp| tcp.sport(1000).dport(80).flags(0x02))
+            })
+            .build();
+        let segment = TcpSegment::parse(frame, ParseMode::Strict).unwrap();
+        assert_eq!(segment.dest_port(), 80);
+    }
+
+    #[test]
+    fn truncated_and_bad_headers() {
+        assert_eq!(
+            EthernetFrame::parse(vec![0; 13].into()).unwrap_err(),
+            PacketParseError::Truncated(Header::Ethernet)
+        );
+
+        let frame = udp_frame();
+        let mut short = reparse(&frame);
+        short.data.truncate(short.data.len() - 1);
+        let err = UdpSegment::parse(short, ParseMode::Lenient).unwrap_err();
+        assert_eq!(err, PacketParseError::Truncated(Header::Ipv4));
+        assert_eq!(err.header(), Header::Ipv4);
+
+        let mut bad_version = reparse(&frame);
+        bad_version.data[14] = 0x65;
+        assert_eq!(
+            Ipv4Packet::parse(bad_version, ParseMode::Lenient).unwrap_err(),
+            PacketParseError::BadVersion(Header::Ipv4)
+        );
+
+        let mut bad_udp_length = reparse(&frame);
+        bad_udp_length.data[14 + 20 + 5] = 4;
+        assert_eq!(
+            UdpSegment::parse(bad_udp_length, ParseMode::Lenient).unwrap_err(),
+            PacketParseError::BadLength(Header::Udp)
+        );
+
+        // The IP header claims a UDP segment too short to hold the UDP header.
+        let mut frame = reparse(&frame);
+        frame.data.truncate(14 + 24);
+        frame.data[14 + 3] = 24;
+        assert_eq!(
+            UdpSegment::parse(frame, ParseMode::Lenient).unwrap_err(),
+            PacketParseError::Truncated(Header::Udp)
+        );
+    }
+
+    #[test]
+    fn strict_and_lenient() {
+        let frame = udp_frame();
+
+        let mut bad_checksum = reparse(&frame);
+        bad_checksum.data[14 + 20 + 8] ^= 0xFF;
+        assert_eq!(
+            UdpSegment::parse(bad_checksum.clone(), ParseMode::Strict).unwrap_err(),
+            PacketParseError::BadChecksum(Header::Udp)
+        );
+        assert!(UdpSegment::parse(bad_checksum, ParseMode::Lenient).is_ok());
+
+        // Padding up to the minimum frame length is dropped in either mode.
+        let mut padded = reparse(&frame);
+        padded.data.resize(MIN_FRAME_LEN, 0);
+        let packet = Ipv4Packet::parse(padded, ParseMode::Strict).unwrap();
+        assert_eq!(packet.data.len(), 14 + 32);
+
+        let mut trailing = reparse(&frame);
+        trailing.data.resize(MIN_FRAME_LEN + 1, 0);
+        assert_eq!(
+            Ipv4Packet::parse(trailing.clone(), ParseMode::Strict).unwrap_err(),
+            PacketParseError::BadLength(Header::Ipv4)
+        );
+        let packet = Ipv4Packet::parse(trailing, ParseMode::Lenient).unwrap();
+        assert_eq!(packet.data.len(), 14 + 32);
+    }
+
+    #[test]
+    fn ipv6_fragments() {
+        let frame = EthernetFrame::builder()
+            .ipv6(|ip| {
+                ip.src(Ipv6Addr::LOCALHOST)
+                    .dst(Ipv6Addr::LOCALHOST)
+                    .udp(|udp| udp.sport(1000).dport(2000).payload(&[1, 2, 3, 4]))
+            })
+            .build();
+        let fragment = |offset_and_flags: u16| {
+            let mut packet = Ipv6Packet::try_from(reparse(&frame)).unwrap();
+            let [high, low] = offset_and_flags.to_be_bytes();
+            packet
+                .insert_extension_header(0, 44, &[high, low, 0, 0, 0, 1])
+                .unwrap();
+            EthernetFrame::parse(packet.data).unwrap()
+        };
+
+        // The checksum of a first fragment covers the segment's other fragments too.
+        let mut first = fragment(1);
+        let last = first.data.len() - 1;
+        first.data[last] ^= 0xFF;
+        assert!(UdpSegment::parse(first, ParseMode::Strict).is_ok());
+
+        let mut whole = fragment(0);
+        whole.data[last] ^= 0xFF;
+        assert_eq!(
+            UdpSegment::parse(whole, ParseMode::Strict).unwrap_err(),
+            PacketParseError::BadChecksum(Header::Udp)
+        );
+
+        assert_eq!(
+            UdpSegment::parse(fragment(1 << 3), ParseMode::Lenient).unwrap_err(),
+            PacketParseError::Malformed(Header::Udp, "Packet is not the first fragment")
+        );
+    }
+
+    #[test]
+    fn jumbo_frames() {
+        // A tagged jumbo frame, as long as they come.
+        let payload: Vec<u8> = (0..MAX_JUMBO_FRAME_LEN - 18 - 20 - 8)
+            .map(|i| i as u8)
+            .collect();
+        let frame = EthernetFrame::builder()
+            .vlan(VlanTag::new(10))
+            .ipv4(|ip| {
+                ip.src(Ipv4Addr::new(10, 0, 0, 1))
+                    .dst(Ipv4Addr::new(10, 0, 0, 2))
+                    .udp(|udp| udp.sport(1000).dport(2000).payload(&payload))
+            })
+            .build();
+        assert_eq!(frame.data.len(), MAX_JUMBO_FRAME_LEN);
+        let segment = UdpSegment::parse(reparse(&frame), ParseMode::Strict).unwrap();
+        assert_eq!(usize::from(segment.length()), payload.len() + 8);
+        assert_eq!(segment.payload(), &payload[..]);
+
+        let frame = EthernetFrame::builder()
+            .ipv6(|ip| {
+                ip.src(Ipv6Addr::LOCALHOST)
+                    .dst(Ipv6Addr::LOCALHOST)
+                    .tcp(|tcp| tcp.sport(1000).dport(80).payload(&payload))
+            })
+            .build();
+        let packet = Ipv6Packet::parse(reparse(&frame), ParseMode::Strict).unwrap();
+        assert_eq!(usize::from(packet.payload_length()), payload.len() + 20);
+        let segment = TcpSegment::parse(frame, ParseMode::Strict).unwrap();
+        assert_eq!(segment.payload(), &payload[..]);
+    }
+}
@@ -0,0 +1,490 @@
+use crate::*;
+use std::convert::{TryFrom, TryInto};
+
+/// Tag types of discovery frames, per RFC 2516.
+pub const PPPOE_TAG_END_OF_LIST: u16 = 0x0000;
+pub const PPPOE_TAG_SERVICE_NAME: u16 = 0x0101;
+pub const PPPOE_TAG_AC_NAME: u16 = 0x0102;
+pub const PPPOE_TAG_HOST_UNIQ: u16 = 0x0103;
+pub const PPPOE_TAG_AC_COOKIE: u16 = 0x0104;
+pub const PPPOE_TAG_VENDOR_SPECIFIC: u16 = 0x0105;
+pub const PPPOE_TAG_RELAY_SESSION_ID: u16 = 0x0110;
+pub const PPPOE_TAG_SERVICE_NAME_ERROR: u16 = 0x0201;
+pub const PPPOE_TAG_AC_SYSTEM_ERROR: u16 = 0x0202;
+pub const PPPOE_TAG_GENERIC_ERROR: u16 = 0x0203;
+
+/// PPP protocol numbers, per RFC 1661, RFC 1332 and RFC 5072.
+pub const PPP_IPV4: u16 = 0x0021;
+pub const PPP_IPV6: u16 = 0x0057;
+pub const PPP_IPCP: u16 = 0x8021;
+pub const PPP_IPV6CP: u16 = 0x8057;
+pub const PPP_LCP: u16 = 0xC021;
+pub const PPP_PAP: u16 = 0xC023;
+pub const PPP_CHAP: u16 = 0xC223;
+
+/// The version and type of PPPoE, both 1, in the first byte of the header.
+const VERSION_TYPE: u8 = 0x11;
+
+const HEADER_LEN: usize = 6;
+
+/// The code of a PPPoE frame: one of the discovery stage's, or Session for frames carrying PPP.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PppoeCode {
+    Session = 0x00,
+    /// Active Discovery Offer, from an access concentrator.
+    Pado = 0x07,
+    /// Active Discovery Initiation, broadcast by a host.
+    Padi = 0x09,
+    /// Active Discovery Request, from a host to the access concentrator it chose.
+    Padr = 0x19,
+    /// Active Discovery Session-confirmation, carrying the session ID.
+    Pads = 0x65,
+    /// Active Discovery Terminate, from either end.
+    Padt = 0xA7,
+}
+
+impl PppoeCode {
+    pub fn from_u8(code: u8) -> Option<PppoeCode> {
+        match code {
+            0x00 => Some(PppoeCode::Session),
+            0x07 => Some(PppoeCode::Pado),
+            0x09 => Some(PppoeCode::Padi),
+            0x19 => Some(PppoeCode::Padr),
+            0x65 => Some(PppoeCode::Pads),
+            0xA7 => Some(PppoeCode::Padt),
+            _ => None,
+        }
+    }
+
+    /// The EtherType of frames with the code: the session EtherType for Session, and otherwise
+    /// the discovery one.
+    pub fn ether_type(self) -> u16 {
+        match self {
+            PppoeCode::Session => PPPOE_SESSION_ETHER_TYPE,
+            _ => PPPOE_DISCOVERY_ETHER_TYPE,
+        }
+    }
+}
+
+/// A tag of a discovery frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PppoeTag {
+    pub tag_type: u16,
+    pub value: Vec<u8>,
+}
+
+impl PppoeTag {
+    pub fn new(tag_type: u16, value: &[u8]) -> PppoeTag {
+        PppoeTag {
+            tag_type,
+            value: value.to_vec(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.tag_type.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&(self.value.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.value);
+        bytes
+    }
+}
+
+/// A packet of a PPP control protocol, such as LCP or IPCP, per RFC 1661: a code, such as
+/// Configure-Request, an identifier matching replies to requests, and the data, which for the
+/// Configure codes is a list of options.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PppControl {
+    pub code: u8,
+    pub identifier: u8,
+    pub data: Vec<u8>,
+}
+
+impl PppControl {
+    /// Configure-Request, -Ack, -Nak and -Reject, whose data is options.
+    pub const CONFIGURE_REQUEST: u8 = 1;
+    pub const CONFIGURE_ACK: u8 = 2;
+    pub const CONFIGURE_NAK: u8 = 3;
+    pub const CONFIGURE_REJECT: u8 = 4;
+    pub const TERMINATE_REQUEST: u8 = 5;
+    pub const TERMINATE_ACK: u8 = 6;
+    pub const CODE_REJECT: u8 = 7;
+    pub const PROTOCOL_REJECT: u8 = 8;
+    pub const ECHO_REQUEST: u8 = 9;
+    pub const ECHO_REPLY: u8 = 10;
+
+    /// Parses a control packet, ignoring any padding after its length. Returns None if it's cut
+    /// short.
+    pub fn from_bytes(bytes: &[u8]) -> Option<PppControl> {
+        let header = bytes.get(..4)?;
+        let len = usize::from(u16::from_be_bytes([header[2], header[3]]));
+        if len < 4 {
+            return None;
+        }
+        Some(PppControl {
+            code: header[0],
+            identifier: header[1],
+            data: bytes.get(4..len)?.to_vec(),
+        })
+    }
+
+    /// Makes a Configure packet, of `code`, with `options` as its data.
+    pub fn configure(code: u8, identifier: u8, options: &[(u8, Vec<u8>)]) -> PppControl {
+        let mut data = vec![];
+        for (kind, value) in options {
+            data.push(*kind);
+            data.push((value.len() + 2) as u8);
+            data.extend_from_slice(value);
+        }
+        PppControl {
+            code,
+            identifier,
+            data,
+        }
+    }
+
+    /// The options of a Configure packet, as their types and values, in order. Options running
+    /// past the end of the data are left out.
+    pub fn options(&self) -> Vec<(u8, Vec<u8>)> {
+        let mut options = vec![];
+        let mut rest = &self.data[..];
+        while rest.len() >= 2 {
+            let len = usize::from(rest[1]);
+            if len < 2 || rest.len() < len {
+                break;
+            }
+            options.push((rest[0], rest[2..len].to_vec()));
+            rest = &rest[len..];
+        }
+        options
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.code, self.identifier];
+        bytes.extend_from_slice(&((self.data.len() + 4) as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+}
+
+///
+/// EthernetFrame wrapper with getters/setters for PPPoE, per RFC 2516: discovery frames, with the
+/// 0x8863 EtherType and tags as payload, and session frames, with the 0x8864 EtherType and a PPP
+/// frame as payload. The payload is as long as the length field says; the rest of the frame is
+/// padding.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PppoeFrame {
+    frame: EthernetFrame,
+}
+
+impl PppoeFrame {
+    /// Makes a PppoeFrame of `code`, in session `session_id`, with no payload, in a frame with the
+    /// EtherType of the code. Discovery frames before PADS have session 0.
+    pub fn new(code: PppoeCode, session_id: u16) -> PppoeFrame {
+        let mut frame = EthernetFrame::empty();
+        frame.set_ether_type(code.ether_type());
+        let mut header = vec![VERSION_TYPE, code as u8];
+        header.extend_from_slice(&session_id.to_be_bytes());
+        header.extend_from_slice(&[0, 0]);
+        frame.set_payload(&header);
+        PppoeFrame { frame }
+    }
+
+    /// Makes a discovery frame of `code` carrying `tags`.
+    pub fn discovery(code: PppoeCode, session_id: u16, tags: &[PppoeTag]) -> PppoeFrame {
+        let mut frame = PppoeFrame::new(code, session_id);
+        frame.set_tags(tags);
+        frame
+    }
+
+    /// Makes a session frame carrying a PPP frame of `protocol`.
+    pub fn session(session_id: u16, protocol: u16, payload: &[u8]) -> PppoeFrame {
+        let mut frame = PppoeFrame::new(PppoeCode::Session, session_id);
+        let mut ppp = protocol.to_be_bytes().to_vec();
+        ppp.extend_from_slice(payload);
+        frame.set_payload(&ppp);
+        frame
+    }
+
+    /// Makes a session frame carrying `ipv4`.
+    pub fn encap_ipv4(session_id: u16, ipv4: Ipv4Packet) -> PppoeFrame {
+        PppoeFrame::session(session_id, PPP_IPV4, &ipv4.data[ipv4.layer3_offset..])
+    }
+
+    /// Makes a session frame carrying `ipv6`.
+    pub fn encap_ipv6(session_id: u16, ipv6: Ipv6Packet) -> PppoeFrame {
+        PppoeFrame::session(session_id, PPP_IPV6, &ipv6.data[ipv6.layer3_offset..])
+    }
+
+    /// The code of the frame. Since it's checked against the EtherType when the frame is made,
+    /// it's always a known one.
+    pub fn code(&self) -> PppoeCode {
+        PppoeCode::from_u8(self.header()[1]).unwrap()
+    }
+
+    pub fn session_id(&self) -> u16 {
+        u16::from_be_bytes(self.header()[2..4].try_into().unwrap())
+    }
+
+    pub fn set_session_id(&mut self, session_id: u16) {
+        let offset = self.frame.payload_offset + 2;
+        self.frame.data[offset..offset + 2].copy_from_slice(&session_id.to_be_bytes());
+    }
+
+    /// The length of the payload, from the length field.
+    pub fn length(&self) -> u16 {
+        u16::from_be_bytes(self.header()[4..6].try_into().unwrap())
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        let offset = self.frame.payload_offset + HEADER_LEN;
+        &self.frame.data[offset..offset + usize::from(self.length())]
+    }
+
+    /// Sets the payload and the length field, dropping any padding.
+    pub fn set_payload(&mut self, payload: &[u8]) {
+        let offset = self.frame.payload_offset;
+        self.frame.data[offset + 4..offset + 6]
+            .copy_from_slice(&(payload.len() as u16).to_be_bytes());
+        self.frame.data.truncate(offset + HEADER_LEN);
+        self.frame.data.extend_from_slice(payload);
+    }
+
+    /// The tags of a discovery frame, in order, up to the End-Of-List tag if there is one. Tags
+    /// running past the end of the payload are left out.
+    pub fn tags(&self) -> Vec<PppoeTag> {
+        let mut tags = vec![];
+        let mut rest = self.payload();
+        while rest.len() >= 4 {
+            let tag_type = u16::from_be_bytes([rest[0], rest[1]]);
+            let len = 4 + usize::from(u16::from_be_bytes([rest[2], rest[3]]));
+            if tag_type == PPPOE_TAG_END_OF_LIST || rest.len() < len {
+                break;
+            }
+            tags.push(PppoeTag::new(tag_type, &rest[4..len]));
+            rest = &rest[len..];
+        }
+        tags
+    }
+
+    /// The value of the first tag of `tag_type`, if there is one.
+    pub fn tag(&self, tag_type: u16) -> Option<Vec<u8>> {
+        self.tags()
+            .into_iter()
+            .find(|tag| tag.tag_type == tag_type)
+            .map(|tag| tag.value)
+    }
+
+    pub fn set_tags(&mut self, tags: &[PppoeTag]) {
+        let payload: Vec<u8> = tags.iter().flat_map(PppoeTag::to_bytes).collect();
+        self.set_payload(&payload);
+    }
+
+    /// The protocol of the PPP frame a session frame carries, allowing for it to be compressed to
+    /// one byte, as RFC 1661 lets odd protocol numbers below 0x100 be.
+    pub fn ppp_protocol(&self) -> Option<u16> {
+        if self.code() != PppoeCode::Session {
+            return None;
+        }
+        match self.payload() {
+            [first, ..] if first & 0x01 != 0 => Some(u16::from(*first)),
+            [high, low, ..] => Some(u16::from_be_bytes([*high, *low])),
+            _ => None,
+        }
+    }
+
+    /// The payload of the PPP frame a session frame carries, after its protocol.
+    pub fn ppp_payload(&self) -> Option<&[u8]> {
+        self.ppp_protocol()?;
+        let protocol_len = if self.payload()[0] & 0x01 != 0 { 1 } else { 2 };
+        Some(&self.payload()[protocol_len..])
+    }
+
+    /// The packet of the control protocol a session frame carries, if it's LCP, IPCP or IPV6CP.
+    pub fn ppp_control(&self) -> Option<PppControl> {
+        match self.ppp_protocol()? {
+            PPP_LCP | PPP_IPCP | PPP_IPV6CP => PppControl::from_bytes(self.ppp_payload()?),
+            _ => None,
+        }
+    }
+
+    /// The IPv4 packet a session frame carries, if its PPP protocol is IPv4.
+    pub fn inner_ipv4(&self) -> Result<Ipv4Packet, &'static str> {
+        if self.ppp_protocol() != Some(PPP_IPV4) {
+            return Err("PppoeFrame does not carry an IPv4 packet");
+        }
+        Ipv4Packet::from_buffer(self.ppp_packet(), None, 0)
+    }
+
+    /// The IPv6 packet a session frame carries, if its PPP protocol is IPv6.
+    pub fn inner_ipv6(&self) -> Result<Ipv6Packet, &'static str> {
+        if self.ppp_protocol() != Some(PPP_IPV6) {
+            return Err("PppoeFrame does not carry an IPv6 packet");
+        }
+        Ipv6Packet::from_buffer(self.ppp_packet(), None, 0)
+    }
+
+    /// A view of the frame's data holding just the PPP payload, for session frames that have one.
+    fn ppp_packet(&self) -> PacketData {
+        let end = self.frame.payload_offset + HEADER_LEN + usize::from(self.length());
+        self.frame
+            .data
+            .slice(end - self.ppp_payload().unwrap().len()..end)
+    }
+
+    pub fn frame(self) -> EthernetFrame {
+        self.frame
+    }
+
+    fn header(&self) -> &[u8] {
+        &self.frame.data[self.frame.payload_offset..self.frame.payload_offset + HEADER_LEN]
+    }
+}
+
+impl TryFrom<EthernetFrame> for PppoeFrame {
+    type Error = &'static str;
+
+    ///
+    /// Decorates the given EthernetFrame with PppoeFrame getters/setters.
+    /// Validates
+    /// - The frame has a PPPoE ether type, and a code of that stage
+    /// - The header is version 1, type 1
+    /// - The payload is at least as long as the length field says
+    ///
+    fn try_from(frame: EthernetFrame) -> Result<Self, Self::Error> {
+        let ether_type = frame.ether_type();
+        if ether_type != PPPOE_DISCOVERY_ETHER_TYPE && ether_type != PPPOE_SESSION_ETHER_TYPE {
+            return Err("Frame does not have a PPPoE ether type");
+        }
+
+        let payload = frame.payload();
+        if payload.len() < HEADER_LEN {
+            return Err("Frame payload is too small");
+        }
+        if payload[0] != VERSION_TYPE {
+            return Err("PppoeFrame has unsupported version or type");
+        }
+        match PppoeCode::from_u8(payload[1]) {
+            Some(code) if code.ether_type() == ether_type => (),
+            _ => return Err("PppoeFrame has an unknown code for its ether type"),
+        }
+        let length = usize::from(u16::from_be_bytes([payload[4], payload[5]]));
+        if payload.len() < HEADER_LEN + length {
+            return Err("Frame payload doesn't match the length field");
+        }
+
+        Ok(PppoeFrame { frame })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discovery() {
+        // A PADI, as sent by pppd, with an empty Service-Name and a Host-Uniq, padded to 60 bytes.
+        let mut data = vec![
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0x0c, 0x29, 0x3a, 0x12, 0x34, 0x88, 0x63,
+            0x11, 0x09, 0x00, 0x00, 0x00, 0x0c, 0x01, 0x01, 0x00, 0x00, 0x01, 0x03, 0x00, 0x04,
+            0xde, 0xad, 0xbe, 0xef,
+        ];
+        data.resize(60, 0);
+        let frame = EthernetFrame::from_buffer(data.into(), 0).unwrap();
+        let padi = PppoeFrame::try_from(frame).unwrap();
+        assert_eq!(padi.code(), PppoeCode::Padi);
+        assert_eq!(padi.session_id(), 0);
+        assert_eq!(padi.length(), 12);
+        assert_eq!(
+            padi.tags(),
+            vec![
+                PppoeTag::new(PPPOE_TAG_SERVICE_NAME, &[]),
+                PppoeTag::new(PPPOE_TAG_HOST_UNIQ, &[0xde, 0xad, 0xbe, 0xef]),
+            ]
+        );
+        assert_eq!(padi.ppp_protocol(), None);
+
+        let pads = PppoeFrame::discovery(
+            PppoeCode::Pads,
+            0x2a,
+            &[PppoeTag::new(
+                PPPOE_TAG_HOST_UNIQ,
+                &[0xde, 0xad, 0xbe, 0xef],
+            )],
+        );
+        let frame = pads.clone().frame();
+        assert_eq!(frame.ether_type(), PPPOE_DISCOVERY_ETHER_TYPE);
+        assert_eq!(PppoeFrame::try_from(frame).unwrap(), pads);
+        assert_eq!(pads.session_id(), 0x2a);
+        assert_eq!(
+            pads.tag(PPPOE_TAG_HOST_UNIQ),
+            Some(vec![0xde, 0xad, 0xbe, 0xef])
+        );
+        assert_eq!(pads.tag(PPPOE_TAG_AC_COOKIE), None);
+    }
+
+    #[test]
+    fn session() {
+        let mut ipv4 = Ipv4Packet::empty();
+        ipv4.set_payload(&[1, 2, 3]);
+        let session = PppoeFrame::encap_ipv4(0x2a, ipv4.clone());
+        let mut frame = session.frame();
+        assert_eq!(frame.ether_type(), PPPOE_SESSION_ETHER_TYPE);
+        assert_eq!(frame.payload()[..8], [0x11, 0, 0, 0x2a, 0, 25, 0x00, 0x21]);
+
+        // Padding after the length is ignored.
+        let mut padded = frame.payload().into_owned();
+        padded.extend_from_slice(&[0; 10]);
+        frame.set_payload(&padded);
+        let session = PppoeFrame::try_from(frame).unwrap();
+        assert_eq!(session.code(), PppoeCode::Session);
+        assert_eq!(session.ppp_protocol(), Some(PPP_IPV4));
+        assert_eq!(session.inner_ipv4().unwrap(), ipv4);
+        assert!(session.inner_ipv6().is_err());
+        assert_eq!(session.ppp_control(), None);
+
+        // A compressed protocol field.
+        let mut compressed = PppoeFrame::new(PppoeCode::Session, 1);
+        compressed.set_payload(&[0x21, 9]);
+        assert_eq!(compressed.ppp_protocol(), Some(PPP_IPV4));
+        assert_eq!(compressed.ppp_payload(), Some(&[9][..]));
+    }
+
+    #[test]
+    fn control() {
+        // An LCP Configure-Request for an MRU of 1492 and a magic number.
+        let request = PppControl::configure(
+            PppControl::CONFIGURE_REQUEST,
+            1,
+            &[(1, vec![0x05, 0xd4]), (5, vec![0x12, 0x34, 0x56, 0x78])],
+        );
+        let session = PppoeFrame::session(0x2a, PPP_LCP, &request.to_bytes());
+        assert_eq!(
+            session.ppp_payload().unwrap(),
+            &[1, 1, 0, 14, 1, 4, 0x05, 0xd4, 5, 6, 0x12, 0x34, 0x56, 0x78][..]
+        );
+        let parsed = session.ppp_control().unwrap();
+        assert_eq!(parsed, request);
+        assert_eq!(parsed.options()[0], (1, vec![0x05, 0xd4]));
+        assert_eq!(parsed.options().len(), 2);
+
+        assert_eq!(PppControl::from_bytes(&[1, 1, 0, 14, 1, 4]), None);
+    }
+
+    #[test]
+    fn rejects_non_pppoe() {
+        assert!(PppoeFrame::try_from(EthernetFrame::encap_ipv4(Ipv4Packet::empty())).is_err());
+
+        // A session code on the discovery EtherType.
+        let mut frame = PppoeFrame::new(PppoeCode::Session, 1).frame();
+        frame.set_ether_type(PPPOE_DISCOVERY_ETHER_TYPE);
+        assert!(PppoeFrame::try_from(frame.clone()).is_err());
+
+        // A length past the end of the frame.
+        frame.set_ether_type(PPPOE_SESSION_ETHER_TYPE);
+        let mut payload = frame.payload().into_owned();
+        payload[5] = 1;
+        frame.set_payload(&payload);
+        assert!(PppoeFrame::try_from(frame).is_err());
+    }
+}
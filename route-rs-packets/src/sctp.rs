@@ -0,0 +1,552 @@
+use crate::*;
+use std::borrow::Cow;
+use std::convert::{TryFrom, TryInto};
+
+/// Chunk types, per RFC 4960.
+pub const SCTP_CHUNK_DATA: u8 = 0;
+pub const SCTP_CHUNK_INIT: u8 = 1;
+pub const SCTP_CHUNK_INIT_ACK: u8 = 2;
+pub const SCTP_CHUNK_SACK: u8 = 3;
+pub const SCTP_CHUNK_HEARTBEAT: u8 = 4;
+pub const SCTP_CHUNK_HEARTBEAT_ACK: u8 = 5;
+pub const SCTP_CHUNK_ABORT: u8 = 6;
+pub const SCTP_CHUNK_SHUTDOWN: u8 = 7;
+pub const SCTP_CHUNK_SHUTDOWN_ACK: u8 = 8;
+pub const SCTP_CHUNK_ERROR: u8 = 9;
+pub const SCTP_CHUNK_COOKIE_ECHO: u8 = 10;
+pub const SCTP_CHUNK_COOKIE_ACK: u8 = 11;
+pub const SCTP_CHUNK_SHUTDOWN_COMPLETE: u8 = 14;
+
+/// Flags of DATA chunks.
+const UNORDERED_FLAG: u8 = 0x04;
+const BEGINNING_FLAG: u8 = 0x02;
+const ENDING_FLAG: u8 = 0x01;
+
+/// The common header: ports, verification tag and checksum.
+const HEADER_LEN: usize = 12;
+
+/// The CRC32c, per RFC 3309, of `data`, with the checksum field, at `checksum_offset`, taken as
+/// zero.
+fn crc32c(data: &[u8], checksum_offset: usize) -> u32 {
+    let mut crc = !0u32;
+    for (i, byte) in data.iter().enumerate() {
+        let byte = if (checksum_offset..checksum_offset + 4).contains(&i) {
+            0
+        } else {
+            *byte
+        };
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            // The reflected Castagnoli polynomial.
+            crc = (crc >> 1) ^ (0x82F6_3B78 & (!(crc & 1)).wrapping_add(1));
+        }
+    }
+    !crc
+}
+
+/// An SCTP chunk. Chunks of known types too short for their fields are kept as Other.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SctpChunk<'a> {
+    Data {
+        unordered: bool,
+        beginning: bool,
+        ending: bool,
+        tsn: u32,
+        stream_id: u16,
+        stream_sequence_number: u16,
+        payload_protocol_id: u32,
+        data: &'a [u8],
+    },
+    /// An INIT or INIT ACK chunk, by `ack`. Their optional parameters are left unparsed.
+    Init {
+        ack: bool,
+        initiate_tag: u32,
+        a_rwnd: u32,
+        outbound_streams: u16,
+        inbound_streams: u16,
+        initial_tsn: u32,
+        parameters: &'a [u8],
+    },
+    Sack {
+        cumulative_tsn_ack: u32,
+        a_rwnd: u32,
+        /// Blocks of TSNs received after the cumulative ack, as their start and end offsets from
+        /// it.
+        gap_ack_blocks: Vec<(u16, u16)>,
+        duplicate_tsns: Vec<u32>,
+    },
+    /// A HEARTBEAT or HEARTBEAT ACK chunk, by `ack`, with the heartbeat info parameter, which the
+    /// ack echoes.
+    Heartbeat { ack: bool, info: &'a [u8] },
+    Other {
+        chunk_type: u8,
+        flags: u8,
+        value: &'a [u8],
+    },
+}
+
+impl<'a> SctpChunk<'a> {
+    /// Parses a chunk of `chunk_type` with `value` following its type, flags and length.
+    fn parse(chunk_type: u8, flags: u8, value: &'a [u8]) -> SctpChunk<'a> {
+        let be_u16 = |offset: usize| u16::from_be_bytes([value[offset], value[offset + 1]]);
+        let be_u32 =
+            |offset: usize| u32::from_be_bytes(value[offset..offset + 4].try_into().unwrap());
+        match (chunk_type, value.len()) {
+            (SCTP_CHUNK_DATA, len) if len >= 12 => SctpChunk::Data {
+                unordered: flags & UNORDERED_FLAG != 0,
+                beginning: flags & BEGINNING_FLAG != 0,
+                ending: flags & ENDING_FLAG != 0,
+                tsn: be_u32(0),
+                stream_id: be_u16(4),
+                stream_sequence_number: be_u16(6),
+                payload_protocol_id: be_u32(8),
+                data: &value[12..],
+            },
+            (SCTP_CHUNK_INIT, len) | (SCTP_CHUNK_INIT_ACK, len) if len >= 16 => SctpChunk::Init {
+                ack: chunk_type == SCTP_CHUNK_INIT_ACK,
+                initiate_tag: be_u32(0),
+                a_rwnd: be_u32(4),
+                outbound_streams: be_u16(8),
+                inbound_streams: be_u16(10),
+                initial_tsn: be_u32(12),
+                parameters: &value[16..],
+            },
+            (SCTP_CHUNK_SACK, len)
+                if len >= 12
+                    && len >= 12 + usize::from(be_u16(8)) * 4 + usize::from(be_u16(10)) * 4 =>
+            {
+                let gaps_end = 12 + usize::from(be_u16(8)) * 4;
+                let duplicates_end = gaps_end + usize::from(be_u16(10)) * 4;
+                SctpChunk::Sack {
+                    cumulative_tsn_ack: be_u32(0),
+                    a_rwnd: be_u32(4),
+                    gap_ack_blocks: (12..gaps_end)
+                        .step_by(4)
+                        .map(|offset| (be_u16(offset), be_u16(offset + 2)))
+                        .collect(),
+                    duplicate_tsns: (gaps_end..duplicates_end).step_by(4).map(be_u32).collect(),
+                }
+            }
+            (SCTP_CHUNK_HEARTBEAT, _) | (SCTP_CHUNK_HEARTBEAT_ACK, _) => SctpChunk::Heartbeat {
+                ack: chunk_type == SCTP_CHUNK_HEARTBEAT_ACK,
+                info: value,
+            },
+            _ => SctpChunk::Other {
+                chunk_type,
+                flags,
+                value,
+            },
+        }
+    }
+
+    pub fn chunk_type(&self) -> u8 {
+        match self {
+            SctpChunk::Data { .. } => SCTP_CHUNK_DATA,
+            SctpChunk::Init { ack: false, .. } => SCTP_CHUNK_INIT,
+            SctpChunk::Init { ack: true, .. } => SCTP_CHUNK_INIT_ACK,
+            SctpChunk::Sack { .. } => SCTP_CHUNK_SACK,
+            SctpChunk::Heartbeat { ack: false, .. } => SCTP_CHUNK_HEARTBEAT,
+            SctpChunk::Heartbeat { ack: true, .. } => SCTP_CHUNK_HEARTBEAT_ACK,
+            SctpChunk::Other { chunk_type, .. } => *chunk_type,
+        }
+    }
+
+    /// The chunk's bytes, including its type, flags and length, padded with zeros to a multiple
+    /// of 4 bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut flags = 0;
+        let mut value = vec![];
+        match self {
+            SctpChunk::Data {
+                unordered,
+                beginning,
+                ending,
+                tsn,
+                stream_id,
+                stream_sequence_number,
+                payload_protocol_id,
+                data,
+            } => {
+                for (set, flag) in [
+                    (unordered, UNORDERED_FLAG),
+                    (beginning, BEGINNING_FLAG),
+                    (ending, ENDING_FLAG),
+                ]
+                .iter()
+                {
+                    if **set {
+                        flags |= flag;
+                    }
+                }
+                value.extend_from_slice(&tsn.to_be_bytes());
+                value.extend_from_slice(&stream_id.to_be_bytes());
+                value.extend_from_slice(&stream_sequence_number.to_be_bytes());
+                value.extend_from_slice(&payload_protocol_id.to_be_bytes());
+                value.extend_from_slice(data);
+            }
+            SctpChunk::Init {
+                initiate_tag,
+                a_rwnd,
+                outbound_streams,
+                inbound_streams,
+                initial_tsn,
+                parameters,
+                ..
+            } => {
+                value.extend_from_slice(&initiate_tag.to_be_bytes());
+                value.extend_from_slice(&a_rwnd.to_be_bytes());
+                value.extend_from_slice(&outbound_streams.to_be_bytes());
+                value.extend_from_slice(&inbound_streams.to_be_bytes());
+                value.extend_from_slice(&initial_tsn.to_be_bytes());
+                value.extend_from_slice(parameters);
+            }
+            SctpChunk::Sack {
+                cumulative_tsn_ack,
+                a_rwnd,
+                gap_ack_blocks,
+                duplicate_tsns,
+            } => {
+                value.extend_from_slice(&cumulative_tsn_ack.to_be_bytes());
+                value.extend_from_slice(&a_rwnd.to_be_bytes());
+                value.extend_from_slice(&(gap_ack_blocks.len() as u16).to_be_bytes());
+                value.extend_from_slice(&(duplicate_tsns.len() as u16).to_be_bytes());
+                for (start, end) in gap_ack_blocks {
+                    value.extend_from_slice(&start.to_be_bytes());
+                    value.extend_from_slice(&end.to_be_bytes());
+                }
+                for tsn in duplicate_tsns {
+                    value.extend_from_slice(&tsn.to_be_bytes());
+                }
+            }
+            SctpChunk::Heartbeat { info, .. } => value.extend_from_slice(info),
+            SctpChunk::Other {
+                flags: other_flags,
+                value: other_value,
+                ..
+            } => {
+                flags = *other_flags;
+                value.extend_from_slice(other_value);
+            }
+        }
+
+        let mut bytes = vec![self.chunk_type(), flags];
+        bytes.extend_from_slice(&((value.len() + 4) as u16).to_be_bytes());
+        bytes.extend(value);
+        bytes.resize(bytes.len().div_ceil(4) * 4, 0);
+        bytes
+    }
+}
+
+/// Iterates over the chunks of an SctpPacket, until a chunk running past the end of the packet.
+pub struct SctpChunks<'a> {
+    chunks: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Iterator for SctpChunks<'a> {
+    type Item = SctpChunk<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.chunks.get(self.offset..self.offset + 4)?;
+        let len = usize::from(u16::from_be_bytes([header[2], header[3]]));
+        if len < 4 || self.offset + len > self.chunks.len() {
+            return None;
+        }
+        let chunk = SctpChunk::parse(
+            header[0],
+            header[1],
+            &self.chunks[self.offset + 4..self.offset + len],
+        );
+        // Chunks are padded to a multiple of 4 bytes, but the last one's padding may be left out.
+        self.offset += len.div_ceil(4) * 4;
+        Some(chunk)
+    }
+}
+
+/// An SCTP packet, per RFC 4960: the common header at `layer4_offset`, followed by chunks, from
+/// `payload_offset`.
+#[derive(Clone, Debug)]
+pub struct SctpPacket {
+    pub data: PacketData,
+    pub layer2_offset: Option<usize>,
+    pub layer3_offset: Option<usize>,
+    pub layer4_offset: usize,
+    pub payload_offset: usize,
+}
+
+impl SctpPacket {
+    pub fn from_buffer(
+        data: PacketData,
+        layer2_offset: Option<usize>,
+        layer3_offset: Option<usize>,
+        layer4_offset: usize,
+    ) -> Result<SctpPacket, &'static str> {
+        if data.len() < layer4_offset + HEADER_LEN {
+            return Err("Data is too short to be an SctpPacket");
+        }
+
+        if let Some(layer3_offset) = layer3_offset {
+            let protocol = match data[layer3_offset] >> 4 {
+                4 => get_ipv4_payload_type(&data, layer3_offset)?,
+                6 => get_ipv6_payload_type(&data, layer3_offset)?,
+                _ => return Err("IP Header has invalid version number"),
+            };
+            if protocol != IpProtocol::SCTP {
+                return Err("Protocol is incorrect, since it isn't SCTP");
+            }
+        }
+
+        Ok(SctpPacket {
+            data,
+            layer2_offset,
+            layer3_offset,
+            layer4_offset,
+            payload_offset: layer4_offset + HEADER_LEN,
+        })
+    }
+
+    /// Makes an SctpPacket carrying `chunks`, with no IP header, and sets its checksum.
+    pub fn new(
+        src_port: u16,
+        dest_port: u16,
+        verification_tag: u32,
+        chunks: &[SctpChunk],
+    ) -> SctpPacket {
+        let mut data = src_port.to_be_bytes().to_vec();
+        data.extend_from_slice(&dest_port.to_be_bytes());
+        data.extend_from_slice(&verification_tag.to_be_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend(chunks.iter().flat_map(SctpChunk::to_bytes));
+        let mut packet = SctpPacket::from_buffer(data.into(), None, None, 0).unwrap();
+        packet.set_checksum();
+        packet
+    }
+
+    pub fn src_port(&self) -> u16 {
+        u16::from_be_bytes(
+            self.data[self.layer4_offset..=self.layer4_offset + 1]
+                .try_into()
+                .unwrap(),
+        )
+    }
+
+    pub fn set_src_port(&mut self, port: u16) {
+        self.data[self.layer4_offset..=self.layer4_offset + 1].copy_from_slice(&port.to_be_bytes());
+    }
+
+    pub fn dest_port(&self) -> u16 {
+        u16::from_be_bytes(
+            self.data[self.layer4_offset + 2..=self.layer4_offset + 3]
+                .try_into()
+                .unwrap(),
+        )
+    }
+
+    pub fn set_dest_port(&mut self, port: u16) {
+        self.data[self.layer4_offset + 2..=self.layer4_offset + 3]
+            .copy_from_slice(&port.to_be_bytes());
+    }
+
+    /// The initiate tag of the association at the receiving end, or 0 on packets carrying INIT.
+    pub fn verification_tag(&self) -> u32 {
+        u32::from_be_bytes(
+            self.data[self.layer4_offset + 4..self.layer4_offset + 8]
+                .try_into()
+                .unwrap(),
+        )
+    }
+
+    pub fn set_verification_tag(&mut self, verification_tag: u32) {
+        self.data[self.layer4_offset + 4..self.layer4_offset + 8]
+            .copy_from_slice(&verification_tag.to_be_bytes());
+    }
+
+    /// The CRC32c checksum. Unlike other fields, it's sent least significant byte first.
+    pub fn checksum(&self) -> u32 {
+        u32::from_le_bytes(
+            self.data[self.layer4_offset + 8..self.layer4_offset + 12]
+                .try_into()
+                .unwrap(),
+        )
+    }
+
+    /// Calculates what the checksum should be set to, given the current header and chunks.
+    pub fn calculate_checksum(&self) -> u32 {
+        crc32c(&self.data[self.layer4_offset..], 8)
+    }
+
+    /// Sets checksum field to valid value
+    pub fn set_checksum(&mut self) {
+        let checksum = self.calculate_checksum();
+        self.data[self.layer4_offset + 8..self.layer4_offset + 12]
+            .copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    pub fn validate_checksum(&self) -> bool {
+        self.checksum() == self.calculate_checksum()
+    }
+
+    /// The chunks of the packet, in order.
+    pub fn chunks(&self) -> SctpChunks<'_> {
+        SctpChunks {
+            chunks: &self.data[self.payload_offset..],
+            offset: 0,
+        }
+    }
+
+    pub fn payload(&self) -> Cow<'_, [u8]> {
+        Cow::from(&self.data[self.payload_offset..])
+    }
+
+    /// Replaces the chunks, does not update the checksum.
+    /// Don't forget to update the length field of the IP packet that contains this.
+    pub fn set_chunks(&mut self, chunks: &[SctpChunk]) {
+        self.data.truncate(self.payload_offset);
+        self.data
+            .extend(chunks.iter().flat_map(SctpChunk::to_bytes));
+    }
+}
+
+/// SctpPackets are considered the same if they have the same data from the layer 4
+/// header and onward. This function does not consider the data before the start of
+/// the SCTP header.
+impl PartialEq for SctpPacket {
+    fn eq(&self, other: &Self) -> bool {
+        self.data[self.layer4_offset..] == other.data[other.layer4_offset..]
+    }
+}
+
+impl Eq for SctpPacket {}
+
+impl TryFrom<Ipv4Packet> for SctpPacket {
+    type Error = &'static str;
+
+    fn try_from(packet: Ipv4Packet) -> Result<Self, Self::Error> {
+        SctpPacket::from_buffer(
+            packet.data,
+            packet.layer2_offset,
+            Some(packet.layer3_offset),
+            packet.payload_offset,
+        )
+    }
+}
+
+impl TryFrom<Ipv6Packet> for SctpPacket {
+    type Error = &'static str;
+
+    fn try_from(packet: Ipv6Packet) -> Result<Self, Self::Error> {
+        SctpPacket::from_buffer(
+            packet.data,
+            packet.layer2_offset,
+            Some(packet.layer3_offset),
+            packet.payload_offset,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() -> SctpChunk<'static> {
+        SctpChunk::Init {
+            ack: false,
+            initiate_tag: 0x1234_5678,
+            a_rwnd: 106_496,
+            outbound_streams: 10,
+            inbound_streams: 65535,
+            initial_tsn: 0x9abc_def0,
+            parameters: &[0x00, 0x0c, 0x00, 0x06, 0x00, 0x05, 0x00, 0x00],
+        }
+    }
+
+    #[test]
+    fn crc32c_check_values() {
+        // From RFC 3720, with the checksum at an offset past the end.
+        assert_eq!(crc32c(b"123456789", 9), 0xE306_9283);
+        assert_eq!(crc32c(&[0; 32], 32), 0x8A91_36AA);
+        assert_eq!(crc32c(&[0xff; 32], 32), 0x62A8_AB43);
+        // The checksum field is taken as zero.
+        assert_eq!(crc32c(&[0xff; 4], 0), 0x48674BC7);
+    }
+
+    #[test]
+    fn sctp_packet() {
+        let packet = Ipv4Packet::encap_sctp(SctpPacket::new(5000, 36412, 0, &[init()]));
+        assert_eq!(packet.protocol(), IpProtocol::SCTP);
+
+        let mut sctp = SctpPacket::try_from(packet).unwrap();
+        assert_eq!(sctp.src_port(), 5000);
+        assert_eq!(sctp.dest_port(), 36412);
+        assert_eq!(sctp.verification_tag(), 0);
+        assert!(sctp.validate_checksum());
+        assert_eq!(sctp.chunks().collect::<Vec<_>>(), vec![init()]);
+
+        sctp.set_verification_tag(0x1234_5678);
+        assert!(!sctp.validate_checksum());
+        sctp.set_checksum();
+        assert!(sctp.validate_checksum());
+
+        let mut udp = Ipv4Packet::empty();
+        udp.set_protocol(17);
+        udp.set_payload(&[0; 12]);
+        assert!(SctpPacket::try_from(udp).is_err());
+    }
+
+    #[test]
+    fn chunks() {
+        let chunks = vec![
+            SctpChunk::Sack {
+                cumulative_tsn_ack: 100,
+                a_rwnd: 65536,
+                gap_ack_blocks: vec![(2, 3), (5, 5)],
+                duplicate_tsns: vec![99],
+            },
+            SctpChunk::Data {
+                unordered: false,
+                beginning: true,
+                ending: true,
+                tsn: 101,
+                stream_id: 1,
+                stream_sequence_number: 7,
+                payload_protocol_id: 46,
+                data: &[1, 2, 3, 4, 5],
+            },
+            SctpChunk::Heartbeat {
+                ack: true,
+                info: &[0, 1, 0, 8, 1, 2, 3, 4],
+            },
+            SctpChunk::Other {
+                chunk_type: SCTP_CHUNK_SHUTDOWN,
+                flags: 0,
+                value: &[0, 0, 0, 100],
+            },
+        ];
+        let packet = SctpPacket::new(5000, 5000, 1, &chunks);
+        // The DATA chunk is padded from 21 bytes to 24.
+        assert_eq!(packet.data.len(), 12 + 28 + 24 + 12 + 8);
+        assert_eq!(packet.data[12 + 28 + 3], 21);
+        assert_eq!(packet.chunks().collect::<Vec<_>>(), chunks);
+        let types: Vec<u8> = packet.chunks().map(|chunk| chunk.chunk_type()).collect();
+        assert_eq!(types, vec![3, 0, 5, 7]);
+    }
+
+    #[test]
+    fn short_chunks() {
+        // A DATA chunk too short for its header is kept as Other.
+        let mut packet = SctpPacket::new(1, 2, 3, &[]);
+        packet.data.extend_from_slice(&[0, 0x03, 0, 8, 0, 0, 0, 1]);
+        assert_eq!(
+            packet.chunks().collect::<Vec<_>>(),
+            vec![SctpChunk::Other {
+                chunk_type: SCTP_CHUNK_DATA,
+                flags: 0x03,
+                value: &[0, 0, 0, 1],
+            }]
+        );
+
+        // A chunk running past the end of the packet ends the iteration.
+        packet.data.extend_from_slice(&[4, 0, 0, 12, 0, 0]);
+        assert_eq!(packet.chunks().count(), 1);
+    }
+}
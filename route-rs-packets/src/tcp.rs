@@ -2,6 +2,136 @@ use crate::*;
 use std::borrow::Cow;
 use std::convert::{TryFrom, TryInto};
 
+/// Option kinds, per RFC 793, RFC 2018 and RFC 7323.
+pub const TCP_OPTION_END: u8 = 0;
+pub const TCP_OPTION_NOP: u8 = 1;
+pub const TCP_OPTION_MSS: u8 = 2;
+pub const TCP_OPTION_WINDOW_SCALE: u8 = 3;
+pub const TCP_OPTION_SACK_PERMITTED: u8 = 4;
+pub const TCP_OPTION_SACK: u8 = 5;
+pub const TCP_OPTION_TIMESTAMPS: u8 = 8;
+
+/// The data offset field counts 32 bit words, so options fit in 40 bytes.
+const MAX_OPTIONS_LEN: usize = 40;
+
+/// A TCP option. Options of known kinds but the wrong length are kept as Other.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TcpOption {
+    MaximumSegmentSize(u16),
+    WindowScale(u8),
+    SackPermitted,
+    /// Blocks of received data, as their left and right edges.
+    Sack(Vec<(u32, u32)>),
+    Timestamps {
+        value: u32,
+        echo_reply: u32,
+    },
+    Other {
+        kind: u8,
+        data: Vec<u8>,
+    },
+}
+
+impl TcpOption {
+    /// Parses an option of `kind` with `data` following its kind and length bytes.
+    fn parse(kind: u8, data: &[u8]) -> TcpOption {
+        let be_u32 = |bytes: &[u8]| u32::from_be_bytes(bytes.try_into().unwrap());
+        match (kind, data.len()) {
+            (TCP_OPTION_MSS, 2) => {
+                TcpOption::MaximumSegmentSize(u16::from_be_bytes(data.try_into().unwrap()))
+            }
+            (TCP_OPTION_WINDOW_SCALE, 1) => TcpOption::WindowScale(data[0]),
+            (TCP_OPTION_SACK_PERMITTED, 0) => TcpOption::SackPermitted,
+            (TCP_OPTION_SACK, len) if len > 0 && len % 8 == 0 => TcpOption::Sack(
+                data.chunks(8)
+                    .map(|block| (be_u32(&block[..4]), be_u32(&block[4..])))
+                    .collect(),
+            ),
+            (TCP_OPTION_TIMESTAMPS, 8) => TcpOption::Timestamps {
+                value: be_u32(&data[..4]),
+                echo_reply: be_u32(&data[4..]),
+            },
+            _ => TcpOption::Other {
+                kind,
+                data: data.to_vec(),
+            },
+        }
+    }
+
+    pub fn kind(&self) -> u8 {
+        match self {
+            TcpOption::MaximumSegmentSize(_) => TCP_OPTION_MSS,
+            TcpOption::WindowScale(_) => TCP_OPTION_WINDOW_SCALE,
+            TcpOption::SackPermitted => TCP_OPTION_SACK_PERMITTED,
+            TcpOption::Sack(_) => TCP_OPTION_SACK,
+            TcpOption::Timestamps { .. } => TCP_OPTION_TIMESTAMPS,
+            TcpOption::Other { kind, .. } => *kind,
+        }
+    }
+
+    /// Returns the option as it's sent, with its kind and length bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let data = match self {
+            TcpOption::MaximumSegmentSize(mss) => mss.to_be_bytes().to_vec(),
+            TcpOption::WindowScale(shift) => vec![*shift],
+            TcpOption::SackPermitted => vec![],
+            TcpOption::Sack(blocks) => blocks
+                .iter()
+                .flat_map(|(left, right)| [left.to_be_bytes(), right.to_be_bytes()].concat())
+                .collect(),
+            TcpOption::Timestamps { value, echo_reply } => {
+                let mut data = value.to_be_bytes().to_vec();
+                data.extend_from_slice(&echo_reply.to_be_bytes());
+                data
+            }
+            TcpOption::Other { data, .. } => data.clone(),
+        };
+
+        let mut bytes = vec![self.kind(), data.len() as u8 + 2];
+        bytes.extend(data);
+        bytes
+    }
+}
+
+/// Iterates over the options of a TcpSegment, skipping no-operation padding, until the end of
+/// option list option, or an option running past the end of the options.
+pub struct TcpOptions<'a> {
+    options: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> TcpOptions<'a> {
+    /// Returns the start and end, in the options, of the next option.
+    fn next_span(&mut self) -> Option<(usize, usize)> {
+        loop {
+            match *self.options.get(self.offset)? {
+                TCP_OPTION_END => return None,
+                TCP_OPTION_NOP => self.offset += 1,
+                _ => break,
+            }
+        }
+        let start = self.offset;
+        let len = usize::from(*self.options.get(start + 1)?);
+        if len < 2 || start + len > self.options.len() {
+            return None;
+        }
+        self.offset += len;
+        Some((start, start + len))
+    }
+}
+
+impl<'a> Iterator for TcpOptions<'a> {
+    type Item = TcpOption;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (start, end) = self.next_span()?;
+        Some(TcpOption::parse(
+            self.options[start],
+            &self.options[start + 2..end],
+        ))
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct TcpSegment {
     pub data: PacketData,
@@ -27,12 +157,10 @@ impl TcpSegment {
             let ip_version = (data[layer3_offset] & 0xF0) >> 4;
             match ip_version {
                 4 => {
-                    protocol = get_ipv4_payload_type(&data, layer3_offset)
-                        .expect("Malformed IPv4 Header in TcpSegment");
+                    protocol = get_ipv4_payload_type(&data, layer3_offset)?;
                 }
                 6 => {
-                    protocol = get_ipv6_payload_type(&data, layer3_offset)
-                        .expect("Malformed IPv6 Header in TcpSegment");
+                    protocol = get_ipv6_payload_type(&data, layer3_offset)?;
                 }
                 _ => {
                     return Err("IP Header has invalid version number");
@@ -60,7 +188,7 @@ impl TcpSegment {
         let mut data = vec![];
         data.resize(20, 0);
         data[12] = 0x50; //Set data offset to minimum
-        TcpSegment::from_buffer(data, None, None, 0).unwrap()
+        TcpSegment::from_buffer(data.into(), None, None, 0).unwrap()
     }
 
     pub fn src_port(&self) -> u16 {
@@ -71,7 +199,9 @@ impl TcpSegment {
         )
     }
 
+    /// Sets the source port, updating the checksum incrementally.
     pub fn set_src_port(&mut self, port: u16) {
+        self.update_checksum(self.src_port(), port);
         self.data[self.layer4_offset..=self.layer4_offset + 1].copy_from_slice(&port.to_be_bytes());
     }
 
@@ -83,7 +213,9 @@ impl TcpSegment {
         )
     }
 
+    /// Sets the destination port, updating the checksum incrementally.
     pub fn set_dest_port(&mut self, port: u16) {
+        self.update_checksum(self.dest_port(), port);
         self.data[self.layer4_offset + 2..=self.layer4_offset + 3]
             .copy_from_slice(&port.to_be_bytes());
     }
@@ -120,9 +252,9 @@ impl TcpSegment {
 
     /// Data offset is the value wanted in BYTES
     pub fn set_data_offset(&mut self, data_offset: usize) {
-        self.data[self.layer4_offset + 12] &= 0xF0;
+        self.data[self.layer4_offset + 12] &= 0x0F;
         self.data[self.layer4_offset + 12] |= (((data_offset / 4) << 4) & 0xF0) as u8;
-        self.payload_offset = data_offset;
+        self.payload_offset = self.layer4_offset + data_offset;
     }
 
     /// Returns the 9 control bits as a u16, the 9 least significant bits
@@ -169,6 +301,14 @@ impl TcpSegment {
             .copy_from_slice(&checksum.to_be_bytes());
     }
 
+    /// Updates the checksum for a 16 bit word of the segment changing from `old` to `new`.
+    fn update_checksum(&mut self, old: u16, new: u16) {
+        let checksum_at = self.layer4_offset + 16;
+        update_checksum_at(&mut self.data, checksum_at, false, |checksum| {
+            update_u16(checksum, old, new)
+        });
+    }
+
     pub fn urgent_pointer(&self) -> u16 {
         u16::from_be_bytes(
             self.data[self.layer4_offset + 18..=self.layer4_offset + 19]
@@ -186,9 +326,13 @@ impl TcpSegment {
         if self.data_offset() <= 5 {
             return None;
         }
-        Some(Cow::from(
-            &self.data[self.layer4_offset + 20..self.payload_offset],
-        ))
+        self.options_bytes().map(Cow::from)
+    }
+
+    /// Returns the bytes of the options, unless the data offset puts them outside the segment,
+    /// short of the fixed header or past the end of the data.
+    fn options_bytes(&self) -> Option<&[u8]> {
+        self.data.get(self.layer4_offset + 20..self.payload_offset)
     }
 
     /// Sets the options of the tcp segment to the provided array, also
@@ -204,6 +348,93 @@ impl TcpSegment {
         self.set_data_offset(options.len() + 20);
     }
 
+    /// Returns an iterator over the options of the segment, which yields none if the data offset
+    /// puts them outside the segment.
+    pub fn tcp_options(&self) -> TcpOptions<'_> {
+        TcpOptions {
+            options: self.options_bytes().unwrap_or(&[]),
+            offset: 0,
+        }
+    }
+
+    /// Returns the first option of `kind`.
+    pub fn option(&self, kind: u8) -> Option<TcpOption> {
+        self.tcp_options().find(|option| option.kind() == kind)
+    }
+
+    /// Returns the maximum segment size option's value, if the segment has one.
+    pub fn mss(&self) -> Option<u16> {
+        match self.option(TCP_OPTION_MSS)? {
+            TcpOption::MaximumSegmentSize(mss) => Some(mss),
+            _ => None,
+        }
+    }
+
+    /// Replaces the options with `options`, padded with end of option list bytes to a multiple
+    /// of 32 bits, and sets the data offset to match. Fails, leaving the segment as it was, if
+    /// they don't fit in the 40 bytes a header has room for, or the data offset puts the current
+    /// ones outside the segment.
+    /// As with set_options, the caller should update the IP length field, and the checksum.
+    pub fn set_tcp_options(&mut self, options: &[TcpOption]) -> Result<(), &'static str> {
+        if self.options_bytes().is_none() {
+            return Err("TCP data offset is outside the segment");
+        }
+        let mut bytes: Vec<u8> = options.iter().flat_map(TcpOption::to_bytes).collect();
+        if bytes.len() > MAX_OPTIONS_LEN {
+            return Err("TCP options must fit in 40 bytes");
+        }
+        bytes.resize(bytes.len().div_ceil(4) * 4, TCP_OPTION_END);
+        self.set_options(&bytes);
+        Ok(())
+    }
+
+    /// Replaces the first option of the same kind as `option`, or adds it after the others if
+    /// there isn't one. An option the same length as the one it replaces is rewritten in place,
+    /// keeping the layout of the others, so the header length doesn't change, as when clamping
+    /// the MSS. Otherwise the options are rewritten as by set_tcp_options.
+    pub fn set_option(&mut self, option: TcpOption) -> Result<(), &'static str> {
+        let bytes = option.to_bytes();
+        let options_offset = self.layer4_offset + 20;
+        let mut spans = self.tcp_options();
+        while let Some((start, end)) = spans.next_span() {
+            if self.data[options_offset + start] != option.kind() {
+                continue;
+            }
+            if end - start == bytes.len() {
+                self.data[options_offset + start..options_offset + end].copy_from_slice(&bytes);
+                return Ok(());
+            }
+            break;
+        }
+
+        let mut replaced = false;
+        let mut options: Vec<TcpOption> = self
+            .tcp_options()
+            .map(|existing| {
+                if !replaced && existing.kind() == option.kind() {
+                    replaced = true;
+                    option.clone()
+                } else {
+                    existing
+                }
+            })
+            .collect();
+        if !replaced {
+            options.push(option);
+        }
+        self.set_tcp_options(&options)
+    }
+
+    /// Removes the first option of `kind`, rewriting the rest as by set_tcp_options, and
+    /// returns it, if there is one.
+    pub fn remove_option(&mut self, kind: u8) -> Option<TcpOption> {
+        let mut options: Vec<TcpOption> = self.tcp_options().collect();
+        let index = options.iter().position(|option| option.kind() == kind)?;
+        let removed = options.remove(index);
+        self.set_tcp_options(&options).unwrap();
+        Some(removed)
+    }
+
     pub fn payload(&self) -> Cow<[u8]> {
         Cow::from(&self.data[self.payload_offset..])
     }
@@ -258,6 +489,19 @@ impl TryFrom<Ipv6Packet> for TcpSegment {
     }
 }
 
+/// Takes the segment an Ethernet frame carries, in either an IPv4 or an IPv6 packet.
+impl TryFrom<EthernetFrame> for TcpSegment {
+    type Error = &'static str;
+
+    fn try_from(frame: EthernetFrame) -> Result<Self, Self::Error> {
+        match frame.ether_type() {
+            IPV4_ETHER_TYPE => TcpSegment::try_from(Ipv4Packet::try_from(frame)?),
+            IPV6_ETHER_TYPE => TcpSegment::try_from(Ipv6Packet::try_from(frame)?),
+            _ => Err("Frame does not carry an IP Packet"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -265,7 +509,9 @@ mod tests {
 
     #[test]
     fn tcp_segment() {
-        let mac_data: Vec<u8> = vec![0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0, 0];
+        let mac_data: Vec<u8> = vec![
+            0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0x08, 0x00,
+        ];
         let ipv4_data: Vec<u8> = vec![
             0x45, 0, 0, 20, 0, 0, 0, 0, 64, 6, 0, 0, 192, 178, 128, 0, 10, 0, 0, 1,
         ];
@@ -274,7 +520,7 @@ mod tests {
             2, 3, 4, 5, 6, 7, 8, 9, 10,
         ];
 
-        let mut frame = EthernetFrame::from_buffer(mac_data, 0).unwrap();
+        let mut frame = EthernetFrame::from_buffer(mac_data.into(), 0).unwrap();
         frame.set_payload(&ipv4_data);
         let mut packet = Ipv4Packet::try_from(frame).unwrap();
         packet.set_payload(&tcp_data);
@@ -302,4 +548,148 @@ mod tests {
         assert_eq!(empty_segment.layer4_offset, 0);
         assert_eq!(empty_segment.payload_offset, 20);
     }
+
+    /// The options of a Linux SYN: MSS, SACK permitted, timestamps, a no-op and window scale.
+    const SYN_OPTIONS: [u8; 20] = [
+        2, 4, 0x05, 0xb4, 4, 2, 8, 10, 0, 0, 0, 1, 0, 0, 0, 0, 1, 3, 3, 7,
+    ];
+
+    /// A SYN carrying SYN_OPTIONS and a short payload, in an IPv4 packet, so its header doesn't
+    /// start the buffer.
+    fn syn() -> TcpSegment {
+        let mut segment = TcpSegment::empty();
+        segment.set_options(&SYN_OPTIONS);
+        segment.set_payload(&[0xaa, 0xbb]);
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(6);
+        packet.set_payload(&segment.data);
+        TcpSegment::try_from(packet).unwrap()
+    }
+
+    #[test]
+    fn parses_options() {
+        let segment = syn();
+        assert_eq!(segment.layer4_offset, 20);
+        assert_eq!(segment.data_offset(), 10);
+        assert_eq!(
+            segment.tcp_options().collect::<Vec<_>>(),
+            vec![
+                TcpOption::MaximumSegmentSize(1460),
+                TcpOption::SackPermitted,
+                TcpOption::Timestamps {
+                    value: 1,
+                    echo_reply: 0
+                },
+                TcpOption::WindowScale(7),
+            ]
+        );
+        assert_eq!(segment.mss(), Some(1460));
+        assert_eq!(
+            segment.option(TCP_OPTION_WINDOW_SCALE),
+            Some(TcpOption::WindowScale(7))
+        );
+        assert_eq!(segment.option(TCP_OPTION_SACK), None);
+        assert_eq!(TcpSegment::empty().tcp_options().count(), 0);
+    }
+
+    #[test]
+    fn data_offset_outside_segment() {
+        // Claims 60 bytes of header in a 20 byte segment, then less than the fixed header.
+        for data_offset in [15, 2].iter() {
+            let mut data = vec![0; 20];
+            data[12] = data_offset << 4;
+            let mut segment = TcpSegment::from_buffer(data.into(), None, None, 0).unwrap();
+            assert_eq!(segment.options(), None);
+            assert_eq!(segment.tcp_options().count(), 0);
+            assert_eq!(segment.mss(), None);
+            assert_eq!(segment.remove_option(TCP_OPTION_MSS), None);
+            assert!(segment
+                .set_option(TcpOption::MaximumSegmentSize(1400))
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn ends_options() {
+        let mut segment = TcpSegment::empty();
+        // An end of option list, then options that should be ignored.
+        segment.set_options(&[1, 3, 3, 2, 0, 2, 4, 5]);
+        assert_eq!(
+            segment.tcp_options().collect::<Vec<_>>(),
+            vec![TcpOption::WindowScale(2)]
+        );
+
+        // A SACK option with a length past the end of the options.
+        segment.set_options(&[1, 1, 5, 10, 0, 0, 0, 1]);
+        assert_eq!(segment.tcp_options().count(), 0);
+
+        // Known kinds with the wrong length are kept as they are.
+        segment.set_options(&[2, 3, 1, 0]);
+        assert_eq!(
+            segment.tcp_options().collect::<Vec<_>>(),
+            vec![TcpOption::Other {
+                kind: 2,
+                data: vec![1]
+            }]
+        );
+    }
+
+    #[test]
+    fn clamps_mss_in_place() {
+        let mut segment = syn();
+        segment
+            .set_option(TcpOption::MaximumSegmentSize(1400))
+            .unwrap();
+        assert_eq!(segment.mss(), Some(1400));
+        assert_eq!(segment.data_offset(), 10);
+        let options = segment.options().unwrap();
+        assert_eq!(options[..4], [2, 4, 0x05, 0x78]);
+        assert_eq!(options[4..], SYN_OPTIONS[4..]);
+        assert_eq!(segment.payload()[..], [0xaa, 0xbb]);
+    }
+
+    #[test]
+    fn rewrites_options() {
+        let mut segment = syn();
+        let sack = TcpOption::Sack(vec![(100, 200), (300, 400)]);
+        segment.set_option(sack.clone()).unwrap();
+        assert_eq!(segment.option(TCP_OPTION_SACK), Some(sack.clone()));
+        // 4 + 2 + 10 + 3 + 18 bytes of options, padded to 40.
+        assert_eq!(segment.data_offset(), 15);
+        assert_eq!(segment.payload_offset, segment.layer4_offset + 60);
+        assert_eq!(segment.payload()[..], [0xaa, 0xbb]);
+
+        // A SACK of one more block doesn't fit.
+        let mut blocks = vec![(100, 200), (300, 400), (500, 600)];
+        assert!(segment.set_option(TcpOption::Sack(blocks.clone())).is_err());
+        assert_eq!(segment.option(TCP_OPTION_SACK), Some(sack.clone()));
+
+        assert_eq!(
+            segment.remove_option(TCP_OPTION_TIMESTAMPS),
+            Some(TcpOption::Timestamps {
+                value: 1,
+                echo_reply: 0
+            })
+        );
+        assert_eq!(segment.remove_option(TCP_OPTION_TIMESTAMPS), None);
+        blocks.truncate(1);
+        segment.set_option(TcpOption::Sack(blocks.clone())).unwrap();
+        assert_eq!(
+            segment.tcp_options().collect::<Vec<_>>(),
+            vec![
+                TcpOption::MaximumSegmentSize(1460),
+                TcpOption::SackPermitted,
+                TcpOption::WindowScale(7),
+                TcpOption::Sack(blocks),
+            ]
+        );
+        assert_eq!(segment.data_offset(), 10);
+        assert_eq!(segment.options().unwrap()[19..], [TCP_OPTION_END]);
+        assert_eq!(segment.payload()[..], [0xaa, 0xbb]);
+
+        segment.set_tcp_options(&[]).unwrap();
+        assert_eq!(segment.data_offset(), 5);
+        assert_eq!(segment.options(), None);
+        assert_eq!(segment.payload()[..], [0xaa, 0xbb]);
+    }
 }
@@ -1,12 +1,114 @@
 // Let's use this area for now to declare common structs, constants, and common helper functions.
 use std::fmt;
+use std::net::IpAddr;
 
 pub const IPV4_ETHER_TYPE: u16 = 0x0800;
 pub const IPV6_ETHER_TYPE: u16 = 0x86DD;
 pub const ARP_ETHER_TYPE: u16 = 0x0806;
+pub const VLAN_ETHER_TYPE: u16 = 0x8100;
+pub const QINQ_ETHER_TYPE: u16 = 0x88A8;
+pub const MPLS_ETHER_TYPE: u16 = 0x8847;
+pub const PPPOE_DISCOVERY_ETHER_TYPE: u16 = 0x8863;
+pub const PPPOE_SESSION_ETHER_TYPE: u16 = 0x8864;
+/// Transparent Ethernet Bridging, the protocol type of Ethernet frames tunneled in GRE or GENEVE.
+pub const ETHERNET_BRIDGING_ETHER_TYPE: u16 = 0x6558;
 
-/// The common datatype that all packet structures share to repreasent their data
-pub type PacketData = Vec<u8>;
+/// Returns the internet checksum, per RFC 1071, of `parts` as though they were one buffer. Every
+/// part but the last should be an even number of bytes long.
+pub fn internet_checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum =
+        parts
+            .iter()
+            .flat_map(|part| part.chunks(2))
+            .fold(0, |acc: u32, word| match word {
+                [high, low] => acc + u32::from(u16::from_be_bytes([*high, *low])),
+                _ => acc + u32::from(u16::from_be_bytes([word[0], 0])),
+            });
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Updates `checksum` for a 16 bit word of the checksummed data changing from `old` to `new`,
+/// without summing the rest of the data again, per RFC 1624.
+pub fn update_u16(checksum: u16, old: u16, new: u16) -> u16 {
+    let mut sum = u32::from(!checksum) + u32::from(!old) + u32::from(new);
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Updates `checksum` for two 16 bit words of the checksummed data changing from `old` to `new`.
+pub fn update_u32(checksum: u16, old: u32, new: u32) -> u16 {
+    let checksum = update_u16(checksum, (old >> 16) as u16, (new >> 16) as u16);
+    update_u16(checksum, old as u16, new as u16)
+}
+
+/// Updates `checksum` for an address in the checksummed data, such as one in a pseudo-header,
+/// changing from `old` to `new`. The addresses must be of the same family.
+pub fn update_addr<A: Into<IpAddr>>(checksum: u16, old: A, new: A) -> u16 {
+    match (old.into(), new.into()) {
+        (IpAddr::V4(old), IpAddr::V4(new)) => update_u32(checksum, old.into(), new.into()),
+        (IpAddr::V6(old), IpAddr::V6(new)) => old
+            .segments()
+            .iter()
+            .zip(new.segments().iter())
+            .fold(checksum, |checksum, (old, new)| {
+                update_u16(checksum, *old, *new)
+            }),
+        _ => panic!("update_addr old and new must be the same family"),
+    }
+}
+
+/// Returns the internet checksum of an upper layer `segment` of `protocol`, carried from `src` to
+/// `dest`, over it and the IP pseudo-header, as TCP, UDP and ICMPv6 checksums are. The addresses
+/// must be of the same family.
+pub fn pseudo_header_checksum<A: Into<IpAddr>>(
+    src: A,
+    dest: A,
+    protocol: u8,
+    segment: &[u8],
+) -> u16 {
+    match (src.into(), dest.into()) {
+        (IpAddr::V4(src), IpAddr::V4(dest)) => internet_checksum(&[
+            &src.octets(),
+            &dest.octets(),
+            &[0, protocol],
+            &(segment.len() as u16).to_be_bytes(),
+            segment,
+        ]),
+        (IpAddr::V6(src), IpAddr::V6(dest)) => internet_checksum(&[
+            &src.octets(),
+            &dest.octets(),
+            &(segment.len() as u32).to_be_bytes(),
+            &[0, 0, 0, protocol],
+            segment,
+        ]),
+        _ => panic!("pseudo_header_checksum src and dest must be the same family"),
+    }
+}
+
+/// Updates the layer 4 checksum at `checksum_at` in `data` with `update`. A UDP checksum of zero
+/// means there is none, so it's left alone, and an updated UDP checksum of zero is sent as all
+/// ones.
+pub(crate) fn update_checksum_at(
+    data: &mut [u8],
+    checksum_at: usize,
+    udp: bool,
+    update: impl FnOnce(u16) -> u16,
+) {
+    let checksum = u16::from_be_bytes([data[checksum_at], data[checksum_at + 1]]);
+    if udp && checksum == 0 {
+        return;
+    }
+    let checksum = match update(checksum) {
+        0 if udp => 0xFFFF,
+        checksum => checksum,
+    };
+    data[checksum_at..checksum_at + 2].copy_from_slice(&checksum.to_be_bytes());
+}
 
 // Most significant byte is 0th
 #[derive(Eq, Clone, Copy, Hash, PartialEq, Debug)]
@@ -29,12 +29,10 @@ impl<'packet> UdpSegment {
             let ip_version = (data[layer3_offset] & 0xF0) >> 4;
             match ip_version {
                 4 => {
-                    protocol = get_ipv4_payload_type(&data, layer3_offset)
-                        .expect("Malformed IPv4 Header in UdpSegment");
+                    protocol = get_ipv4_payload_type(&data, layer3_offset)?;
                 }
                 6 => {
-                    protocol = get_ipv6_payload_type(&data, layer3_offset)
-                        .expect("Malformed IPv6 Header in UdpSegment");
+                    protocol = get_ipv6_payload_type(&data, layer3_offset)?;
                 }
                 _ => {
                     return Err("IP Header has invalid version number");
@@ -70,7 +68,7 @@ impl<'packet> UdpSegment {
         let mut data = vec![];
         data.resize(8, 0);
         data[5] = 8; //Set length field.
-        UdpSegment::from_buffer(data, None, None, 0).unwrap()
+        UdpSegment::from_buffer(data.into(), None, None, 0).unwrap()
     }
 
     pub fn src_port(&self) -> u16 {
@@ -81,7 +79,9 @@ impl<'packet> UdpSegment {
         )
     }
 
+    /// Sets the source port, updating the checksum incrementally.
     pub fn set_src_port(&mut self, port: u16) {
+        self.update_checksum(self.src_port(), port);
         self.data[self.layer4_offset..=self.layer4_offset + 1].copy_from_slice(&port.to_be_bytes());
     }
 
@@ -93,7 +93,9 @@ impl<'packet> UdpSegment {
         )
     }
 
+    /// Sets the destination port, updating the checksum incrementally.
     pub fn set_dest_port(&mut self, port: u16) {
+        self.update_checksum(self.dest_port(), port);
         self.data[self.layer4_offset + 2..=self.layer4_offset + 3]
             .copy_from_slice(&port.to_be_bytes());
     }
@@ -121,6 +123,15 @@ impl<'packet> UdpSegment {
             .copy_from_slice(&checksum.to_be_bytes())
     }
 
+    /// Updates the checksum for a 16 bit word of the segment changing from `old` to `new`. A
+    /// checksum of zero means there is none, and is left alone.
+    fn update_checksum(&mut self, old: u16, new: u16) {
+        let checksum_at = self.layer4_offset + 6;
+        update_checksum_at(&mut self.data, checksum_at, true, |checksum| {
+            update_u16(checksum, old, new)
+        });
+    }
+
     pub fn payload(&self) -> Cow<[u8]> {
         Cow::from(&self.data[self.layer4_offset + 8..])
     }
@@ -172,6 +183,19 @@ impl TryFrom<Ipv6Packet> for UdpSegment {
     }
 }
 
+/// Takes the segment an Ethernet frame carries, in either an IPv4 or an IPv6 packet.
+impl TryFrom<EthernetFrame> for UdpSegment {
+    type Error = &'static str;
+
+    fn try_from(frame: EthernetFrame) -> Result<Self, Self::Error> {
+        match frame.ether_type() {
+            IPV4_ETHER_TYPE => UdpSegment::try_from(Ipv4Packet::try_from(frame)?),
+            IPV6_ETHER_TYPE => UdpSegment::try_from(Ipv6Packet::try_from(frame)?),
+            _ => Err("Frame does not carry an IP Packet"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -179,7 +203,9 @@ mod tests {
 
     #[test]
     fn udp_segment() {
-        let mac_data: Vec<u8> = vec![0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0, 0];
+        let mac_data: Vec<u8> = vec![
+            0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0x08, 0x00,
+        ];
         let ipv4_data: Vec<u8> = vec![
             0x45, 0, 0, 20, 0, 0, 0, 0, 64, 17, 0, 0, 192, 178, 128, 0, 10, 0, 0, 1,
         ];
@@ -187,7 +213,7 @@ mod tests {
             0, 99, 0, 88, 0, 19, 0xDE, 0xAD, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10,
         ];
 
-        let mut frame = EthernetFrame::from_buffer(mac_data, 0).unwrap();
+        let mut frame = EthernetFrame::from_buffer(mac_data.into(), 0).unwrap();
         frame.set_payload(&ipv4_data);
         let mut packet = Ipv4Packet::try_from(frame).unwrap();
         packet.set_payload(&udp_data);
@@ -201,6 +227,20 @@ mod tests {
         assert_eq!(segment.payload()[0], 0);
     }
 
+    #[test]
+    fn set_ports() {
+        let mut segment = UdpSegment::empty();
+        segment.set_src_port(1000);
+        // A checksum of zero means there is none, so is left as it is.
+        assert_eq!(segment.checksum(), 0);
+
+        segment.set_checksum(internet_checksum(&[&segment.data]));
+        segment.set_dest_port(2000);
+        let checksum = segment.checksum();
+        segment.set_checksum(0);
+        assert_eq!(checksum, internet_checksum(&[&segment.data]));
+    }
+
     #[test]
     fn empty() {
         let empty_segment = UdpSegment::empty();
@@ -0,0 +1,126 @@
+use crate::*;
+use std::borrow::Cow;
+use std::convert::TryFrom;
+
+/// The UDP port IANA assigned to VXLAN.
+pub const VXLAN_PORT: u16 = 4789;
+
+/// The VXLAN flag marking the VNI valid.
+const VNI_VALID: u8 = 0x08;
+
+const HEADER_LEN: usize = 8;
+
+/// A VXLAN packet, per RFC 7348, as carried in the payload of a UDP segment: the 8 byte VXLAN
+/// header, then the Ethernet frame it tunnels. The data is the packet alone, without the headers
+/// of the segment it came in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VxlanPacket {
+    pub data: PacketData,
+}
+
+impl VxlanPacket {
+    pub fn from_buffer(data: PacketData) -> Result<VxlanPacket, &'static str> {
+        if data.len() < HEADER_LEN {
+            return Err("Data is too short to be a VxlanPacket");
+        }
+        Ok(VxlanPacket { data })
+    }
+
+    /// Makes a VxlanPacket tunneling `frame` on segment `vni`, which must be less than 2^24.
+    pub fn new(vni: u32, frame: &EthernetFrame) -> VxlanPacket {
+        let mut packet = VxlanPacket::from_buffer(vec![0; HEADER_LEN].into()).unwrap();
+        packet.set_vni(Some(vni));
+        packet.set_payload(&frame.data[frame.layer2_offset..]);
+        packet
+    }
+
+    /// The VXLAN Network Identifier of the segment the frame is on, if it's marked valid.
+    pub fn vni(&self) -> Option<u32> {
+        if self.data[0] & VNI_VALID == 0 {
+            return None;
+        }
+        Some(u32::from_be_bytes([
+            0,
+            self.data[4],
+            self.data[5],
+            self.data[6],
+        ]))
+    }
+
+    /// Sets the VNI, which must be less than 2^24, and marks it valid, or invalid if None.
+    pub fn set_vni(&mut self, vni: Option<u32>) {
+        match vni {
+            Some(vni) => {
+                assert!(vni < 1 << 24, "VxlanPacket vni: {}, must be < 2^24", vni);
+                self.data[0] |= VNI_VALID;
+                self.data[4..7].copy_from_slice(&vni.to_be_bytes()[1..]);
+            }
+            None => {
+                self.data[0] &= !VNI_VALID;
+                self.data[4..7].copy_from_slice(&[0; 3]);
+            }
+        }
+    }
+
+    pub fn payload(&self) -> Cow<[u8]> {
+        Cow::from(&self.data[HEADER_LEN..])
+    }
+
+    /// Sets the payload. Don't forget to update the length field of the UDP segment that contains
+    /// this.
+    pub fn set_payload(&mut self, payload: &[u8]) {
+        self.data.truncate(HEADER_LEN);
+        self.data.reserve_exact(payload.len());
+        self.data.extend(payload);
+    }
+
+    /// The Ethernet frame the packet tunnels.
+    pub fn inner_frame(&self) -> Result<EthernetFrame, &'static str> {
+        EthernetFrame::from_buffer(self.data.slice(HEADER_LEN..), 0)
+    }
+}
+
+impl TryFrom<UdpSegment> for VxlanPacket {
+    type Error = &'static str;
+
+    fn try_from(mut segment: UdpSegment) -> Result<Self, Self::Error> {
+        VxlanPacket::from_buffer(segment.data.split_off(segment.payload_offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vxlan_packet() {
+        let mut inner = Ipv4Packet::empty();
+        inner.set_payload(&[1, 2, 3]);
+        let frame = EthernetFrame::encap_ipv4(inner);
+
+        let packet = VxlanPacket::new(5001, &frame);
+        assert_eq!(packet.data[..8], [0x08, 0, 0, 0, 0, 0x13, 0x89, 0]);
+        assert_eq!(packet.vni(), Some(5001));
+        assert_eq!(packet.inner_frame().unwrap(), frame);
+
+        let mut segment = UdpSegment::empty();
+        segment.set_dest_port(VXLAN_PORT);
+        segment.set_payload(&packet.data);
+        let mut packet = VxlanPacket::try_from(segment).unwrap();
+        assert_eq!(packet.vni(), Some(5001));
+
+        packet.set_vni(None);
+        assert_eq!(packet.vni(), None);
+        assert_eq!(packet.data[..8], [0; 8]);
+    }
+
+    #[test]
+    fn short_packets() {
+        assert!(VxlanPacket::from_buffer(vec![0x08, 0, 0, 0, 0, 0, 1].into()).is_err());
+
+        // A header with no frame after it is a VxlanPacket, but has no inner frame.
+        let packet = VxlanPacket::from_buffer(vec![0x08, 0, 0, 0, 0, 0, 1, 0].into()).unwrap();
+        assert_eq!(packet.vni(), Some(1));
+        assert!(packet.inner_frame().is_err());
+    }
+}
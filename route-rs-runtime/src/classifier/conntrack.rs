@@ -0,0 +1,564 @@
+use crate::classifier::Classifier;
+use route_rs_packets::{FlowKey, Ipv4Packet};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const TCP: u8 = 6;
+const UDP: u8 = 17;
+const ICMP: u8 = 1;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_ACK: u8 = 0x10;
+
+/// How a packet relates to the connections in a ConntrackTable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ConnState {
+    /// The packet starts a connection, or belongs to one that has had no reply yet.
+    New,
+    /// The packet belongs to a connection that has seen traffic both ways.
+    Established,
+    /// The packet is an ICMP error about a tracked connection.
+    Related,
+    /// The packet can't be tracked, or doesn't fit the state of its connection, like a TCP
+    /// segment that is not a SYN but has no connection.
+    Invalid,
+}
+
+/// Identifies a connection by the protocol, addresses and ports of the packets of one
+/// direction. ICMP queries use their identifier as the port of the querier, and 0 for the other
+/// side. Protocols without ports have 0 for both.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ConnTuple {
+    pub protocol: u8,
+    pub src: Ipv4Addr,
+    pub src_port: u16,
+    pub dst: Ipv4Addr,
+    pub dst_port: u16,
+}
+
+impl ConnTuple {
+    /// Returns the tuple of the packet, if it can be tracked. Fragments other than the first
+    /// have no ports, so can't be.
+    pub fn from_packet(packet: &Ipv4Packet) -> Option<ConnTuple> {
+        parse_tuple(&packet.data, packet.layer3_offset)
+    }
+
+    /// Returns the tuple of packets in the other direction of the connection.
+    pub fn reverse(&self) -> ConnTuple {
+        ConnTuple {
+            protocol: self.protocol,
+            src: self.dst,
+            src_port: self.dst_port,
+            dst: self.src,
+            dst_port: self.src_port,
+        }
+    }
+}
+
+/// Reads the tuple of the IPv4 header at `layer3`, needing no more than the first 8 bytes of its
+/// payload, as quoted by ICMP errors.
+fn parse_tuple(data: &[u8], layer3: usize) -> Option<ConnTuple> {
+    let flow = FlowKey::from_packet(data, layer3)?;
+    let fragment_offset = u16::from_be_bytes([data[layer3 + 6], data[layer3 + 7]]) & 0x1FFF;
+    if fragment_offset != 0 {
+        return None;
+    }
+
+    match flow {
+        FlowKey {
+            protocol,
+            src: IpAddr::V4(src),
+            src_port,
+            dst: IpAddr::V4(dst),
+            dst_port,
+        } => Some(ConnTuple {
+            protocol,
+            src,
+            src_port,
+            dst,
+            dst_port,
+        }),
+        _ => None,
+    }
+}
+
+/// Where a TCP connection is in its life, as far as can be told from the middle of it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TcpState {
+    /// The originator has sent a SYN.
+    SynSent,
+    /// The responder has answered with a SYN ACK.
+    SynReceived,
+    Established,
+    /// One side has sent a FIN.
+    Closing,
+    /// Both sides have sent a FIN, or one an RST.
+    Closed,
+}
+
+/// A snapshot of a tracked connection.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Connection {
+    /// The tuple of the packet that started the connection.
+    pub original: ConnTuple,
+    /// Whether there has been traffic in the reply direction.
+    pub replied: bool,
+    /// The TCP state, for TCP connections.
+    pub tcp_state: Option<TcpState>,
+    /// Set by other processors, for instance to remember a firewall decision.
+    pub mark: u32,
+    last_seen: Instant,
+    /// FINs seen from the original and reply directions.
+    fins: (bool, bool),
+}
+
+/// Timeouts for unused connections, following Linux's nf_conntrack defaults.
+#[derive(Clone)]
+struct Timeouts {
+    tcp_handshake: Duration,
+    tcp_established: Duration,
+    tcp_closing: Duration,
+    tcp_closed: Duration,
+    udp: Duration,
+    udp_replied: Duration,
+    icmp: Duration,
+    other: Duration,
+}
+
+/// The table of connections shared by Conntrack classifiers, and other processors, like a
+/// firewall or NAT, that want to consult or annotate it. Connections are forgotten once unused
+/// for the timeout of their protocol and state. Cloning it gives another handle to the same
+/// table.
+#[derive(Clone)]
+pub struct ConntrackTable {
+    timeouts: Timeouts,
+    max_connections: usize,
+    connections: Arc<Mutex<HashMap<ConnTuple, Connection>>>,
+}
+
+impl Default for ConntrackTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConntrackTable {
+    pub fn new() -> Self {
+        ConntrackTable {
+            timeouts: Timeouts {
+                tcp_handshake: Duration::from_secs(120),
+                tcp_established: Duration::from_secs(432_000),
+                tcp_closing: Duration::from_secs(120),
+                tcp_closed: Duration::from_secs(10),
+                udp: Duration::from_secs(30),
+                udp_replied: Duration::from_secs(180),
+                icmp: Duration::from_secs(30),
+                other: Duration::from_secs(600),
+            },
+            max_connections: 65536,
+            connections: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Changes how long an established TCP connection lasts unused, default 5 days.
+    pub fn tcp_established_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.tcp_established = timeout;
+        self
+    }
+
+    /// Changes how long a UDP flow lasts unused, default 30 seconds, or 180 once it has had a
+    /// reply.
+    pub fn udp_timeouts(mut self, timeout: Duration, replied_timeout: Duration) -> Self {
+        self.timeouts.udp = timeout;
+        self.timeouts.udp_replied = replied_timeout;
+        self
+    }
+
+    /// Changes how long an ICMP query lasts unanswered, default 30 seconds.
+    pub fn icmp_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.icmp = timeout;
+        self
+    }
+
+    /// Changes the most connections tracked at once, default 65536. When full, packets starting
+    /// new connections are INVALID.
+    pub fn max_connections(self, max_connections: usize) -> Self {
+        assert!(
+            max_connections > 0,
+            "ConntrackTable max_connections: {}, must be > 0",
+            max_connections
+        );
+
+        ConntrackTable {
+            max_connections,
+            ..self
+        }
+    }
+
+    /// Tracks the packet, updating its connection, and returns how it relates to it.
+    pub fn track(&self, packet: &Ipv4Packet) -> ConnState {
+        self.track_at(packet, Instant::now())
+    }
+
+    fn track_at(&self, packet: &Ipv4Packet, now: Instant) -> ConnState {
+        let tuple = match ConnTuple::from_packet(packet) {
+            Some(tuple) => tuple,
+            None => return ConnState::Invalid,
+        };
+        let layer4 = match packet.data.get(packet.payload_offset..) {
+            Some(layer4) => layer4,
+            None => return ConnState::Invalid,
+        };
+        let mut connections = self.connections.lock().unwrap();
+
+        if tuple.protocol == ICMP && is_icmp_error(layer4) {
+            return match parse_tuple(layer4, 8) {
+                Some(quoted) if self.find(&mut connections, &quoted, now).is_some() => {
+                    ConnState::Related
+                }
+                _ => ConnState::Invalid,
+            };
+        }
+
+        let tcp_flags = match (tuple.protocol, layer4.get(13)) {
+            (TCP, Some(flags)) => Some(*flags),
+            (TCP, None) => return ConnState::Invalid,
+            _ => None,
+        };
+
+        let (key, is_reply) = match self.find(&mut connections, &tuple, now) {
+            Some(found) => found,
+            None => {
+                let starts_connection = match tcp_flags {
+                    Some(flags) => flags & (TCP_SYN | TCP_ACK | TCP_RST) == TCP_SYN,
+                    // An ICMP reply can't start a connection.
+                    None => !(tuple.protocol == ICMP && tuple.src_port == 0 && tuple.dst_port != 0),
+                };
+                if !starts_connection {
+                    return ConnState::Invalid;
+                }
+                if connections.len() >= self.max_connections {
+                    let timeouts = &self.timeouts;
+                    connections.retain(|_, connection| !is_expired(timeouts, connection, now));
+                    if connections.len() >= self.max_connections {
+                        return ConnState::Invalid;
+                    }
+                }
+                connections.insert(
+                    tuple,
+                    Connection {
+                        original: tuple,
+                        replied: false,
+                        tcp_state: tcp_flags.map(|_| TcpState::SynSent),
+                        mark: 0,
+                        last_seen: now,
+                        fins: (false, false),
+                    },
+                );
+                return ConnState::New;
+            }
+        };
+
+        let connection = connections.get_mut(&key).unwrap();
+        if let Some(flags) = tcp_flags {
+            match next_tcp_state(connection, flags, is_reply) {
+                Some(state) => connection.tcp_state = Some(state),
+                None => return ConnState::Invalid,
+            }
+        }
+        connection.last_seen = now;
+        connection.replied |= is_reply;
+        if connection.replied {
+            ConnState::Established
+        } else {
+            ConnState::New
+        }
+    }
+
+    /// Finds the connection of a packet with `tuple`, in either direction, returning its key
+    /// and whether the packet is a reply. Expired connections are removed, rather than found.
+    fn find(
+        &self,
+        connections: &mut HashMap<ConnTuple, Connection>,
+        tuple: &ConnTuple,
+        now: Instant,
+    ) -> Option<(ConnTuple, bool)> {
+        let reverse = tuple.reverse();
+        let (key, is_reply) = if connections.contains_key(tuple) {
+            (*tuple, false)
+        } else if connections.contains_key(&reverse) {
+            (reverse, true)
+        } else {
+            return None;
+        };
+
+        if is_expired(&self.timeouts, &connections[&key], now) {
+            connections.remove(&key);
+            return None;
+        }
+        Some((key, is_reply))
+    }
+
+    /// Returns the connection a packet with `tuple` belongs to, in either direction.
+    pub fn get(&self, tuple: &ConnTuple) -> Option<Connection> {
+        let mut connections = self.connections.lock().unwrap();
+        let (key, _) = self.find(&mut connections, tuple, Instant::now())?;
+        connections.get(&key).cloned()
+    }
+
+    /// Sets the mark of the connection a packet with `tuple` belongs to. Returns false if there
+    /// is no such connection.
+    pub fn set_mark(&self, tuple: &ConnTuple, mark: u32) -> bool {
+        let mut connections = self.connections.lock().unwrap();
+        match self.find(&mut connections, tuple, Instant::now()) {
+            Some((key, _)) => {
+                connections.get_mut(&key).unwrap().mark = mark;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forgets the connection a packet with `tuple` belongs to, returning it.
+    pub fn remove(&self, tuple: &ConnTuple) -> Option<Connection> {
+        let mut connections = self.connections.lock().unwrap();
+        connections
+            .remove(tuple)
+            .or_else(|| connections.remove(&tuple.reverse()))
+    }
+
+    /// Returns the number of connections, including any expired ones not yet cleaned up.
+    pub fn len(&self) -> usize {
+        self.connections.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn is_icmp_error(icmp: &[u8]) -> bool {
+    match icmp.first() {
+        Some(icmp_type) => [3, 4, 5, 11, 12].contains(icmp_type),
+        None => false,
+    }
+}
+
+fn is_expired(timeouts: &Timeouts, connection: &Connection, now: Instant) -> bool {
+    let timeout = match (connection.original.protocol, connection.tcp_state) {
+        (_, Some(TcpState::SynSent)) | (_, Some(TcpState::SynReceived)) => timeouts.tcp_handshake,
+        (_, Some(TcpState::Established)) => timeouts.tcp_established,
+        (_, Some(TcpState::Closing)) => timeouts.tcp_closing,
+        (_, Some(TcpState::Closed)) => timeouts.tcp_closed,
+        (UDP, None) if connection.replied => timeouts.udp_replied,
+        (UDP, None) => timeouts.udp,
+        (ICMP, None) => timeouts.icmp,
+        _ => timeouts.other,
+    };
+    now.duration_since(connection.last_seen) >= timeout
+}
+
+/// Returns the state a TCP connection moves to on a segment with `flags`, or None if the
+/// segment doesn't fit the connection.
+fn next_tcp_state(connection: &mut Connection, flags: u8, is_reply: bool) -> Option<TcpState> {
+    let state = connection.tcp_state.unwrap_or(TcpState::SynSent);
+    if flags & TCP_RST != 0 {
+        return Some(TcpState::Closed);
+    }
+    let syn = flags & TCP_SYN != 0;
+    let ack = flags & TCP_ACK != 0;
+
+    let state = match state {
+        // Retransmitted SYNs, or the SYN ACK.
+        TcpState::SynSent if syn && !is_reply => TcpState::SynSent,
+        TcpState::SynSent if syn && ack && is_reply => TcpState::SynReceived,
+        TcpState::SynSent => return None,
+        TcpState::SynReceived if syn && ack && is_reply => TcpState::SynReceived,
+        TcpState::SynReceived if ack && !syn && !is_reply => TcpState::Established,
+        TcpState::SynReceived => return None,
+        _ if syn => return None,
+        state => state,
+    };
+
+    if flags & TCP_FIN != 0 {
+        if is_reply {
+            connection.fins.1 = true;
+        } else {
+            connection.fins.0 = true;
+        }
+    }
+    Some(match connection.fins {
+        (true, true) => TcpState::Closed,
+        (true, false) | (false, true) => TcpState::Closing,
+        (false, false) => state,
+    })
+}
+
+/// Classifies IPv4 packets by their ConnState, tracking their connections in a ConntrackTable.
+/// Run in a ClassifyLink to handle each state differently, for instance to only let
+/// established traffic in.
+pub struct Conntrack {
+    table: ConntrackTable,
+}
+
+impl Conntrack {
+    pub fn new(table: ConntrackTable) -> Self {
+        Conntrack { table }
+    }
+}
+
+impl Classifier for Conntrack {
+    type Packet = Ipv4Packet;
+    type Class = ConnState;
+
+    fn classify(&self, packet: &Self::Packet) -> Self::Class {
+        self.table.track(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+
+    const CLIENT: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 10);
+    const SERVER: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 7);
+
+    fn packet(protocol: u8, src: Ipv4Addr, dst: Ipv4Addr, layer4: &[u8]) -> Ipv4Packet {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_ttl(64);
+        packet.set_protocol(protocol);
+        packet.set_src_addr(src);
+        packet.set_dest_addr(dst);
+        packet.set_payload(layer4);
+        packet.set_checksum();
+        packet
+    }
+
+    fn tcp(from_client: bool, flags: u8) -> Ipv4Packet {
+        let mut segment = vec![0; 20];
+        let (src, dst) = if from_client {
+            segment[0..4].copy_from_slice(&[0x9c, 0x40, 0, 80]);
+            (CLIENT, SERVER)
+        } else {
+            segment[0..4].copy_from_slice(&[0, 80, 0x9c, 0x40]);
+            (SERVER, CLIENT)
+        };
+        segment[12] = 5 << 4;
+        segment[13] = flags;
+        packet(TCP, src, dst, &segment)
+    }
+
+    fn udp(from_client: bool) -> Ipv4Packet {
+        if from_client {
+            packet(UDP, CLIENT, SERVER, &[0x9c, 0x40, 0, 53, 0, 8, 0, 0])
+        } else {
+            packet(UDP, SERVER, CLIENT, &[0, 53, 0x9c, 0x40, 0, 8, 0, 0])
+        }
+    }
+
+    #[test]
+    fn tcp_handshake_and_teardown() {
+        let conntrack = Conntrack::new(ConntrackTable::new());
+        assert_eq!(conntrack.classify(&tcp(true, TCP_SYN)), ConnState::New);
+        assert_eq!(conntrack.classify(&tcp(true, TCP_SYN)), ConnState::New);
+        assert_eq!(
+            conntrack.classify(&tcp(false, TCP_SYN | TCP_ACK)),
+            ConnState::Established
+        );
+        assert_eq!(
+            conntrack.classify(&tcp(true, TCP_ACK)),
+            ConnState::Established
+        );
+
+        let tuple = ConnTuple::from_packet(&tcp(false, TCP_ACK)).unwrap();
+        let connection = conntrack.table.get(&tuple).unwrap();
+        assert_eq!(connection.tcp_state, Some(TcpState::Established));
+        assert_eq!(connection.original, tuple.reverse());
+
+        conntrack.classify(&tcp(true, TCP_FIN | TCP_ACK));
+        assert_eq!(
+            conntrack.table.get(&tuple).unwrap().tcp_state,
+            Some(TcpState::Closing)
+        );
+        conntrack.classify(&tcp(false, TCP_FIN | TCP_ACK));
+        assert_eq!(
+            conntrack.table.get(&tuple).unwrap().tcp_state,
+            Some(TcpState::Closed)
+        );
+    }
+
+    #[test]
+    fn tcp_invalid() {
+        let conntrack = Conntrack::new(ConntrackTable::new());
+        assert_eq!(conntrack.classify(&tcp(true, TCP_ACK)), ConnState::Invalid);
+        assert_eq!(
+            conntrack.classify(&tcp(true, TCP_SYN | TCP_ACK)),
+            ConnState::Invalid
+        );
+        assert!(conntrack.table.is_empty());
+
+        conntrack.classify(&tcp(true, TCP_SYN));
+        // Data before the handshake completes.
+        assert_eq!(conntrack.classify(&tcp(false, TCP_ACK)), ConnState::Invalid);
+
+        let mut header_too_long = tcp(true, TCP_SYN);
+        header_too_long.payload_offset = header_too_long.data.len() + 4;
+        assert_eq!(conntrack.classify(&header_too_long), ConnState::Invalid);
+    }
+
+    #[test]
+    fn udp_and_expiry() {
+        let table = ConntrackTable::new()
+            .udp_timeouts(Duration::from_millis(20), Duration::from_millis(20));
+        let conntrack = Conntrack::new(table);
+        assert_eq!(conntrack.classify(&udp(true)), ConnState::New);
+        assert_eq!(conntrack.classify(&udp(false)), ConnState::Established);
+
+        sleep(Duration::from_millis(30));
+        assert_eq!(conntrack.classify(&udp(false)), ConnState::New);
+    }
+
+    #[test]
+    fn icmp_echo_and_errors() {
+        let conntrack = Conntrack::new(ConntrackTable::new());
+        let reply = packet(ICMP, SERVER, CLIENT, &[0, 0, 0, 0, 0, 7, 0, 1]);
+        assert_eq!(conntrack.classify(&reply), ConnState::Invalid);
+
+        let request = packet(ICMP, CLIENT, SERVER, &[8, 0, 0, 0, 0, 7, 0, 1]);
+        assert_eq!(conntrack.classify(&request), ConnState::New);
+        assert_eq!(conntrack.classify(&reply), ConnState::Established);
+
+        let mut unreachable = vec![3, 3, 0, 0, 0, 0, 0, 0];
+        unreachable.extend_from_slice(&udp(true).data[..28]);
+        let error = packet(ICMP, SERVER, CLIENT, &unreachable);
+        assert_eq!(conntrack.classify(&error), ConnState::Invalid);
+
+        conntrack.classify(&udp(true));
+        assert_eq!(conntrack.classify(&error), ConnState::Related);
+    }
+
+    #[test]
+    fn full_table() {
+        let conntrack = Conntrack::new(ConntrackTable::new().max_connections(1));
+        assert_eq!(conntrack.classify(&udp(true)), ConnState::New);
+        assert_eq!(conntrack.classify(&tcp(true, TCP_SYN)), ConnState::Invalid);
+    }
+
+    #[test]
+    fn marks() {
+        let table = ConntrackTable::new();
+        let tuple = ConnTuple::from_packet(&udp(true)).unwrap();
+        assert!(!table.set_mark(&tuple, 7));
+
+        table.track(&udp(true));
+        assert!(table.set_mark(&tuple.reverse(), 7));
+        assert_eq!(table.get(&tuple).unwrap().mark, 7);
+        assert!(table.remove(&tuple).is_some());
+        assert!(table.is_empty());
+    }
+}
@@ -0,0 +1,410 @@
+use crate::classifier::Classifier;
+use crate::link::{primitive::ClassifyLink, Link, LinkBuilder, PacketStream};
+use route_rs_packets::Ipv4Packet;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::ops::RangeInclusive;
+
+/// How deep jumps between chains may nest, so rules that jump in a loop drop the packet rather
+/// than hang.
+const MAX_JUMP_DEPTH: usize = 16;
+
+/// What a FirewallFilter decides for a packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FirewallVerdict {
+    Accept,
+    Drop,
+}
+
+/// What a FirewallRule does with the packets it matches.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FirewallAction {
+    Accept,
+    Drop,
+    /// Evaluates the chain with the given name, carrying on after this rule if none of its rules
+    /// decide.
+    Jump(String),
+    /// Stops evaluating the current chain, carrying on after the rule that jumped to it.
+    Return,
+}
+
+/// A firewall rule, matching packets on every condition it is given, or on everything if it is
+/// given none.
+#[derive(Clone, Debug)]
+pub struct FirewallRule {
+    action: FirewallAction,
+    interface: Option<usize>,
+    src: Option<(Ipv4Addr, u8)>,
+    dst: Option<(Ipv4Addr, u8)>,
+    protocol: Option<u8>,
+    src_ports: Option<RangeInclusive<u16>>,
+    dst_ports: Option<RangeInclusive<u16>>,
+    tcp_flags: Option<(u8, u8)>,
+}
+
+impl FirewallRule {
+    pub fn new(action: FirewallAction) -> Self {
+        FirewallRule {
+            action,
+            interface: None,
+            src: None,
+            dst: None,
+            protocol: None,
+            src_ports: None,
+            dst_ports: None,
+            tcp_flags: None,
+        }
+    }
+
+    /// Matches packets arriving on `interface`, as set on the FirewallFilter.
+    pub fn interface(self, interface: usize) -> Self {
+        FirewallRule {
+            interface: Some(interface),
+            ..self
+        }
+    }
+
+    /// Matches packets with a source in `addr`/`prefix_len`.
+    pub fn src(self, addr: Ipv4Addr, prefix_len: u8) -> Self {
+        assert!(
+            prefix_len <= 32,
+            "FirewallRule src prefix_len: {}, must be <= 32",
+            prefix_len
+        );
+
+        FirewallRule {
+            src: Some((addr, prefix_len)),
+            ..self
+        }
+    }
+
+    /// Matches packets with a destination in `addr`/`prefix_len`.
+    pub fn dst(self, addr: Ipv4Addr, prefix_len: u8) -> Self {
+        assert!(
+            prefix_len <= 32,
+            "FirewallRule dst prefix_len: {}, must be <= 32",
+            prefix_len
+        );
+
+        FirewallRule {
+            dst: Some((addr, prefix_len)),
+            ..self
+        }
+    }
+
+    /// Matches packets of the IP protocol numbered `protocol`.
+    pub fn protocol(self, protocol: u8) -> Self {
+        FirewallRule {
+            protocol: Some(protocol),
+            ..self
+        }
+    }
+
+    /// Matches TCP and UDP packets from a port in `ports`.
+    pub fn src_ports(self, ports: RangeInclusive<u16>) -> Self {
+        FirewallRule {
+            src_ports: Some(ports),
+            ..self
+        }
+    }
+
+    /// Matches TCP and UDP packets to a port in `ports`.
+    pub fn dst_ports(self, ports: RangeInclusive<u16>) -> Self {
+        FirewallRule {
+            dst_ports: Some(ports),
+            ..self
+        }
+    }
+
+    /// Matches TCP segments whose flags, masked with `mask`, equal `flags`. For instance, a mask
+    /// of SYN | ACK and flags of SYN matches connection attempts.
+    pub fn tcp_flags(self, mask: u8, flags: u8) -> Self {
+        FirewallRule {
+            tcp_flags: Some((mask, flags)),
+            ..self
+        }
+    }
+
+    fn matches(&self, packet: &Ipv4Packet, interface: Option<usize>) -> bool {
+        if self.interface.is_some() && self.interface != interface {
+            return false;
+        }
+        if let Some((addr, prefix_len)) = self.src {
+            if !in_prefix(packet.src_addr(), addr, prefix_len) {
+                return false;
+            }
+        }
+        if let Some((addr, prefix_len)) = self.dst {
+            if !in_prefix(packet.dest_addr(), addr, prefix_len) {
+                return false;
+            }
+        }
+
+        let protocol = packet.data[packet.layer3_offset + 9];
+        if self.protocol.is_some() && self.protocol != Some(protocol) {
+            return false;
+        }
+        if self.src_ports.is_none() && self.dst_ports.is_none() && self.tcp_flags.is_none() {
+            return true;
+        }
+
+        // Only the first fragment has the layer 4 header.
+        let layer4 = match packet.fragment_offset() {
+            0 => &packet.data[packet.payload_offset..],
+            _ => return false,
+        };
+        if let Some(ports) = &self.src_ports {
+            match port(protocol, layer4, 0) {
+                Some(port) if ports.contains(&port) => (),
+                _ => return false,
+            }
+        }
+        if let Some(ports) = &self.dst_ports {
+            match port(protocol, layer4, 2) {
+                Some(port) if ports.contains(&port) => (),
+                _ => return false,
+            }
+        }
+        if let Some((mask, flags)) = self.tcp_flags {
+            match layer4.get(13) {
+                Some(actual) if protocol == 6 && actual & mask == flags => (),
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+fn in_prefix(addr: Ipv4Addr, prefix: Ipv4Addr, prefix_len: u8) -> bool {
+    let mask = match prefix_len {
+        0 => 0,
+        _ => u32::MAX << (32 - prefix_len),
+    };
+    u32::from(addr) & mask == u32::from(prefix) & mask
+}
+
+/// Reads the TCP or UDP port at `offset` of the layer 4 header.
+fn port(protocol: u8, layer4: &[u8], offset: usize) -> Option<u16> {
+    match (protocol, layer4.get(offset..offset + 2)) {
+        (6, Some(port)) | (17, Some(port)) => Some(u16::from_be_bytes([port[0], port[1]])),
+        _ => None,
+    }
+}
+
+/// A stateless firewall, deciding whether IPv4 packets are accepted or dropped by the first rule
+/// of its ordered list that matches them, or by its policy if none do. Rules may jump to named
+/// chains of further rules. Packets carry no note of where they arrived, so a filter serving a
+/// single interface is told which one, for rules matching on it. Run in a ClassifyLink, as
+/// `firewall_link` does, so dropped traffic can be counted or logged on its own egressor.
+pub struct FirewallFilter {
+    rules: Vec<FirewallRule>,
+    chains: HashMap<String, Vec<FirewallRule>>,
+    policy: FirewallVerdict,
+    interface: Option<usize>,
+}
+
+impl FirewallFilter {
+    pub fn new(rules: Vec<FirewallRule>) -> Self {
+        FirewallFilter {
+            rules,
+            chains: HashMap::new(),
+            policy: FirewallVerdict::Accept,
+            interface: None,
+        }
+    }
+
+    /// Adds a chain of rules that Jump rules can name.
+    pub fn chain(mut self, name: &str, rules: Vec<FirewallRule>) -> Self {
+        self.chains.insert(name.to_string(), rules);
+        self
+    }
+
+    /// Sets the verdict for packets no rule decides, Accept by default.
+    pub fn policy(self, policy: FirewallVerdict) -> Self {
+        FirewallFilter { policy, ..self }
+    }
+
+    /// Sets the interface the filtered packets arrived on.
+    pub fn interface(self, interface: usize) -> Self {
+        FirewallFilter {
+            interface: Some(interface),
+            ..self
+        }
+    }
+
+    /// Evaluates `rules` against the packet, returning None if they don't decide it.
+    fn evaluate(
+        &self,
+        rules: &[FirewallRule],
+        packet: &Ipv4Packet,
+        depth: usize,
+    ) -> Option<FirewallVerdict> {
+        for rule in rules {
+            if !rule.matches(packet, self.interface) {
+                continue;
+            }
+            match &rule.action {
+                FirewallAction::Accept => return Some(FirewallVerdict::Accept),
+                FirewallAction::Drop => return Some(FirewallVerdict::Drop),
+                FirewallAction::Return => return None,
+                FirewallAction::Jump(name) => {
+                    if depth >= MAX_JUMP_DEPTH {
+                        return Some(FirewallVerdict::Drop);
+                    }
+                    let chain = self
+                        .chains
+                        .get(name)
+                        .unwrap_or_else(|| panic!("FirewallFilter has no chain {}", name));
+                    if let Some(verdict) = self.evaluate(chain, packet, depth + 1) {
+                        return Some(verdict);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+impl Classifier for FirewallFilter {
+    type Packet = Ipv4Packet;
+    type Class = FirewallVerdict;
+
+    fn classify(&self, packet: &Self::Packet) -> Self::Class {
+        self.evaluate(&self.rules, packet, 0).unwrap_or(self.policy)
+    }
+}
+
+/// Filters `stream` with `filter`, sending accepted packets to the first egressor, and dropped
+/// packets to the second.
+pub fn firewall_link(stream: PacketStream<Ipv4Packet>, filter: FirewallFilter) -> Link<Ipv4Packet> {
+    ClassifyLink::new()
+        .ingressor(stream)
+        .num_egressors(2)
+        .classifier(filter)
+        .dispatcher(Box::new(|verdict| match verdict {
+            FirewallVerdict::Accept => 0,
+            FirewallVerdict::Drop => 1,
+        }))
+        .build_link()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    const SYN: u8 = 0x02;
+    const ACK: u8 = 0x10;
+
+    fn tcp(src: Ipv4Addr, dst_port: u16, flags: u8) -> Ipv4Packet {
+        let mut segment = vec![0; 20];
+        segment[0..2].copy_from_slice(&40000u16.to_be_bytes());
+        segment[2..4].copy_from_slice(&dst_port.to_be_bytes());
+        segment[12] = 5 << 4;
+        segment[13] = flags;
+
+        let mut packet = Ipv4Packet::empty();
+        packet.set_ttl(64);
+        packet.set_protocol(6);
+        packet.set_src_addr(src);
+        packet.set_dest_addr(Ipv4Addr::new(192, 168, 1, 1));
+        packet.set_payload(&segment);
+        packet
+    }
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let filter = FirewallFilter::new(vec![
+            FirewallRule::new(FirewallAction::Drop).src(Ipv4Addr::new(10, 0, 0, 0), 8),
+            FirewallRule::new(FirewallAction::Accept)
+                .protocol(6)
+                .dst_ports(22..=22),
+        ])
+        .policy(FirewallVerdict::Drop);
+
+        let lan = Ipv4Addr::new(192, 168, 1, 20);
+        assert_eq!(filter.classify(&tcp(lan, 22, SYN)), FirewallVerdict::Accept);
+        assert_eq!(filter.classify(&tcp(lan, 80, SYN)), FirewallVerdict::Drop);
+        assert_eq!(
+            filter.classify(&tcp(Ipv4Addr::new(10, 1, 2, 3), 22, SYN)),
+            FirewallVerdict::Drop
+        );
+    }
+
+    #[test]
+    fn tcp_flags_and_interface() {
+        let filter = FirewallFilter::new(vec![FirewallRule::new(FirewallAction::Drop)
+            .interface(1)
+            .tcp_flags(SYN | ACK, SYN)]);
+
+        let wan = Ipv4Addr::new(203, 0, 113, 5);
+        assert_eq!(filter.classify(&tcp(wan, 80, SYN)), FirewallVerdict::Accept);
+
+        let filter = filter.interface(1);
+        assert_eq!(filter.classify(&tcp(wan, 80, SYN)), FirewallVerdict::Drop);
+        assert_eq!(
+            filter.classify(&tcp(wan, 80, SYN | ACK)),
+            FirewallVerdict::Accept
+        );
+    }
+
+    #[test]
+    fn jump_and_return() {
+        let filter = FirewallFilter::new(vec![
+            FirewallRule::new(FirewallAction::Jump("web".to_string())).dst_ports(80..=443),
+            FirewallRule::new(FirewallAction::Drop),
+        ])
+        .chain(
+            "web",
+            vec![
+                FirewallRule::new(FirewallAction::Return).dst_ports(81..=442),
+                FirewallRule::new(FirewallAction::Accept),
+            ],
+        );
+
+        let host = Ipv4Addr::new(192, 168, 1, 20);
+        assert_eq!(
+            filter.classify(&tcp(host, 80, SYN)),
+            FirewallVerdict::Accept
+        );
+        assert_eq!(
+            filter.classify(&tcp(host, 443, SYN)),
+            FirewallVerdict::Accept
+        );
+        assert_eq!(filter.classify(&tcp(host, 100, SYN)), FirewallVerdict::Drop);
+        assert_eq!(filter.classify(&tcp(host, 22, SYN)), FirewallVerdict::Drop);
+    }
+
+    #[test]
+    fn jump_loops_drop() {
+        let filter = FirewallFilter::new(vec![FirewallRule::new(FirewallAction::Jump(
+            "loop".to_string(),
+        ))])
+        .chain(
+            "loop",
+            vec![FirewallRule::new(FirewallAction::Jump("loop".to_string()))],
+        );
+
+        let host = Ipv4Addr::new(192, 168, 1, 20);
+        assert_eq!(filter.classify(&tcp(host, 80, SYN)), FirewallVerdict::Drop);
+    }
+
+    #[test]
+    fn link_separates_dropped_packets() {
+        let filter = FirewallFilter::new(vec![
+            FirewallRule::new(FirewallAction::Drop).dst_ports(23..=23)
+        ]);
+        let host = Ipv4Addr::new(192, 168, 1, 20);
+        let packets = vec![tcp(host, 80, SYN), tcp(host, 23, SYN), tcp(host, 443, SYN)];
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = firewall_link(immediate_stream(packets.clone()), filter);
+            run_link(link).await
+        });
+
+        assert_eq!(results[0], vec![packets[0].clone(), packets[2].clone()]);
+        assert_eq!(results[1], vec![packets[1].clone()]);
+    }
+}
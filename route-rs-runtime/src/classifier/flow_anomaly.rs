@@ -0,0 +1,609 @@
+use crate::classifier::{Classifier, ConnTuple};
+use crate::link::{primitive::ClassifyLink, Link, LinkBuilder, PacketStream};
+use route_rs_packets::Ipv4Packet;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const TCP: u8 = 6;
+const UDP: u8 = 17;
+
+const TCP_SYN: u8 = 0x02;
+const TCP_ACK: u8 = 0x10;
+
+/// Which threshold a flow exceeded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FloodKind {
+    /// TCP segments opening connections, SYN without ACK.
+    Syn,
+    /// UDP datagrams.
+    Udp,
+    /// Packets of any protocol.
+    Packets,
+    /// Bytes of IP packets of any protocol.
+    Bytes,
+}
+
+/// Whether a packet's flow is within its thresholds, or is being penalized for exceeding one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FlowVerdict {
+    Normal,
+    Penalized,
+}
+
+/// A snapshot of a flow that exceeded a threshold, and is penalized.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Offender {
+    pub flow: ConnTuple,
+    /// The threshold the flow last exceeded.
+    pub kind: FloodKind,
+    /// The flow's rate, per window, of what it last exceeded, when it did.
+    pub rate: u64,
+    /// When the flow was first flagged.
+    pub flagged_at: Instant,
+    /// How many packets of the flow have been penalized.
+    pub penalized: u64,
+    /// When the flow stops being penalized, unless it exceeds a threshold again.
+    pub until: Instant,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Counts {
+    packets: u64,
+    bytes: u64,
+    syns: u64,
+    udp: u64,
+}
+
+/// A flow's counts over the current and previous windows, from which its rate over the last
+/// window's length is estimated, taking the part of the previous window that's still in it.
+struct FlowRates {
+    window_start: Instant,
+    current: Counts,
+    previous: Counts,
+}
+
+impl FlowRates {
+    fn roll(&mut self, now: Instant, window: Duration) {
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed >= window * 2 {
+            self.previous = Counts::default();
+            self.current = Counts::default();
+            self.window_start = now;
+        } else if elapsed >= window {
+            self.previous = self.current;
+            self.current = Counts::default();
+            self.window_start += window;
+        }
+    }
+
+    fn estimate(&self, now: Instant, window: Duration) -> Counts {
+        let elapsed = now.duration_since(self.window_start);
+        let weight = 1.0 - elapsed.as_secs_f64() / window.as_secs_f64();
+        let weigh = |previous: u64, current: u64| (previous as f64 * weight) as u64 + current;
+        Counts {
+            packets: weigh(self.previous.packets, self.current.packets),
+            bytes: weigh(self.previous.bytes, self.current.bytes),
+            syns: weigh(self.previous.syns, self.current.syns),
+            udp: weigh(self.previous.udp, self.current.udp),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Thresholds {
+    syns: Option<u64>,
+    udp: Option<u64>,
+    packets: Option<u64>,
+    bytes: Option<u64>,
+}
+
+struct Flows {
+    rates: HashMap<ConnTuple, FlowRates>,
+    offenders: HashMap<ConnTuple, Offender>,
+}
+
+/// Tracks the rates of IPv4 flows over a sliding window, and flags those exceeding a threshold,
+/// such as SYN or UDP floods, as offenders, penalized until they've kept within their thresholds
+/// for the penalty time. Shared by FlowAnomaly classifiers; cloning it gives another handle to the
+/// same flows, for instance to log the offenders.
+#[derive(Clone)]
+pub struct FlowAnomalyTable {
+    window: Duration,
+    thresholds: Thresholds,
+    penalty_time: Duration,
+    max_flows: usize,
+    ignore_ports: bool,
+    flows: Arc<Mutex<Flows>>,
+}
+
+impl Default for FlowAnomalyTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlowAnomalyTable {
+    pub fn new() -> Self {
+        FlowAnomalyTable {
+            window: Duration::from_secs(1),
+            thresholds: Thresholds {
+                syns: Some(200),
+                udp: Some(10_000),
+                packets: None,
+                bytes: None,
+            },
+            penalty_time: Duration::from_secs(10),
+            max_flows: 65536,
+            ignore_ports: false,
+            flows: Arc::new(Mutex::new(Flows {
+                rates: HashMap::new(),
+                offenders: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Changes the length of the window rates are measured over, default 1 second.
+    pub fn window(self, window: Duration) -> Self {
+        assert!(
+            window > Duration::from_secs(0),
+            "FlowAnomalyTable window: {:?}, must be > 0",
+            window
+        );
+
+        FlowAnomalyTable { window, ..self }
+    }
+
+    /// Changes the most SYNs a flow may send per window, default 200.
+    pub fn max_syns(mut self, max_syns: u64) -> Self {
+        self.thresholds.syns = Some(max_syns);
+        self
+    }
+
+    /// Changes the most UDP datagrams a flow may send per window, default 10000.
+    pub fn max_udp_packets(mut self, max_udp_packets: u64) -> Self {
+        self.thresholds.udp = Some(max_udp_packets);
+        self
+    }
+
+    /// Limits the packets of any protocol a flow may send per window, unlimited by default.
+    pub fn max_packets(mut self, max_packets: u64) -> Self {
+        self.thresholds.packets = Some(max_packets);
+        self
+    }
+
+    /// Limits the bytes a flow may send per window, counting whole IP packets, unlimited by
+    /// default.
+    pub fn max_bytes(mut self, max_bytes: u64) -> Self {
+        self.thresholds.bytes = Some(max_bytes);
+        self
+    }
+
+    /// Changes how long an offender is penalized after it last exceeded a threshold, default 10
+    /// seconds.
+    pub fn penalty_time(self, penalty_time: Duration) -> Self {
+        FlowAnomalyTable {
+            penalty_time,
+            ..self
+        }
+    }
+
+    /// Changes the most flows tracked at once, default 65536. When full, flows unseen for two
+    /// windows are forgotten, and if none are, new flows go untracked, and are Normal.
+    pub fn max_flows(self, max_flows: usize) -> Self {
+        assert!(
+            max_flows > 0,
+            "FlowAnomalyTable max_flows: {}, must be > 0",
+            max_flows
+        );
+
+        FlowAnomalyTable { max_flows, ..self }
+    }
+
+    /// Counts all traffic of a protocol between two hosts as one flow, rather than each pair of
+    /// ports apart, which catches floods that vary their ports, as SYN floods usually do.
+    pub fn ignore_ports(self, ignore_ports: bool) -> Self {
+        FlowAnomalyTable {
+            ignore_ports,
+            ..self
+        }
+    }
+
+    /// Counts the packet against its flow, and returns whether the flow is penalized.
+    pub fn track(&self, packet: &Ipv4Packet) -> FlowVerdict {
+        self.track_at(packet, Instant::now())
+    }
+
+    fn track_at(&self, packet: &Ipv4Packet, now: Instant) -> FlowVerdict {
+        let mut flow = match ConnTuple::from_packet(packet) {
+            Some(flow) => flow,
+            None => return FlowVerdict::Normal,
+        };
+        if self.ignore_ports {
+            flow.src_port = 0;
+            flow.dst_port = 0;
+        }
+
+        // A header claiming to be longer than the packet leaves no ports to go by.
+        let layer4 = match packet.data.get(packet.payload_offset..) {
+            Some(layer4) => layer4,
+            None => {
+                flow.src_port = 0;
+                flow.dst_port = 0;
+                &[]
+            }
+        };
+        let mut counts = Counts {
+            packets: 1,
+            bytes: (packet.data.len() - packet.layer3_offset) as u64,
+            ..Counts::default()
+        };
+        match flow.protocol {
+            TCP if layer4.get(13).map(|flags| flags & (TCP_SYN | TCP_ACK)) == Some(TCP_SYN) => {
+                counts.syns = 1
+            }
+            UDP => counts.udp = 1,
+            _ => (),
+        }
+
+        let mut flows = self.flows.lock().unwrap();
+        let flows = &mut *flows;
+        if !flows.rates.contains_key(&flow) && flows.rates.len() >= self.max_flows {
+            let stale = self.window * 2;
+            flows
+                .rates
+                .retain(|_, rates| now.duration_since(rates.window_start) < stale);
+        }
+        let exceeded = if flows.rates.len() < self.max_flows || flows.rates.contains_key(&flow) {
+            let rates = flows.rates.entry(flow).or_insert(FlowRates {
+                window_start: now,
+                current: Counts::default(),
+                previous: Counts::default(),
+            });
+            rates.roll(now, self.window);
+            rates.current.packets += counts.packets;
+            rates.current.bytes += counts.bytes;
+            rates.current.syns += counts.syns;
+            rates.current.udp += counts.udp;
+            self.exceeded(rates.estimate(now, self.window), counts)
+        } else {
+            None
+        };
+
+        match exceeded {
+            Some((kind, rate)) => {
+                let offender = flows.offenders.entry(flow).or_insert(Offender {
+                    flow,
+                    kind,
+                    rate,
+                    flagged_at: now,
+                    penalized: 0,
+                    until: now,
+                });
+                offender.kind = kind;
+                offender.rate = rate;
+                offender.penalized += 1;
+                offender.until = now + self.penalty_time;
+                FlowVerdict::Penalized
+            }
+            None => match flows.offenders.get_mut(&flow) {
+                Some(offender) if now < offender.until => {
+                    offender.penalized += 1;
+                    FlowVerdict::Penalized
+                }
+                Some(_) => {
+                    flows.offenders.remove(&flow);
+                    FlowVerdict::Normal
+                }
+                None => FlowVerdict::Normal,
+            },
+        }
+    }
+
+    /// The first threshold `rates` exceed, of those the packet, counted in `counts`, counts
+    /// toward, and the rate that exceeds it.
+    fn exceeded(&self, rates: Counts, counts: Counts) -> Option<(FloodKind, u64)> {
+        let thresholds = self.thresholds;
+        [
+            (FloodKind::Syn, counts.syns, rates.syns, thresholds.syns),
+            (FloodKind::Udp, counts.udp, rates.udp, thresholds.udp),
+            (FloodKind::Packets, 1, rates.packets, thresholds.packets),
+            (FloodKind::Bytes, 1, rates.bytes, thresholds.bytes),
+        ]
+        .iter()
+        .find(|(_, counted, rate, threshold)| {
+            *counted > 0 && matches!(threshold, Some(threshold) if rate > threshold)
+        })
+        .map(|(kind, _, rate, _)| (*kind, *rate))
+    }
+
+    /// Returns the flows being penalized.
+    pub fn offenders(&self) -> Vec<Offender> {
+        self.offenders_at(Instant::now())
+    }
+
+    fn offenders_at(&self, now: Instant) -> Vec<Offender> {
+        let mut flows = self.flows.lock().unwrap();
+        flows.offenders.retain(|_, offender| now < offender.until);
+        flows.offenders.values().cloned().collect()
+    }
+
+    /// Stops penalizing `flow`, as keyed with ports or without, as the table does. Returns false
+    /// if it wasn't penalized.
+    pub fn pardon(&self, flow: &ConnTuple) -> bool {
+        let mut flows = self.flows.lock().unwrap();
+        flows.rates.remove(flow);
+        flows.offenders.remove(flow).is_some()
+    }
+}
+
+/// Classifies IPv4 packets by whether their flow is penalized by a FlowAnomalyTable, counting
+/// them against it. Run in a ClassifyLink, as `flow_anomaly_link` does, to send offending flows
+/// to a penalty egressor, to be dropped, rate limited or logged.
+pub struct FlowAnomaly {
+    table: FlowAnomalyTable,
+}
+
+impl FlowAnomaly {
+    pub fn new(table: FlowAnomalyTable) -> Self {
+        FlowAnomaly { table }
+    }
+}
+
+impl Classifier for FlowAnomaly {
+    type Packet = Ipv4Packet;
+    type Class = FlowVerdict;
+
+    fn classify(&self, packet: &Self::Packet) -> Self::Class {
+        self.table.track(packet)
+    }
+}
+
+/// Sends packets of `stream` whose flows are normal to the first egressor, and those of flows
+/// `detector` penalizes to the second.
+pub fn flow_anomaly_link(
+    stream: PacketStream<Ipv4Packet>,
+    detector: FlowAnomaly,
+) -> Link<Ipv4Packet> {
+    ClassifyLink::new()
+        .ingressor(stream)
+        .num_egressors(2)
+        .classifier(detector)
+        .dispatcher(Box::new(|verdict| match verdict {
+            FlowVerdict::Normal => 0,
+            FlowVerdict::Penalized => 1,
+        }))
+        .build_link()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use std::net::Ipv4Addr;
+
+    const ATTACKER: Ipv4Addr = Ipv4Addr::new(203, 0, 113, 66);
+    const CLIENT: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 7);
+    const SERVER: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 10);
+
+    fn packet(protocol: u8, src: Ipv4Addr, src_port: u16, layer4_tail: &[u8]) -> Ipv4Packet {
+        let mut layer4 = src_port.to_be_bytes().to_vec();
+        layer4.extend_from_slice(&80u16.to_be_bytes());
+        layer4.extend_from_slice(layer4_tail);
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(protocol);
+        packet.set_src_addr(src);
+        packet.set_dest_addr(SERVER);
+        packet.set_payload(&layer4);
+        packet
+    }
+
+    fn syn(src: Ipv4Addr, src_port: u16) -> Ipv4Packet {
+        packet(
+            TCP,
+            src,
+            src_port,
+            &[0, 0, 0, 0, 0, 0, 0, 0, 0x50, TCP_SYN, 0, 0],
+        )
+    }
+
+    fn ack(src: Ipv4Addr, src_port: u16) -> Ipv4Packet {
+        packet(
+            TCP,
+            src,
+            src_port,
+            &[0, 0, 0, 0, 0, 0, 0, 0, 0x50, TCP_ACK, 0, 0],
+        )
+    }
+
+    fn udp(src: Ipv4Addr) -> Ipv4Packet {
+        packet(UDP, src, 5353, &[0, 8, 0, 0])
+    }
+
+    #[test]
+    fn flags_syn_floods() {
+        let table = FlowAnomalyTable::new().max_syns(3).ignore_ports(true);
+        let now = Instant::now();
+
+        for port in 0..3 {
+            assert_eq!(
+                table.track_at(&syn(ATTACKER, port), now),
+                FlowVerdict::Normal
+            );
+        }
+        assert_eq!(
+            table.track_at(&syn(ATTACKER, 3), now),
+            FlowVerdict::Penalized
+        );
+        // The whole flow is penalized, not just its SYNs, but other flows aren't.
+        assert_eq!(
+            table.track_at(&ack(ATTACKER, 0), now),
+            FlowVerdict::Penalized
+        );
+        assert_eq!(table.track_at(&syn(CLIENT, 0), now), FlowVerdict::Normal);
+
+        let offenders = table.offenders_at(now);
+        assert_eq!(offenders.len(), 1);
+        assert_eq!(offenders[0].flow.src, ATTACKER);
+        assert_eq!(offenders[0].flow.src_port, 0);
+        assert_eq!(offenders[0].kind, FloodKind::Syn);
+        assert_eq!(offenders[0].rate, 4);
+        assert_eq!(offenders[0].penalized, 2);
+    }
+
+    #[test]
+    fn counts_flows_by_ports() {
+        let table = FlowAnomalyTable::new().max_syns(1);
+        let now = Instant::now();
+
+        // Each port is a flow of its own, so a flood across ports passes.
+        for port in 0..10 {
+            assert_eq!(
+                table.track_at(&syn(ATTACKER, port), now),
+                FlowVerdict::Normal
+            );
+        }
+        assert_eq!(
+            table.track_at(&syn(ATTACKER, 0), now),
+            FlowVerdict::Penalized
+        );
+        assert_eq!(
+            table.track_at(&syn(ATTACKER, 1), now),
+            FlowVerdict::Penalized
+        );
+        assert_eq!(table.track_at(&syn(ATTACKER, 10), now), FlowVerdict::Normal);
+    }
+
+    #[test]
+    fn flags_udp_packet_and_byte_floods() {
+        let now = Instant::now();
+        let table = FlowAnomalyTable::new().max_udp_packets(2);
+        table.track_at(&udp(ATTACKER), now);
+        table.track_at(&udp(ATTACKER), now);
+        assert_eq!(table.track_at(&udp(ATTACKER), now), FlowVerdict::Penalized);
+        assert_eq!(table.offenders_at(now)[0].kind, FloodKind::Udp);
+
+        // UDP thresholds don't count TCP, but packet thresholds count everything.
+        let table = FlowAnomalyTable::new().max_udp_packets(1).max_packets(2);
+        assert_eq!(table.track_at(&ack(CLIENT, 1), now), FlowVerdict::Normal);
+        assert_eq!(table.track_at(&ack(CLIENT, 1), now), FlowVerdict::Normal);
+        assert_eq!(table.track_at(&ack(CLIENT, 1), now), FlowVerdict::Penalized);
+        assert_eq!(table.offenders_at(now)[0].kind, FloodKind::Packets);
+
+        let table = FlowAnomalyTable::new().max_bytes(100);
+        let packet = udp(CLIENT);
+        let len = (packet.data.len() - packet.layer3_offset) as u64;
+        for _ in 0..100 / len {
+            assert_eq!(table.track_at(&udp(CLIENT), now), FlowVerdict::Normal);
+        }
+        assert_eq!(table.track_at(&udp(CLIENT), now), FlowVerdict::Penalized);
+        assert_eq!(table.offenders_at(now)[0].kind, FloodKind::Bytes);
+    }
+
+    #[test]
+    fn counts_header_longer_than_packet() {
+        let table = FlowAnomalyTable::new().max_packets(1);
+        let now = Instant::now();
+        let mut packet = syn(ATTACKER, 1000);
+        packet.payload_offset = packet.data.len() + 4;
+
+        assert_eq!(table.track_at(&packet, now), FlowVerdict::Normal);
+        assert_eq!(table.track_at(&packet, now), FlowVerdict::Penalized);
+        let offenders = table.offenders_at(now);
+        assert_eq!(offenders[0].kind, FloodKind::Packets);
+        assert_eq!(offenders[0].flow.src_port, 0);
+    }
+
+    #[test]
+    fn window_slides() {
+        let table = FlowAnomalyTable::new().max_udp_packets(4);
+        let now = Instant::now();
+        for _ in 0..4 {
+            table.track_at(&udp(CLIENT), now);
+        }
+
+        // Three quarters into the next window, a quarter of the last one's 4 still counts.
+        let later = now + Duration::from_millis(1750);
+        for _ in 0..3 {
+            assert_eq!(table.track_at(&udp(CLIENT), later), FlowVerdict::Normal);
+        }
+        assert_eq!(table.track_at(&udp(CLIENT), later), FlowVerdict::Penalized);
+
+        // Two windows on, nothing from before counts.
+        let table = FlowAnomalyTable::new().max_udp_packets(4);
+        for _ in 0..4 {
+            table.track_at(&udp(CLIENT), now);
+        }
+        let later = now + Duration::from_secs(2);
+        for _ in 0..4 {
+            assert_eq!(table.track_at(&udp(CLIENT), later), FlowVerdict::Normal);
+        }
+    }
+
+    #[test]
+    fn penalties_expire_and_pardon() {
+        let table = FlowAnomalyTable::new()
+            .max_udp_packets(1)
+            .penalty_time(Duration::from_secs(5));
+        let now = Instant::now();
+        table.track_at(&udp(ATTACKER), now);
+        table.track_at(&udp(ATTACKER), now);
+
+        // Within its threshold again, the flow is still penalized until the penalty time passes.
+        let later = now + Duration::from_secs(3);
+        assert_eq!(
+            table.track_at(&udp(ATTACKER), later),
+            FlowVerdict::Penalized
+        );
+        assert_eq!(table.offenders_at(later)[0].penalized, 2);
+        let later = now + Duration::from_secs(5);
+        assert!(table.offenders_at(later).is_empty());
+        assert_eq!(table.track_at(&udp(ATTACKER), later), FlowVerdict::Normal);
+
+        table.track_at(&udp(ATTACKER), later);
+        let flow = table.offenders_at(later)[0].flow;
+        assert!(table.pardon(&flow));
+        assert!(!table.pardon(&flow));
+        assert_eq!(table.track_at(&udp(ATTACKER), later), FlowVerdict::Normal);
+    }
+
+    #[test]
+    fn full_table() {
+        let table = FlowAnomalyTable::new().max_syns(1).max_flows(1);
+        let now = Instant::now();
+        table.track_at(&syn(CLIENT, 1), now);
+
+        // New flows go untracked while the table is full of fresh flows.
+        assert_eq!(table.track_at(&syn(ATTACKER, 1), now), FlowVerdict::Normal);
+        assert_eq!(table.track_at(&syn(ATTACKER, 1), now), FlowVerdict::Normal);
+        let later = now + Duration::from_secs(2);
+        assert_eq!(
+            table.track_at(&syn(ATTACKER, 1), later),
+            FlowVerdict::Normal
+        );
+        assert_eq!(
+            table.track_at(&syn(ATTACKER, 1), later),
+            FlowVerdict::Penalized
+        );
+    }
+
+    #[test]
+    fn penalizes_through_link() {
+        let packets = vec![udp(ATTACKER), udp(CLIENT), udp(ATTACKER), udp(ATTACKER)];
+        let table = FlowAnomalyTable::new().max_udp_packets(1);
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = flow_anomaly_link(
+                immediate_stream(packets.clone()),
+                FlowAnomaly::new(table.clone()),
+            );
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![packets[0].clone(), packets[1].clone()]);
+        assert_eq!(results[1], vec![packets[2].clone(), packets[3].clone()]);
+        assert_eq!(table.offenders()[0].flow.src, ATTACKER);
+    }
+}
@@ -0,0 +1,179 @@
+use crate::classifier::{Classifier, HostPattern};
+use crate::processor::HttpFrame;
+
+/// Classifies frames tagged by HttpParse by the host their HTTP request is for, into the class of
+/// the first pattern given that matches it, or into `default` if none do, or the frame doesn't
+/// start a request with a host. Patterns may be limited to some methods. Useful for
+/// parental-control style filtering of plaintext HTTP; TlsSni does the same for HTTPS.
+pub struct HttpHost<Class> {
+    default: Class,
+    patterns: Vec<(HostPattern, Option<Vec<String>>, Class)>,
+}
+
+impl<Class: Clone> HttpHost<Class> {
+    pub fn new(default: Class) -> Self {
+        HttpHost {
+            default,
+            patterns: Vec::new(),
+        }
+    }
+
+    /// Classifies requests for hosts matching `pattern`, as `example.com` or `*.example.com`, as
+    /// `class`, unless an earlier pattern matches them. Patterns are matched ignoring case.
+    pub fn host(self, pattern: &str, class: Class) -> Self {
+        self.add(pattern, None, class)
+    }
+
+    /// Like `host`, but only matching requests with one of `methods`, such as POST to block
+    /// uploads.
+    pub fn host_methods(self, pattern: &str, methods: &[&str], class: Class) -> Self {
+        let methods = methods.iter().map(|method| method.to_string()).collect();
+        self.add(pattern, Some(methods), class)
+    }
+
+    fn add(mut self, pattern: &str, methods: Option<Vec<String>>, class: Class) -> Self {
+        let host_pattern = HostPattern::new(pattern);
+        assert!(
+            host_pattern.is_some(),
+            "HttpHost pattern: {:?}, must be a name, or *. and a domain",
+            pattern
+        );
+
+        self.patterns.push((host_pattern.unwrap(), methods, class));
+        self
+    }
+
+    /// Returns the class of a request with `method` for `host`.
+    pub fn lookup(&self, method: &str, host: &str) -> &Class {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        self.patterns
+            .iter()
+            .find(|(pattern, methods, _)| {
+                pattern.matches(&host)
+                    && match methods {
+                        Some(methods) => methods.iter().any(|m| m == method),
+                        None => true,
+                    }
+            })
+            .map_or(&self.default, |(_, _, class)| class)
+    }
+}
+
+impl<Class: Clone> Classifier for HttpHost<Class> {
+    type Packet = HttpFrame;
+    type Class = Class;
+
+    fn classify(&self, frame: &Self::Packet) -> Self::Class {
+        match &frame.request {
+            Some(request) => match &request.host {
+                Some(host) => self.lookup(&request.method, host).clone(),
+                None => self.default.clone(),
+            },
+            None => self.default.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::primitive::{ClassifyLink, ProcessLink};
+    use crate::link::{LinkBuilder, ProcessLinkBuilder};
+    use crate::processor::{HttpParse, HttpRequest};
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use route_rs_packets::{EthernetFrame, Ipv4Packet, TcpSegment};
+
+    fn http_frame(payload: &[u8]) -> EthernetFrame {
+        let mut segment = TcpSegment::empty();
+        segment.set_payload(payload);
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(6);
+        packet.set_payload(&segment.data[segment.layer4_offset..]);
+        EthernetFrame::encap_ipv4(packet)
+    }
+
+    fn tagged(method: &str, host: Option<&str>) -> HttpFrame {
+        HttpFrame {
+            request: Some(HttpRequest {
+                method: method.to_string(),
+                host: host.map(str::to_string),
+            }),
+            frame: http_frame(b""),
+        }
+    }
+
+    #[test]
+    fn matches_hosts_and_methods() {
+        let classifier = HttpHost::new("allow")
+            .host_methods("*.uploads.example", &["POST", "PUT"], "block")
+            .host("games.example", "block")
+            .host("*.games.example", "block");
+
+        assert_eq!(
+            classifier.classify(&tagged("GET", Some("games.example"))),
+            "block"
+        );
+        assert_eq!(
+            classifier.classify(&tagged("GET", Some("www.games.example"))),
+            "block"
+        );
+        assert_eq!(
+            classifier.classify(&tagged("GET", Some("a.uploads.example"))),
+            "allow"
+        );
+        assert_eq!(
+            classifier.classify(&tagged("PUT", Some("a.uploads.example"))),
+            "block"
+        );
+        assert_eq!(classifier.classify(&tagged("GET", None)), "allow");
+        let untagged = HttpFrame {
+            request: None,
+            frame: http_frame(b""),
+        };
+        assert_eq!(classifier.classify(&untagged), "allow");
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_on_empty_pattern() {
+        HttpHost::new(()).host("*.", ());
+    }
+
+    #[test]
+    fn filters_through_links() {
+        let frames = vec![
+            http_frame(b"GET / HTTP/1.1\r\nHost: www.games.example\r\n\r\n"),
+            http_frame(b"GET / HTTP/1.1\r\nHost: school.example\r\n\r\n"),
+            http_frame(b"not http"),
+        ];
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (mut runnables, mut parsed) = ProcessLink::new()
+                .ingressor(immediate_stream(frames.clone()))
+                .processor(HttpParse::new())
+                .build_link();
+            let (classify_runnables, egressors) = ClassifyLink::new()
+                .ingressor(parsed.remove(0))
+                .num_egressors(2)
+                .classifier(HttpHost::new(true).host("*.games.example", false))
+                .dispatcher(Box::new(|allow| if allow { 0 } else { 1 }))
+                .build_link();
+            runnables.extend(classify_runnables);
+
+            run_link((runnables, egressors)).await
+        });
+        let frames_of = |tagged: &Vec<HttpFrame>| {
+            tagged
+                .iter()
+                .map(|tagged| tagged.frame.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            frames_of(&results[0]),
+            vec![frames[1].clone(), frames[2].clone()]
+        );
+        assert_eq!(frames_of(&results[1]), vec![frames[0].clone()]);
+    }
+}
@@ -4,12 +4,36 @@
 //! and are not able to modify it. They are only used in the ClassifyLink. Classifiers are able to return any type, but generally return an Enum
 //! that will inform the Dispatch section of the ClassifyLink which group each packet belongs to. The Dispatch then moves each packet to a port
 //! based on its classification.
+mod conntrack;
+pub use self::conntrack::*;
+
 mod even;
 pub use self::even::*;
 
+mod firewall;
+pub use self::firewall::*;
+
+mod flow_anomaly;
+pub use self::flow_anomaly::*;
+
 mod fizz_buzz;
 pub use self::fizz_buzz::*;
 
+mod http_host;
+pub use self::http_host::*;
+
+mod prefix;
+pub use self::prefix::*;
+
+mod schedule;
+pub use self::schedule::*;
+
+mod tls_sni;
+pub use self::tls_sni::*;
+
+mod regex_match;
+pub use self::regex_match::*;
+
 /// Used by a ClassifyLink to determine the kind of packet we have. Classifier::Class is then
 /// consumed by the dispatcher on the ClassifyLink to send it down the appropriate path.
 pub trait Classifier {
@@ -18,3 +42,14 @@ pub trait Classifier {
 
     fn classify(&self, packet: &Self::Packet) -> Self::Class;
 }
+
+/// Like Classifier, but for classifications that can fail, for instance on a malformed packet.
+/// Used by a TryClassifyLink, which sends packets that fail to classify to its error egressor,
+/// along with the reason they failed.
+pub trait TryClassifier {
+    type Packet: Send + Clone;
+    type Class: Sized;
+    type Reason: Sized;
+
+    fn try_classify(&self, packet: &Self::Packet) -> Result<Self::Class, Self::Reason>;
+}
@@ -0,0 +1,261 @@
+use crate::classifier::Classifier;
+use route_rs_packets::{EthernetFrame, IPV4_ETHER_TYPE, IPV6_ETHER_TYPE};
+use std::convert::TryFrom;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Which address of a packet a PrefixClassifier matches.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PrefixField {
+    Source,
+    Destination,
+}
+
+struct TrieNode<Class> {
+    children: [Option<usize>; 2],
+    class: Option<Class>,
+}
+
+/// A binary trie of prefixes, their bits held from the top of a u128.
+struct PrefixTrie<Class> {
+    nodes: Vec<TrieNode<Class>>,
+}
+
+impl<Class> PrefixTrie<Class> {
+    fn new() -> Self {
+        PrefixTrie {
+            nodes: vec![TrieNode {
+                children: [None, None],
+                class: None,
+            }],
+        }
+    }
+
+    fn insert(&mut self, bits: u128, prefix_len: u8, class: Class) {
+        let mut node = 0;
+        for depth in 0..prefix_len {
+            let bit = (bits >> (127 - depth)) as usize & 1;
+            node = match self.nodes[node].children[bit] {
+                Some(child) => child,
+                None => {
+                    self.nodes.push(TrieNode {
+                        children: [None, None],
+                        class: None,
+                    });
+                    let child = self.nodes.len() - 1;
+                    self.nodes[node].children[bit] = Some(child);
+                    child
+                }
+            };
+        }
+        self.nodes[node].class = Some(class);
+    }
+
+    /// The class of the longest prefix `bits` is in.
+    fn longest_match(&self, bits: u128) -> Option<&Class> {
+        let mut node = 0;
+        let mut longest = self.nodes[0].class.as_ref();
+        for depth in 0..128 {
+            let bit = (bits >> (127 - depth)) as usize & 1;
+            node = match self.nodes[node].children[bit] {
+                Some(child) => child,
+                None => break,
+            };
+            longest = self.nodes[node].class.as_ref().or(longest);
+        }
+        longest
+    }
+}
+
+/// Classifies frames by the longest of its prefixes the source or destination address is in,
+/// into the class given with the prefix, or into `default` if it's in none, or isn't IPv4 or
+/// IPv6. Prefixes of either family may be given, and are kept in a trie per family. Useful for
+/// policy routing, filtering bogons, or blocking the address blocks of a country.
+pub struct PrefixClassifier<Class> {
+    field: PrefixField,
+    default: Class,
+    ipv4: PrefixTrie<Class>,
+    ipv6: PrefixTrie<Class>,
+}
+
+impl<Class: Clone> PrefixClassifier<Class> {
+    pub fn new(field: PrefixField, default: Class) -> Self {
+        PrefixClassifier {
+            field,
+            default,
+            ipv4: PrefixTrie::new(),
+            ipv6: PrefixTrie::new(),
+        }
+    }
+
+    /// Classifies addresses in `addr`/`prefix_len` as `class`, unless they're in a longer prefix.
+    /// A prefix given again replaces the class it was given before.
+    pub fn prefix(mut self, addr: IpAddr, prefix_len: u8, class: Class) -> Self {
+        match addr {
+            IpAddr::V4(addr) => {
+                assert!(
+                    prefix_len <= 32,
+                    "PrefixClassifier prefix_len: {}, must be <= 32",
+                    prefix_len
+                );
+                self.ipv4
+                    .insert(u128::from(u32::from(addr)) << 96, prefix_len, class);
+            }
+            IpAddr::V6(addr) => {
+                assert!(
+                    prefix_len <= 128,
+                    "PrefixClassifier prefix_len: {}, must be <= 128",
+                    prefix_len
+                );
+                self.ipv6.insert(u128::from(addr), prefix_len, class);
+            }
+        }
+        self
+    }
+
+    /// Returns the class of `addr`.
+    pub fn lookup(&self, addr: IpAddr) -> &Class {
+        let class = match addr {
+            IpAddr::V4(addr) => self.ipv4.longest_match(u128::from(u32::from(addr)) << 96),
+            IpAddr::V6(addr) => self.ipv6.longest_match(u128::from(addr)),
+        };
+        class.unwrap_or(&self.default)
+    }
+
+    /// The address of the frame that's matched, if it's IPv4 or IPv6.
+    fn addr(&self, frame: &EthernetFrame) -> Option<IpAddr> {
+        let ip = frame.payload_offset;
+        match frame.ether_type() {
+            IPV4_ETHER_TYPE => {
+                let offset = match self.field {
+                    PrefixField::Source => ip + 12,
+                    PrefixField::Destination => ip + 16,
+                };
+                let addr = <[u8; 4]>::try_from(frame.data.get(offset..offset + 4)?).unwrap();
+                Some(Ipv4Addr::from(addr).into())
+            }
+            IPV6_ETHER_TYPE => {
+                let offset = match self.field {
+                    PrefixField::Source => ip + 8,
+                    PrefixField::Destination => ip + 24,
+                };
+                let addr = <[u8; 16]>::try_from(frame.data.get(offset..offset + 16)?).unwrap();
+                Some(Ipv6Addr::from(addr).into())
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<Class: Clone> Classifier for PrefixClassifier<Class> {
+    type Packet = EthernetFrame;
+    type Class = Class;
+
+    fn classify(&self, frame: &Self::Packet) -> Self::Class {
+        match self.addr(frame) {
+            Some(addr) => self.lookup(addr).clone(),
+            None => self.default.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use route_rs_packets::{Ipv4Packet, Ipv6Packet};
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum Route {
+        Wan,
+        Vpn,
+        Lan,
+        Drop,
+    }
+
+    fn v4(a: u8, b: u8, c: u8, d: u8) -> IpAddr {
+        Ipv4Addr::new(a, b, c, d).into()
+    }
+
+    fn ipv4_frame(src: Ipv4Addr, dst: Ipv4Addr) -> EthernetFrame {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_src_addr(src);
+        packet.set_dest_addr(dst);
+        EthernetFrame::encap_ipv4(packet)
+    }
+
+    #[test]
+    fn longest_prefix_wins() {
+        let classifier = PrefixClassifier::new(PrefixField::Destination, Route::Drop)
+            .prefix(v4(0, 0, 0, 0), 0, Route::Wan)
+            .prefix(v4(10, 0, 0, 0), 8, Route::Vpn)
+            .prefix(v4(10, 1, 0, 0), 16, Route::Lan)
+            .prefix(v4(10, 1, 2, 3), 32, Route::Drop);
+
+        assert_eq!(*classifier.lookup(v4(8, 8, 8, 8)), Route::Wan);
+        assert_eq!(*classifier.lookup(v4(10, 2, 0, 1)), Route::Vpn);
+        assert_eq!(*classifier.lookup(v4(10, 1, 200, 1)), Route::Lan);
+        assert_eq!(*classifier.lookup(v4(10, 1, 2, 3)), Route::Drop);
+        assert_eq!(*classifier.lookup(v4(10, 1, 2, 4)), Route::Lan);
+
+        // Without a default route, unmatched addresses get the default class.
+        let classifier = PrefixClassifier::new(PrefixField::Destination, Route::Drop)
+            .prefix(v4(10, 0, 0, 0), 8, Route::Lan)
+            .prefix(v4(10, 0, 0, 0), 8, Route::Vpn);
+        assert_eq!(*classifier.lookup(v4(11, 0, 0, 1)), Route::Drop);
+        assert_eq!(*classifier.lookup(v4(10, 0, 0, 1)), Route::Vpn);
+    }
+
+    #[test]
+    fn matches_source_or_destination() {
+        let lan = Ipv4Addr::new(192, 168, 1, 20);
+        let wan = Ipv4Addr::new(203, 0, 113, 5);
+        let frame = ipv4_frame(lan, wan);
+
+        let source =
+            PrefixClassifier::new(PrefixField::Source, false).prefix(v4(192, 168, 0, 0), 16, true);
+        let destination = PrefixClassifier::new(PrefixField::Destination, false).prefix(
+            v4(192, 168, 0, 0),
+            16,
+            true,
+        );
+        assert!(source.classify(&frame));
+        assert!(!destination.classify(&frame));
+        assert!(destination.classify(&ipv4_frame(wan, lan)));
+    }
+
+    #[test]
+    fn classifies_ipv6() {
+        let documentation = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0);
+        let classifier = PrefixClassifier::new(PrefixField::Destination, Route::Wan)
+            .prefix(documentation.into(), 32, Route::Drop)
+            .prefix(v4(0, 0, 0, 0), 0, Route::Lan);
+
+        let mut packet = Ipv6Packet::empty();
+        packet.set_dest_addr(Ipv6Addr::new(0x2001, 0xdb8, 1, 0, 0, 0, 0, 1));
+        let frame = EthernetFrame::encap_ipv6(packet.clone());
+        assert_eq!(classifier.classify(&frame), Route::Drop);
+
+        // IPv4 prefixes don't match IPv6 addresses.
+        packet.set_dest_addr(Ipv6Addr::new(0x2001, 0xdb9, 0, 0, 0, 0, 0, 1));
+        let frame = EthernetFrame::encap_ipv6(packet);
+        assert_eq!(classifier.classify(&frame), Route::Wan);
+    }
+
+    #[test]
+    fn other_frames_get_default() {
+        let classifier = PrefixClassifier::new(PrefixField::Source, Route::Drop).prefix(
+            v4(0, 0, 0, 0),
+            0,
+            Route::Wan,
+        );
+        let mut frame = ipv4_frame(Ipv4Addr::new(1, 2, 3, 4), Ipv4Addr::new(5, 6, 7, 8));
+        assert_eq!(classifier.classify(&frame), Route::Wan);
+        frame.set_ether_type(0x0806);
+        assert_eq!(classifier.classify(&frame), Route::Drop);
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_on_long_ipv4_prefix() {
+        PrefixClassifier::new(PrefixField::Source, ()).prefix(v4(10, 0, 0, 0), 33, ());
+    }
+}
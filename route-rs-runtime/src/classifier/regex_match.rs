@@ -0,0 +1,126 @@
+use crate::classifier::Classifier;
+use regex::bytes::RegexSet;
+use std::borrow::Cow;
+
+/// Classifies packets by which of a set of byte-regex patterns their payload matches.
+///
+/// Patterns are tested in the order they were provided, and the class is the index of the first
+/// pattern that matches, or `None` if the payload matches none of them. Pair this with a dispatcher
+/// that sends `None` to a default port for simple L7 routing.
+///
+/// Since the packet types do not share a common trait, the caller provides a function that
+/// borrows the payload out of the packet, for instance `UdpSegment::payload`.
+pub struct RegexMatch<P> {
+    patterns: RegexSet,
+    payload: fn(&P) -> Cow<[u8]>,
+}
+
+impl<P> RegexMatch<P> {
+    pub fn new<I, S>(patterns: I, payload: fn(&P) -> Cow<[u8]>) -> Result<Self, regex::Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Ok(RegexMatch {
+            patterns: RegexSet::new(patterns)?,
+            payload,
+        })
+    }
+
+    /// Number of patterns the classifier was built with.
+    pub fn len(&self) -> usize {
+        self.patterns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+}
+
+impl<P: Send + Clone> Classifier for RegexMatch<P> {
+    type Packet = P;
+    type Class = Option<usize>;
+
+    fn classify(&self, packet: &Self::Packet) -> Self::Class {
+        self.patterns
+            .matches(&(self.payload)(packet))
+            .into_iter()
+            .next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::primitive::ClassifyLink;
+    use crate::link::LinkBuilder;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use route_rs_packets::UdpSegment;
+
+    fn segment(payload: &[u8]) -> UdpSegment {
+        let mut segment = UdpSegment::empty();
+        segment.set_payload(payload);
+        segment
+    }
+
+    fn http_dns_classifier() -> RegexMatch<UdpSegment> {
+        RegexMatch::new(
+            [r"^(GET|POST) ", r"(?s-u)^..\x01\x00", r"HTTP/1\.1"],
+            UdpSegment::payload,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn classifies_by_first_matching_pattern() {
+        let classifier = http_dns_classifier();
+
+        assert_eq!(classifier.classify(&segment(b"GET / HTTP/1.1")), Some(0));
+        assert_eq!(classifier.classify(&segment(b"POST /form")), Some(0));
+        assert_eq!(
+            classifier.classify(&segment(&[0x00, 0x2a, 0x01, 0x00, 0x00, 0x01])),
+            Some(1)
+        );
+        assert_eq!(classifier.classify(&segment(b"HTTP/1.1 200 OK")), Some(2));
+    }
+
+    #[test]
+    fn non_matching_payload_is_default() {
+        let classifier = http_dns_classifier();
+
+        assert_eq!(classifier.classify(&segment(b"")), None);
+        assert_eq!(classifier.classify(&segment(b"SSH-2.0-OpenSSH")), None);
+    }
+
+    #[test]
+    fn invalid_pattern_is_an_error() {
+        assert!(RegexMatch::new(["(unclosed"], UdpSegment::payload).is_err());
+    }
+
+    #[test]
+    fn routes_through_classify_link() {
+        let packets = vec![
+            segment(b"GET /index.html"),
+            segment(b"garbage"),
+            segment(b"HTTP/1.1 404 Not Found"),
+        ];
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = ClassifyLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .num_egressors(2)
+                .classifier(http_dns_classifier())
+                .dispatcher(Box::new(|class| match class {
+                    Some(0) => 0,
+                    _ => 1,
+                }))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![packets[0].clone()]);
+        assert_eq!(results[1], vec![packets[1].clone(), packets[2].clone()]);
+    }
+}
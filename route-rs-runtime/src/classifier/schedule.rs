@@ -0,0 +1,300 @@
+use crate::classifier::Classifier;
+use route_rs_packets::{EthernetFrame, MacAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// A day of the week.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Weekday {
+    const ALL: [Weekday; 7] = [
+        Weekday::Monday,
+        Weekday::Tuesday,
+        Weekday::Wednesday,
+        Weekday::Thursday,
+        Weekday::Friday,
+        Weekday::Saturday,
+        Weekday::Sunday,
+    ];
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    fn previous(self) -> Weekday {
+        Weekday::ALL[(self as usize + 6) % 7]
+    }
+}
+
+/// A period of the week, and the class of packets in it, optionally only those from one device.
+#[derive(Clone, Debug)]
+pub struct ScheduleRule<Class> {
+    class: Class,
+    start: u32,
+    end: u32,
+    days: u8,
+    mac: Option<MacAddr>,
+}
+
+impl<Class> ScheduleRule<Class> {
+    /// Matches packets from `start` until `end`, as hours and minutes, every day. A period
+    /// ending before it starts, like 22:00 to 07:00, runs past midnight, and one ending when it
+    /// starts runs all day.
+    pub fn new(start: (u8, u8), end: (u8, u8), class: Class) -> Self {
+        for (hour, minute) in [start, end].iter() {
+            assert!(*hour < 24, "ScheduleRule hour: {}, must be < 24", hour);
+            assert!(
+                *minute < 60,
+                "ScheduleRule minute: {}, must be < 60",
+                minute
+            );
+        }
+
+        let minutes = |(hour, minute): (u8, u8)| u32::from(hour) * 60 + u32::from(minute);
+        ScheduleRule {
+            class,
+            start: minutes(start),
+            end: minutes(end),
+            days: 0x7f,
+            mac: None,
+        }
+    }
+
+    /// Matches only periods starting on `days`, so one from Friday 22:00 to 07:00 runs until
+    /// Saturday morning.
+    pub fn days(self, days: &[Weekday]) -> Self {
+        assert!(
+            !days.is_empty(),
+            "ScheduleRule days: {:?}, must not be empty",
+            days
+        );
+
+        ScheduleRule {
+            days: days.iter().fold(0, |mask, day| mask | day.bit()),
+            ..self
+        }
+    }
+
+    /// Matches only packets from the device with `mac`.
+    pub fn mac(self, mac: MacAddr) -> Self {
+        ScheduleRule {
+            mac: Some(mac),
+            ..self
+        }
+    }
+
+    fn matches(&self, mac: MacAddr, day: Weekday, minute: u32) -> bool {
+        if matches!(self.mac, Some(rule_mac) if rule_mac != mac) {
+            return false;
+        }
+        let started_today = self.days & day.bit() != 0;
+        let started_yesterday = self.days & day.previous().bit() != 0;
+        if self.start < self.end {
+            started_today && self.start <= minute && minute < self.end
+        } else if self.start > self.end {
+            (started_today && minute >= self.start) || (started_yesterday && minute < self.end)
+        } else {
+            started_today
+        }
+    }
+}
+
+/// Rules of a weekly schedule, in the router's local time, given as a fixed offset from UTC,
+/// and the class of packets no rule matches.
+#[derive(Clone, Debug)]
+pub struct Schedule<Class> {
+    default: Class,
+    utc_offset: i32,
+    rules: Vec<ScheduleRule<Class>>,
+}
+
+impl<Class> Schedule<Class> {
+    pub fn new(default: Class) -> Self {
+        Schedule {
+            default,
+            utc_offset: 0,
+            rules: Vec::new(),
+        }
+    }
+
+    /// Sets the local time's offset from UTC, in minutes, default 0. Offsets don't follow
+    /// daylight saving time, so set a new schedule when it starts or ends.
+    pub fn utc_offset(self, minutes: i32) -> Self {
+        assert!(
+            minutes.abs() < MINUTES_PER_DAY as i32,
+            "Schedule utc_offset: {}, must be less than a day",
+            minutes
+        );
+
+        Schedule {
+            utc_offset: minutes,
+            ..self
+        }
+    }
+
+    /// Adds a rule, which applies unless an earlier rule matches.
+    pub fn rule(mut self, rule: ScheduleRule<Class>) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Returns the class of packets from `mac` at `time`.
+    pub fn class_at(&self, mac: MacAddr, time: SystemTime) -> &Class {
+        let utc = match time.duration_since(UNIX_EPOCH) {
+            Ok(since_epoch) => since_epoch.as_secs() as i64,
+            Err(before_epoch) => -(before_epoch.duration().as_secs() as i64),
+        };
+        let local = utc + i64::from(self.utc_offset) * 60;
+        // The epoch was a Thursday.
+        let day = Weekday::ALL[(local.div_euclid(86400) + 3).rem_euclid(7) as usize];
+        let minute = (local.rem_euclid(86400) / 60) as u32;
+
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(mac, day, minute))
+            .map_or(&self.default, |rule| &rule.class)
+    }
+}
+
+/// Holds the schedule TimeOfDay classifiers follow, so the control plane can replace it while
+/// they run, taking effect from the next packet. Clones share the schedule.
+#[derive(Clone)]
+pub struct ScheduleStore<Class> {
+    schedule: Arc<Mutex<Arc<Schedule<Class>>>>,
+}
+
+impl<Class> ScheduleStore<Class> {
+    pub fn new(schedule: Schedule<Class>) -> Self {
+        ScheduleStore {
+            schedule: Arc::new(Mutex::new(Arc::new(schedule))),
+        }
+    }
+
+    /// Returns the current schedule.
+    pub fn get(&self) -> Arc<Schedule<Class>> {
+        Arc::clone(&self.schedule.lock().unwrap())
+    }
+
+    /// Replaces the schedule.
+    pub fn set(&self, schedule: Schedule<Class>) {
+        *self.schedule.lock().unwrap() = Arc::new(schedule);
+    }
+}
+
+/// Classifies frames by the current time of day, and their source MAC address, following the
+/// schedule in a ScheduleStore, for instance to block a child's devices at night.
+pub struct TimeOfDay<Class> {
+    store: ScheduleStore<Class>,
+}
+
+impl<Class> TimeOfDay<Class> {
+    pub fn new(store: ScheduleStore<Class>) -> Self {
+        TimeOfDay { store }
+    }
+}
+
+impl<Class: Clone> Classifier for TimeOfDay<Class> {
+    type Packet = EthernetFrame;
+    type Class = Class;
+
+    fn classify(&self, frame: &Self::Packet) -> Self::Class {
+        self.store
+            .get()
+            .class_at(frame.src_mac(), SystemTime::now())
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn laptop() -> MacAddr {
+        MacAddr::new([0x02, 0, 0, 0, 0, 1])
+    }
+
+    fn console() -> MacAddr {
+        MacAddr::new([0x02, 0, 0, 0, 0, 2])
+    }
+
+    /// 2024-01-05, a Friday, at `hour`:`minute` UTC.
+    fn friday(hour: u64, minute: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_704_412_800 + hour * 3600 + minute * 60)
+    }
+
+    fn bedtime() -> Schedule<bool> {
+        Schedule::new(true).rule(ScheduleRule::new((22, 0), (7, 0), false).mac(console()))
+    }
+
+    #[test]
+    fn periods_span_midnight() {
+        let schedule = bedtime();
+        assert!(*schedule.class_at(console(), friday(21, 59)));
+        assert!(!*schedule.class_at(console(), friday(22, 0)));
+        assert!(!*schedule.class_at(console(), friday(6, 59)));
+        assert!(*schedule.class_at(console(), friday(7, 0)));
+        // Other devices aren't matched.
+        assert!(*schedule.class_at(laptop(), friday(23, 0)));
+    }
+
+    #[test]
+    fn rules_apply_on_their_days() {
+        let saturday = |hour, minute| friday(hour, minute) + Duration::from_secs(86400);
+        let schedule = Schedule::new("open")
+            .rule(ScheduleRule::new((22, 0), (7, 0), "closed").days(&[Weekday::Friday]))
+            .rule(ScheduleRule::new((9, 0), (9, 0), "weekend").days(&[Weekday::Saturday]));
+
+        assert_eq!(*schedule.class_at(laptop(), friday(6, 0)), "open");
+        assert_eq!(*schedule.class_at(laptop(), friday(23, 0)), "closed");
+        // Friday night's period runs into Saturday, ahead of Saturday's rule.
+        assert_eq!(*schedule.class_at(laptop(), saturday(6, 0)), "closed");
+        assert_eq!(*schedule.class_at(laptop(), saturday(7, 0)), "weekend");
+        assert_eq!(*schedule.class_at(laptop(), saturday(23, 0)), "weekend");
+        assert_eq!(
+            *schedule.class_at(laptop(), saturday(23, 0) + Duration::from_secs(3600)),
+            "open"
+        );
+    }
+
+    #[test]
+    fn follows_utc_offset() {
+        // 20:00 UTC is 22:00 two hours east, and 15:00 five hours west.
+        let east = bedtime().utc_offset(120);
+        assert!(!*east.class_at(console(), friday(20, 0)));
+        let west = bedtime().utc_offset(-300);
+        assert!(*west.class_at(console(), friday(20, 0)));
+        assert!(!*west.class_at(console(), friday(3, 30)));
+    }
+
+    #[test]
+    fn store_reloads() {
+        let store = ScheduleStore::new(Schedule::new(1));
+        let classifier = TimeOfDay::new(store.clone());
+        let mut frame = EthernetFrame::empty();
+        frame.set_src_mac(laptop());
+        assert_eq!(classifier.classify(&frame), 1);
+
+        store.set(Schedule::new(0).rule(ScheduleRule::new((0, 0), (0, 0), 2).mac(laptop())));
+        assert_eq!(classifier.classify(&frame), 2);
+        frame.set_src_mac(console());
+        assert_eq!(classifier.classify(&frame), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_on_bad_hour() {
+        ScheduleRule::new((24, 0), (7, 0), ());
+    }
+}
@@ -0,0 +1,340 @@
+use crate::classifier::Classifier;
+use route_rs_packets::{EthernetFrame, IPV4_ETHER_TYPE, IPV6_ETHER_TYPE};
+
+const TCP_PROTOCOL: u8 = 6;
+const HANDSHAKE_CONTENT_TYPE: u8 = 22;
+const CLIENT_HELLO: u8 = 1;
+const SERVER_NAME_EXTENSION: u16 = 0;
+const HOST_NAME_TYPE: u8 = 0;
+
+/// Splits `n` bytes off the front of `data`.
+fn take<'a>(data: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+    if data.len() < n {
+        return None;
+    }
+    let (taken, rest) = data.split_at(n);
+    *data = rest;
+    Some(taken)
+}
+
+fn take_u8(data: &mut &[u8]) -> Option<u8> {
+    take(data, 1).map(|bytes| bytes[0])
+}
+
+fn take_u16(data: &mut &[u8]) -> Option<u16> {
+    take(data, 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// Skips a vector with a length of `len_bytes` bytes in front.
+fn skip_vector(data: &mut &[u8], len_bytes: usize) -> Option<()> {
+    let len = take(data, len_bytes)?
+        .iter()
+        .fold(0, |len, byte| len << 8 | usize::from(*byte));
+    take(data, len).map(|_| ())
+}
+
+/// Returns the host name a TLS client asked for in the server name extension of its ClientHello,
+/// if `payload`, a TCP payload, starts with one. The name is lowercased, without a trailing dot.
+/// Only the part of the ClientHello in `payload` is read, which almost always holds the name.
+pub fn tls_server_name(payload: &[u8]) -> Option<String> {
+    let mut data = payload;
+    if take_u8(&mut data)? != HANDSHAKE_CONTENT_TYPE {
+        return None;
+    }
+    take(&mut data, 2)?;
+    let record_len = usize::from(take_u16(&mut data)?);
+    data = &data[..record_len.min(data.len())];
+
+    if take_u8(&mut data)? != CLIENT_HELLO {
+        return None;
+    }
+    // The handshake length, version and random.
+    take(&mut data, 3 + 2 + 32)?;
+    // The session ID, cipher suites and compression methods.
+    skip_vector(&mut data, 1)?;
+    skip_vector(&mut data, 2)?;
+    skip_vector(&mut data, 1)?;
+
+    let extensions_len = usize::from(take_u16(&mut data)?);
+    data = &data[..extensions_len.min(data.len())];
+    while !data.is_empty() {
+        let extension_type = take_u16(&mut data)?;
+        let extension_len = usize::from(take_u16(&mut data)?);
+        let mut extension = take(&mut data, extension_len)?;
+        if extension_type != SERVER_NAME_EXTENSION {
+            continue;
+        }
+
+        take_u16(&mut extension)?;
+        while !extension.is_empty() {
+            let name_type = take_u8(&mut extension)?;
+            let name_len = usize::from(take_u16(&mut extension)?);
+            let name = take(&mut extension, name_len)?;
+            if name_type == HOST_NAME_TYPE {
+                let name = std::str::from_utf8(name).ok()?;
+                if name.is_empty() || !name.is_ascii() {
+                    return None;
+                }
+                return Some(name.trim_end_matches('.').to_ascii_lowercase());
+            }
+        }
+        return None;
+    }
+    None
+}
+
+/// The TCP payload of the frame, if it's IPv4 or IPv6 and carries TCP, and isn't a fragment
+/// past the first.
+pub(crate) fn tcp_payload(frame: &EthernetFrame) -> Option<&[u8]> {
+    let ip = frame.data.get(frame.payload_offset..)?;
+    let tcp = match frame.ether_type() {
+        IPV4_ETHER_TYPE => {
+            let header_len = usize::from(*ip.first()? & 0x0f) * 4;
+            let fragment_offset = u16::from_be_bytes([*ip.get(6)?, *ip.get(7)?]) & 0x1fff;
+            if *ip.get(9)? != TCP_PROTOCOL || fragment_offset != 0 {
+                return None;
+            }
+            let total_len = usize::from(u16::from_be_bytes([ip[2], ip[3]]));
+            ip.get(header_len..total_len.min(ip.len()))?
+        }
+        IPV6_ETHER_TYPE => {
+            if *ip.get(6)? != TCP_PROTOCOL {
+                return None;
+            }
+            let payload_len = usize::from(u16::from_be_bytes([ip[4], ip[5]]));
+            ip.get(40..(40 + payload_len).min(ip.len()))?
+        }
+        _ => return None,
+    };
+    let header_len = usize::from(*tcp.get(12)? >> 4) * 4;
+    tcp.get(header_len..)
+}
+
+/// A pattern of host names: either a name, matching only itself, or `*.` and a domain, matching
+/// every name under the domain but not the domain itself.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum HostPattern {
+    Exact(String),
+    Subdomains(String),
+}
+
+impl HostPattern {
+    /// Parses `pattern`, ignoring case and a trailing dot, if it's valid.
+    pub(crate) fn new(pattern: &str) -> Option<Self> {
+        let pattern = pattern.trim_end_matches('.').to_ascii_lowercase();
+        let pattern = match pattern.strip_prefix("*.") {
+            Some(domain) => HostPattern::Subdomains(domain.to_string()),
+            None => HostPattern::Exact(pattern),
+        };
+        match &pattern {
+            HostPattern::Exact(name) | HostPattern::Subdomains(name)
+                if name.is_empty() || name.contains('*') =>
+            {
+                None
+            }
+            _ => Some(pattern),
+        }
+    }
+
+    /// Whether `host`, lowercased and without a trailing dot, matches.
+    pub(crate) fn matches(&self, host: &str) -> bool {
+        match self {
+            HostPattern::Exact(name) => host == name,
+            HostPattern::Subdomains(domain) => {
+                host.len() > domain.len() + 1
+                    && host.ends_with(domain.as_str())
+                    && host.as_bytes()[host.len() - domain.len() - 1] == b'.'
+            }
+        }
+    }
+}
+
+/// Classifies frames carrying a TLS ClientHello by the host name it asks for, in its server name
+/// indication (SNI), into the class of the first pattern given that matches it, or into `default`
+/// if none do, or the frame isn't the start of a ClientHello with a name. Useful for per-site
+/// policy, such as blocklists or QoS, without terminating TLS.
+///
+/// Only the first segment of each connection's ClientHello holds the name, so the classifier is
+/// best paired with connection tracking, which applies the class to the rest of the connection.
+pub struct TlsSni<Class> {
+    default: Class,
+    patterns: Vec<(HostPattern, Class)>,
+}
+
+impl<Class: Clone> TlsSni<Class> {
+    pub fn new(default: Class) -> Self {
+        TlsSni {
+            default,
+            patterns: Vec::new(),
+        }
+    }
+
+    /// Classifies names matching `pattern`, as `example.com` or `*.example.com`, as `class`,
+    /// unless an earlier pattern matches them. Patterns are matched ignoring case.
+    pub fn host(mut self, pattern: &str, class: Class) -> Self {
+        let host_pattern = HostPattern::new(pattern);
+        assert!(
+            host_pattern.is_some(),
+            "TlsSni pattern: {:?}, must be a name, or *. and a domain",
+            pattern
+        );
+
+        self.patterns.push((host_pattern.unwrap(), class));
+        self
+    }
+
+    /// Returns the class of the host name `host`.
+    pub fn lookup(&self, host: &str) -> &Class {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        self.patterns
+            .iter()
+            .find(|(pattern, _)| pattern.matches(&host))
+            .map_or(&self.default, |(_, class)| class)
+    }
+}
+
+impl<Class: Clone> Classifier for TlsSni<Class> {
+    type Packet = EthernetFrame;
+    type Class = Class;
+
+    fn classify(&self, frame: &Self::Packet) -> Self::Class {
+        match tcp_payload(frame).and_then(tls_server_name) {
+            Some(host) => self.lookup(&host).clone(),
+            None => self.default.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use route_rs_packets::{Ipv4Packet, Ipv6Packet, TcpSegment};
+    use std::net::Ipv4Addr;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum Policy {
+        Allow,
+        Block,
+        Priority,
+    }
+
+    /// A ClientHello, as sent by a browser, asking for `host` if given.
+    fn client_hello(host: Option<&str>) -> Vec<u8> {
+        let mut extensions = Vec::new();
+        // Supported groups, ahead of the server name, as some clients send them.
+        extensions.extend_from_slice(&[0x00, 0x0a, 0x00, 0x04, 0x00, 0x02, 0x00, 0x1d]);
+        if let Some(host) = host {
+            let len = host.len() as u16;
+            extensions.extend_from_slice(&[0x00, 0x00]);
+            extensions.extend_from_slice(&(len + 5).to_be_bytes());
+            extensions.extend_from_slice(&(len + 3).to_be_bytes());
+            extensions.push(HOST_NAME_TYPE);
+            extensions.extend_from_slice(&len.to_be_bytes());
+            extensions.extend_from_slice(host.as_bytes());
+        }
+
+        let mut hello = vec![0x03, 0x03];
+        hello.extend_from_slice(&[0xab; 32]);
+        hello.push(32);
+        hello.extend_from_slice(&[0xcd; 32]);
+        hello.extend_from_slice(&[0x00, 0x04, 0x13, 0x01, 0x13, 0x02]);
+        hello.extend_from_slice(&[0x01, 0x00]);
+        hello.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        hello.extend_from_slice(&extensions);
+
+        let mut handshake = vec![CLIENT_HELLO];
+        handshake.extend_from_slice(&(hello.len() as u32).to_be_bytes()[1..]);
+        handshake.extend_from_slice(&hello);
+
+        let mut record = vec![HANDSHAKE_CONTENT_TYPE, 0x03, 0x01];
+        record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+        record.extend_from_slice(&handshake);
+        record
+    }
+
+    fn ipv4_frame(payload: &[u8]) -> EthernetFrame {
+        let mut segment = TcpSegment::empty();
+        segment.set_dest_port(443);
+        segment.set_payload(payload);
+        let mut packet = Ipv4Packet::empty();
+        packet.set_src_addr(Ipv4Addr::new(192, 168, 1, 20));
+        packet.set_dest_addr(Ipv4Addr::new(203, 0, 113, 5));
+        packet.set_protocol(TCP_PROTOCOL);
+        packet.set_payload(&segment.data[segment.layer4_offset..]);
+        EthernetFrame::encap_ipv4(packet)
+    }
+
+    fn classifier() -> TlsSni<Policy> {
+        TlsSni::new(Policy::Allow)
+            .host("ads.example.com", Policy::Allow)
+            .host("*.example.com", Policy::Block)
+            .host("Video.Example.net.", Policy::Priority)
+    }
+
+    #[test]
+    fn extracts_server_name() {
+        assert_eq!(
+            tls_server_name(&client_hello(Some("Www.Example.COM."))),
+            Some("www.example.com".to_string())
+        );
+        assert_eq!(tls_server_name(&client_hello(None)), None);
+        assert_eq!(tls_server_name(b"GET / HTTP/1.1\r\n"), None);
+
+        // A ClientHello cut short inside the name doesn't yield it; extensions after it are
+        // skipped.
+        let hello = client_hello(Some("example.org"));
+        assert_eq!(tls_server_name(&hello[..hello.len() - 1]), None);
+        let mut padded = hello.clone();
+        padded.extend_from_slice(&[0x00, 0x15, 0x00, 0x02, 0x00, 0x00]);
+        assert_eq!(tls_server_name(&padded), Some("example.org".to_string()));
+    }
+
+    #[test]
+    fn first_matching_pattern_wins() {
+        let classifier = classifier();
+        assert_eq!(*classifier.lookup("ads.example.com"), Policy::Allow);
+        assert_eq!(*classifier.lookup("tracker.ads.example.com"), Policy::Block);
+        assert_eq!(*classifier.lookup("www.example.com"), Policy::Block);
+        // Subdomain patterns don't match the domain, or names that merely end like it.
+        assert_eq!(*classifier.lookup("example.com"), Policy::Allow);
+        assert_eq!(*classifier.lookup("badexample.com"), Policy::Allow);
+        assert_eq!(*classifier.lookup("VIDEO.example.net"), Policy::Priority);
+    }
+
+    #[test]
+    fn classifies_client_hellos() {
+        let classifier = classifier();
+        let frame = ipv4_frame(&client_hello(Some("www.example.com")));
+        assert_eq!(classifier.classify(&frame), Policy::Block);
+        let frame = ipv4_frame(&client_hello(Some("video.example.net")));
+        assert_eq!(classifier.classify(&frame), Policy::Priority);
+
+        let mut segment = TcpSegment::empty();
+        segment.set_payload(&client_hello(Some("www.example.com")));
+        let mut packet = Ipv6Packet::empty();
+        packet.set_next_header(TCP_PROTOCOL);
+        packet.set_payload(&segment.data[segment.layer4_offset..]);
+        let frame = EthernetFrame::encap_ipv6(packet);
+        assert_eq!(classifier.classify(&frame), Policy::Block);
+    }
+
+    #[test]
+    fn other_frames_get_default() {
+        let classifier = classifier();
+        assert_eq!(classifier.classify(&ipv4_frame(&[])), Policy::Allow);
+        assert_eq!(
+            classifier.classify(&ipv4_frame(&[0x17, 0x03, 0x03])),
+            Policy::Allow
+        );
+
+        let mut frame = ipv4_frame(&client_hello(Some("www.example.com")));
+        frame.data[frame.payload_offset + 9] = 17;
+        assert_eq!(classifier.classify(&frame), Policy::Allow);
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_on_inner_wildcard() {
+        TlsSni::new(()).host("www.*.com", ());
+    }
+}
@@ -14,6 +14,9 @@ pub mod classifier;
 /// Wrappers around Processors and Classfiers, and implement all the movement of Packets through the Router.
 pub mod link;
 
+/// Pools of packet buffers, recycled rather than allocated for each packet received.
+pub mod pool;
+
 /// Structure meant to encapsulate a router as and input and output channel. Used by graphgen.
 pub mod pipeline;
 
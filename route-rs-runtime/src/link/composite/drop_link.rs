@@ -109,7 +109,7 @@ mod tests {
     fn finishes() {
         let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let link = DropLink::new()
                 .ingressor(immediate_stream(packets))
@@ -124,7 +124,7 @@ mod tests {
     fn finishes_with_wait() {
         let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let packet_generator =
                 PacketIntervalGenerator::new(time::Duration::from_millis(10), packets.into_iter());
@@ -140,7 +140,7 @@ mod tests {
 
     #[test]
     fn drops_odd_packets() {
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let packet_generator = immediate_stream(vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9]);
 
@@ -165,7 +165,7 @@ mod tests {
     fn drops_randomly() {
         let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let link: Link<i32> = DropLink::new()
                 .ingressor(immediate_stream(packets))
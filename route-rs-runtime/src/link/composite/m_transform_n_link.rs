@@ -168,7 +168,7 @@ mod tests {
     fn transform_m_streams_on_to_n_egress_streams() {
         let packets = vec![0xDEAD_BEEF, 0xBEEF_DEAD, 0x0A00_0001, 0xFFFF_FFFF];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let mut input_streams: Vec<PacketStream<u32>> = Vec::new();
             input_streams.push(immediate_stream(packets.clone()));
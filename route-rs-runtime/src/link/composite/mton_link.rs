@@ -139,7 +139,7 @@ mod tests {
     fn clone_m_streams_on_to_n_egress_streams() {
         let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9, 11];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let mut input_streams: Vec<PacketStream<usize>> = Vec::new();
             input_streams.push(immediate_stream(packets.clone()));
@@ -0,0 +1,188 @@
+use std::fmt::Write;
+
+/// The topology of a router's named links, as recorded by a `LinkRegistry` while they were built:
+/// which links there are, and which egressor of which link feeds which ingressor of which. Taken
+/// from a running router with `LinkRegistry::graph`, it shows what the deployed pipeline actually
+/// looks like, and exports as DOT, for Graphviz, or JSON. Only connections directly between named
+/// links are recorded, so the inputs of a link fed by an unnamed link, or from outside the
+/// router, are counted in its `inputs` but have no edge.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+/// A named link.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Node {
+    pub name: String,
+    /// The type of the link, such as `route_rs_runtime::link::primitive::queue_link::QueueLink<...>`.
+    pub kind: String,
+    /// The number of ingressors the link was built with.
+    pub inputs: usize,
+    /// The number of egressors the link was built with.
+    pub outputs: usize,
+}
+
+/// Egressor `from_port` of link `from` feeds ingressor `to_port` of link `to`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Edge {
+    pub from: String,
+    pub from_port: usize,
+    pub to: String,
+    pub to_port: usize,
+}
+
+impl Graph {
+    /// Returns the graph in the DOT language, labelling each link with its name and the short
+    /// name of its type, and each edge with its ports.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph {\n");
+        for node in self.nodes.iter() {
+            let label = format!("{}\n{}", node.name, short_type_name(&node.kind));
+            writeln!(dot, "    {} [label={}];", quote(&node.name), quote(&label)).unwrap();
+        }
+        for edge in self.edges.iter() {
+            writeln!(
+                dot,
+                "    {} -> {} [taillabel=\"{}\", headlabel=\"{}\"];",
+                quote(&edge.from),
+                quote(&edge.to),
+                edge.from_port,
+                edge.to_port
+            )
+            .unwrap();
+        }
+        dot.push('}');
+        dot
+    }
+
+    /// Returns the graph as a JSON object, with `nodes` and `edges` arrays of objects with the
+    /// fields of `Node` and `Edge`.
+    pub fn to_json(&self) -> String {
+        let nodes: Vec<String> = self
+            .nodes
+            .iter()
+            .map(|node| {
+                format!(
+                    "{{\"name\":{},\"kind\":{},\"inputs\":{},\"outputs\":{}}}",
+                    quote(&node.name),
+                    quote(&node.kind),
+                    node.inputs,
+                    node.outputs
+                )
+            })
+            .collect();
+        let edges: Vec<String> = self
+            .edges
+            .iter()
+            .map(|edge| {
+                format!(
+                    "{{\"from\":{},\"from_port\":{},\"to\":{},\"to_port\":{}}}",
+                    quote(&edge.from),
+                    edge.from_port,
+                    quote(&edge.to),
+                    edge.to_port
+                )
+            })
+            .collect();
+        format!(
+            "{{\"nodes\":[{}],\"edges\":[{}]}}",
+            nodes.join(","),
+            edges.join(",")
+        )
+    }
+}
+
+/// Quotes `s`, escaping backslashes, quotes and control characters as both DOT and JSON read
+/// them.
+fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '"' => quoted.push_str("\\\""),
+            c if c.is_control() => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Drops the module paths from the type names in `kind`, so that
+/// `route_rs_runtime::link::primitive::QueueLink<route_rs_runtime::processor::Identity<i32>>`
+/// becomes `QueueLink<Identity<i32>>`.
+fn short_type_name(kind: &str) -> String {
+    let mut short = String::with_capacity(kind.len());
+    let mut segment_start = 0;
+    let mut chars = kind.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == ':' && chars.peek() == Some(&':') {
+            chars.next();
+            short.truncate(segment_start);
+        } else {
+            short.push(c);
+            if !(c.is_alphanumeric() || c == '_') {
+                segment_start = short.len();
+            }
+        }
+    }
+    short
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph() -> Graph {
+        Graph {
+            nodes: vec![
+                Node {
+                    name: "classify".to_string(),
+                    kind: "route_rs_runtime::link::primitive::ClassifyLink<a::B>".to_string(),
+                    inputs: 1,
+                    outputs: 2,
+                },
+                Node {
+                    name: "wan \"egress\"".to_string(),
+                    kind: "route_rs_runtime::link::primitive::QueueLink<a::B>".to_string(),
+                    inputs: 1,
+                    outputs: 1,
+                },
+            ],
+            edges: vec![Edge {
+                from: "classify".to_string(),
+                from_port: 1,
+                to: "wan \"egress\"".to_string(),
+                to_port: 0,
+            }],
+        }
+    }
+
+    #[test]
+    fn dot() {
+        assert_eq!(
+            graph().to_dot(),
+            "digraph {\n    \
+             \"classify\" [label=\"classify\\nClassifyLink<B>\"];\n    \
+             \"wan \\\"egress\\\"\" [label=\"wan \\\"egress\\\"\\nQueueLink<B>\"];\n    \
+             \"classify\" -> \"wan \\\"egress\\\"\" [taillabel=\"1\", headlabel=\"0\"];\n\
+             }"
+        );
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(
+            graph().to_json(),
+            "{\"nodes\":[\
+             {\"name\":\"classify\",\"kind\":\"route_rs_runtime::link::primitive::ClassifyLink<a::B>\",\"inputs\":1,\"outputs\":2},\
+             {\"name\":\"wan \\\"egress\\\"\",\"kind\":\"route_rs_runtime::link::primitive::QueueLink<a::B>\",\"inputs\":1,\"outputs\":1}],\
+             \"edges\":[{\"from\":\"classify\",\"from_port\":1,\"to\":\"wan \\\"egress\\\"\",\"to_port\":0}]}"
+        );
+        assert_eq!(Graph::default().to_json(), "{\"nodes\":[],\"edges\":[]}");
+    }
+}
@@ -12,6 +12,7 @@
 //! Links by composing `Primitives` and other `CompositeLinks` together. This prevents the user from having to worry about the complexities of generically
 //! chaining asynchronous computation together around Channels; freeing you to focus on the business logic you would like your router to implement.
 
+use crate::link::registry::{LinkHandle, Named};
 use crate::processor::Processor;
 
 /// Composites are groups of links pre-assmebled to provide higher level functionality. They are highly customizable and users of the
@@ -23,6 +24,18 @@ pub mod composite;
 /// their own custom composite links.
 pub mod primitive;
 
+/// The topology of a router's named links, recorded as they're built, for exporting as DOT or
+/// JSON.
+pub mod graph;
+
+/// A registry mapping the names of links to their handles, such as stats and controls, so a router's
+/// links can be found by name once it's built.
+pub mod registry;
+
+/// Wrapper recording the hops of traced packets through a link, for following a packet's path
+/// through the router.
+pub mod trace;
+
 /// Commmon utilities used by links, for instance the `task_park` utility used in primitive links to facilite sleeping and waking.
 pub mod utils;
 
@@ -53,6 +66,21 @@ pub trait LinkBuilder<Input, Output> {
     /// `Link`s to use. This method consumes the `Link` since we want to move ownership of a `Link`'s
     /// runnables and egressors to the caller.
     fn build_link(self) -> Link<Output>;
+
+    /// Returns handles to the link, such as its stats or control, for a `LinkRegistry` to hand out
+    /// by name once the link is built. Links without any return none.
+    fn handles(&self) -> Vec<LinkHandle> {
+        vec![]
+    }
+
+    /// Names the link, so that once built it, and its handles, can be found by name in the global
+    /// `LinkRegistry`, or another given to the returned `Named` link.
+    fn name(self, name: &str) -> Named<Self>
+    where
+        Self: Sized,
+    {
+        Named::new(self, name)
+    }
 }
 
 /// `ProcessLink` and `QueueLink` impl `ProcessLinkBuilder`, since they are required to have their
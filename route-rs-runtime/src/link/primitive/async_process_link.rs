@@ -0,0 +1,282 @@
+use crate::link::primitive::QueueEgressor;
+use crate::link::utils::task_park::*;
+use crate::link::{Link, LinkBuilder, PacketStream};
+use crate::processor::AsyncProcessor;
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::Sender;
+use futures::prelude::*;
+use futures::stream::FuturesOrdered;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Runs packets through an AsyncProcessor, with up to `concurrency` packets being processed at
+/// once. Packets leave in the order they arrived, so a slow packet holds up those behind it, and
+/// once `concurrency` packets are in flight, no more are taken from the input until the oldest
+/// finishes. Processed packets are queued for the egressor, as in QueueLink.
+pub struct AsyncProcessLink<P: AsyncProcessor> {
+    in_stream: Option<PacketStream<P::Input>>,
+    processor: Option<P>,
+    concurrency: usize,
+    queue_capacity: usize,
+}
+
+impl<P: AsyncProcessor> Default for AsyncProcessLink<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: AsyncProcessor> AsyncProcessLink<P> {
+    pub fn new() -> Self {
+        AsyncProcessLink {
+            in_stream: None,
+            processor: None,
+            concurrency: 1,
+            queue_capacity: 10,
+        }
+    }
+
+    pub fn processor(self, processor: P) -> Self {
+        AsyncProcessLink {
+            in_stream: self.in_stream,
+            processor: Some(processor),
+            concurrency: self.concurrency,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Changes concurrency, the most packets processed at once, default value is 1.
+    pub fn concurrency(self, concurrency: usize) -> Self {
+        assert!(
+            concurrency > 0,
+            "AsyncProcessLink concurrency: {}, must be > 0",
+            concurrency
+        );
+
+        AsyncProcessLink {
+            in_stream: self.in_stream,
+            processor: self.processor,
+            concurrency,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Changes queue_capacity, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "AsyncProcessLink queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        AsyncProcessLink {
+            in_stream: self.in_stream,
+            processor: self.processor,
+            concurrency: self.concurrency,
+            queue_capacity,
+        }
+    }
+}
+
+impl<P: AsyncProcessor + Send + 'static> LinkBuilder<P::Input, P::Output> for AsyncProcessLink<P> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<P::Input>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "AsyncProcessLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("AsyncProcessLink may only take 1 input stream")
+        }
+
+        AsyncProcessLink {
+            in_stream: Some(in_streams.remove(0)),
+            processor: self.processor,
+            concurrency: self.concurrency,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<P::Input>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("AsyncProcessLink may only take 1 input stream")
+        }
+
+        AsyncProcessLink {
+            in_stream: Some(in_stream),
+            processor: self.processor,
+            concurrency: self.concurrency,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn build_link(self) -> Link<P::Output> {
+        match (self.in_stream, self.processor) {
+            (None, _) => panic!("Cannot build link! Missing input stream"),
+            (_, None) => panic!("Cannot build link! Missing processor"),
+            (Some(in_stream), Some(processor)) => {
+                let (to_egressor, from_ingressor) =
+                    crossbeam_channel::bounded::<Option<P::Output>>(self.queue_capacity);
+                let task_park: Arc<AtomicCell<TaskParkState>> =
+                    Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+                let ingressor = AsyncProcessIngressor {
+                    input_stream: in_stream,
+                    input_finished: false,
+                    to_egressor,
+                    task_park: Arc::clone(&task_park),
+                    processor,
+                    in_flight: FuturesOrdered::new(),
+                    concurrency: self.concurrency,
+                };
+                let egressor = QueueEgressor::new(from_ingressor, task_park);
+
+                (vec![Box::new(ingressor)], vec![Box::new(egressor)])
+            }
+        }
+    }
+}
+
+struct AsyncProcessIngressor<P: AsyncProcessor> {
+    input_stream: PacketStream<P::Input>,
+    input_finished: bool,
+    to_egressor: Sender<Option<P::Output>>,
+    task_park: Arc<AtomicCell<TaskParkState>>,
+    processor: P,
+    in_flight: FuturesOrdered<P::Future>,
+    concurrency: usize,
+}
+
+impl<P: AsyncProcessor> Unpin for AsyncProcessIngressor<P> {}
+
+impl<P: AsyncProcessor> Future for AsyncProcessIngressor<P> {
+    type Output = ();
+
+    /// Same logic as QueueIngressor, except that we start processing every packet the input has
+    /// ready, up to `concurrency`, then send on whichever packets have finished processing, in
+    /// order. We are woken by the input, or by the packets being processed.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let ingressor = Pin::into_inner(self);
+        loop {
+            if ingressor.to_egressor.is_full() {
+                park_and_wake(&ingressor.task_park, cx.waker().clone());
+                return Poll::Pending;
+            }
+
+            while !ingressor.input_finished && ingressor.in_flight.len() < ingressor.concurrency {
+                match Pin::new(&mut ingressor.input_stream).poll_next(cx) {
+                    Poll::Ready(Some(packet)) => {
+                        let processing = ingressor.processor.process(packet);
+                        ingressor.in_flight.push_back(processing);
+                    }
+                    Poll::Ready(None) => ingressor.input_finished = true,
+                    Poll::Pending => break,
+                }
+            }
+
+            match ready!(Pin::new(&mut ingressor.in_flight).poll_next(cx)) {
+                Some(Some(packet)) => {
+                    ingressor.to_egressor.try_send(Some(packet)).expect(
+                        "AsyncProcessIngressor::Poll::Ready(Some(val)) try_send to_egressor shouldn't fail",
+                    );
+                    unpark_and_wake(&ingressor.task_park);
+                }
+                Some(None) => {}
+                None => {
+                    if !ingressor.input_finished {
+                        return Poll::Pending;
+                    }
+                    ingressor.to_egressor.try_send(None).expect(
+                        "AsyncProcessIngressor::Poll::Ready(None) try_send to_egressor shouldn't fail",
+                    );
+                    die_and_wake(&ingressor.task_park);
+                    return Poll::Ready(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use std::time::{Duration, Instant};
+    use tokio::time::sleep;
+
+    /// Waits `packet` milliseconds, then drops odd packets.
+    struct SleepyEven {}
+
+    impl AsyncProcessor for SleepyEven {
+        type Input = u64;
+        type Output = u64;
+        type Future = Pin<Box<dyn Future<Output = Option<u64>> + Send>>;
+
+        fn process(&mut self, packet: Self::Input) -> Self::Future {
+            Box::pin(async move {
+                sleep(Duration::from_millis(packet)).await;
+                if packet % 2 == 0 {
+                    Some(packet)
+                } else {
+                    None
+                }
+            })
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_processor() {
+        AsyncProcessLink::<SleepyEven>::new()
+            .ingressor(immediate_stream(vec![]))
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_with_zero_concurrency() {
+        AsyncProcessLink::<SleepyEven>::new().concurrency(0);
+    }
+
+    #[test]
+    fn processes_in_order() {
+        let packets: Vec<u64> = vec![40, 2, 30, 4, 5, 20, 0, 1];
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = AsyncProcessLink::new()
+                .ingressor(immediate_stream(packets))
+                .processor(SleepyEven {})
+                .concurrency(3)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![40, 2, 30, 4, 20, 0]);
+    }
+
+    #[test]
+    fn processes_concurrently() {
+        let packets: Vec<u64> = vec![50; 8];
+
+        let runtime = initialize_runtime();
+        let (results, elapsed) = runtime.block_on(async {
+            let start = Instant::now();
+            let link = AsyncProcessLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .processor(SleepyEven {})
+                .concurrency(8)
+                .build_link();
+
+            let results = run_link(link).await;
+            (results, start.elapsed())
+        });
+        assert_eq!(results[0], packets);
+        // One at a time would take 400ms.
+        assert!(elapsed < Duration::from_millis(300), "{:?}", elapsed);
+    }
+}
@@ -0,0 +1,480 @@
+use crate::link::{Link, LinkBuilder, PacketStream};
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::time::{sleep, Sleep};
+
+/// Groups packets into batches of up to `batch_size`, so that downstream links move a `Vec` of
+/// packets through their channels at a time, amortizing the per packet cost of channel operations
+/// and wakeups. If a `timeout` is set, a partial batch is released once its first packet has waited
+/// that long; otherwise partial batches are only released when the input finishes. Like
+/// ProcessLink, this link only does work when it is polled.
+#[derive(Default)]
+pub struct BatchLink<Packet> {
+    in_stream: Option<PacketStream<Packet>>,
+    batch_size: Option<usize>,
+    timeout: Option<Duration>,
+}
+
+impl<Packet> BatchLink<Packet> {
+    pub fn new() -> Self {
+        BatchLink {
+            in_stream: None,
+            batch_size: None,
+            timeout: None,
+        }
+    }
+
+    pub fn batch_size(self, batch_size: usize) -> Self {
+        assert!(
+            batch_size > 0,
+            "BatchLink batch_size: {}, must be > 0",
+            batch_size
+        );
+
+        BatchLink {
+            in_stream: self.in_stream,
+            batch_size: Some(batch_size),
+            timeout: self.timeout,
+        }
+    }
+
+    pub fn timeout(self, timeout: Duration) -> Self {
+        BatchLink {
+            in_stream: self.in_stream,
+            batch_size: self.batch_size,
+            timeout: Some(timeout),
+        }
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Packet, Vec<Packet>> for BatchLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "BatchLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("BatchLink may only take 1 input stream")
+        }
+
+        BatchLink {
+            in_stream: Some(in_streams.remove(0)),
+            batch_size: self.batch_size,
+            timeout: self.timeout,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("BatchLink may only take 1 input stream")
+        }
+
+        BatchLink {
+            in_stream: Some(in_stream),
+            batch_size: self.batch_size,
+            timeout: self.timeout,
+        }
+    }
+
+    fn build_link(self) -> Link<Vec<Packet>> {
+        match (self.in_stream, self.batch_size) {
+            (None, _) => panic!("Cannot build link! Missing input streams"),
+            (_, None) => panic!("Cannot build link! Missing batch_size"),
+            (Some(in_stream), Some(batch_size)) => {
+                let batcher = Batcher {
+                    in_stream: Some(in_stream),
+                    batch: Vec::with_capacity(batch_size),
+                    batch_size,
+                    timeout: self.timeout,
+                    idle: false,
+                    timer: None,
+                };
+                (vec![], vec![Box::new(batcher)])
+            }
+        }
+    }
+}
+
+/// Like BatchLink, but rather than from the first packet of a batch, `idle_timeout` runs from the
+/// most recent packet. A batch is released as soon as it fills, or once the input has gone quiet
+/// for `idle_timeout`, so the tail of a burst is not left waiting on the next one, while a steady
+/// stream of packets still makes full batches. Like ProcessLink, this link only does work when it
+/// is polled.
+#[derive(Default)]
+pub struct TimeoutFlushLink<Packet> {
+    in_stream: Option<PacketStream<Packet>>,
+    batch_size: Option<usize>,
+    idle_timeout: Option<Duration>,
+}
+
+impl<Packet> TimeoutFlushLink<Packet> {
+    pub fn new() -> Self {
+        TimeoutFlushLink {
+            in_stream: None,
+            batch_size: None,
+            idle_timeout: None,
+        }
+    }
+
+    pub fn batch_size(self, batch_size: usize) -> Self {
+        assert!(
+            batch_size > 0,
+            "TimeoutFlushLink batch_size: {}, must be > 0",
+            batch_size
+        );
+
+        TimeoutFlushLink {
+            in_stream: self.in_stream,
+            batch_size: Some(batch_size),
+            idle_timeout: self.idle_timeout,
+        }
+    }
+
+    pub fn idle_timeout(self, idle_timeout: Duration) -> Self {
+        TimeoutFlushLink {
+            in_stream: self.in_stream,
+            batch_size: self.batch_size,
+            idle_timeout: Some(idle_timeout),
+        }
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Packet, Vec<Packet>> for TimeoutFlushLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "TimeoutFlushLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("TimeoutFlushLink may only take 1 input stream")
+        }
+
+        TimeoutFlushLink {
+            in_stream: Some(in_streams.remove(0)),
+            batch_size: self.batch_size,
+            idle_timeout: self.idle_timeout,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("TimeoutFlushLink may only take 1 input stream")
+        }
+
+        TimeoutFlushLink {
+            in_stream: Some(in_stream),
+            batch_size: self.batch_size,
+            idle_timeout: self.idle_timeout,
+        }
+    }
+
+    fn build_link(self) -> Link<Vec<Packet>> {
+        match (self.in_stream, self.batch_size, self.idle_timeout) {
+            (None, _, _) => panic!("Cannot build link! Missing input streams"),
+            (_, None, _) => panic!("Cannot build link! Missing batch_size"),
+            (_, _, None) => panic!("Cannot build link! Missing idle_timeout"),
+            (Some(in_stream), Some(batch_size), Some(idle_timeout)) => {
+                let batcher = Batcher {
+                    in_stream: Some(in_stream),
+                    batch: Vec::with_capacity(batch_size),
+                    batch_size,
+                    timeout: Some(idle_timeout),
+                    idle: true,
+                    timer: None,
+                };
+                (vec![], vec![Box::new(batcher)])
+            }
+        }
+    }
+}
+
+/// The single egressor of BatchLink and TimeoutFlushLink
+struct Batcher<Packet> {
+    /// Set to `None` once the upstream has finished.
+    in_stream: Option<PacketStream<Packet>>,
+    batch: Vec<Packet>,
+    batch_size: usize,
+    timeout: Option<Duration>,
+    /// Whether `timeout` restarts with every packet, rather than running from the first packet of
+    /// each batch.
+    idle: bool,
+    /// Deadline for the current partial batch.
+    timer: Option<Pin<Box<Sleep>>>,
+}
+
+impl<Packet> Unpin for Batcher<Packet> {}
+
+impl<Packet> Batcher<Packet> {
+    fn take_batch(&mut self) -> Vec<Packet> {
+        self.timer = None;
+        std::mem::replace(&mut self.batch, Vec::with_capacity(self.batch_size))
+    }
+}
+
+impl<Packet> Stream for Batcher<Packet> {
+    type Item = Vec<Packet>;
+
+    /// Pulls packets until the batch is full, then returns it. When the upstream is `Pending` with a
+    /// partial batch, we wait on the batch's timer, if there is one, and release the partial batch
+    /// when it fires. When the upstream finishes, any partial batch is released before we finish.
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let batcher = Pin::into_inner(self);
+        loop {
+            let in_stream = match &mut batcher.in_stream {
+                Some(in_stream) => in_stream,
+                None => {
+                    if batcher.batch.is_empty() {
+                        return Poll::Ready(None);
+                    }
+                    return Poll::Ready(Some(batcher.take_batch()));
+                }
+            };
+
+            match Pin::new(in_stream).poll_next(cx) {
+                Poll::Ready(Some(packet)) => {
+                    if batcher.batch.is_empty() || batcher.idle {
+                        if let Some(timeout) = batcher.timeout {
+                            batcher.timer = Some(Box::pin(sleep(timeout)));
+                        }
+                    }
+                    batcher.batch.push(packet);
+                    if batcher.batch.len() >= batcher.batch_size {
+                        return Poll::Ready(Some(batcher.take_batch()));
+                    }
+                }
+                Poll::Ready(None) => batcher.in_stream = None,
+                Poll::Pending => {
+                    if let Some(timer) = &mut batcher.timer {
+                        if timer.as_mut().poll(cx).is_ready() {
+                            return Poll::Ready(Some(batcher.take_batch()));
+                        }
+                    }
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+/// Flattens batches, such as those made by BatchLink, back out into individual packets. Like
+/// ProcessLink, this link only does work when it is polled.
+#[derive(Default)]
+pub struct UnbatchLink<Packet> {
+    in_stream: Option<PacketStream<Vec<Packet>>>,
+}
+
+impl<Packet> UnbatchLink<Packet> {
+    pub fn new() -> Self {
+        UnbatchLink { in_stream: None }
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Vec<Packet>, Packet> for UnbatchLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Vec<Packet>>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "UnbatchLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("UnbatchLink may only take 1 input stream")
+        }
+
+        UnbatchLink {
+            in_stream: Some(in_streams.remove(0)),
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Vec<Packet>>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("UnbatchLink may only take 1 input stream")
+        }
+
+        UnbatchLink {
+            in_stream: Some(in_stream),
+        }
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match self.in_stream {
+            None => panic!("Cannot build link! Missing input streams"),
+            Some(in_stream) => {
+                let unbatcher = Unbatcher {
+                    in_stream,
+                    batch: Vec::new().into_iter(),
+                };
+                (vec![], vec![Box::new(unbatcher)])
+            }
+        }
+    }
+}
+
+/// The single egressor of UnbatchLink
+struct Unbatcher<Packet> {
+    in_stream: PacketStream<Vec<Packet>>,
+    batch: std::vec::IntoIter<Packet>,
+}
+
+impl<Packet> Unpin for Unbatcher<Packet> {}
+
+impl<Packet> Stream for Unbatcher<Packet> {
+    type Item = Packet;
+
+    /// Returns packets from the current batch until it is exhausted, then pulls the next batch.
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let unbatcher = Pin::into_inner(self);
+        loop {
+            if let Some(packet) = unbatcher.batch.next() {
+                return Poll::Ready(Some(packet));
+            }
+
+            match ready!(Pin::new(&mut unbatcher.in_stream).poll_next(cx)) {
+                Some(batch) => unbatcher.batch = batch.into_iter(),
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::primitive::QueueLink;
+    use crate::link::ProcessLinkBuilder;
+    use crate::processor::Identity;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
+    use core::time;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_batch_size() {
+        BatchLink::<i32>::new()
+            .ingressor(immediate_stream(vec![]))
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_with_zero_batch_size() {
+        BatchLink::<i32>::new().batch_size(0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn unbatch_panics_when_built_without_input_streams() {
+        UnbatchLink::<i32>::new().build_link();
+    }
+
+    #[test]
+    fn batches_with_partial_remainder() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = BatchLink::new()
+                .ingressor(immediate_stream(0..10))
+                .batch_size(4)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(
+            results[0],
+            vec![vec![0, 1, 2, 3], vec![4, 5, 6, 7], vec![8, 9]]
+        );
+    }
+
+    #[test]
+    fn timeout_releases_partial_batches() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let packet_generator = PacketIntervalGenerator::new(
+                time::Duration::from_millis(50),
+                vec![0, 1, 2].into_iter(),
+            );
+
+            let link = BatchLink::new()
+                .ingressor(Box::new(packet_generator))
+                .batch_size(10)
+                .timeout(Duration::from_millis(10))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![vec![0], vec![1], vec![2]]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn timeout_flush_panics_when_built_without_idle_timeout() {
+        TimeoutFlushLink::<i32>::new()
+            .ingressor(immediate_stream(vec![]))
+            .batch_size(4)
+            .build_link();
+    }
+
+    #[test]
+    fn idle_timeout_flushes_end_of_burst() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            // A burst of packets 10ms apart, which BatchLink's timeout would split, then a few
+            // stragglers 100ms apart. The first straggler follows straight on from the burst.
+            let burst = PacketIntervalGenerator::new(time::Duration::from_millis(10), 0..5);
+            let stragglers = PacketIntervalGenerator::new(time::Duration::from_millis(100), 5..8);
+
+            let link = TimeoutFlushLink::new()
+                .ingressor(Box::new(burst.chain(stragglers)))
+                .batch_size(10)
+                .idle_timeout(Duration::from_millis(30))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![vec![0, 1, 2, 3, 4, 5], vec![6], vec![7]]);
+    }
+
+    #[test]
+    fn unbatches_in_order() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = UnbatchLink::new()
+                .ingressor(immediate_stream(vec![vec![0, 1], vec![], vec![2, 3, 4]]))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn batches_through_a_queue() {
+        let packets: Vec<i32> = (0..1000).collect();
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (mut runnables, batches) = BatchLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .batch_size(32)
+                .build_link();
+
+            let (mut queue_runnables, queued) = QueueLink::new()
+                .ingressors(batches)
+                .processor(Identity::new())
+                .build_link();
+            runnables.append(&mut queue_runnables);
+
+            let (mut unbatch_runnables, egressors) =
+                UnbatchLink::new().ingressors(queued).build_link();
+            runnables.append(&mut unbatch_runnables);
+
+            run_link((runnables, egressors)).await
+        });
+        assert_eq!(results[0], packets);
+    }
+}
@@ -0,0 +1,390 @@
+use crate::link::{Link, LinkBuilder, PacketStream};
+use crossbeam::crossbeam_channel::TrySendError;
+use futures::channel::mpsc;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::collections::VecDeque;
+use std::pin::Pin;
+
+/// Like `OutputChannelLink`, but tolerates the downstream going away. When the output channel
+/// disconnects, packets are buffered, up to `buffer_capacity`, dropping the oldest when the buffer
+/// fills. A replacement channel can be handed over the `reconnect` channel, at which point the buffer
+/// is replayed in order before any new packets are forwarded.
+#[derive(Default)]
+pub struct BufferedSinkLink<Packet> {
+    in_stream: Option<PacketStream<Packet>>,
+    channel_sender: Option<crossbeam::Sender<Packet>>,
+    reconnect_receiver: Option<mpsc::UnboundedReceiver<crossbeam::Sender<Packet>>>,
+    buffer_capacity: usize,
+}
+
+impl<Packet> BufferedSinkLink<Packet> {
+    pub fn new() -> Self {
+        BufferedSinkLink {
+            in_stream: None,
+            channel_sender: None,
+            reconnect_receiver: None,
+            buffer_capacity: 10,
+        }
+    }
+
+    pub fn channel(self, channel_sender: crossbeam::Sender<Packet>) -> Self {
+        BufferedSinkLink {
+            in_stream: self.in_stream,
+            channel_sender: Some(channel_sender),
+            reconnect_receiver: self.reconnect_receiver,
+            buffer_capacity: self.buffer_capacity,
+        }
+    }
+
+    /// Channel over which replacement output channels are delivered once the downstream returns.
+    /// While disconnected, the link sleeps until one is delivered.
+    pub fn reconnect(
+        self,
+        reconnect_receiver: mpsc::UnboundedReceiver<crossbeam::Sender<Packet>>,
+    ) -> Self {
+        BufferedSinkLink {
+            in_stream: self.in_stream,
+            channel_sender: self.channel_sender,
+            reconnect_receiver: Some(reconnect_receiver),
+            buffer_capacity: self.buffer_capacity,
+        }
+    }
+
+    /// Changes buffer_capacity, default value is 10.
+    pub fn buffer_capacity(self, buffer_capacity: usize) -> Self {
+        assert!(
+            buffer_capacity > 0,
+            "buffer_capacity: {}, must be > 0",
+            buffer_capacity
+        );
+
+        BufferedSinkLink {
+            in_stream: self.in_stream,
+            channel_sender: self.channel_sender,
+            reconnect_receiver: self.reconnect_receiver,
+            buffer_capacity,
+        }
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Packet, ()> for BufferedSinkLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "BufferedSinkLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("BufferedSinkLink may only take 1 input stream");
+        }
+
+        BufferedSinkLink {
+            in_stream: Some(in_streams.remove(0)),
+            channel_sender: self.channel_sender,
+            reconnect_receiver: self.reconnect_receiver,
+            buffer_capacity: self.buffer_capacity,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("BufferedSinkLink may only take 1 input stream");
+        }
+
+        BufferedSinkLink {
+            in_stream: Some(in_stream),
+            channel_sender: self.channel_sender,
+            reconnect_receiver: self.reconnect_receiver,
+            buffer_capacity: self.buffer_capacity,
+        }
+    }
+
+    fn build_link(self) -> Link<()> {
+        match (self.in_stream, self.channel_sender) {
+            (None, _) => panic!("Cannot build link! Missing input streams"),
+            (_, None) => panic!("Cannot build link! Missing channel"),
+            (Some(in_stream), Some(sender)) => (
+                vec![Box::new(BufferedStreamToChannel {
+                    stream: Some(in_stream),
+                    channel_sender: Some(sender),
+                    reconnect_receiver: self.reconnect_receiver,
+                    buffer: VecDeque::with_capacity(self.buffer_capacity),
+                    buffer_capacity: self.buffer_capacity,
+                })],
+                vec![],
+            ),
+        }
+    }
+}
+
+struct BufferedStreamToChannel<Packet> {
+    /// Set to `None` once the upstream has finished.
+    stream: Option<PacketStream<Packet>>,
+    /// Set to `None` while the downstream is disconnected.
+    channel_sender: Option<crossbeam::Sender<Packet>>,
+    reconnect_receiver: Option<mpsc::UnboundedReceiver<crossbeam::Sender<Packet>>>,
+    buffer: VecDeque<Packet>,
+    buffer_capacity: usize,
+}
+
+impl<Packet> Unpin for BufferedStreamToChannel<Packet> {}
+
+impl<Packet> BufferedStreamToChannel<Packet> {
+    /// Takes the most recent replacement sender off the reconnect channel, if any. Otherwise the
+    /// reconnect channel wakes the task once one is delivered.
+    fn check_reconnect(&mut self, cx: &mut Context) {
+        let mut disconnected = false;
+        if let Some(reconnect_receiver) = &mut self.reconnect_receiver {
+            loop {
+                match reconnect_receiver.poll_next_unpin(cx) {
+                    Poll::Ready(Some(sender)) => self.channel_sender = Some(sender),
+                    Poll::Ready(None) => {
+                        disconnected = true;
+                        break;
+                    }
+                    Poll::Pending => break,
+                }
+            }
+        }
+        if disconnected {
+            self.reconnect_receiver = None;
+        }
+    }
+
+    fn buffer_packet(&mut self, packet: Packet) {
+        if self.buffer.len() == self.buffer_capacity {
+            self.buffer.pop_front();
+        }
+        self.buffer.push_back(packet);
+    }
+
+    /// Replays as much of the buffer as the output channel will take. Returns `false` if the channel
+    /// filled up before the buffer was emptied.
+    fn flush_buffer(&mut self) -> bool {
+        while let Some(packet) = self.buffer.pop_front() {
+            let sender = match &self.channel_sender {
+                Some(sender) => sender,
+                None => {
+                    self.buffer.push_front(packet);
+                    return true;
+                }
+            };
+            match sender.try_send(packet) {
+                Ok(()) => {}
+                Err(TrySendError::Full(packet)) => {
+                    self.buffer.push_front(packet);
+                    return false;
+                }
+                Err(TrySendError::Disconnected(packet)) => {
+                    self.buffer.push_front(packet);
+                    self.channel_sender = None;
+                }
+            }
+        }
+        true
+    }
+}
+
+impl<Packet> Future for BufferedStreamToChannel<Packet> {
+    type Output = ();
+
+    /// Replays any buffered packets first, then forwards packets from the upstream. There are
+    /// several cases where we can not make progress:
+    /// ###
+    /// #1 The output channel is full. As with `OutputChannelLink` we do not know anything about the
+    /// other side of the channel, so we self-wake and hope it empties eventually.
+    ///
+    /// #2 We are disconnected with packets in the buffer and the upstream is either exhausted or
+    /// `Pending`. We sleep and rely on the reconnect channel to wake us when a new channel is
+    /// delivered, or the upstream to wake us with another packet.
+    ///
+    /// #3 The upstream is `Pending` and we have nothing buffered, we sleep and rely on the upstream
+    /// to wake us.
+    ///
+    /// We finish once the upstream is exhausted and the buffer is empty, or if there is nothing left
+    /// we could ever reconnect to.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let sink = Pin::into_inner(self);
+        loop {
+            sink.check_reconnect(cx);
+
+            if !sink.flush_buffer() {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+
+            let disconnected = sink.channel_sender.is_none();
+            if disconnected && sink.reconnect_receiver.is_none() {
+                // Nobody can hand us a new channel, so there is no point holding on to packets.
+                sink.buffer.clear();
+            }
+
+            let stream = match &mut sink.stream {
+                Some(stream) => stream,
+                None => {
+                    if sink.buffer.is_empty() {
+                        return Poll::Ready(());
+                    }
+                    // Case #2, flush_buffer only leaves packets buffered while disconnected.
+                    return Poll::Pending;
+                }
+            };
+
+            if let Some(sender) = &sink.channel_sender {
+                if sender.is_full() {
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+            }
+
+            match Pin::new(stream).poll_next(cx) {
+                Poll::Ready(Some(packet)) => match &sink.channel_sender {
+                    Some(sender) => match sender.try_send(packet) {
+                        Ok(()) => {}
+                        Err(TrySendError::Full(packet)) => sink.buffer_packet(packet),
+                        Err(TrySendError::Disconnected(packet)) => {
+                            sink.channel_sender = None;
+                            sink.buffer_packet(packet);
+                        }
+                    },
+                    None => {
+                        if sink.reconnect_receiver.is_some() {
+                            sink.buffer_packet(packet);
+                        }
+                    }
+                },
+                Poll::Ready(None) => sink.stream = None,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use crossbeam::crossbeam_channel;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::time::{sleep, Duration};
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_ingressor() {
+        let (s, _r) = crossbeam::unbounded();
+
+        BufferedSinkLink::<()>::new().channel(s).build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_channel() {
+        BufferedSinkLink::<()>::new()
+            .ingressor(immediate_stream(vec![]))
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_with_zero_buffer_capacity() {
+        BufferedSinkLink::<()>::new().buffer_capacity(0);
+    }
+
+    #[test]
+    fn connected_passes_packets_through() {
+        let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (send, recv) = crossbeam_channel::unbounded::<i32>();
+            let link = BufferedSinkLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .channel(send)
+                .build_link();
+
+            let link_results = run_link(link).await;
+            (link_results, recv)
+        });
+        assert!(results.0.is_empty());
+        assert_eq!(results.1.iter().collect::<Vec<i32>>(), packets);
+    }
+
+    #[test]
+    fn disconnected_without_reconnect_drops_packets() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (send, recv) = crossbeam_channel::unbounded::<i32>();
+            drop(recv);
+            let link = BufferedSinkLink::new()
+                .ingressor(immediate_stream(vec![0, 1, 2, 3]))
+                .channel(send)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn replays_newest_packets_on_reconnect() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (send, recv) = crossbeam_channel::unbounded::<i32>();
+            drop(recv);
+            let (reconnect_send, reconnect_recv) = mpsc::unbounded();
+            let (new_send, new_recv) = crossbeam_channel::unbounded::<i32>();
+
+            let link = BufferedSinkLink::new()
+                .ingressor(immediate_stream(0..10))
+                .channel(send)
+                .reconnect(reconnect_recv)
+                .buffer_capacity(4)
+                .build_link();
+
+            let reconnect = tokio::spawn(async move {
+                sleep(Duration::from_millis(50)).await;
+                reconnect_send.unbounded_send(new_send).unwrap();
+            });
+
+            run_link(link).await;
+            reconnect.await.unwrap();
+            new_recv
+        });
+        assert_eq!(results.iter().collect::<Vec<i32>>(), vec![6, 7, 8, 9]);
+    }
+    #[test]
+    fn sleeps_while_disconnected() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (send, recv) = crossbeam_channel::unbounded::<i32>();
+            drop(recv);
+            let (reconnect_send, reconnect_recv) = mpsc::unbounded();
+            let (new_send, new_recv) = crossbeam_channel::unbounded::<i32>();
+
+            let (mut runnables, _) = BufferedSinkLink::new()
+                .ingressor(immediate_stream(0..3))
+                .channel(send)
+                .reconnect(reconnect_recv)
+                .build_link();
+            let mut runnable = runnables.remove(0);
+            let polls = Arc::new(AtomicUsize::new(0));
+            let counted = Arc::clone(&polls);
+            let sink = tokio::spawn(future::poll_fn(move |cx| {
+                counted.fetch_add(1, Ordering::Relaxed);
+                runnable.poll_unpin(cx)
+            }));
+
+            sleep(Duration::from_millis(50)).await;
+            assert_eq!(polls.load(Ordering::Relaxed), 1);
+
+            reconnect_send.unbounded_send(new_send).unwrap();
+            sink.await.unwrap();
+            new_recv
+        });
+        assert_eq!(results.iter().collect::<Vec<i32>>(), vec![0, 1, 2]);
+    }
+}
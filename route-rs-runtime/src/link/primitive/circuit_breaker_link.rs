@@ -0,0 +1,487 @@
+use crate::link::utils::task_park::*;
+use crate::link::{
+    primitive::QueueEgressor, registry::LinkHandle, Link, LinkBuilder, PacketStream,
+    ProcessLinkBuilder,
+};
+use crate::processor::Processor;
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::Sender;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The egressor processed packets are sent to.
+pub const CIRCUIT_PROCESSED_PORT: usize = 0;
+/// The egressor packets are diverted to while the breaker is tripped.
+pub const CIRCUIT_BYPASS_PORT: usize = 1;
+
+/// Runs packets through an optional processing stage, such as a DPI processor, and watches how
+/// many it drops. If, out of the last `window` packets, the fraction dropped reaches
+/// `failure_threshold`, the breaker trips: for the next `cool_down`, packets skip the processor
+/// and are sent unchanged to the bypass egressor, `CIRCUIT_BYPASS_PORT`. The processor is then
+/// tried again, with a fresh window. Whether the breaker is tripped can be watched through a
+/// `CircuitBreakerStatus` taken from the builder.
+pub struct CircuitBreakerLink<P: Processor> {
+    in_stream: Option<PacketStream<P::Input>>,
+    processor: Option<P>,
+    failure_threshold: f64,
+    window: usize,
+    cool_down: Duration,
+    queue_capacity: usize,
+    status: CircuitBreakerStatus,
+}
+
+impl<P: Processor> Default for CircuitBreakerLink<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: Processor> CircuitBreakerLink<P> {
+    pub fn new() -> Self {
+        CircuitBreakerLink {
+            in_stream: None,
+            processor: None,
+            failure_threshold: 0.5,
+            window: 100,
+            cool_down: Duration::from_secs(1),
+            queue_capacity: 10,
+            status: CircuitBreakerStatus::new(),
+        }
+    }
+
+    /// Changes failure_threshold, the fraction of packets dropped that trips the breaker, default
+    /// value is 0.5.
+    pub fn failure_threshold(self, failure_threshold: f64) -> Self {
+        assert!(
+            failure_threshold > 0.0 && failure_threshold <= 1.0,
+            "CircuitBreakerLink failure_threshold: {}, must be > 0 and <= 1",
+            failure_threshold
+        );
+
+        CircuitBreakerLink {
+            in_stream: self.in_stream,
+            processor: self.processor,
+            failure_threshold,
+            window: self.window,
+            cool_down: self.cool_down,
+            queue_capacity: self.queue_capacity,
+            status: self.status,
+        }
+    }
+
+    /// Changes window, how many of the most recent packets the failure rate is measured over,
+    /// default value is 100.
+    pub fn window(self, window: usize) -> Self {
+        assert!(
+            window > 0,
+            "CircuitBreakerLink window: {}, must be > 0",
+            window
+        );
+
+        CircuitBreakerLink {
+            in_stream: self.in_stream,
+            processor: self.processor,
+            failure_threshold: self.failure_threshold,
+            window,
+            cool_down: self.cool_down,
+            queue_capacity: self.queue_capacity,
+            status: self.status,
+        }
+    }
+
+    /// Changes cool_down, how long packets bypass the processor once the breaker trips, default
+    /// value is 1 second.
+    pub fn cool_down(self, cool_down: Duration) -> Self {
+        CircuitBreakerLink {
+            in_stream: self.in_stream,
+            processor: self.processor,
+            failure_threshold: self.failure_threshold,
+            window: self.window,
+            cool_down,
+            queue_capacity: self.queue_capacity,
+            status: self.status,
+        }
+    }
+
+    /// Changes queue_capacity, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "CircuitBreakerLink queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        CircuitBreakerLink {
+            in_stream: self.in_stream,
+            processor: self.processor,
+            failure_threshold: self.failure_threshold,
+            window: self.window,
+            cool_down: self.cool_down,
+            queue_capacity,
+            status: self.status,
+        }
+    }
+
+    /// Returns a handle for watching the breaker. It may be read before or after the link is
+    /// built.
+    pub fn status(&self) -> CircuitBreakerStatus {
+        self.status.clone()
+    }
+}
+
+impl<P> LinkBuilder<P::Input, P::Output> for CircuitBreakerLink<P>
+where
+    P: Processor<Output = <P as Processor>::Input> + Send + 'static,
+{
+    fn ingressors(self, mut in_streams: Vec<PacketStream<P::Input>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "CircuitBreakerLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("CircuitBreakerLink may only take 1 input stream")
+        }
+
+        CircuitBreakerLink {
+            in_stream: Some(in_streams.remove(0)),
+            processor: self.processor,
+            failure_threshold: self.failure_threshold,
+            window: self.window,
+            cool_down: self.cool_down,
+            queue_capacity: self.queue_capacity,
+            status: self.status,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<P::Input>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("CircuitBreakerLink may only take 1 input stream")
+        }
+
+        CircuitBreakerLink {
+            in_stream: Some(in_stream),
+            processor: self.processor,
+            failure_threshold: self.failure_threshold,
+            window: self.window,
+            cool_down: self.cool_down,
+            queue_capacity: self.queue_capacity,
+            status: self.status,
+        }
+    }
+
+    fn handles(&self) -> Vec<LinkHandle> {
+        vec![Box::new(self.status())]
+    }
+
+    fn build_link(self) -> Link<P::Output> {
+        match (self.in_stream, self.processor) {
+            (None, _) => panic!("Cannot build link! Missing input stream"),
+            (_, None) => panic!("Cannot build link! Missing processor"),
+            (Some(in_stream), Some(processor)) => {
+                let mut to_egressors: Vec<Sender<Option<P::Output>>> = Vec::new();
+                let mut egressors: Vec<PacketStream<P::Output>> = Vec::new();
+                let mut task_parks: Vec<Arc<AtomicCell<TaskParkState>>> = Vec::new();
+
+                for _ in 0..2 {
+                    let (to_egressor, from_ingressor) =
+                        crossbeam_channel::bounded::<Option<P::Output>>(self.queue_capacity);
+                    let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+                    let egressor = QueueEgressor::new(from_ingressor, Arc::clone(&task_park));
+
+                    to_egressors.push(to_egressor);
+                    egressors.push(Box::new(egressor));
+                    task_parks.push(task_park);
+                }
+
+                let ingressor = CircuitBreakerIngressor {
+                    input_stream: in_stream,
+                    to_egressors,
+                    task_parks,
+                    processor,
+                    failure_threshold: self.failure_threshold,
+                    window: self.window,
+                    cool_down: self.cool_down,
+                    outcomes: VecDeque::with_capacity(self.window),
+                    failures: 0,
+                    tripped_until: None,
+                    status: self.status,
+                    ports_finished: None,
+                };
+
+                (vec![Box::new(ingressor)], egressors)
+            }
+        }
+    }
+}
+
+impl<P> ProcessLinkBuilder<P> for CircuitBreakerLink<P>
+where
+    P: Processor<Output = <P as Processor>::Input> + Send + 'static,
+{
+    fn processor(self, processor: P) -> Self {
+        CircuitBreakerLink {
+            in_stream: self.in_stream,
+            processor: Some(processor),
+            failure_threshold: self.failure_threshold,
+            window: self.window,
+            cool_down: self.cool_down,
+            queue_capacity: self.queue_capacity,
+            status: self.status,
+        }
+    }
+}
+
+/// Handle to a CircuitBreakerLink, for watching whether its breaker is tripped.
+#[derive(Clone)]
+pub struct CircuitBreakerStatus {
+    tripped: Arc<AtomicBool>,
+    trips: Arc<AtomicU64>,
+}
+
+impl CircuitBreakerStatus {
+    fn new() -> Self {
+        CircuitBreakerStatus {
+            tripped: Arc::new(AtomicBool::new(false)),
+            trips: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Whether packets are currently bypassing the processor.
+    pub fn is_tripped(&self) -> bool {
+        self.tripped.load(Ordering::Relaxed)
+    }
+
+    /// How many times the breaker has tripped.
+    pub fn trips(&self) -> u64 {
+        self.trips.load(Ordering::Relaxed)
+    }
+}
+
+pub struct CircuitBreakerIngressor<P: Processor> {
+    input_stream: PacketStream<P::Input>,
+    to_egressors: Vec<Sender<Option<P::Output>>>,
+    task_parks: Vec<Arc<AtomicCell<TaskParkState>>>,
+    processor: P,
+    failure_threshold: f64,
+    window: usize,
+    cool_down: Duration,
+    /// Whether each of the last `window` packets was dropped by the processor, oldest first.
+    outcomes: VecDeque<bool>,
+    failures: usize,
+    tripped_until: Option<Instant>,
+    status: CircuitBreakerStatus,
+    /// Once the input has finished, how many egressors we have told so.
+    ports_finished: Option<usize>,
+}
+
+impl<P: Processor> Unpin for CircuitBreakerIngressor<P> {}
+
+impl<P: Processor> CircuitBreakerIngressor<P> {
+    /// The port the next packet goes to, resetting the breaker if its cool down is over.
+    fn port(&mut self) -> usize {
+        match self.tripped_until {
+            Some(until) if Instant::now() < until => CIRCUIT_BYPASS_PORT,
+            Some(_) => {
+                self.tripped_until = None;
+                self.status.tripped.store(false, Ordering::Relaxed);
+                CIRCUIT_PROCESSED_PORT
+            }
+            None => CIRCUIT_PROCESSED_PORT,
+        }
+    }
+
+    /// Records whether the processor dropped a packet, tripping the breaker if the window is full
+    /// and too many were.
+    fn record(&mut self, failed: bool) {
+        if self.outcomes.len() == self.window && self.outcomes.pop_front() == Some(true) {
+            self.failures -= 1;
+        }
+        self.outcomes.push_back(failed);
+        if failed {
+            self.failures += 1;
+        }
+
+        let failure_rate = self.failures as f64 / self.window as f64;
+        if self.outcomes.len() == self.window && failure_rate >= self.failure_threshold {
+            self.tripped_until = Some(Instant::now() + self.cool_down);
+            self.outcomes.clear();
+            self.failures = 0;
+            self.status.tripped.store(true, Ordering::Relaxed);
+            self.status.trips.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn send(&self, port: usize, packet: P::Output) {
+        if let Err(err) = self.to_egressors[port].try_send(Some(packet)) {
+            panic!(
+                "Error in to_egressors[{}] sender, have nowhere to put packet: {:?}",
+                port, err
+            );
+        }
+        unpark_and_wake(&self.task_parks[port]);
+    }
+
+    /// Tells each egressor in turn that we are finished, waiting on any whose queue is full.
+    fn finish(&mut self, cx: &mut Context) -> Poll<()> {
+        let mut port = self.ports_finished.unwrap_or(0);
+        while port < self.to_egressors.len() {
+            if self.to_egressors[port].is_full() {
+                self.ports_finished = Some(port);
+                park_and_wake(&self.task_parks[port], cx.waker().clone());
+                return Poll::Pending;
+            }
+            if let Err(err) = self.to_egressors[port].try_send(None) {
+                panic!(
+                    "CircuitBreakerIngressor: try_send to_egressor shouldn't fail, {:?}",
+                    err
+                );
+            }
+            die_and_wake(&self.task_parks[port]);
+            port += 1;
+        }
+        self.ports_finished = Some(port);
+        Poll::Ready(())
+    }
+}
+
+impl<P> Future for CircuitBreakerIngressor<P>
+where
+    P: Processor<Output = <P as Processor>::Input>,
+{
+    type Output = ();
+
+    /// Same logic as QueueIngressor, except that while the breaker is tripped, packets skip the
+    /// processor and go to the bypass egressor. We only wait on the egressor the next packet is
+    /// going to having room. When the input finishes, we wait on each egressor in turn to have
+    /// room for the `None` that tears it down.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let ingressor = Pin::into_inner(self);
+        if ingressor.ports_finished.is_some() {
+            return ingressor.finish(cx);
+        }
+
+        loop {
+            let port = ingressor.port();
+            if ingressor.to_egressors[port].is_full() {
+                park_and_wake(&ingressor.task_parks[port], cx.waker().clone());
+                return Poll::Pending;
+            }
+
+            match ready!(Pin::new(&mut ingressor.input_stream).poll_next(cx)) {
+                None => return ingressor.finish(cx),
+                Some(packet) if port == CIRCUIT_BYPASS_PORT => ingressor.send(port, packet),
+                Some(packet) => match ingressor.processor.process(packet) {
+                    Some(packet) => {
+                        ingressor.record(false);
+                        ingressor.send(port, packet);
+                    }
+                    None => ingressor.record(true),
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::Identity;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
+
+    /// Drops every packet at or above `fail_from`, as a stage that has started misbehaving would.
+    struct FailFrom {
+        fail_from: i32,
+    }
+
+    impl Processor for FailFrom {
+        type Input = i32;
+        type Output = i32;
+
+        fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+            if packet >= self.fail_from {
+                None
+            } else {
+                Some(packet)
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_processor() {
+        CircuitBreakerLink::<Identity<i32>>::new()
+            .ingressor(immediate_stream(vec![]))
+            .build_link();
+    }
+
+    #[test]
+    fn healthy_processor_is_not_bypassed() {
+        let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
+
+        let runtime = initialize_runtime();
+        let link = CircuitBreakerLink::new()
+            .ingressor(immediate_stream(packets.clone()))
+            .processor(Identity::new())
+            .window(4);
+        let status = link.status();
+
+        let results = runtime.block_on(run_link(link.build_link()));
+        assert_eq!(results[CIRCUIT_PROCESSED_PORT], packets);
+        assert!(results[CIRCUIT_BYPASS_PORT].is_empty());
+        assert_eq!(status.trips(), 0);
+    }
+
+    #[test]
+    fn trips_and_bypasses_failing_processor() {
+        let runtime = initialize_runtime();
+        let link = CircuitBreakerLink::new()
+            .ingressor(immediate_stream(0..30))
+            .processor(FailFrom { fail_from: 10 })
+            .window(4)
+            .failure_threshold(0.5)
+            .cool_down(Duration::from_secs(60));
+        let status = link.status();
+
+        let results = runtime.block_on(run_link(link.build_link()));
+        // 10 and 11 were dropped, which is half the window of 8, 9, 10 and 11.
+        assert_eq!(
+            results[CIRCUIT_PROCESSED_PORT],
+            (0..10).collect::<Vec<i32>>()
+        );
+        assert_eq!(results[CIRCUIT_BYPASS_PORT], (12..30).collect::<Vec<i32>>());
+        assert!(status.is_tripped());
+        assert_eq!(status.trips(), 1);
+    }
+
+    #[test]
+    fn retries_processor_after_cool_down() {
+        let runtime = initialize_runtime();
+        let (results, status) = runtime.block_on(async {
+            let packet_generator = PacketIntervalGenerator::new(Duration::from_millis(10), 0..20);
+
+            let link = CircuitBreakerLink::new()
+                .ingressor(Box::new(packet_generator))
+                .processor(FailFrom { fail_from: 2 })
+                .window(2)
+                .failure_threshold(1.0)
+                .cool_down(Duration::from_millis(55));
+            let status = link.status();
+
+            (run_link(link.build_link()).await, status)
+        });
+        assert_eq!(results[CIRCUIT_PROCESSED_PORT], vec![0, 1]);
+        // Each retry drops another window of packets before tripping again.
+        assert!(status.trips() > 1);
+        assert!(results[CIRCUIT_BYPASS_PORT].len() < 18);
+    }
+}
@@ -1,6 +1,10 @@
 use crate::classifier::Classifier;
+use crate::link::primitive::PacketCost;
+use crate::link::utils::stats::{LinkCounter, LinkStats};
 use crate::link::utils::task_park::*;
-use crate::link::{primitive::QueueEgressor, Link, LinkBuilder, PacketStream};
+use crate::link::{
+    primitive::QueueEgressor, registry::LinkHandle, Link, LinkBuilder, PacketStream,
+};
 use crossbeam::atomic::AtomicCell;
 use crossbeam::crossbeam_channel;
 use crossbeam::crossbeam_channel::{Receiver, Sender};
@@ -8,7 +12,6 @@ use futures::prelude::*;
 use futures::task::{Context, Poll};
 use std::pin::Pin;
 use std::sync::Arc;
-use tokio::stream::Stream;
 
 #[derive(Default)]
 pub struct ClassifyLink<C: Classifier> {
@@ -17,6 +20,9 @@ pub struct ClassifyLink<C: Classifier> {
     dispatcher: Option<Box<dyn Fn(C::Class) -> usize + Send + Sync + 'static>>,
     queue_capacity: usize,
     num_egressors: Option<usize>,
+    default_port: Option<usize>,
+    bytes: Option<Arc<PacketCost<C::Packet>>>,
+    stats: Arc<LinkStats>,
 }
 
 impl<C: Classifier> ClassifyLink<C> {
@@ -27,6 +33,9 @@ impl<C: Classifier> ClassifyLink<C> {
             dispatcher: None,
             queue_capacity: 10,
             num_egressors: None,
+            default_port: None,
+            bytes: None,
+            stats: Arc::new(LinkStats::default()),
         }
     }
 
@@ -37,6 +46,9 @@ impl<C: Classifier> ClassifyLink<C> {
             dispatcher: self.dispatcher,
             queue_capacity: self.queue_capacity,
             num_egressors: self.num_egressors,
+            default_port: self.default_port,
+            bytes: self.bytes,
+            stats: self.stats,
         }
     }
 
@@ -50,6 +62,9 @@ impl<C: Classifier> ClassifyLink<C> {
             dispatcher: Some(dispatcher),
             queue_capacity: self.queue_capacity,
             num_egressors: self.num_egressors,
+            default_port: self.default_port,
+            bytes: self.bytes,
+            stats: self.stats,
         }
     }
 
@@ -64,6 +79,9 @@ impl<C: Classifier> ClassifyLink<C> {
             dispatcher: self.dispatcher,
             queue_capacity,
             num_egressors: self.num_egressors,
+            default_port: self.default_port,
+            bytes: self.bytes,
+            stats: self.stats,
         }
     }
 
@@ -78,8 +96,48 @@ impl<C: Classifier> ClassifyLink<C> {
             dispatcher: self.dispatcher,
             queue_capacity: self.queue_capacity,
             num_egressors: Some(num_egressors),
+            default_port: self.default_port,
+            bytes: self.bytes,
+            stats: self.stats,
         }
     }
+
+    /// Sends packets the dispatcher maps to a port that does not exist to `default_port`
+    /// instead of panicking. Dispatchers may return `usize::MAX` for a "no match" class to send
+    /// it there.
+    pub fn default_port(self, default_port: usize) -> Self {
+        ClassifyLink {
+            in_stream: self.in_stream,
+            classifier: self.classifier,
+            dispatcher: self.dispatcher,
+            queue_capacity: self.queue_capacity,
+            num_egressors: self.num_egressors,
+            default_port: Some(default_port),
+            bytes: self.bytes,
+            stats: self.stats,
+        }
+    }
+
+    /// Sets the closure that gives the length of a packet in bytes, to count the bytes given out
+    /// in the link's stats. Without it, only packets are counted.
+    pub fn bytes(self, bytes: PacketCost<C::Packet>) -> Self {
+        ClassifyLink {
+            in_stream: self.in_stream,
+            classifier: self.classifier,
+            dispatcher: self.dispatcher,
+            queue_capacity: self.queue_capacity,
+            num_egressors: self.num_egressors,
+            default_port: self.default_port,
+            bytes: Some(Arc::new(bytes)),
+            stats: self.stats,
+        }
+    }
+
+    /// Returns the link's counters, kept with the `metrics` feature. They may be read before or
+    /// after the link is built.
+    pub fn stats(&self) -> Arc<LinkStats> {
+        Arc::clone(&self.stats)
+    }
 }
 
 impl<C: Classifier + Send + 'static> LinkBuilder<C::Packet, C::Packet> for ClassifyLink<C> {
@@ -100,6 +158,9 @@ impl<C: Classifier + Send + 'static> LinkBuilder<C::Packet, C::Packet> for Class
             dispatcher: self.dispatcher,
             queue_capacity: self.queue_capacity,
             num_egressors: self.num_egressors,
+            default_port: self.default_port,
+            bytes: self.bytes,
+            stats: self.stats,
         }
     }
 
@@ -114,9 +175,16 @@ impl<C: Classifier + Send + 'static> LinkBuilder<C::Packet, C::Packet> for Class
             dispatcher: self.dispatcher,
             queue_capacity: self.queue_capacity,
             num_egressors: self.num_egressors,
+            default_port: self.default_port,
+            bytes: self.bytes,
+            stats: self.stats,
         }
     }
 
+    fn handles(&self) -> Vec<LinkHandle> {
+        vec![Box::new(self.stats())]
+    }
+
     fn build_link(self) -> Link<C::Packet> {
         if self.in_stream.is_none() {
             panic!("Cannot build link! Missing input streams");
@@ -127,6 +195,14 @@ impl<C: Classifier + Send + 'static> LinkBuilder<C::Packet, C::Packet> for Class
         } else if self.num_egressors.is_none() {
             panic!("Cannot build link! Missing num_egressors");
         } else {
+            if let Some(default_port) = self.default_port {
+                assert!(
+                    default_port < self.num_egressors.unwrap(),
+                    "ClassifyLink default_port: {}, must be < num_egressors",
+                    default_port
+                );
+            }
+
             let mut to_egressors: Vec<Sender<Option<C::Packet>>> = Vec::new();
             let mut egressors: Vec<PacketStream<C::Packet>> = Vec::new();
 
@@ -134,12 +210,13 @@ impl<C: Classifier + Send + 'static> LinkBuilder<C::Packet, C::Packet> for Class
 
             let mut task_parks: Vec<Arc<AtomicCell<TaskParkState>>> = Vec::new();
 
-            for _ in 0..self.num_egressors.unwrap() {
+            for port in 0..self.num_egressors.unwrap() {
                 let (to_egressor, from_ingressor) =
                     crossbeam_channel::bounded::<Option<C::Packet>>(self.queue_capacity);
                 let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
 
-                let provider = QueueEgressor::new(from_ingressor.clone(), Arc::clone(&task_park));
+                let provider = QueueEgressor::new(from_ingressor.clone(), Arc::clone(&task_park))
+                    .counter(LinkCounter::output(&self.stats, port, self.bytes.as_ref()));
 
                 to_egressors.push(to_egressor);
                 egressors.push(Box::new(provider));
@@ -152,6 +229,8 @@ impl<C: Classifier + Send + 'static> LinkBuilder<C::Packet, C::Packet> for Class
                 to_egressors,
                 self.classifier.unwrap(),
                 task_parks,
+                self.default_port,
+                LinkCounter::input(&self.stats, 0),
             );
             (vec![Box::new(ingressor)], egressors)
         }
@@ -164,6 +243,8 @@ pub struct ClassifyIngressor<'a, C: Classifier> {
     to_egressors: Vec<Sender<Option<C::Packet>>>,
     classifier: C,
     task_parks: Vec<Arc<AtomicCell<TaskParkState>>>,
+    default_port: Option<usize>,
+    counter: LinkCounter<C::Packet>,
 }
 
 impl<'a, C: Classifier> Unpin for ClassifyIngressor<'a, C> {}
@@ -175,6 +256,8 @@ impl<'a, C: Classifier> ClassifyIngressor<'a, C> {
         to_egressors: Vec<Sender<Option<C::Packet>>>,
         classifier: C,
         task_parks: Vec<Arc<AtomicCell<TaskParkState>>>,
+        default_port: Option<usize>,
+        counter: LinkCounter<C::Packet>,
     ) -> Self {
         ClassifyIngressor {
             input_stream,
@@ -182,6 +265,8 @@ impl<'a, C: Classifier> ClassifyIngressor<'a, C> {
             to_egressors,
             classifier,
             task_parks,
+            default_port,
+            counter,
         }
     }
 }
@@ -221,10 +306,15 @@ impl<'a, C: Classifier> Future for ClassifyIngressor<'a, C> {
                 }
                 Some(packet) => {
                     let class = ingressor.classifier.classify(&packet);
-                    let port = (ingressor.dispatcher)(class);
+                    let mut port = (ingressor.dispatcher)(class);
                     if port >= ingressor.to_egressors.len() {
-                        panic!("Tried to access invalid port: {}", port);
+                        match ingressor.default_port {
+                            Some(default_port) => port = default_port,
+                            None => panic!("Tried to access invalid port: {}", port),
+                        }
                     }
+                    ingressor.counter.received(1);
+                    ingressor.counter.enqueued(1);
                     if let Err(err) = ingressor.to_egressors[port].try_send(Some(packet)) {
                         panic!(
                             "Error in to_egressors[{}] sender, have nowhere to put packet: {:?}",
@@ -294,9 +384,21 @@ mod tests {
             .build_link();
     }
 
+    #[test]
+    #[should_panic]
+    fn panics_with_default_port_beyond_egressors() {
+        ClassifyLink::new()
+            .ingressor(immediate_stream(vec![]))
+            .num_egressors(2)
+            .classifier(Even::new())
+            .dispatcher(Box::new(|evenness| if evenness { 0 } else { 1 }))
+            .default_port(2)
+            .build_link();
+    }
+
     #[test]
     fn even_odd() {
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let packet_generator = immediate_stream(vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9]);
 
@@ -310,7 +412,7 @@ mod tests {
     fn even_odd_wait_between_packets() {
         let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let packet_generator =
                 PacketIntervalGenerator::new(time::Duration::from_millis(10), packets.into_iter());
@@ -323,7 +425,7 @@ mod tests {
 
     #[test]
     fn only_odd() {
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let packet_generator = immediate_stream(vec![1, 1337, 3, 5, 7, 9]);
 
@@ -333,9 +435,29 @@ mod tests {
         assert_eq!(results[1], vec![1, 1337, 3, 5, 7, 9]);
     }
 
+    #[test]
+    #[cfg(feature = "metrics")]
+    fn metrics() {
+        let runtime = initialize_runtime();
+        let link = ClassifyLink::new()
+            .ingressor(immediate_stream(vec![0, 1, 2, 3, 4]))
+            .classifier(Even::new())
+            .dispatcher(Box::new(|evenness| if evenness { 0 } else { 1 }))
+            .num_egressors(2)
+            .bytes(Box::new(|packet| *packet as u64));
+        let stats = link.stats();
+
+        let results = runtime.block_on(run_link(link.build_link()));
+        assert_eq!(results[0], vec![0, 2, 4]);
+        assert_eq!((stats.packets_in(), stats.packets_out()), (5, 5));
+        assert_eq!(stats.packets_out_by_port(), vec![(0, 3), (1, 2)]);
+        assert_eq!(stats.bytes_out(), 10);
+        assert_eq!(stats.queue_depth(), 0);
+    }
+
     #[test]
     fn even_odd_long_stream() {
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let packet_generator = immediate_stream(0..2000);
 
@@ -345,9 +467,27 @@ mod tests {
         assert_eq!(results[1].len(), 1000);
     }
 
+    #[test]
+    fn unknown_ports_go_to_default_port() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = ClassifyLink::new()
+                .ingressor(immediate_stream(vec![0, 1, 2, 420, 1337, 3, 4, 5]))
+                .num_egressors(2)
+                .classifier(Even::new())
+                .dispatcher(Box::new(|evenness| if evenness { 0 } else { usize::MAX }))
+                .default_port(1)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![0, 2, 420, 4]);
+        assert_eq!(results[1], vec![1, 1337, 3, 5]);
+    }
+
     #[test]
     fn fizz_buzz() {
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let packet_generator = immediate_stream(0..=30);
 
@@ -369,7 +509,7 @@ mod tests {
 
     #[test]
     fn fizz_buzz_to_even_odd() {
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let packet_generator = immediate_stream(0..=30);
 
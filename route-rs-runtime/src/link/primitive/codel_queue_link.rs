@@ -0,0 +1,363 @@
+use crate::link::primitive::QueueIngressor;
+use crate::link::utils::task_park::*;
+use crate::link::{Link, LinkBuilder, PacketStream};
+use crate::processor::{ReceiveTime, Timestamp, Timestamped};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::{Receiver, TryRecvError};
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A queue managed by the CoDel (Controlled Delay) algorithm, RFC 8289. Packets are timestamped as
+/// they are enqueued, and when the time they spend in the queue stays above `target` for at least an
+/// `interval`, the egressor starts dropping packets, increasingly often, until the delay comes back
+/// down. This keeps queues short on bottleneck links, such as WAN egress, without the tuning RED
+/// needs.
+#[derive(Default)]
+pub struct CoDelQueueLink<Packet: Send + Clone> {
+    in_stream: Option<PacketStream<Packet>>,
+    target: Duration,
+    interval: Duration,
+    queue_capacity: usize,
+    timestamp: Timestamp<Packet>,
+}
+
+impl<Packet: Send + Clone> CoDelQueueLink<Packet> {
+    pub fn new() -> Self {
+        CoDelQueueLink {
+            in_stream: None,
+            target: Duration::from_millis(5),
+            interval: Duration::from_millis(100),
+            queue_capacity: 10,
+            timestamp: Timestamp::new(),
+        }
+    }
+
+    /// Changes target, the acceptable standing queue delay, default value is 5ms.
+    pub fn target(self, target: Duration) -> Self {
+        assert!(
+            target > Duration::from_secs(0),
+            "CoDelQueueLink target: {:?}, must be > 0",
+            target
+        );
+
+        CoDelQueueLink {
+            in_stream: self.in_stream,
+            target,
+            interval: self.interval,
+            queue_capacity: self.queue_capacity,
+            timestamp: self.timestamp,
+        }
+    }
+
+    /// Changes interval, how long delay must stay above target before dropping starts, default
+    /// value is 100ms. This should be on the order of a worst case round trip time.
+    pub fn interval(self, interval: Duration) -> Self {
+        assert!(
+            interval > Duration::from_secs(0),
+            "CoDelQueueLink interval: {:?}, must be > 0",
+            interval
+        );
+
+        CoDelQueueLink {
+            in_stream: self.in_stream,
+            target: self.target,
+            interval,
+            queue_capacity: self.queue_capacity,
+            timestamp: self.timestamp,
+        }
+    }
+
+    /// Changes queue_capacity, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "CoDelQueueLink queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        CoDelQueueLink {
+            in_stream: self.in_stream,
+            target: self.target,
+            interval: self.interval,
+            queue_capacity,
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+impl<Packet: ReceiveTime + Send + Clone> CoDelQueueLink<Packet> {
+    /// Measures how long packets have been delayed from when they were received, as they carry,
+    /// rather than from when they joined the queue, so CoDel also counts the delay of the graph
+    /// before it. Packets without a receive time are measured from when they joined the queue.
+    pub fn use_receive_time(self) -> Self {
+        CoDelQueueLink {
+            timestamp: Timestamp::from_receive_time(),
+            ..self
+        }
+    }
+}
+
+impl<Packet: Send + Clone + 'static> LinkBuilder<Packet, Packet> for CoDelQueueLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "CoDelQueueLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("CoDelQueueLink may only take 1 input stream")
+        }
+
+        CoDelQueueLink {
+            in_stream: Some(in_streams.remove(0)),
+            target: self.target,
+            interval: self.interval,
+            queue_capacity: self.queue_capacity,
+            timestamp: self.timestamp,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("CoDelQueueLink may only take 1 input stream")
+        }
+
+        CoDelQueueLink {
+            in_stream: Some(in_stream),
+            target: self.target,
+            interval: self.interval,
+            queue_capacity: self.queue_capacity,
+            timestamp: self.timestamp,
+        }
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match self.in_stream {
+            None => panic!("Cannot build link! Missing input stream"),
+            Some(in_stream) => {
+                let (to_egressor, from_ingressor) =
+                    crossbeam_channel::bounded::<Option<Timestamped<Packet>>>(self.queue_capacity);
+                let task_park: Arc<AtomicCell<TaskParkState>> =
+                    Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+                let ingressor = QueueIngressor::new(
+                    in_stream,
+                    to_egressor,
+                    self.timestamp,
+                    Arc::clone(&task_park),
+                );
+                let egressor = CoDelEgressor {
+                    from_ingressor,
+                    task_park,
+                    target: self.target,
+                    interval: self.interval,
+                    first_above_time: None,
+                    dropping: false,
+                    drop_next: Instant::now(),
+                    count: 0,
+                    last_count: 0,
+                };
+
+                (vec![Box::new(ingressor)], vec![Box::new(egressor)])
+            }
+        }
+    }
+}
+
+/// Result of taking one packet off the queue.
+enum Dequeue<Packet> {
+    Packet(Packet, bool),
+    Empty,
+    Finished,
+}
+
+pub struct CoDelEgressor<Packet> {
+    from_ingressor: Receiver<Option<Timestamped<Packet>>>,
+    task_park: Arc<AtomicCell<TaskParkState>>,
+    target: Duration,
+    interval: Duration,
+    /// When the sojourn time first went above target, plus an interval. If the sojourn time is
+    /// still above target at this point, we start dropping.
+    first_above_time: Option<Instant>,
+    dropping: bool,
+    drop_next: Instant,
+    /// Packets dropped since we entered the dropping state.
+    count: u32,
+    last_count: u32,
+}
+
+impl<Packet> Unpin for CoDelEgressor<Packet> {}
+
+impl<Packet> CoDelEgressor<Packet> {
+    /// Takes a packet off the queue, along with whether CoDel considers it ok to drop.
+    fn do_dequeue(&mut self, now: Instant) -> Dequeue<Packet> {
+        match self.from_ingressor.try_recv() {
+            Ok(Some(packet)) => {
+                unpark_and_wake(&self.task_park);
+                let sojourn = now.saturating_duration_since(packet.received);
+                let ok_to_drop = if sojourn < self.target || self.from_ingressor.is_empty() {
+                    self.first_above_time = None;
+                    false
+                } else {
+                    match self.first_above_time {
+                        None => {
+                            self.first_above_time = Some(now + self.interval);
+                            false
+                        }
+                        Some(first_above_time) => now >= first_above_time,
+                    }
+                };
+                Dequeue::Packet(packet.packet, ok_to_drop)
+            }
+            Ok(None) | Err(TryRecvError::Disconnected) => Dequeue::Finished,
+            Err(TryRecvError::Empty) => {
+                self.first_above_time = None;
+                Dequeue::Empty
+            }
+        }
+    }
+
+    /// Drops get closer together the longer we stay in the dropping state.
+    fn control_law(&self, from: Instant) -> Instant {
+        from + self.interval.div_f64(f64::from(self.count).sqrt())
+    }
+}
+
+impl<Packet> Stream for CoDelEgressor<Packet> {
+    type Item = Packet;
+
+    /// The CoDel dequeue procedure from RFC 8289. Once the sojourn time of packets has stayed above
+    /// target for an interval we enter the dropping state, and drop a packet every
+    /// `interval / sqrt(count)` until a packet comes through below target. Otherwise this behaves as
+    /// QueueEgressor does, parking when the queue is empty and finishing when the ingressor has.
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let egressor = Pin::into_inner(self);
+        let now = Instant::now();
+
+        let (mut packet, mut ok_to_drop) = match egressor.do_dequeue(now) {
+            Dequeue::Packet(packet, ok_to_drop) => (packet, ok_to_drop),
+            Dequeue::Empty => {
+                egressor.dropping = false;
+                park_and_wake(&egressor.task_park, cx.waker().clone());
+                return Poll::Pending;
+            }
+            Dequeue::Finished => {
+                die_and_wake(&egressor.task_park);
+                return Poll::Ready(None);
+            }
+        };
+
+        if egressor.dropping {
+            if !ok_to_drop {
+                egressor.dropping = false;
+            } else {
+                while egressor.dropping && now >= egressor.drop_next {
+                    egressor.count += 1;
+                    match egressor.do_dequeue(now) {
+                        Dequeue::Packet(next, next_ok_to_drop) => {
+                            packet = next;
+                            ok_to_drop = next_ok_to_drop;
+                        }
+                        // The packet we hold has already been judged droppable, but it is the
+                        // last one, so let it through rather than leaving the queue idle.
+                        Dequeue::Empty | Dequeue::Finished => {
+                            egressor.dropping = false;
+                            break;
+                        }
+                    }
+                    if !ok_to_drop {
+                        egressor.dropping = false;
+                    } else {
+                        egressor.drop_next = egressor.control_law(egressor.drop_next);
+                    }
+                }
+            }
+        } else if ok_to_drop {
+            if let Dequeue::Packet(next, _) = egressor.do_dequeue(now) {
+                packet = next;
+            }
+            egressor.dropping = true;
+            let delta = egressor.count.saturating_sub(egressor.last_count);
+            egressor.count = if delta > 1
+                && now.saturating_duration_since(egressor.drop_next) < egressor.interval * 16
+            {
+                delta
+            } else {
+                1
+            };
+            egressor.drop_next = egressor.control_law(now);
+            egressor.last_count = egressor.count;
+        }
+
+        Poll::Ready(Some(packet))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use tokio::time::sleep;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_ingressor() {
+        CoDelQueueLink::<i32>::new().build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_with_zero_target() {
+        CoDelQueueLink::<i32>::new().target(Duration::from_secs(0));
+    }
+
+    #[test]
+    fn fast_consumer_sees_no_drops() {
+        let packets: Vec<i32> = (0..100).collect();
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = CoDelQueueLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .target(Duration::from_millis(50))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], packets);
+    }
+
+    #[test]
+    fn standing_queue_is_dropped() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (runnables, mut egressors) = CoDelQueueLink::new()
+                .ingressor(immediate_stream(0..300))
+                .target(Duration::from_millis(2))
+                .interval(Duration::from_millis(20))
+                .queue_capacity(50)
+                .build_link();
+            for runnable in runnables {
+                tokio::spawn(runnable);
+            }
+
+            // A slow consumer keeps a standing queue of 50 packets, well above target.
+            let mut egressor = egressors.remove(0);
+            let mut output = vec![];
+            while let Some(packet) = egressor.next().await {
+                output.push(packet);
+                sleep(Duration::from_millis(1)).await;
+            }
+            output
+        });
+        assert!(results.len() < 300, "forwarded {} packets", results.len());
+        assert!(results.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(results.last(), Some(&299));
+    }
+}
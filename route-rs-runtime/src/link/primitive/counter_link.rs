@@ -0,0 +1,177 @@
+use crate::link::primitive::PacketCost;
+pub use crate::link::utils::stats::LinkStats;
+use crate::link::{registry::LinkHandle, Link, LinkBuilder, PacketStream};
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Counts the packets that pass through it, and their bytes as given by an optional `bytes`
+/// closure, then forwards them unchanged. The counters are read through an `Arc<LinkStats>` taken
+/// from the builder, and are kept with or without the `metrics` feature. Like ProcessLink, it
+/// only does work when it is polled.
+pub struct CounterLink<Packet> {
+    in_stream: Option<PacketStream<Packet>>,
+    bytes: Option<PacketCost<Packet>>,
+    stats: Arc<LinkStats>,
+}
+
+impl<Packet> Default for CounterLink<Packet> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Packet> CounterLink<Packet> {
+    pub fn new() -> Self {
+        CounterLink {
+            in_stream: None,
+            bytes: None,
+            stats: Arc::new(LinkStats::default()),
+        }
+    }
+
+    /// Sets the closure that gives the length of a packet in bytes. Without it, only packets are
+    /// counted.
+    pub fn bytes(self, bytes: PacketCost<Packet>) -> Self {
+        CounterLink {
+            in_stream: self.in_stream,
+            bytes: Some(bytes),
+            stats: self.stats,
+        }
+    }
+
+    /// Returns the link's counters. They may be read before or after the link is built.
+    pub fn stats(&self) -> Arc<LinkStats> {
+        Arc::clone(&self.stats)
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Packet, Packet> for CounterLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "CounterLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("CounterLink may only take 1 input stream")
+        }
+
+        CounterLink {
+            in_stream: Some(in_streams.remove(0)),
+            bytes: self.bytes,
+            stats: self.stats,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("CounterLink may only take 1 input stream")
+        }
+
+        CounterLink {
+            in_stream: Some(in_stream),
+            bytes: self.bytes,
+            stats: self.stats,
+        }
+    }
+
+    fn handles(&self) -> Vec<LinkHandle> {
+        vec![Box::new(self.stats())]
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match self.in_stream {
+            None => panic!("Cannot build link! Missing input stream"),
+            Some(in_stream) => {
+                let counter = Counter {
+                    in_stream,
+                    bytes: self.bytes,
+                    input: self.stats.input(0),
+                    output: self.stats.output(0),
+                    stats: self.stats,
+                };
+                (vec![], vec![Box::new(counter)])
+            }
+        }
+    }
+}
+
+/// The single egressor of CounterLink
+struct Counter<Packet> {
+    in_stream: PacketStream<Packet>,
+    bytes: Option<PacketCost<Packet>>,
+    stats: Arc<LinkStats>,
+    input: Arc<AtomicU64>,
+    output: Arc<AtomicU64>,
+}
+
+impl<Packet> Unpin for Counter<Packet> {}
+
+impl<Packet> Stream for Counter<Packet> {
+    type Item = Packet;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let packet = ready!(Pin::new(&mut self.in_stream).poll_next(cx));
+        if let Some(packet) = &packet {
+            let bytes = self.bytes.as_ref().map_or(0, |bytes| bytes(packet));
+            self.stats.received(1);
+            self.stats.sent(1, bytes);
+            self.input.fetch_add(1, Ordering::Relaxed);
+            self.output.fetch_add(1, Ordering::Relaxed);
+        }
+        Poll::Ready(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_input_stream() {
+        CounterLink::<i32>::new().build_link();
+    }
+
+    #[test]
+    fn counts_packets() {
+        let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
+
+        let runtime = initialize_runtime();
+        let link = CounterLink::new().ingressor(immediate_stream(packets.clone()));
+        let stats = link.stats();
+
+        let results = runtime.block_on(run_link(link.build_link()));
+        assert_eq!(results[0], packets);
+        assert_eq!(stats.packets_in(), packets.len() as u64);
+        assert_eq!(stats.packets_out(), packets.len() as u64);
+        assert_eq!(stats.packets_out_by_port(), vec![(0, packets.len() as u64)]);
+        assert_eq!(stats.bytes_out(), 0);
+    }
+
+    #[test]
+    fn counts_bytes() {
+        let packets = vec!["route", "-", "rs"];
+
+        let runtime = initialize_runtime();
+        let link = CounterLink::new()
+            .ingressor(immediate_stream(packets.clone()))
+            .bytes(Box::new(|packet| packet.len() as u64));
+        let stats = link.stats();
+
+        let results = runtime.block_on(run_link(link.build_link()));
+        assert_eq!(results[0], packets);
+        assert_eq!(stats.packets_out(), 3);
+        assert_eq!(stats.bytes_out(), 8);
+
+        stats.reset();
+        assert_eq!(stats.packets_out(), 0);
+        assert_eq!(stats.bytes_out(), 0);
+    }
+}
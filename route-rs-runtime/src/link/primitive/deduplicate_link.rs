@@ -0,0 +1,240 @@
+use crate::link::{Link, LinkBuilder, PacketStream};
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::collections::{HashSet, VecDeque};
+use std::hash::Hash;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+/// Maps a packet to the key duplicates are detected by, for instance its IP ID and checksum.
+pub type PacketKey<Packet, Key> = Box<dyn Fn(&Packet) -> Key + Send + Sync + 'static>;
+
+/// Drops packets whose key has already been seen within the last `window`, such as the copies of
+/// a broadcast frame that arrive on each of several bridged interfaces. The window starts at the
+/// first packet with a key, so a steady stream of identical packets still gets one through per
+/// window. Like ProcessLink, this link only does work when it is polled.
+#[derive(Default)]
+pub struct DeduplicateLink<Packet, Key> {
+    in_stream: Option<PacketStream<Packet>>,
+    key: Option<PacketKey<Packet, Key>>,
+    window: Option<Duration>,
+}
+
+impl<Packet, Key> DeduplicateLink<Packet, Key> {
+    pub fn new() -> Self {
+        DeduplicateLink {
+            in_stream: None,
+            key: None,
+            window: None,
+        }
+    }
+
+    pub fn key(self, key: PacketKey<Packet, Key>) -> Self {
+        DeduplicateLink {
+            in_stream: self.in_stream,
+            key: Some(key),
+            window: self.window,
+        }
+    }
+
+    /// How long after a packet is forwarded that packets with the same key are dropped.
+    pub fn window(self, window: Duration) -> Self {
+        assert!(
+            window > Duration::from_secs(0),
+            "DeduplicateLink window: {:?}, must be > 0",
+            window
+        );
+
+        DeduplicateLink {
+            in_stream: self.in_stream,
+            key: self.key,
+            window: Some(window),
+        }
+    }
+}
+
+impl<Packet: Send + 'static, Key: Hash + Eq + Clone + Send + 'static> LinkBuilder<Packet, Packet>
+    for DeduplicateLink<Packet, Key>
+{
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "DeduplicateLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("DeduplicateLink may only take 1 input stream")
+        }
+
+        DeduplicateLink {
+            in_stream: Some(in_streams.remove(0)),
+            key: self.key,
+            window: self.window,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("DeduplicateLink may only take 1 input stream")
+        }
+
+        DeduplicateLink {
+            in_stream: Some(in_stream),
+            key: self.key,
+            window: self.window,
+        }
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match (self.in_stream, self.key, self.window) {
+            (None, _, _) => panic!("Cannot build link! Missing input streams"),
+            (_, None, _) => panic!("Cannot build link! Missing key"),
+            (_, _, None) => panic!("Cannot build link! Missing window"),
+            (Some(in_stream), Some(key), Some(window)) => {
+                let deduplicator = Deduplicator {
+                    in_stream,
+                    key,
+                    window,
+                    seen: HashSet::new(),
+                    expiries: VecDeque::new(),
+                };
+                (vec![], vec![Box::new(deduplicator)])
+            }
+        }
+    }
+}
+
+/// The single egressor of DeduplicateLink
+struct Deduplicator<Packet, Key> {
+    in_stream: PacketStream<Packet>,
+    key: PacketKey<Packet, Key>,
+    window: Duration,
+    /// Keys seen within the window.
+    seen: HashSet<Key>,
+    /// Keys in the order they were seen, so they can be forgotten once their window has passed.
+    expiries: VecDeque<(Instant, Key)>,
+}
+
+impl<Packet, Key> Unpin for Deduplicator<Packet, Key> {}
+
+impl<Packet, Key: Hash + Eq + Clone> Deduplicator<Packet, Key> {
+    fn forget_expired(&mut self, now: Instant) {
+        while let Some((seen_at, _)) = self.expiries.front() {
+            if now.saturating_duration_since(*seen_at) < self.window {
+                break;
+            }
+            if let Some((_, key)) = self.expiries.pop_front() {
+                self.seen.remove(&key);
+            }
+        }
+    }
+
+    /// Returns whether the packet is the first with its key in the window, remembering it if so.
+    fn first_sighting(&mut self, packet: &Packet) -> bool {
+        let now = Instant::now();
+        self.forget_expired(now);
+
+        let key = (self.key)(packet);
+        if !self.seen.insert(key.clone()) {
+            return false;
+        }
+        self.expiries.push_back((now, key));
+        true
+    }
+}
+
+impl<Packet, Key: Hash + Eq + Clone> Stream for Deduplicator<Packet, Key> {
+    type Item = Packet;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let deduplicator = Pin::into_inner(self);
+        loop {
+            match ready!(Pin::new(&mut deduplicator.in_stream).poll_next(cx)) {
+                None => return Poll::Ready(None),
+                Some(packet) => {
+                    if deduplicator.first_sighting(&packet) {
+                        return Poll::Ready(Some(packet));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_key() {
+        DeduplicateLink::<i32, i32>::new()
+            .ingressor(immediate_stream(vec![]))
+            .window(Duration::from_secs(1))
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_window() {
+        DeduplicateLink::<i32, i32>::new()
+            .ingressor(immediate_stream(vec![]))
+            .key(Box::new(|packet| *packet))
+            .build_link();
+    }
+
+    #[test]
+    fn drops_duplicates_within_window() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = DeduplicateLink::new()
+                .ingressor(immediate_stream(vec![1, 2, 1, 3, 2, 2, 4, 1]))
+                .key(Box::new(|packet: &i32| *packet))
+                .window(Duration::from_secs(10))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn dedups_by_key() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = DeduplicateLink::new()
+                .ingressor(immediate_stream(vec![(1, 'a'), (1, 'b'), (2, 'c')]))
+                .key(Box::new(|packet: &(i32, char)| packet.0))
+                .window(Duration::from_secs(10))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![(1, 'a'), (2, 'c')]);
+    }
+
+    #[test]
+    fn forwards_again_after_window() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let packet_generator =
+                PacketIntervalGenerator::new(Duration::from_millis(20), vec![7; 5].into_iter());
+
+            let link = DeduplicateLink::new()
+                .ingressor(Box::new(packet_generator))
+                .key(Box::new(|packet: &i32| *packet))
+                .window(Duration::from_millis(50))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert!(
+            results[0].len() >= 2 && results[0].len() <= 3,
+            "forwarded: {:?}",
+            results[0]
+        );
+    }
+}
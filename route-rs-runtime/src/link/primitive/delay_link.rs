@@ -0,0 +1,356 @@
+use crate::link::primitive::QueueEgressor;
+use crate::link::utils::task_park::*;
+use crate::link::{Link, LinkBuilder, PacketStream};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::Sender;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use rand::Rng;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::{sleep_until, Sleep};
+
+/// Holds each packet for a fixed `delay`, plus a random amount of up to `jitter`, before releasing
+/// it downstream. Useful for emulating WAN links, or for testing timeout behavior. With jitter,
+/// packets are released in the order their delay expires, so they may be reordered, just as they
+/// would be on a real network.
+#[derive(Default)]
+pub struct DelayLink<Packet> {
+    in_stream: Option<PacketStream<Packet>>,
+    delay: Option<Duration>,
+    jitter: Duration,
+    queue_capacity: usize,
+}
+
+impl<Packet> DelayLink<Packet> {
+    pub fn new() -> Self {
+        DelayLink {
+            in_stream: None,
+            delay: None,
+            jitter: Duration::from_secs(0),
+            queue_capacity: 10,
+        }
+    }
+
+    pub fn delay(self, delay: Duration) -> Self {
+        DelayLink {
+            in_stream: self.in_stream,
+            delay: Some(delay),
+            jitter: self.jitter,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Changes jitter, the most extra delay drawn uniformly at random for each packet, default
+    /// value is 0.
+    pub fn jitter(self, jitter: Duration) -> Self {
+        DelayLink {
+            in_stream: self.in_stream,
+            delay: self.delay,
+            jitter,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Changes queue_capacity, default value is 10. This bounds both the packets being held and
+    /// the packets waiting to be pulled downstream.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "DelayLink queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        DelayLink {
+            in_stream: self.in_stream,
+            delay: self.delay,
+            jitter: self.jitter,
+            queue_capacity,
+        }
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Packet, Packet> for DelayLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "DelayLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("DelayLink may only take 1 input stream")
+        }
+
+        DelayLink {
+            in_stream: Some(in_streams.remove(0)),
+            delay: self.delay,
+            jitter: self.jitter,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("DelayLink may only take 1 input stream")
+        }
+
+        DelayLink {
+            in_stream: Some(in_stream),
+            delay: self.delay,
+            jitter: self.jitter,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match (self.in_stream, self.delay) {
+            (None, _) => panic!("Cannot build link! Missing input stream"),
+            (_, None) => panic!("Cannot build link! Missing delay"),
+            (Some(in_stream), Some(delay)) => {
+                let (to_egressor, from_ingressor) =
+                    crossbeam_channel::bounded::<Option<Packet>>(self.queue_capacity);
+                let task_park: Arc<AtomicCell<TaskParkState>> =
+                    Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+                let ingressor = DelayIngressor {
+                    input_stream: Some(in_stream),
+                    to_egressor,
+                    task_park: Arc::clone(&task_park),
+                    delay,
+                    jitter: self.jitter,
+                    held: BinaryHeap::with_capacity(self.queue_capacity),
+                    held_capacity: self.queue_capacity,
+                    sequence: 0,
+                    release_timer: None,
+                };
+                let egressor = QueueEgressor::new(from_ingressor, task_park);
+
+                (vec![Box::new(ingressor)], vec![Box::new(egressor)])
+            }
+        }
+    }
+}
+
+/// A packet being held until `release`. Ordered by release time, then by arrival, so that packets
+/// with the same release time keep their order.
+struct Delayed<Packet> {
+    release: Instant,
+    sequence: u64,
+    packet: Packet,
+}
+
+impl<Packet> PartialEq for Delayed<Packet> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<Packet> Eq for Delayed<Packet> {}
+
+impl<Packet> PartialOrd for Delayed<Packet> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<Packet> Ord for Delayed<Packet> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.release, self.sequence).cmp(&(other.release, other.sequence))
+    }
+}
+
+struct DelayIngressor<Packet> {
+    /// Set to `None` once the upstream has finished.
+    input_stream: Option<PacketStream<Packet>>,
+    to_egressor: Sender<Option<Packet>>,
+    task_park: Arc<AtomicCell<TaskParkState>>,
+    delay: Duration,
+    jitter: Duration,
+    held: BinaryHeap<Reverse<Delayed<Packet>>>,
+    held_capacity: usize,
+    sequence: u64,
+    release_timer: Option<Pin<Box<Sleep>>>,
+}
+
+impl<Packet> Unpin for DelayIngressor<Packet> {}
+
+impl<Packet> DelayIngressor<Packet> {
+    fn release_time(&self, now: Instant) -> Instant {
+        if self.jitter == Duration::from_secs(0) {
+            now + self.delay
+        } else {
+            let jitter_nanos = rand::thread_rng().gen_range(0, self.jitter.as_nanos() as u64 + 1);
+            now + self.delay + Duration::from_nanos(jitter_nanos)
+        }
+    }
+}
+
+impl<Packet> Future for DelayIngressor<Packet> {
+    type Output = ();
+
+    /// Releases any packets whose delay has expired, then pulls new packets from upstream and
+    /// stamps them with a release time. There are several cases where we can not make progress:
+    /// ###
+    /// #1 A packet is due but the to_egressor queue is full, we park and rely on the egressor to
+    /// wake us.
+    ///
+    /// #2 We are holding `queue_capacity` packets, or the upstream is `Pending`. We set a timer for
+    /// the next packet to be released, and rely on it, or the upstream, to wake us.
+    ///
+    /// #3 The upstream is finished and every packet has been released, we push a None onto the
+    /// to_egressor queue and enter tear-down.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let ingressor = Pin::into_inner(self);
+        loop {
+            let now = Instant::now();
+            while let Some(Reverse(next)) = ingressor.held.peek() {
+                if next.release > now {
+                    break;
+                }
+                if ingressor.to_egressor.is_full() {
+                    park_and_wake(&ingressor.task_park, cx.waker().clone());
+                    return Poll::Pending;
+                }
+                let Reverse(delayed) = ingressor.held.pop().unwrap();
+                ingressor
+                    .to_egressor
+                    .try_send(Some(delayed.packet))
+                    .expect("DelayIngressor::Poll try_send to_egressor shouldn't fail");
+                unpark_and_wake(&ingressor.task_park);
+            }
+
+            if ingressor.held.len() < ingressor.held_capacity {
+                if let Some(input_stream) = &mut ingressor.input_stream {
+                    match Pin::new(input_stream).poll_next(cx) {
+                        Poll::Ready(Some(packet)) => {
+                            let release = ingressor.release_time(now);
+                            ingressor.held.push(Reverse(Delayed {
+                                release,
+                                sequence: ingressor.sequence,
+                                packet,
+                            }));
+                            ingressor.sequence += 1;
+                            continue;
+                        }
+                        Poll::Ready(None) => ingressor.input_stream = None,
+                        Poll::Pending => {}
+                    }
+                }
+            }
+
+            match ingressor.held.peek() {
+                Some(Reverse(next)) => {
+                    let release = next.release;
+                    let timer = ingressor
+                        .release_timer
+                        .get_or_insert_with(|| Box::pin(sleep_until(release.into())));
+                    timer.as_mut().reset(release.into());
+                    if timer.as_mut().poll(cx).is_pending() {
+                        return Poll::Pending;
+                    }
+                }
+                None if ingressor.input_stream.is_none() => {
+                    if ingressor.to_egressor.is_full() {
+                        park_and_wake(&ingressor.task_park, cx.waker().clone());
+                        return Poll::Pending;
+                    }
+                    ingressor.to_egressor.try_send(None).expect(
+                        "DelayIngressor::Poll::Ready(None) try_send to_egressor shouldn't fail",
+                    );
+                    die_and_wake(&ingressor.task_park);
+                    return Poll::Ready(());
+                }
+                None => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_ingressor() {
+        DelayLink::<i32>::new()
+            .delay(Duration::from_millis(10))
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_delay() {
+        DelayLink::<i32>::new()
+            .ingressor(immediate_stream(vec![]))
+            .build_link();
+    }
+
+    #[test]
+    fn no_input() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = DelayLink::<i32>::new()
+                .ingressor(immediate_stream(vec![]))
+                .delay(Duration::from_millis(10))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert!(results[0].is_empty());
+    }
+
+    #[test]
+    fn delays_packets_in_order() {
+        let packets: Vec<i32> = (0..30).collect();
+
+        let runtime = initialize_runtime();
+        let (results, elapsed) = runtime.block_on(async {
+            let start = Instant::now();
+            let link = DelayLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .delay(Duration::from_millis(50))
+                .build_link();
+
+            (run_link(link).await, start.elapsed())
+        });
+        assert_eq!(results[0], packets);
+        assert!(elapsed >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn jitter_delays_every_packet() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (runnables, mut egressors) = DelayLink::new()
+                .ingressor(immediate_stream(
+                    (0..20).map(|_| Instant::now()).collect::<Vec<Instant>>(),
+                ))
+                .delay(Duration::from_millis(20))
+                .jitter(Duration::from_millis(20))
+                .queue_capacity(20)
+                .build_link();
+            for runnable in runnables {
+                tokio::spawn(runnable);
+            }
+
+            let mut egressor = egressors.remove(0);
+            let mut delays = vec![];
+            while let Some(sent) = egressor.next().await {
+                delays.push(sent.elapsed());
+            }
+            delays
+        });
+        assert_eq!(results.len(), 20);
+        assert!(results
+            .iter()
+            .all(|delay| *delay >= Duration::from_millis(20)));
+    }
+}
@@ -0,0 +1,371 @@
+use crate::link::primitive::{JoinIngressor, PacketCost};
+use crate::link::utils::task_park::*;
+use crate::link::{Link, LinkBuilder, PacketStream, TokioRunnable};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::Receiver;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Joins several inputs into one output, scheduling between them with Deficit Round Robin. Each
+/// input earns its `quantum` of bytes every round, and may send packets while it has enough
+/// deficit to cover their length, giving approximate byte fairness, weighted by quantum, regardless
+/// of packet sizes. Since the runtime is generic over packets, `packet_length` tells the link how
+/// long each packet is.
+#[derive(Default)]
+pub struct DrrLink<Packet: Send> {
+    in_streams: Option<Vec<PacketStream<Packet>>>,
+    quantums: Option<Vec<u64>>,
+    packet_length: Option<PacketCost<Packet>>,
+    queue_capacity: usize,
+}
+
+impl<Packet: Send> DrrLink<Packet> {
+    pub fn new() -> Self {
+        DrrLink {
+            in_streams: None,
+            quantums: None,
+            packet_length: None,
+            queue_capacity: 10,
+        }
+    }
+
+    /// Bytes each input earns per round, in the same order as the input streams.
+    pub fn quantums(self, quantums: Vec<u64>) -> Self {
+        assert!(
+            quantums.iter().all(|quantum| *quantum > 0),
+            "DrrLink quantums: {:?}, must all be > 0",
+            quantums
+        );
+
+        DrrLink {
+            in_streams: self.in_streams,
+            quantums: Some(quantums),
+            packet_length: self.packet_length,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    pub fn packet_length(self, packet_length: PacketCost<Packet>) -> Self {
+        DrrLink {
+            in_streams: self.in_streams,
+            quantums: self.quantums,
+            packet_length: Some(packet_length),
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Changes queue_capacity, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "Queue capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        DrrLink {
+            in_streams: self.in_streams,
+            quantums: self.quantums,
+            packet_length: self.packet_length,
+            queue_capacity,
+        }
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Packet, Packet> for DrrLink<Packet> {
+    fn ingressors(self, in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert!(
+            !in_streams.is_empty(),
+            "number of in_streams: {}, must be greater than 0",
+            in_streams.len()
+        );
+
+        if self.in_streams.is_some() {
+            panic!("DrrLink already has input streams")
+        }
+
+        DrrLink {
+            in_streams: Some(in_streams),
+            quantums: self.quantums,
+            packet_length: self.packet_length,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Appends the ingressor to the ingressors of the link.
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        let mut in_streams = self.in_streams.unwrap_or_default();
+        in_streams.push(in_stream);
+
+        DrrLink {
+            in_streams: Some(in_streams),
+            quantums: self.quantums,
+            packet_length: self.packet_length,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match (self.in_streams, self.quantums, self.packet_length) {
+            (None, _, _) => panic!("Cannot build link! Missing input streams"),
+            (_, None, _) => panic!("Cannot build link! Missing quantums"),
+            (_, _, None) => panic!("Cannot build link! Missing packet_length"),
+            (Some(input_streams), Some(quantums), Some(packet_length)) => {
+                assert_eq!(
+                    input_streams.len(),
+                    quantums.len(),
+                    "DrrLink needs a quantum for each input stream"
+                );
+
+                let number_ingressors = input_streams.len();
+                let mut ingressors: Vec<TokioRunnable> = Vec::new();
+                let mut from_ingressors: Vec<Receiver<Option<Packet>>> = Vec::new();
+                let mut task_parks: Vec<Arc<AtomicCell<TaskParkState>>> = Vec::new();
+
+                for input_stream in input_streams {
+                    let (to_egressor, from_ingressor) =
+                        crossbeam_channel::bounded::<Option<Packet>>(self.queue_capacity);
+                    let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+                    let ingressor =
+                        JoinIngressor::new(input_stream, to_egressor, Arc::clone(&task_park));
+                    ingressors.push(Box::new(ingressor));
+                    from_ingressors.push(from_ingressor);
+                    task_parks.push(task_park);
+                }
+
+                let egressor = DrrEgressor {
+                    from_ingressors,
+                    task_parks,
+                    ingressors_alive: number_ingressors,
+                    heads: (0..number_ingressors).map(|_| None).collect(),
+                    deficits: vec![0; number_ingressors],
+                    quantums,
+                    packet_length,
+                    current_port: 0,
+                    quantum_granted: false,
+                };
+
+                (ingressors, vec![Box::new(egressor)])
+            }
+        }
+    }
+}
+
+pub struct DrrEgressor<Packet: Sized> {
+    from_ingressors: Vec<Receiver<Option<Packet>>>,
+    task_parks: Vec<Arc<AtomicCell<TaskParkState>>>,
+    ingressors_alive: usize,
+    /// The packet at the head of each input, taken off its channel so that its length can be
+    /// checked against the deficit before it is sent.
+    heads: Vec<Option<Packet>>,
+    deficits: Vec<u64>,
+    quantums: Vec<u64>,
+    packet_length: PacketCost<Packet>,
+    current_port: usize,
+    /// Whether `current_port` has been given its quantum for this turn.
+    quantum_granted: bool,
+}
+
+impl<Packet: Sized> Unpin for DrrEgressor<Packet> {}
+
+impl<Packet: Sized> DrrEgressor<Packet> {
+    fn fill_head(&mut self, port: usize) {
+        if self.heads[port].is_some() {
+            return;
+        }
+        match self.from_ingressors[port].try_recv() {
+            Ok(Some(packet)) => {
+                self.heads[port] = Some(packet);
+                unpark_and_wake(&self.task_parks[port]);
+            }
+            Ok(None) => self.ingressors_alive -= 1,
+            Err(_) => {}
+        }
+    }
+
+    fn next_port(&mut self) {
+        self.current_port = (self.current_port + 1) % self.from_ingressors.len();
+        self.quantum_granted = false;
+    }
+}
+
+impl<Packet: Sized> Stream for DrrEgressor<Packet> {
+    type Item = Packet;
+
+    /// Visits each input in turn. On its turn an input with packets waiting is granted its quantum,
+    /// then sends packets until the packet at its head is longer than its deficit, at which point
+    /// the next input takes its turn. An input that is empty on its turn forfeits its deficit, so
+    /// idle inputs can not build up credit. If we visit every input without finding a packet, we
+    /// park with every ingressor, as JoinEgressor does.
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let egressor = Pin::into_inner(self);
+        let mut empty_ports = 0;
+        while empty_ports < egressor.from_ingressors.len() {
+            let port = egressor.current_port;
+            egressor.fill_head(port);
+
+            let length = match &egressor.heads[port] {
+                Some(packet) => (egressor.packet_length)(packet),
+                None => {
+                    egressor.deficits[port] = 0;
+                    egressor.next_port();
+                    empty_ports += 1;
+                    continue;
+                }
+            };
+            empty_ports = 0;
+
+            if !egressor.quantum_granted {
+                egressor.deficits[port] += egressor.quantums[port];
+                egressor.quantum_granted = true;
+            }
+            if length <= egressor.deficits[port] {
+                egressor.deficits[port] -= length;
+                return Poll::Ready(egressor.heads[port].take());
+            }
+            egressor.next_port();
+        }
+
+        if egressor.ingressors_alive == 0 {
+            for task_park in egressor.task_parks.iter() {
+                die_and_wake(task_park);
+            }
+            return Poll::Ready(None);
+        }
+
+        let mut parked_egressor_task = false;
+        let egressor_task = Arc::new(AtomicCell::new(Some(cx.waker().clone())));
+        for task_park in egressor.task_parks.iter() {
+            if indirect_park_and_wake(task_park, Arc::clone(&egressor_task)) {
+                parked_egressor_task = true;
+            }
+        }
+        if !parked_egressor_task {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    /// Packets are (input, length) pairs.
+    fn length() -> PacketCost<(usize, u64)> {
+        Box::new(|packet| packet.1)
+    }
+
+    /// Fills every input queue before the egressor is polled, so the schedule is deterministic.
+    async fn run_preloaded(link: Link<(usize, u64)>) -> Vec<(usize, u64)> {
+        let (runnables, mut egressors) = link;
+        for runnable in runnables {
+            tokio::spawn(runnable).await.unwrap();
+        }
+
+        let mut egressor = egressors.remove(0);
+        let mut output = vec![];
+        while let Some(packet) = egressor.next().await {
+            output.push(packet);
+        }
+        output
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_quantums() {
+        DrrLink::new()
+            .ingressor(immediate_stream(vec![(0, 1)]))
+            .packet_length(length())
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_quantums_do_not_match_inputs() {
+        DrrLink::new()
+            .ingressor(immediate_stream(vec![(0, 1)]))
+            .quantums(vec![100, 100])
+            .packet_length(length())
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_with_zero_quantum() {
+        DrrLink::<(usize, u64)>::new().quantums(vec![100, 0]);
+    }
+
+    #[test]
+    fn passes_all_packets() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = DrrLink::new()
+                .ingressor(immediate_stream((0..50).map(|_| (0, 100))))
+                .ingressor(immediate_stream((0..50).map(|_| (1, 1500))))
+                .quantums(vec![500, 500])
+                .packet_length(length())
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0].iter().filter(|packet| packet.0 == 0).count(), 50);
+        assert_eq!(results[0].iter().filter(|packet| packet.0 == 1).count(), 50);
+    }
+
+    #[test]
+    fn byte_fair_between_packet_sizes() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = DrrLink::new()
+                .ingressor(immediate_stream((0..9).map(|_| (0, 100))))
+                .ingressor(immediate_stream((0..3).map(|_| (1, 300))))
+                .quantums(vec![300, 300])
+                .packet_length(length())
+                .build_link();
+
+            run_preloaded(link).await
+        });
+        let ports: Vec<usize> = results.iter().map(|packet| packet.0).collect();
+        assert_eq!(ports, vec![0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn quantums_weight_inputs() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = DrrLink::new()
+                .ingressor(immediate_stream((0..4).map(|_| (0, 100))))
+                .ingressor(immediate_stream((0..4).map(|_| (1, 100))))
+                .quantums(vec![200, 100])
+                .packet_length(length())
+                .build_link();
+
+            run_preloaded(link).await
+        });
+        let ports: Vec<usize> = results.iter().map(|packet| packet.0).collect();
+        assert_eq!(ports, vec![0, 0, 1, 0, 0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn large_packets_accumulate_deficit() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = DrrLink::new()
+                .ingressor(immediate_stream((0..4).map(|_| (0, 100))))
+                .ingressor(immediate_stream(vec![(1, 250)]))
+                .quantums(vec![100, 100])
+                .packet_length(length())
+                .build_link();
+
+            run_preloaded(link).await
+        });
+        let ports: Vec<usize> = results.iter().map(|packet| packet.0).collect();
+        assert_eq!(ports, vec![0, 0, 0, 1, 0]);
+    }
+}
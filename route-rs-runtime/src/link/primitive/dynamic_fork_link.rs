@@ -0,0 +1,477 @@
+use crate::link::utils::task_park::*;
+use crate::link::{
+    primitive::QueueEgressor, registry::LinkHandle, Link, LinkBuilder, PacketStream,
+};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::{Sender, TrySendError};
+use futures::prelude::*;
+use futures::task::{Context, Poll, Waker};
+use std::collections::HashSet;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A ForkLink whose egressors can be attached and detached while the router is running, through a
+/// `DynamicForkControl` taken from the builder. This lets monitoring taps be added to a live
+/// router without rebuilding the pipeline. As with ForkLink, every packet is cloned to every
+/// attached egressor, and a full egressor holds up the rest. While no egressors are attached,
+/// packets are dropped.
+pub struct DynamicForkLink<Packet: Clone + Send> {
+    in_stream: Option<PacketStream<Packet>>,
+    num_egressors: usize,
+    control: DynamicForkControl<Packet>,
+}
+
+impl<Packet: Clone + Send> Default for DynamicForkLink<Packet> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Packet: Clone + Send> DynamicForkLink<Packet> {
+    pub fn new() -> Self {
+        DynamicForkLink {
+            in_stream: None,
+            num_egressors: 0,
+            control: DynamicForkControl::new(),
+        }
+    }
+
+    /// Changes queue_capacity, default value is 10. Applies to every egressor attached afterwards.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "DynamicForkLink queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        self.control.state.ports.lock().unwrap().queue_capacity = queue_capacity;
+        self
+    }
+
+    /// Changes num_egressors, the number of egressors returned by `build_link`, default value is 0.
+    /// These have ids `0..num_egressors`, and may be detached like any other.
+    pub fn num_egressors(self, num_egressors: usize) -> Self {
+        DynamicForkLink {
+            in_stream: self.in_stream,
+            num_egressors,
+            control: self.control,
+        }
+    }
+
+    /// Returns a handle for attaching and detaching egressors. It may be used before or after the
+    /// link is built.
+    pub fn control(&self) -> DynamicForkControl<Packet> {
+        self.control.clone()
+    }
+}
+
+impl<Packet: Send + Clone + 'static> LinkBuilder<Packet, Packet> for DynamicForkLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "DynamicForkLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("DynamicForkLink may only take 1 input stream")
+        }
+
+        DynamicForkLink {
+            in_stream: Some(in_streams.remove(0)),
+            num_egressors: self.num_egressors,
+            control: self.control,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("DynamicForkLink may only take 1 input stream")
+        }
+
+        DynamicForkLink {
+            in_stream: Some(in_stream),
+            num_egressors: self.num_egressors,
+            control: self.control,
+        }
+    }
+
+    fn handles(&self) -> Vec<LinkHandle> {
+        vec![Box::new(self.control())]
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        let control = self.control;
+        match self.in_stream {
+            None => panic!("Cannot build link! Missing input stream"),
+            Some(in_stream) => {
+                let egressors = (0..self.num_egressors)
+                    .map(|_| control.attach().1)
+                    .collect();
+
+                let ingressor = DynamicForkIngressor {
+                    input_stream: in_stream,
+                    ports: vec![],
+                    state: Arc::clone(&control.state),
+                };
+
+                (vec![Box::new(ingressor)], egressors)
+            }
+        }
+    }
+}
+
+/// Handle to a DynamicForkLink, for attaching and detaching egressors at runtime.
+pub struct DynamicForkControl<Packet> {
+    state: Arc<ForkState<Packet>>,
+}
+
+impl<Packet> Clone for DynamicForkControl<Packet> {
+    fn clone(&self) -> Self {
+        DynamicForkControl {
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+impl<Packet> DynamicForkControl<Packet> {
+    fn new() -> Self {
+        DynamicForkControl {
+            state: Arc::new(ForkState {
+                ports: Mutex::new(ForkPorts {
+                    queue_capacity: 10,
+                    next_id: 0,
+                    attached: vec![],
+                    detached: vec![],
+                    live: HashSet::new(),
+                    finished: false,
+                }),
+                changed: AtomicBool::new(false),
+                ingressor_task: AtomicCell::new(None),
+            }),
+        }
+    }
+}
+
+impl<Packet: Send + 'static> DynamicForkControl<Packet> {
+    /// Attaches a new egressor, which receives a copy of every packet from now on. Returns the id
+    /// to detach it with, and the egressor. If the link has already finished, the egressor is
+    /// finished too.
+    pub fn attach(&self) -> (usize, PacketStream<Packet>) {
+        let mut ports = self.state.ports.lock().unwrap();
+        let id = ports.next_id;
+        ports.next_id += 1;
+
+        let (to_egressor, from_ingressor) =
+            crossbeam_channel::bounded::<Option<Packet>>(ports.queue_capacity);
+        let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+        let egressor = QueueEgressor::new(from_ingressor, Arc::clone(&task_park));
+
+        if ports.finished {
+            to_egressor
+                .try_send(None)
+                .expect("DynamicForkControl::attach try_send to new egressor shouldn't fail");
+            die_and_wake(&task_park);
+        } else {
+            ports.attached.push(ForkPort {
+                id,
+                to_egressor,
+                task_park,
+            });
+            ports.live.insert(id);
+            drop(ports);
+            self.state.notify_ingressor();
+        }
+
+        (id, Box::new(egressor))
+    }
+
+    /// Detaches the egressor with the given id. It receives any packets already queued for it, and
+    /// then finishes. Returns `false` if no such egressor is attached.
+    pub fn detach(&self, id: usize) -> bool {
+        let mut ports = self.state.ports.lock().unwrap();
+        if !ports.live.remove(&id) {
+            return false;
+        }
+        ports.detached.push(id);
+        drop(ports);
+        self.state.notify_ingressor();
+        true
+    }
+
+    /// The number of egressors currently attached.
+    pub fn num_egressors(&self) -> usize {
+        self.state.ports.lock().unwrap().live.len()
+    }
+}
+
+/// State shared between a DynamicForkLink's ingressor and its controls.
+struct ForkState<Packet> {
+    ports: Mutex<ForkPorts<Packet>>,
+    /// Set by the controls when `ports` has changes for the ingressor to pick up.
+    changed: AtomicBool,
+    /// The ingressor's waker while it is asleep, so controls can wake it to pick up changes.
+    ingressor_task: AtomicCell<Option<Waker>>,
+}
+
+impl<Packet> ForkState<Packet> {
+    fn notify_ingressor(&self) {
+        self.changed.store(true, Ordering::SeqCst);
+        if let Some(task) = self.ingressor_task.take() {
+            task.wake();
+        }
+    }
+}
+
+struct ForkPorts<Packet> {
+    queue_capacity: usize,
+    next_id: usize,
+    /// Ports attached since the ingressor last picked up changes.
+    attached: Vec<ForkPort<Packet>>,
+    /// Ids detached since the ingressor last picked up changes.
+    detached: Vec<usize>,
+    /// Ids of every attached port, including those the ingressor has not picked up yet.
+    live: HashSet<usize>,
+    finished: bool,
+}
+
+struct ForkPort<Packet> {
+    id: usize,
+    to_egressor: Sender<Option<Packet>>,
+    task_park: Arc<AtomicCell<TaskParkState>>,
+}
+
+impl<Packet> ForkPort<Packet> {
+    /// Dropping our sender lets the egressor drain what is queued, then finish, so it only needs
+    /// waking in case it is parked.
+    fn close(self) {
+        drop(self.to_egressor);
+        die_and_wake(&self.task_park);
+    }
+}
+
+pub struct DynamicForkIngressor<Packet> {
+    input_stream: PacketStream<Packet>,
+    ports: Vec<ForkPort<Packet>>,
+    state: Arc<ForkState<Packet>>,
+}
+
+impl<Packet> Unpin for DynamicForkIngressor<Packet> {}
+
+impl<Packet> DynamicForkIngressor<Packet> {
+    /// Picks up ports attached and detached by the controls.
+    fn apply_changes(&mut self) {
+        if !self.state.changed.swap(false, Ordering::SeqCst) {
+            return;
+        }
+
+        let mut ports = self.state.ports.lock().unwrap();
+        self.ports.append(&mut ports.attached);
+        for id in ports.detached.drain(..) {
+            if let Some(index) = self.ports.iter().position(|port| port.id == id) {
+                self.ports.remove(index).close();
+            }
+        }
+    }
+
+    /// Leaves our waker for the controls before sleeping. If they made changes before it was
+    /// there to be woken, we wake ourselves to pick them up.
+    fn sleep(&self, cx: &mut Context) -> Poll<()> {
+        self.state.ingressor_task.store(Some(cx.waker().clone()));
+        if self.state.changed.load(Ordering::SeqCst) {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+
+    /// Finishes every port, including any attached that we have not picked up yet, and any
+    /// attached from now on.
+    fn finish(&mut self) {
+        let mut ports = self.state.ports.lock().unwrap();
+        ports.finished = true;
+        ports.live.clear();
+        ports.detached.clear();
+        self.ports.append(&mut ports.attached);
+
+        for port in self.ports.drain(..) {
+            if let Err(TrySendError::Full(_)) = port.to_egressor.try_send(None) {
+                panic!("DynamicForkIngressor::finish try_send to_egressor shouldn't fail");
+            }
+            port.close();
+        }
+    }
+}
+
+impl<Packet: Send + Clone> Future for DynamicForkIngressor<Packet> {
+    type Output = ();
+
+    /// Same logic as ForkIngressor, except that ports attached and detached by the controls are
+    /// picked up before each packet. If an egressor has been dropped, its port is detached.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let ingressor = Pin::into_inner(self);
+        loop {
+            ingressor.apply_changes();
+
+            if let Some(port) = ingressor
+                .ports
+                .iter()
+                .find(|port| port.to_egressor.is_full())
+            {
+                park_and_wake(&port.task_park, cx.waker().clone());
+                return ingressor.sleep(cx);
+            }
+
+            let packet = match Pin::new(&mut ingressor.input_stream).poll_next(cx) {
+                Poll::Pending => return ingressor.sleep(cx),
+                Poll::Ready(None) => {
+                    ingressor.finish();
+                    return Poll::Ready(());
+                }
+                Poll::Ready(Some(packet)) => packet,
+            };
+
+            let mut disconnected = vec![];
+            for port in ingressor.ports.iter() {
+                match port.to_egressor.try_send(Some(packet.clone())) {
+                    Ok(()) => unpark_and_wake(&port.task_park),
+                    Err(TrySendError::Disconnected(_)) => disconnected.push(port.id),
+                    Err(TrySendError::Full(_)) => panic!(
+                        "Error in port {} sender, have nowhere to put packet",
+                        port.id
+                    ),
+                }
+            }
+
+            if !disconnected.is_empty() {
+                let mut ports = ingressor.state.ports.lock().unwrap();
+                for id in disconnected.iter() {
+                    ports.live.remove(id);
+                }
+                ingressor
+                    .ports
+                    .retain(|port| !disconnected.contains(&port.id));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
+    use std::time::Duration;
+    use tokio::time::sleep;
+
+    async fn collect(mut egressor: PacketStream<i32>) -> Vec<i32> {
+        let mut output = vec![];
+        while let Some(packet) = egressor.next().await {
+            output.push(packet);
+        }
+        output
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_input_stream() {
+        DynamicForkLink::<i32>::new().num_egressors(1).build_link();
+    }
+
+    #[test]
+    fn forks_to_initial_egressors() {
+        let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = DynamicForkLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .num_egressors(2)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], packets);
+        assert_eq!(results[1], packets);
+    }
+
+    #[test]
+    fn attach_while_running() {
+        let runtime = initialize_runtime();
+        let (tap, rest) = runtime.block_on(async {
+            let packet_generator = PacketIntervalGenerator::new(Duration::from_millis(10), 0..20);
+
+            let link = DynamicForkLink::new()
+                .ingressor(Box::new(packet_generator))
+                .num_egressors(1);
+            let control = link.control();
+            let (runnables, mut egressors) = link.build_link();
+            for runnable in runnables {
+                tokio::spawn(runnable);
+            }
+            let rest = tokio::spawn(collect(egressors.remove(0)));
+
+            sleep(Duration::from_millis(75)).await;
+            let (_, tap) = control.attach();
+            (collect(tap).await, rest.await.unwrap())
+        });
+        assert_eq!(rest, (0..20).collect::<Vec<i32>>());
+        assert!(!tap.is_empty() && tap.len() < 20, "tap: {:?}", tap);
+        assert_eq!(tap, ((20 - tap.len() as i32)..20).collect::<Vec<i32>>());
+    }
+
+    #[test]
+    fn detach_while_running() {
+        let runtime = initialize_runtime();
+        let (tap, rest) = runtime.block_on(async {
+            let packet_generator = PacketIntervalGenerator::new(Duration::from_millis(10), 0..20);
+
+            let link = DynamicForkLink::new()
+                .ingressor(Box::new(packet_generator))
+                .num_egressors(2);
+            let control = link.control();
+            let (runnables, mut egressors) = link.build_link();
+            for runnable in runnables {
+                tokio::spawn(runnable);
+            }
+            let tap = tokio::spawn(collect(egressors.remove(0)));
+            let rest = tokio::spawn(collect(egressors.remove(0)));
+
+            sleep(Duration::from_millis(75)).await;
+            assert!(control.detach(0));
+            assert_eq!(control.num_egressors(), 1);
+            (tap.await.unwrap(), rest.await.unwrap())
+        });
+        assert_eq!(rest, (0..20).collect::<Vec<i32>>());
+        assert!(!tap.is_empty() && tap.len() < 20, "tap: {:?}", tap);
+        assert_eq!(tap, (0..tap.len() as i32).collect::<Vec<i32>>());
+    }
+
+    #[test]
+    fn detach_unknown_id() {
+        let link = DynamicForkLink::<i32>::new().num_egressors(1);
+        let control = link.control();
+        assert!(!control.detach(0));
+
+        link.ingressor(immediate_stream(vec![])).build_link();
+        assert!(control.detach(0));
+        assert!(!control.detach(0));
+    }
+
+    #[test]
+    fn attach_after_finished() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = DynamicForkLink::new().ingressor(immediate_stream(vec![0, 1, 2]));
+            let control = link.control();
+            let (runnables, _) = link.build_link();
+            for runnable in runnables {
+                tokio::spawn(runnable).await.unwrap();
+            }
+
+            collect(control.attach().1).await
+        });
+        assert!(results.is_empty());
+    }
+}
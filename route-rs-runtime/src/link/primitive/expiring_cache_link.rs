@@ -0,0 +1,558 @@
+use crate::link::primitive::PacketKey;
+use crate::link::utils::task_park::*;
+use crate::link::{primitive::QueueEgressor, Link, LinkBuilder, PacketStream};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::Sender;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The egressor requests missing the cache are forwarded to.
+pub const CACHE_MISS_PORT: usize = 0;
+/// The egressor replies built from the cache are sent to.
+pub const CACHE_HIT_PORT: usize = 1;
+/// The egressor responses are passed on to, once they have been cached.
+pub const CACHE_RESPONSE_PORT: usize = 2;
+
+/// Builds the reply to a request from the response cached for its key, for instance copying the
+/// request's transaction ID and addresses into a cached DNS answer.
+pub type CacheResponder<Packet> = Box<dyn Fn(&Packet, &Packet) -> Packet + Send + Sync + 'static>;
+
+/// Caches responses, so that repeated requests can be answered without going further into the
+/// graph, such as DNS answers for a resolver. Requests come in through `ingressor`, and are looked
+/// up by their `request_key`. A hit is answered straight away on `CACHE_HIT_PORT`, by the
+/// `responder`, and a miss is forwarded on `CACHE_MISS_PORT`. Responses come back in through
+/// `response_ingressor`, are cached under their `response_key` for `ttl`, and passed on through
+/// `CACHE_RESPONSE_PORT`. Packets whose key is None are never cached, nor answered from the cache.
+/// The cache holds at most `max_entries` responses; once full, expired responses are swept out,
+/// and if there are none, new responses go uncached until some expire.
+pub struct ExpiringCacheLink<Packet, Key> {
+    in_stream: Option<PacketStream<Packet>>,
+    response_stream: Option<PacketStream<Packet>>,
+    request_key: Option<PacketKey<Packet, Option<Key>>>,
+    response_key: Option<PacketKey<Packet, Option<Key>>>,
+    responder: Option<CacheResponder<Packet>>,
+    ttl: Option<Duration>,
+    max_entries: usize,
+    queue_capacity: usize,
+}
+
+impl<Packet, Key> Default for ExpiringCacheLink<Packet, Key> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Packet, Key> ExpiringCacheLink<Packet, Key> {
+    pub fn new() -> Self {
+        ExpiringCacheLink {
+            in_stream: None,
+            response_stream: None,
+            request_key: None,
+            response_key: None,
+            responder: None,
+            ttl: None,
+            max_entries: 1024,
+            queue_capacity: 10,
+        }
+    }
+
+    pub fn response_ingressor(self, response_stream: PacketStream<Packet>) -> Self {
+        if self.response_stream.is_some() {
+            panic!("ExpiringCacheLink may only take 1 response input stream")
+        }
+
+        ExpiringCacheLink {
+            in_stream: self.in_stream,
+            response_stream: Some(response_stream),
+            request_key: self.request_key,
+            response_key: self.response_key,
+            responder: self.responder,
+            ttl: self.ttl,
+            max_entries: self.max_entries,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    pub fn request_key(self, request_key: PacketKey<Packet, Option<Key>>) -> Self {
+        ExpiringCacheLink {
+            in_stream: self.in_stream,
+            response_stream: self.response_stream,
+            request_key: Some(request_key),
+            response_key: self.response_key,
+            responder: self.responder,
+            ttl: self.ttl,
+            max_entries: self.max_entries,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    pub fn response_key(self, response_key: PacketKey<Packet, Option<Key>>) -> Self {
+        ExpiringCacheLink {
+            in_stream: self.in_stream,
+            response_stream: self.response_stream,
+            request_key: self.request_key,
+            response_key: Some(response_key),
+            responder: self.responder,
+            ttl: self.ttl,
+            max_entries: self.max_entries,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    pub fn responder(self, responder: CacheResponder<Packet>) -> Self {
+        ExpiringCacheLink {
+            in_stream: self.in_stream,
+            response_stream: self.response_stream,
+            request_key: self.request_key,
+            response_key: self.response_key,
+            responder: Some(responder),
+            ttl: self.ttl,
+            max_entries: self.max_entries,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// How long after a response is cached that it may answer requests.
+    pub fn ttl(self, ttl: Duration) -> Self {
+        assert!(
+            ttl > Duration::from_secs(0),
+            "ExpiringCacheLink ttl: {:?}, must be > 0",
+            ttl
+        );
+
+        ExpiringCacheLink {
+            in_stream: self.in_stream,
+            response_stream: self.response_stream,
+            request_key: self.request_key,
+            response_key: self.response_key,
+            responder: self.responder,
+            ttl: Some(ttl),
+            max_entries: self.max_entries,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Changes max_entries, the most responses cached at once, default value is 1024.
+    pub fn max_entries(self, max_entries: usize) -> Self {
+        assert!(
+            max_entries > 0,
+            "ExpiringCacheLink max_entries: {}, must be > 0",
+            max_entries
+        );
+
+        ExpiringCacheLink {
+            in_stream: self.in_stream,
+            response_stream: self.response_stream,
+            request_key: self.request_key,
+            response_key: self.response_key,
+            responder: self.responder,
+            ttl: self.ttl,
+            max_entries,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Changes queue_capacity, of the miss and hit egressors, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "ExpiringCacheLink queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        ExpiringCacheLink {
+            in_stream: self.in_stream,
+            response_stream: self.response_stream,
+            request_key: self.request_key,
+            response_key: self.response_key,
+            responder: self.responder,
+            ttl: self.ttl,
+            max_entries: self.max_entries,
+            queue_capacity,
+        }
+    }
+}
+
+impl<Packet: Clone + Send + 'static, Key: Hash + Eq + Send + 'static> LinkBuilder<Packet, Packet>
+    for ExpiringCacheLink<Packet, Key>
+{
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "ExpiringCacheLink may only take 1 request input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("ExpiringCacheLink may only take 1 request input stream")
+        }
+
+        ExpiringCacheLink {
+            in_stream: Some(in_streams.remove(0)),
+            response_stream: self.response_stream,
+            request_key: self.request_key,
+            response_key: self.response_key,
+            responder: self.responder,
+            ttl: self.ttl,
+            max_entries: self.max_entries,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("ExpiringCacheLink may only take 1 request input stream")
+        }
+
+        ExpiringCacheLink {
+            in_stream: Some(in_stream),
+            response_stream: self.response_stream,
+            request_key: self.request_key,
+            response_key: self.response_key,
+            responder: self.responder,
+            ttl: self.ttl,
+            max_entries: self.max_entries,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match (
+            self.in_stream,
+            self.response_stream,
+            self.request_key,
+            self.response_key,
+            self.responder,
+            self.ttl,
+        ) {
+            (None, _, _, _, _, _) => panic!("Cannot build link! Missing request input stream"),
+            (_, None, _, _, _, _) => panic!("Cannot build link! Missing response input stream"),
+            (_, _, None, _, _, _) => panic!("Cannot build link! Missing request_key"),
+            (_, _, _, None, _, _) => panic!("Cannot build link! Missing response_key"),
+            (_, _, _, _, None, _) => panic!("Cannot build link! Missing responder"),
+            (_, _, _, _, _, None) => panic!("Cannot build link! Missing ttl"),
+            (
+                Some(in_stream),
+                Some(response_stream),
+                Some(request_key),
+                Some(response_key),
+                Some(responder),
+                Some(ttl),
+            ) => {
+                let cache = Arc::new(Mutex::new(ResponseCache {
+                    entries: HashMap::new(),
+                    ttl,
+                    max_entries: self.max_entries,
+                }));
+
+                let mut to_egressors: Vec<Sender<Option<Packet>>> = Vec::new();
+                let mut egressors: Vec<PacketStream<Packet>> = Vec::new();
+                let mut task_parks: Vec<Arc<AtomicCell<TaskParkState>>> = Vec::new();
+
+                for _ in 0..2 {
+                    let (to_egressor, from_ingressor) =
+                        crossbeam_channel::bounded::<Option<Packet>>(self.queue_capacity);
+                    let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+                    let egressor = QueueEgressor::new(from_ingressor, Arc::clone(&task_park));
+
+                    to_egressors.push(to_egressor);
+                    egressors.push(Box::new(egressor));
+                    task_parks.push(task_park);
+                }
+
+                let ingressor = CacheIngressor {
+                    input_stream: in_stream,
+                    to_egressors,
+                    task_parks,
+                    request_key,
+                    responder,
+                    cache: Arc::clone(&cache),
+                };
+
+                let responses = response_stream.map(move |response| {
+                    if let Some(key) = response_key(&response) {
+                        cache.lock().unwrap().insert(key, &response, Instant::now());
+                    }
+                    response
+                });
+                egressors.push(Box::new(responses));
+
+                (vec![Box::new(ingressor)], egressors)
+            }
+        }
+    }
+}
+
+struct ResponseCache<Packet, Key> {
+    /// Each cached response, and when it expires.
+    entries: HashMap<Key, (Packet, Instant)>,
+    ttl: Duration,
+    max_entries: usize,
+}
+
+impl<Packet, Key: Hash + Eq> ResponseCache<Packet, Key> {
+    fn lookup(&mut self, key: &Key, now: Instant) -> Option<&Packet> {
+        let expired = match self.entries.get(key) {
+            Some((_, expires)) => *expires <= now,
+            None => return None,
+        };
+        if expired {
+            self.entries.remove(key);
+            return None;
+        }
+        self.entries.get(key).map(|(response, _)| response)
+    }
+}
+
+impl<Packet: Clone, Key: Hash + Eq> ResponseCache<Packet, Key> {
+    fn insert(&mut self, key: Key, response: &Packet, now: Instant) {
+        if self.entries.len() >= self.max_entries && !self.entries.contains_key(&key) {
+            self.entries.retain(|_, (_, expires)| *expires > now);
+            if self.entries.len() >= self.max_entries {
+                return;
+            }
+        }
+        self.entries.insert(key, (response.clone(), now + self.ttl));
+    }
+}
+
+pub struct CacheIngressor<Packet, Key> {
+    input_stream: PacketStream<Packet>,
+    to_egressors: Vec<Sender<Option<Packet>>>,
+    task_parks: Vec<Arc<AtomicCell<TaskParkState>>>,
+    request_key: PacketKey<Packet, Option<Key>>,
+    responder: CacheResponder<Packet>,
+    cache: Arc<Mutex<ResponseCache<Packet, Key>>>,
+}
+
+impl<Packet, Key> Unpin for CacheIngressor<Packet, Key> {}
+
+impl<Packet, Key: Hash + Eq> CacheIngressor<Packet, Key> {
+    /// Returns the port a request goes to, and the packet to send there: the request itself on a
+    /// miss, or the reply built from the cache on a hit.
+    fn answer(&self, request: Packet) -> (usize, Packet) {
+        let key = match (self.request_key)(&request) {
+            Some(key) => key,
+            None => return (CACHE_MISS_PORT, request),
+        };
+        let mut cache = self.cache.lock().unwrap();
+        match cache.lookup(&key, Instant::now()) {
+            Some(response) => (CACHE_HIT_PORT, (self.responder)(&request, response)),
+            None => (CACHE_MISS_PORT, request),
+        }
+    }
+}
+
+impl<Packet, Key: Hash + Eq> Future for CacheIngressor<Packet, Key> {
+    type Output = ();
+
+    /// Same logic as ClassifyIngressor, with the cache deciding whether each request is a hit or a
+    /// miss.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let ingressor = Pin::into_inner(self);
+        loop {
+            for (port, to_egressor) in ingressor.to_egressors.iter().enumerate() {
+                if to_egressor.is_full() {
+                    park_and_wake(&ingressor.task_parks[port], cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+
+            match ready!(Pin::new(&mut ingressor.input_stream).poll_next(cx)) {
+                None => {
+                    for to_egressor in ingressor.to_egressors.iter() {
+                        to_egressor
+                            .try_send(None)
+                            .expect("CacheIngressor: try_send to_egressor shouldn't fail");
+                    }
+                    for task_park in ingressor.task_parks.iter() {
+                        die_and_wake(task_park);
+                    }
+                    return Poll::Ready(());
+                }
+                Some(request) => {
+                    let (port, packet) = ingressor.answer(request);
+                    ingressor.to_egressors[port]
+                        .try_send(Some(packet))
+                        .expect("CacheIngressor: try_send to_egressor shouldn't fail");
+                    unpark_and_wake(&ingressor.task_parks[port]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use tokio::time::sleep;
+
+    /// Packets are (id, name, answer) triples: requests have no answer, responses do.
+    type Packet = (u32, &'static str, Option<u32>);
+
+    fn request_key() -> PacketKey<Packet, Option<&'static str>> {
+        Box::new(|packet| Some(packet.1))
+    }
+
+    fn response_key() -> PacketKey<Packet, Option<&'static str>> {
+        Box::new(|packet| packet.2.map(|_| packet.1))
+    }
+
+    /// Answers with the cached answer, under the request's id.
+    fn responder() -> CacheResponder<Packet> {
+        Box::new(|request, response| (request.0, request.1, response.2))
+    }
+
+    fn cache_link(
+        requests: PacketStream<Packet>,
+        responses: PacketStream<Packet>,
+        ttl: Duration,
+    ) -> Link<Packet> {
+        ExpiringCacheLink::new()
+            .ingressor(requests)
+            .response_ingressor(responses)
+            .request_key(request_key())
+            .response_key(response_key())
+            .responder(responder())
+            .ttl(ttl)
+            .build_link()
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_response_stream() {
+        ExpiringCacheLink::new()
+            .ingressor(immediate_stream(vec![]))
+            .request_key(request_key())
+            .response_key(response_key())
+            .responder(responder())
+            .ttl(Duration::from_secs(1))
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_with_zero_ttl() {
+        ExpiringCacheLink::<Packet, &'static str>::new().ttl(Duration::from_secs(0));
+    }
+
+    #[test]
+    fn forwards_misses_and_responses() {
+        let requests = vec![(1, "a", None), (2, "b", None)];
+        let responses = vec![(1, "a", Some(10))];
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = cache_link(
+                immediate_stream(requests.clone()),
+                immediate_stream(responses.clone()),
+                Duration::from_secs(60),
+            );
+
+            run_link(link).await
+        });
+        assert_eq!(results[CACHE_MISS_PORT], requests);
+        assert!(results[CACHE_HIT_PORT].is_empty());
+        assert_eq!(results[CACHE_RESPONSE_PORT], responses);
+    }
+
+    #[test]
+    fn answers_hits_from_cache() {
+        let runtime = initialize_runtime();
+        let (hits, misses) = runtime.block_on(async {
+            let (to_link, requests) = futures::channel::mpsc::unbounded();
+            let (mut runnables, mut egressors) = cache_link(
+                Box::new(requests),
+                immediate_stream(vec![(1, "a", Some(10)), (2, "b", Some(20))]),
+                Duration::from_secs(60),
+            );
+            tokio::spawn(runnables.remove(0));
+
+            let mut responses = egressors.pop().unwrap();
+            while responses.next().await.is_some() {}
+
+            for request in [(3, "a", None), (4, "c", None), (5, "b", None)]
+                .iter()
+                .cloned()
+            {
+                to_link.unbounded_send(request).unwrap();
+            }
+            drop(to_link);
+
+            let mut hits = vec![];
+            while let Some(packet) = egressors[CACHE_HIT_PORT].next().await {
+                hits.push(packet);
+            }
+            let mut misses = vec![];
+            while let Some(packet) = egressors[CACHE_MISS_PORT].next().await {
+                misses.push(packet);
+            }
+            (hits, misses)
+        });
+        assert_eq!(hits, vec![(3, "a", Some(10)), (5, "b", Some(20))]);
+        assert_eq!(misses, vec![(4, "c", None)]);
+    }
+
+    #[test]
+    fn expired_responses_miss() {
+        let runtime = initialize_runtime();
+        let (hits, misses) = runtime.block_on(async {
+            let (to_link, requests) = futures::channel::mpsc::unbounded();
+            let (mut runnables, mut egressors) = cache_link(
+                Box::new(requests),
+                immediate_stream(vec![(1, "a", Some(10))]),
+                Duration::from_millis(20),
+            );
+            tokio::spawn(runnables.remove(0));
+
+            let mut responses = egressors.pop().unwrap();
+            while responses.next().await.is_some() {}
+
+            to_link.unbounded_send((2, "a", None)).unwrap();
+            sleep(Duration::from_millis(50)).await;
+            to_link.unbounded_send((3, "a", None)).unwrap();
+            drop(to_link);
+
+            let mut hits = vec![];
+            while let Some(packet) = egressors[CACHE_HIT_PORT].next().await {
+                hits.push(packet);
+            }
+            let mut misses = vec![];
+            while let Some(packet) = egressors[CACHE_MISS_PORT].next().await {
+                misses.push(packet);
+            }
+            (hits, misses)
+        });
+        assert_eq!(hits, vec![(2, "a", Some(10))]);
+        assert_eq!(misses, vec![(3, "a", None)]);
+    }
+
+    #[test]
+    fn full_cache_keeps_unexpired_entries() {
+        let mut cache = ResponseCache {
+            entries: HashMap::new(),
+            ttl: Duration::from_secs(60),
+            max_entries: 2,
+        };
+        let now = Instant::now();
+        cache.insert("a", &1, now);
+        cache.insert("b", &2, now);
+        cache.insert("c", &3, now);
+        assert_eq!(cache.lookup(&"a", now), Some(&1));
+        assert_eq!(cache.lookup(&"c", now), None);
+
+        let later = now + Duration::from_secs(61);
+        cache.insert("c", &3, later);
+        assert_eq!(cache.lookup(&"c", later), Some(&3));
+        assert_eq!(cache.lookup(&"a", later), None);
+    }
+}
@@ -1,5 +1,9 @@
+use crate::link::primitive::PacketCost;
+use crate::link::utils::stats::{LinkCounter, LinkStats};
 use crate::link::utils::task_park::*;
-use crate::link::{primitive::QueueEgressor, Link, LinkBuilder, PacketStream};
+use crate::link::{
+    primitive::QueueEgressor, registry::LinkHandle, Link, LinkBuilder, PacketStream,
+};
 use crossbeam::atomic::AtomicCell;
 use crossbeam::crossbeam_channel;
 use crossbeam::crossbeam_channel::{Receiver, Sender};
@@ -13,6 +17,8 @@ pub struct ForkLink<Packet: Clone + Send> {
     in_stream: Option<PacketStream<Packet>>,
     queue_capacity: usize,
     num_egressors: Option<usize>,
+    bytes: Option<Arc<PacketCost<Packet>>>,
+    stats: Arc<LinkStats>,
 }
 
 impl<Packet: Clone + Send> ForkLink<Packet> {
@@ -21,6 +27,8 @@ impl<Packet: Clone + Send> ForkLink<Packet> {
             in_stream: None,
             queue_capacity: 10,
             num_egressors: None,
+            bytes: None,
+            stats: Arc::new(LinkStats::default()),
         }
     }
 
@@ -35,6 +43,8 @@ impl<Packet: Clone + Send> ForkLink<Packet> {
             in_stream: self.in_stream,
             queue_capacity,
             num_egressors: self.num_egressors,
+            bytes: self.bytes,
+            stats: self.stats,
         }
     }
 
@@ -48,8 +58,28 @@ impl<Packet: Clone + Send> ForkLink<Packet> {
             in_stream: self.in_stream,
             queue_capacity: self.queue_capacity,
             num_egressors: Some(num_egressors),
+            bytes: self.bytes,
+            stats: self.stats,
         }
     }
+
+    /// Sets the closure that gives the length of a packet in bytes, to count the bytes given out
+    /// in the link's stats. Without it, only packets are counted.
+    pub fn bytes(self, bytes: PacketCost<Packet>) -> Self {
+        ForkLink {
+            in_stream: self.in_stream,
+            queue_capacity: self.queue_capacity,
+            num_egressors: self.num_egressors,
+            bytes: Some(Arc::new(bytes)),
+            stats: self.stats,
+        }
+    }
+
+    /// Returns the link's counters, kept with the `metrics` feature. They may be read before or
+    /// after the link is built.
+    pub fn stats(&self) -> Arc<LinkStats> {
+        Arc::clone(&self.stats)
+    }
 }
 
 impl<Packet: Send + Clone + 'static> LinkBuilder<Packet, Packet> for ForkLink<Packet> {
@@ -68,6 +98,8 @@ impl<Packet: Send + Clone + 'static> LinkBuilder<Packet, Packet> for ForkLink<Pa
             in_stream: Some(in_streams.remove(0)),
             queue_capacity: self.queue_capacity,
             num_egressors: self.num_egressors,
+            bytes: self.bytes,
+            stats: self.stats,
         }
     }
 
@@ -80,9 +112,15 @@ impl<Packet: Send + Clone + 'static> LinkBuilder<Packet, Packet> for ForkLink<Pa
             in_stream: Some(in_stream),
             queue_capacity: self.queue_capacity,
             num_egressors: self.num_egressors,
+            bytes: self.bytes,
+            stats: self.stats,
         }
     }
 
+    fn handles(&self) -> Vec<LinkHandle> {
+        vec![Box::new(self.stats())]
+    }
+
     fn build_link(self) -> Link<Packet> {
         if self.in_stream.is_none() {
             panic!("Cannot build link! Missing input stream");
@@ -96,12 +134,13 @@ impl<Packet: Send + Clone + 'static> LinkBuilder<Packet, Packet> for ForkLink<Pa
 
             let mut task_parks: Vec<Arc<AtomicCell<TaskParkState>>> = Vec::new();
 
-            for _ in 0..self.num_egressors.unwrap() {
+            for port in 0..self.num_egressors.unwrap() {
                 let (to_egressor, from_ingressor) =
                     crossbeam_channel::bounded::<Option<Packet>>(self.queue_capacity);
                 let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
 
-                let egressor = QueueEgressor::new(from_ingressor.clone(), Arc::clone(&task_park));
+                let egressor = QueueEgressor::new(from_ingressor.clone(), Arc::clone(&task_park))
+                    .counter(LinkCounter::output(&self.stats, port, self.bytes.as_ref()));
 
                 to_egressors.push(to_egressor);
                 egressors.push(Box::new(egressor));
@@ -109,17 +148,121 @@ impl<Packet: Send + Clone + 'static> LinkBuilder<Packet, Packet> for ForkLink<Pa
                 task_parks.push(task_park);
             }
 
-            let ingressor = ForkIngressor::new(self.in_stream.unwrap(), to_egressors, task_parks);
+            let ingressor = ForkIngressor::new(
+                self.in_stream.unwrap(),
+                to_egressors,
+                task_parks,
+                LinkCounter::input(&self.stats, 0),
+            );
 
             (vec![Box::new(ingressor)], egressors)
         }
     }
 }
 
+/// Like ForkLink, but rather than cloning each packet for every egressor, it wraps the packet in
+/// an `Arc` once, and hands each egressor a clone of the `Arc`. This makes copying large packets,
+/// such as whole EthernetFrames, cheap, for consumers that only need to read them, like
+/// monitoring taps.
+#[derive(Default)]
+pub struct ArcForkLink<Packet: Send + Sync> {
+    in_stream: Option<PacketStream<Packet>>,
+    queue_capacity: usize,
+    num_egressors: Option<usize>,
+}
+
+impl<Packet: Send + Sync> ArcForkLink<Packet> {
+    pub fn new() -> Self {
+        ArcForkLink {
+            in_stream: None,
+            queue_capacity: 10,
+            num_egressors: None,
+        }
+    }
+
+    /// Changes queue_capacity, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "ArcForkLink queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        ArcForkLink {
+            in_stream: self.in_stream,
+            queue_capacity,
+            num_egressors: self.num_egressors,
+        }
+    }
+
+    pub fn num_egressors(self, num_egressors: usize) -> Self {
+        assert!(
+            num_egressors > 0,
+            "ArcForkLink num_egressors: {}, must be > 0",
+            num_egressors
+        );
+
+        ArcForkLink {
+            in_stream: self.in_stream,
+            queue_capacity: self.queue_capacity,
+            num_egressors: Some(num_egressors),
+        }
+    }
+}
+
+impl<Packet: Send + Sync + 'static> LinkBuilder<Packet, Arc<Packet>> for ArcForkLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "ArcForkLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("ArcForkLink may only take 1 input stream")
+        }
+
+        ArcForkLink {
+            in_stream: Some(in_streams.remove(0)),
+            queue_capacity: self.queue_capacity,
+            num_egressors: self.num_egressors,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("ArcForkLink may only take 1 input stream")
+        }
+
+        ArcForkLink {
+            in_stream: Some(in_stream),
+            queue_capacity: self.queue_capacity,
+            num_egressors: self.num_egressors,
+        }
+    }
+
+    /// Builds a ForkLink over the input with each packet wrapped in an `Arc`.
+    fn build_link(self) -> Link<Arc<Packet>> {
+        match (self.in_stream, self.num_egressors) {
+            (None, _) => panic!("Cannot build link! Missing input stream"),
+            (_, None) => panic!("Cannot build link! Missing number of num_egressors"),
+            (Some(in_stream), Some(num_egressors)) => {
+                let shared_stream: PacketStream<Arc<Packet>> = Box::new(in_stream.map(Arc::new));
+                ForkLink::new()
+                    .ingressor(shared_stream)
+                    .queue_capacity(self.queue_capacity)
+                    .num_egressors(num_egressors)
+                    .build_link()
+            }
+        }
+    }
+}
+
 pub struct ForkIngressor<P> {
     input_stream: PacketStream<P>,
     to_egressors: Vec<Sender<Option<P>>>,
     task_parks: Vec<Arc<AtomicCell<TaskParkState>>>,
+    counter: LinkCounter<P>,
 }
 
 impl<P> ForkIngressor<P> {
@@ -127,11 +270,13 @@ impl<P> ForkIngressor<P> {
         input_stream: PacketStream<P>,
         to_egressors: Vec<Sender<Option<P>>>,
         task_parks: Vec<Arc<AtomicCell<TaskParkState>>>,
+        counter: LinkCounter<P>,
     ) -> Self {
         ForkIngressor {
             input_stream,
             to_egressors,
             task_parks,
+            counter,
         }
     }
 }
@@ -165,7 +310,9 @@ impl<P: Send + Clone> Future for ForkIngressor<P> {
                 Some(packet) => {
                     //TODO: should packet but put in an iterator? or only cloned? or last one reused?
                     assert!(self.to_egressors.len() == self.task_parks.len());
+                    self.counter.received(1);
                     for port in 0..self.to_egressors.len() {
+                        self.counter.enqueued(1);
                         if let Err(err) = self.to_egressors[port].try_send(Some(packet.clone())) {
                             panic!(
                                 "Error in to_egressors[{}] sender, have nowhere to put packet: {:?}",
@@ -215,7 +362,7 @@ mod tests {
 
     #[test]
     fn no_input() {
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let link = ForkLink::<i32>::new()
                 .ingressor(immediate_stream(vec![]))
@@ -231,7 +378,7 @@ mod tests {
     fn one_way() {
         let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let link = ForkLink::new()
                 .ingressor(immediate_stream(packets.clone()))
@@ -247,7 +394,7 @@ mod tests {
     fn two_way() {
         let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let link = ForkLink::new()
                 .ingressor(immediate_stream(packets.clone()))
@@ -264,7 +411,7 @@ mod tests {
     fn three_way() {
         let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let link = ForkLink::new()
                 .ingressor(immediate_stream(packets.clone()))
@@ -277,4 +424,53 @@ mod tests {
         assert_eq!(results[1], packets.clone());
         assert_eq!(results[2], packets);
     }
+
+    #[test]
+    #[should_panic]
+    fn arc_fork_panics_when_built_without_num_egressors() {
+        ArcForkLink::<i32>::new()
+            .ingressor(immediate_stream(vec![]))
+            .build_link();
+    }
+
+    #[test]
+    fn arc_fork_shares_packets() {
+        let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = ArcForkLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .num_egressors(3)
+                .build_link();
+
+            run_link(link).await
+        });
+        for result in results.iter() {
+            let output: Vec<i32> = result.iter().map(|packet| **packet).collect();
+            assert_eq!(output, packets);
+        }
+        for (packet, copy) in results[0].iter().zip(results[2].iter()) {
+            assert!(Arc::ptr_eq(packet, copy));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "metrics")]
+    fn metrics() {
+        let runtime = initialize_runtime();
+        let link = ForkLink::new()
+            .ingressor(immediate_stream(0..5))
+            .num_egressors(2)
+            .bytes(Box::new(|packet| *packet as u64));
+        let stats = link.stats();
+
+        let results = runtime.block_on(run_link(link.build_link()));
+        assert_eq!(results[1], vec![0, 1, 2, 3, 4]);
+        // Each copy counts as a packet out.
+        assert_eq!((stats.packets_in(), stats.packets_out()), (5, 10));
+        assert_eq!(stats.bytes_out(), 20);
+        assert_eq!(stats.queue_depth(), 0);
+        assert!(stats.queue_high_water() > 0);
+    }
 }
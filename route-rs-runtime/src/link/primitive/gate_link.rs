@@ -0,0 +1,328 @@
+use crate::link::primitive::{QueueEgressor, QueueIngressor};
+use crate::link::utils::task_park::*;
+use crate::link::{registry::LinkHandle, Link, LinkBuilder, PacketStream};
+use crate::processor::Processor;
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use futures::prelude::*;
+use futures::task::{Context, Poll, Waker};
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// What a GateLink does with packets while it is closed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WhileClosed {
+    /// Hold packets in the queue, and then push back on upstream links, until the gate opens.
+    Buffer,
+    /// Drop packets as they arrive. Packets queued before the gate closed are still forwarded.
+    Drop,
+}
+
+/// A queue whose forwarding can be switched on and off at runtime through a `GateControl` taken
+/// from the builder, for instance to take an interface administratively down. While closed,
+/// packets are either buffered or dropped, see `WhileClosed`. Gates start open.
+pub struct GateLink<Packet: Send + Clone> {
+    in_stream: Option<PacketStream<Packet>>,
+    while_closed: WhileClosed,
+    queue_capacity: usize,
+    control: GateControl,
+}
+
+impl<Packet: Send + Clone> Default for GateLink<Packet> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Packet: Send + Clone> GateLink<Packet> {
+    pub fn new() -> Self {
+        GateLink {
+            in_stream: None,
+            while_closed: WhileClosed::Buffer,
+            queue_capacity: 10,
+            control: GateControl::new(),
+        }
+    }
+
+    /// Changes what happens to packets while the gate is closed, default value is
+    /// `WhileClosed::Buffer`.
+    pub fn while_closed(self, while_closed: WhileClosed) -> Self {
+        GateLink {
+            in_stream: self.in_stream,
+            while_closed,
+            queue_capacity: self.queue_capacity,
+            control: self.control,
+        }
+    }
+
+    /// Changes queue_capacity, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "GateLink queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        GateLink {
+            in_stream: self.in_stream,
+            while_closed: self.while_closed,
+            queue_capacity,
+            control: self.control,
+        }
+    }
+
+    /// Returns a handle for opening and closing the gate. It may be used before or after the link
+    /// is built.
+    pub fn control(&self) -> GateControl {
+        self.control.clone()
+    }
+}
+
+impl<Packet: Send + Clone + 'static> LinkBuilder<Packet, Packet> for GateLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(in_streams.len(), 1, "GateLink may only take 1 input stream");
+
+        if self.in_stream.is_some() {
+            panic!("GateLink may only take 1 input stream")
+        }
+
+        GateLink {
+            in_stream: Some(in_streams.remove(0)),
+            while_closed: self.while_closed,
+            queue_capacity: self.queue_capacity,
+            control: self.control,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("GateLink may only take 1 input stream")
+        }
+
+        GateLink {
+            in_stream: Some(in_stream),
+            while_closed: self.while_closed,
+            queue_capacity: self.queue_capacity,
+            control: self.control,
+        }
+    }
+
+    fn handles(&self) -> Vec<LinkHandle> {
+        vec![Box::new(self.control())]
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match self.in_stream {
+            None => panic!("Cannot build link! Missing input stream"),
+            Some(in_stream) => {
+                let (to_egressor, from_ingressor) =
+                    crossbeam_channel::bounded::<Option<Packet>>(self.queue_capacity);
+                let task_park: Arc<AtomicCell<TaskParkState>> =
+                    Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+                let filter = GateFilter {
+                    control: self.control.clone(),
+                    drop_while_closed: self.while_closed == WhileClosed::Drop,
+                    phantom: PhantomData,
+                };
+                let ingressor =
+                    QueueIngressor::new(in_stream, to_egressor, filter, Arc::clone(&task_park));
+                let egressor = GateEgressor {
+                    egressor: QueueEgressor::new(from_ingressor, task_park),
+                    control: self.control,
+                    buffer_while_closed: self.while_closed == WhileClosed::Buffer,
+                };
+
+                (vec![Box::new(ingressor)], vec![Box::new(egressor)])
+            }
+        }
+    }
+}
+
+/// Handle to a GateLink, for opening and closing it at runtime.
+#[derive(Clone)]
+pub struct GateControl {
+    state: Arc<GateState>,
+}
+
+struct GateState {
+    open: AtomicBool,
+    /// The egressor's waker while it waits on a closed gate.
+    egressor_task: AtomicCell<Option<Waker>>,
+}
+
+impl GateControl {
+    fn new() -> Self {
+        GateControl {
+            state: Arc::new(GateState {
+                open: AtomicBool::new(true),
+                egressor_task: AtomicCell::new(None),
+            }),
+        }
+    }
+
+    pub fn open(&self) {
+        self.state.open.store(true, Ordering::SeqCst);
+        if let Some(task) = self.state.egressor_task.take() {
+            task.wake();
+        }
+    }
+
+    pub fn close(&self) {
+        self.state.open.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.state.open.load(Ordering::SeqCst)
+    }
+}
+
+/// Drops packets on their way into the queue while the gate is closed, in `WhileClosed::Drop`.
+struct GateFilter<Packet> {
+    control: GateControl,
+    drop_while_closed: bool,
+    phantom: PhantomData<Packet>,
+}
+
+impl<Packet: Send + Clone> Processor for GateFilter<Packet> {
+    type Input = Packet;
+    type Output = Packet;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        if self.drop_while_closed && !self.control.is_open() {
+            None
+        } else {
+            Some(packet)
+        }
+    }
+}
+
+pub struct GateEgressor<Packet> {
+    egressor: QueueEgressor<Packet>,
+    control: GateControl,
+    buffer_while_closed: bool,
+}
+
+impl<Packet> Unpin for GateEgressor<Packet> {}
+
+impl<Packet> Stream for GateEgressor<Packet> {
+    type Item = Packet;
+
+    /// Behaves as QueueEgressor, except that in `WhileClosed::Buffer` we stop taking packets off
+    /// the queue while the gate is closed, and leave our waker for `GateControl::open`. If the gate
+    /// opened before our waker was there to be woken, we wake ourselves.
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if self.buffer_while_closed && !self.control.is_open() {
+            self.control
+                .state
+                .egressor_task
+                .store(Some(cx.waker().clone()));
+            if self.control.is_open() {
+                cx.waker().wake_by_ref();
+            }
+            return Poll::Pending;
+        }
+        Pin::new(&mut self.egressor).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
+    use std::time::Duration;
+    use tokio::time::sleep;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_input_stream() {
+        GateLink::<i32>::new().build_link();
+    }
+
+    #[test]
+    fn open_gate_forwards() {
+        let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = GateLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], packets);
+    }
+
+    #[test]
+    fn closed_gate_drops() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = GateLink::new()
+                .ingressor(immediate_stream(0..100))
+                .while_closed(WhileClosed::Drop);
+            link.control().close();
+
+            run_link(link.build_link()).await
+        });
+        assert!(results[0].is_empty());
+    }
+
+    #[test]
+    fn closed_gate_buffers_until_opened() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = GateLink::new()
+                .ingressor(immediate_stream(0..5))
+                .queue_capacity(5);
+            let control = link.control();
+            control.close();
+
+            let (runnables, mut egressors) = link.build_link();
+            for runnable in runnables {
+                tokio::spawn(runnable);
+            }
+            let mut egressor = egressors.remove(0);
+
+            sleep(Duration::from_millis(20)).await;
+            assert!(futures::poll!(egressor.next()).is_pending());
+
+            control.open();
+            let mut output = vec![];
+            while let Some(packet) = egressor.next().await {
+                output.push(packet);
+            }
+            output
+        });
+        assert_eq!(results, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn drops_only_while_closed() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let packet_generator = PacketIntervalGenerator::new(Duration::from_millis(10), 0..30);
+
+            let link = GateLink::new()
+                .ingressor(Box::new(packet_generator))
+                .while_closed(WhileClosed::Drop);
+            let control = link.control();
+            let link = link.build_link();
+
+            tokio::spawn(async move {
+                sleep(Duration::from_millis(95)).await;
+                control.close();
+                sleep(Duration::from_millis(100)).await;
+                control.open();
+            });
+            run_link(link).await
+        });
+        let forwarded = &results[0];
+        assert!(forwarded.len() < 30, "forwarded: {:?}", forwarded);
+        assert_eq!(forwarded[0], 0);
+        assert_eq!(forwarded.last(), Some(&29));
+    }
+}
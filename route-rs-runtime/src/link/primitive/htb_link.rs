@@ -0,0 +1,640 @@
+use crate::link::primitive::{PacketCost, QueueEgressor};
+use crate::link::utils::task_park::*;
+use crate::link::utils::token_bucket::TokenBucket;
+use crate::link::{Link, LinkBuilder, PacketStream};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::Sender;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::{sleep_until, Sleep};
+
+/// Returns the leaf class a packet belongs to, as numbered by the order classes were added.
+pub type HtbClassifier<Packet> = Box<dyn Fn(&Packet) -> usize + Send + Sync + 'static>;
+
+/// One class of an HtbLink's tree. A class is guaranteed its `rate`, and may borrow unused rate
+/// from its ancestors up to its `ceil`. Rates are in tokens per second, so packets per second by
+/// default, or bytes per second with a `packet_cost` on the link.
+pub struct HtbClass {
+    parent: Option<usize>,
+    rate: u64,
+    ceil: u64,
+    burst: Option<u64>,
+    cburst: Option<u64>,
+}
+
+impl HtbClass {
+    pub fn new(rate: u64, ceil: u64) -> Self {
+        assert!(rate > 0, "HtbClass rate: {}, must be > 0", rate);
+        assert!(
+            ceil >= rate,
+            "HtbClass ceil: {}, must be >= rate: {}",
+            ceil,
+            rate
+        );
+
+        HtbClass {
+            parent: None,
+            rate,
+            ceil,
+            burst: None,
+            cburst: None,
+        }
+    }
+
+    /// Makes this class a child of an earlier class. Classes without a parent are roots.
+    pub fn parent(self, parent: usize) -> Self {
+        HtbClass {
+            parent: Some(parent),
+            rate: self.rate,
+            ceil: self.ceil,
+            burst: self.burst,
+            cburst: self.cburst,
+        }
+    }
+
+    /// Tokens the class may send at once at its guaranteed rate, default value is a tenth of a
+    /// second of `rate`.
+    pub fn burst(self, burst: u64) -> Self {
+        assert!(burst > 0, "HtbClass burst: {}, must be > 0", burst);
+
+        HtbClass {
+            parent: self.parent,
+            rate: self.rate,
+            ceil: self.ceil,
+            burst: Some(burst),
+            cburst: self.cburst,
+        }
+    }
+
+    /// Tokens the class may send at once at its ceiling, default value is a tenth of a second of
+    /// `ceil`.
+    pub fn cburst(self, cburst: u64) -> Self {
+        assert!(cburst > 0, "HtbClass cburst: {}, must be > 0", cburst);
+
+        HtbClass {
+            parent: self.parent,
+            rate: self.rate,
+            ceil: self.ceil,
+            burst: self.burst,
+            cburst: Some(cburst),
+        }
+    }
+}
+
+/// Shapes traffic with a Hierarchical Token Bucket. Classes form a tree, built by adding them with
+/// `class`, each numbered by the order it was added and naming an earlier class as its parent. The
+/// `classifier` assigns packets to leaf classes, each of which queues up to `queue_capacity`
+/// packets, dropping further arrivals. A leaf within its guaranteed rate always sends; a leaf over
+/// its rate but under its ceiling borrows from the nearest ancestor that has rate to spare, so long
+/// as every class on the way is also under its ceiling. Sending charges every class up to the root,
+/// even those already out of tokens, which go into debt. So rate a leaf leaves unused is left for
+/// its siblings to borrow. Leaves sending at their own rate go first, then those borrowing from the
+/// closest ancestors, taking turns among equals. Packets classified to a class that is not a leaf
+/// are dropped.
+pub struct HtbLink<Packet> {
+    in_stream: Option<PacketStream<Packet>>,
+    classes: Vec<HtbClass>,
+    classifier: Option<HtbClassifier<Packet>>,
+    packet_cost: Option<PacketCost<Packet>>,
+    queue_capacity: usize,
+}
+
+impl<Packet> Default for HtbLink<Packet> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Packet> HtbLink<Packet> {
+    pub fn new() -> Self {
+        HtbLink {
+            in_stream: None,
+            classes: Vec::new(),
+            classifier: None,
+            packet_cost: None,
+            queue_capacity: 10,
+        }
+    }
+
+    /// Adds a class to the tree, its number is the count of classes added before it.
+    pub fn class(self, class: HtbClass) -> Self {
+        if let Some(parent) = class.parent {
+            assert!(
+                parent < self.classes.len(),
+                "HtbLink class parent: {}, must be an earlier class",
+                parent
+            );
+        }
+        let mut classes = self.classes;
+        classes.push(class);
+
+        HtbLink {
+            in_stream: self.in_stream,
+            classes,
+            classifier: self.classifier,
+            packet_cost: self.packet_cost,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    pub fn classifier(self, classifier: HtbClassifier<Packet>) -> Self {
+        HtbLink {
+            in_stream: self.in_stream,
+            classes: self.classes,
+            classifier: Some(classifier),
+            packet_cost: self.packet_cost,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    pub fn packet_cost(self, packet_cost: PacketCost<Packet>) -> Self {
+        HtbLink {
+            in_stream: self.in_stream,
+            classes: self.classes,
+            classifier: self.classifier,
+            packet_cost: Some(packet_cost),
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Changes queue_capacity, of each leaf class and of the output, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "HtbLink queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        HtbLink {
+            in_stream: self.in_stream,
+            classes: self.classes,
+            classifier: self.classifier,
+            packet_cost: self.packet_cost,
+            queue_capacity,
+        }
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Packet, Packet> for HtbLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(in_streams.len(), 1, "HtbLink may only take 1 input stream");
+
+        if self.in_stream.is_some() {
+            panic!("HtbLink may only take 1 input stream")
+        }
+
+        HtbLink {
+            in_stream: Some(in_streams.remove(0)),
+            classes: self.classes,
+            classifier: self.classifier,
+            packet_cost: self.packet_cost,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("HtbLink may only take 1 input stream")
+        }
+
+        HtbLink {
+            in_stream: Some(in_stream),
+            classes: self.classes,
+            classifier: self.classifier,
+            packet_cost: self.packet_cost,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        if self.classes.is_empty() {
+            panic!("Cannot build link! Missing classes");
+        }
+
+        match (self.in_stream, self.classifier) {
+            (None, _) => panic!("Cannot build link! Missing input stream"),
+            (_, None) => panic!("Cannot build link! Missing classifier"),
+            (Some(in_stream), Some(classifier)) => {
+                let (to_egressor, from_ingressor) =
+                    crossbeam_channel::bounded::<Option<Packet>>(self.queue_capacity);
+                let task_park: Arc<AtomicCell<TaskParkState>> =
+                    Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+                let mut is_leaf = vec![true; self.classes.len()];
+                for class in self.classes.iter() {
+                    if let Some(parent) = class.parent {
+                        is_leaf[parent] = false;
+                    }
+                }
+                let leaves = (0..self.classes.len())
+                    .filter(|class| is_leaf[*class])
+                    .collect();
+
+                let classes = self
+                    .classes
+                    .into_iter()
+                    .map(|class| HtbClassState {
+                        parent: class.parent,
+                        rate: TokenBucket::new(
+                            class.rate,
+                            class.burst.unwrap_or_else(|| (class.rate / 10).max(1)),
+                        ),
+                        ceil: TokenBucket::new(
+                            class.ceil,
+                            class.cburst.unwrap_or_else(|| (class.ceil / 10).max(1)),
+                        ),
+                    })
+                    .collect();
+
+                let ingressor = HtbIngressor {
+                    input_stream: in_stream,
+                    input_finished: false,
+                    to_egressor,
+                    task_park: Arc::clone(&task_park),
+                    classes,
+                    queues: is_leaf.iter().map(|_| VecDeque::new()).collect(),
+                    is_leaf,
+                    leaves,
+                    next_leaf: 0,
+                    classifier,
+                    packet_cost: self.packet_cost,
+                    queue_capacity: self.queue_capacity,
+                    refill_timer: None,
+                };
+                let egressor = QueueEgressor::new(from_ingressor, task_park);
+
+                (vec![Box::new(ingressor)], vec![Box::new(egressor)])
+            }
+        }
+    }
+}
+
+struct HtbClassState {
+    parent: Option<usize>,
+    rate: TokenBucket,
+    ceil: TokenBucket,
+}
+
+/// Refills the bucket, then reports whether it could pay for a packet. Costs larger than the
+/// bucket can hold are treated as its burst, as TokenBucketLink does, so they do not stall forever.
+fn has_tokens(bucket: &mut TokenBucket, now: Instant, cost: u64) -> bool {
+    bucket.refill(now);
+    bucket.tokens() >= cost.min(bucket.burst())
+}
+
+struct HtbIngressor<Packet> {
+    input_stream: PacketStream<Packet>,
+    input_finished: bool,
+    to_egressor: Sender<Option<Packet>>,
+    task_park: Arc<AtomicCell<TaskParkState>>,
+    classes: Vec<HtbClassState>,
+    /// Packets waiting in each class, and their costs. Only leaves ever queue packets.
+    queues: Vec<VecDeque<(Packet, u64)>>,
+    is_leaf: Vec<bool>,
+    leaves: Vec<usize>,
+    /// Index into `leaves` of the leaf that goes first among equals.
+    next_leaf: usize,
+    classifier: HtbClassifier<Packet>,
+    packet_cost: Option<PacketCost<Packet>>,
+    queue_capacity: usize,
+    refill_timer: Option<Pin<Box<Sleep>>>,
+}
+
+impl<Packet> Unpin for HtbIngressor<Packet> {}
+
+impl<Packet> HtbIngressor<Packet> {
+    fn enqueue(&mut self, packet: Packet) {
+        let class = (self.classifier)(&packet);
+        if class >= self.classes.len() || !self.is_leaf[class] {
+            return;
+        }
+        if self.queues[class].len() >= self.queue_capacity {
+            return;
+        }
+        let cost = match &self.packet_cost {
+            Some(packet_cost) => packet_cost(&packet),
+            None => 1,
+        };
+        self.queues[class].push_back((packet, cost));
+    }
+
+    /// How many levels up from `leaf` the class it may send a packet on the rate of is, zero if
+    /// it is within its own rate, or None if it may not send yet.
+    fn lender_distance(&mut self, leaf: usize, now: Instant, cost: u64) -> Option<usize> {
+        if !has_tokens(&mut self.classes[leaf].ceil, now, cost) {
+            return None;
+        }
+        let mut class = leaf;
+        let mut distance = 0;
+        loop {
+            if has_tokens(&mut self.classes[class].rate, now, cost) {
+                return Some(distance);
+            }
+            if class != leaf && !has_tokens(&mut self.classes[class].ceil, now, cost) {
+                return None;
+            }
+            class = self.classes[class].parent?;
+            distance += 1;
+        }
+    }
+
+    /// How long until `leaf` may send a packet, given no other class sends first.
+    fn time_until_sendable(&self, leaf: usize, now: Instant, cost: u64) -> Duration {
+        let leaf_ceil = self.classes[leaf].ceil.time_until(now, cost);
+        let mut soonest = leaf_ceil.max(self.classes[leaf].rate.time_until(now, cost));
+        let mut path_ceil = leaf_ceil;
+        let mut class = leaf;
+        while let Some(parent) = self.classes[class].parent {
+            let lend = path_ceil.max(self.classes[parent].rate.time_until(now, cost));
+            soonest = soonest.min(lend);
+            path_ceil = path_ceil.max(self.classes[parent].ceil.time_until(now, cost));
+            class = parent;
+        }
+        soonest
+    }
+
+    /// Picks the leaf to send from: the first, starting from `next_leaf`, of those with the
+    /// closest lender.
+    fn pick_leaf(&mut self, now: Instant) -> Option<usize> {
+        let mut picked: Option<(usize, usize)> = None;
+        for offset in 0..self.leaves.len() {
+            let index = (self.next_leaf + offset) % self.leaves.len();
+            let leaf = self.leaves[index];
+            let cost = match self.queues[leaf].front() {
+                Some((_, cost)) => *cost,
+                None => continue,
+            };
+            if let Some(distance) = self.lender_distance(leaf, now, cost) {
+                let closer = match picked {
+                    Some((_, closest)) => distance < closest,
+                    None => true,
+                };
+                if closer {
+                    picked = Some((index, distance));
+                }
+                if distance == 0 {
+                    break;
+                }
+            }
+        }
+        picked.map(|(index, _)| {
+            self.next_leaf = (index + 1) % self.leaves.len();
+            self.leaves[index]
+        })
+    }
+
+    /// Charges a packet sent from `leaf` to every class from it up to the root.
+    fn charge(&mut self, leaf: usize, now: Instant, cost: u64) {
+        let mut class = Some(leaf);
+        while let Some(current) = class {
+            self.classes[current].rate.drain(now, cost);
+            self.classes[current].ceil.drain(now, cost);
+            class = self.classes[current].parent;
+        }
+    }
+}
+
+impl<Packet> Future for HtbIngressor<Packet> {
+    type Output = ();
+
+    /// Takes in everything the input has ready, sorting it into the leaf queues, then sends the
+    /// packet at the head of the leaf picked by `pick_leaf`. When no leaf may send, we sleep on a
+    /// timer until the soonest any of them could. We finish once the input has finished and every
+    /// queue has drained.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let ingressor = Pin::into_inner(self);
+        loop {
+            while !ingressor.input_finished {
+                match Pin::new(&mut ingressor.input_stream).poll_next(cx) {
+                    Poll::Ready(Some(packet)) => ingressor.enqueue(packet),
+                    Poll::Ready(None) => ingressor.input_finished = true,
+                    Poll::Pending => break,
+                }
+            }
+
+            let queues_empty = ingressor.queues.iter().all(|queue| queue.is_empty());
+            if queues_empty && !ingressor.input_finished {
+                return Poll::Pending;
+            }
+
+            if ingressor.to_egressor.is_full() {
+                park_and_wake(&ingressor.task_park, cx.waker().clone());
+                return Poll::Pending;
+            }
+
+            if queues_empty {
+                ingressor
+                    .to_egressor
+                    .try_send(None)
+                    .expect("HtbIngressor::Poll::Ready(None) try_send to_egressor shouldn't fail");
+                die_and_wake(&ingressor.task_park);
+                return Poll::Ready(());
+            }
+
+            let now = Instant::now();
+            match ingressor.pick_leaf(now) {
+                Some(leaf) => {
+                    let (packet, cost) = ingressor.queues[leaf].pop_front().unwrap();
+                    ingressor.charge(leaf, now, cost);
+                    ingressor.to_egressor.try_send(Some(packet)).expect(
+                        "HtbIngressor::Poll::Ready(Some(val)) try_send to_egressor shouldn't fail",
+                    );
+                    unpark_and_wake(&ingressor.task_park);
+                }
+                None => {
+                    let wait = ingressor
+                        .leaves
+                        .iter()
+                        .filter_map(|leaf| {
+                            let (_, cost) = ingressor.queues[*leaf].front()?;
+                            Some(ingressor.time_until_sendable(*leaf, now, *cost))
+                        })
+                        .min()
+                        .unwrap_or_default();
+                    let ready_at = now + wait;
+                    let timer = ingressor
+                        .refill_timer
+                        .get_or_insert_with(|| Box::pin(sleep_until(ready_at.into())));
+                    timer.as_mut().reset(ready_at.into());
+                    if timer.as_mut().poll(cx).is_pending() {
+                        return Poll::Pending;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    /// Packets are (leaf, sequence number) pairs, the classifier sends them to their leaf.
+    fn by_leaf() -> HtbClassifier<(usize, usize)> {
+        Box::new(|packet| packet.0)
+    }
+
+    /// A class with bursts of a single packet, so timings follow its rates closely.
+    fn class(rate: u64, ceil: u64) -> HtbClass {
+        HtbClass::new(rate, ceil).burst(1).cburst(1)
+    }
+
+    /// Runs the link, returning its output and how long it took to drain.
+    async fn timed_run(link: Link<(usize, usize)>) -> (Vec<(usize, usize)>, Duration) {
+        let start = Instant::now();
+        let mut results = run_link(link).await;
+        (results.remove(0), start.elapsed())
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_classifier() {
+        HtbLink::<i32>::new()
+            .ingressor(immediate_stream(vec![]))
+            .class(HtbClass::new(10, 10))
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_classes() {
+        HtbLink::<i32>::new()
+            .ingressor(immediate_stream(vec![]))
+            .classifier(Box::new(|_| 0))
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_with_later_parent() {
+        HtbLink::<i32>::new().class(HtbClass::new(10, 10).parent(0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_with_ceil_below_rate() {
+        HtbClass::new(10, 5);
+    }
+
+    #[test]
+    fn passes_traffic_within_rate() {
+        let packets: Vec<(usize, usize)> = (0..20).map(|seq| (seq % 2 + 1, seq)).collect();
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = HtbLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .class(HtbClass::new(1000, 1000).burst(20).cburst(20))
+                .class(HtbClass::new(500, 1000).parent(0).burst(10).cburst(10))
+                .class(HtbClass::new(500, 1000).parent(0).burst(10).cburst(10))
+                .classifier(by_leaf())
+                .queue_capacity(20)
+                .build_link();
+
+            run_link(link).await
+        });
+        let mut sorted = results[0].clone();
+        sorted.sort_by_key(|packet| packet.1);
+        assert_eq!(sorted, packets);
+    }
+
+    #[test]
+    fn drops_packets_for_inner_classes() {
+        let packets = vec![(0, 0), (1, 1), (0, 2), (1, 3)];
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = HtbLink::new()
+                .ingressor(immediate_stream(packets))
+                .class(HtbClass::new(100, 100))
+                .class(HtbClass::new(100, 100).parent(0))
+                .classifier(by_leaf())
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![(1, 1), (1, 3)]);
+    }
+
+    #[test]
+    fn borrows_unused_rate_from_sibling() {
+        // Leaf 1 alone would take 19 / 50 = 380ms past its burst, borrowing its idle sibling's
+        // rate takes 19 / 100 = 190ms.
+        let packets: Vec<(usize, usize)> = (0..20).map(|seq| (1, seq)).collect();
+
+        let runtime = initialize_runtime();
+        let (output, elapsed) = runtime.block_on(async {
+            let link = HtbLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .class(class(100, 100))
+                .class(class(50, 100).parent(0))
+                .class(class(50, 100).parent(0))
+                .classifier(by_leaf())
+                .queue_capacity(20)
+                .build_link();
+
+            timed_run(link).await
+        });
+        assert_eq!(output, packets);
+        assert!(elapsed >= Duration::from_millis(150), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(330), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn ceil_limits_borrowing() {
+        let packets: Vec<(usize, usize)> = (0..20).map(|seq| (1, seq)).collect();
+
+        let runtime = initialize_runtime();
+        let (output, elapsed) = runtime.block_on(async {
+            let link = HtbLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .class(class(100, 100))
+                .class(class(50, 50).parent(0))
+                .class(class(50, 100).parent(0))
+                .classifier(by_leaf())
+                .queue_capacity(20)
+                .build_link();
+
+            timed_run(link).await
+        });
+        assert_eq!(output, packets);
+        assert!(elapsed >= Duration::from_millis(330), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn shares_by_guaranteed_rate_under_contention() {
+        let packets: Vec<(usize, usize)> =
+            (0..40).flat_map(|seq| vec![(1, seq), (2, seq)]).collect();
+
+        let runtime = initialize_runtime();
+        let first_half = runtime.block_on(async {
+            let (mut runnables, mut egressors) = HtbLink::new()
+                .ingressor(immediate_stream(packets))
+                .class(class(200, 200))
+                .class(class(150, 200).parent(0))
+                .class(class(50, 200).parent(0))
+                .classifier(by_leaf())
+                .queue_capacity(40)
+                .build_link();
+            tokio::spawn(runnables.remove(0));
+
+            let mut egressor = egressors.remove(0);
+            let mut output = vec![];
+            for _ in 0..40 {
+                output.push(egressor.next().await.unwrap());
+            }
+            output
+        });
+        // While both leaves are backed up, the parent's rate is used up by their guarantees, so
+        // they share it 3:1.
+        let from_first = first_half.iter().filter(|packet| packet.0 == 1).count();
+        assert!((26..=34).contains(&from_first), "{}", from_first);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::link::{Link, LinkBuilder, PacketStream};
+use crate::utils::shutdown::ShutdownHandle;
 use crossbeam::crossbeam_channel;
 use futures::prelude::*;
 use futures::task::{Context, Poll};
@@ -7,18 +8,30 @@ use std::pin::Pin;
 #[derive(Default)]
 pub struct InputChannelLink<Packet> {
     channel_receiver: Option<crossbeam::Receiver<Packet>>,
+    shutdown: Option<ShutdownHandle>,
 }
 
 impl<Packet> InputChannelLink<Packet> {
     pub fn new() -> Self {
         InputChannelLink {
             channel_receiver: None,
+            shutdown: None,
         }
     }
 
     pub fn channel(self, channel_receiver: crossbeam::Receiver<Packet>) -> Self {
         InputChannelLink {
             channel_receiver: Some(channel_receiver),
+            shutdown: self.shutdown,
+        }
+    }
+
+    /// Stops taking packets from the channel at shutdown, ending the link's output, rather than
+    /// only once every sender is dropped.
+    pub fn shutdown(self, shutdown: ShutdownHandle) -> Self {
+        InputChannelLink {
+            channel_receiver: self.channel_receiver,
+            shutdown: Some(shutdown),
         }
     }
 }
@@ -36,12 +49,13 @@ impl<Packet: Send + 'static> LinkBuilder<(), Packet> for InputChannelLink<Packet
         if self.channel_receiver.is_none() {
             panic!("Cannot build link! Missing channel");
         } else {
-            (
-                vec![],
-                vec![Box::new(StreamFromChannel {
-                    channel_receiver: self.channel_receiver.unwrap(),
-                })],
-            )
+            let stream: PacketStream<Packet> = Box::new(StreamFromChannel {
+                channel_receiver: self.channel_receiver.unwrap(),
+            });
+            match self.shutdown {
+                Some(shutdown) => (vec![], vec![shutdown.guard(stream)]),
+                None => (vec![], vec![stream]),
+            }
         }
     }
 }
@@ -88,7 +102,7 @@ mod tests {
     fn immediate_packets() {
         let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let (send, recv) = crossbeam_channel::unbounded();
 
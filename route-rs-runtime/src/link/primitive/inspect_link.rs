@@ -0,0 +1,141 @@
+use crate::link::{Link, LinkBuilder, PacketStream};
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+
+/// Looks at a packet without taking it, for side effects such as logging or counting.
+pub type PacketInspector<Packet> = Box<dyn Fn(&Packet) + Send + Sync + 'static>;
+
+/// `InspectLink` calls a user provided `inspector` on every packet that passes through, then
+/// forwards the packet unchanged. It is a lighter weight tap than writing a Processor, for
+/// logging, counting, or debugging. Like ProcessLink, it only does work when it is polled.
+#[derive(Default)]
+pub struct InspectLink<Packet> {
+    in_stream: Option<PacketStream<Packet>>,
+    inspector: Option<PacketInspector<Packet>>,
+}
+
+impl<Packet> InspectLink<Packet> {
+    pub fn new() -> Self {
+        InspectLink {
+            in_stream: None,
+            inspector: None,
+        }
+    }
+
+    pub fn inspector(self, inspector: PacketInspector<Packet>) -> Self {
+        InspectLink {
+            in_stream: self.in_stream,
+            inspector: Some(inspector),
+        }
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Packet, Packet> for InspectLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "InspectLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("InspectLink may only take 1 input stream")
+        }
+
+        InspectLink {
+            in_stream: Some(in_streams.remove(0)),
+            inspector: self.inspector,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("InspectLink may only take 1 input stream")
+        }
+
+        InspectLink {
+            in_stream: Some(in_stream),
+            inspector: self.inspector,
+        }
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match (self.in_stream, self.inspector) {
+            (None, _) => panic!("Cannot build link! Missing input streams"),
+            (_, None) => panic!("Cannot build link! Missing inspector"),
+            (Some(in_stream), Some(inspector)) => {
+                let inspector = Inspector {
+                    in_stream,
+                    inspector,
+                };
+                (vec![], vec![Box::new(inspector)])
+            }
+        }
+    }
+}
+
+/// The single egressor of InspectLink
+struct Inspector<Packet> {
+    in_stream: PacketStream<Packet>,
+    inspector: PacketInspector<Packet>,
+}
+
+impl<Packet> Unpin for Inspector<Packet> {}
+
+impl<Packet> Stream for Inspector<Packet> {
+    type Item = Packet;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let packet = ready!(Pin::new(&mut self.in_stream).poll_next(cx));
+        if let Some(packet) = &packet {
+            (self.inspector)(packet);
+        }
+        Poll::Ready(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_inspector() {
+        InspectLink::<i32>::new()
+            .ingressor(immediate_stream(vec![]))
+            .build_link();
+    }
+
+    #[test]
+    fn forwards_unchanged_and_inspects_each_packet() {
+        let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
+        let count = Arc::new(AtomicUsize::new(0));
+        let sum = Arc::new(AtomicUsize::new(0));
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on({
+            let count = Arc::clone(&count);
+            let sum = Arc::clone(&sum);
+            let packets = packets.clone();
+            async move {
+                let link = InspectLink::new()
+                    .ingressor(immediate_stream(packets))
+                    .inspector(Box::new(move |packet: &usize| {
+                        count.fetch_add(1, Ordering::Relaxed);
+                        sum.fetch_add(*packet, Ordering::Relaxed);
+                    }))
+                    .build_link();
+
+                run_link(link).await
+            }
+        });
+        assert_eq!(results[0], packets);
+        assert_eq!(count.load(Ordering::Relaxed), packets.len());
+        assert_eq!(sum.load(Ordering::Relaxed), packets.iter().sum());
+    }
+}
@@ -1,5 +1,8 @@
+use crate::link::primitive::{PacketCost, QueueIngressor};
+use crate::link::utils::stats::{LinkCounter, LinkStats};
 use crate::link::utils::task_park::*;
-use crate::link::{Link, LinkBuilder, PacketStream, TokioRunnable};
+use crate::link::{registry::LinkHandle, Link, LinkBuilder, PacketStream, TokioRunnable};
+use crate::processor::{Timestamp, Timestamped};
 use crossbeam::atomic::AtomicCell;
 use crossbeam::crossbeam_channel;
 use crossbeam::crossbeam_channel::{Receiver, Sender};
@@ -8,10 +11,25 @@ use futures::task::{Context, Poll};
 use std::pin::Pin;
 use std::sync::Arc;
 
+/// How a JoinLink picks which ingressor to take the next packet from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JoinFairness {
+    /// Take from each ingressor with packets waiting in turn.
+    RoundRobin,
+    /// Take from whichever ingressor has the most packets waiting, in turn when they are tied.
+    /// This keeps queues even, so a backed up input is served before a lightly loaded one.
+    LongestQueueFirst,
+    /// Take packets in the order they arrived at the link, across all ingressors.
+    ArrivalOrder,
+}
+
 #[derive(Default)]
 pub struct JoinLink<Packet: Send + Clone> {
     in_streams: Option<Vec<PacketStream<Packet>>>,
     queue_capacity: usize,
+    fairness: Option<JoinFairness>,
+    bytes: Option<Arc<PacketCost<Packet>>>,
+    stats: Arc<LinkStats>,
 }
 
 impl<Packet: Send + Clone> JoinLink<Packet> {
@@ -19,6 +37,9 @@ impl<Packet: Send + Clone> JoinLink<Packet> {
         JoinLink {
             in_streams: None,
             queue_capacity: 10,
+            fairness: None,
+            bytes: None,
+            stats: Arc::new(LinkStats::default()),
         }
     }
 
@@ -32,8 +53,40 @@ impl<Packet: Send + Clone> JoinLink<Packet> {
         JoinLink {
             in_streams: self.in_streams,
             queue_capacity,
+            fairness: self.fairness,
+            bytes: self.bytes,
+            stats: self.stats,
+        }
+    }
+
+    /// Changes fairness, default value is `JoinFairness::RoundRobin`.
+    pub fn fairness(self, fairness: JoinFairness) -> Self {
+        JoinLink {
+            in_streams: self.in_streams,
+            queue_capacity: self.queue_capacity,
+            fairness: Some(fairness),
+            bytes: self.bytes,
+            stats: self.stats,
         }
     }
+
+    /// Sets the closure that gives the length of a packet in bytes, to count the bytes given out
+    /// in the link's stats. Without it, only packets are counted.
+    pub fn bytes(self, bytes: PacketCost<Packet>) -> Self {
+        JoinLink {
+            in_streams: self.in_streams,
+            queue_capacity: self.queue_capacity,
+            fairness: self.fairness,
+            bytes: Some(Arc::new(bytes)),
+            stats: self.stats,
+        }
+    }
+
+    /// Returns the link's counters, kept with the `metrics` feature. They may be read before or
+    /// after the link is built.
+    pub fn stats(&self) -> Arc<LinkStats> {
+        Arc::clone(&self.stats)
+    }
 }
 
 impl<Packet: Send + Clone + 'static> LinkBuilder<Packet, Packet> for JoinLink<Packet> {
@@ -53,6 +106,9 @@ impl<Packet: Send + Clone + 'static> LinkBuilder<Packet, Packet> for JoinLink<Pa
         JoinLink {
             in_streams: Some(in_streams),
             queue_capacity: self.queue_capacity,
+            fairness: self.fairness,
+            bytes: self.bytes,
+            stats: self.stats,
         }
     }
 
@@ -64,6 +120,9 @@ impl<Packet: Send + Clone + 'static> LinkBuilder<Packet, Packet> for JoinLink<Pa
                 JoinLink {
                     in_streams,
                     queue_capacity: self.queue_capacity,
+                    fairness: self.fairness,
+                    bytes: self.bytes,
+                    stats: self.stats,
                 }
             }
             Some(mut in_streams) => {
@@ -71,50 +130,109 @@ impl<Packet: Send + Clone + 'static> LinkBuilder<Packet, Packet> for JoinLink<Pa
                 JoinLink {
                     in_streams: Some(in_streams),
                     queue_capacity: self.queue_capacity,
+                    fairness: self.fairness,
+                    bytes: self.bytes,
+                    stats: self.stats,
                 }
             }
         }
     }
 
+    fn handles(&self) -> Vec<LinkHandle> {
+        vec![Box::new(self.stats())]
+    }
+
     fn build_link(self) -> Link<Packet> {
         if self.in_streams.is_none() {
             panic!("Cannot build link! Missing input streams");
-        } else {
-            let input_streams = self.in_streams.unwrap();
-            let number_ingressors = input_streams.len();
-            let mut ingressors: Vec<TokioRunnable> = Vec::new();
-            let mut from_ingressors: Vec<Receiver<Option<Packet>>> = Vec::new();
-            let mut task_parks: Vec<Arc<AtomicCell<TaskParkState>>> = Vec::new();
-
-            for input_stream in input_streams {
-                let (to_egressor, from_ingressor) =
-                    crossbeam_channel::bounded::<Option<Packet>>(self.queue_capacity);
-                let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
-
-                let ingressor =
-                    JoinIngressor::new(input_stream, to_egressor, Arc::clone(&task_park));
-                ingressors.push(Box::new(ingressor));
-                from_ingressors.push(from_ingressor);
-                task_parks.push(task_park);
-            }
-
-            let egressor = JoinEgressor::new(from_ingressors, task_parks, number_ingressors);
+        }
+        let input_streams = self.in_streams.unwrap();
+        let fairness = self.fairness.unwrap_or(JoinFairness::RoundRobin);
+        if fairness == JoinFairness::ArrivalOrder {
+            return build_arrival_order_link(
+                input_streams,
+                self.queue_capacity,
+                &self.stats,
+                self.bytes.as_ref(),
+            );
+        }
 
-            (ingressors, vec![Box::new(egressor)])
+        let number_ingressors = input_streams.len();
+        let mut ingressors: Vec<TokioRunnable> = Vec::new();
+        let mut from_ingressors: Vec<Receiver<Option<Packet>>> = Vec::new();
+        let mut task_parks: Vec<Arc<AtomicCell<TaskParkState>>> = Vec::new();
+
+        for (port, input_stream) in input_streams.into_iter().enumerate() {
+            let (to_egressor, from_ingressor) =
+                crossbeam_channel::bounded::<Option<Packet>>(self.queue_capacity);
+            let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+            let ingressor = JoinIngressor::new(input_stream, to_egressor, Arc::clone(&task_park))
+                .counter(LinkCounter::input(&self.stats, port));
+            ingressors.push(Box::new(ingressor));
+            from_ingressors.push(from_ingressor);
+            task_parks.push(task_park);
         }
+
+        let mut egressor = JoinEgressor::new(from_ingressors, task_parks, number_ingressors);
+        egressor.longest_queue_first = fairness == JoinFairness::LongestQueueFirst;
+        egressor.counter = LinkCounter::output(&self.stats, 0, self.bytes.as_ref());
+
+        (ingressors, vec![Box::new(egressor)])
     }
 }
 
+/// Packets are timestamped by their ingressor as they arrive, so the egressor can release them in
+/// that order.
+fn build_arrival_order_link<Packet: Send + Clone + 'static>(
+    input_streams: Vec<PacketStream<Packet>>,
+    queue_capacity: usize,
+    stats: &Arc<LinkStats>,
+    bytes: Option<&Arc<PacketCost<Packet>>>,
+) -> Link<Packet> {
+    let mut ingressors: Vec<TokioRunnable> = Vec::new();
+    let mut from_ingressors: Vec<Receiver<Option<Timestamped<Packet>>>> = Vec::new();
+    let mut task_parks: Vec<Arc<AtomicCell<TaskParkState>>> = Vec::new();
+
+    for (port, input_stream) in input_streams.into_iter().enumerate() {
+        let (to_egressor, from_ingressor) =
+            crossbeam_channel::bounded::<Option<Timestamped<Packet>>>(queue_capacity);
+        let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+        let ingressor = QueueIngressor::new(
+            input_stream,
+            to_egressor,
+            Timestamp::new(),
+            Arc::clone(&task_park),
+        )
+        .counter(LinkCounter::input(stats, port));
+        ingressors.push(Box::new(ingressor));
+        from_ingressors.push(from_ingressor);
+        task_parks.push(task_park);
+    }
+
+    let egressor = ArrivalOrderJoinEgressor {
+        heads: from_ingressors.iter().map(|_| None).collect(),
+        alive: from_ingressors.iter().map(|_| true).collect(),
+        from_ingressors,
+        task_parks,
+        counter: LinkCounter::output(stats, 0, bytes),
+    };
+
+    (ingressors, vec![Box::new(egressor)])
+}
+
 pub struct JoinIngressor<Packet: Sized> {
     input_stream: PacketStream<Packet>,
     to_egressor: Sender<Option<Packet>>,
     task_park: Arc<AtomicCell<TaskParkState>>,
+    counter: LinkCounter<Packet>,
 }
 
 impl<Packet: Sized> Unpin for JoinIngressor<Packet> {}
 
 impl<Packet: Sized> JoinIngressor<Packet> {
-    fn new(
+    pub fn new(
         input_stream: PacketStream<Packet>,
         to_egressor: Sender<Option<Packet>>,
         task_park: Arc<AtomicCell<TaskParkState>>,
@@ -123,8 +241,15 @@ impl<Packet: Sized> JoinIngressor<Packet> {
             input_stream,
             to_egressor,
             task_park,
+            counter: LinkCounter::default(),
         }
     }
+
+    /// Counts with `counter` the packets we take in and enqueue.
+    pub(crate) fn counter(mut self, counter: LinkCounter<Packet>) -> Self {
+        self.counter = counter;
+        self
+    }
 }
 
 impl<Packet: Sized> Future for JoinIngressor<Packet> {
@@ -139,14 +264,14 @@ impl<Packet: Sized> Future for JoinIngressor<Packet> {
     /// #1 The to_egressor queue is full, we wake the egressor that we need
     /// awaking when there is work to do, and go to sleep.
     ///
-    /// #2 The input_stream returns a NotReady, we sleep, with the assumption
-    /// that whomever produced the NotReady will awaken the task in the Future.
+    /// #2 The input_stream returns a Pending, we sleep, with the assumption
+    /// that whomever produced the Pending will awaken the task in the Future.
     ///
     /// #3 We get a Ready(None), in which case we push a None onto the to_egressor
     /// queue and then return Ready(()), which means we enter tear-down, since there
     /// is no futher work to complete.
     /// ###
-    /// By Sleep, we mean we return a NotReady to the runtime which will sleep the task.
+    /// By Sleep, we mean we return a Pending to the runtime which will sleep the task.
     fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
         let ingressor = Pin::into_inner(self);
         loop {
@@ -166,6 +291,8 @@ impl<Packet: Sized> Future for JoinIngressor<Packet> {
                     return Poll::Ready(());
                 }
                 Some(packet) => {
+                    ingressor.counter.received(1);
+                    ingressor.counter.enqueued(1);
                     ingressor.to_egressor.try_send(Some(packet)).expect(
                         "JoinIngressor::Poll:Ready(Some(Val)) try_send to_egressor shouldn't fail",
                     );
@@ -182,6 +309,8 @@ pub struct JoinEgressor<Packet: Sized> {
     task_parks: Vec<Arc<AtomicCell<TaskParkState>>>,
     ingressors_alive: usize,
     next_pull_ingressor: usize,
+    longest_queue_first: bool,
+    counter: LinkCounter<Packet>,
 }
 
 impl<Packet: Sized> JoinEgressor<Packet> {
@@ -196,8 +325,25 @@ impl<Packet: Sized> JoinEgressor<Packet> {
             task_parks,
             ingressors_alive,
             next_pull_ingressor,
+            longest_queue_first: false,
+            counter: LinkCounter::default(),
         }
     }
+
+    /// The ingressor with the most packets waiting, or if they are tied, the first of them from
+    /// `next_pull_ingressor` on.
+    fn longest_queue(&self) -> usize {
+        let len = self.from_ingressors.len();
+        (0..len)
+            .map(|offset| (self.next_pull_ingressor + offset) % len)
+            .fold(self.next_pull_ingressor % len, |longest, port| {
+                if self.from_ingressors[port].len() > self.from_ingressors[longest].len() {
+                    port
+                } else {
+                    longest
+                }
+            })
+    }
 }
 
 impl<Packet: Sized> Unpin for JoinEgressor<Packet> {}
@@ -210,20 +356,28 @@ impl<Packet: Sized> Stream for JoinEgressor<Packet> {
     type Item = Packet;
 
     /// Iterate over all the channels, pull the first packet that is available.
-    /// This starts at the next index after the last successful recv
+    /// This starts at the next index after the last successful recv, or with longest queue first,
+    /// at the ingressor with the most packets waiting.
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
         //rotate_slice exists in 1.22 nightly experimental
         let egressor = Pin::into_inner(self);
+        let first_pull_ingressor = if egressor.longest_queue_first {
+            egressor.longest_queue()
+        } else {
+            egressor.next_pull_ingressor
+        };
         let rotated_iter = egressor
             .from_ingressors
             .iter()
             .enumerate()
             .cycle()
-            .skip(egressor.next_pull_ingressor)
+            .skip(first_pull_ingressor)
             .take(egressor.from_ingressors.len());
         for (port, from_ingressor) in rotated_iter {
             match from_ingressor.try_recv() {
                 Ok(Some(packet)) => {
+                    egressor.counter.dequeued(1);
+                    egressor.counter.sent(&packet);
                     unpark_and_wake(&egressor.task_parks[port]);
                     egressor.next_pull_ingressor = port + 1;
                     return Poll::Ready(Some(packet));
@@ -262,6 +416,74 @@ impl<Packet: Sized> Stream for JoinEgressor<Packet> {
     }
 }
 
+pub struct ArrivalOrderJoinEgressor<Packet> {
+    from_ingressors: Vec<Receiver<Option<Timestamped<Packet>>>>,
+    task_parks: Vec<Arc<AtomicCell<TaskParkState>>>,
+    /// The next packet from each ingressor, taken off its channel so we can see when it arrived.
+    heads: Vec<Option<Timestamped<Packet>>>,
+    alive: Vec<bool>,
+    counter: LinkCounter<Packet>,
+}
+
+impl<Packet> Unpin for ArrivalOrderJoinEgressor<Packet> {}
+
+impl<Packet> Stream for ArrivalOrderJoinEgressor<Packet> {
+    type Item = Packet;
+
+    /// Takes the next packet from each ingressor we are not already holding one for, then returns
+    /// whichever of the packets we hold arrived first. If we hold none, we finish once every
+    /// ingressor has, and otherwise park as JoinEgressor does.
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let egressor = Pin::into_inner(self);
+        for (port, from_ingressor) in egressor.from_ingressors.iter().enumerate() {
+            if egressor.heads[port].is_some() || !egressor.alive[port] {
+                continue;
+            }
+            match from_ingressor.try_recv() {
+                Ok(Some(packet)) => {
+                    unpark_and_wake(&egressor.task_parks[port]);
+                    egressor.heads[port] = Some(packet);
+                }
+                Ok(None) => egressor.alive[port] = false,
+                Err(_) => {}
+            }
+        }
+
+        let earliest = egressor
+            .heads
+            .iter()
+            .enumerate()
+            .filter_map(|(port, head)| head.as_ref().map(|packet| (packet.received, port)))
+            .min();
+        if let Some((_, port)) = earliest {
+            if let Some(packet) = egressor.heads[port].take() {
+                egressor.counter.dequeued(1);
+                egressor.counter.sent(&packet.packet);
+                return Poll::Ready(Some(packet.packet));
+            }
+        }
+
+        if egressor.alive.iter().all(|alive| !alive) {
+            for task_park in egressor.task_parks.iter() {
+                die_and_wake(task_park);
+            }
+            return Poll::Ready(None);
+        }
+
+        let mut parked_egressor_task = false;
+        let egressor_task = Arc::new(AtomicCell::new(Some(cx.waker().clone())));
+        for task_park in egressor.task_parks.iter() {
+            if indirect_park_and_wake(task_park, Arc::clone(&egressor_task)) {
+                parked_egressor_task = true;
+            }
+        }
+        if !parked_egressor_task {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+}
+
 #[cfg(test)]
 #[allow(dead_code)]
 mod tests {
@@ -307,7 +529,7 @@ mod tests {
     fn join_link() {
         let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9, 11];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let mut input_streams: Vec<PacketStream<usize>> = Vec::new();
             input_streams.push(immediate_stream(packets.clone()));
@@ -324,7 +546,7 @@ mod tests {
     fn multiple_ingressor_calls_works() {
         let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9, 11];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let link = JoinLink::new()
                 .ingressor(immediate_stream(packets.clone()))
@@ -342,7 +564,7 @@ mod tests {
         let stream_len = rng.gen_range(2000, 3000);
         let num_streams = rng.gen_range(5, 10);
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async move {
             let mut input_streams: Vec<PacketStream<usize>> = Vec::new();
             for _ in 0..num_streams {
@@ -358,7 +580,7 @@ mod tests {
 
     #[test]
     fn wait_between_packets() {
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9, 11];
         let results = runtime.block_on(async {
             let packet_generator0 = PacketIntervalGenerator::new(
@@ -383,7 +605,7 @@ mod tests {
 
     #[test]
     fn fairness_test() {
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             //If fairness changes, may need to update test
             let mut input_streams: Vec<PacketStream<usize>> = Vec::new();
@@ -399,7 +621,7 @@ mod tests {
 
     #[test]
     fn small_channel() {
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9, 11];
         let results = runtime.block_on(async {
             let mut input_streams: Vec<PacketStream<usize>> = Vec::new();
@@ -415,7 +637,7 @@ mod tests {
 
     #[test]
     fn empty_stream() {
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let mut input_streams: Vec<PacketStream<usize>> = Vec::new();
             input_streams.push(immediate_stream(vec![]));
@@ -428,6 +650,108 @@ mod tests {
         assert_eq!(results[0], []);
     }
 
+    /// Runs a heavily loaded input against a lightly loaded one, returning the position of the
+    /// last packet from the lightly loaded input in the output.
+    fn last_light_packet(fairness: JoinFairness) -> usize {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = JoinLink::new()
+                .ingressor(immediate_stream(vec![0; 1000]))
+                .ingressor(immediate_stream(vec![1; 100]))
+                .fairness(fairness)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0].len(), 1100);
+        results[0].iter().rposition(|packet| *packet == 1).unwrap()
+    }
+
+    #[test]
+    fn round_robin_does_not_starve() {
+        assert!(last_light_packet(JoinFairness::RoundRobin) < 500);
+    }
+
+    #[test]
+    fn longest_queue_first_does_not_starve() {
+        assert!(last_light_packet(JoinFairness::LongestQueueFirst) < 500);
+    }
+
+    #[test]
+    fn arrival_order_does_not_starve() {
+        assert!(last_light_packet(JoinFairness::ArrivalOrder) < 500);
+    }
+
+    #[test]
+    fn arrival_order() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (mut runnables, mut egressors) = JoinLink::new()
+                .ingressor(immediate_stream(vec![0, 1, 2, 3, 4]))
+                .ingressor(immediate_stream(vec![5, 6, 7]))
+                .queue_capacity(10)
+                .fairness(JoinFairness::ArrivalOrder)
+                .build_link();
+
+            // The first input is done before the second starts.
+            tokio::spawn(runnables.remove(0)).await.unwrap();
+            tokio::spawn(runnables.remove(0)).await.unwrap();
+
+            let mut egressor = egressors.remove(0);
+            let mut output = vec![];
+            while let Some(packet) = egressor.next().await {
+                output.push(packet);
+            }
+            output
+        });
+        assert_eq!(results, vec![0, 1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn longest_queue_first_serves_backed_up_input() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (mut runnables, mut egressors) = JoinLink::new()
+                .ingressor(immediate_stream(vec![0, 0]))
+                .ingressor(immediate_stream(vec![1, 1, 1, 1, 1, 1]))
+                .queue_capacity(10)
+                .fairness(JoinFairness::LongestQueueFirst)
+                .build_link();
+
+            tokio::spawn(runnables.remove(0)).await.unwrap();
+            tokio::spawn(runnables.remove(0)).await.unwrap();
+
+            let mut egressor = egressors.remove(0);
+            let mut output = vec![];
+            while let Some(packet) = egressor.next().await {
+                output.push(packet);
+            }
+            output
+        });
+        assert_eq!(results, vec![1, 1, 1, 1, 0, 1, 0, 1]);
+    }
+
+    #[test]
+    #[cfg(feature = "metrics")]
+    fn metrics() {
+        for fairness in [JoinFairness::RoundRobin, JoinFairness::ArrivalOrder] {
+            let runtime = initialize_runtime();
+            let link = JoinLink::new()
+                .ingressor(immediate_stream(vec![0, 1, 2]))
+                .ingressor(immediate_stream(vec![3, 4, 5]))
+                .fairness(fairness)
+                .bytes(Box::new(|packet| *packet as u64));
+            let stats = link.stats();
+
+            let results = runtime.block_on(run_link(link.build_link()));
+            assert_eq!(results[0].len(), 6);
+            assert_eq!((stats.packets_in(), stats.packets_out()), (6, 6));
+            assert_eq!(stats.packets_in_by_port(), vec![(0, 3), (1, 3)]);
+            assert_eq!(stats.bytes_out(), 15);
+            assert_eq!(stats.queue_depth(), 0);
+        }
+    }
+
     #[test]
     #[should_panic]
     fn empty_channel() {
@@ -0,0 +1,365 @@
+use crate::link::primitive::PacketKey;
+use crate::link::utils::task_park::*;
+use crate::link::{primitive::QueueEgressor, Link, LinkBuilder, PacketStream};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::Sender;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// A queue per flow, keyed by a user provided `key` closure, for instance over the 5-tuple. Flows
+/// take turns to send packets on, so a single heavy flow can not starve the others. Each flow's
+/// queue holds up to `flow_capacity` packets; when it is full, further packets of that flow are
+/// dropped rather than holding up the rest, and upstream links only see backpressure from flows
+/// that are keeping up. A flow's queue is evicted as soon as the flow goes idle and its queue
+/// drains, so only flows with packets waiting take up memory.
+#[derive(Default)]
+pub struct KeyedQueueLink<Packet, Key> {
+    in_stream: Option<PacketStream<Packet>>,
+    key: Option<PacketKey<Packet, Key>>,
+    flow_capacity: usize,
+    queue_capacity: usize,
+}
+
+impl<Packet, Key> KeyedQueueLink<Packet, Key> {
+    pub fn new() -> Self {
+        KeyedQueueLink {
+            in_stream: None,
+            key: None,
+            flow_capacity: 10,
+            queue_capacity: 10,
+        }
+    }
+
+    pub fn key(self, key: PacketKey<Packet, Key>) -> Self {
+        KeyedQueueLink {
+            in_stream: self.in_stream,
+            key: Some(key),
+            flow_capacity: self.flow_capacity,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Changes flow_capacity, the most packets queued per flow, default value is 10.
+    pub fn flow_capacity(self, flow_capacity: usize) -> Self {
+        assert!(
+            flow_capacity > 0,
+            "KeyedQueueLink flow_capacity: {}, must be > 0",
+            flow_capacity
+        );
+
+        KeyedQueueLink {
+            in_stream: self.in_stream,
+            key: self.key,
+            flow_capacity,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Changes queue_capacity, of the queue to the egressor that flows take turns on, default
+    /// value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "KeyedQueueLink queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        KeyedQueueLink {
+            in_stream: self.in_stream,
+            key: self.key,
+            flow_capacity: self.flow_capacity,
+            queue_capacity,
+        }
+    }
+}
+
+impl<Packet: Send + 'static, Key: Hash + Eq + Clone + Send + 'static> LinkBuilder<Packet, Packet>
+    for KeyedQueueLink<Packet, Key>
+{
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "KeyedQueueLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("KeyedQueueLink may only take 1 input stream")
+        }
+
+        KeyedQueueLink {
+            in_stream: Some(in_streams.remove(0)),
+            key: self.key,
+            flow_capacity: self.flow_capacity,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("KeyedQueueLink may only take 1 input stream")
+        }
+
+        KeyedQueueLink {
+            in_stream: Some(in_stream),
+            key: self.key,
+            flow_capacity: self.flow_capacity,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match (self.in_stream, self.key) {
+            (None, _) => panic!("Cannot build link! Missing input stream"),
+            (_, None) => panic!("Cannot build link! Missing key"),
+            (Some(in_stream), Some(key)) => {
+                let (to_egressor, from_ingressor) =
+                    crossbeam_channel::bounded::<Option<Packet>>(self.queue_capacity);
+                let task_park: Arc<AtomicCell<TaskParkState>> =
+                    Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+                let ingressor = KeyedQueueIngressor {
+                    input_stream: Some(in_stream),
+                    to_egressor,
+                    task_park: Arc::clone(&task_park),
+                    key,
+                    flow_capacity: self.flow_capacity,
+                    flows: HashMap::new(),
+                    turns: VecDeque::new(),
+                };
+                let egressor = QueueEgressor::new(from_ingressor, task_park);
+
+                (vec![Box::new(ingressor)], vec![Box::new(egressor)])
+            }
+        }
+    }
+}
+
+pub struct KeyedQueueIngressor<Packet, Key> {
+    /// Set to `None` once the input has finished.
+    input_stream: Option<PacketStream<Packet>>,
+    to_egressor: Sender<Option<Packet>>,
+    task_park: Arc<AtomicCell<TaskParkState>>,
+    key: PacketKey<Packet, Key>,
+    flow_capacity: usize,
+    /// The queue of every flow with packets waiting.
+    flows: HashMap<Key, VecDeque<Packet>>,
+    /// The order flows take turns in. Holds each key in `flows` exactly once.
+    turns: VecDeque<Key>,
+}
+
+impl<Packet, Key> Unpin for KeyedQueueIngressor<Packet, Key> {}
+
+impl<Packet, Key: Hash + Eq + Clone> KeyedQueueIngressor<Packet, Key> {
+    /// Queues the packet on its flow, dropping it if the flow's queue is full.
+    fn enqueue(&mut self, packet: Packet) {
+        let key = (self.key)(&packet);
+        match self.flows.get_mut(&key) {
+            Some(flow) => {
+                if flow.len() < self.flow_capacity {
+                    flow.push_back(packet);
+                }
+            }
+            None => {
+                let mut flow = VecDeque::with_capacity(self.flow_capacity);
+                flow.push_back(packet);
+                self.flows.insert(key.clone(), flow);
+                self.turns.push_back(key);
+            }
+        }
+    }
+
+    /// Takes a packet from the flow whose turn it is, evicting the flow if that empties it.
+    fn dequeue(&mut self) -> Option<Packet> {
+        let key = self.turns.pop_front()?;
+        let flow = self
+            .flows
+            .get_mut(&key)
+            .expect("KeyedQueueIngressor: every key in turns has a flow");
+        let packet = flow.pop_front();
+        if flow.is_empty() {
+            self.flows.remove(&key);
+        } else {
+            self.turns.push_back(key);
+        }
+        packet
+    }
+
+    /// Sends packets to the egressor, a flow at a time, until it is full or we run out.
+    fn send(&mut self) {
+        while !self.to_egressor.is_full() {
+            match self.dequeue() {
+                Some(packet) => {
+                    self.to_egressor
+                        .try_send(Some(packet))
+                        .expect("KeyedQueueIngressor try_send to_egressor shouldn't fail");
+                    unpark_and_wake(&self.task_park);
+                }
+                None => return,
+            }
+        }
+    }
+}
+
+impl<Packet, Key: Hash + Eq + Clone> Future for KeyedQueueIngressor<Packet, Key> {
+    type Output = ();
+
+    /// We keep pulling packets onto their flows' queues for as long as the input has them, even
+    /// while the egressor is full, so heavy flows drop their own packets rather than blocking
+    /// others. Between packets, flows take turns to send to the egressor. There are several cases:
+    /// ###
+    /// #1 The input has a packet, we queue it on its flow and send what the egressor has room for.
+    ///
+    /// #2 The input is `Pending`, if we still have packets waiting on a full egressor, we park for
+    /// the egressor to wake us as well.
+    ///
+    /// #3 The input has finished, we send the rest of the packets as the egressor makes room,
+    /// followed by the `None` that tears it down.
+    /// ###
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let ingressor = Pin::into_inner(self);
+        loop {
+            ingressor.send();
+
+            let input_stream = match &mut ingressor.input_stream {
+                Some(input_stream) => input_stream,
+                None => {
+                    if !ingressor.turns.is_empty() || ingressor.to_egressor.is_full() {
+                        park_and_wake(&ingressor.task_park, cx.waker().clone());
+                        return Poll::Pending;
+                    }
+                    ingressor.to_egressor.try_send(None).expect(
+                        "KeyedQueueIngressor::Poll::Ready(None) try_send to_egressor shouldn't fail",
+                    );
+                    die_and_wake(&ingressor.task_park);
+                    return Poll::Ready(());
+                }
+            };
+
+            match Pin::new(input_stream).poll_next(cx) {
+                Poll::Ready(Some(packet)) => ingressor.enqueue(packet),
+                Poll::Ready(None) => ingressor.input_stream = None,
+                Poll::Pending => {
+                    if !ingressor.turns.is_empty() {
+                        park_and_wake(&ingressor.task_park, cx.waker().clone());
+                    }
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use std::time::Duration;
+    use tokio::time::sleep;
+
+    /// Lets the ingressor take all of its input before anything is drained, so flows back up.
+    async fn run_backed_up(link: Link<(u32, i32)>) -> Vec<(u32, i32)> {
+        let (mut runnables, mut egressors) = link;
+        tokio::spawn(runnables.remove(0));
+        sleep(Duration::from_millis(50)).await;
+
+        let mut egressor = egressors.remove(0);
+        let mut output = vec![];
+        while let Some(packet) = egressor.next().await {
+            output.push(packet);
+        }
+        output
+    }
+
+    fn flow_key() -> PacketKey<(u32, i32), u32> {
+        Box::new(|packet| packet.0)
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_key() {
+        KeyedQueueLink::<i32, i32>::new()
+            .ingressor(immediate_stream(vec![]))
+            .build_link();
+    }
+
+    #[test]
+    fn single_flow_in_order() {
+        let packets: Vec<(u32, i32)> = (0..100).map(|packet| (0, packet)).collect();
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = KeyedQueueLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .key(flow_key())
+                .flow_capacity(100)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], packets);
+    }
+
+    #[test]
+    fn flows_take_turns() {
+        let mut heavy: Vec<(u32, i32)> = (0..8).map(|packet| (0, packet)).collect();
+        heavy.extend(vec![(1, 0), (2, 0), (1, 1), (2, 1)]);
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = KeyedQueueLink::new()
+                .ingressor(immediate_stream(heavy))
+                .key(flow_key())
+                .queue_capacity(1)
+                .build_link();
+
+            run_backed_up(link).await
+        });
+        // The first packet went straight to the egressor, before the other flows arrived.
+        assert_eq!(
+            results,
+            vec![
+                (0, 0),
+                (0, 1),
+                (1, 0),
+                (2, 0),
+                (0, 2),
+                (1, 1),
+                (2, 1),
+                (0, 3),
+                (0, 4),
+                (0, 5),
+                (0, 6),
+                (0, 7)
+            ]
+        );
+    }
+
+    #[test]
+    fn heavy_flow_drops_its_own_packets() {
+        let mut packets: Vec<(u32, i32)> = (0..100).map(|packet| (0, packet)).collect();
+        packets.push((1, 0));
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = KeyedQueueLink::new()
+                .ingressor(immediate_stream(packets))
+                .key(flow_key())
+                .flow_capacity(5)
+                .queue_capacity(1)
+                .build_link();
+
+            run_backed_up(link).await
+        });
+        assert_eq!(
+            results,
+            vec![(0, 0), (0, 1), (1, 0), (0, 2), (0, 3), (0, 4), (0, 5)]
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use crate::link::utils::histogram::LatencyHistogram;
+use crate::link::{registry::LinkHandle, Link, LinkBuilder, PacketStream};
+use crate::processor::Timestamped;
+use futures::prelude::*;
+
+/// Stamps each packet with the instant it passed through, for a `LatencyMeasureLink` further
+/// down the graph to measure how long it took to get there. Like ProcessLink, this link only does
+/// work when it is polled.
+#[derive(Default)]
+pub struct LatencyStampLink<Packet> {
+    in_stream: Option<PacketStream<Packet>>,
+}
+
+impl<Packet> LatencyStampLink<Packet> {
+    pub fn new() -> Self {
+        LatencyStampLink { in_stream: None }
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Packet, Timestamped<Packet>> for LatencyStampLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "LatencyStampLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("LatencyStampLink may only take 1 input stream")
+        }
+
+        LatencyStampLink {
+            in_stream: Some(in_streams.remove(0)),
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("LatencyStampLink may only take 1 input stream")
+        }
+
+        LatencyStampLink {
+            in_stream: Some(in_stream),
+        }
+    }
+
+    fn build_link(self) -> Link<Timestamped<Packet>> {
+        match self.in_stream {
+            None => panic!("Cannot build link! Missing input stream"),
+            Some(in_stream) => (vec![], vec![Box::new(in_stream.map(Timestamped::new))]),
+        }
+    }
+}
+
+/// Records how long each packet took to get here since it passed through a `LatencyStampLink`,
+/// into a `LatencyHistogram` taken from the builder, then strips the stamp off. Placing the pair
+/// of links around a section of the graph shows how much latency that section adds. Like
+/// ProcessLink, this link only does work when it is polled.
+pub struct LatencyMeasureLink<Packet> {
+    in_stream: Option<PacketStream<Timestamped<Packet>>>,
+    histogram: LatencyHistogram,
+}
+
+impl<Packet> Default for LatencyMeasureLink<Packet> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Packet> LatencyMeasureLink<Packet> {
+    pub fn new() -> Self {
+        LatencyMeasureLink {
+            in_stream: None,
+            histogram: LatencyHistogram::new(),
+        }
+    }
+
+    /// Returns a handle to the histogram latencies are recorded into. It may be read before or
+    /// after the link is built.
+    pub fn histogram(&self) -> LatencyHistogram {
+        self.histogram.clone()
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Timestamped<Packet>, Packet>
+    for LatencyMeasureLink<Packet>
+{
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Timestamped<Packet>>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "LatencyMeasureLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("LatencyMeasureLink may only take 1 input stream")
+        }
+
+        LatencyMeasureLink {
+            in_stream: Some(in_streams.remove(0)),
+            histogram: self.histogram,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Timestamped<Packet>>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("LatencyMeasureLink may only take 1 input stream")
+        }
+
+        LatencyMeasureLink {
+            in_stream: Some(in_stream),
+            histogram: self.histogram,
+        }
+    }
+
+    fn handles(&self) -> Vec<LinkHandle> {
+        vec![Box::new(self.histogram())]
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match self.in_stream {
+            None => panic!("Cannot build link! Missing input stream"),
+            Some(in_stream) => {
+                let histogram = self.histogram;
+                let measurer = in_stream.map(move |packet| {
+                    histogram.record(packet.received.elapsed());
+                    packet.packet
+                });
+                (vec![], vec![Box::new(measurer)])
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::primitive::DelayLink;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use std::time::Duration;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_input_stream() {
+        LatencyMeasureLink::<i32>::new().build_link();
+    }
+
+    #[test]
+    fn measures_section_between_links() {
+        let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
+
+        let runtime = initialize_runtime();
+        let measure = LatencyMeasureLink::new();
+        let histogram = measure.histogram();
+        let results = runtime.block_on(async {
+            let (_, mut stamped) = LatencyStampLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .build_link();
+            let (runnables, mut delayed) = DelayLink::new()
+                .ingressor(stamped.remove(0))
+                .delay(Duration::from_millis(20))
+                .queue_capacity(packets.len())
+                .build_link();
+            let (_, egressors) = measure.ingressor(delayed.remove(0)).build_link();
+
+            run_link((runnables, egressors)).await
+        });
+        assert_eq!(results[0], packets);
+        assert_eq!(histogram.count(), packets.len() as u64);
+        assert!(histogram.value_at_quantile(0.0) >= Duration::from_millis(20));
+        assert!(histogram.max() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,302 @@
+use crate::link::utils::task_park::*;
+use crate::link::{primitive::QueueEgressor, Link, LinkBuilder, PacketStream};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::Sender;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Maps a packet to a flow hash, packets of the same flow must hash the same.
+pub type PacketHasher<Packet> = Box<dyn Fn(&Packet) -> u64 + Send + Sync + 'static>;
+
+/// Spreads packets across several egressors by hashing them with a user provided `hasher`, for
+/// instance the `stable_hash` of the packet's `FlowKey`. Packets that hash the same always land on
+/// the same egressor, so per-flow ordering is preserved across parallel pipelines.
+#[derive(Default)]
+pub struct LoadBalanceLink<Packet: Send> {
+    in_stream: Option<PacketStream<Packet>>,
+    hasher: Option<PacketHasher<Packet>>,
+    queue_capacity: usize,
+    num_egressors: Option<usize>,
+}
+
+impl<Packet: Send> LoadBalanceLink<Packet> {
+    pub fn new() -> Self {
+        LoadBalanceLink {
+            in_stream: None,
+            hasher: None,
+            queue_capacity: 10,
+            num_egressors: None,
+        }
+    }
+
+    pub fn hasher(self, hasher: PacketHasher<Packet>) -> Self {
+        LoadBalanceLink {
+            in_stream: self.in_stream,
+            hasher: Some(hasher),
+            queue_capacity: self.queue_capacity,
+            num_egressors: self.num_egressors,
+        }
+    }
+
+    /// Changes queue_capacity, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        LoadBalanceLink {
+            in_stream: self.in_stream,
+            hasher: self.hasher,
+            queue_capacity,
+            num_egressors: self.num_egressors,
+        }
+    }
+
+    pub fn num_egressors(self, num_egressors: usize) -> Self {
+        assert!(
+            num_egressors > 0,
+            "num_egressors: {}, must be > 0",
+            num_egressors
+        );
+
+        LoadBalanceLink {
+            in_stream: self.in_stream,
+            hasher: self.hasher,
+            queue_capacity: self.queue_capacity,
+            num_egressors: Some(num_egressors),
+        }
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Packet, Packet> for LoadBalanceLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "LoadBalanceLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("LoadBalanceLink may only take 1 input stream")
+        }
+
+        LoadBalanceLink {
+            in_stream: Some(in_streams.remove(0)),
+            hasher: self.hasher,
+            queue_capacity: self.queue_capacity,
+            num_egressors: self.num_egressors,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("LoadBalanceLink may only take 1 input stream")
+        }
+
+        LoadBalanceLink {
+            in_stream: Some(in_stream),
+            hasher: self.hasher,
+            queue_capacity: self.queue_capacity,
+            num_egressors: self.num_egressors,
+        }
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match (self.in_stream, self.hasher, self.num_egressors) {
+            (None, _, _) => panic!("Cannot build link! Missing input stream"),
+            (_, None, _) => panic!("Cannot build link! Missing hasher"),
+            (_, _, None) => panic!("Cannot build link! Missing number of num_egressors"),
+            (Some(in_stream), Some(hasher), Some(num_egressors)) => {
+                let mut to_egressors: Vec<Sender<Option<Packet>>> = Vec::new();
+                let mut egressors: Vec<PacketStream<Packet>> = Vec::new();
+                let mut task_parks: Vec<Arc<AtomicCell<TaskParkState>>> = Vec::new();
+
+                for _ in 0..num_egressors {
+                    let (to_egressor, from_ingressor) =
+                        crossbeam_channel::bounded::<Option<Packet>>(self.queue_capacity);
+                    let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+                    let egressor = QueueEgressor::new(from_ingressor, Arc::clone(&task_park));
+
+                    to_egressors.push(to_egressor);
+                    egressors.push(Box::new(egressor));
+                    task_parks.push(task_park);
+                }
+
+                let ingressor = LoadBalanceIngressor {
+                    input_stream: in_stream,
+                    hasher,
+                    to_egressors,
+                    task_parks,
+                };
+
+                (vec![Box::new(ingressor)], egressors)
+            }
+        }
+    }
+}
+
+pub struct LoadBalanceIngressor<P> {
+    input_stream: PacketStream<P>,
+    hasher: PacketHasher<P>,
+    to_egressors: Vec<Sender<Option<P>>>,
+    task_parks: Vec<Arc<AtomicCell<TaskParkState>>>,
+}
+
+impl<P> Unpin for LoadBalanceIngressor<P> {}
+
+impl<P: Send> Future for LoadBalanceIngressor<P> {
+    type Output = ();
+
+    /// Same logic as ClassifyIngressor, except the port is chosen by hashing the packet. If any of the
+    /// channels are full we await that channel before pulling a new packet, since we do not know
+    /// which channel the next packet will hash to.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let ingressor = Pin::into_inner(self);
+        loop {
+            for (port, to_egressor) in ingressor.to_egressors.iter().enumerate() {
+                if to_egressor.is_full() {
+                    park_and_wake(&ingressor.task_parks[port], cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+
+            let packet_option: Option<P> =
+                ready!(Pin::new(&mut ingressor.input_stream).poll_next(cx));
+
+            match packet_option {
+                None => {
+                    for to_egressor in ingressor.to_egressors.iter() {
+                        if let Err(err) = to_egressor.try_send(None) {
+                            panic!(
+                                "LoadBalanceIngressor: try_send to_egressor shouldn't fail, {:?}",
+                                err
+                            );
+                        }
+                    }
+                    for task_park in ingressor.task_parks.iter() {
+                        die_and_wake(task_park);
+                    }
+                    return Poll::Ready(());
+                }
+                Some(packet) => {
+                    let port = ((ingressor.hasher)(&packet) % ingressor.to_egressors.len() as u64)
+                        as usize;
+                    if let Err(err) = ingressor.to_egressors[port].try_send(Some(packet)) {
+                        panic!(
+                            "Error in to_egressors[{}] sender, have nowhere to put packet: {:?}",
+                            port, err
+                        );
+                    }
+                    unpark_and_wake(&ingressor.task_parks[port]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
+    use core::time;
+
+    /// Stand-in for a 5-tuple hash, packets with the same value mod 10 are the same flow.
+    fn flow_hasher() -> PacketHasher<i32> {
+        Box::new(|packet| (*packet % 10) as u64)
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_input_streams() {
+        LoadBalanceLink::<i32>::new()
+            .hasher(flow_hasher())
+            .num_egressors(2)
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_hasher() {
+        LoadBalanceLink::new()
+            .ingressor(immediate_stream(vec![0]))
+            .num_egressors(2)
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_num_egressors() {
+        LoadBalanceLink::new()
+            .ingressor(immediate_stream(vec![0]))
+            .hasher(flow_hasher())
+            .build_link();
+    }
+
+    #[test]
+    fn no_input() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = LoadBalanceLink::new()
+                .ingressor(immediate_stream(Vec::<i32>::new()))
+                .hasher(flow_hasher())
+                .num_egressors(3)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results, vec![vec![], vec![], vec![]]);
+    }
+
+    #[test]
+    fn flows_stay_together_and_in_order() {
+        let packets: Vec<i32> = (0..100).collect();
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = LoadBalanceLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .hasher(flow_hasher())
+                .num_egressors(3)
+                .build_link();
+
+            run_link(link).await
+        });
+
+        assert_eq!(results.iter().map(|port| port.len()).sum::<usize>(), 100);
+        for (port, output) in results.iter().enumerate() {
+            assert!(output
+                .iter()
+                .all(|packet| (packet % 10) as usize % 3 == port));
+            assert!(output.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+    }
+
+    #[test]
+    fn wait_between_packets() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let packet_generator = PacketIntervalGenerator::new(
+                time::Duration::from_millis(10),
+                vec![0, 1, 2, 3, 4, 5].into_iter(),
+            );
+
+            let link = LoadBalanceLink::new()
+                .ingressor(Box::new(packet_generator))
+                .hasher(flow_hasher())
+                .num_egressors(2)
+                .queue_capacity(1)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![0, 2, 4]);
+        assert_eq!(results[1], vec![1, 3, 5]);
+    }
+}
@@ -0,0 +1,340 @@
+use crate::link::utils::task_park::*;
+use crate::link::{
+    primitive::QueueEgressor, registry::LinkHandle, Link, LinkBuilder, PacketStream,
+};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::{Receiver, Sender};
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Returns whether a packet may be shed when the queue is overloaded.
+pub type ShedClassifier<Packet> = Box<dyn Fn(&Packet) -> bool + Send + Sync + 'static>;
+
+/// Which packets a LoadSheddingLink drops when its queue is full.
+pub enum ShedPolicy<Packet> {
+    /// Drop the arriving packet.
+    DropNewest,
+    /// Drop the packet at the head of the queue to make room for the arriving one, so what is
+    /// forwarded stays fresh.
+    DropOldest,
+    /// Drop the arriving packet if the classifier says it may be shed. Otherwise, drop the packet
+    /// at the head of the queue to make room for it.
+    DropClass(ShedClassifier<Packet>),
+}
+
+/// A queue that sheds load when it is full, according to a `ShedPolicy`, rather than pushing back
+/// on upstream links. This keeps an overloaded downstream from backing traffic up all the way to
+/// the NIC. Counts of what was shed are available through a `LoadSheddingStats` taken from the
+/// builder.
+pub struct LoadSheddingLink<Packet: Send> {
+    in_stream: Option<PacketStream<Packet>>,
+    policy: ShedPolicy<Packet>,
+    queue_capacity: usize,
+    stats: LoadSheddingStats,
+}
+
+impl<Packet: Send> Default for LoadSheddingLink<Packet> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Packet: Send> LoadSheddingLink<Packet> {
+    pub fn new() -> Self {
+        LoadSheddingLink {
+            in_stream: None,
+            policy: ShedPolicy::DropNewest,
+            queue_capacity: 10,
+            stats: LoadSheddingStats::new(),
+        }
+    }
+
+    /// Changes policy, default value is `ShedPolicy::DropNewest`.
+    pub fn policy(self, policy: ShedPolicy<Packet>) -> Self {
+        LoadSheddingLink {
+            in_stream: self.in_stream,
+            policy,
+            queue_capacity: self.queue_capacity,
+            stats: self.stats,
+        }
+    }
+
+    /// Changes queue_capacity, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "LoadSheddingLink queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        LoadSheddingLink {
+            in_stream: self.in_stream,
+            policy: self.policy,
+            queue_capacity,
+            stats: self.stats,
+        }
+    }
+
+    /// Returns a handle to the link's counters. It may be read before or after the link is built.
+    pub fn stats(&self) -> LoadSheddingStats {
+        self.stats.clone()
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Packet, Packet> for LoadSheddingLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "LoadSheddingLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("LoadSheddingLink may only take 1 input stream")
+        }
+
+        LoadSheddingLink {
+            in_stream: Some(in_streams.remove(0)),
+            policy: self.policy,
+            queue_capacity: self.queue_capacity,
+            stats: self.stats,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("LoadSheddingLink may only take 1 input stream")
+        }
+
+        LoadSheddingLink {
+            in_stream: Some(in_stream),
+            policy: self.policy,
+            queue_capacity: self.queue_capacity,
+            stats: self.stats,
+        }
+    }
+
+    fn handles(&self) -> Vec<LinkHandle> {
+        vec![Box::new(self.stats())]
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match self.in_stream {
+            None => panic!("Cannot build link! Missing input stream"),
+            Some(in_stream) => {
+                let (to_egressor, from_ingressor) =
+                    crossbeam_channel::bounded::<Option<Packet>>(self.queue_capacity);
+                let task_park: Arc<AtomicCell<TaskParkState>> =
+                    Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+                let ingressor = LoadSheddingIngressor {
+                    input_stream: in_stream,
+                    to_egressor,
+                    head: from_ingressor.clone(),
+                    task_park: Arc::clone(&task_park),
+                    policy: self.policy,
+                    stats: self.stats,
+                    finishing: false,
+                };
+                let egressor = QueueEgressor::new(from_ingressor, task_park);
+
+                (vec![Box::new(ingressor)], vec![Box::new(egressor)])
+            }
+        }
+    }
+}
+
+/// Handle to a LoadSheddingLink's counters.
+#[derive(Clone)]
+pub struct LoadSheddingStats {
+    enqueued: Arc<AtomicU64>,
+    shed: Arc<AtomicU64>,
+}
+
+impl LoadSheddingStats {
+    fn new() -> Self {
+        LoadSheddingStats {
+            enqueued: Arc::new(AtomicU64::new(0)),
+            shed: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Packets that made it into the queue, including any later shed from its head.
+    pub fn enqueued(&self) -> u64 {
+        self.enqueued.load(Ordering::Relaxed)
+    }
+
+    /// Packets dropped to shed load.
+    pub fn shed(&self) -> u64 {
+        self.shed.load(Ordering::Relaxed)
+    }
+}
+
+pub struct LoadSheddingIngressor<Packet> {
+    input_stream: PacketStream<Packet>,
+    to_egressor: Sender<Option<Packet>>,
+    /// Our own handle on the queue, for dropping packets off its head.
+    head: Receiver<Option<Packet>>,
+    task_park: Arc<AtomicCell<TaskParkState>>,
+    policy: ShedPolicy<Packet>,
+    stats: LoadSheddingStats,
+    /// Set once the input has finished and we are waiting for room to tell the egressor so.
+    finishing: bool,
+}
+
+impl<Packet> Unpin for LoadSheddingIngressor<Packet> {}
+
+impl<Packet> LoadSheddingIngressor<Packet> {
+    /// Makes room for the packet if the queue is full, returning it if it should be enqueued.
+    fn shed(&mut self, packet: Packet) -> Option<Packet> {
+        if !self.to_egressor.is_full() {
+            return Some(packet);
+        }
+
+        let drop_newest = match &self.policy {
+            ShedPolicy::DropNewest => true,
+            ShedPolicy::DropOldest => false,
+            ShedPolicy::DropClass(classifier) => classifier(&packet),
+        };
+        if drop_newest {
+            self.stats.shed.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        // The egressor may take the head first, in which case there is room already.
+        if let Ok(Some(_)) = self.head.try_recv() {
+            self.stats.shed.fetch_add(1, Ordering::Relaxed);
+        }
+        Some(packet)
+    }
+}
+
+impl<Packet> Future for LoadSheddingIngressor<Packet> {
+    type Output = ();
+
+    /// Same logic as QueueIngressor, except that rather than waiting when the queue is full we
+    /// shed a packet according to our policy. We only wait for room to push the `None` that tears
+    /// the egressor down.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let ingressor = Pin::into_inner(self);
+        loop {
+            if ingressor.finishing {
+                if ingressor.to_egressor.is_full() {
+                    park_and_wake(&ingressor.task_park, cx.waker().clone());
+                    return Poll::Pending;
+                }
+                ingressor.to_egressor.try_send(None).expect(
+                    "LoadSheddingIngressor::Poll::Ready(None) try_send to_egressor shouldn't fail",
+                );
+                die_and_wake(&ingressor.task_park);
+                return Poll::Ready(());
+            }
+
+            match ready!(Pin::new(&mut ingressor.input_stream).poll_next(cx)) {
+                None => ingressor.finishing = true,
+                Some(packet) => {
+                    if let Some(packet) = ingressor.shed(packet) {
+                        ingressor.to_egressor.try_send(Some(packet)).expect(
+                            "LoadSheddingIngressor::Poll::Ready(Some(val)) try_send to_egressor shouldn't fail",
+                        );
+                        ingressor.stats.enqueued.fetch_add(1, Ordering::Relaxed);
+                        unpark_and_wake(&ingressor.task_park);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use std::time::Duration;
+    use tokio::time::sleep;
+
+    /// Lets the ingressor take all of its input before anything is drained, so the queue
+    /// overflows.
+    async fn run_overloaded(link: Link<i32>) -> Vec<i32> {
+        let (mut runnables, mut egressors) = link;
+        tokio::spawn(runnables.remove(0));
+        sleep(Duration::from_millis(50)).await;
+
+        let mut egressor = egressors.remove(0);
+        let mut output = vec![];
+        while let Some(packet) = egressor.next().await {
+            output.push(packet);
+        }
+        output
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_input_stream() {
+        LoadSheddingLink::<i32>::new().build_link();
+    }
+
+    #[test]
+    fn forwards_everything_when_not_overloaded() {
+        let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = LoadSheddingLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .queue_capacity(packets.len() + 1)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], packets);
+    }
+
+    #[test]
+    fn drop_newest() {
+        let runtime = initialize_runtime();
+        let link = LoadSheddingLink::new()
+            .ingressor(immediate_stream(0..20))
+            .queue_capacity(6);
+        let stats = link.stats();
+
+        let results = runtime.block_on(run_overloaded(link.build_link()));
+        assert_eq!(results, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(stats.enqueued(), 6);
+        assert_eq!(stats.shed(), 14);
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let runtime = initialize_runtime();
+        let link = LoadSheddingLink::new()
+            .ingressor(immediate_stream(0..20))
+            .policy(ShedPolicy::DropOldest)
+            .queue_capacity(6);
+        let stats = link.stats();
+
+        let results = runtime.block_on(run_overloaded(link.build_link()));
+        assert_eq!(results, vec![14, 15, 16, 17, 18, 19]);
+        assert_eq!(stats.enqueued(), 20);
+        assert_eq!(stats.shed(), 14);
+    }
+
+    #[test]
+    fn drop_class() {
+        let runtime = initialize_runtime();
+        // Odd packets are bulk traffic, which may be shed.
+        let link = LoadSheddingLink::new()
+            .ingressor(immediate_stream(0..20))
+            .policy(ShedPolicy::DropClass(Box::new(|packet| packet % 2 == 1)))
+            .queue_capacity(6);
+        let stats = link.stats();
+
+        let results = runtime.block_on(run_overloaded(link.build_link()));
+        assert_eq!(results, vec![8, 10, 12, 14, 16, 18]);
+        assert_eq!(stats.shed(), 14);
+    }
+}
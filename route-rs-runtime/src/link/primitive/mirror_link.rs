@@ -0,0 +1,288 @@
+use crate::link::utils::task_park::*;
+use crate::link::{primitive::QueueEgressor, Link, LinkBuilder, PacketStream};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::Sender;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Returns whether a mirror should get a copy of a packet.
+pub type MirrorFilter<Packet> = Box<dyn Fn(&Packet) -> bool + Send + Sync + 'static>;
+
+/// Like a switch's span port: every packet passes through to egressor 0 untouched, and each
+/// mirror added with `mirror` gets a copy of the packets its filter matches, on egressors 1, 2, and
+/// so on, in the order they were added. Mirrors are for observing traffic, so they never hold it
+/// up; a copy is dropped if its mirror's queue is full.
+pub struct MirrorLink<Packet: Clone + Send> {
+    in_stream: Option<PacketStream<Packet>>,
+    mirrors: Vec<MirrorFilter<Packet>>,
+    queue_capacity: usize,
+}
+
+impl<Packet: Clone + Send> Default for MirrorLink<Packet> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Packet: Clone + Send> MirrorLink<Packet> {
+    pub fn new() -> Self {
+        MirrorLink {
+            in_stream: None,
+            mirrors: Vec::new(),
+            queue_capacity: 10,
+        }
+    }
+
+    /// Adds a mirror egressor, which gets a copy of every packet `filter` matches.
+    pub fn mirror(self, filter: MirrorFilter<Packet>) -> Self {
+        let mut mirrors = self.mirrors;
+        mirrors.push(filter);
+
+        MirrorLink {
+            in_stream: self.in_stream,
+            mirrors,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Changes queue_capacity, of the pass through egressor and each mirror, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "MirrorLink queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        MirrorLink {
+            in_stream: self.in_stream,
+            mirrors: self.mirrors,
+            queue_capacity,
+        }
+    }
+}
+
+impl<Packet: Clone + Send + 'static> LinkBuilder<Packet, Packet> for MirrorLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "MirrorLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("MirrorLink may only take 1 input stream")
+        }
+
+        MirrorLink {
+            in_stream: Some(in_streams.remove(0)),
+            mirrors: self.mirrors,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("MirrorLink may only take 1 input stream")
+        }
+
+        MirrorLink {
+            in_stream: Some(in_stream),
+            mirrors: self.mirrors,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match self.in_stream {
+            None => panic!("Cannot build link! Missing input stream"),
+            Some(in_stream) => {
+                let mut to_egressors: Vec<Sender<Option<Packet>>> = Vec::new();
+                let mut egressors: Vec<PacketStream<Packet>> = Vec::new();
+                let mut task_parks: Vec<Arc<AtomicCell<TaskParkState>>> = Vec::new();
+
+                for _ in 0..=self.mirrors.len() {
+                    let (to_egressor, from_ingressor) =
+                        crossbeam_channel::bounded::<Option<Packet>>(self.queue_capacity);
+                    let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+                    let egressor = QueueEgressor::new(from_ingressor, Arc::clone(&task_park));
+
+                    to_egressors.push(to_egressor);
+                    egressors.push(Box::new(egressor));
+                    task_parks.push(task_park);
+                }
+
+                let ingressor = MirrorIngressor {
+                    input_stream: in_stream,
+                    to_egressors,
+                    task_parks,
+                    mirrors: self.mirrors,
+                    ports_finished: None,
+                };
+
+                (vec![Box::new(ingressor)], egressors)
+            }
+        }
+    }
+}
+
+pub struct MirrorIngressor<P> {
+    input_stream: PacketStream<P>,
+    /// The pass through egressor, followed by one for each mirror.
+    to_egressors: Vec<Sender<Option<P>>>,
+    task_parks: Vec<Arc<AtomicCell<TaskParkState>>>,
+    mirrors: Vec<MirrorFilter<P>>,
+    /// Once the input has finished, how many egressors we have told so.
+    ports_finished: Option<usize>,
+}
+
+impl<P> Unpin for MirrorIngressor<P> {}
+
+impl<P> MirrorIngressor<P> {
+    /// Tells each egressor in turn that we are finished, waiting on any whose queue is full.
+    fn finish(&mut self, cx: &mut Context) -> Poll<()> {
+        let mut port = self.ports_finished.unwrap_or(0);
+        while port < self.to_egressors.len() {
+            if self.to_egressors[port].is_full() {
+                self.ports_finished = Some(port);
+                park_and_wake(&self.task_parks[port], cx.waker().clone());
+                return Poll::Pending;
+            }
+            if let Err(err) = self.to_egressors[port].try_send(None) {
+                panic!(
+                    "MirrorIngressor: try_send to_egressor shouldn't fail, {:?}",
+                    err
+                );
+            }
+            die_and_wake(&self.task_parks[port]);
+            port += 1;
+        }
+        self.ports_finished = Some(port);
+        Poll::Ready(())
+    }
+}
+
+impl<P: Send + Clone> Future for MirrorIngressor<P> {
+    type Output = ();
+
+    /// Same logic as QueueIngressor, except that we also try to send a copy of each packet to
+    /// every mirror whose filter matches it. We only wait on the pass through egressor having
+    /// room. When the input finishes, we wait on each egressor in turn to have room for the
+    /// `None` that tears it down.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let ingressor = Pin::into_inner(self);
+        if ingressor.ports_finished.is_some() {
+            return ingressor.finish(cx);
+        }
+
+        loop {
+            if ingressor.to_egressors[0].is_full() {
+                park_and_wake(&ingressor.task_parks[0], cx.waker().clone());
+                return Poll::Pending;
+            }
+
+            match ready!(Pin::new(&mut ingressor.input_stream).poll_next(cx)) {
+                None => return ingressor.finish(cx),
+                Some(packet) => {
+                    for (mirror, filter) in ingressor.mirrors.iter().enumerate() {
+                        let port = mirror + 1;
+                        if filter(&packet)
+                            && ingressor.to_egressors[port]
+                                .try_send(Some(packet.clone()))
+                                .is_ok()
+                        {
+                            unpark_and_wake(&ingressor.task_parks[port]);
+                        }
+                    }
+                    ingressor.to_egressors[0]
+                        .try_send(Some(packet))
+                        .expect("MirrorIngressor try_send to_egressor shouldn't fail");
+                    unpark_and_wake(&ingressor.task_parks[0]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use std::time::Duration;
+    use tokio::time::sleep;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_input_stream() {
+        MirrorLink::<i32>::new().build_link();
+    }
+
+    #[test]
+    fn passes_through_without_mirrors() {
+        let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = MirrorLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0], packets);
+    }
+
+    #[test]
+    fn mirrors_matching_packets() {
+        let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = MirrorLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .mirror(Box::new(|packet| packet % 2 == 0))
+                .mirror(Box::new(|packet| *packet > 100))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], packets);
+        assert_eq!(results[1], vec![0, 2, 420, 4, 6, 8]);
+        assert_eq!(results[2], vec![420, 1337]);
+    }
+
+    #[test]
+    fn full_mirror_does_not_hold_up_traffic() {
+        let runtime = initialize_runtime();
+        runtime.block_on(async {
+            let (mut runnables, mut egressors) = MirrorLink::new()
+                .ingressor(immediate_stream(0..20))
+                .mirror(Box::new(|_| true))
+                .queue_capacity(5)
+                .build_link();
+            tokio::spawn(runnables.remove(0));
+
+            // Nobody reads the mirror while traffic passes through.
+            let mut pass_through = egressors.remove(0);
+            let mut output = vec![];
+            for _ in 0..20 {
+                output.push(pass_through.next().await.unwrap());
+            }
+            assert_eq!(output, (0..20).collect::<Vec<i32>>());
+
+            sleep(Duration::from_millis(10)).await;
+            let mut mirror = egressors.remove(0);
+            let mut mirrored = vec![];
+            while let Some(packet) = mirror.next().await {
+                mirrored.push(packet);
+            }
+            assert_eq!(mirrored, vec![0, 1, 2, 3, 4]);
+            assert_eq!(pass_through.next().await, None);
+        });
+    }
+}
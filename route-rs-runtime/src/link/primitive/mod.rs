@@ -14,16 +14,37 @@ pub use self::process_link::*;
 mod queue_link;
 pub use self::queue_link::*;
 
+/// Runs input through an `AsyncProcessor`, awaiting several packets' processing at once while
+/// keeping them in order, asynchronous.
+mod async_process_link;
+pub use self::async_process_link::*;
+
 /// Uses processor defined classifications to sort input into different channels, a good example would
 /// be a flow that splits IPv4 and IPv6 packets, asynchronous.
 mod classify_link;
 pub use self::classify_link::*;
 
+/// Like ClassifyLink, except each class may be dispatched to several outputs, with the packet
+/// cloned to each, asynchronous.
+mod multicast_classify_link;
+pub use self::multicast_classify_link::*;
+
+/// Like ClassifyLink, except classification may fail, in which case the packet and the reason
+/// are sent to a separate error egressor, asynchronous.
+mod try_classify_link;
+pub use self::try_classify_link::*;
+
+/// Runs input through a `TryProcessor`, sending packets that fail, along with the error, to a
+/// separate error egressor, asynchronous.
+mod try_process_link;
+pub use self::try_process_link::*;
+
 /// Fairly combines all inputs into a single output, asynchronous.
 mod join_link;
 pub use self::join_link::*;
 
-/// Copies all input to each of its outputs, asynchronous.
+/// Copies all input to each of its outputs, or shares it between them through an `Arc`,
+/// asynchronous.
 mod fork_link;
 pub use self::fork_link::*;
 
@@ -34,3 +55,157 @@ pub use self::input_channel_link::*;
 /// Takes a stream and converts it to a channel for output.
 mod output_channel_link;
 pub use self::output_channel_link::*;
+
+/// Takes a stream and converts it to a channel for output, buffering packets while the channel is
+/// disconnected and replaying them when a new channel is provided.
+mod buffered_sink_link;
+pub use self::buffered_sink_link::*;
+
+/// Limits the rate packets pass through to a configured number per second, using a token bucket
+/// to allow short bursts, asynchronous.
+mod rate_limit_link;
+pub use self::rate_limit_link::*;
+
+/// Queues input from several traffic classes in one shared queue, dropping each class early
+/// according to its own Random Early Detection curve as the queue fills, asynchronous.
+mod weighted_red_link;
+pub use self::weighted_red_link::*;
+
+/// Distributes input across each of its outputs in turn, without copying, asynchronous.
+mod round_robin_link;
+pub use self::round_robin_link::*;
+
+/// Distributes input across its outputs by a hash of each packet, keeping flows together, asynchronous.
+mod load_balance_link;
+pub use self::load_balance_link::*;
+
+/// Polices or shapes input to a rate and burst size with a token bucket, counting packets or bytes,
+/// asynchronous.
+mod token_bucket_link;
+pub use self::token_bucket_link::*;
+
+/// Holds each packet for a fixed delay, with optional random jitter, before passing it on,
+/// asynchronous.
+mod delay_link;
+pub use self::delay_link::*;
+
+/// Combines all inputs into a single output, always preferring earlier inputs over later ones,
+/// asynchronous.
+mod priority_join_link;
+pub use self::priority_join_link::*;
+
+/// Combines all inputs into a single output, scheduling between them with Deficit Round Robin for
+/// approximate byte fairness, asynchronous.
+mod drr_link;
+pub use self::drr_link::*;
+
+/// A queue that drops packets with the CoDel algorithm when packets spend too long in it,
+/// asynchronous.
+mod codel_queue_link;
+pub use self::codel_queue_link::*;
+
+/// A queue that drops packets with increasing probability as it fills, with Random Early
+/// Detection, asynchronous.
+mod random_early_detection_link;
+pub use self::random_early_detection_link::*;
+
+/// Combines inputs tagged by a `Sequencer` into a single output, restoring their original order,
+/// asynchronous.
+mod reorder_link;
+pub use self::reorder_link::*;
+
+/// Groups input into batches of packets, released when full, after a timeout, or once input goes
+/// idle, or flattens batches back into packets, to amortize per packet overhead in downstream
+/// links, synchronous.
+mod batch_link;
+pub use self::batch_link::*;
+
+/// Copies input to every attached egressor, egressors may be attached and detached at runtime
+/// through a control handle, asynchronous.
+mod dynamic_fork_link;
+pub use self::dynamic_fork_link::*;
+
+/// Calls a closure on a reference to each packet before forwarding it, synchronous.
+mod inspect_link;
+pub use self::inspect_link::*;
+
+/// Reassembles IPv4 or IPv6 fragments into whole datagrams, passing other packets straight
+/// through, synchronous.
+mod reassembly_link;
+pub use self::reassembly_link::*;
+
+/// Drops packets whose key was already seen within a time window, synchronous.
+mod deduplicate_link;
+pub use self::deduplicate_link::*;
+
+/// Queue whose forwarding can be opened and closed at runtime through a control handle,
+/// asynchronous.
+mod gate_link;
+pub use self::gate_link::*;
+
+/// Sends input to one of several egressors, picked at runtime through a control handle,
+/// asynchronous.
+mod switch_link;
+pub use self::switch_link::*;
+
+/// Queue that sheds packets by a policy when full, rather than pushing back on upstream links,
+/// asynchronous.
+mod load_shedding_link;
+pub use self::load_shedding_link::*;
+
+/// Queues packets per flow, and has flows take turns sending, asynchronous.
+mod keyed_queue_link;
+pub use self::keyed_queue_link::*;
+
+/// Queue that spills into a larger secondary buffer when full, rather than pushing back on
+/// upstream links, asynchronous.
+mod spillover_queue_link;
+pub use self::spillover_queue_link::*;
+
+/// Pairs up the items of two input streams into tuples, asynchronous.
+mod zip_link;
+pub use self::zip_link::*;
+
+/// Passes input through unchanged, until it is paused at runtime through a control handle,
+/// synchronous.
+mod pauseable_link;
+pub use self::pauseable_link::*;
+
+/// A pair of links, one stamping packets and the other recording how long they took to reach it
+/// into a histogram, synchronous.
+mod latency_measure_link;
+pub use self::latency_measure_link::*;
+
+/// Counts the packets and bytes passing through it, synchronous.
+mod counter_link;
+pub use self::counter_link::*;
+
+/// Forwards input unchanged, while writing a copy of each packet to a pcap file from a separate
+/// task, synchronous.
+mod tee_to_pcap_link;
+pub use self::tee_to_pcap_link::*;
+
+/// Runs input through a processor, diverting it to a bypass egressor for a while if the processor
+/// drops too much of it, asynchronous.
+mod circuit_breaker_link;
+pub use self::circuit_breaker_link::*;
+
+/// Passes input through unchanged, while copying packets matching each mirror's filter to that
+/// mirror's egressor, asynchronous.
+mod mirror_link;
+pub use self::mirror_link::*;
+
+/// Shapes input with a Hierarchical Token Bucket, a tree of classes with guaranteed and ceiling
+/// rates, where classes borrow rate their siblings leave unused, asynchronous.
+mod htb_link;
+pub use self::htb_link::*;
+
+/// Answers requests from a cache of earlier responses, which expire after a TTL, forwarding
+/// misses on, asynchronous.
+mod expiring_cache_link;
+pub use self::expiring_cache_link::*;
+
+/// Runs a segment of the router that can be swapped for another at runtime through a control
+/// handle, draining the old segment before tearing it down, asynchronous.
+mod swap_link;
+pub use self::swap_link::*;
@@ -0,0 +1,305 @@
+use crate::classifier::Classifier;
+use crate::link::utils::task_park::*;
+use crate::link::{primitive::QueueEgressor, Link, LinkBuilder, PacketStream};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::Sender;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Maps a class to every port a packet of that class should be sent to.
+pub type MulticastDispatcher<Class> = Box<dyn Fn(Class) -> Vec<usize> + Send + Sync + 'static>;
+
+/// Like ClassifyLink, except the dispatcher maps each class to a set of ports rather than just
+/// one, and the packet is cloned to each of them, for instance to forward a multicast group's
+/// traffic out of every interface that has members. A class dispatched to no ports is dropped.
+pub struct MulticastClassifyLink<C: Classifier> {
+    in_stream: Option<PacketStream<C::Packet>>,
+    classifier: Option<C>,
+    dispatcher: Option<MulticastDispatcher<C::Class>>,
+    queue_capacity: usize,
+    num_egressors: Option<usize>,
+}
+
+impl<C: Classifier> Default for MulticastClassifyLink<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: Classifier> MulticastClassifyLink<C> {
+    pub fn new() -> Self {
+        MulticastClassifyLink {
+            in_stream: None,
+            classifier: None,
+            dispatcher: None,
+            queue_capacity: 10,
+            num_egressors: None,
+        }
+    }
+
+    pub fn classifier(self, classifier: C) -> Self {
+        MulticastClassifyLink {
+            in_stream: self.in_stream,
+            classifier: Some(classifier),
+            dispatcher: self.dispatcher,
+            queue_capacity: self.queue_capacity,
+            num_egressors: self.num_egressors,
+        }
+    }
+
+    pub fn dispatcher(self, dispatcher: MulticastDispatcher<C::Class>) -> Self {
+        MulticastClassifyLink {
+            in_stream: self.in_stream,
+            classifier: self.classifier,
+            dispatcher: Some(dispatcher),
+            queue_capacity: self.queue_capacity,
+            num_egressors: self.num_egressors,
+        }
+    }
+
+    /// Changes queue_capacity, of each egressor, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "MulticastClassifyLink queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        MulticastClassifyLink {
+            in_stream: self.in_stream,
+            classifier: self.classifier,
+            dispatcher: self.dispatcher,
+            queue_capacity,
+            num_egressors: self.num_egressors,
+        }
+    }
+
+    pub fn num_egressors(self, num_egressors: usize) -> Self {
+        assert!(
+            num_egressors > 0,
+            "MulticastClassifyLink num_egressors: {}, must be > 0",
+            num_egressors
+        );
+
+        MulticastClassifyLink {
+            in_stream: self.in_stream,
+            classifier: self.classifier,
+            dispatcher: self.dispatcher,
+            queue_capacity: self.queue_capacity,
+            num_egressors: Some(num_egressors),
+        }
+    }
+}
+
+impl<C: Classifier + Send + 'static> LinkBuilder<C::Packet, C::Packet>
+    for MulticastClassifyLink<C>
+{
+    fn ingressors(self, mut in_streams: Vec<PacketStream<C::Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "MulticastClassifyLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("MulticastClassifyLink may only take 1 input stream")
+        }
+
+        MulticastClassifyLink {
+            in_stream: Some(in_streams.remove(0)),
+            classifier: self.classifier,
+            dispatcher: self.dispatcher,
+            queue_capacity: self.queue_capacity,
+            num_egressors: self.num_egressors,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<C::Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("MulticastClassifyLink may only take 1 input stream")
+        }
+
+        MulticastClassifyLink {
+            in_stream: Some(in_stream),
+            classifier: self.classifier,
+            dispatcher: self.dispatcher,
+            queue_capacity: self.queue_capacity,
+            num_egressors: self.num_egressors,
+        }
+    }
+
+    fn build_link(self) -> Link<C::Packet> {
+        match (
+            self.in_stream,
+            self.classifier,
+            self.dispatcher,
+            self.num_egressors,
+        ) {
+            (None, _, _, _) => panic!("Cannot build link! Missing input streams"),
+            (_, None, _, _) => panic!("Cannot build link! Missing classifier"),
+            (_, _, None, _) => panic!("Cannot build link! Missing dispatcher"),
+            (_, _, _, None) => panic!("Cannot build link! Missing num_egressors"),
+            (Some(in_stream), Some(classifier), Some(dispatcher), Some(num_egressors)) => {
+                let mut to_egressors: Vec<Sender<Option<C::Packet>>> = Vec::new();
+                let mut egressors: Vec<PacketStream<C::Packet>> = Vec::new();
+                let mut task_parks: Vec<Arc<AtomicCell<TaskParkState>>> = Vec::new();
+
+                for _ in 0..num_egressors {
+                    let (to_egressor, from_ingressor) =
+                        crossbeam_channel::bounded::<Option<C::Packet>>(self.queue_capacity);
+                    let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+                    let egressor = QueueEgressor::new(from_ingressor, Arc::clone(&task_park));
+
+                    to_egressors.push(to_egressor);
+                    egressors.push(Box::new(egressor));
+                    task_parks.push(task_park);
+                }
+
+                let ingressor = MulticastClassifyIngressor {
+                    input_stream: in_stream,
+                    dispatcher,
+                    to_egressors,
+                    classifier,
+                    task_parks,
+                };
+                (vec![Box::new(ingressor)], egressors)
+            }
+        }
+    }
+}
+
+pub struct MulticastClassifyIngressor<C: Classifier> {
+    input_stream: PacketStream<C::Packet>,
+    dispatcher: MulticastDispatcher<C::Class>,
+    to_egressors: Vec<Sender<Option<C::Packet>>>,
+    classifier: C,
+    task_parks: Vec<Arc<AtomicCell<TaskParkState>>>,
+}
+
+impl<C: Classifier> Unpin for MulticastClassifyIngressor<C> {}
+
+impl<C: Classifier> Future for MulticastClassifyIngressor<C> {
+    type Output = ();
+
+    /// Same logic as ClassifyIngressor, except the packet is cloned to every port it is dispatched
+    /// to, with the last one getting the original.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let ingressor = Pin::into_inner(self);
+        loop {
+            for (port, to_egressor) in ingressor.to_egressors.iter().enumerate() {
+                if to_egressor.is_full() {
+                    park_and_wake(&ingressor.task_parks[port], cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+
+            match ready!(Pin::new(&mut ingressor.input_stream).poll_next(cx)) {
+                None => {
+                    for to_egressor in ingressor.to_egressors.iter() {
+                        to_egressor.try_send(None).expect(
+                            "MulticastClassifyIngressor::Drop: try_send to_egressor shouldn't fail",
+                        );
+                    }
+                    for task_park in ingressor.task_parks.iter() {
+                        die_and_wake(task_park);
+                    }
+                    return Poll::Ready(());
+                }
+                Some(packet) => {
+                    let class = ingressor.classifier.classify(&packet);
+                    let mut ports = (ingressor.dispatcher)(class);
+                    // Each port has room for one packet, so must be sent at most one copy.
+                    ports.sort_unstable();
+                    ports.dedup();
+                    let last_port = match ports.pop() {
+                        Some(port) => port,
+                        None => continue,
+                    };
+
+                    let mut copies: Vec<(usize, C::Packet)> = ports
+                        .into_iter()
+                        .map(|port| (port, packet.clone()))
+                        .collect();
+                    copies.push((last_port, packet));
+                    for (port, packet) in copies {
+                        if port >= ingressor.to_egressors.len() {
+                            panic!("Tried to access invalid port: {}", port);
+                        }
+                        if let Err(err) = ingressor.to_egressors[port].try_send(Some(packet)) {
+                            panic!(
+                                "Error in to_egressors[{}] sender, have nowhere to put packet: {:?}",
+                                port, err
+                            );
+                        }
+                        unpark_and_wake(&ingressor.task_parks[port]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classifier::Even;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_dispatcher() {
+        MulticastClassifyLink::new()
+            .ingressor(immediate_stream(vec![]))
+            .classifier(Even::new())
+            .num_egressors(2)
+            .build_link();
+    }
+
+    #[test]
+    fn copies_to_every_dispatched_port() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = MulticastClassifyLink::new()
+                .ingressor(immediate_stream(vec![0, 1, 2, 420, 1337, 3, 4, 5]))
+                .classifier(Even::new())
+                .dispatcher(Box::new(
+                    |is_even| {
+                        if is_even {
+                            vec![0, 2]
+                        } else {
+                            vec![1, 2]
+                        }
+                    },
+                ))
+                .num_egressors(3)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![0, 2, 420, 4]);
+        assert_eq!(results[1], vec![1, 1337, 3, 5]);
+        assert_eq!(results[2], vec![0, 1, 2, 420, 1337, 3, 4, 5]);
+    }
+
+    #[test]
+    fn drops_packets_dispatched_nowhere() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = MulticastClassifyLink::new()
+                .ingressor(immediate_stream(vec![0, 1, 2, 420, 1337, 3, 4, 5]))
+                .classifier(Even::new())
+                .dispatcher(Box::new(|is_even| if is_even { vec![0] } else { vec![] }))
+                .num_egressors(2)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![0, 2, 420, 4]);
+        assert!(results[1].is_empty());
+    }
+}
@@ -137,7 +137,7 @@ mod tests {
 
     #[test]
     fn immediate_packets() {
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
 
         let results = runtime.block_on(async {
@@ -158,7 +158,7 @@ mod tests {
     fn small_queue() {
         let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let (send, recv) = crossbeam_channel::bounded::<i32>(2);
 
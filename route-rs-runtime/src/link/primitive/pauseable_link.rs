@@ -0,0 +1,242 @@
+use crate::link::{registry::LinkHandle, Link, LinkBuilder, PacketStream};
+use crossbeam::atomic::AtomicCell;
+use futures::prelude::*;
+use futures::task::{Context, Poll, Waker};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Passes packets through unchanged, until it is paused through a `PauseControl` taken from the
+/// builder. While paused, it stops pulling from its input, so backpressure propagates to upstream
+/// links just as if downstream had stalled. This allows, for instance, holding traffic during a
+/// maintenance window, or draining part of a graph before reconfiguring it. Like ProcessLink, it
+/// only does work when it is polled. Links start running.
+pub struct PauseableLink<Packet> {
+    in_stream: Option<PacketStream<Packet>>,
+    control: PauseControl,
+}
+
+impl<Packet> Default for PauseableLink<Packet> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Packet> PauseableLink<Packet> {
+    pub fn new() -> Self {
+        PauseableLink {
+            in_stream: None,
+            control: PauseControl::new(),
+        }
+    }
+
+    /// Returns a handle for pausing and resuming the link. It may be used before or after the
+    /// link is built.
+    pub fn control(&self) -> PauseControl {
+        self.control.clone()
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Packet, Packet> for PauseableLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "PauseableLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("PauseableLink may only take 1 input stream")
+        }
+
+        PauseableLink {
+            in_stream: Some(in_streams.remove(0)),
+            control: self.control,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("PauseableLink may only take 1 input stream")
+        }
+
+        PauseableLink {
+            in_stream: Some(in_stream),
+            control: self.control,
+        }
+    }
+
+    fn handles(&self) -> Vec<LinkHandle> {
+        vec![Box::new(self.control())]
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match self.in_stream {
+            None => panic!("Cannot build link! Missing input stream"),
+            Some(in_stream) => {
+                let pauser = Pauser {
+                    in_stream,
+                    control: self.control,
+                };
+                (vec![], vec![Box::new(pauser)])
+            }
+        }
+    }
+}
+
+/// Handle to a PauseableLink, for pausing and resuming it at runtime.
+#[derive(Clone)]
+pub struct PauseControl {
+    state: Arc<PauseState>,
+}
+
+struct PauseState {
+    paused: AtomicBool,
+    /// The link's waker while it is paused.
+    task: AtomicCell<Option<Waker>>,
+}
+
+impl PauseControl {
+    fn new() -> Self {
+        PauseControl {
+            state: Arc::new(PauseState {
+                paused: AtomicBool::new(false),
+                task: AtomicCell::new(None),
+            }),
+        }
+    }
+
+    /// Stops the link pulling packets from its input. A packet already being pulled when this is
+    /// called is still forwarded.
+    pub fn pause(&self) {
+        self.state.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.state.paused.store(false, Ordering::SeqCst);
+        if let Some(task) = self.state.task.take() {
+            task.wake();
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.paused.load(Ordering::SeqCst)
+    }
+}
+
+/// The single egressor of PauseableLink
+struct Pauser<Packet> {
+    in_stream: PacketStream<Packet>,
+    control: PauseControl,
+}
+
+impl<Packet> Unpin for Pauser<Packet> {}
+
+impl<Packet> Stream for Pauser<Packet> {
+    type Item = Packet;
+
+    /// While paused, we leave our waker for `PauseControl::resume` rather than polling our input.
+    /// If we were resumed before our waker was there to be woken, we wake ourselves.
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if self.control.is_paused() {
+            self.control.state.task.store(Some(cx.waker().clone()));
+            if !self.control.is_paused() {
+                cx.waker().wake_by_ref();
+            }
+            return Poll::Pending;
+        }
+        Pin::new(&mut self.in_stream).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::primitive::QueueLink;
+    use crate::link::ProcessLinkBuilder;
+    use crate::processor::Identity;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+    use tokio::time::sleep;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_input_stream() {
+        PauseableLink::<i32>::new().build_link();
+    }
+
+    #[test]
+    fn forwards_while_running() {
+        let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = PauseableLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], packets);
+    }
+
+    #[test]
+    fn holds_packets_while_paused() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let packet_generator = PacketIntervalGenerator::new(Duration::from_millis(10), 0..10);
+            let link = PauseableLink::new().ingressor(Box::new(packet_generator));
+            let control = link.control();
+            control.pause();
+            let link = link.build_link();
+
+            tokio::spawn(async move {
+                sleep(Duration::from_millis(50)).await;
+                control.resume();
+            });
+            run_link(link).await
+        });
+        assert_eq!(results[0], (0..10).collect::<Vec<i32>>());
+    }
+
+    #[test]
+    fn pause_pushes_back_on_upstream() {
+        let runtime = initialize_runtime();
+        runtime.block_on(async {
+            let consumed = Arc::new(AtomicUsize::new(0));
+            let counted = {
+                let consumed = Arc::clone(&consumed);
+                immediate_stream(0..100).inspect(move |_| {
+                    consumed.fetch_add(1, Ordering::SeqCst);
+                })
+            };
+
+            let (mut runnables, mut egressors) = QueueLink::new()
+                .ingressor(Box::new(counted))
+                .processor(Identity::new())
+                .queue_capacity(5)
+                .build_link();
+            let link = PauseableLink::new().ingressor(egressors.remove(0));
+            let control = link.control();
+            control.pause();
+            let (_, mut egressors) = link.build_link();
+
+            tokio::spawn(runnables.remove(0));
+            sleep(Duration::from_millis(50)).await;
+
+            // The queue filled up behind the paused link, and the queue stopped taking packets.
+            let mut egressor = egressors.remove(0);
+            assert!(futures::poll!(egressor.next()).is_pending());
+            assert_eq!(consumed.load(Ordering::SeqCst), 5);
+
+            control.resume();
+            let mut output = vec![];
+            while let Some(packet) = egressor.next().await {
+                output.push(packet);
+            }
+            assert_eq!(output, (0..100).collect::<Vec<i32>>());
+        });
+    }
+}
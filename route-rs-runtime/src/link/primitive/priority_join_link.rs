@@ -0,0 +1,215 @@
+use crate::link::primitive::JoinIngressor;
+use crate::link::utils::task_park::*;
+use crate::link::{Link, LinkBuilder, PacketStream, TokioRunnable};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::Receiver;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Like JoinLink, but with strict priority between the inputs rather than fairness. Ingressor 0 is
+/// always drained first, then ingressor 1, and so on, so that control traffic such as ARP or ICMP
+/// can preempt bulk data. Lower priority inputs may starve while higher priority inputs are busy.
+#[derive(Default)]
+pub struct PriorityJoinLink<Packet: Send> {
+    in_streams: Option<Vec<PacketStream<Packet>>>,
+    queue_capacity: usize,
+}
+
+impl<Packet: Send> PriorityJoinLink<Packet> {
+    pub fn new() -> Self {
+        PriorityJoinLink {
+            in_streams: None,
+            queue_capacity: 10,
+        }
+    }
+
+    /// Changes queue_capacity, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "Queue capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        PriorityJoinLink {
+            in_streams: self.in_streams,
+            queue_capacity,
+        }
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Packet, Packet> for PriorityJoinLink<Packet> {
+    /// Input streams are given priority in the order they are provided, highest first.
+    fn ingressors(self, in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert!(
+            !in_streams.is_empty(),
+            "number of in_streams: {}, must be greater than 0",
+            in_streams.len()
+        );
+
+        if self.in_streams.is_some() {
+            panic!("PriorityJoinLink already has input streams")
+        }
+
+        PriorityJoinLink {
+            in_streams: Some(in_streams),
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Appends the ingressor to the ingressors of the link, at a lower priority than those
+    /// already provided.
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        let mut in_streams = self.in_streams.unwrap_or_default();
+        in_streams.push(in_stream);
+
+        PriorityJoinLink {
+            in_streams: Some(in_streams),
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match self.in_streams {
+            None => panic!("Cannot build link! Missing input streams"),
+            Some(input_streams) => {
+                let number_ingressors = input_streams.len();
+                let mut ingressors: Vec<TokioRunnable> = Vec::new();
+                let mut from_ingressors: Vec<Receiver<Option<Packet>>> = Vec::new();
+                let mut task_parks: Vec<Arc<AtomicCell<TaskParkState>>> = Vec::new();
+
+                for input_stream in input_streams {
+                    let (to_egressor, from_ingressor) =
+                        crossbeam_channel::bounded::<Option<Packet>>(self.queue_capacity);
+                    let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+                    let ingressor =
+                        JoinIngressor::new(input_stream, to_egressor, Arc::clone(&task_park));
+                    ingressors.push(Box::new(ingressor));
+                    from_ingressors.push(from_ingressor);
+                    task_parks.push(task_park);
+                }
+
+                let egressor = PriorityJoinEgressor {
+                    from_ingressors,
+                    task_parks,
+                    ingressors_alive: number_ingressors,
+                };
+
+                (ingressors, vec![Box::new(egressor)])
+            }
+        }
+    }
+}
+
+pub struct PriorityJoinEgressor<Packet: Sized> {
+    from_ingressors: Vec<Receiver<Option<Packet>>>,
+    task_parks: Vec<Arc<AtomicCell<TaskParkState>>>,
+    ingressors_alive: usize,
+}
+
+impl<Packet: Sized> Unpin for PriorityJoinEgressor<Packet> {}
+
+impl<Packet: Sized> Stream for PriorityJoinEgressor<Packet> {
+    type Item = Packet;
+
+    /// Same as JoinEgressor, except every poll starts over from ingressor 0, so a packet is only
+    /// taken from an ingressor when every higher priority ingressor is empty.
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let egressor = Pin::into_inner(self);
+        for (port, from_ingressor) in egressor.from_ingressors.iter().enumerate() {
+            match from_ingressor.try_recv() {
+                Ok(Some(packet)) => {
+                    unpark_and_wake(&egressor.task_parks[port]);
+                    return Poll::Ready(Some(packet));
+                }
+                Ok(None) => {
+                    egressor.ingressors_alive -= 1;
+                    if egressor.ingressors_alive == 0 {
+                        for task_park in egressor.task_parks.iter() {
+                            die_and_wake(task_park);
+                        }
+                        return Poll::Ready(None);
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+
+        let mut parked_egressor_task = false;
+        let egressor_task = Arc::new(AtomicCell::new(Some(cx.waker().clone())));
+        for task_park in egressor.task_parks.iter() {
+            if indirect_park_and_wake(task_park, Arc::clone(&egressor_task)) {
+                parked_egressor_task = true;
+            }
+        }
+        if !parked_egressor_task {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_input_streams() {
+        PriorityJoinLink::<i32>::new().build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_input_streams_is_empty() {
+        PriorityJoinLink::<i32>::new().ingressors(vec![]);
+    }
+
+    #[test]
+    fn passes_all_packets() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = PriorityJoinLink::new()
+                .ingressor(immediate_stream(0..50))
+                .ingressor(immediate_stream(50..100))
+                .ingressor(immediate_stream(vec![]))
+                .build_link();
+
+            run_link(link).await
+        });
+        let mut output = results[0].clone();
+        output.sort();
+        assert_eq!(output, (0..100).collect::<Vec<i32>>());
+    }
+
+    #[test]
+    fn drains_higher_priority_first() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (runnables, mut egressors) = PriorityJoinLink::new()
+                .ingressor(immediate_stream(vec![0, 0, 0, 0]))
+                .ingressor(immediate_stream(vec![1, 1, 1, 1]))
+                .ingressor(immediate_stream(vec![2, 2, 2, 2]))
+                .build_link();
+
+            // Queue everything up before the egressor is polled.
+            for runnable in runnables {
+                tokio::spawn(runnable).await.unwrap();
+            }
+
+            let mut egressor = egressors.remove(0);
+            let mut output = vec![];
+            while let Some(packet) = egressor.next().await {
+                output.push(packet);
+            }
+            output
+        });
+        assert_eq!(results, vec![0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2]);
+    }
+}
@@ -2,11 +2,16 @@ use crate::link::{Link, LinkBuilder, PacketStream, ProcessLinkBuilder};
 use crate::processor::Processor;
 use futures::prelude::*;
 use futures::task::{Context, Poll};
+use std::collections::VecDeque;
 use std::pin::Pin;
 
+/// The most packets ProcessRunner takes off its input for one call to `process_batch`.
+const MAX_BATCH_SIZE: usize = 32;
+
 /// `ProcessLink` processes packets through a user-defined processor.
 /// It can not buffer packets, so it only does work when it is called. It must immediately drop
-/// or return a transformed packet.
+/// or return a transformed packet. Packets the input has ready at once are processed as a batch,
+/// of up to 32, and returned one by one.
 #[derive(Default)]
 pub struct ProcessLink<P: Processor> {
     in_stream: Option<PacketStream<P::Input>>,
@@ -78,14 +83,20 @@ impl<P: Processor + Send + 'static> ProcessLinkBuilder<P> for ProcessLink<P> {
 /// The single egressor of ProcessLink
 struct ProcessRunner<P: Processor> {
     in_stream: PacketStream<P::Input>,
+    input_finished: bool,
     processor: P,
+    batch: Vec<P::Input>,
+    processed: VecDeque<P::Output>,
 }
 
 impl<P: Processor> ProcessRunner<P> {
     fn new(in_stream: PacketStream<P::Input>, processor: P) -> Self {
         ProcessRunner {
             in_stream,
+            input_finished: false,
             processor,
+            batch: Vec::new(),
+            processed: VecDeque::new(),
         }
     }
 }
@@ -99,7 +110,9 @@ impl<P: Processor> Stream for ProcessRunner<P> {
     /// 3 cases: `Poll::Ready(Some)`, `Poll::Ready(None)`, `Poll::Pending`
     ///
     /// `Poll::Ready(Some)`: We have a packet ready to process from the upstream processor.
-    /// It's passed to our core's process function for... processing
+    /// We keep taking packets until the input has no more ready, or we have a full batch, and
+    /// pass them to our core's process_batch function for... processing. We return the first
+    /// processed packet, and hold on to the rest for the next calls.
     ///
     /// `Poll::Ready(None)`: The input_stream doesn't have anymore input. Semantically,
     /// it's like an iterator has exhausted it's input. We should return `Poll::Ready(None)`
@@ -110,20 +123,35 @@ impl<P: Processor> Stream for ProcessRunner<P> {
     /// `Poll::Pending`: There is more input for us to process, but we can't make any more
     /// progress right now. The contract for Streams asks us to register with a Reactor so we
     /// will be woken up again by an Executor, but we will be relying on Tokio to do that for us.
-    /// This case is handled by the `try_ready!` macro, which will automatically return
-    /// `Ok(Async::NotReady)` if the input stream gives us NotReady.
+    /// This case is handled by the `ready!` macro, which will automatically return
+    /// `Poll::Pending` if the input stream gives us Pending.
     ///
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let runner = Pin::into_inner(self);
         loop {
-            match ready!(Pin::new(&mut self.in_stream).poll_next(cx)) {
-                None => return Poll::Ready(None),
-                Some(input_packet) => {
-                    // if `processor.process` returns None, do nothing, loop around and try polling again.
-                    if let Some(output_packet) = self.processor.process(input_packet) {
-                        return Poll::Ready(Some(output_packet));
+            if let Some(output_packet) = runner.processed.pop_front() {
+                return Poll::Ready(Some(output_packet));
+            }
+            if runner.input_finished {
+                return Poll::Ready(None);
+            }
+
+            while runner.batch.len() < MAX_BATCH_SIZE {
+                match Pin::new(&mut runner.in_stream).poll_next(cx) {
+                    Poll::Ready(Some(input_packet)) => runner.batch.push(input_packet),
+                    Poll::Ready(None) => {
+                        runner.input_finished = true;
+                        break;
                     }
+                    Poll::Pending if runner.batch.is_empty() => return Poll::Pending,
+                    Poll::Pending => break,
                 }
             }
+
+            // If every packet is dropped, loop around and try polling again.
+            let processed = runner.processor.process_batch(&mut runner.batch);
+            runner.batch.clear();
+            runner.processed.extend(processed);
         }
     }
 }
@@ -173,7 +201,7 @@ mod tests {
     fn identity() {
         let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let link = ProcessLink::new()
                 .ingressor(immediate_stream(packets.clone()))
@@ -189,7 +217,7 @@ mod tests {
     fn wait_between_packets() {
         let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let packet_generator = PacketIntervalGenerator::new(
                 time::Duration::from_millis(10),
@@ -210,7 +238,7 @@ mod tests {
     fn type_transform() {
         let packets = "route-rs".chars();
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let packet_generator = immediate_stream(packets.clone());
 
@@ -229,7 +257,7 @@ mod tests {
     fn drop() {
         let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let link = ProcessLink::new()
                 .ingressor(immediate_stream(packets))
@@ -240,4 +268,54 @@ mod tests {
         });
         assert_eq!(results[0], []);
     }
+
+    /// Replaces each packet with the size of the batch it was processed in.
+    struct BatchSize {}
+
+    impl Processor for BatchSize {
+        type Input = i32;
+        type Output = usize;
+
+        fn process(&mut self, _packet: i32) -> Option<usize> {
+            Some(1)
+        }
+
+        fn process_batch(&mut self, packets: &mut Vec<i32>) -> Vec<usize> {
+            let batch_size = packets.len();
+            packets.drain(..).map(|_| batch_size).collect()
+        }
+    }
+
+    #[test]
+    fn processes_ready_packets_in_batches() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = ProcessLink::new()
+                .ingressor(immediate_stream(0..40))
+                .processor(BatchSize {})
+                .build_link();
+
+            run_link(link).await
+        });
+        let mut expected = vec![32; 32];
+        expected.extend(vec![8; 8]);
+        assert_eq!(results[0], expected);
+    }
+
+    #[test]
+    fn waiting_packets_are_processed_alone() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let packet_generator =
+                PacketIntervalGenerator::new(time::Duration::from_millis(10), 0..5);
+
+            let link = ProcessLink::new()
+                .ingressor(Box::new(packet_generator))
+                .processor(BatchSize {})
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![1; 5]);
+    }
 }
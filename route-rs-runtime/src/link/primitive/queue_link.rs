@@ -1,21 +1,52 @@
+use crate::link::primitive::PacketCost;
+use crate::link::utils::stats::{LinkCounter, LinkStats};
 use crate::link::utils::task_park::*;
-use crate::link::{Link, LinkBuilder, PacketStream, ProcessLinkBuilder};
+use crate::link::{registry::LinkHandle, Link, LinkBuilder, PacketStream, ProcessLinkBuilder};
 use crate::processor::Processor;
 use crossbeam::atomic::AtomicCell;
 use crossbeam::crossbeam_channel;
 use crossbeam::crossbeam_channel::{Receiver, Sender, TryRecvError};
 use futures::prelude::*;
 use futures::task::{Context, Poll};
+use std::collections::VecDeque;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
+/// What a QueueLink does with a packet when its queue is full.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DropPolicy {
+    /// Drop the arriving packet.
+    TailDrop,
+    /// Drop the packet at the head of the queue, and enqueue the arriving packet behind the rest.
+    HeadDrop,
+    /// Put the arriving packet in place of the packet at the head of the queue, so it is the next
+    /// one out. Under sustained overload, the freshest packet is sent first, followed by the rest
+    /// of the queue in order.
+    ReplaceOldest,
+}
+
 /// A link used to create queues, buffers, or Task boundries. Packets may be
-/// transformed with a Processor prior to being enqueued.
-#[derive(Default)]
+/// transformed with a Processor prior to being enqueued, in batches of those the input
+/// has ready at once. When the queue is full,
+/// the ingressor waits for room, unless a `DropPolicy` is set, in which case it drops
+/// packets instead. Counts of what was dropped are available through a `LinkStats` taken from
+/// the builder, with why a full queue dropped them through a `QueueStats`. With the `metrics`
+/// feature, the `LinkStats` also count what passed through, and the queue's depth.
 pub struct QueueLink<P: Processor> {
     in_stream: Option<PacketStream<P::Input>>,
     processor: Option<P>,
     queue_capacity: usize,
+    drop_policy: Option<DropPolicy>,
+    bytes: Option<Arc<PacketCost<P::Output>>>,
+    stats: Arc<LinkStats>,
+    drop_stats: QueueStats,
+}
+
+impl<P: Processor> Default for QueueLink<P> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<P: Processor> QueueLink<P> {
@@ -24,6 +55,10 @@ impl<P: Processor> QueueLink<P> {
             in_stream: None,
             processor: None,
             queue_capacity: 10,
+            drop_policy: None,
+            bytes: None,
+            stats: Arc::new(LinkStats::default()),
+            drop_stats: QueueStats::new(),
         }
     }
 
@@ -39,8 +74,51 @@ impl<P: Processor> QueueLink<P> {
             in_stream: self.in_stream,
             processor: self.processor,
             queue_capacity,
+            drop_policy: self.drop_policy,
+            bytes: self.bytes,
+            stats: self.stats,
+            drop_stats: self.drop_stats,
+        }
+    }
+
+    /// Drops packets according to `drop_policy` when the queue is full, rather than waiting for
+    /// room, which is the default.
+    pub fn drop_policy(self, drop_policy: DropPolicy) -> Self {
+        QueueLink {
+            in_stream: self.in_stream,
+            processor: self.processor,
+            queue_capacity: self.queue_capacity,
+            drop_policy: Some(drop_policy),
+            bytes: self.bytes,
+            stats: self.stats,
+            drop_stats: self.drop_stats,
+        }
+    }
+
+    /// Sets the closure that gives the length of a packet in bytes, to count the bytes given out
+    /// in the link's stats. Without it, only packets are counted.
+    pub fn bytes(self, bytes: PacketCost<P::Output>) -> Self {
+        QueueLink {
+            in_stream: self.in_stream,
+            processor: self.processor,
+            queue_capacity: self.queue_capacity,
+            drop_policy: self.drop_policy,
+            bytes: Some(Arc::new(bytes)),
+            stats: self.stats,
+            drop_stats: self.drop_stats,
         }
     }
+
+    /// Returns the link's counters. They may be read before or after the link is built.
+    pub fn stats(&self) -> Arc<LinkStats> {
+        Arc::clone(&self.stats)
+    }
+
+    /// Returns a handle to the counts of why the link's queue dropped packets. It may be read
+    /// before or after the link is built.
+    pub fn drop_stats(&self) -> QueueStats {
+        self.drop_stats.clone()
+    }
 }
 
 impl<P: Processor + Send + 'static> LinkBuilder<P::Input, P::Output> for QueueLink<P> {
@@ -59,6 +137,10 @@ impl<P: Processor + Send + 'static> LinkBuilder<P::Input, P::Output> for QueueLi
             in_stream: Some(in_streams.remove(0)),
             processor: self.processor,
             queue_capacity: self.queue_capacity,
+            drop_policy: self.drop_policy,
+            bytes: self.bytes,
+            stats: self.stats,
+            drop_stats: self.drop_stats,
         }
     }
 
@@ -71,9 +153,17 @@ impl<P: Processor + Send + 'static> LinkBuilder<P::Input, P::Output> for QueueLi
             in_stream: Some(in_stream),
             processor: self.processor,
             queue_capacity: self.queue_capacity,
+            drop_policy: self.drop_policy,
+            bytes: self.bytes,
+            stats: self.stats,
+            drop_stats: self.drop_stats,
         }
     }
 
+    fn handles(&self) -> Vec<LinkHandle> {
+        vec![Box::new(self.stats()), Box::new(self.drop_stats())]
+    }
+
     fn build_link(self) -> Link<P::Output> {
         if self.in_stream.is_none() {
             panic!("Cannot build link! Missing input stream");
@@ -85,13 +175,21 @@ impl<P: Processor + Send + 'static> LinkBuilder<P::Input, P::Output> for QueueLi
             let task_park: Arc<AtomicCell<TaskParkState>> =
                 Arc::new(AtomicCell::new(TaskParkState::Empty));
 
-            let ingresssor = QueueIngressor::new(
+            let mut ingresssor = QueueIngressor::new(
                 self.in_stream.unwrap(),
                 to_egressor,
                 self.processor.unwrap(),
                 Arc::clone(&task_park),
             );
-            let egressor = QueueEgressor::new(from_ingressor, task_park);
+            ingresssor.dropping = self.drop_policy.map(|policy| Dropping {
+                policy,
+                head: from_ingressor.clone(),
+            });
+            ingresssor.counter = LinkCounter::input(&self.stats, 0);
+            ingresssor.stats = Arc::clone(&self.stats);
+            ingresssor.drop_stats = self.drop_stats;
+            let egressor = QueueEgressor::new(from_ingressor, task_park)
+                .counter(LinkCounter::output(&self.stats, 0, self.bytes.as_ref()));
 
             (vec![Box::new(ingresssor)], vec![Box::new(egressor)])
         }
@@ -104,25 +202,81 @@ impl<P: Processor + Send + 'static> ProcessLinkBuilder<P> for QueueLink<P> {
             in_stream: self.in_stream,
             processor: Some(processor),
             queue_capacity: self.queue_capacity,
+            drop_policy: self.drop_policy,
+            bytes: self.bytes,
+            stats: self.stats,
+            drop_stats: self.drop_stats,
+        }
+    }
+}
+
+/// Handle to the counts of a QueueLink's queue, and of why it dropped packets when full. Every
+/// drop, including the processor's, is also counted in the link's `LinkStats`.
+#[derive(Clone)]
+pub struct QueueStats {
+    enqueued: Arc<AtomicU64>,
+    tail_dropped: Arc<AtomicU64>,
+    head_dropped: Arc<AtomicU64>,
+}
+
+impl QueueStats {
+    fn new() -> Self {
+        QueueStats {
+            enqueued: Arc::new(AtomicU64::new(0)),
+            tail_dropped: Arc::new(AtomicU64::new(0)),
+            head_dropped: Arc::new(AtomicU64::new(0)),
         }
     }
+
+    /// Packets that made it into the queue, including any later dropped from its head.
+    pub fn enqueued(&self) -> u64 {
+        self.enqueued.load(Ordering::Relaxed)
+    }
+
+    /// Arriving packets dropped because the queue was full, under `DropPolicy::TailDrop`.
+    pub fn tail_dropped(&self) -> u64 {
+        self.tail_dropped.load(Ordering::Relaxed)
+    }
+
+    /// Packets dropped off the head of the queue to make room, under `DropPolicy::HeadDrop` or
+    /// `DropPolicy::ReplaceOldest`.
+    pub fn head_dropped(&self) -> u64 {
+        self.head_dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Set on a QueueIngressor that drops packets when its queue is full.
+struct Dropping<Packet> {
+    policy: DropPolicy,
+    /// Our own handle on the queue, for dropping packets off its head.
+    head: Receiver<Option<Packet>>,
 }
 
 /// The QueueIngressor is responsible for polling its input stream,
 /// processing them using the `processor`s process function, and pushing the
 /// output packet onto the to_egressor queue. It does work in batches, so it
 /// will continue to pull packets as long as it can make forward progess,
-/// after which it will return NotReady to sleep. This is handed to, and is
+/// after which it will return Pending to sleep. This is handed to, and is
 /// polled by the runtime.
 pub struct QueueIngressor<P: Processor> {
     input_stream: PacketStream<P::Input>,
     to_egressor: Sender<Option<P::Output>>,
     processor: P,
     task_park: Arc<AtomicCell<TaskParkState>>,
+    dropping: Option<Dropping<P::Output>>,
+    /// Where we count what we drop, with or without the `metrics` feature.
+    stats: Arc<LinkStats>,
+    drop_stats: QueueStats,
+    counter: LinkCounter<P::Input>,
+    /// Set once the input has finished and we are waiting for room to tell the egressor so.
+    finishing: bool,
+    batch: Vec<P::Input>,
+    /// Processed packets still waiting for room in the queue.
+    processed: VecDeque<P::Output>,
 }
 
 impl<P: Processor> QueueIngressor<P> {
-    fn new(
+    pub fn new(
         input_stream: PacketStream<P::Input>,
         to_egressor: Sender<Option<P::Output>>,
         processor: P,
@@ -133,8 +287,81 @@ impl<P: Processor> QueueIngressor<P> {
             to_egressor,
             processor,
             task_park,
+            dropping: None,
+            stats: Arc::new(LinkStats::default()),
+            drop_stats: QueueStats::new(),
+            counter: LinkCounter::default(),
+            finishing: false,
+            batch: Vec::new(),
+            processed: VecDeque::new(),
+        }
+    }
+
+    /// Counts with `counter` the packets we take in and enqueue.
+    pub(crate) fn counter(mut self, counter: LinkCounter<P::Input>) -> Self {
+        self.counter = counter;
+        self
+    }
+
+    /// The most packets to take off the input for one batch: as many as the queue has room for,
+    /// or as it holds, if we are dropping packets. Always at least one, so a full queue that we
+    /// drop from still makes progress.
+    fn batch_size(&self) -> usize {
+        let capacity = self.to_egressor.capacity().unwrap_or(1);
+        if self.dropping.is_some() {
+            capacity
+        } else {
+            capacity.saturating_sub(self.to_egressor.len()).max(1)
+        }
+    }
+
+    /// Makes room for the packet if the queue is full and we are dropping packets, then enqueues
+    /// it, unless it is the packet that gets dropped.
+    fn enqueue(&self, packet: P::Output) {
+        let dropping = match &self.dropping {
+            Some(dropping) if self.to_egressor.is_full() => dropping,
+            _ => return self.send(packet),
+        };
+        match dropping.policy {
+            DropPolicy::TailDrop => {
+                self.drop_stats.tail_dropped.fetch_add(1, Ordering::Relaxed);
+                self.stats.dropped_packets(1);
+            }
+            DropPolicy::HeadDrop => {
+                // The egressor may take the head first, in which case there is room already.
+                if let Ok(Some(_)) = dropping.head.try_recv() {
+                    self.drop_stats.head_dropped.fetch_add(1, Ordering::Relaxed);
+                    self.counter.dequeued(1);
+                    self.stats.dropped_packets(1);
+                }
+                self.send(packet);
+            }
+            DropPolicy::ReplaceOldest => {
+                // We take everything off the queue, to put it back behind the packet.
+                let mut queued: VecDeque<P::Output> = dropping.head.try_iter().flatten().collect();
+                if queued.pop_front().is_some() {
+                    self.drop_stats.head_dropped.fetch_add(1, Ordering::Relaxed);
+                    self.counter.dequeued(1);
+                    self.stats.dropped_packets(1);
+                }
+                self.send(packet);
+                for packet in queued {
+                    self.to_egressor
+                        .try_send(Some(packet))
+                        .expect("QueueIngressor ReplaceOldest try_send to_egressor shouldn't fail");
+                }
+            }
         }
     }
+
+    fn send(&self, packet: P::Output) {
+        self.counter.enqueued(1);
+        self.to_egressor
+            .try_send(Some(packet))
+            .expect("QueueIngressor::Poll::Ready(Some(val)) try_send to_egressor shouldn't fail");
+        self.drop_stats.enqueued.fetch_add(1, Ordering::Relaxed);
+        unpark_and_wake(&self.task_park);
+    }
 }
 
 impl<P: Processor> Unpin for QueueIngressor<P> {}
@@ -149,49 +376,69 @@ impl<P: Processor> Future for QueueIngressor<P> {
     /// make forward progress. There are several cases:
     /// ###
     /// #1 The to_egressor queue is full, we wake the Egressor that we need
-    /// awaking when there is work to do, and go to sleep by returning `Async::NotReady`.
+    /// awaking when there is work to do, and go to sleep by returning `Poll::Pending`.
     ///
-    /// #2 The input_stream returns a NotReady, we sleep, with the assumption
-    /// that whomever produced the NotReady will awaken the task in the Future.
+    /// #2 The input_stream returns a Pending, we sleep, with the assumption
+    /// that whomever produced the Pending will awaken the task in the Future.
     ///
     /// #3 We get a Ready(None), in which case we push a None onto the to_Egressor
     /// queue and then return Ready(()), which means we enter tear-down, since there
     /// is no further work to complete.
     ///
-    /// #4 If our upstream `PacketStream` has a packet for us, we pass it to our `processor`
-    /// for `process`ing. Most of the time, it will yield a `Some(output_packet)` that has
-    /// been transformed in some way. We pass that on to our egress channel and wake
-    /// our `Egressor` that it has work to do, and continue polling our upstream `PacketStream`.
+    /// #4 If our upstream `PacketStream` has packets for us, we take as many as it has ready,
+    /// and the queue has room for, and pass them to our `processor` to `process_batch`. Most of
+    /// the time, it will yield the packets transformed in some way. We pass those on to our
+    /// egress channel and wake our `Egressor` that it has work to do, and continue polling our
+    /// upstream `PacketStream`. Should the batch yield more packets than there is room for, we
+    /// hold on to the rest until there is.
     ///
-    /// #5 `processor`s may also choose to "drop" packets by returning `None`, so we do nothing
+    /// #5 `processor`s may also choose to "drop" packets by not returning them, so we do nothing
     /// and poll our upstream `PacketStream` again.
     ///
-    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+    /// If we are dropping packets, we do not wait in #1, and instead make room in #4 according
+    /// to our `DropPolicy`. We only wait for room to push the `None` in #3.
+    ///
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let ingressor = Pin::into_inner(self);
         loop {
-            if self.to_egressor.is_full() {
-                park_and_wake(&self.task_park, cx.waker().clone());
+            let waits_for_room = ingressor.dropping.is_none()
+                || (ingressor.finishing && ingressor.processed.is_empty());
+            if ingressor.to_egressor.is_full() && waits_for_room {
+                park_and_wake(&ingressor.task_park, cx.waker().clone());
                 return Poll::Pending;
             }
-            let input_packet_option: Option<P::Input> =
-                ready!(Pin::new(&mut self.input_stream).poll_next(cx));
-
-            match input_packet_option {
-                None => {
-                    self.to_egressor.try_send(None).expect(
-                        "QueueIngressor::Poll::Ready(None) try_send to_egressor shouldn't fail",
-                    );
-                    die_and_wake(&self.task_park);
-                    return Poll::Ready(());
-                }
-                Some(input_packet) => {
-                    if let Some(output_packet) = self.processor.process(input_packet) {
-                        self.to_egressor
-                            .try_send(Some(output_packet))
-                            .expect("QueueIngressor::Poll::Ready(Some(val)) try_send to_egressor shouldn't fail");
-                        unpark_and_wake(&self.task_park);
+            if let Some(output_packet) = ingressor.processed.pop_front() {
+                ingressor.enqueue(output_packet);
+                continue;
+            }
+            if ingressor.finishing {
+                ingressor.to_egressor.try_send(None).expect(
+                    "QueueIngressor::Poll::Ready(None) try_send to_egressor shouldn't fail",
+                );
+                die_and_wake(&ingressor.task_park);
+                return Poll::Ready(());
+            }
+
+            let batch_size = ingressor.batch_size();
+            while ingressor.batch.len() < batch_size {
+                match Pin::new(&mut ingressor.input_stream).poll_next(cx) {
+                    Poll::Ready(Some(input_packet)) => ingressor.batch.push(input_packet),
+                    Poll::Ready(None) => {
+                        ingressor.finishing = true;
+                        break;
                     }
+                    Poll::Pending if ingressor.batch.is_empty() => return Poll::Pending,
+                    Poll::Pending => break,
                 }
             }
+
+            let batch_len = ingressor.batch.len();
+            let processed = ingressor.processor.process_batch(&mut ingressor.batch);
+            ingressor.batch.clear();
+            let dropped = batch_len.saturating_sub(processed.len()) as u64;
+            ingressor.counter.received(batch_len as u64);
+            ingressor.stats.dropped_packets(dropped);
+            ingressor.processed.extend(processed);
         }
     }
 }
@@ -203,6 +450,7 @@ impl<P: Processor> Future for QueueIngressor<P> {
 pub struct QueueEgressor<Packet: Sized> {
     from_ingressor: Receiver<Option<Packet>>,
     task_park: Arc<AtomicCell<TaskParkState>>,
+    counter: LinkCounter<Packet>,
 }
 
 impl<Packet: Sized> QueueEgressor<Packet> {
@@ -213,8 +461,15 @@ impl<Packet: Sized> QueueEgressor<Packet> {
         QueueEgressor {
             from_ingressor,
             task_park,
+            counter: LinkCounter::default(),
         }
     }
+
+    /// Counts with `counter` the packets we take off the queue and give out.
+    pub(crate) fn counter(mut self, counter: LinkCounter<Packet>) -> Self {
+        self.counter = counter;
+        self
+    }
 }
 
 impl<Packet: Sized> Unpin for QueueEgressor<Packet> {}
@@ -228,21 +483,23 @@ impl<Packet: Sized> Stream for QueueEgressor<Packet> {
     /// channel, there are four cases:
     /// ###
     /// #1 Ok(Some(Packet)): Got a packet. If the Ingressor needs (likely due to
-    /// an until now full channel) to be awoken, wake them. Return the Async::Ready(Option(Packet))
+    /// an until now full channel) to be awoken, wake them. Return the Poll::Ready(Option(Packet))
     ///
     /// #2 Ok(None): this means that the Ingressor is in tear-down, and we
-    /// will no longer be receivig packets. Return Async::Ready(None) to forward propagate teardown
+    /// will no longer be receivig packets. Return Poll::Ready(None) to forward propagate teardown
     ///
     /// #3 Err(TryRecvError::Empty): Packet queue is empty, await the Ingressor to awaken us with more
-    /// work, by returning Async::NotReady to signal to runtime to sleep this task.
+    /// work, by returning Poll::Pending to signal to runtime to sleep this task.
     ///
     /// #4 Err(TryRecvError::Disconnected): Ingressor is in teardown and has dropped its side of the
-    /// from_ingressor channel; we will no longer receive packets. Return Async::Ready(None) to forward
+    /// from_ingressor channel; we will no longer receive packets. Return Poll::Ready(None) to forward
     /// propagate teardown.
     /// ###
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
         match self.from_ingressor.try_recv() {
             Ok(Some(packet)) => {
+                self.counter.dequeued(1);
+                self.counter.sent(&packet);
                 unpark_and_wake(&self.task_park);
                 Poll::Ready(Some(packet))
             }
@@ -269,6 +526,21 @@ mod tests {
     use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
     use core::time;
     use rand::{thread_rng, Rng};
+    use tokio::time::sleep;
+
+    /// Lets the ingressor take all of its input before anything is drained, so the queue fills.
+    async fn run_overloaded(link: Link<i32>) -> Vec<i32> {
+        let (mut runnables, mut egressors) = link;
+        tokio::spawn(runnables.remove(0));
+        sleep(time::Duration::from_millis(50)).await;
+
+        let mut egressor = egressors.remove(0);
+        let mut output = vec![];
+        while let Some(packet) = egressor.next().await {
+            output.push(packet);
+        }
+        output
+    }
 
     #[test]
     #[should_panic]
@@ -305,7 +577,7 @@ mod tests {
     fn queue_link_works() {
         let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let link = QueueLink::new()
                 .ingressor(immediate_stream(packets.clone()))
@@ -321,7 +593,7 @@ mod tests {
         let mut rng = thread_rng();
         let stream_len = rng.gen_range(2000, 4000);
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let link = QueueLink::new()
                 .ingressor(immediate_stream(0..stream_len))
@@ -349,7 +621,7 @@ mod tests {
     fn small_channel() {
         let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let link = QueueLink::new()
                 .ingressor(immediate_stream(packets.clone()))
@@ -364,7 +636,7 @@ mod tests {
 
     #[test]
     fn empty_stream() {
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let packets: Vec<i32> = vec![];
 
@@ -382,7 +654,7 @@ mod tests {
     fn two_links() {
         let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let (mut runnables0, mut egressors0) = QueueLink::new()
                 .ingressor(immediate_stream(packets.clone()))
@@ -406,7 +678,7 @@ mod tests {
     fn series_of_process_and_queue_links() {
         let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let (_, mut egressors0) = ProcessLink::new()
                 .ingressor(immediate_stream(packets.clone()))
@@ -444,7 +716,7 @@ mod tests {
     fn wait_between_packets() {
         let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let packet_generator = PacketIntervalGenerator::new(
                 time::Duration::from_millis(10),
@@ -465,7 +737,7 @@ mod tests {
     fn transform_processor() {
         let packets = "route-rs".chars();
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let link = QueueLink::new()
                 .ingressor(immediate_stream(packets.clone()))
@@ -483,7 +755,7 @@ mod tests {
     fn drop_processor() {
         let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
 
-        let mut runtime = initialize_runtime();
+        let runtime = initialize_runtime();
         let results = runtime.block_on(async {
             let link = QueueLink::new()
                 .ingressor(immediate_stream(packets))
@@ -494,4 +766,121 @@ mod tests {
         });
         assert_eq!(results[0], [])
     }
+
+    #[test]
+    fn tail_drop() {
+        let runtime = initialize_runtime();
+        let link = QueueLink::new()
+            .ingressor(immediate_stream(0..20))
+            .processor(Identity::new())
+            .queue_capacity(6)
+            .drop_policy(DropPolicy::TailDrop);
+        let (stats, drop_stats) = (link.stats(), link.drop_stats());
+
+        let results = runtime.block_on(run_overloaded(link.build_link()));
+        assert_eq!(results, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(drop_stats.enqueued(), 6);
+        assert_eq!(drop_stats.tail_dropped(), 14);
+        assert_eq!(stats.dropped(), 14);
+    }
+
+    #[test]
+    fn head_drop() {
+        let runtime = initialize_runtime();
+        let link = QueueLink::new()
+            .ingressor(immediate_stream(0..20))
+            .processor(Identity::new())
+            .queue_capacity(6)
+            .drop_policy(DropPolicy::HeadDrop);
+        let stats = link.drop_stats();
+
+        let results = runtime.block_on(run_overloaded(link.build_link()));
+        assert_eq!(results, vec![14, 15, 16, 17, 18, 19]);
+        assert_eq!(stats.enqueued(), 20);
+        assert_eq!(stats.head_dropped(), 14);
+    }
+
+    #[test]
+    fn replace_oldest() {
+        let runtime = initialize_runtime();
+        let link = QueueLink::new()
+            .ingressor(immediate_stream(0..20))
+            .processor(Identity::new())
+            .queue_capacity(6)
+            .drop_policy(DropPolicy::ReplaceOldest);
+        let stats = link.drop_stats();
+
+        let results = runtime.block_on(run_overloaded(link.build_link()));
+        assert_eq!(results, vec![19, 1, 2, 3, 4, 5]);
+        assert_eq!(stats.enqueued(), 20);
+        assert_eq!(stats.head_dropped(), 14);
+    }
+
+    #[test]
+    #[cfg(feature = "metrics")]
+    fn metrics() {
+        let runtime = initialize_runtime();
+        let link = QueueLink::new()
+            .ingressor(immediate_stream(0..20))
+            .processor(Identity::new())
+            .queue_capacity(6)
+            .drop_policy(DropPolicy::HeadDrop)
+            .bytes(Box::new(|_| 100));
+        let stats = link.stats();
+
+        let results = runtime.block_on(run_overloaded(link.build_link()));
+        assert_eq!(results.len(), 6);
+        assert_eq!((stats.packets_in(), stats.packets_out()), (20, 6));
+        assert_eq!(stats.bytes_out(), 600);
+        assert_eq!(stats.dropped(), 14);
+        assert_eq!((stats.queue_depth(), stats.queue_high_water()), (0, 6));
+    }
+
+    #[test]
+    fn counts_processor_drops() {
+        let runtime = initialize_runtime();
+        let link = QueueLink::new()
+            .ingressor(immediate_stream(0..20))
+            .processor(Drop::new());
+        let (stats, drop_stats) = (link.stats(), link.drop_stats());
+
+        let results = runtime.block_on(run_link(link.build_link()));
+        assert_eq!(results[0], []);
+        assert_eq!(drop_stats.enqueued(), 0);
+        assert_eq!(stats.dropped(), 20);
+    }
+
+    /// Replaces each packet with the size of the batch it was processed in.
+    struct BatchSize {}
+
+    impl Processor for BatchSize {
+        type Input = i32;
+        type Output = usize;
+
+        fn process(&mut self, _packet: i32) -> Option<usize> {
+            Some(1)
+        }
+
+        fn process_batch(&mut self, packets: &mut Vec<i32>) -> Vec<usize> {
+            let batch_size = packets.len();
+            packets.drain(..).map(|_| batch_size).collect()
+        }
+    }
+
+    #[test]
+    fn processes_ready_packets_in_batches() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = QueueLink::new()
+                .ingressor(immediate_stream(0..40))
+                .processor(BatchSize {})
+                .queue_capacity(10)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0].len(), 40);
+        assert_eq!(results[0][0], 10);
+        assert!(results[0].iter().all(|batch_size| *batch_size <= 10));
+    }
 }
@@ -0,0 +1,374 @@
+use crate::link::primitive::QueueEgressor;
+use crate::link::utils::red::RedCurve;
+use crate::link::utils::task_park::*;
+use crate::link::{Link, LinkBuilder, PacketStream};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::Sender;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Maps a packet to the index of the class curve that applies to it, or `None` for the default
+/// curve.
+pub type RedClassifier<Packet> = Box<dyn Fn(&Packet) -> Option<usize> + Send + Sync + 'static>;
+
+/// A queue that starts shedding load before it is full, with Random Early Detection. An average of
+/// the queue depth is kept, and each arriving packet is dropped with a probability that rises from 0
+/// at `min_threshold` to `max_probability` at `max_threshold`, above which every packet is dropped.
+///
+/// Optionally, a `classifier` closure can pick a different curve per class of traffic from
+/// `class_curves`, making this Weighted RED. For classification with a `Classifier`, see
+/// `WeightedRedLink`.
+#[derive(Default)]
+pub struct RandomEarlyDetectionLink<Packet> {
+    in_stream: Option<PacketStream<Packet>>,
+    curve: Option<RedCurve>,
+    classifier: Option<RedClassifier<Packet>>,
+    class_curves: Vec<RedCurve>,
+    weight: f64,
+    queue_capacity: usize,
+}
+
+impl<Packet> RandomEarlyDetectionLink<Packet> {
+    pub fn new() -> Self {
+        RandomEarlyDetectionLink {
+            in_stream: None,
+            curve: None,
+            classifier: None,
+            class_curves: vec![],
+            weight: 1.0,
+            queue_capacity: 10,
+        }
+    }
+
+    /// The default drop curve, used for every packet that is not given a class curve.
+    pub fn curve(self, min_threshold: usize, max_threshold: usize, max_probability: f64) -> Self {
+        RandomEarlyDetectionLink {
+            in_stream: self.in_stream,
+            curve: Some(RedCurve::new(min_threshold, max_threshold, max_probability)),
+            classifier: self.classifier,
+            class_curves: self.class_curves,
+            weight: self.weight,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    pub fn classifier(self, classifier: RedClassifier<Packet>) -> Self {
+        RandomEarlyDetectionLink {
+            in_stream: self.in_stream,
+            curve: self.curve,
+            classifier: Some(classifier),
+            class_curves: self.class_curves,
+            weight: self.weight,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Curves picked by the `classifier`, indexed by class.
+    pub fn class_curves(self, class_curves: Vec<RedCurve>) -> Self {
+        RandomEarlyDetectionLink {
+            in_stream: self.in_stream,
+            curve: self.curve,
+            classifier: self.classifier,
+            class_curves,
+            weight: self.weight,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Changes weight, how strongly each new sample of the queue depth moves the average, default
+    /// value is 1.0, ie no averaging. Lower values let short bursts through without drops.
+    pub fn weight(self, weight: f64) -> Self {
+        assert!(
+            weight > 0.0 && weight <= 1.0,
+            "RandomEarlyDetectionLink weight: {}, must be within (0, 1]",
+            weight
+        );
+
+        RandomEarlyDetectionLink {
+            in_stream: self.in_stream,
+            curve: self.curve,
+            classifier: self.classifier,
+            class_curves: self.class_curves,
+            weight,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Changes queue_capacity, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "RandomEarlyDetectionLink queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        RandomEarlyDetectionLink {
+            in_stream: self.in_stream,
+            curve: self.curve,
+            classifier: self.classifier,
+            class_curves: self.class_curves,
+            weight: self.weight,
+            queue_capacity,
+        }
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Packet, Packet> for RandomEarlyDetectionLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "RandomEarlyDetectionLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("RandomEarlyDetectionLink may only take 1 input stream")
+        }
+
+        RandomEarlyDetectionLink {
+            in_stream: Some(in_streams.remove(0)),
+            curve: self.curve,
+            classifier: self.classifier,
+            class_curves: self.class_curves,
+            weight: self.weight,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("RandomEarlyDetectionLink may only take 1 input stream")
+        }
+
+        RandomEarlyDetectionLink {
+            in_stream: Some(in_stream),
+            curve: self.curve,
+            classifier: self.classifier,
+            class_curves: self.class_curves,
+            weight: self.weight,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match (self.in_stream, self.curve) {
+            (None, _) => panic!("Cannot build link! Missing input stream"),
+            (_, None) => panic!("Cannot build link! Missing curve"),
+            (Some(in_stream), Some(curve)) => {
+                if self.classifier.is_some() && self.class_curves.is_empty() {
+                    panic!("Cannot build link! Missing class_curves for classifier");
+                }
+
+                let (to_egressor, from_ingressor) =
+                    crossbeam_channel::bounded::<Option<Packet>>(self.queue_capacity);
+                let task_park: Arc<AtomicCell<TaskParkState>> =
+                    Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+                let ingressor = RedIngressor {
+                    input_stream: in_stream,
+                    to_egressor,
+                    task_park: Arc::clone(&task_park),
+                    curve,
+                    classifier: self.classifier,
+                    class_curves: self.class_curves,
+                    weight: self.weight,
+                    average_queue_len: 0.0,
+                };
+                let egressor = QueueEgressor::new(from_ingressor, task_park);
+
+                (vec![Box::new(ingressor)], vec![Box::new(egressor)])
+            }
+        }
+    }
+}
+
+struct RedIngressor<Packet> {
+    input_stream: PacketStream<Packet>,
+    to_egressor: Sender<Option<Packet>>,
+    task_park: Arc<AtomicCell<TaskParkState>>,
+    curve: RedCurve,
+    classifier: Option<RedClassifier<Packet>>,
+    class_curves: Vec<RedCurve>,
+    weight: f64,
+    average_queue_len: f64,
+}
+
+impl<Packet> Unpin for RedIngressor<Packet> {}
+
+impl<Packet> RedIngressor<Packet> {
+    fn curve_for(&self, packet: &Packet) -> &RedCurve {
+        match &self.classifier {
+            Some(classifier) => match classifier(packet) {
+                Some(class) => match self.class_curves.get(class) {
+                    Some(curve) => curve,
+                    None => panic!("Tried to access invalid class curve: {}", class),
+                },
+                None => &self.curve,
+            },
+            None => &self.curve,
+        }
+    }
+}
+
+impl<Packet> Future for RedIngressor<Packet> {
+    type Output = ();
+
+    /// Same logic as QueueIngressor, except that as each packet arrives the average queue depth is
+    /// updated, and the packet's curve decides whether it is dropped. If the curves allow the queue
+    /// to fill completely, we fall back to parking until the egressor makes room.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let ingressor = Pin::into_inner(self);
+        loop {
+            if ingressor.to_egressor.is_full() {
+                park_and_wake(&ingressor.task_park, cx.waker().clone());
+                return Poll::Pending;
+            }
+
+            let packet = match ready!(Pin::new(&mut ingressor.input_stream).poll_next(cx)) {
+                Some(packet) => packet,
+                None => {
+                    ingressor.to_egressor.try_send(None).expect(
+                        "RedIngressor::Poll::Ready(None) try_send to_egressor shouldn't fail",
+                    );
+                    die_and_wake(&ingressor.task_park);
+                    return Poll::Ready(());
+                }
+            };
+
+            let queue_len = ingressor.to_egressor.len() as f64;
+            ingressor.average_queue_len +=
+                ingressor.weight * (queue_len - ingressor.average_queue_len);
+            if ingressor
+                .curve_for(&packet)
+                .should_drop(ingressor.average_queue_len)
+            {
+                continue;
+            }
+
+            ingressor
+                .to_egressor
+                .try_send(Some(packet))
+                .expect("RedIngressor::Poll::Ready(Some(val)) try_send to_egressor shouldn't fail");
+            unpark_and_wake(&ingressor.task_park);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::initialize_runtime;
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    /// Runs the ingressor to completion before anything is drained, so the queue fills up.
+    async fn run_congested(link: Link<i32>) -> Vec<i32> {
+        let (mut runnables, mut egressors) = link;
+        tokio::spawn(runnables.remove(0)).await.unwrap();
+
+        let mut egressor = egressors.remove(0);
+        let mut output = vec![];
+        while let Some(packet) = egressor.next().await {
+            output.push(packet);
+        }
+        output
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_curve() {
+        RandomEarlyDetectionLink::<i32>::new()
+            .ingressor(immediate_stream(vec![]))
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_with_classifier_without_class_curves() {
+        RandomEarlyDetectionLink::<i32>::new()
+            .ingressor(immediate_stream(vec![]))
+            .curve(5, 10, 0.1)
+            .classifier(Box::new(|_| Some(0)))
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_with_inverted_thresholds() {
+        RandomEarlyDetectionLink::<i32>::new().curve(10, 5, 0.1);
+    }
+
+    #[test]
+    fn no_drops_below_min_threshold() {
+        let packets: Vec<i32> = (0..10).collect();
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = RandomEarlyDetectionLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .curve(10, 20, 0.5)
+                .queue_capacity(50)
+                .build_link();
+
+            run_congested(link).await
+        });
+        assert_eq!(results, packets);
+    }
+
+    #[test]
+    fn sheds_load_before_full() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = RandomEarlyDetectionLink::new()
+                .ingressor(immediate_stream(0..1000))
+                .curve(10, 30, 0.2)
+                .queue_capacity(50)
+                .build_link();
+
+            run_congested(link).await
+        });
+        assert!(results.len() >= 10 && results.len() <= 30);
+        assert_eq!(results[..10], (0..10).collect::<Vec<i32>>()[..]);
+    }
+
+    #[test]
+    fn averaging_absorbs_bursts() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = RandomEarlyDetectionLink::new()
+                .ingressor(immediate_stream(0..40))
+                .curve(10, 30, 0.2)
+                .weight(0.01)
+                .queue_capacity(50)
+                .build_link();
+
+            run_congested(link).await
+        });
+        assert_eq!(results, (0..40).collect::<Vec<i32>>());
+    }
+
+    #[test]
+    fn class_curves_weight_drops() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            // Multiples of 3 are control traffic, with a more lenient curve.
+            let link = RandomEarlyDetectionLink::new()
+                .ingressor(immediate_stream(0..3000))
+                .curve(5, 20, 0.5)
+                .classifier(Box::new(
+                    |packet| if packet % 3 == 0 { Some(0) } else { None },
+                ))
+                .class_curves(vec![RedCurve::new(20, 60, 0.1)])
+                .queue_capacity(100)
+                .build_link();
+
+            run_congested(link).await
+        });
+        let control = results.iter().filter(|packet| *packet % 3 == 0).count();
+        let bulk = results.len() - control;
+        assert!(bulk <= 20);
+        assert!(control > bulk, "control: {}, bulk: {}", control, bulk);
+    }
+}
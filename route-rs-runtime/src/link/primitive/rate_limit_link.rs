@@ -0,0 +1,316 @@
+use crate::link::primitive::QueueEgressor;
+use crate::link::utils::task_park::*;
+use crate::link::utils::token_bucket::TokenBucket;
+use crate::link::{Link, LinkBuilder, PacketStream};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::Sender;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::time::{sleep_until, Sleep};
+
+/// Shapes traffic to at most `tokens_per_second` packets per second, allowing bursts of up to
+/// `burst` packets after a quiet period. Packets wait in the link until a token is available, so
+/// nothing is dropped; upstream is backpressured instead.
+#[derive(Default)]
+pub struct RateLimitLink<Packet> {
+    in_stream: Option<PacketStream<Packet>>,
+    tokens_per_second: Option<u64>,
+    burst: usize,
+    queue_capacity: usize,
+}
+
+impl<Packet> RateLimitLink<Packet> {
+    pub fn new() -> Self {
+        RateLimitLink {
+            in_stream: None,
+            tokens_per_second: None,
+            burst: 1,
+            queue_capacity: 10,
+        }
+    }
+
+    pub fn tokens_per_second(self, tokens_per_second: u64) -> Self {
+        assert!(
+            tokens_per_second > 0,
+            "RateLimitLink tokens_per_second: {}, must be > 0",
+            tokens_per_second
+        );
+
+        RateLimitLink {
+            in_stream: self.in_stream,
+            tokens_per_second: Some(tokens_per_second),
+            burst: self.burst,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Changes burst, the most tokens that may build up while the link is idle, default value is 1.
+    pub fn burst(self, burst: usize) -> Self {
+        assert!(burst > 0, "RateLimitLink burst: {}, must be > 0", burst);
+
+        RateLimitLink {
+            in_stream: self.in_stream,
+            tokens_per_second: self.tokens_per_second,
+            burst,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Changes queue_capacity, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "RateLimitLink queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        RateLimitLink {
+            in_stream: self.in_stream,
+            tokens_per_second: self.tokens_per_second,
+            burst: self.burst,
+            queue_capacity,
+        }
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Packet, Packet> for RateLimitLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "RateLimitLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("RateLimitLink may only take 1 input stream")
+        }
+
+        RateLimitLink {
+            in_stream: Some(in_streams.remove(0)),
+            tokens_per_second: self.tokens_per_second,
+            burst: self.burst,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("RateLimitLink may only take 1 input stream")
+        }
+
+        RateLimitLink {
+            in_stream: Some(in_stream),
+            tokens_per_second: self.tokens_per_second,
+            burst: self.burst,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match (self.in_stream, self.tokens_per_second) {
+            (None, _) => panic!("Cannot build link! Missing input stream"),
+            (_, None) => panic!("Cannot build link! Missing tokens_per_second"),
+            (Some(in_stream), Some(tokens_per_second)) => {
+                let (to_egressor, from_ingressor) =
+                    crossbeam_channel::bounded::<Option<Packet>>(self.queue_capacity);
+                let task_park: Arc<AtomicCell<TaskParkState>> =
+                    Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+                let ingressor = RateLimitIngressor {
+                    input_stream: in_stream,
+                    to_egressor,
+                    task_park: Arc::clone(&task_park),
+                    bucket: TokenBucket::new(tokens_per_second, self.burst as u64),
+                    held_packet: None,
+                    refill_timer: None,
+                };
+                let egressor = QueueEgressor::new(from_ingressor, task_park);
+
+                (vec![Box::new(ingressor)], vec![Box::new(egressor)])
+            }
+        }
+    }
+}
+
+struct RateLimitIngressor<Packet> {
+    input_stream: PacketStream<Packet>,
+    to_egressor: Sender<Option<Packet>>,
+    task_park: Arc<AtomicCell<TaskParkState>>,
+    bucket: TokenBucket,
+    /// A packet pulled from upstream that is waiting on a token.
+    held_packet: Option<Packet>,
+    refill_timer: Option<Pin<Box<Sleep>>>,
+}
+
+impl<Packet> Unpin for RateLimitIngressor<Packet> {}
+
+impl<Packet> Future for RateLimitIngressor<Packet> {
+    type Output = ();
+
+    /// Pulls packets from upstream and forwards each one once it can take a token from the bucket.
+    /// There are several cases where we can not make progress:
+    /// ###
+    /// #1 The to_egressor queue is full, we park and rely on the egressor to wake us.
+    ///
+    /// #2 The bucket is empty. We hold on to the packet and set a timer for when the next token
+    /// accrues, the timer wakes us.
+    ///
+    /// #3 The input_stream is `Pending`, we sleep and rely on the upstream to wake us.
+    ///
+    /// #4 We get a Ready(None), in which case we push a None onto the to_egressor queue and enter
+    /// tear-down.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let ingressor = Pin::into_inner(self);
+        loop {
+            if ingressor.to_egressor.is_full() {
+                park_and_wake(&ingressor.task_park, cx.waker().clone());
+                return Poll::Pending;
+            }
+
+            let packet = match ingressor.held_packet.take() {
+                Some(packet) => packet,
+                None => match ready!(Pin::new(&mut ingressor.input_stream).poll_next(cx)) {
+                    Some(packet) => packet,
+                    None => {
+                        ingressor.to_egressor.try_send(None).expect(
+                            "RateLimitIngressor::Poll::Ready(None) try_send to_egressor shouldn't fail",
+                        );
+                        die_and_wake(&ingressor.task_park);
+                        return Poll::Ready(());
+                    }
+                },
+            };
+
+            let now = Instant::now();
+            if !ingressor.bucket.try_consume(now, 1) {
+                ingressor.held_packet = Some(packet);
+                let ready_at = now + ingressor.bucket.time_until(now, 1);
+                let timer = ingressor
+                    .refill_timer
+                    .get_or_insert_with(|| Box::pin(sleep_until(ready_at.into())));
+                timer.as_mut().reset(ready_at.into());
+                match timer.as_mut().poll(cx) {
+                    Poll::Ready(()) => continue,
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+
+            ingressor.to_egressor.try_send(Some(packet)).expect(
+                "RateLimitIngressor::Poll::Ready(Some(val)) try_send to_egressor shouldn't fail",
+            );
+            unpark_and_wake(&ingressor.task_park);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use tokio::time::{timeout, Duration};
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_ingressor() {
+        RateLimitLink::<()>::new()
+            .tokens_per_second(100)
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_tokens_per_second() {
+        RateLimitLink::<()>::new()
+            .ingressor(immediate_stream(vec![]))
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_with_zero_tokens_per_second() {
+        RateLimitLink::<()>::new().tokens_per_second(0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_with_zero_burst() {
+        RateLimitLink::<()>::new().burst(0);
+    }
+
+    #[test]
+    fn passes_all_packets_in_order() {
+        let packets: Vec<i32> = (0..50).collect();
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = RateLimitLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .tokens_per_second(1000)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], packets);
+    }
+
+    #[test]
+    fn limits_to_rate() {
+        let runtime = initialize_runtime();
+        let forwarded = runtime.block_on(async {
+            let (runnables, mut egressors) = RateLimitLink::new()
+                .ingressor(immediate_stream(0..1000))
+                .tokens_per_second(100)
+                .build_link();
+            for runnable in runnables {
+                tokio::spawn(runnable);
+            }
+
+            let mut egressor = egressors.remove(0);
+            let mut forwarded = 0;
+            let _ = timeout(Duration::from_secs(1), async {
+                while egressor.next().await.is_some() {
+                    forwarded += 1;
+                }
+            })
+            .await;
+            forwarded
+        });
+        assert!(
+            (90..=110).contains(&forwarded),
+            "forwarded {} packets in one second",
+            forwarded
+        );
+    }
+
+    #[test]
+    fn burst_passes_immediately() {
+        let runtime = initialize_runtime();
+        let forwarded = runtime.block_on(async {
+            let (runnables, mut egressors) = RateLimitLink::new()
+                .ingressor(immediate_stream(0..1000))
+                .tokens_per_second(1)
+                .burst(20)
+                .queue_capacity(50)
+                .build_link();
+            for runnable in runnables {
+                tokio::spawn(runnable);
+            }
+
+            let mut egressor = egressors.remove(0);
+            let mut forwarded = 0;
+            let _ = timeout(Duration::from_millis(200), async {
+                while egressor.next().await.is_some() {
+                    forwarded += 1;
+                }
+            })
+            .await;
+            forwarded
+        });
+        assert_eq!(forwarded, 20);
+    }
+}
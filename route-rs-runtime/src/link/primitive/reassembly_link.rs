@@ -0,0 +1,558 @@
+use crate::link::{Link, LinkBuilder, PacketStream};
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use route_rs_packets::{Ipv4Packet, Ipv6Packet};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::hash::Hash;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+/// The largest IP datagram, or IPv6 payload, a fragment may extend to.
+const MAX_DATAGRAM_LEN: usize = 65535;
+/// IPv6 extension headers that come before the Fragment header.
+const HOP_BY_HOP: u8 = 0;
+const ROUTING: u8 = 43;
+const DESTINATION_OPTIONS: u8 = 60;
+const FRAGMENT: u8 = 44;
+
+/// Where a fragment fits in its datagram.
+pub struct FragmentInfo<Key> {
+    /// Identifies the datagram the fragment belongs to.
+    pub key: Key,
+    /// Where the fragment's data goes in the datagram's payload, in bytes.
+    pub offset: usize,
+    /// Whether more fragments follow this one.
+    pub more_fragments: bool,
+    /// The fragment's part of the datagram's payload.
+    pub data: Vec<u8>,
+}
+
+/// IP packets that ReassemblyLink can reassemble.
+pub trait IpFragment: Sized {
+    type Key: Hash + Eq + Clone + Send;
+
+    /// Returns where the packet fits in its datagram, or None if it isn't a fragment.
+    fn fragment_info(&self) -> Option<FragmentInfo<Self::Key>>;
+
+    /// Builds the datagram from the headers of its `first` fragment, and its whole `payload`.
+    fn reassemble(first: &Self, payload: &[u8]) -> Option<Self>;
+}
+
+impl IpFragment for Ipv4Packet {
+    /// Source, destination, identification and protocol.
+    type Key = (Ipv4Addr, Ipv4Addr, u16, u8);
+
+    fn fragment_info(&self) -> Option<FragmentInfo<Self::Key>> {
+        let (_, more_fragments) = self.flags();
+        if self.fragment_offset() == 0 && !more_fragments {
+            return None;
+        }
+
+        Some(FragmentInfo {
+            key: (
+                self.src_addr(),
+                self.dest_addr(),
+                self.indentification(),
+                self.data[self.layer3_offset + 9],
+            ),
+            offset: usize::from(self.fragment_offset()) * 8,
+            more_fragments,
+            data: self.data[self.payload_offset..].to_vec(),
+        })
+    }
+
+    fn reassemble(first: &Self, payload: &[u8]) -> Option<Self> {
+        let total_len = first.payload_offset - first.layer3_offset + payload.len();
+        if total_len > MAX_DATAGRAM_LEN {
+            return None;
+        }
+
+        let mut data = Vec::with_capacity(first.payload_offset + payload.len());
+        data.extend_from_slice(&first.data[..first.payload_offset]);
+        data.extend_from_slice(payload);
+        let layer3_offset = first.layer3_offset;
+        data[layer3_offset + 2..layer3_offset + 4]
+            .copy_from_slice(&(total_len as u16).to_be_bytes());
+
+        let mut packet =
+            Ipv4Packet::from_buffer(data.into(), first.layer2_offset, layer3_offset).ok()?;
+        let (dont_fragment, _) = first.flags();
+        packet.set_flags(dont_fragment, false);
+        packet.set_fragment_offset(0);
+        packet.set_checksum();
+        Some(packet)
+    }
+}
+
+/// Finds the IPv6 Fragment header, returning the offset of the next header field naming it, and
+/// of the header itself.
+fn ipv6_fragment_header(packet: &Ipv6Packet) -> Option<(usize, usize)> {
+    let mut next_header_at = packet.layer3_offset + 6;
+    let mut offset = packet.layer3_offset + 40;
+    loop {
+        match *packet.data.get(next_header_at)? {
+            FRAGMENT if packet.data.len() >= offset + 8 => return Some((next_header_at, offset)),
+            HOP_BY_HOP | ROUTING | DESTINATION_OPTIONS => {
+                let length = (usize::from(*packet.data.get(offset + 1)?) + 1) * 8;
+                next_header_at = offset;
+                offset += length;
+            }
+            _ => return None,
+        }
+    }
+}
+
+impl IpFragment for Ipv6Packet {
+    /// Source, destination, identification and the protocol after the Fragment header.
+    type Key = (Ipv6Addr, Ipv6Addr, u32, u8);
+
+    fn fragment_info(&self) -> Option<FragmentInfo<Self::Key>> {
+        let (_, header) = ipv6_fragment_header(self)?;
+        let offset_and_flags = u16::from_be_bytes([self.data[header + 2], self.data[header + 3]]);
+        let identification = u32::from_be_bytes([
+            self.data[header + 4],
+            self.data[header + 5],
+            self.data[header + 6],
+            self.data[header + 7],
+        ]);
+
+        Some(FragmentInfo {
+            key: (
+                self.src_addr(),
+                self.dest_addr(),
+                identification,
+                self.data[header],
+            ),
+            offset: usize::from(offset_and_flags >> 3) * 8,
+            more_fragments: offset_and_flags & 1 != 0,
+            data: self.data[header + 8..].to_vec(),
+        })
+    }
+
+    fn reassemble(first: &Self, payload: &[u8]) -> Option<Self> {
+        let (next_header_at, header) = ipv6_fragment_header(first)?;
+        let payload_len = header - first.layer3_offset - 40 + payload.len();
+        if payload_len > MAX_DATAGRAM_LEN {
+            return None;
+        }
+
+        // The headers before the Fragment header, which now names what followed it.
+        let mut data = Vec::with_capacity(header + payload.len());
+        data.extend_from_slice(&first.data[..header]);
+        data[next_header_at] = first.data[header];
+        data.extend_from_slice(payload);
+        let layer3_offset = first.layer3_offset;
+        data[layer3_offset + 4..layer3_offset + 6]
+            .copy_from_slice(&(payload_len as u16).to_be_bytes());
+
+        Ipv6Packet::from_buffer(data.into(), first.layer2_offset, layer3_offset).ok()
+    }
+}
+
+/// Reassembles IPv4 or IPv6 fragments into whole datagrams, for processing that needs to see
+/// past the IP header, like finding the ports for a firewall or NAT. Packets that aren't
+/// fragments pass straight through. Datagrams still missing fragments after `timeout`, 30
+/// seconds by default, are discarded, as are the oldest datagrams when those waiting take more
+/// than `memory_limit` bytes, 4 MiB by default. Fragments that overlap are an attack on
+/// reassembly, so their datagrams are discarded too. Like ProcessLink, this link only does work
+/// when it is polled, so expired datagrams are only discarded as more fragments arrive.
+pub struct ReassemblyLink<Packet> {
+    in_stream: Option<PacketStream<Packet>>,
+    timeout: Duration,
+    memory_limit: usize,
+}
+
+impl<Packet> Default for ReassemblyLink<Packet> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Packet> ReassemblyLink<Packet> {
+    pub fn new() -> Self {
+        ReassemblyLink {
+            in_stream: None,
+            timeout: Duration::from_secs(30),
+            memory_limit: 4 * 1024 * 1024,
+        }
+    }
+
+    /// How long a datagram may wait for its missing fragments.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        assert!(
+            timeout > Duration::from_secs(0),
+            "ReassemblyLink timeout: {:?}, must be > 0",
+            timeout
+        );
+
+        ReassemblyLink {
+            in_stream: self.in_stream,
+            timeout,
+            memory_limit: self.memory_limit,
+        }
+    }
+
+    /// How many bytes of fragments may wait for reassembly at once.
+    pub fn memory_limit(self, memory_limit: usize) -> Self {
+        assert!(
+            memory_limit > 0,
+            "ReassemblyLink memory_limit: {}, must be > 0",
+            memory_limit
+        );
+
+        ReassemblyLink {
+            in_stream: self.in_stream,
+            timeout: self.timeout,
+            memory_limit,
+        }
+    }
+}
+
+impl<Packet: IpFragment + Send + 'static> LinkBuilder<Packet, Packet> for ReassemblyLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "ReassemblyLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("ReassemblyLink may only take 1 input stream")
+        }
+
+        ReassemblyLink {
+            in_stream: Some(in_streams.remove(0)),
+            timeout: self.timeout,
+            memory_limit: self.memory_limit,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("ReassemblyLink may only take 1 input stream")
+        }
+
+        ReassemblyLink {
+            in_stream: Some(in_stream),
+            timeout: self.timeout,
+            memory_limit: self.memory_limit,
+        }
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match self.in_stream {
+            None => panic!("Cannot build link! Missing input streams"),
+            Some(in_stream) => {
+                let reassembler = Reassembler {
+                    in_stream,
+                    timeout: self.timeout,
+                    memory_limit: self.memory_limit,
+                    memory_used: 0,
+                    datagrams: HashMap::new(),
+                    arrivals: VecDeque::new(),
+                };
+                (vec![], vec![Box::new(reassembler)])
+            }
+        }
+    }
+}
+
+/// The fragments of a datagram received so far.
+struct PartialDatagram<Packet> {
+    started: Instant,
+    /// The fragment at offset 0, whose headers the datagram gets.
+    first: Option<Packet>,
+    /// Fragment data by offset.
+    pieces: BTreeMap<usize, Vec<u8>>,
+    /// The payload length, known once the last fragment arrives.
+    len: Option<usize>,
+    bytes: usize,
+}
+
+/// The single egressor of ReassemblyLink
+struct Reassembler<Packet: IpFragment> {
+    in_stream: PacketStream<Packet>,
+    timeout: Duration,
+    memory_limit: usize,
+    memory_used: usize,
+    datagrams: HashMap<Packet::Key, PartialDatagram<Packet>>,
+    /// Datagrams in the order they started, so the oldest can be discarded first.
+    arrivals: VecDeque<(Instant, Packet::Key)>,
+}
+
+impl<Packet: IpFragment> Unpin for Reassembler<Packet> {}
+
+impl<Packet: IpFragment> Reassembler<Packet> {
+    /// Discards the oldest datagram, if it started at `started`; otherwise it was already
+    /// reassembled or discarded.
+    fn discard(&mut self, started: Instant, key: &Packet::Key) {
+        if let Some(datagram) = self.datagrams.get(key) {
+            if datagram.started == started {
+                self.memory_used -= datagram.bytes;
+                self.datagrams.remove(key);
+            }
+        }
+    }
+
+    fn discard_expired(&mut self, now: Instant) {
+        while let Some((started, _)) = self.arrivals.front() {
+            if now.saturating_duration_since(*started) < self.timeout {
+                break;
+            }
+            if let Some((started, key)) = self.arrivals.pop_front() {
+                self.discard(started, &key);
+            }
+        }
+    }
+
+    /// Adds a fragment, returning its datagram if that completes it, or the packet itself if it
+    /// isn't a fragment.
+    fn add(&mut self, packet: Packet, now: Instant) -> Option<Packet> {
+        let info = match packet.fragment_info() {
+            Some(info) => info,
+            None => return Some(packet),
+        };
+        self.discard_expired(now);
+
+        let len = info.data.len();
+        let end = info.offset + len;
+        // Only the last fragment may have a length that isn't a multiple of 8.
+        if end > MAX_DATAGRAM_LEN || (info.more_fragments && len % 8 != 0) {
+            return None;
+        }
+
+        let arrivals = &mut self.arrivals;
+        let datagram = self.datagrams.entry(info.key.clone()).or_insert_with(|| {
+            arrivals.push_back((now, info.key.clone()));
+            PartialDatagram {
+                started: now,
+                first: None,
+                pieces: BTreeMap::new(),
+                len: None,
+                bytes: 0,
+            }
+        });
+
+        let overlaps = match datagram.pieces.range(..end).next_back() {
+            Some((offset, piece)) => offset + piece.len() > info.offset,
+            None => false,
+        };
+        let conflicting_len = match (datagram.len, datagram.pieces.iter().next_back()) {
+            (Some(datagram_len), _) => {
+                end > datagram_len || (!info.more_fragments && end != datagram_len)
+            }
+            (None, Some((offset, piece))) => !info.more_fragments && offset + piece.len() > end,
+            (None, None) => false,
+        };
+        if overlaps || conflicting_len {
+            self.memory_used -= datagram.bytes;
+            self.datagrams.remove(&info.key);
+            return None;
+        }
+
+        datagram.pieces.insert(info.offset, info.data);
+        datagram.bytes += len;
+        self.memory_used += len;
+        if !info.more_fragments {
+            datagram.len = Some(end);
+        }
+        if info.offset == 0 {
+            datagram.first = Some(packet);
+        }
+
+        let complete = match (&datagram.first, datagram.len) {
+            (Some(_), Some(datagram_len)) => datagram.bytes == datagram_len,
+            _ => false,
+        };
+        if complete {
+            let datagram = self.datagrams.remove(&info.key).unwrap();
+            self.memory_used -= datagram.bytes;
+            let payload: Vec<u8> = datagram.pieces.values().flatten().copied().collect();
+            return Packet::reassemble(datagram.first.as_ref().unwrap(), &payload);
+        }
+
+        while self.memory_used > self.memory_limit {
+            match self.arrivals.pop_front() {
+                Some((started, key)) => self.discard(started, &key),
+                None => break,
+            }
+        }
+        None
+    }
+}
+
+impl<Packet: IpFragment> Stream for Reassembler<Packet> {
+    type Item = Packet;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let reassembler = Pin::into_inner(self);
+        loop {
+            match ready!(Pin::new(&mut reassembler.in_stream).poll_next(cx)) {
+                None => return Poll::Ready(None),
+                Some(packet) => {
+                    if let Some(packet) = reassembler.add(packet, Instant::now()) {
+                        return Poll::Ready(Some(packet));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{Fragment, Processor};
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    fn ipv4_packet(identification: u16, payload_len: usize) -> Ipv4Packet {
+        let payload: Vec<u8> = (0..payload_len).map(|i| i as u8).collect();
+        let mut packet = Ipv4Packet::empty();
+        packet.set_ttl(64);
+        packet.set_protocol(17);
+        packet.set_identification(identification);
+        packet.set_src_addr(Ipv4Addr::new(10, 0, 0, 1));
+        packet.set_dest_addr(Ipv4Addr::new(192, 168, 0, 1));
+        packet.set_payload(&payload);
+        packet.set_checksum();
+        packet
+    }
+
+    fn ipv4_fragments(identification: u16, payload_len: usize, mtu: usize) -> Vec<Ipv4Packet> {
+        Fragment::new(mtu)
+            .process(ipv4_packet(identification, payload_len))
+            .unwrap()
+    }
+
+    /// An IPv6 fragment with a Hop-by-Hop Options header before the Fragment header.
+    fn ipv6_fragment(offset: usize, more_fragments: bool, data: &[u8]) -> Ipv6Packet {
+        let offset_and_flags = (offset / 8) as u16 * 8 + more_fragments as u16;
+        let mut headers = vec![FRAGMENT, 0, 1, 4, 0, 0, 0, 0];
+        headers.extend_from_slice(&[17, 0]);
+        headers.extend_from_slice(&offset_and_flags.to_be_bytes());
+        headers.extend_from_slice(&[0, 0, 0, 42]);
+        headers.extend_from_slice(data);
+
+        let mut packet = Ipv6Packet::empty();
+        packet.set_next_header(HOP_BY_HOP);
+        packet.set_src_addr("2001:db8::1".parse().unwrap());
+        packet.set_dest_addr("2001:db8::2".parse().unwrap());
+        packet.set_payload(&headers);
+        packet
+    }
+
+    fn reassembler<Packet: IpFragment + Send + 'static>(
+        memory_limit: usize,
+    ) -> Reassembler<Packet> {
+        Reassembler {
+            in_stream: immediate_stream(vec![]),
+            timeout: Duration::from_secs(30),
+            memory_limit,
+            memory_used: 0,
+            datagrams: HashMap::new(),
+            arrivals: VecDeque::new(),
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_input_streams() {
+        ReassemblyLink::<Ipv4Packet>::new().build_link();
+    }
+
+    #[test]
+    fn reassembles_ipv4() {
+        let mut fragments = ipv4_fragments(1, 1000, 300);
+        fragments.reverse();
+        let mut packets = vec![ipv4_packet(2, 10)];
+        packets.append(&mut fragments);
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = ReassemblyLink::new()
+                .ingressor(immediate_stream(packets))
+                .build_link();
+
+            run_link(link).await
+        });
+
+        let mut reassembled = ipv4_packet(1, 1000);
+        assert_eq!(results[0].len(), 2);
+        assert_eq!(results[0][0], ipv4_packet(2, 10));
+        assert_eq!(results[0][1], reassembled);
+        assert!(reassembled.validate_checksum());
+    }
+
+    #[test]
+    fn reassembles_ipv6() {
+        let payload: Vec<u8> = (0..100).collect();
+        let mut reassembler = reassembler::<Ipv6Packet>(1024);
+        let now = Instant::now();
+
+        let last = ipv6_fragment(48, false, &payload[48..]);
+        assert!(reassembler.add(last, now).is_none());
+        let packet = reassembler
+            .add(ipv6_fragment(0, true, &payload[..48]), now)
+            .unwrap();
+
+        assert_eq!(packet.payload_length(), 108);
+        assert_eq!(packet.data[6], HOP_BY_HOP);
+        assert_eq!(packet.data[40], 17);
+        assert_eq!(packet.data[48..], payload[..]);
+        assert_eq!(reassembler.memory_used, 0);
+    }
+
+    #[test]
+    fn discards_overlapping_fragments() {
+        let payload = [7; 64];
+        let mut reassembler = reassembler::<Ipv6Packet>(1024);
+        let now = Instant::now();
+
+        assert!(reassembler
+            .add(ipv6_fragment(0, true, &payload[..32]), now)
+            .is_none());
+        assert!(reassembler
+            .add(ipv6_fragment(24, true, &payload[24..40]), now)
+            .is_none());
+        assert!(reassembler
+            .add(ipv6_fragment(32, false, &payload[32..]), now)
+            .is_none());
+        assert_eq!(reassembler.datagrams.len(), 1);
+        assert_eq!(reassembler.memory_used, 32);
+    }
+
+    #[test]
+    fn discards_expired_datagrams() {
+        let fragments = ipv4_fragments(1, 1000, 300);
+        let mut reassembler = reassembler::<Ipv4Packet>(1 << 20);
+        let start = Instant::now();
+
+        for fragment in &fragments[..fragments.len() - 1] {
+            assert!(reassembler.add(fragment.clone(), start).is_none());
+        }
+        let later = start + Duration::from_secs(31);
+        assert!(reassembler
+            .add(fragments.last().unwrap().clone(), later)
+            .is_none());
+        assert_eq!(reassembler.datagrams.len(), 1);
+    }
+
+    #[test]
+    fn discards_oldest_over_memory_limit() {
+        let mut reassembler = reassembler::<Ipv4Packet>(1000);
+        let now = Instant::now();
+        let first = ipv4_fragments(1, 1000, 500);
+        let second = ipv4_fragments(2, 1000, 500);
+
+        assert!(reassembler.add(first[0].clone(), now).is_none());
+        assert!(reassembler.add(second[0].clone(), now).is_none());
+        assert!(reassembler.add(second[1].clone(), now).is_none());
+        assert_eq!(reassembler.memory_used, 960);
+        assert!(reassembler.add(second[2].clone(), now).is_some());
+
+        assert!(reassembler.add(first[1].clone(), now).is_none());
+        assert!(reassembler.add(first[2].clone(), now).is_none());
+    }
+}
@@ -0,0 +1,328 @@
+use crate::link::primitive::JoinIngressor;
+use crate::link::utils::task_park::*;
+use crate::link::{Link, LinkBuilder, PacketStream, TokioRunnable};
+use crate::processor::Sequenced;
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::Receiver;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::collections::BTreeMap;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Joins the outputs of parallel pipelines back together, restoring the order packets were tagged
+/// in by a `Sequencer`. Out of order packets are held until the packets before them arrive, up to
+/// `window` packets. Since processors may drop packets, once the window is full we stop waiting on
+/// the missing packets and move on; any that show up afterwards are dropped.
+#[derive(Default)]
+pub struct ReorderLink<Packet: Send> {
+    in_streams: Option<Vec<PacketStream<Sequenced<Packet>>>>,
+    window: usize,
+    queue_capacity: usize,
+}
+
+impl<Packet: Send> ReorderLink<Packet> {
+    pub fn new() -> Self {
+        ReorderLink {
+            in_streams: None,
+            window: 64,
+            queue_capacity: 10,
+        }
+    }
+
+    /// Changes window, the most packets held while waiting for a missing one, default value is 64.
+    pub fn window(self, window: usize) -> Self {
+        assert!(window > 0, "ReorderLink window: {}, must be > 0", window);
+
+        ReorderLink {
+            in_streams: self.in_streams,
+            window,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Changes queue_capacity, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "Queue capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        ReorderLink {
+            in_streams: self.in_streams,
+            window: self.window,
+            queue_capacity,
+        }
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Sequenced<Packet>, Packet> for ReorderLink<Packet> {
+    fn ingressors(self, in_streams: Vec<PacketStream<Sequenced<Packet>>>) -> Self {
+        assert!(
+            !in_streams.is_empty(),
+            "number of in_streams: {}, must be greater than 0",
+            in_streams.len()
+        );
+
+        if self.in_streams.is_some() {
+            panic!("ReorderLink already has input streams")
+        }
+
+        ReorderLink {
+            in_streams: Some(in_streams),
+            window: self.window,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Appends the ingressor to the ingressors of the link.
+    fn ingressor(self, in_stream: PacketStream<Sequenced<Packet>>) -> Self {
+        let mut in_streams = self.in_streams.unwrap_or_default();
+        in_streams.push(in_stream);
+
+        ReorderLink {
+            in_streams: Some(in_streams),
+            window: self.window,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match self.in_streams {
+            None => panic!("Cannot build link! Missing input streams"),
+            Some(input_streams) => {
+                let number_ingressors = input_streams.len();
+                let mut ingressors: Vec<TokioRunnable> = Vec::new();
+                let mut from_ingressors: Vec<Receiver<Option<Sequenced<Packet>>>> = Vec::new();
+                let mut task_parks: Vec<Arc<AtomicCell<TaskParkState>>> = Vec::new();
+
+                for input_stream in input_streams {
+                    let (to_egressor, from_ingressor) = crossbeam_channel::bounded::<
+                        Option<Sequenced<Packet>>,
+                    >(self.queue_capacity);
+                    let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+                    let ingressor =
+                        JoinIngressor::new(input_stream, to_egressor, Arc::clone(&task_park));
+                    ingressors.push(Box::new(ingressor));
+                    from_ingressors.push(from_ingressor);
+                    task_parks.push(task_park);
+                }
+
+                let egressor = ReorderEgressor {
+                    from_ingressors,
+                    task_parks,
+                    ingressors_alive: number_ingressors,
+                    held: BTreeMap::new(),
+                    next_sequence: 0,
+                    window: self.window,
+                };
+
+                (ingressors, vec![Box::new(egressor)])
+            }
+        }
+    }
+}
+
+pub struct ReorderEgressor<Packet: Sized> {
+    from_ingressors: Vec<Receiver<Option<Sequenced<Packet>>>>,
+    task_parks: Vec<Arc<AtomicCell<TaskParkState>>>,
+    ingressors_alive: usize,
+    held: BTreeMap<u64, Packet>,
+    next_sequence: u64,
+    window: usize,
+}
+
+impl<Packet: Sized> Unpin for ReorderEgressor<Packet> {}
+
+impl<Packet: Sized> ReorderEgressor<Packet> {
+    /// Takes at most one packet from each ingressor. Returns whether anything was received.
+    fn receive(&mut self) -> bool {
+        let mut received = false;
+        for (port, from_ingressor) in self.from_ingressors.iter().enumerate() {
+            match from_ingressor.try_recv() {
+                Ok(Some(sequenced)) => {
+                    unpark_and_wake(&self.task_parks[port]);
+                    received = true;
+                    if sequenced.sequence >= self.next_sequence {
+                        self.held.insert(sequenced.sequence, sequenced.packet);
+                    }
+                }
+                Ok(None) => {
+                    self.ingressors_alive -= 1;
+                    received = true;
+                }
+                Err(_) => {}
+            }
+        }
+        received
+    }
+
+    /// Gives up on any missing packets before the earliest one we hold.
+    fn skip_to_held(&mut self) {
+        if let Some(sequence) = self.held.keys().next() {
+            self.next_sequence = *sequence;
+        }
+    }
+}
+
+impl<Packet: Sized> Stream for ReorderEgressor<Packet> {
+    type Item = Packet;
+
+    /// Releases the next packet in sequence as soon as we hold it. Otherwise we keep receiving
+    /// packets from the ingressors until either it arrives, or the window is full, in which case
+    /// we skip ahead to the earliest packet we hold. Once every ingressor has finished we flush
+    /// the rest in order. If no ingressor has a packet for us, we park with every ingressor, as
+    /// JoinEgressor does.
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let egressor = Pin::into_inner(self);
+        loop {
+            if let Some(packet) = egressor.held.remove(&egressor.next_sequence) {
+                egressor.next_sequence += 1;
+                return Poll::Ready(Some(packet));
+            }
+
+            if egressor.held.len() >= egressor.window {
+                egressor.skip_to_held();
+                continue;
+            }
+
+            if egressor.ingressors_alive == 0 {
+                if egressor.held.is_empty() {
+                    for task_park in egressor.task_parks.iter() {
+                        die_and_wake(task_park);
+                    }
+                    return Poll::Ready(None);
+                }
+                egressor.skip_to_held();
+                continue;
+            }
+
+            if egressor.receive() {
+                continue;
+            }
+
+            let mut parked_egressor_task = false;
+            let egressor_task = Arc::new(AtomicCell::new(Some(cx.waker().clone())));
+            for task_park in egressor.task_parks.iter() {
+                if indirect_park_and_wake(task_park, Arc::clone(&egressor_task)) {
+                    parked_egressor_task = true;
+                }
+            }
+            if !parked_egressor_task {
+                cx.waker().wake_by_ref();
+            }
+            return Poll::Pending;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::primitive::{ProcessLink, QueueLink, RoundRobinLink};
+    use crate::link::ProcessLinkBuilder;
+    use crate::processor::{Identity, Sequencer};
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    fn sequenced(sequences: Vec<u64>) -> PacketStream<Sequenced<u64>> {
+        immediate_stream(sequences.into_iter().map(|sequence| Sequenced {
+            sequence,
+            packet: sequence,
+        }))
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_input_streams() {
+        ReorderLink::<i32>::new().build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_with_zero_window() {
+        ReorderLink::<i32>::new().window(0);
+    }
+
+    #[test]
+    fn restores_order_across_inputs() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = ReorderLink::new()
+                .ingressor(sequenced(vec![1, 3, 5, 6, 9]))
+                .ingressor(sequenced(vec![0, 2, 4, 7, 8]))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], (0..10).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn skips_dropped_packets() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = ReorderLink::new()
+                .ingressor(sequenced(vec![0, 1, 3, 4, 5, 6, 8, 9]))
+                .window(2)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![0, 1, 3, 4, 5, 6, 8, 9]);
+    }
+
+    #[test]
+    fn drops_packets_behind_the_window() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = ReorderLink::new()
+                .ingressor(sequenced(vec![1, 2, 3, 0, 4]))
+                .window(2)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn reorders_after_parallel_pipelines() {
+        let packets: Vec<i32> = (0..200).collect();
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (mut runnables, sequenced) = ProcessLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .processor(Sequencer::new())
+                .build_link();
+
+            let (mut fan_out_runnables, fan_out) = RoundRobinLink::new()
+                .ingressors(sequenced)
+                .num_egressors(3)
+                .build_link();
+            runnables.append(&mut fan_out_runnables);
+
+            let mut pipelines = vec![];
+            for (worker, stream) in fan_out.into_iter().enumerate() {
+                let (mut worker_runnables, mut worker_egressors) = QueueLink::new()
+                    .ingressor(stream)
+                    .processor(Identity::new())
+                    .queue_capacity(worker * 10 + 1)
+                    .build_link();
+                runnables.append(&mut worker_runnables);
+                pipelines.push(worker_egressors.remove(0));
+            }
+
+            let (mut reorder_runnables, egressors) =
+                ReorderLink::new().ingressors(pipelines).build_link();
+            runnables.append(&mut reorder_runnables);
+
+            run_link((runnables, egressors)).await
+        });
+        assert_eq!(results[0], packets);
+    }
+}
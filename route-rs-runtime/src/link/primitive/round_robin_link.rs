@@ -0,0 +1,276 @@
+use crate::link::utils::task_park::*;
+use crate::link::{primitive::QueueEgressor, Link, LinkBuilder, PacketStream};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::Sender;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Like ForkLink, but each packet is sent to only one egressor, taking turns in order. Useful for
+/// spreading work across several identical pipelines.
+#[derive(Default)]
+pub struct RoundRobinLink<Packet: Send> {
+    in_stream: Option<PacketStream<Packet>>,
+    queue_capacity: usize,
+    num_egressors: Option<usize>,
+}
+
+impl<Packet: Send> RoundRobinLink<Packet> {
+    pub fn new() -> Self {
+        RoundRobinLink {
+            in_stream: None,
+            queue_capacity: 10,
+            num_egressors: None,
+        }
+    }
+
+    /// Changes queue_capacity, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        RoundRobinLink {
+            in_stream: self.in_stream,
+            queue_capacity,
+            num_egressors: self.num_egressors,
+        }
+    }
+
+    pub fn num_egressors(self, num_egressors: usize) -> Self {
+        assert!(
+            num_egressors > 0,
+            "num_egressors: {}, must be > 0",
+            num_egressors
+        );
+
+        RoundRobinLink {
+            in_stream: self.in_stream,
+            queue_capacity: self.queue_capacity,
+            num_egressors: Some(num_egressors),
+        }
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Packet, Packet> for RoundRobinLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "RoundRobinLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("RoundRobinLink may only take 1 input stream")
+        }
+
+        RoundRobinLink {
+            in_stream: Some(in_streams.remove(0)),
+            queue_capacity: self.queue_capacity,
+            num_egressors: self.num_egressors,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("RoundRobinLink may only take 1 input stream")
+        }
+
+        RoundRobinLink {
+            in_stream: Some(in_stream),
+            queue_capacity: self.queue_capacity,
+            num_egressors: self.num_egressors,
+        }
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match (self.in_stream, self.num_egressors) {
+            (None, _) => panic!("Cannot build link! Missing input stream"),
+            (_, None) => panic!("Cannot build link! Missing number of num_egressors"),
+            (Some(in_stream), Some(num_egressors)) => {
+                let mut to_egressors: Vec<Sender<Option<Packet>>> = Vec::new();
+                let mut egressors: Vec<PacketStream<Packet>> = Vec::new();
+                let mut task_parks: Vec<Arc<AtomicCell<TaskParkState>>> = Vec::new();
+
+                for _ in 0..num_egressors {
+                    let (to_egressor, from_ingressor) =
+                        crossbeam_channel::bounded::<Option<Packet>>(self.queue_capacity);
+                    let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+                    let egressor = QueueEgressor::new(from_ingressor, Arc::clone(&task_park));
+
+                    to_egressors.push(to_egressor);
+                    egressors.push(Box::new(egressor));
+                    task_parks.push(task_park);
+                }
+
+                let ingressor = RoundRobinIngressor {
+                    input_stream: in_stream,
+                    to_egressors,
+                    task_parks,
+                    next_port: 0,
+                };
+
+                (vec![Box::new(ingressor)], egressors)
+            }
+        }
+    }
+}
+
+pub struct RoundRobinIngressor<P> {
+    input_stream: PacketStream<P>,
+    to_egressors: Vec<Sender<Option<P>>>,
+    task_parks: Vec<Arc<AtomicCell<TaskParkState>>>,
+    next_port: usize,
+}
+
+impl<P> Unpin for RoundRobinIngressor<P> {}
+
+impl<P: Send> Future for RoundRobinIngressor<P> {
+    type Output = ();
+
+    /// Same logic as QueueIngressor, except packets are sent to each egressor in turn. As with
+    /// ClassifyIngressor, if any of the channels are full we await that channel before pulling a new
+    /// packet. We never skip an egressor whose turn it is, so that the distribution stays even.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let ingressor = Pin::into_inner(self);
+        loop {
+            for (port, to_egressor) in ingressor.to_egressors.iter().enumerate() {
+                if to_egressor.is_full() {
+                    park_and_wake(&ingressor.task_parks[port], cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+
+            let packet_option: Option<P> =
+                ready!(Pin::new(&mut ingressor.input_stream).poll_next(cx));
+
+            match packet_option {
+                None => {
+                    for to_egressor in ingressor.to_egressors.iter() {
+                        if let Err(err) = to_egressor.try_send(None) {
+                            panic!(
+                                "RoundRobinIngressor: try_send to_egressor shouldn't fail, {:?}",
+                                err
+                            );
+                        }
+                    }
+                    for task_park in ingressor.task_parks.iter() {
+                        die_and_wake(task_park);
+                    }
+                    return Poll::Ready(());
+                }
+                Some(packet) => {
+                    let port = ingressor.next_port;
+                    if let Err(err) = ingressor.to_egressors[port].try_send(Some(packet)) {
+                        panic!(
+                            "Error in to_egressors[{}] sender, have nowhere to put packet: {:?}",
+                            port, err
+                        );
+                    }
+                    unpark_and_wake(&ingressor.task_parks[port]);
+                    ingressor.next_port = (port + 1) % ingressor.to_egressors.len();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
+    use core::time;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_input_streams() {
+        RoundRobinLink::<i32>::new().num_egressors(2).build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_num_egressors() {
+        RoundRobinLink::new()
+            .ingressor(immediate_stream(vec![0]))
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_with_zero_num_egressors() {
+        RoundRobinLink::<i32>::new().num_egressors(0);
+    }
+
+    #[test]
+    fn no_input() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = RoundRobinLink::new()
+                .ingressor(immediate_stream(Vec::<i32>::new()))
+                .num_egressors(3)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results, vec![vec![], vec![], vec![]]);
+    }
+
+    #[test]
+    fn one_way() {
+        let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = RoundRobinLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .num_egressors(1)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], packets);
+    }
+
+    #[test]
+    fn three_way() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = RoundRobinLink::new()
+                .ingressor(immediate_stream(0..10))
+                .num_egressors(3)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![0, 3, 6, 9]);
+        assert_eq!(results[1], vec![1, 4, 7]);
+        assert_eq!(results[2], vec![2, 5, 8]);
+    }
+
+    #[test]
+    fn wait_between_packets() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let packet_generator = PacketIntervalGenerator::new(
+                time::Duration::from_millis(10),
+                vec![0, 1, 2, 3, 4, 5].into_iter(),
+            );
+
+            let link = RoundRobinLink::new()
+                .ingressor(Box::new(packet_generator))
+                .num_egressors(2)
+                .queue_capacity(1)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![0, 2, 4]);
+        assert_eq!(results[1], vec![1, 3, 5]);
+    }
+}
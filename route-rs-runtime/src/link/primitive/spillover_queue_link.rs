@@ -0,0 +1,302 @@
+use crate::link::utils::task_park::*;
+use crate::link::{primitive::QueueEgressor, Link, LinkBuilder, PacketStream};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::Sender;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// A queue that, when its channel to the egressor fills, spills packets into a larger secondary
+/// buffer rather than pushing back on upstream links. This absorbs microbursts from fast
+/// interfaces without giving every channel a large capacity. Packets stay in order, and the
+/// buffer is drained into the channel as the egressor makes room. If a `spillover_capacity` is
+/// set, the ingressor waits once the buffer is full as well; otherwise the buffer is unbounded.
+#[derive(Default)]
+pub struct SpilloverQueueLink<Packet> {
+    in_stream: Option<PacketStream<Packet>>,
+    queue_capacity: usize,
+    spillover_capacity: Option<usize>,
+}
+
+impl<Packet> SpilloverQueueLink<Packet> {
+    pub fn new() -> Self {
+        SpilloverQueueLink {
+            in_stream: None,
+            queue_capacity: 10,
+            spillover_capacity: None,
+        }
+    }
+
+    /// Changes queue_capacity, of the channel to the egressor, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "SpilloverQueueLink queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        SpilloverQueueLink {
+            in_stream: self.in_stream,
+            queue_capacity,
+            spillover_capacity: self.spillover_capacity,
+        }
+    }
+
+    /// Bounds the spillover buffer, which is unbounded by default.
+    pub fn spillover_capacity(self, spillover_capacity: usize) -> Self {
+        assert!(
+            spillover_capacity > 0,
+            "SpilloverQueueLink spillover_capacity: {}, must be > 0",
+            spillover_capacity
+        );
+
+        SpilloverQueueLink {
+            in_stream: self.in_stream,
+            queue_capacity: self.queue_capacity,
+            spillover_capacity: Some(spillover_capacity),
+        }
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Packet, Packet> for SpilloverQueueLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "SpilloverQueueLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("SpilloverQueueLink may only take 1 input stream")
+        }
+
+        SpilloverQueueLink {
+            in_stream: Some(in_streams.remove(0)),
+            queue_capacity: self.queue_capacity,
+            spillover_capacity: self.spillover_capacity,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("SpilloverQueueLink may only take 1 input stream")
+        }
+
+        SpilloverQueueLink {
+            in_stream: Some(in_stream),
+            queue_capacity: self.queue_capacity,
+            spillover_capacity: self.spillover_capacity,
+        }
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match self.in_stream {
+            None => panic!("Cannot build link! Missing input stream"),
+            Some(in_stream) => {
+                let (to_egressor, from_ingressor) =
+                    crossbeam_channel::bounded::<Option<Packet>>(self.queue_capacity);
+                let task_park: Arc<AtomicCell<TaskParkState>> =
+                    Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+                let ingressor = SpilloverIngressor {
+                    input_stream: Some(in_stream),
+                    to_egressor,
+                    task_park: Arc::clone(&task_park),
+                    spillover: VecDeque::new(),
+                    spillover_capacity: self.spillover_capacity,
+                };
+                let egressor = QueueEgressor::new(from_ingressor, task_park);
+
+                (vec![Box::new(ingressor)], vec![Box::new(egressor)])
+            }
+        }
+    }
+}
+
+pub struct SpilloverIngressor<Packet> {
+    /// Set to `None` once the input has finished.
+    input_stream: Option<PacketStream<Packet>>,
+    to_egressor: Sender<Option<Packet>>,
+    task_park: Arc<AtomicCell<TaskParkState>>,
+    spillover: VecDeque<Packet>,
+    spillover_capacity: Option<usize>,
+}
+
+impl<Packet> Unpin for SpilloverIngressor<Packet> {}
+
+impl<Packet> SpilloverIngressor<Packet> {
+    /// Moves packets from the spillover buffer into the channel, while it has room.
+    fn drain_spillover(&mut self) {
+        while !self.to_egressor.is_full() {
+            match self.spillover.pop_front() {
+                Some(packet) => {
+                    self.to_egressor
+                        .try_send(Some(packet))
+                        .expect("SpilloverIngressor try_send to_egressor shouldn't fail");
+                    unpark_and_wake(&self.task_park);
+                }
+                None => return,
+            }
+        }
+    }
+
+    fn spillover_is_full(&self) -> bool {
+        match self.spillover_capacity {
+            Some(capacity) => self.spillover.len() >= capacity,
+            None => false,
+        }
+    }
+}
+
+impl<Packet> Future for SpilloverIngressor<Packet> {
+    type Output = ();
+
+    /// Same logic as QueueIngressor, except that when the channel is full, packets go into the
+    /// spillover buffer. Once anything has spilled over, new packets go behind it, to keep them in
+    /// order. Whenever we have packets spilled over, we park for the egressor to wake us when it
+    /// makes room, as well as waiting on the input. If the spillover buffer is bounded and full,
+    /// we only wait on the egressor. When the input has finished, we wait for the buffer to drain
+    /// before sending the `None` that tears the egressor down.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let ingressor = Pin::into_inner(self);
+        loop {
+            ingressor.drain_spillover();
+
+            let spillover_is_full = ingressor.spillover_is_full();
+            let input_stream = match &mut ingressor.input_stream {
+                Some(input_stream) if !spillover_is_full => input_stream,
+                Some(_) => {
+                    park_and_wake(&ingressor.task_park, cx.waker().clone());
+                    return Poll::Pending;
+                }
+                None => {
+                    if !ingressor.spillover.is_empty() || ingressor.to_egressor.is_full() {
+                        park_and_wake(&ingressor.task_park, cx.waker().clone());
+                        return Poll::Pending;
+                    }
+                    ingressor.to_egressor.try_send(None).expect(
+                        "SpilloverIngressor::Poll::Ready(None) try_send to_egressor shouldn't fail",
+                    );
+                    die_and_wake(&ingressor.task_park);
+                    return Poll::Ready(());
+                }
+            };
+
+            match Pin::new(input_stream).poll_next(cx) {
+                Poll::Ready(Some(packet)) => {
+                    if ingressor.spillover.is_empty() && !ingressor.to_egressor.is_full() {
+                        ingressor.to_egressor.try_send(Some(packet)).expect(
+                            "SpilloverIngressor::Poll::Ready(Some(val)) try_send to_egressor shouldn't fail",
+                        );
+                        unpark_and_wake(&ingressor.task_park);
+                    } else {
+                        ingressor.spillover.push_back(packet);
+                    }
+                }
+                Poll::Ready(None) => ingressor.input_stream = None,
+                Poll::Pending => {
+                    if !ingressor.spillover.is_empty() {
+                        park_and_wake(&ingressor.task_park, cx.waker().clone());
+                    }
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tokio::time::sleep;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_input_stream() {
+        SpilloverQueueLink::<i32>::new().build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_with_zero_spillover_capacity() {
+        SpilloverQueueLink::<i32>::new().spillover_capacity(0);
+    }
+
+    #[test]
+    fn forwards_in_order() {
+        let packets: Vec<i32> = (0..1000).collect();
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = SpilloverQueueLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .queue_capacity(2)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], packets);
+    }
+
+    #[test]
+    fn absorbs_burst_without_backpressure() {
+        let runtime = initialize_runtime();
+        runtime.block_on(async {
+            let (mut runnables, mut egressors) = SpilloverQueueLink::new()
+                .ingressor(immediate_stream(0..100))
+                .queue_capacity(5)
+                .build_link();
+
+            // The ingressor takes the whole burst, and finishes once it is drained.
+            let ingressor = tokio::spawn(runnables.remove(0));
+            sleep(Duration::from_millis(50)).await;
+
+            let mut egressor = egressors.remove(0);
+            let mut output = vec![];
+            while let Some(packet) = egressor.next().await {
+                output.push(packet);
+            }
+            ingressor.await.unwrap();
+            assert_eq!(output, (0..100).collect::<Vec<i32>>());
+        });
+    }
+
+    #[test]
+    fn bounded_spillover_pushes_back() {
+        let runtime = initialize_runtime();
+        runtime.block_on(async {
+            let consumed = Arc::new(AtomicUsize::new(0));
+            let counted = {
+                let consumed = Arc::clone(&consumed);
+                immediate_stream(0..100).inspect(move |_| {
+                    consumed.fetch_add(1, Ordering::SeqCst);
+                })
+            };
+
+            let (mut runnables, mut egressors) = SpilloverQueueLink::new()
+                .ingressor(Box::new(counted))
+                .queue_capacity(5)
+                .spillover_capacity(10)
+                .build_link();
+            tokio::spawn(runnables.remove(0));
+            sleep(Duration::from_millis(50)).await;
+
+            // The channel and the buffer are full, so the ingressor waits rather than taking more.
+            assert_eq!(consumed.load(Ordering::SeqCst), 15);
+
+            let mut egressor = egressors.remove(0);
+            let mut output = vec![];
+            while let Some(packet) = egressor.next().await {
+                output.push(packet);
+            }
+            assert_eq!(output, (0..100).collect::<Vec<i32>>());
+        });
+    }
+}
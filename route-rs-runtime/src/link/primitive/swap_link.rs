@@ -0,0 +1,328 @@
+use crate::link::{registry::LinkHandle, Link, LinkBuilder, PacketStream};
+use futures::channel::{mpsc, oneshot};
+use futures::future::{self, Either, JoinAll};
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::mem;
+use std::pin::Pin;
+use tokio::task::JoinHandle;
+
+/// A section of a router that a SwapLink runs, and may swap for another while running. Given the
+/// input stream of the section, it builds the section's link, which must have exactly 1 egressor.
+pub type Segment<Input, Output> = Box<dyn FnOnce(PacketStream<Input>) -> Link<Output> + Send>;
+
+/// Runs a segment of the router, such as a firewall's rule pipeline, that can be replaced with
+/// another while the router runs, through a `SwapControl` taken from the builder. A swap is
+/// atomic: every packet before it goes to the old segment, and every packet after it to the new
+/// one. The old segment's input then ends, so it drains the packets it holds to the egressor and
+/// finishes, as it would at the end of input, before it is torn down. While it drains, its output
+/// may interleave with the new segment's. Segments are built and their runnables spawned on the
+/// tokio runtime running the SwapLink, which finishes once its input and every segment have.
+pub struct SwapLink<Input, Output> {
+    in_stream: Option<PacketStream<Input>>,
+    segment: Option<Segment<Input, Output>>,
+    queue_capacity: usize,
+    control: SwapControl<Input, Output>,
+    swaps: mpsc::UnboundedReceiver<Swap<Input, Output>>,
+}
+
+/// A segment to swap in, and the sender to tell of the old segment draining with.
+type Swap<Input, Output> = (Segment<Input, Output>, oneshot::Sender<()>);
+
+impl<Input: Send + 'static, Output: Send + 'static> Default for SwapLink<Input, Output> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Input: Send + 'static, Output: Send + 'static> SwapLink<Input, Output> {
+    pub fn new() -> Self {
+        let (to_link, swaps) = mpsc::unbounded();
+        SwapLink {
+            in_stream: None,
+            segment: None,
+            queue_capacity: 10,
+            control: SwapControl { to_link },
+            swaps,
+        }
+    }
+
+    /// Sets the segment the link starts with.
+    pub fn segment(
+        self,
+        segment: impl FnOnce(PacketStream<Input>) -> Link<Output> + Send + 'static,
+    ) -> Self {
+        SwapLink {
+            in_stream: self.in_stream,
+            segment: Some(Box::new(segment)),
+            queue_capacity: self.queue_capacity,
+            control: self.control,
+            swaps: self.swaps,
+        }
+    }
+
+    /// Changes queue_capacity, default value is 10. The capacity of the queues into and out of
+    /// each segment.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "SwapLink queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        SwapLink {
+            in_stream: self.in_stream,
+            segment: self.segment,
+            queue_capacity,
+            control: self.control,
+            swaps: self.swaps,
+        }
+    }
+
+    /// Returns a handle for swapping segments. It may be used before or after the link is built.
+    pub fn control(&self) -> SwapControl<Input, Output> {
+        self.control.clone()
+    }
+}
+
+impl<Input: Send + 'static, Output: Send + 'static> LinkBuilder<Input, Output>
+    for SwapLink<Input, Output>
+{
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Input>>) -> Self {
+        assert_eq!(in_streams.len(), 1, "SwapLink may only take 1 input stream");
+
+        if self.in_stream.is_some() {
+            panic!("SwapLink may only take 1 input stream")
+        }
+
+        SwapLink {
+            in_stream: Some(in_streams.remove(0)),
+            segment: self.segment,
+            queue_capacity: self.queue_capacity,
+            control: self.control,
+            swaps: self.swaps,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Input>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("SwapLink may only take 1 input stream")
+        }
+
+        SwapLink {
+            in_stream: Some(in_stream),
+            segment: self.segment,
+            queue_capacity: self.queue_capacity,
+            control: self.control,
+            swaps: self.swaps,
+        }
+    }
+
+    fn handles(&self) -> Vec<LinkHandle> {
+        vec![Box::new(self.control())]
+    }
+
+    fn build_link(self) -> Link<Output> {
+        let mut in_stream = match self.in_stream {
+            None => panic!("Cannot build link! Missing input stream"),
+            Some(in_stream) => in_stream,
+        };
+        let segment = match self.segment {
+            None => panic!("Cannot build link! Missing segment"),
+            Some(segment) => segment,
+        };
+        let queue_capacity = self.queue_capacity;
+        let mut swaps = self.swaps;
+        let (to_egressor, egressor) = mpsc::channel(queue_capacity);
+
+        let dispatcher = async move {
+            let (mut current, mut running) = start_segment(segment, queue_capacity, &to_egressor);
+            let mut retired = vec![];
+            let mut swaps_open = true;
+            loop {
+                let event = if swaps_open {
+                    match future::select(in_stream.next(), swaps.next()).await {
+                        Either::Left((packet, _)) => Either::Left(packet),
+                        Either::Right((swap, _)) => Either::Right(swap),
+                    }
+                } else {
+                    Either::Left(in_stream.next().await)
+                };
+
+                match event {
+                    Either::Left(Some(packet)) => {
+                        // A segment that has stopped taking input drops the packet.
+                        let _ = current.send(packet).await;
+                    }
+                    Either::Left(None) => break,
+                    Either::Right(Some((segment, drained))) => {
+                        let (next, next_running) =
+                            start_segment(segment, queue_capacity, &to_egressor);
+                        // Dropping the old segment's sender ends its input.
+                        drop(mem::replace(&mut current, next));
+                        let old = mem::replace(&mut running, next_running);
+                        retired.push(tokio::spawn(async move {
+                            old.await;
+                            let _ = drained.send(());
+                        }));
+                    }
+                    Either::Right(None) => swaps_open = false,
+                }
+            }
+
+            // Swaps that never happened are dropped, which resolves their `Drained`s.
+            drop(swaps);
+            drop(current);
+            drop(to_egressor);
+            running.await;
+            future::join_all(retired).await;
+        };
+
+        (
+            vec![Box::new(Box::pin(dispatcher))],
+            vec![Box::new(egressor)],
+        )
+    }
+}
+
+/// Builds `segment` to read from a new queue, spawns its runnables, and forwards its egressor to
+/// `output`. Returns the sender into the segment, and a future that completes when the segment
+/// has finished.
+fn start_segment<Input: Send + 'static, Output: Send + 'static>(
+    segment: Segment<Input, Output>,
+    queue_capacity: usize,
+    output: &mpsc::Sender<Output>,
+) -> (mpsc::Sender<Input>, JoinAll<JoinHandle<()>>) {
+    let (to_segment, from_dispatcher) = mpsc::channel(queue_capacity);
+    let (runnables, mut egressors) = segment(Box::new(from_dispatcher));
+    assert_eq!(
+        egressors.len(),
+        1,
+        "SwapLink segments must have exactly 1 egressor"
+    );
+
+    let forwarder = egressors
+        .remove(0)
+        .map(Ok)
+        .forward(output.clone())
+        .map(|_| ());
+    let mut tasks: Vec<JoinHandle<()>> = runnables.into_iter().map(tokio::spawn).collect();
+    tasks.push(tokio::spawn(forwarder));
+    (to_segment, future::join_all(tasks))
+}
+
+/// Handle to a SwapLink, for swapping its segment at runtime.
+pub struct SwapControl<Input, Output> {
+    to_link: mpsc::UnboundedSender<Swap<Input, Output>>,
+}
+
+impl<Input, Output> Clone for SwapControl<Input, Output> {
+    fn clone(&self) -> Self {
+        SwapControl {
+            to_link: self.to_link.clone(),
+        }
+    }
+}
+
+impl<Input: Send + 'static, Output: Send + 'static> SwapControl<Input, Output> {
+    /// Swaps `segment` in for the running one. Returns a future that completes once the old
+    /// segment has drained and finished. If the link finishes before the swap is made, the
+    /// segment is dropped unused, and the future completes then.
+    pub fn swap(
+        &self,
+        segment: impl FnOnce(PacketStream<Input>) -> Link<Output> + Send + 'static,
+    ) -> Drained {
+        let (drained, receiver) = oneshot::channel();
+        let _ = self.to_link.unbounded_send((Box::new(segment), drained));
+        Drained { receiver }
+    }
+}
+
+/// Future returned by `SwapControl::swap`, that completes once the segment swapped out has
+/// finished.
+pub struct Drained {
+    receiver: oneshot::Receiver<()>,
+}
+
+impl Future for Drained {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        Pin::new(&mut self.receiver).poll(cx).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::primitive::ProcessLink;
+    use crate::link::ProcessLinkBuilder;
+    use crate::processor::ClosureProcessor;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
+    use std::time::Duration;
+    use tokio::time::sleep;
+
+    /// A segment that adds `n` to every packet.
+    fn add(n: i32) -> impl FnOnce(PacketStream<i32>) -> Link<i32> + Send + 'static {
+        move |input| {
+            ProcessLink::new()
+                .ingressor(input)
+                .processor(ClosureProcessor::new(move |packet| Some(packet + n)))
+                .build_link()
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_segment() {
+        SwapLink::<i32, i32>::new()
+            .ingressor(immediate_stream(vec![]))
+            .build_link();
+    }
+
+    #[test]
+    fn runs_segment() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = SwapLink::new()
+                .ingressor(immediate_stream(vec![0, 1, 2, 3]))
+                .segment(add(10))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![10, 11, 12, 13]);
+    }
+
+    #[test]
+    fn swaps_without_losing_packets() {
+        let runtime = initialize_runtime();
+        let mut results = runtime.block_on(async {
+            let packet_generator = PacketIntervalGenerator::new(Duration::from_millis(10), 0..20);
+            let link = SwapLink::new()
+                .ingressor(Box::new(packet_generator))
+                .segment(add(100));
+            let control = link.control();
+
+            let swapper = tokio::spawn(async move {
+                sleep(Duration::from_millis(95)).await;
+                control.swap(add(200)).await;
+            });
+            let results = run_link(link.build_link()).await;
+            swapper.await.unwrap();
+            results
+        });
+
+        let output = results.remove(0);
+        assert_eq!(output.len(), 20);
+        let swapped_at = output.iter().position(|packet| *packet >= 200).unwrap();
+        assert!(swapped_at > 0);
+        let (old, new) = output.split_at(swapped_at);
+        assert!(old.iter().all(|packet| *packet < 200));
+        assert!(new.iter().all(|packet| *packet >= 200));
+        let mut inputs: Vec<i32> = old.iter().map(|packet| packet - 100).collect();
+        inputs.extend(new.iter().map(|packet| packet - 200));
+        assert_eq!(inputs, (0..20).collect::<Vec<_>>());
+    }
+}
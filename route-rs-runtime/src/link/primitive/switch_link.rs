@@ -0,0 +1,329 @@
+use crate::link::utils::task_park::*;
+use crate::link::{
+    primitive::QueueEgressor, registry::LinkHandle, Link, LinkBuilder, PacketStream,
+};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::Sender;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Sends every packet to one of several egressors, the active port, which is picked at runtime
+/// through a `SwitchControl` taken from the builder rather than by the packets themselves. This
+/// allows, for instance, a health checking task to fail traffic over from a primary path to a
+/// backup. Packets already queued for a port when the switch moves away from it are still
+/// forwarded. Port 0 starts active.
+pub struct SwitchLink<Packet: Send> {
+    in_stream: Option<PacketStream<Packet>>,
+    queue_capacity: usize,
+    num_egressors: Option<usize>,
+    control: SwitchControl,
+}
+
+impl<Packet: Send> Default for SwitchLink<Packet> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Packet: Send> SwitchLink<Packet> {
+    pub fn new() -> Self {
+        SwitchLink {
+            in_stream: None,
+            queue_capacity: 10,
+            num_egressors: None,
+            control: SwitchControl::new(),
+        }
+    }
+
+    /// Changes queue_capacity, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "SwitchLink queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        SwitchLink {
+            in_stream: self.in_stream,
+            queue_capacity,
+            num_egressors: self.num_egressors,
+            control: self.control,
+        }
+    }
+
+    pub fn num_egressors(self, num_egressors: usize) -> Self {
+        assert!(
+            num_egressors > 0,
+            "SwitchLink num_egressors: {}, must be > 0",
+            num_egressors
+        );
+
+        self.control
+            .num_egressors
+            .store(num_egressors, Ordering::SeqCst);
+        SwitchLink {
+            in_stream: self.in_stream,
+            queue_capacity: self.queue_capacity,
+            num_egressors: Some(num_egressors),
+            control: self.control,
+        }
+    }
+
+    /// Returns a handle for picking the active port. It may be used before or after the link is
+    /// built.
+    pub fn control(&self) -> SwitchControl {
+        self.control.clone()
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Packet, Packet> for SwitchLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "SwitchLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("SwitchLink may only take 1 input stream")
+        }
+
+        SwitchLink {
+            in_stream: Some(in_streams.remove(0)),
+            queue_capacity: self.queue_capacity,
+            num_egressors: self.num_egressors,
+            control: self.control,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("SwitchLink may only take 1 input stream")
+        }
+
+        SwitchLink {
+            in_stream: Some(in_stream),
+            queue_capacity: self.queue_capacity,
+            num_egressors: self.num_egressors,
+            control: self.control,
+        }
+    }
+
+    fn handles(&self) -> Vec<LinkHandle> {
+        vec![Box::new(self.control())]
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match (self.in_stream, self.num_egressors) {
+            (None, _) => panic!("Cannot build link! Missing input stream"),
+            (_, None) => panic!("Cannot build link! Missing number of num_egressors"),
+            (Some(in_stream), Some(num_egressors)) => {
+                let mut to_egressors: Vec<Sender<Option<Packet>>> = Vec::new();
+                let mut egressors: Vec<PacketStream<Packet>> = Vec::new();
+                let mut task_parks: Vec<Arc<AtomicCell<TaskParkState>>> = Vec::new();
+
+                for _ in 0..num_egressors {
+                    let (to_egressor, from_ingressor) =
+                        crossbeam_channel::bounded::<Option<Packet>>(self.queue_capacity);
+                    let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+                    let egressor = QueueEgressor::new(from_ingressor, Arc::clone(&task_park));
+
+                    to_egressors.push(to_egressor);
+                    egressors.push(Box::new(egressor));
+                    task_parks.push(task_park);
+                }
+
+                let ingressor = SwitchIngressor {
+                    input_stream: in_stream,
+                    to_egressors,
+                    task_parks,
+                    control: self.control,
+                    ports_finished: None,
+                };
+
+                (vec![Box::new(ingressor)], egressors)
+            }
+        }
+    }
+}
+
+/// Handle to a SwitchLink, for picking its active port at runtime.
+#[derive(Clone)]
+pub struct SwitchControl {
+    active: Arc<AtomicUsize>,
+    /// Zero until the link's num_egressors is known.
+    num_egressors: Arc<AtomicUsize>,
+}
+
+impl SwitchControl {
+    fn new() -> Self {
+        SwitchControl {
+            active: Arc::new(AtomicUsize::new(0)),
+            num_egressors: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Sends all packets from now on to `port`.
+    pub fn select(&self, port: usize) {
+        let num_egressors = self.num_egressors.load(Ordering::SeqCst);
+        assert!(
+            num_egressors == 0 || port < num_egressors,
+            "SwitchControl port: {}, must be < num_egressors: {}",
+            port,
+            num_egressors
+        );
+
+        self.active.store(port, Ordering::SeqCst);
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+}
+
+pub struct SwitchIngressor<P> {
+    input_stream: PacketStream<P>,
+    to_egressors: Vec<Sender<Option<P>>>,
+    task_parks: Vec<Arc<AtomicCell<TaskParkState>>>,
+    control: SwitchControl,
+    /// Once the input has finished, how many egressors we have told so.
+    ports_finished: Option<usize>,
+}
+
+impl<P> Unpin for SwitchIngressor<P> {}
+
+impl<P> SwitchIngressor<P> {
+    /// Tells each egressor in turn that we are finished, waiting on any whose queue is full.
+    fn finish(&mut self, cx: &mut Context) -> Poll<()> {
+        let mut port = self.ports_finished.unwrap_or(0);
+        while port < self.to_egressors.len() {
+            if self.to_egressors[port].is_full() {
+                self.ports_finished = Some(port);
+                park_and_wake(&self.task_parks[port], cx.waker().clone());
+                return Poll::Pending;
+            }
+            if let Err(err) = self.to_egressors[port].try_send(None) {
+                panic!(
+                    "SwitchIngressor: try_send to_egressor shouldn't fail, {:?}",
+                    err
+                );
+            }
+            die_and_wake(&self.task_parks[port]);
+            port += 1;
+        }
+        self.ports_finished = Some(port);
+        Poll::Ready(())
+    }
+}
+
+impl<P: Send> Future for SwitchIngressor<P> {
+    type Output = ();
+
+    /// Same logic as QueueIngressor, sending to whichever port is active when each packet arrives.
+    /// We only wait on the active port having room, so a stalled inactive path does not hold up
+    /// traffic. When the input finishes, we wait on each port in turn to have room for the `None`
+    /// that tears it down.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let ingressor = Pin::into_inner(self);
+        if ingressor.ports_finished.is_some() {
+            return ingressor.finish(cx);
+        }
+
+        loop {
+            let port = ingressor.control.active();
+            if ingressor.to_egressors[port].is_full() {
+                park_and_wake(&ingressor.task_parks[port], cx.waker().clone());
+                return Poll::Pending;
+            }
+
+            match ready!(Pin::new(&mut ingressor.input_stream).poll_next(cx)) {
+                None => return ingressor.finish(cx),
+                Some(packet) => {
+                    if let Err(err) = ingressor.to_egressors[port].try_send(Some(packet)) {
+                        panic!(
+                            "Error in to_egressors[{}] sender, have nowhere to put packet: {:?}",
+                            port, err
+                        );
+                    }
+                    unpark_and_wake(&ingressor.task_parks[port]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
+    use std::time::Duration;
+    use tokio::time::sleep;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_num_egressors() {
+        SwitchLink::new()
+            .ingressor(immediate_stream(vec![0]))
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_selecting_invalid_port() {
+        SwitchLink::<i32>::new()
+            .num_egressors(2)
+            .control()
+            .select(2);
+    }
+
+    #[test]
+    fn sends_to_selected_port() {
+        let packets = vec![0, 1, 2, 420, 1337, 3, 4, 5, 6, 7, 8, 9];
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = SwitchLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .num_egressors(3);
+            link.control().select(1);
+
+            run_link(link.build_link()).await
+        });
+        assert_eq!(results[0], vec![]);
+        assert_eq!(results[1], packets);
+        assert_eq!(results[2], vec![]);
+    }
+
+    #[test]
+    fn fails_over_while_running() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let packet_generator = PacketIntervalGenerator::new(Duration::from_millis(10), 0..20);
+
+            let link = SwitchLink::new()
+                .ingressor(Box::new(packet_generator))
+                .num_egressors(2);
+            let control = link.control();
+            let link = link.build_link();
+
+            tokio::spawn(async move {
+                sleep(Duration::from_millis(95)).await;
+                control.select(1);
+            });
+            run_link(link).await
+        });
+        let primary = &results[0];
+        let backup = &results[1];
+        assert!(!primary.is_empty() && !backup.is_empty());
+        assert_eq!(
+            [&primary[..], &backup[..]].concat(),
+            (0..20).collect::<Vec<i32>>()
+        );
+    }
+}
@@ -0,0 +1,385 @@
+use crate::link::{registry::LinkHandle, Link, LinkBuilder, PacketStream};
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::fs::File;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+
+/// Returns the bytes of a packet to capture, for instance `&frame.data[frame.layer2_offset..]`
+/// for an EthernetFrame.
+pub type PacketBytes<Packet> = Box<dyn Fn(&Packet) -> &[u8] + Send + Sync + 'static>;
+
+/// The pcap link type of Ethernet frames, LINKTYPE_ETHERNET.
+pub const PCAP_LINKTYPE_ETHERNET: u32 = 1;
+
+/// Forwards packets unchanged, while writing a copy of each to a pcap file, for capturing traffic
+/// at any point within the graph to analyse offline, in Wireshark for instance. Copies are handed
+/// to a separate writer task, which buffers its writes to the file. The data path never waits on
+/// the file: if the writer falls more than `queue_capacity` copies behind, or fails to write,
+/// further copies are dropped. The forwarding side of the link is synchronous, like ProcessLink.
+/// Counts of what was captured and dropped, and the error that stopped the capture, if one did,
+/// are available through a `TeeToPcapStats` taken from the builder.
+pub struct TeeToPcapLink<Packet> {
+    in_stream: Option<PacketStream<Packet>>,
+    bytes: Option<PacketBytes<Packet>>,
+    file: Option<File>,
+    link_type: u32,
+    queue_capacity: usize,
+    stats: TeeToPcapStats,
+}
+
+impl<Packet> Default for TeeToPcapLink<Packet> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Packet> TeeToPcapLink<Packet> {
+    pub fn new() -> Self {
+        TeeToPcapLink {
+            in_stream: None,
+            bytes: None,
+            file: None,
+            link_type: PCAP_LINKTYPE_ETHERNET,
+            queue_capacity: 10,
+            stats: TeeToPcapStats::new(),
+        }
+    }
+
+    pub fn bytes(self, bytes: PacketBytes<Packet>) -> Self {
+        TeeToPcapLink {
+            in_stream: self.in_stream,
+            bytes: Some(bytes),
+            file: self.file,
+            link_type: self.link_type,
+            queue_capacity: self.queue_capacity,
+            stats: self.stats,
+        }
+    }
+
+    /// The file to write the capture to, which should be empty.
+    pub fn file(self, file: File) -> Self {
+        TeeToPcapLink {
+            in_stream: self.in_stream,
+            bytes: self.bytes,
+            file: Some(file),
+            link_type: self.link_type,
+            queue_capacity: self.queue_capacity,
+            stats: self.stats,
+        }
+    }
+
+    /// Changes link_type, written to the pcap header, default value is `PCAP_LINKTYPE_ETHERNET`.
+    pub fn link_type(self, link_type: u32) -> Self {
+        TeeToPcapLink {
+            in_stream: self.in_stream,
+            bytes: self.bytes,
+            file: self.file,
+            link_type,
+            queue_capacity: self.queue_capacity,
+            stats: self.stats,
+        }
+    }
+
+    /// Changes queue_capacity, the most copies waiting to be written, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "TeeToPcapLink queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        TeeToPcapLink {
+            in_stream: self.in_stream,
+            bytes: self.bytes,
+            file: self.file,
+            link_type: self.link_type,
+            queue_capacity,
+            stats: self.stats,
+        }
+    }
+
+    /// Returns a handle to the link's counters. It may be read before or after the link is built.
+    pub fn stats(&self) -> TeeToPcapStats {
+        self.stats.clone()
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Packet, Packet> for TeeToPcapLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "TeeToPcapLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("TeeToPcapLink may only take 1 input stream")
+        }
+
+        TeeToPcapLink {
+            in_stream: Some(in_streams.remove(0)),
+            bytes: self.bytes,
+            file: self.file,
+            link_type: self.link_type,
+            queue_capacity: self.queue_capacity,
+            stats: self.stats,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("TeeToPcapLink may only take 1 input stream")
+        }
+
+        TeeToPcapLink {
+            in_stream: Some(in_stream),
+            bytes: self.bytes,
+            file: self.file,
+            link_type: self.link_type,
+            queue_capacity: self.queue_capacity,
+            stats: self.stats,
+        }
+    }
+
+    fn handles(&self) -> Vec<LinkHandle> {
+        vec![Box::new(self.stats())]
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match (self.in_stream, self.bytes, self.file) {
+            (None, _, _) => panic!("Cannot build link! Missing input stream"),
+            (_, None, _) => panic!("Cannot build link! Missing bytes"),
+            (_, _, None) => panic!("Cannot build link! Missing file"),
+            (Some(in_stream), Some(bytes), Some(file)) => {
+                let (to_writer, from_tee) = mpsc::channel::<PcapRecord>(self.queue_capacity);
+                let tee = PcapTee {
+                    in_stream,
+                    bytes,
+                    to_writer,
+                    stats: self.stats.clone(),
+                };
+                let stats = self.stats;
+                let writer = write_pcap(file, self.link_type, from_tee, stats.clone())
+                    .unwrap_or_else(move |err| stats.stop(err));
+
+                (vec![Box::new(Box::pin(writer))], vec![Box::new(tee)])
+            }
+        }
+    }
+}
+
+/// Handle to a TeeToPcapLink's counters, and the error that stopped its capture, if one did.
+#[derive(Clone)]
+pub struct TeeToPcapStats {
+    captured: Arc<AtomicU64>,
+    dropped: Arc<AtomicU64>,
+    error: Arc<Mutex<Option<String>>>,
+}
+
+impl TeeToPcapStats {
+    fn new() -> Self {
+        TeeToPcapStats {
+            captured: Arc::new(AtomicU64::new(0)),
+            dropped: Arc::new(AtomicU64::new(0)),
+            error: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Copies written to the file, though they may still be buffered.
+    pub fn captured(&self) -> u64 {
+        self.captured.load(Ordering::Relaxed)
+    }
+
+    /// Copies dropped because the writer fell behind, or had stopped.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// The error writing to the file that stopped the capture, if one has.
+    pub fn error(&self) -> Option<String> {
+        self.error.lock().unwrap().clone()
+    }
+
+    fn stop(&self, err: io::Error) {
+        *self.error.lock().unwrap() = Some(err.to_string());
+    }
+}
+
+/// A copy of a packet on its way to the file.
+struct PcapRecord {
+    captured: SystemTime,
+    data: Vec<u8>,
+}
+
+/// The egressor of TeeToPcapLink
+struct PcapTee<Packet> {
+    in_stream: PacketStream<Packet>,
+    bytes: PacketBytes<Packet>,
+    to_writer: mpsc::Sender<PcapRecord>,
+    stats: TeeToPcapStats,
+}
+
+impl<Packet> Unpin for PcapTee<Packet> {}
+
+impl<Packet> Stream for PcapTee<Packet> {
+    type Item = Packet;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let packet = ready!(Pin::new(&mut self.in_stream).poll_next(cx));
+        if let Some(packet) = &packet {
+            let record = PcapRecord {
+                captured: SystemTime::now(),
+                data: (self.bytes)(packet).to_vec(),
+            };
+            // A full queue, or a writer that gave up, drops the copy rather than holding us up.
+            if self.to_writer.try_send(record).is_err() {
+                self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        Poll::Ready(packet)
+    }
+}
+
+/// Writes the pcap header, then a record for each copy, until the tee is dropped.
+async fn write_pcap(
+    file: File,
+    link_type: u32,
+    mut from_tee: mpsc::Receiver<PcapRecord>,
+    stats: TeeToPcapStats,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(tokio::fs::File::from_std(file));
+    writer.write_all(&pcap_header(link_type)).await?;
+    loop {
+        // Flush whenever we catch up, so the file is up to date while traffic is quiet.
+        let record = match from_tee.try_recv() {
+            Ok(record) => record,
+            Err(_) => {
+                writer.flush().await?;
+                match from_tee.recv().await {
+                    Some(record) => record,
+                    None => return Ok(()),
+                }
+            }
+        };
+        writer.write_all(&record_header(&record)).await?;
+        writer.write_all(&record.data).await?;
+        stats.captured.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The most bytes of each packet written to the file.
+const SNAPLEN: u32 = 65535;
+
+fn pcap_header(link_type: u32) -> Vec<u8> {
+    let mut header = Vec::with_capacity(24);
+    header.extend_from_slice(&0xa1b2_c3d4u32.to_le_bytes());
+    header.extend_from_slice(&2u16.to_le_bytes());
+    header.extend_from_slice(&4u16.to_le_bytes());
+    header.extend_from_slice(&0i32.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(&SNAPLEN.to_le_bytes());
+    header.extend_from_slice(&link_type.to_le_bytes());
+    header
+}
+
+/// Truncates the record's data to `SNAPLEN` if it is longer.
+fn record_header(record: &PcapRecord) -> Vec<u8> {
+    let since_epoch = record
+        .captured
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let original_len = record.data.len() as u32;
+    let mut header = Vec::with_capacity(16);
+    header.extend_from_slice(&(since_epoch.as_secs() as u32).to_le_bytes());
+    header.extend_from_slice(&since_epoch.subsec_micros().to_le_bytes());
+    header.extend_from_slice(&original_len.min(SNAPLEN).to_le_bytes());
+    header.extend_from_slice(&original_len.to_le_bytes());
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use std::convert::TryInto;
+    use std::fs::{create_dir_all, read, remove_file};
+    use std::path::Path;
+    use uuid::Uuid;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_file() {
+        TeeToPcapLink::<Vec<u8>>::new()
+            .ingressor(immediate_stream(vec![]))
+            .bytes(Box::new(|packet| &packet[..]))
+            .build_link();
+    }
+
+    #[test]
+    fn forwards_and_captures() {
+        let packets: Vec<Vec<u8>> = vec![vec![0xde, 0xad], vec![0xbe, 0xef, 0x00], vec![]];
+
+        let capture_dir = Path::new("test_logs");
+        let capture_path = capture_dir.join(format!("{}.pcap", Uuid::new_v4()));
+        create_dir_all(capture_dir).unwrap();
+
+        let link = TeeToPcapLink::new()
+            .ingressor(immediate_stream(packets.clone()))
+            .bytes(Box::new(|packet| &packet[..]))
+            .file(File::create(&capture_path).unwrap())
+            .queue_capacity(packets.len());
+        let stats = link.stats();
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(run_link(link.build_link()));
+        assert_eq!(results[0], packets);
+        assert_eq!(stats.captured(), packets.len() as u64);
+        assert_eq!(stats.dropped(), 0);
+        assert_eq!(stats.error(), None);
+
+        let capture = read(&capture_path).unwrap();
+        remove_file(&capture_path).unwrap();
+        assert_eq!(capture[0..4], 0xa1b2_c3d4u32.to_le_bytes());
+        assert_eq!(capture[20..24], PCAP_LINKTYPE_ETHERNET.to_le_bytes());
+
+        let mut records = &capture[24..];
+        for packet in packets.iter() {
+            let length = u32::from_le_bytes(records[8..12].try_into().unwrap()) as usize;
+            assert_eq!(length, packet.len());
+            assert_eq!(&records[16..16 + length], &packet[..]);
+            records = &records[16 + length..];
+        }
+        assert!(records.is_empty());
+    }
+
+    #[test]
+    fn reports_write_errors() {
+        let packets: Vec<Vec<u8>> = vec![vec![0xde, 0xad], vec![0xbe, 0xef]];
+
+        let capture_dir = Path::new("test_logs");
+        let capture_path = capture_dir.join(format!("{}.pcap", Uuid::new_v4()));
+        create_dir_all(capture_dir).unwrap();
+        File::create(&capture_path).unwrap();
+
+        // A file opened only for reading fails the writes, but not the forwarding.
+        let link = TeeToPcapLink::new()
+            .ingressor(immediate_stream(packets.clone()))
+            .bytes(Box::new(|packet| &packet[..]))
+            .file(File::open(&capture_path).unwrap())
+            .queue_capacity(packets.len());
+        let stats = link.stats();
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(run_link(link.build_link()));
+        remove_file(&capture_path).unwrap();
+        assert_eq!(results[0], packets);
+        assert!(stats.error().is_some());
+    }
+}
@@ -0,0 +1,395 @@
+use crate::link::primitive::QueueEgressor;
+use crate::link::utils::task_park::*;
+use crate::link::utils::token_bucket::TokenBucket;
+use crate::link::{Link, LinkBuilder, PacketStream};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::Sender;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::time::{sleep_until, Sleep};
+
+/// Returns how many tokens a packet costs, for instance its length in bytes.
+pub type PacketCost<Packet> = Box<dyn Fn(&Packet) -> u64 + Send + Sync + 'static>;
+
+/// What a TokenBucketLink does with packets that arrive when there are not enough tokens.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NonConforming {
+    /// Drop the packet, policing the traffic.
+    Drop,
+    /// Hold the packet until enough tokens accrue, shaping the traffic.
+    Queue,
+}
+
+/// Enforces a `rate` and `burst` on traffic with a token bucket. By default every packet costs one
+/// token, so the rate is in packets per second; providing a `packet_cost` such as the packet length
+/// makes it bytes per second instead. Non-conforming packets are dropped unless the link is set to
+/// queue them.
+#[derive(Default)]
+pub struct TokenBucketLink<Packet> {
+    in_stream: Option<PacketStream<Packet>>,
+    rate: Option<u64>,
+    burst: Option<u64>,
+    packet_cost: Option<PacketCost<Packet>>,
+    non_conforming: Option<NonConforming>,
+    queue_capacity: usize,
+}
+
+impl<Packet> TokenBucketLink<Packet> {
+    pub fn new() -> Self {
+        TokenBucketLink {
+            in_stream: None,
+            rate: None,
+            burst: None,
+            packet_cost: None,
+            non_conforming: None,
+            queue_capacity: 10,
+        }
+    }
+
+    /// Tokens added to the bucket per second.
+    pub fn rate(self, rate: u64) -> Self {
+        assert!(rate > 0, "TokenBucketLink rate: {}, must be > 0", rate);
+
+        TokenBucketLink {
+            in_stream: self.in_stream,
+            rate: Some(rate),
+            burst: self.burst,
+            packet_cost: self.packet_cost,
+            non_conforming: self.non_conforming,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Most tokens the bucket may hold.
+    pub fn burst(self, burst: u64) -> Self {
+        assert!(burst > 0, "TokenBucketLink burst: {}, must be > 0", burst);
+
+        TokenBucketLink {
+            in_stream: self.in_stream,
+            rate: self.rate,
+            burst: Some(burst),
+            packet_cost: self.packet_cost,
+            non_conforming: self.non_conforming,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Changes how many tokens each packet costs, default is 1 per packet.
+    pub fn packet_cost(self, packet_cost: PacketCost<Packet>) -> Self {
+        TokenBucketLink {
+            in_stream: self.in_stream,
+            rate: self.rate,
+            burst: self.burst,
+            packet_cost: Some(packet_cost),
+            non_conforming: self.non_conforming,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Changes what is done with non-conforming packets, default is `NonConforming::Drop`.
+    pub fn non_conforming(self, non_conforming: NonConforming) -> Self {
+        TokenBucketLink {
+            in_stream: self.in_stream,
+            rate: self.rate,
+            burst: self.burst,
+            packet_cost: self.packet_cost,
+            non_conforming: Some(non_conforming),
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Changes queue_capacity, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "TokenBucketLink queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        TokenBucketLink {
+            in_stream: self.in_stream,
+            rate: self.rate,
+            burst: self.burst,
+            packet_cost: self.packet_cost,
+            non_conforming: self.non_conforming,
+            queue_capacity,
+        }
+    }
+}
+
+impl<Packet: Send + 'static> LinkBuilder<Packet, Packet> for TokenBucketLink<Packet> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "TokenBucketLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("TokenBucketLink may only take 1 input stream")
+        }
+
+        TokenBucketLink {
+            in_stream: Some(in_streams.remove(0)),
+            rate: self.rate,
+            burst: self.burst,
+            packet_cost: self.packet_cost,
+            non_conforming: self.non_conforming,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("TokenBucketLink may only take 1 input stream")
+        }
+
+        TokenBucketLink {
+            in_stream: Some(in_stream),
+            rate: self.rate,
+            burst: self.burst,
+            packet_cost: self.packet_cost,
+            non_conforming: self.non_conforming,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn build_link(self) -> Link<Packet> {
+        match (self.in_stream, self.rate, self.burst) {
+            (None, _, _) => panic!("Cannot build link! Missing input stream"),
+            (_, None, _) => panic!("Cannot build link! Missing rate"),
+            (_, _, None) => panic!("Cannot build link! Missing burst"),
+            (Some(in_stream), Some(rate), Some(burst)) => {
+                let (to_egressor, from_ingressor) =
+                    crossbeam_channel::bounded::<Option<Packet>>(self.queue_capacity);
+                let task_park: Arc<AtomicCell<TaskParkState>> =
+                    Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+                let ingressor = TokenBucketIngressor {
+                    input_stream: in_stream,
+                    to_egressor,
+                    task_park: Arc::clone(&task_park),
+                    bucket: TokenBucket::new(rate, burst),
+                    packet_cost: self.packet_cost,
+                    non_conforming: self.non_conforming.unwrap_or(NonConforming::Drop),
+                    held_packet: None,
+                    refill_timer: None,
+                };
+                let egressor = QueueEgressor::new(from_ingressor, task_park);
+
+                (vec![Box::new(ingressor)], vec![Box::new(egressor)])
+            }
+        }
+    }
+}
+
+struct TokenBucketIngressor<Packet> {
+    input_stream: PacketStream<Packet>,
+    to_egressor: Sender<Option<Packet>>,
+    task_park: Arc<AtomicCell<TaskParkState>>,
+    bucket: TokenBucket,
+    packet_cost: Option<PacketCost<Packet>>,
+    non_conforming: NonConforming,
+    /// A packet, and its cost, waiting on tokens. Only used with `NonConforming::Queue`.
+    held_packet: Option<(Packet, u64)>,
+    refill_timer: Option<Pin<Box<Sleep>>>,
+}
+
+impl<Packet> Unpin for TokenBucketIngressor<Packet> {}
+
+impl<Packet> Future for TokenBucketIngressor<Packet> {
+    type Output = ();
+
+    /// Same logic as RateLimitIngressor, except packets may cost more than one token, and when set
+    /// to `NonConforming::Drop` packets that find too few tokens are dropped rather than held. When
+    /// queueing, a packet that costs more than `burst` could never conform, so it is let through
+    /// once the bucket is full instead of stalling the link forever.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let ingressor = Pin::into_inner(self);
+        loop {
+            if ingressor.to_egressor.is_full() {
+                park_and_wake(&ingressor.task_park, cx.waker().clone());
+                return Poll::Pending;
+            }
+
+            let (packet, cost) = match ingressor.held_packet.take() {
+                Some(held) => held,
+                None => match ready!(Pin::new(&mut ingressor.input_stream).poll_next(cx)) {
+                    Some(packet) => {
+                        let cost = match &ingressor.packet_cost {
+                            Some(packet_cost) => packet_cost(&packet),
+                            None => 1,
+                        };
+                        (packet, cost)
+                    }
+                    None => {
+                        ingressor.to_egressor.try_send(None).expect(
+                            "TokenBucketIngressor::Poll::Ready(None) try_send to_egressor shouldn't fail",
+                        );
+                        die_and_wake(&ingressor.task_park);
+                        return Poll::Ready(());
+                    }
+                },
+            };
+
+            let now = Instant::now();
+            match ingressor.non_conforming {
+                NonConforming::Drop => {
+                    if !ingressor.bucket.try_consume(now, cost) {
+                        continue;
+                    }
+                }
+                NonConforming::Queue => {
+                    let cost = cost.min(ingressor.bucket.burst());
+                    if !ingressor.bucket.try_consume(now, cost) {
+                        ingressor.held_packet = Some((packet, cost));
+                        let ready_at = now + ingressor.bucket.time_until(now, cost);
+                        let timer = ingressor
+                            .refill_timer
+                            .get_or_insert_with(|| Box::pin(sleep_until(ready_at.into())));
+                        timer.as_mut().reset(ready_at.into());
+                        match timer.as_mut().poll(cx) {
+                            Poll::Ready(()) => continue,
+                            Poll::Pending => return Poll::Pending,
+                        }
+                    }
+                }
+            }
+
+            ingressor.to_egressor.try_send(Some(packet)).expect(
+                "TokenBucketIngressor::Poll::Ready(Some(val)) try_send to_egressor shouldn't fail",
+            );
+            unpark_and_wake(&ingressor.task_park);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
+    use core::time;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_rate() {
+        TokenBucketLink::<i32>::new()
+            .ingressor(immediate_stream(vec![]))
+            .burst(10)
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_burst() {
+        TokenBucketLink::<i32>::new()
+            .ingressor(immediate_stream(vec![]))
+            .rate(10)
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_with_zero_rate() {
+        TokenBucketLink::<i32>::new().rate(0);
+    }
+
+    #[test]
+    fn drops_packets_beyond_burst() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = TokenBucketLink::new()
+                .ingressor(immediate_stream(0..100))
+                .rate(1)
+                .burst(5)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn drops_by_byte_cost() {
+        let packets = vec![
+            vec![0u8; 600],
+            vec![1u8; 300],
+            vec![2u8; 200],
+            vec![3u8; 100],
+        ];
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = TokenBucketLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .rate(1)
+                .burst(1000)
+                .packet_cost(Box::new(|packet: &Vec<u8>| packet.len() as u64))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(
+            results[0],
+            vec![packets[0].clone(), packets[1].clone(), packets[3].clone()]
+        );
+    }
+
+    #[test]
+    fn conforming_traffic_is_untouched() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let packet_generator = PacketIntervalGenerator::new(
+                time::Duration::from_millis(10),
+                vec![0, 1, 2, 3, 4, 5].into_iter(),
+            );
+
+            let link = TokenBucketLink::new()
+                .ingressor(Box::new(packet_generator))
+                .rate(1000)
+                .burst(1)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn queues_non_conforming_packets() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = TokenBucketLink::new()
+                .ingressor(immediate_stream(0..20))
+                .rate(200)
+                .burst(5)
+                .non_conforming(NonConforming::Queue)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], (0..20).collect::<Vec<i32>>());
+    }
+
+    #[test]
+    fn queues_packets_larger_than_burst() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = TokenBucketLink::new()
+                .ingressor(immediate_stream(vec![vec![0u8; 50], vec![1u8; 50]]))
+                .rate(1000)
+                .burst(10)
+                .packet_cost(Box::new(|packet: &Vec<u8>| packet.len() as u64))
+                .non_conforming(NonConforming::Queue)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0].len(), 2);
+    }
+}
@@ -0,0 +1,396 @@
+use crate::classifier::TryClassifier;
+use crate::link::utils::error_port::ErrorPort;
+use crate::link::utils::task_park::*;
+use crate::link::{primitive::QueueEgressor, Link, LinkBuilder, PacketStream};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::Sender;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// A packet that failed to classify, and the reason the classifier gave.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Misclassified<Packet, Reason> {
+    pub packet: Packet,
+    pub reason: Reason,
+}
+
+/// Like ClassifyLink, but with a TryClassifier, so packets that fail to classify are kept apart
+/// rather than forced into a normal class. They are emitted, along with the reason, on an error
+/// egressor taken from the builder with `error_egressor`. Like a MirrorLink's mirrors, the error
+/// egressor is for diagnostics, so never holds up traffic: errors are dropped if its queue is
+/// full, or if it was never taken.
+pub struct TryClassifyLink<C: TryClassifier> {
+    in_stream: Option<PacketStream<C::Packet>>,
+    classifier: Option<C>,
+    dispatcher: Option<Box<dyn Fn(C::Class) -> usize + Send + Sync + 'static>>,
+    queue_capacity: usize,
+    num_egressors: Option<usize>,
+    to_error_egressor: Option<ErrorPort<Misclassified<C::Packet, C::Reason>>>,
+}
+
+impl<C: TryClassifier> Default for TryClassifyLink<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: TryClassifier> TryClassifyLink<C> {
+    pub fn new() -> Self {
+        TryClassifyLink {
+            in_stream: None,
+            classifier: None,
+            dispatcher: None,
+            queue_capacity: 10,
+            num_egressors: None,
+            to_error_egressor: None,
+        }
+    }
+
+    pub fn classifier(self, classifier: C) -> Self {
+        TryClassifyLink {
+            in_stream: self.in_stream,
+            classifier: Some(classifier),
+            dispatcher: self.dispatcher,
+            queue_capacity: self.queue_capacity,
+            num_egressors: self.num_egressors,
+            to_error_egressor: self.to_error_egressor,
+        }
+    }
+
+    pub fn dispatcher(
+        self,
+        dispatcher: Box<dyn Fn(C::Class) -> usize + Send + Sync + 'static>,
+    ) -> Self {
+        TryClassifyLink {
+            in_stream: self.in_stream,
+            classifier: self.classifier,
+            dispatcher: Some(dispatcher),
+            queue_capacity: self.queue_capacity,
+            num_egressors: self.num_egressors,
+            to_error_egressor: self.to_error_egressor,
+        }
+    }
+
+    /// Changes queue_capacity, of each egressor, default value is 10. The error egressor's queue
+    /// has the capacity set when it is taken.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "TryClassifyLink queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        TryClassifyLink {
+            in_stream: self.in_stream,
+            classifier: self.classifier,
+            dispatcher: self.dispatcher,
+            queue_capacity,
+            num_egressors: self.num_egressors,
+            to_error_egressor: self.to_error_egressor,
+        }
+    }
+
+    pub fn num_egressors(self, num_egressors: usize) -> Self {
+        assert!(
+            num_egressors > 0,
+            "TryClassifyLink num_egressors: {}, must be > 0",
+            num_egressors
+        );
+
+        TryClassifyLink {
+            in_stream: self.in_stream,
+            classifier: self.classifier,
+            dispatcher: self.dispatcher,
+            queue_capacity: self.queue_capacity,
+            num_egressors: Some(num_egressors),
+            to_error_egressor: self.to_error_egressor,
+        }
+    }
+
+    /// Returns the egressor packets that fail to classify are emitted on. It may only be taken
+    /// once, before the link is built.
+    pub fn error_egressor(&mut self) -> PacketStream<Misclassified<C::Packet, C::Reason>>
+    where
+        C::Packet: 'static,
+        C::Reason: Send + 'static,
+    {
+        if self.to_error_egressor.is_some() {
+            panic!("TryClassifyLink error_egressor may only be taken once")
+        }
+
+        let (to_error_egressor, error_egressor) = ErrorPort::new(self.queue_capacity);
+        self.to_error_egressor = Some(to_error_egressor);
+        error_egressor
+    }
+}
+
+impl<C: TryClassifier + Send + 'static> LinkBuilder<C::Packet, C::Packet> for TryClassifyLink<C>
+where
+    C::Reason: Send,
+{
+    fn ingressors(self, mut in_streams: Vec<PacketStream<C::Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "TryClassifyLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("TryClassifyLink may only take 1 input stream")
+        }
+
+        TryClassifyLink {
+            in_stream: Some(in_streams.remove(0)),
+            classifier: self.classifier,
+            dispatcher: self.dispatcher,
+            queue_capacity: self.queue_capacity,
+            num_egressors: self.num_egressors,
+            to_error_egressor: self.to_error_egressor,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<C::Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("TryClassifyLink may only take 1 input stream")
+        }
+
+        TryClassifyLink {
+            in_stream: Some(in_stream),
+            classifier: self.classifier,
+            dispatcher: self.dispatcher,
+            queue_capacity: self.queue_capacity,
+            num_egressors: self.num_egressors,
+            to_error_egressor: self.to_error_egressor,
+        }
+    }
+
+    fn build_link(self) -> Link<C::Packet> {
+        match (
+            self.in_stream,
+            self.classifier,
+            self.dispatcher,
+            self.num_egressors,
+        ) {
+            (None, _, _, _) => panic!("Cannot build link! Missing input streams"),
+            (_, None, _, _) => panic!("Cannot build link! Missing classifier"),
+            (_, _, None, _) => panic!("Cannot build link! Missing dispatcher"),
+            (_, _, _, None) => panic!("Cannot build link! Missing num_egressors"),
+            (Some(in_stream), Some(classifier), Some(dispatcher), Some(num_egressors)) => {
+                let mut to_egressors: Vec<Sender<Option<C::Packet>>> = Vec::new();
+                let mut egressors: Vec<PacketStream<C::Packet>> = Vec::new();
+                let mut task_parks: Vec<Arc<AtomicCell<TaskParkState>>> = Vec::new();
+
+                for _ in 0..num_egressors {
+                    let (to_egressor, from_ingressor) =
+                        crossbeam_channel::bounded::<Option<C::Packet>>(self.queue_capacity);
+                    let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+                    let egressor = QueueEgressor::new(from_ingressor, Arc::clone(&task_park));
+
+                    to_egressors.push(to_egressor);
+                    egressors.push(Box::new(egressor));
+                    task_parks.push(task_park);
+                }
+
+                let ingressor = TryClassifyIngressor {
+                    input_stream: in_stream,
+                    dispatcher,
+                    to_egressors,
+                    classifier,
+                    task_parks,
+                    to_error_egressor: self.to_error_egressor,
+                    finishing: false,
+                };
+                (vec![Box::new(ingressor)], egressors)
+            }
+        }
+    }
+}
+
+pub struct TryClassifyIngressor<C: TryClassifier> {
+    input_stream: PacketStream<C::Packet>,
+    dispatcher: Box<dyn Fn(C::Class) -> usize + Send + Sync + 'static>,
+    to_egressors: Vec<Sender<Option<C::Packet>>>,
+    classifier: C,
+    task_parks: Vec<Arc<AtomicCell<TaskParkState>>>,
+    to_error_egressor: Option<ErrorPort<Misclassified<C::Packet, C::Reason>>>,
+    /// Set once the input has finished and the normal egressors have been told so.
+    finishing: bool,
+}
+
+impl<C: TryClassifier> Unpin for TryClassifyIngressor<C> {}
+
+impl<C: TryClassifier> TryClassifyIngressor<C> {
+    fn finish_errors(&mut self, cx: &mut Context) -> Poll<()> {
+        match &self.to_error_egressor {
+            Some(to_error_egressor) => to_error_egressor.finish(cx),
+            None => Poll::Ready(()),
+        }
+    }
+}
+
+impl<C: TryClassifier> Future for TryClassifyIngressor<C> {
+    type Output = ();
+
+    /// Same logic as ClassifyIngressor, except that packets failing to classify are sent to the
+    /// error egressor, if it has room. When the input finishes, we may have to wait on the error
+    /// egressor to have room for the `None` that tears it down.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let ingressor = Pin::into_inner(self);
+        if ingressor.finishing {
+            return ingressor.finish_errors(cx);
+        }
+
+        loop {
+            for (port, to_egressor) in ingressor.to_egressors.iter().enumerate() {
+                if to_egressor.is_full() {
+                    park_and_wake(&ingressor.task_parks[port], cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+
+            match ready!(Pin::new(&mut ingressor.input_stream).poll_next(cx)) {
+                None => {
+                    for to_egressor in ingressor.to_egressors.iter() {
+                        to_egressor.try_send(None).expect(
+                            "TryClassifyIngressor::Drop: try_send to_egressor shouldn't fail",
+                        );
+                    }
+                    for task_park in ingressor.task_parks.iter() {
+                        die_and_wake(task_park);
+                    }
+                    ingressor.finishing = true;
+                    return ingressor.finish_errors(cx);
+                }
+                Some(packet) => match ingressor.classifier.try_classify(&packet) {
+                    Ok(class) => {
+                        let port = (ingressor.dispatcher)(class);
+                        if port >= ingressor.to_egressors.len() {
+                            panic!("Tried to access invalid port: {}", port);
+                        }
+                        if let Err(err) = ingressor.to_egressors[port].try_send(Some(packet)) {
+                            panic!(
+                                "Error in to_egressors[{}] sender, have nowhere to put packet: {:?}",
+                                port, err
+                            );
+                        }
+                        unpark_and_wake(&ingressor.task_parks[port]);
+                    }
+                    Err(reason) => {
+                        if let Some(to_error_egressor) = &ingressor.to_error_egressor {
+                            to_error_egressor.send(Misclassified { packet, reason });
+                        }
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use std::num::ParseIntError;
+
+    /// Classifies numbers written as strings by whether they are even.
+    struct EvenString {}
+
+    impl TryClassifier for EvenString {
+        type Packet = &'static str;
+        type Class = bool;
+        type Reason = ParseIntError;
+
+        fn try_classify(&self, packet: &Self::Packet) -> Result<Self::Class, Self::Reason> {
+            packet.parse::<i32>().map(|number| number % 2 == 0)
+        }
+    }
+
+    fn even_string_link() -> TryClassifyLink<EvenString> {
+        TryClassifyLink::new()
+            .ingressor(immediate_stream(vec!["0", "1", "two", "420", "", "1337"]))
+            .classifier(EvenString {})
+            .dispatcher(Box::new(|is_even| if is_even { 0 } else { 1 }))
+            .num_egressors(2)
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_classifier() {
+        TryClassifyLink::<EvenString>::new()
+            .ingressor(immediate_stream(vec![]))
+            .dispatcher(Box::new(|is_even| if is_even { 0 } else { 1 }))
+            .num_egressors(2)
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_error_egressor_taken_twice() {
+        let mut link = TryClassifyLink::<EvenString>::new();
+        let _first = link.error_egressor();
+        let _second = link.error_egressor();
+    }
+
+    #[test]
+    fn sends_errors_to_error_egressor() {
+        let runtime = initialize_runtime();
+        let (results, errors) = runtime.block_on(async {
+            let mut link = even_string_link();
+            let error_egressor = link.error_egressor();
+            let errors = tokio::spawn(error_egressor.collect::<Vec<_>>());
+
+            let results = run_link(link.build_link()).await;
+            (results, errors.await.unwrap())
+        });
+        assert_eq!(results[0], vec!["0", "420"]);
+        assert_eq!(results[1], vec!["1", "1337"]);
+
+        let packets: Vec<&str> = errors.iter().map(|error| error.packet).collect();
+        assert_eq!(packets, vec!["two", ""]);
+        assert_eq!(errors[0].reason, "two".parse::<i32>().unwrap_err());
+    }
+
+    #[test]
+    fn drops_errors_without_error_egressor() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(run_link(even_string_link().build_link()));
+        assert_eq!(results[0], vec!["0", "420"]);
+        assert_eq!(results[1], vec!["1", "1337"]);
+    }
+
+    #[test]
+    fn full_error_egressor_does_not_hold_up_traffic() {
+        let runtime = initialize_runtime();
+        let (results, errors) = runtime.block_on(async {
+            let mut link = TryClassifyLink::new()
+                .ingressor(immediate_stream(vec!["a", "b", "c", "0", "d", "1"]))
+                .classifier(EvenString {})
+                .dispatcher(Box::new(|is_even| if is_even { 0 } else { 1 }))
+                .num_egressors(2)
+                .queue_capacity(2);
+            let mut error_egressor = link.error_egressor();
+            let (mut runnables, egressors) = link.build_link();
+            let ingressor = tokio::spawn(runnables.remove(0));
+
+            // Nobody reads the errors until traffic has passed through.
+            let mut results = vec![];
+            for egressor in egressors {
+                results.push(egressor.collect::<Vec<_>>().await);
+            }
+            let mut errors = vec![];
+            while let Some(error) = error_egressor.next().await {
+                errors.push(error.packet);
+            }
+            ingressor.await.unwrap();
+            (results, errors)
+        });
+        assert_eq!(results[0], vec!["0"]);
+        assert_eq!(results[1], vec!["1"]);
+        assert_eq!(errors, vec!["a", "b"]);
+    }
+}
@@ -0,0 +1,277 @@
+use crate::link::primitive::QueueEgressor;
+use crate::link::utils::error_port::ErrorPort;
+use crate::link::utils::task_park::*;
+use crate::link::{Link, LinkBuilder, PacketStream};
+use crate::processor::TryProcessor;
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::Sender;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Like QueueLink, but with a TryProcessor. When processing fails, the input packet and the error
+/// are emitted as a tuple on an error egressor taken from the builder with `error_egressor`. Since
+/// the processor consumes its input, each packet is cloned before processing while the error
+/// egressor is taken. Errors never hold up traffic: they are dropped if the error egressor's queue
+/// is full, or if it was never taken.
+pub struct TryProcessLink<P: TryProcessor> {
+    in_stream: Option<PacketStream<P::Input>>,
+    processor: Option<P>,
+    queue_capacity: usize,
+    to_error_egressor: Option<ErrorPort<(P::Input, P::Error)>>,
+}
+
+impl<P: TryProcessor> Default for TryProcessLink<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: TryProcessor> TryProcessLink<P> {
+    pub fn new() -> Self {
+        TryProcessLink {
+            in_stream: None,
+            processor: None,
+            queue_capacity: 10,
+            to_error_egressor: None,
+        }
+    }
+
+    pub fn processor(self, processor: P) -> Self {
+        TryProcessLink {
+            in_stream: self.in_stream,
+            processor: Some(processor),
+            queue_capacity: self.queue_capacity,
+            to_error_egressor: self.to_error_egressor,
+        }
+    }
+
+    /// Changes queue_capacity, default value is 10. The error egressor's queue has the capacity
+    /// set when it is taken.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "TryProcessLink queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        TryProcessLink {
+            in_stream: self.in_stream,
+            processor: self.processor,
+            queue_capacity,
+            to_error_egressor: self.to_error_egressor,
+        }
+    }
+
+    /// Returns the egressor failed packets are emitted on, with their errors. It may only be
+    /// taken once, before the link is built.
+    pub fn error_egressor(&mut self) -> PacketStream<(P::Input, P::Error)>
+    where
+        P::Input: 'static,
+        P::Error: 'static,
+    {
+        if self.to_error_egressor.is_some() {
+            panic!("TryProcessLink error_egressor may only be taken once")
+        }
+
+        let (to_error_egressor, error_egressor) = ErrorPort::new(self.queue_capacity);
+        self.to_error_egressor = Some(to_error_egressor);
+        error_egressor
+    }
+}
+
+impl<P: TryProcessor + Send + 'static> LinkBuilder<P::Input, P::Output> for TryProcessLink<P> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<P::Input>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "TryProcessLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("TryProcessLink may only take 1 input stream")
+        }
+
+        TryProcessLink {
+            in_stream: Some(in_streams.remove(0)),
+            processor: self.processor,
+            queue_capacity: self.queue_capacity,
+            to_error_egressor: self.to_error_egressor,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<P::Input>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("TryProcessLink may only take 1 input stream")
+        }
+
+        TryProcessLink {
+            in_stream: Some(in_stream),
+            processor: self.processor,
+            queue_capacity: self.queue_capacity,
+            to_error_egressor: self.to_error_egressor,
+        }
+    }
+
+    fn build_link(self) -> Link<P::Output> {
+        match (self.in_stream, self.processor) {
+            (None, _) => panic!("Cannot build link! Missing input stream"),
+            (_, None) => panic!("Cannot build link! Missing processor"),
+            (Some(in_stream), Some(processor)) => {
+                let (to_egressor, from_ingressor) =
+                    crossbeam_channel::bounded::<Option<P::Output>>(self.queue_capacity);
+                let task_park: Arc<AtomicCell<TaskParkState>> =
+                    Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+                let ingressor = TryProcessIngressor {
+                    input_stream: in_stream,
+                    to_egressor,
+                    task_park: Arc::clone(&task_park),
+                    processor,
+                    to_error_egressor: self.to_error_egressor,
+                    finishing: false,
+                };
+                let egressor = QueueEgressor::new(from_ingressor, task_park);
+
+                (vec![Box::new(ingressor)], vec![Box::new(egressor)])
+            }
+        }
+    }
+}
+
+pub struct TryProcessIngressor<P: TryProcessor> {
+    input_stream: PacketStream<P::Input>,
+    to_egressor: Sender<Option<P::Output>>,
+    task_park: Arc<AtomicCell<TaskParkState>>,
+    processor: P,
+    to_error_egressor: Option<ErrorPort<(P::Input, P::Error)>>,
+    /// Set once the input has finished and the egressor has been told so.
+    finishing: bool,
+}
+
+impl<P: TryProcessor> Unpin for TryProcessIngressor<P> {}
+
+impl<P: TryProcessor> TryProcessIngressor<P> {
+    fn finish_errors(&mut self, cx: &mut Context) -> Poll<()> {
+        match &self.to_error_egressor {
+            Some(to_error_egressor) => to_error_egressor.finish(cx),
+            None => Poll::Ready(()),
+        }
+    }
+
+    fn process(&mut self, packet: P::Input) {
+        let result = match &self.to_error_egressor {
+            Some(to_error_egressor) => match self.processor.try_process(packet.clone()) {
+                Ok(result) => result,
+                Err(error) => {
+                    to_error_egressor.send((packet, error));
+                    None
+                }
+            },
+            None => self.processor.try_process(packet).unwrap_or(None),
+        };
+
+        if let Some(packet) = result {
+            self.to_egressor.try_send(Some(packet)).expect(
+                "TryProcessIngressor::Poll::Ready(Some(val)) try_send to_egressor shouldn't fail",
+            );
+            unpark_and_wake(&self.task_park);
+        }
+    }
+}
+
+impl<P: TryProcessor> Future for TryProcessIngressor<P> {
+    type Output = ();
+
+    /// Same logic as QueueIngressor, except failed packets are sent to the error egressor, if it
+    /// has room. When the input finishes, we may have to wait on the error egressor to have room
+    /// for the `None` that tears it down.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let ingressor = Pin::into_inner(self);
+        if ingressor.finishing {
+            return ingressor.finish_errors(cx);
+        }
+
+        loop {
+            if ingressor.to_egressor.is_full() {
+                park_and_wake(&ingressor.task_park, cx.waker().clone());
+                return Poll::Pending;
+            }
+
+            match ready!(Pin::new(&mut ingressor.input_stream).poll_next(cx)) {
+                None => {
+                    ingressor.to_egressor.try_send(None).expect(
+                        "TryProcessIngressor::Poll::Ready(None) try_send to_egressor shouldn't fail",
+                    );
+                    die_and_wake(&ingressor.task_park);
+                    ingressor.finishing = true;
+                    return ingressor.finish_errors(cx);
+                }
+                Some(packet) => ingressor.process(packet),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use std::num::ParseIntError;
+
+    /// Parses numbers written as strings, dropping zeros.
+    struct ParseNonZero {}
+
+    impl TryProcessor for ParseNonZero {
+        type Input = &'static str;
+        type Output = i32;
+        type Error = ParseIntError;
+
+        fn try_process(&mut self, packet: Self::Input) -> Result<Option<i32>, ParseIntError> {
+            let number = packet.parse::<i32>()?;
+            Ok(if number == 0 { None } else { Some(number) })
+        }
+    }
+
+    fn parse_link() -> TryProcessLink<ParseNonZero> {
+        TryProcessLink::new()
+            .ingressor(immediate_stream(vec!["1", "two", "0", "3", "", "420"]))
+            .processor(ParseNonZero {})
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_processor() {
+        TryProcessLink::<ParseNonZero>::new()
+            .ingressor(immediate_stream(vec![]))
+            .build_link();
+    }
+
+    #[test]
+    fn sends_errors_to_error_egressor() {
+        let runtime = initialize_runtime();
+        let (results, errors) = runtime.block_on(async {
+            let mut link = parse_link();
+            let error_egressor = link.error_egressor();
+            let errors = tokio::spawn(error_egressor.collect::<Vec<_>>());
+
+            let results = run_link(link.build_link()).await;
+            (results, errors.await.unwrap())
+        });
+        assert_eq!(results[0], vec![1, 3, 420]);
+
+        let inputs: Vec<&str> = errors.iter().map(|error| error.0).collect();
+        assert_eq!(inputs, vec!["two", ""]);
+        assert_eq!(errors[0].1, "two".parse::<i32>().unwrap_err());
+    }
+
+    #[test]
+    fn drops_errors_without_error_egressor() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(run_link(parse_link().build_link()));
+        assert_eq!(results[0], vec![1, 3, 420]);
+    }
+}
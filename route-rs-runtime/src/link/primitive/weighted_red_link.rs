@@ -0,0 +1,308 @@
+use crate::classifier::Classifier;
+use crate::link::primitive::QueueEgressor;
+use crate::link::utils::red::RedCurve;
+use crate::link::utils::task_park::*;
+use crate::link::{Link, LinkBuilder, PacketStream};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::Sender;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// A queue shared by several traffic classes, where each class gets its own Random Early Detection
+/// curve. The classifier and dispatcher pick which of the `curves` applies to a packet, and that
+/// curve decides, based on how full the shared queue is, whether the packet is dropped or enqueued.
+/// Giving low priority classes lower thresholds means they are shed first as the queue fills.
+#[derive(Default)]
+pub struct WeightedRedLink<C: Classifier> {
+    in_stream: Option<PacketStream<C::Packet>>,
+    classifier: Option<C>,
+    dispatcher: Option<Box<dyn Fn(C::Class) -> usize + Send + Sync + 'static>>,
+    curves: Option<Vec<RedCurve>>,
+    queue_capacity: usize,
+}
+
+impl<C: Classifier> WeightedRedLink<C> {
+    pub fn new() -> Self {
+        WeightedRedLink {
+            in_stream: None,
+            classifier: None,
+            dispatcher: None,
+            curves: None,
+            queue_capacity: 10,
+        }
+    }
+
+    pub fn classifier(self, classifier: C) -> Self {
+        WeightedRedLink {
+            in_stream: self.in_stream,
+            classifier: Some(classifier),
+            dispatcher: self.dispatcher,
+            curves: self.curves,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Maps a class to the index of the curve in `curves` that applies to it.
+    pub fn dispatcher(
+        self,
+        dispatcher: Box<dyn Fn(C::Class) -> usize + Send + Sync + 'static>,
+    ) -> Self {
+        WeightedRedLink {
+            in_stream: self.in_stream,
+            classifier: self.classifier,
+            dispatcher: Some(dispatcher),
+            curves: self.curves,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    pub fn curves(self, curves: Vec<RedCurve>) -> Self {
+        assert!(
+            !curves.is_empty(),
+            "WeightedRedLink curves must be non-empty"
+        );
+
+        WeightedRedLink {
+            in_stream: self.in_stream,
+            classifier: self.classifier,
+            dispatcher: self.dispatcher,
+            curves: Some(curves),
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Changes queue_capacity, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "WeightedRedLink queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        WeightedRedLink {
+            in_stream: self.in_stream,
+            classifier: self.classifier,
+            dispatcher: self.dispatcher,
+            curves: self.curves,
+            queue_capacity,
+        }
+    }
+}
+
+impl<C: Classifier + Send + 'static> LinkBuilder<C::Packet, C::Packet> for WeightedRedLink<C> {
+    fn ingressors(self, mut in_streams: Vec<PacketStream<C::Packet>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "WeightedRedLink may only take 1 input stream"
+        );
+
+        if self.in_stream.is_some() {
+            panic!("WeightedRedLink may only take 1 input stream")
+        }
+
+        WeightedRedLink {
+            in_stream: Some(in_streams.remove(0)),
+            classifier: self.classifier,
+            dispatcher: self.dispatcher,
+            curves: self.curves,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<C::Packet>) -> Self {
+        if self.in_stream.is_some() {
+            panic!("WeightedRedLink may only take 1 input stream")
+        }
+
+        WeightedRedLink {
+            in_stream: Some(in_stream),
+            classifier: self.classifier,
+            dispatcher: self.dispatcher,
+            curves: self.curves,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn build_link(self) -> Link<C::Packet> {
+        match (
+            self.in_stream,
+            self.classifier,
+            self.dispatcher,
+            self.curves,
+        ) {
+            (None, _, _, _) => panic!("Cannot build link! Missing input streams"),
+            (_, None, _, _) => panic!("Cannot build link! Missing classifier"),
+            (_, _, None, _) => panic!("Cannot build link! Missing dispatcher"),
+            (_, _, _, None) => panic!("Cannot build link! Missing curves"),
+            (Some(in_stream), Some(classifier), Some(dispatcher), Some(curves)) => {
+                let (to_egressor, from_ingressor) =
+                    crossbeam_channel::bounded::<Option<C::Packet>>(self.queue_capacity);
+                let task_park: Arc<AtomicCell<TaskParkState>> =
+                    Arc::new(AtomicCell::new(TaskParkState::Empty));
+
+                let ingressor = WeightedRedIngressor {
+                    input_stream: in_stream,
+                    to_egressor,
+                    classifier,
+                    dispatcher,
+                    curves,
+                    task_park: Arc::clone(&task_park),
+                };
+                let egressor = QueueEgressor::new(from_ingressor, task_park);
+
+                (vec![Box::new(ingressor)], vec![Box::new(egressor)])
+            }
+        }
+    }
+}
+
+struct WeightedRedIngressor<C: Classifier> {
+    input_stream: PacketStream<C::Packet>,
+    to_egressor: Sender<Option<C::Packet>>,
+    classifier: C,
+    dispatcher: Box<dyn Fn(C::Class) -> usize + Send + Sync + 'static>,
+    curves: Vec<RedCurve>,
+    task_park: Arc<AtomicCell<TaskParkState>>,
+}
+
+impl<C: Classifier> Unpin for WeightedRedIngressor<C> {}
+
+impl<C: Classifier> Future for WeightedRedIngressor<C> {
+    type Output = ();
+
+    /// Same logic as QueueIngressor, except that before a packet is enqueued, the curve for its
+    /// class is consulted with the current depth of the queue, and the packet may be dropped.
+    /// If a curve allows the queue to fill completely, we fall back to parking until the egressor
+    /// makes room.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let ingressor = Pin::into_inner(self);
+        loop {
+            if ingressor.to_egressor.is_full() {
+                park_and_wake(&ingressor.task_park, cx.waker().clone());
+                return Poll::Pending;
+            }
+
+            let packet_option: Option<C::Packet> =
+                ready!(Pin::new(&mut ingressor.input_stream).poll_next(cx));
+
+            match packet_option {
+                None => {
+                    ingressor.to_egressor.try_send(None).expect(
+                        "WeightedRedIngressor::Poll::Ready(None) try_send to_egressor shouldn't fail",
+                    );
+                    die_and_wake(&ingressor.task_park);
+                    return Poll::Ready(());
+                }
+                Some(packet) => {
+                    let class = ingressor.classifier.classify(&packet);
+                    let index = (ingressor.dispatcher)(class);
+                    if index >= ingressor.curves.len() {
+                        panic!("Tried to access invalid curve: {}", index);
+                    }
+
+                    let queue_len = ingressor.to_egressor.len() as f64;
+                    if ingressor.curves[index].should_drop(queue_len) {
+                        continue;
+                    }
+
+                    ingressor.to_egressor.try_send(Some(packet)).expect(
+                        "WeightedRedIngressor::Poll::Ready(Some(val)) try_send to_egressor shouldn't fail",
+                    );
+                    unpark_and_wake(&ingressor.task_park);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classifier::Even;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    /// Even packets are high priority, odd packets are low priority.
+    fn priority_link(packets: Vec<i32>) -> WeightedRedLink<Even> {
+        WeightedRedLink::new()
+            .ingressor(immediate_stream(packets))
+            .classifier(Even::new())
+            .dispatcher(Box::new(|even| if even { 0 } else { 1 }))
+            .curves(vec![RedCurve::new(40, 80, 0.1), RedCurve::new(10, 40, 0.5)])
+            .queue_capacity(100)
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_input_streams() {
+        WeightedRedLink::new()
+            .classifier(Even::new())
+            .dispatcher(Box::new(|_| 0))
+            .curves(vec![RedCurve::new(1, 2, 0.5)])
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_curves() {
+        WeightedRedLink::new()
+            .ingressor(immediate_stream(vec![]))
+            .classifier(Even::new())
+            .dispatcher(Box::new(|_| 0))
+            .build_link();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_with_empty_curves() {
+        WeightedRedLink::<Even>::new().curves(vec![]);
+    }
+
+    #[test]
+    fn no_drops_below_thresholds() {
+        let packets: Vec<i32> = (0..10).collect();
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = priority_link(packets.clone()).build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], packets);
+    }
+
+    #[test]
+    fn low_priority_dropped_more_as_queue_fills() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (mut runnables, mut egressors) = priority_link((0..2000).collect()).build_link();
+
+            // Let the queue fill up before anything is drained from it.
+            tokio::spawn(runnables.remove(0)).await.unwrap();
+
+            let mut egressor = egressors.remove(0);
+            let mut output = vec![];
+            while let Some(packet) = egressor.next().await {
+                output.push(packet);
+            }
+            output
+        });
+
+        let high = results.iter().filter(|packet| *packet % 2 == 0).count();
+        let low = results.len() - high;
+        assert!(results.len() <= 80);
+        assert!(low <= 40);
+        assert!(
+            high > low,
+            "high priority: {} packets, low priority: {} packets",
+            high,
+            low
+        );
+        // Below the low priority min_threshold, everything is enqueued in order.
+        assert_eq!(results[..10], (0..10).collect::<Vec<i32>>()[..]);
+    }
+}
@@ -0,0 +1,308 @@
+use crate::link::primitive::JoinIngressor;
+use crate::link::utils::task_park::*;
+use crate::link::{Link, LinkBuilder, PacketStream};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::{Receiver, TryRecvError};
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Pairs up the items of two input streams, in order, emitting `(Left, Right)` tuples. For
+/// instance, to rejoin a packet stream with a stream of lookup results computed for it on a
+/// separate branch. The left stream is given with `ingressor`, and the right with
+/// `right_ingressor`. Each side buffers up to `queue_capacity` items while waiting on the other.
+/// Once either stream finishes, the rest of the other is discarded.
+#[derive(Default)]
+pub struct ZipLink<Left, Right> {
+    left_stream: Option<PacketStream<Left>>,
+    right_stream: Option<PacketStream<Right>>,
+    queue_capacity: usize,
+}
+
+impl<Left, Right> ZipLink<Left, Right> {
+    pub fn new() -> Self {
+        ZipLink {
+            left_stream: None,
+            right_stream: None,
+            queue_capacity: 10,
+        }
+    }
+
+    pub fn right_ingressor(self, right_stream: PacketStream<Right>) -> Self {
+        if self.right_stream.is_some() {
+            panic!("ZipLink may only take 1 right input stream")
+        }
+
+        ZipLink {
+            left_stream: self.left_stream,
+            right_stream: Some(right_stream),
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Changes queue_capacity, the most items buffered on each side, default value is 10.
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "ZipLink queue_capacity: {}, must be > 0",
+            queue_capacity
+        );
+
+        ZipLink {
+            left_stream: self.left_stream,
+            right_stream: self.right_stream,
+            queue_capacity,
+        }
+    }
+}
+
+impl<Left: Send + 'static, Right: Send + 'static> LinkBuilder<Left, (Left, Right)>
+    for ZipLink<Left, Right>
+{
+    fn ingressors(self, mut in_streams: Vec<PacketStream<Left>>) -> Self {
+        assert_eq!(
+            in_streams.len(),
+            1,
+            "ZipLink may only take 1 left input stream"
+        );
+
+        if self.left_stream.is_some() {
+            panic!("ZipLink may only take 1 left input stream")
+        }
+
+        ZipLink {
+            left_stream: Some(in_streams.remove(0)),
+            right_stream: self.right_stream,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Left>) -> Self {
+        if self.left_stream.is_some() {
+            panic!("ZipLink may only take 1 left input stream")
+        }
+
+        ZipLink {
+            left_stream: Some(in_stream),
+            right_stream: self.right_stream,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    fn build_link(self) -> Link<(Left, Right)> {
+        match (self.left_stream, self.right_stream) {
+            (None, _) => panic!("Cannot build link! Missing left input stream"),
+            (_, None) => panic!("Cannot build link! Missing right input stream"),
+            (Some(left_stream), Some(right_stream)) => {
+                let (to_egressor, from_left) =
+                    crossbeam_channel::bounded::<Option<Left>>(self.queue_capacity);
+                let left_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+                let left_ingressor =
+                    JoinIngressor::new(left_stream, to_egressor, Arc::clone(&left_park));
+
+                let (to_egressor, from_right) =
+                    crossbeam_channel::bounded::<Option<Right>>(self.queue_capacity);
+                let right_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+                let right_ingressor =
+                    JoinIngressor::new(right_stream, to_egressor, Arc::clone(&right_park));
+
+                let egressor = ZipEgressor {
+                    left: ZipSide::new(from_left, left_park),
+                    right: ZipSide::new(from_right, right_park),
+                };
+
+                (
+                    vec![Box::new(left_ingressor), Box::new(right_ingressor)],
+                    vec![Box::new(egressor)],
+                )
+            }
+        }
+    }
+}
+
+/// One input of a ZipEgressor.
+struct ZipSide<Packet> {
+    from_ingressor: Receiver<Option<Packet>>,
+    task_park: Arc<AtomicCell<TaskParkState>>,
+    alive: bool,
+    /// An item waiting to be paired.
+    held: Option<Packet>,
+}
+
+impl<Packet> ZipSide<Packet> {
+    fn new(
+        from_ingressor: Receiver<Option<Packet>>,
+        task_park: Arc<AtomicCell<TaskParkState>>,
+    ) -> Self {
+        ZipSide {
+            from_ingressor,
+            task_park,
+            alive: true,
+            held: None,
+        }
+    }
+
+    /// Receives an item into `held`, if we have none and the ingressor has one for us.
+    fn receive(&mut self) {
+        if self.held.is_some() || !self.alive {
+            return;
+        }
+        match self.from_ingressor.try_recv() {
+            Ok(Some(packet)) => {
+                unpark_and_wake(&self.task_park);
+                self.held = Some(packet);
+            }
+            Ok(None) | Err(TryRecvError::Disconnected) => self.alive = false,
+            Err(TryRecvError::Empty) => {}
+        }
+    }
+
+    /// Whether this side has run out of items to pair.
+    fn is_exhausted(&self) -> bool {
+        !self.alive && self.held.is_none()
+    }
+
+    /// Throws away everything the ingressor sends, so it can run to completion.
+    fn discard(&mut self) {
+        self.held = None;
+        while self.alive {
+            self.receive();
+            if self.held.take().is_none() && self.alive {
+                return;
+            }
+        }
+    }
+}
+
+pub struct ZipEgressor<Left, Right> {
+    left: ZipSide<Left>,
+    right: ZipSide<Right>,
+}
+
+impl<Left, Right> Unpin for ZipEgressor<Left, Right> {}
+
+impl<Left, Right> Stream for ZipEgressor<Left, Right> {
+    type Item = (Left, Right);
+
+    /// We receive an item from each side we are not already holding one for. There are three cases:
+    /// ###
+    /// #1 We hold an item from both sides, we return them as a pair.
+    ///
+    /// #2 Either side has finished, and we hold nothing from it to pair. We discard everything
+    /// from the other side until it finishes too, so its ingressor is not left waiting on us, and
+    /// then return `Ready(None)`.
+    ///
+    /// #3 Otherwise we park, as JoinEgressor does, with the ingressors we are waiting on.
+    /// ###
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let egressor = Pin::into_inner(self);
+        egressor.left.receive();
+        egressor.right.receive();
+
+        if egressor.left.held.is_some() && egressor.right.held.is_some() {
+            if let (Some(left), Some(right)) =
+                (egressor.left.held.take(), egressor.right.held.take())
+            {
+                return Poll::Ready(Some((left, right)));
+            }
+        }
+
+        let finished = egressor.left.is_exhausted() || egressor.right.is_exhausted();
+        if finished {
+            egressor.left.discard();
+            egressor.right.discard();
+            if !egressor.left.alive && !egressor.right.alive {
+                die_and_wake(&egressor.left.task_park);
+                die_and_wake(&egressor.right.task_park);
+                return Poll::Ready(None);
+            }
+        }
+
+        let egressor_task = Arc::new(AtomicCell::new(Some(cx.waker().clone())));
+        let mut parked_egressor_task = false;
+        if egressor.left.alive && egressor.left.held.is_none() {
+            parked_egressor_task |=
+                indirect_park_and_wake(&egressor.left.task_park, Arc::clone(&egressor_task));
+        }
+        if egressor.right.alive && egressor.right.held.is_none() {
+            parked_egressor_task |=
+                indirect_park_and_wake(&egressor.right.task_park, Arc::clone(&egressor_task));
+        }
+        if !parked_egressor_task {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::{immediate_stream, PacketIntervalGenerator};
+    use std::time::Duration;
+
+    #[test]
+    #[should_panic]
+    fn panics_when_built_without_right_input_stream() {
+        ZipLink::<i32, i32>::new()
+            .ingressor(immediate_stream(vec![]))
+            .build_link();
+    }
+
+    #[test]
+    fn pairs_in_order() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = ZipLink::new()
+                .ingressor(immediate_stream(0..20))
+                .right_ingressor(immediate_stream((0..20).map(|packet| packet * 10)))
+                .queue_capacity(3)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(
+            results[0],
+            (0..20)
+                .map(|packet| (packet, packet * 10))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn waits_on_slower_side() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let right = PacketIntervalGenerator::new(
+                Duration::from_millis(10),
+                vec!["a", "b", "c"].into_iter(),
+            );
+
+            let link = ZipLink::new()
+                .ingressor(immediate_stream(0..3))
+                .right_ingressor(Box::new(right))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![(0, "a"), (1, "b"), (2, "c")]);
+    }
+
+    #[test]
+    fn stops_at_shorter_side() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = ZipLink::new()
+                .ingressor(immediate_stream(0..3))
+                .right_ingressor(immediate_stream(0..100))
+                .queue_capacity(2)
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec![(0, 0), (1, 1), (2, 2)]);
+    }
+}
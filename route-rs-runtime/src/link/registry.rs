@@ -0,0 +1,475 @@
+use crate::link::graph::{Edge, Graph, Node};
+use crate::link::trace::TracePoint;
+use crate::link::{Link, LinkBuilder, PacketStream, ProcessLinkBuilder};
+use crate::processor::Processor;
+use futures::prelude::*;
+use futures::task::{Context, Poll};
+use std::any::{type_name, Any};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// A handle to a link, such as its stats or control, as kept by a `LinkRegistry`.
+pub type LinkHandle = Box<dyn Any + Send>;
+
+/// Maps the names of links to their handles, so that a link deep in a router can be found and
+/// inspected, or controlled, by name once it's built, rather than by threading its handles
+/// through the code that builds the router. Links are registered by building them named with
+/// `LinkBuilder::name`, into the global registry unless given another. As they're built, the
+/// registry also records which feeds which, for `graph`. A link built under a name already
+/// registered replaces it, as happens when a `SwapLink` rebuilds a segment. Clones of a registry
+/// share its links.
+#[derive(Clone, Default)]
+pub struct LinkRegistry {
+    state: Arc<Mutex<RegistryState>>,
+}
+
+#[derive(Default)]
+struct RegistryState {
+    links: BTreeMap<String, RegisteredLink>,
+    /// Edges into each link, by the name of the link they feed.
+    edges: BTreeMap<String, Vec<Edge>>,
+    /// The link and port of each egressor of a named link not yet taken as an ingressor, by the
+    /// address of the `Port` wrapping it.
+    ports: HashMap<usize, (String, usize)>,
+}
+
+struct RegisteredLink {
+    kind: &'static str,
+    handles: Vec<LinkHandle>,
+    inputs: usize,
+    outputs: usize,
+}
+
+impl LinkRegistry {
+    pub fn new() -> Self {
+        LinkRegistry::default()
+    }
+
+    /// Returns the registry links are registered in by default.
+    pub fn global() -> LinkRegistry {
+        static GLOBAL: OnceLock<LinkRegistry> = OnceLock::new();
+        GLOBAL.get_or_init(LinkRegistry::new).clone()
+    }
+
+    /// Registers the link `name`, of type `kind`, with its handles.
+    pub fn register(&self, name: &str, kind: &'static str, handles: Vec<LinkHandle>) {
+        self.register_link(name, kind, handles, 0, 0, vec![]);
+    }
+
+    fn register_link(
+        &self,
+        name: &str,
+        kind: &'static str,
+        handles: Vec<LinkHandle>,
+        inputs: usize,
+        outputs: usize,
+        edges: Vec<Edge>,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        let link = RegisteredLink {
+            kind,
+            handles,
+            inputs,
+            outputs,
+        };
+        state.links.insert(name.to_string(), link);
+        state.edges.insert(name.to_string(), edges);
+    }
+
+    /// Removes the link `name`, and the edges into it. Returns `false` if no such link is
+    /// registered.
+    pub fn unregister(&self, name: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.edges.remove(name);
+        state.links.remove(name).is_some()
+    }
+
+    /// The names of every registered link, in order.
+    pub fn names(&self) -> Vec<String> {
+        self.state.lock().unwrap().links.keys().cloned().collect()
+    }
+
+    /// The type of the link `name`, if registered.
+    pub fn kind(&self, name: &str) -> Option<&'static str> {
+        let state = self.state.lock().unwrap();
+        state.links.get(name).map(|link| link.kind)
+    }
+
+    /// Returns the handle of type `H` of the link `name`, such as the `QueueStats` of a
+    /// `QueueLink`, if the link is registered and has one.
+    pub fn get<H: Any + Clone>(&self, name: &str) -> Option<H> {
+        let state = self.state.lock().unwrap();
+        state
+            .links
+            .get(name)?
+            .handles
+            .iter()
+            .find_map(|handle| handle.downcast_ref::<H>())
+            .cloned()
+    }
+
+    /// Returns the topology of the registered links, in order of their names. Edges from links
+    /// since unregistered are left out.
+    pub fn graph(&self) -> Graph {
+        let state = self.state.lock().unwrap();
+        let nodes = state
+            .links
+            .iter()
+            .map(|(name, link)| Node {
+                name: name.clone(),
+                kind: link.kind.to_string(),
+                inputs: link.inputs,
+                outputs: link.outputs,
+            })
+            .collect();
+        let mut edges: Vec<Edge> = state
+            .edges
+            .values()
+            .flatten()
+            .filter(|edge| state.links.contains_key(&edge.from))
+            .cloned()
+            .collect();
+        edges.sort();
+        Graph { nodes, edges }
+    }
+
+    /// Wraps the egressors of the link `name` in `Port`s, so that the links they're given to can
+    /// tell where they come from.
+    fn ports<Packet: Send + 'static>(
+        &self,
+        name: &str,
+        egressors: Vec<PacketStream<Packet>>,
+    ) -> Vec<PacketStream<Packet>> {
+        let mut state = self.state.lock().unwrap();
+        egressors
+            .into_iter()
+            .enumerate()
+            .map(|(port_number, stream)| {
+                let mut port = Box::new(Port {
+                    stream,
+                    key: 0,
+                    registry: self.clone(),
+                });
+                port.key = &*port as *const Port<Packet> as usize;
+                state
+                    .ports
+                    .insert(port.key, (name.to_string(), port_number));
+                port as PacketStream<Packet>
+            })
+            .collect()
+    }
+
+    /// Returns the link and port `stream` comes from, if it's an egressor of a named link.
+    fn source<Packet>(&self, stream: &PacketStream<Packet>) -> Option<(String, usize)> {
+        let key =
+            &**stream as *const (dyn Stream<Item = Packet> + Send + Unpin) as *const () as usize;
+        self.state.lock().unwrap().ports.remove(&key)
+    }
+}
+
+impl fmt::Debug for LinkRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_map()
+            .entries(state.links.iter().map(|(name, link)| (name, link.kind)))
+            .finish()
+    }
+}
+
+/// An egressor of a named link. Its address identifies it to the registry, for as long as it
+/// lives.
+struct Port<Packet> {
+    stream: PacketStream<Packet>,
+    key: usize,
+    registry: LinkRegistry,
+}
+
+impl<Packet> Stream for Port<Packet> {
+    type Item = Packet;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.stream).poll_next(cx)
+    }
+}
+
+impl<Packet> Drop for Port<Packet> {
+    fn drop(&mut self) {
+        let mut state = self.registry.state.lock().unwrap();
+        state.ports.remove(&self.key);
+    }
+}
+
+/// A link that registers itself in a `LinkRegistry` under its name when built, as made by
+/// `LinkBuilder::name`. It takes ingressors, and a processor, as the link it wraps does, but none
+/// of its other settings, so name a link once it's configured, and before giving it ingressors,
+/// so that it can record the named links they come from.
+pub struct Named<L> {
+    link: L,
+    name: String,
+    /// The type of the link, as registered, which is that of the link named even once it's
+    /// wrapped by `traced`.
+    kind: &'static str,
+    registry: LinkRegistry,
+    /// The link and port each ingressor comes from, if it's from a named link.
+    sources: Vec<Option<(String, usize)>>,
+}
+
+impl<L> Named<L> {
+    pub fn new(link: L, name: &str) -> Self {
+        Named {
+            link,
+            name: name.to_string(),
+            kind: type_name::<L>(),
+            registry: LinkRegistry::global(),
+            sources: vec![],
+        }
+    }
+
+    /// Registers the link in `registry`, rather than the global registry.
+    pub fn registry(self, registry: &LinkRegistry) -> Self {
+        Named {
+            link: self.link,
+            name: self.name,
+            kind: self.kind,
+            registry: registry.clone(),
+            sources: self.sources,
+        }
+    }
+
+    /// Records the hops of traced packets through the link under its name, as a `TracePoint`
+    /// does. Trace a link right after naming it.
+    pub fn traced(self) -> Named<TracePoint<L>> {
+        Named {
+            link: TracePoint::new(self.link, &self.name),
+            name: self.name,
+            kind: self.kind,
+            registry: self.registry,
+            sources: self.sources,
+        }
+    }
+}
+
+impl<Input, Output, L> LinkBuilder<Input, Output> for Named<L>
+where
+    Output: Send + 'static,
+    L: LinkBuilder<Input, Output>,
+{
+    fn ingressors(self, in_streams: Vec<PacketStream<Input>>) -> Self {
+        let registry = &self.registry;
+        let mut sources = self.sources;
+        sources.extend(in_streams.iter().map(|stream| registry.source(stream)));
+        Named {
+            link: self.link.ingressors(in_streams),
+            name: self.name,
+            kind: self.kind,
+            registry: self.registry,
+            sources,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Input>) -> Self {
+        let mut sources = self.sources;
+        sources.push(self.registry.source(&in_stream));
+        Named {
+            link: self.link.ingressor(in_stream),
+            name: self.name,
+            kind: self.kind,
+            registry: self.registry,
+            sources,
+        }
+    }
+
+    fn build_link(self) -> Link<Output> {
+        let handles = self.link.handles();
+        let (runnables, egressors) = self.link.build_link();
+
+        let name = self.name;
+        let edges = self
+            .sources
+            .iter()
+            .enumerate()
+            .filter_map(|(to_port, source)| {
+                source.as_ref().map(|(from, from_port)| Edge {
+                    from: from.clone(),
+                    from_port: *from_port,
+                    to: name.clone(),
+                    to_port,
+                })
+            })
+            .collect();
+        self.registry.register_link(
+            &name,
+            self.kind,
+            handles,
+            self.sources.len(),
+            egressors.len(),
+            edges,
+        );
+        (runnables, self.registry.ports(&name, egressors))
+    }
+
+    fn handles(&self) -> Vec<LinkHandle> {
+        self.link.handles()
+    }
+}
+
+impl<P: Processor, L: ProcessLinkBuilder<P>> ProcessLinkBuilder<P> for Named<L>
+where
+    P::Output: Send + 'static,
+{
+    fn processor(self, processor: P) -> Self {
+        Named {
+            link: self.link.processor(processor),
+            name: self.name,
+            kind: self.kind,
+            registry: self.registry,
+            sources: self.sources,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::primitive::{
+        ForkLink, GateControl, GateLink, JoinLink, ProcessLink, QueueLink, QueueStats,
+    };
+    use crate::processor::Identity;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    #[test]
+    fn finds_handles_by_name() {
+        let registry = LinkRegistry::new();
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let queue = QueueLink::new()
+                .ingressor(immediate_stream(vec![1, 2, 3]))
+                .processor(Identity::new())
+                .name("ingress_queue")
+                .registry(&registry)
+                .build_link();
+            let gate = GateLink::new()
+                .ingressors(queue.1)
+                .name("egress_gate")
+                .registry(&registry)
+                .build_link();
+            let process = ProcessLink::new()
+                .ingressors(gate.1)
+                .name("plain")
+                .registry(&registry)
+                .processor(Identity::new())
+                .build_link();
+
+            let mut runnables = queue.0;
+            runnables.extend(gate.0);
+            runnables.extend(process.0);
+            run_link((runnables, process.1)).await
+        });
+        assert_eq!(results[0], vec![1, 2, 3]);
+
+        assert_eq!(
+            registry.names(),
+            vec!["egress_gate", "ingress_queue", "plain"]
+        );
+        assert!(registry
+            .kind("ingress_queue")
+            .unwrap()
+            .contains("QueueLink"));
+        let stats = registry.get::<QueueStats>("ingress_queue").unwrap();
+        assert_eq!(stats.enqueued(), 3);
+        assert!(registry
+            .get::<GateControl>("egress_gate")
+            .unwrap()
+            .is_open());
+
+        // Links without handles, or without one of the type asked for.
+        assert!(registry.get::<GateControl>("plain").is_none());
+        assert!(registry.get::<GateControl>("ingress_queue").is_none());
+        assert!(registry.get::<GateControl>("missing").is_none());
+
+        assert!(registry.unregister("plain"));
+        assert!(!registry.unregister("plain"));
+        assert!(format!("{:?}", registry).starts_with("{\"egress_gate\": "));
+    }
+
+    #[test]
+    fn records_topology() {
+        let registry = LinkRegistry::new();
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let queue = QueueLink::new()
+                .name("queue")
+                .registry(&registry)
+                .ingressor(immediate_stream(vec![1, 2]))
+                .processor(Identity::new())
+                .build_link();
+            let mut fork = ForkLink::new()
+                .num_egressors(2)
+                .name("fork")
+                .registry(&registry)
+                .ingressors(queue.1)
+                .build_link();
+            // Unnamed, so fork's port 1 feeds nothing in the graph.
+            let mut unnamed = ProcessLink::new()
+                .ingressor(fork.1.remove(1))
+                .processor(Identity::new())
+                .build_link();
+            let join = JoinLink::new()
+                .name("join")
+                .registry(&registry)
+                .ingressor(fork.1.remove(0))
+                .ingressor(unnamed.1.remove(0))
+                .build_link();
+
+            let mut runnables = queue.0;
+            runnables.extend(fork.0);
+            runnables.extend(unnamed.0);
+            runnables.extend(join.0);
+            run_link((runnables, join.1)).await
+        });
+        assert_eq!(results[0].len(), 4);
+
+        let graph = registry.graph();
+        let shapes: Vec<_> = graph
+            .nodes
+            .iter()
+            .map(|node| (node.name.as_str(), node.inputs, node.outputs))
+            .collect();
+        assert_eq!(
+            shapes,
+            vec![("fork", 1, 2), ("join", 2, 1), ("queue", 1, 1)]
+        );
+        let edge = |from: &str, from_port, to: &str, to_port| Edge {
+            from: from.to_string(),
+            from_port,
+            to: to.to_string(),
+            to_port,
+        };
+        assert_eq!(
+            graph.edges,
+            vec![edge("fork", 0, "join", 0), edge("queue", 0, "fork", 0)]
+        );
+        assert!(graph
+            .to_dot()
+            .contains("\"queue\" -> \"fork\" [taillabel=\"0\", headlabel=\"0\"];"));
+
+        // Edges from links since unregistered are left out.
+        registry.unregister("queue");
+        assert_eq!(registry.graph().edges, vec![edge("fork", 0, "join", 0)]);
+    }
+
+    #[test]
+    fn global_registry() {
+        GateLink::<i32>::new()
+            .ingressor(immediate_stream(vec![]))
+            .name("registry_tests_global_gate")
+            .build_link();
+        assert!(LinkRegistry::global()
+            .get::<GateControl>("registry_tests_global_gate")
+            .is_some());
+        assert!(LinkRegistry::global().unregister("registry_tests_global_gate"));
+    }
+}
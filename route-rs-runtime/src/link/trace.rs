@@ -0,0 +1,169 @@
+use crate::link::{registry::LinkHandle, Link, LinkBuilder, PacketStream, ProcessLinkBuilder};
+use crate::processor::{HopEvent, Processor, Traced};
+use futures::prelude::*;
+use std::sync::Arc;
+
+/// Records the hops of traced packets through the link it wraps: entering it as one of its
+/// ingressors takes them, and leaving it as one of its egressors gives them out, under the name
+/// the TracePoint was given. Packets are marked for tracing by a `MarkTrace` upstream. A named
+/// link can be traced under its name with `Named::traced`. Like `Named`, it takes ingressors, and
+/// a processor, as the link it wraps does, but none of its other settings, so wrap a link once
+/// it's configured, and before giving it ingressors.
+pub struct TracePoint<L> {
+    link: L,
+    name: Arc<str>,
+}
+
+impl<L> TracePoint<L> {
+    pub fn new(link: L, name: &str) -> Self {
+        TracePoint {
+            link,
+            name: Arc::from(name),
+        }
+    }
+}
+
+impl<Input, Output, L> LinkBuilder<Input, Output> for TracePoint<L>
+where
+    Input: Traced + Send + 'static,
+    Output: Traced + Send + 'static,
+    L: LinkBuilder<Input, Output>,
+{
+    fn ingressors(self, in_streams: Vec<PacketStream<Input>>) -> Self {
+        let in_streams = in_streams
+            .into_iter()
+            .map(|stream| record_hops(stream, &self.name, HopEvent::Enter))
+            .collect();
+        TracePoint {
+            link: self.link.ingressors(in_streams),
+            name: self.name,
+        }
+    }
+
+    fn ingressor(self, in_stream: PacketStream<Input>) -> Self {
+        let in_stream = record_hops(in_stream, &self.name, HopEvent::Enter);
+        TracePoint {
+            link: self.link.ingressor(in_stream),
+            name: self.name,
+        }
+    }
+
+    fn build_link(self) -> Link<Output> {
+        let name = self.name;
+        let (runnables, egressors) = self.link.build_link();
+        let egressors = egressors
+            .into_iter()
+            .map(|stream| record_hops(stream, &name, HopEvent::Exit))
+            .collect();
+        (runnables, egressors)
+    }
+
+    fn handles(&self) -> Vec<LinkHandle> {
+        self.link.handles()
+    }
+}
+
+impl<P: Processor, L: ProcessLinkBuilder<P>> ProcessLinkBuilder<P> for TracePoint<L>
+where
+    P::Input: Traced + 'static,
+    P::Output: Traced + Send + 'static,
+{
+    fn processor(self, processor: P) -> Self {
+        TracePoint {
+            link: self.link.processor(processor),
+            name: self.name,
+        }
+    }
+}
+
+fn record_hops<Packet: Traced + Send + 'static>(
+    stream: PacketStream<Packet>,
+    name: &Arc<str>,
+    event: HopEvent,
+) -> PacketStream<Packet> {
+    let name = Arc::clone(name);
+    Box::new(stream.inspect(move |packet| {
+        if let Some(trace) = packet.trace() {
+            trace.record(&name, event);
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::primitive::{ProcessLink, QueueLink};
+    use crate::link::registry::LinkRegistry;
+    use crate::processor::{
+        ClosureProcessor, Identity, MarkTrace, OnPacket, PacketTrace, TraceLog,
+    };
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    fn path(trace: &PacketTrace) -> Vec<(&str, HopEvent)> {
+        trace
+            .path()
+            .into_iter()
+            .map(|(link, event, _)| (link, event))
+            .collect()
+    }
+
+    #[test]
+    fn traces_marked_packets() {
+        let log = TraceLog::new();
+        let registry = LinkRegistry::new();
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (mut runnables, egressors) = ProcessLink::new()
+                .ingressor(immediate_stream(vec![1, 2, 3, 4]))
+                .processor(MarkTrace::new(|packet: &i32| *packet >= 3, &log))
+                .build_link();
+            let (queue_runnables, egressors) = TracePoint::new(QueueLink::new(), "queue")
+                .ingressors(egressors)
+                .processor(Identity::new())
+                .build_link();
+            let (filter_runnables, egressors) = ProcessLink::new()
+                .name("even")
+                .registry(&registry)
+                .traced()
+                .ingressors(egressors)
+                .processor(OnPacket::new(ClosureProcessor::new(|packet: i32| {
+                    Some(packet).filter(|packet| packet % 2 == 0)
+                })))
+                .build_link();
+            runnables.extend(queue_runnables);
+            runnables.extend(filter_runnables);
+            run_link((runnables, egressors)).await
+        });
+        let packets: Vec<i32> = results[0].iter().map(|packet| packet.packet).collect();
+        assert_eq!(packets, vec![2, 4]);
+
+        // 3 was dropped in the filter, while 4 is still around.
+        let traces = log.take();
+        assert_eq!(traces.len(), 1);
+        assert_eq!(traces[0].id, 0);
+        assert_eq!(
+            path(&traces[0]),
+            vec![
+                ("queue", HopEvent::Enter),
+                ("queue", HopEvent::Exit),
+                ("even", HopEvent::Enter)
+            ]
+        );
+
+        drop(results);
+        let traces = log.take();
+        assert_eq!(traces.len(), 1);
+        assert_eq!(traces[0].id, 1);
+        assert_eq!(
+            path(&traces[0]),
+            vec![
+                ("queue", HopEvent::Enter),
+                ("queue", HopEvent::Exit),
+                ("even", HopEvent::Enter),
+                ("even", HopEvent::Exit)
+            ]
+        );
+        assert_eq!(registry.names(), vec!["even".to_string()]);
+    }
+}
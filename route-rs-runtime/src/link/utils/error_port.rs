@@ -0,0 +1,60 @@
+//! # What is it for?
+//!
+//! Some links send the packets they could not handle, along with why, to an error egressor. Its
+//! items are a different type to the link's normal output, so rather than being one of the link's
+//! egressors, it is taken from the link's builder. An `ErrorPort` is the ingressor's end of it.
+//! Errors are for diagnostics, so they are dropped rather than waited on when the error queue is
+//! full, or when nobody took the error egressor. Only the `None` that tears the egressor down is
+//! waited on, so that it always gets through.
+
+use crate::link::primitive::QueueEgressor;
+use crate::link::utils::task_park::*;
+use crate::link::PacketStream;
+use crossbeam::atomic::AtomicCell;
+use crossbeam::crossbeam_channel;
+use crossbeam::crossbeam_channel::{Sender, TrySendError};
+use futures::task::{Context, Poll};
+use std::sync::Arc;
+
+pub struct ErrorPort<Item> {
+    to_egressor: Sender<Option<Item>>,
+    task_park: Arc<AtomicCell<TaskParkState>>,
+}
+
+impl<Item: Send + 'static> ErrorPort<Item> {
+    /// Creates the port, and the error egressor to hand out, which queues up to `capacity` items.
+    pub fn new(capacity: usize) -> (Self, PacketStream<Item>) {
+        let (to_egressor, from_ingressor) = crossbeam_channel::bounded(capacity);
+        let task_park = Arc::new(AtomicCell::new(TaskParkState::Empty));
+        let egressor = QueueEgressor::new(from_ingressor, Arc::clone(&task_park));
+        let port = ErrorPort {
+            to_egressor,
+            task_park,
+        };
+        (port, Box::new(egressor))
+    }
+}
+
+impl<Item> ErrorPort<Item> {
+    /// Sends an error to the egressor, or drops it if the egressor's queue is full.
+    pub fn send(&self, item: Item) {
+        if self.to_egressor.try_send(Some(item)).is_ok() {
+            unpark_and_wake(&self.task_park);
+        }
+    }
+
+    /// Tells the egressor there will be no more errors, returning `Poll::Pending` if we have to
+    /// wait for room in its queue to do so.
+    pub fn finish(&self, cx: &mut Context) -> Poll<()> {
+        match self.to_egressor.try_send(None) {
+            Err(TrySendError::Full(_)) => {
+                park_and_wake(&self.task_park, cx.waker().clone());
+                Poll::Pending
+            }
+            Ok(()) | Err(TrySendError::Disconnected(_)) => {
+                die_and_wake(&self.task_park);
+                Poll::Ready(())
+            }
+        }
+    }
+}
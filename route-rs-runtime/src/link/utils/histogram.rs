@@ -0,0 +1,185 @@
+//! # What is it for?
+//!
+//! A histogram of latencies that can be recorded into from the data path and read from anywhere
+//! else, without locking. Like an HDR histogram, buckets are linear within each power of two, so
+//! every recorded value is kept to within 1% of its true value, from nanoseconds up to the
+//! longest `Duration` that fits in a `u64` of nanoseconds. Clones share the same buckets.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Values below `2^SUB_BUCKET_BITS` nanoseconds are recorded exactly, and every power of two above
+/// that is split into `2^(SUB_BUCKET_BITS - 1)` buckets.
+const SUB_BUCKET_BITS: u32 = 7;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+const HALF_SUB_BUCKETS: u64 = SUB_BUCKETS / 2;
+const NUM_BUCKETS: usize =
+    ((64 - SUB_BUCKET_BITS as usize + 1) * HALF_SUB_BUCKETS as usize) + HALF_SUB_BUCKETS as usize;
+
+#[derive(Clone)]
+pub struct LatencyHistogram {
+    state: Arc<HistogramState>,
+}
+
+struct HistogramState {
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    /// Sum of every recorded value, in nanoseconds.
+    total: AtomicU64,
+    max: AtomicU64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        LatencyHistogram {
+            state: Arc::new(HistogramState {
+                buckets: (0..NUM_BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+                count: AtomicU64::new(0),
+                total: AtomicU64::new(0),
+                max: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    pub fn record(&self, latency: Duration) {
+        let nanos = latency.as_nanos().min(u128::from(u64::MAX)) as u64;
+        self.state.buckets[bucket_index(nanos)].fetch_add(1, Ordering::Relaxed);
+        self.state.count.fetch_add(1, Ordering::Relaxed);
+        self.state.total.fetch_add(nanos, Ordering::Relaxed);
+        self.state.max.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    /// Number of latencies recorded.
+    pub fn count(&self) -> u64 {
+        self.state.count.load(Ordering::Relaxed)
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.state.max.load(Ordering::Relaxed))
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.count() {
+            0 => Duration::from_nanos(0),
+            count => Duration::from_nanos(self.state.total.load(Ordering::Relaxed) / count),
+        }
+    }
+
+    /// The latency that `quantile` of recorded latencies are at or below, for instance 0.99 for
+    /// the 99th percentile. Returns zero if nothing has been recorded.
+    pub fn value_at_quantile(&self, quantile: f64) -> Duration {
+        assert!(
+            (0.0..=1.0).contains(&quantile),
+            "LatencyHistogram quantile: {}, must be between 0 and 1",
+            quantile
+        );
+
+        let count = self.count();
+        if count == 0 {
+            return Duration::from_nanos(0);
+        }
+        let rank = ((quantile * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, bucket) in self.state.buckets.iter().enumerate() {
+            seen += bucket.load(Ordering::Relaxed);
+            if seen >= rank {
+                return Duration::from_nanos(bucket_value(index)).min(self.max());
+            }
+        }
+        self.max()
+    }
+
+    /// Clears every recorded latency, for instance to start a new measurement interval.
+    pub fn reset(&self) {
+        for bucket in self.state.buckets.iter() {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.state.count.store(0, Ordering::Relaxed);
+        self.state.total.store(0, Ordering::Relaxed);
+        self.state.max.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Values below `SUB_BUCKETS` have a bucket each. Above that, `magnitude` is how many times the
+/// value must be halved to fall in the top half of the sub buckets, and it lands in the bucket for
+/// what is left.
+fn bucket_index(nanos: u64) -> usize {
+    if nanos < SUB_BUCKETS {
+        return nanos as usize;
+    }
+    let magnitude = (63 - nanos.leading_zeros()) - (SUB_BUCKET_BITS - 1);
+    let sub_bucket = nanos >> magnitude;
+    (u64::from(magnitude) * HALF_SUB_BUCKETS + sub_bucket) as usize
+}
+
+/// The highest value recorded in a bucket.
+fn bucket_value(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+        return index;
+    }
+    let magnitude = index / HALF_SUB_BUCKETS - 1;
+    let sub_bucket = index - magnitude * HALF_SUB_BUCKETS;
+    (sub_bucket << magnitude) + ((1 << magnitude) - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_are_ordered_and_cover_every_value() {
+        assert_eq!(bucket_index(0), 0);
+        assert_eq!(bucket_index(SUB_BUCKETS - 1), SUB_BUCKETS as usize - 1);
+        assert_eq!(bucket_index(SUB_BUCKETS), SUB_BUCKETS as usize);
+        assert_eq!(bucket_index(u64::MAX), NUM_BUCKETS - 1);
+        for nanos in [1, 127, 128, 129, 255, 256, 1_000_000, 123_456_789, u64::MAX].iter() {
+            let index = bucket_index(*nanos);
+            assert!(bucket_value(index) >= *nanos);
+            if index > 0 {
+                assert!(bucket_value(index - 1) < *nanos);
+            }
+        }
+    }
+
+    #[test]
+    fn quantiles_within_one_percent() {
+        let histogram = LatencyHistogram::new();
+        for micros in 1..=1000 {
+            histogram.record(Duration::from_micros(micros));
+        }
+
+        assert_eq!(histogram.count(), 1000);
+        assert_eq!(histogram.max(), Duration::from_micros(1000));
+        for (quantile, expected) in [(0.5, 500), (0.9, 900), (0.99, 990), (1.0, 1000)].iter() {
+            let value = histogram.value_at_quantile(*quantile).as_nanos() as f64;
+            let expected = Duration::from_micros(*expected).as_nanos() as f64;
+            assert!(
+                (value - expected).abs() / expected < 0.01,
+                "quantile {}: {}, expected {}",
+                quantile,
+                value,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn reset_clears() {
+        let histogram = LatencyHistogram::new();
+        let shared = histogram.clone();
+        histogram.record(Duration::from_millis(5));
+        assert_eq!(shared.count(), 1);
+
+        shared.reset();
+        assert_eq!(histogram.count(), 0);
+        assert_eq!(histogram.value_at_quantile(0.5), Duration::from_nanos(0));
+    }
+}
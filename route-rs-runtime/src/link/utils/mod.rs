@@ -1,2 +1,18 @@
 /// A cache for storing task handles.
 pub mod task_park;
+
+/// A token bucket, used by links that rate limit traffic.
+pub mod token_bucket;
+
+/// Random Early Detection drop curves, used by active queue management links.
+pub mod red;
+
+/// Lock free counters of the packets and bytes passing through a link, its drops and its queue
+/// depth.
+pub mod stats;
+
+/// A lock free latency histogram, used by links that measure latency.
+pub mod histogram;
+
+/// The sending end of an error egressor, used by links that report packets they failed to handle.
+pub mod error_port;
@@ -0,0 +1,106 @@
+//! # What is it for?
+//!
+//! Random Early Detection drops packets with a probability that grows with the depth of a queue,
+//! so that senders back off before the queue is completely full. A `RedCurve` describes that
+//! relationship: below `min_threshold` nothing is dropped, between the thresholds the drop
+//! probability rises linearly to `max_probability`, and at or above `max_threshold` every packet
+//! is dropped. Links that do active queue management share these curves.
+
+/// Drop probability curve for Random Early Detection.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RedCurve {
+    min_threshold: usize,
+    max_threshold: usize,
+    max_probability: f64,
+}
+
+impl RedCurve {
+    pub fn new(min_threshold: usize, max_threshold: usize, max_probability: f64) -> Self {
+        assert!(
+            min_threshold < max_threshold,
+            "RedCurve min_threshold: {}, must be < max_threshold: {}",
+            min_threshold,
+            max_threshold
+        );
+        assert!(
+            (0.0..=1.0).contains(&max_probability),
+            "RedCurve max_probability: {}, must be within [0, 1]",
+            max_probability
+        );
+
+        RedCurve {
+            min_threshold,
+            max_threshold,
+            max_probability,
+        }
+    }
+
+    pub fn min_threshold(&self) -> usize {
+        self.min_threshold
+    }
+
+    pub fn max_threshold(&self) -> usize {
+        self.max_threshold
+    }
+
+    pub fn max_probability(&self) -> f64 {
+        self.max_probability
+    }
+
+    /// Probability that a packet arriving at a queue of depth `queue_len` should be dropped. The
+    /// depth may be fractional, for callers that track an average queue length.
+    pub fn drop_probability(&self, queue_len: f64) -> f64 {
+        let min = self.min_threshold as f64;
+        let max = self.max_threshold as f64;
+        if queue_len < min {
+            0.0
+        } else if queue_len >= max {
+            1.0
+        } else {
+            self.max_probability * (queue_len - min) / (max - min)
+        }
+    }
+
+    /// Rolls the dice for a packet arriving at a queue of depth `queue_len`.
+    pub fn should_drop(&self, queue_len: f64) -> bool {
+        let probability = self.drop_probability(queue_len);
+        probability >= 1.0 || (probability > 0.0 && rand::random::<f64>() < probability)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic]
+    fn panics_with_inverted_thresholds() {
+        RedCurve::new(10, 5, 0.1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_with_invalid_probability() {
+        RedCurve::new(5, 10, 1.5);
+    }
+
+    #[test]
+    fn drop_probability_follows_curve() {
+        let curve = RedCurve::new(10, 30, 0.5);
+
+        assert_eq!(curve.drop_probability(0.0), 0.0);
+        assert_eq!(curve.drop_probability(9.9), 0.0);
+        assert_eq!(curve.drop_probability(10.0), 0.0);
+        assert!((curve.drop_probability(20.0) - 0.25).abs() < 1e-9);
+        assert_eq!(curve.drop_probability(30.0), 1.0);
+        assert_eq!(curve.drop_probability(100.0), 1.0);
+    }
+
+    #[test]
+    fn should_drop_at_extremes() {
+        let curve = RedCurve::new(10, 30, 0.5);
+
+        assert!((0..100).all(|_| !curve.should_drop(5.0)));
+        assert!((0..100).all(|_| curve.should_drop(30.0)));
+    }
+}
@@ -0,0 +1,308 @@
+//! # What is it for?
+//!
+//! Counters of the packets and bytes passing through a link, of what it drops, and of how many
+//! packets it holds in its queues, that the link's ingressors and egressors update from the data
+//! path, and that can be read from anywhere else without locking, through a `LinkStats`.
+//!
+//! CounterLink always counts, and QueueLink always counts what it drops. Otherwise QueueLink,
+//! ForkLink, JoinLink and ClassifyLink count through a `LinkCounter`, which only counts with the
+//! `metrics` feature. Without it, a `LinkCounter` holds nothing and updating it does nothing, so
+//! those links are instrumented unconditionally without costing the data path anything.
+
+use crate::link::primitive::PacketCost;
+use std::collections::BTreeMap;
+#[cfg(not(feature = "metrics"))]
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Counters of a link, taken from its builder as an `Arc<LinkStats>`.
+#[derive(Default)]
+pub struct LinkStats {
+    packets_in: AtomicU64,
+    packets_out: AtomicU64,
+    bytes_out: AtomicU64,
+    dropped: AtomicU64,
+    queue_depth: AtomicU64,
+    queue_high_water: AtomicU64,
+    /// Packets in through each ingressor, and out through each egressor, by port. Only locked
+    /// while the link is built, and while reading.
+    inputs: Mutex<BTreeMap<usize, Arc<AtomicU64>>>,
+    outputs: Mutex<BTreeMap<usize, Arc<AtomicU64>>>,
+}
+
+impl LinkStats {
+    /// Whether QueueLink, ForkLink, JoinLink and ClassifyLink count, that is, whether the
+    /// `metrics` feature is enabled.
+    pub fn enabled() -> bool {
+        cfg!(feature = "metrics")
+    }
+
+    /// Packets the link has taken from its ingressors.
+    pub fn packets_in(&self) -> u64 {
+        self.packets_in.load(Ordering::Relaxed)
+    }
+
+    /// Packets the link's egressors have given out. A link that copies packets, like ForkLink,
+    /// counts every copy.
+    pub fn packets_out(&self) -> u64 {
+        self.packets_out.load(Ordering::Relaxed)
+    }
+
+    /// Bytes of the packets the link's egressors have given out, as measured by the closure given
+    /// to the link's `bytes`. Always zero if it was not given one.
+    pub fn bytes_out(&self) -> u64 {
+        self.bytes_out.load(Ordering::Relaxed)
+    }
+
+    /// Packets the link has dropped, by its processor or because its queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Packets currently waiting in the link's queues, all of them together for a link with
+    /// several.
+    pub fn queue_depth(&self) -> u64 {
+        self.queue_depth.load(Ordering::Relaxed)
+    }
+
+    /// The most packets that have waited in the link's queues at once.
+    pub fn queue_high_water(&self) -> u64 {
+        self.queue_high_water.load(Ordering::Relaxed)
+    }
+
+    /// Packets the link has taken from each of its ingressors, by port, for the ports it has
+    /// counted. Empty until the link is built.
+    pub fn packets_in_by_port(&self) -> Vec<(usize, u64)> {
+        read_ports(&self.inputs)
+    }
+
+    /// Packets each of the link's egressors has given out, by port, as `packets_in_by_port`.
+    pub fn packets_out_by_port(&self) -> Vec<(usize, u64)> {
+        read_ports(&self.outputs)
+    }
+
+    /// Sets the counters back to zero, and the high water mark to the current depth, for
+    /// instance to start a new measurement interval.
+    pub fn reset(&self) {
+        self.packets_in.store(0, Ordering::Relaxed);
+        self.packets_out.store(0, Ordering::Relaxed);
+        self.bytes_out.store(0, Ordering::Relaxed);
+        self.dropped.store(0, Ordering::Relaxed);
+        for ports in [&self.inputs, &self.outputs] {
+            for counter in ports.lock().unwrap().values() {
+                counter.store(0, Ordering::Relaxed);
+            }
+        }
+        let depth = self.queue_depth.load(Ordering::Relaxed);
+        self.queue_high_water.store(depth, Ordering::Relaxed);
+    }
+
+    /// The counter of packets taken in through ingressor `port`.
+    pub(crate) fn input(&self, port: usize) -> Arc<AtomicU64> {
+        Arc::clone(self.inputs.lock().unwrap().entry(port).or_default())
+    }
+
+    /// The counter of packets given out through egressor `port`.
+    pub(crate) fn output(&self, port: usize) -> Arc<AtomicU64> {
+        Arc::clone(self.outputs.lock().unwrap().entry(port).or_default())
+    }
+
+    pub(crate) fn received(&self, packets: u64) {
+        self.packets_in.fetch_add(packets, Ordering::Relaxed);
+    }
+
+    pub(crate) fn sent(&self, packets: u64, bytes: u64) {
+        self.packets_out.fetch_add(packets, Ordering::Relaxed);
+        self.bytes_out.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dropped_packets(&self, packets: u64) {
+        self.dropped.fetch_add(packets, Ordering::Relaxed);
+    }
+
+    /// Counts packets put on a queue. Call before putting them there, so that the depth never
+    /// counts them as taken off first.
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    pub(crate) fn enqueued(&self, packets: u64) {
+        let depth = self.queue_depth.fetch_add(packets, Ordering::Relaxed) + packets;
+        self.queue_high_water.fetch_max(depth, Ordering::Relaxed);
+    }
+
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    pub(crate) fn dequeued(&self, packets: u64) {
+        self.queue_depth.fetch_sub(packets, Ordering::Relaxed);
+    }
+}
+
+fn read_ports(ports: &Mutex<BTreeMap<usize, Arc<AtomicU64>>>) -> Vec<(usize, u64)> {
+    ports
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(port, counter)| (*port, counter.load(Ordering::Relaxed)))
+        .collect()
+}
+
+/// Counts what passes through one of a link's ingressors or egressors into the link's
+/// `LinkStats`, with the `metrics` feature. One made with `default` counts nothing, for
+/// ingressors and egressors of links that keep no stats.
+pub(crate) struct LinkCounter<Packet> {
+    #[cfg(feature = "metrics")]
+    stats: Option<Arc<LinkStats>>,
+    #[cfg(feature = "metrics")]
+    input: Option<Arc<AtomicU64>>,
+    #[cfg(feature = "metrics")]
+    output: Option<Arc<AtomicU64>>,
+    #[cfg(feature = "metrics")]
+    bytes: Option<Arc<PacketCost<Packet>>>,
+    #[cfg(not(feature = "metrics"))]
+    packet: PhantomData<fn(&Packet)>,
+}
+
+impl<Packet> Default for LinkCounter<Packet> {
+    fn default() -> Self {
+        #[cfg(feature = "metrics")]
+        return LinkCounter {
+            stats: None,
+            input: None,
+            output: None,
+            bytes: None,
+        };
+        #[cfg(not(feature = "metrics"))]
+        LinkCounter {
+            packet: PhantomData,
+        }
+    }
+}
+
+impl<Packet> LinkCounter<Packet> {
+    /// Counts into `stats` the packets taken in through ingressor `port`.
+    pub(crate) fn input(stats: &Arc<LinkStats>, port: usize) -> Self {
+        #[cfg(feature = "metrics")]
+        return LinkCounter {
+            stats: Some(Arc::clone(stats)),
+            input: Some(stats.input(port)),
+            ..LinkCounter::default()
+        };
+        #[cfg(not(feature = "metrics"))]
+        {
+            let _ = (stats, port);
+            LinkCounter::default()
+        }
+    }
+
+    /// Counts into `stats` the packets given out through egressor `port`, and their bytes as
+    /// measured by `bytes`, if given.
+    pub(crate) fn output(
+        stats: &Arc<LinkStats>,
+        port: usize,
+        bytes: Option<&Arc<PacketCost<Packet>>>,
+    ) -> Self {
+        #[cfg(feature = "metrics")]
+        return LinkCounter {
+            stats: Some(Arc::clone(stats)),
+            output: Some(stats.output(port)),
+            bytes: bytes.cloned(),
+            ..LinkCounter::default()
+        };
+        #[cfg(not(feature = "metrics"))]
+        {
+            let _ = (stats, port, bytes);
+            LinkCounter::default()
+        }
+    }
+
+    #[inline]
+    pub(crate) fn received(&self, packets: u64) {
+        #[cfg(feature = "metrics")]
+        if let Some(stats) = &self.stats {
+            stats.received(packets);
+            if let Some(input) = &self.input {
+                input.fetch_add(packets, Ordering::Relaxed);
+            }
+        }
+        #[cfg(not(feature = "metrics"))]
+        let _ = packets;
+    }
+
+    #[inline]
+    pub(crate) fn sent(&self, packet: &Packet) {
+        #[cfg(feature = "metrics")]
+        if let Some(stats) = &self.stats {
+            stats.sent(1, self.bytes.as_ref().map_or(0, |bytes| bytes(packet)));
+            if let Some(output) = &self.output {
+                output.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        #[cfg(not(feature = "metrics"))]
+        let _ = packet;
+    }
+
+    /// Counts packets put on a queue. Call before putting them there, so that the depth never
+    /// counts them as taken off first.
+    #[inline]
+    pub(crate) fn enqueued(&self, packets: u64) {
+        #[cfg(feature = "metrics")]
+        if let Some(stats) = &self.stats {
+            stats.enqueued(packets);
+        }
+        #[cfg(not(feature = "metrics"))]
+        let _ = packets;
+    }
+
+    #[inline]
+    pub(crate) fn dequeued(&self, packets: u64) {
+        #[cfg(feature = "metrics")]
+        if let Some(stats) = &self.stats {
+            stats.dequeued(packets);
+        }
+        #[cfg(not(feature = "metrics"))]
+        let _ = packets;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depth_and_high_water() {
+        let stats = LinkStats::default();
+        stats.received(3);
+        stats.enqueued(3);
+        stats.dequeued(2);
+        stats.sent(2, 100);
+        stats.enqueued(1);
+        assert_eq!((stats.packets_in(), stats.packets_out()), (3, 2));
+        assert_eq!(stats.bytes_out(), 100);
+        assert_eq!((stats.queue_depth(), stats.queue_high_water()), (2, 3));
+
+        stats.reset();
+        assert_eq!((stats.packets_in(), stats.packets_out()), (0, 0));
+        assert_eq!(stats.bytes_out(), 0);
+        assert_eq!((stats.queue_depth(), stats.queue_high_water()), (2, 2));
+    }
+
+    #[test]
+    #[cfg(feature = "metrics")]
+    fn ports() {
+        let stats = Arc::new(LinkStats::default());
+        let bytes: Arc<PacketCost<&str>> = Arc::new(Box::new(|packet| packet.len() as u64));
+        LinkCounter::<&str>::input(&stats, 1).received(2);
+        LinkCounter::<&str>::default().received(1);
+        LinkCounter::<&str>::default().sent(&"uncounted");
+        LinkCounter::output(&stats, 0, None).sent(&"a");
+        let output = LinkCounter::output(&stats, 3, Some(&bytes));
+        output.sent(&"route");
+        output.sent(&"rs");
+        assert_eq!(stats.packets_in(), 2);
+        assert_eq!(stats.packets_in_by_port(), vec![(1, 2)]);
+        assert_eq!(stats.packets_out(), 3);
+        assert_eq!(stats.packets_out_by_port(), vec![(0, 1), (3, 2)]);
+        assert_eq!(stats.bytes_out(), 7);
+
+        stats.reset();
+        assert_eq!(stats.packets_out_by_port(), vec![(0, 0), (3, 0)]);
+    }
+}
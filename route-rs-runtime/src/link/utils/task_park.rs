@@ -25,8 +25,8 @@ route-rs processors. These utilities should not be exposed via the Processors AP
 /// when there is work to do. A simple example exists in  `QueueLink`. When the provider attempts
 /// to pull a packet from the channel, and finds it empty, it must await more packets
 /// before it can make forward progress. So it calls `park_and_wake`, which will awaken any
-/// task handle inside in the task_park, and place the task_park in the `Parked(task::Task)` state.
-/// It can now got to sleep by returning `Async::NotReady`, knowing that the other task will awaken it
+/// task handle inside in the task_park, and place the task_park in the `Parked(task::Waker)` state.
+/// It can now got to sleep by returning `Poll::Pending`, knowing that the other task will awaken it
 /// in the future.
 ///
 /// The task_park can be in one of four states
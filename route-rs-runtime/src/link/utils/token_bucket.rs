@@ -0,0 +1,221 @@
+//! # What is it for?
+//!
+//! A token bucket is the classic building block for rate limiting: tokens drip into a bucket at a
+//! fixed `rate`, up to a maximum of `burst` tokens, and traffic may only pass when it can remove
+//! enough tokens from the bucket. Links that shape or police traffic keep one of these, and use
+//! `time_until` to decide how long to sleep on a timer when the bucket runs dry.
+
+use std::time::{Duration, Instant};
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+pub struct TokenBucket {
+    /// Tokens added per second.
+    rate: u64,
+    /// Maximum number of tokens the bucket can hold.
+    burst: u64,
+    tokens: u64,
+    /// Tokens drained beyond what the bucket held, repaid from refills before any new tokens
+    /// become available. Only ever non-zero while `tokens` is zero.
+    debt: u64,
+    /// Point in time up to which tokens have been credited. Any fractional token accrued since
+    /// then is credited on a later refill rather than lost.
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a bucket that starts full.
+    pub fn new(rate: u64, burst: u64) -> Self {
+        assert!(rate > 0, "token bucket rate: {}, must be > 0", rate);
+        assert!(burst > 0, "token bucket burst: {}, must be > 0", burst);
+
+        TokenBucket {
+            rate,
+            burst,
+            tokens: burst,
+            debt: 0,
+            last_refill: Instant::now(),
+        }
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    pub fn burst(&self) -> u64 {
+        self.burst
+    }
+
+    /// Tokens currently in the bucket, as of the last refill.
+    pub fn tokens(&self) -> u64 {
+        self.tokens
+    }
+
+    /// Credits the bucket with every whole token that accrued between the last refill and `now`,
+    /// capped at `burst`. Long gaps between refills are not lost, they simply fill the bucket.
+    pub fn refill(&mut self, now: Instant) {
+        if now <= self.last_refill {
+            return;
+        }
+        if self.tokens >= self.burst {
+            self.last_refill = now;
+            return;
+        }
+
+        let elapsed = now.duration_since(self.last_refill).as_nanos();
+        let new_tokens = elapsed * u128::from(self.rate) / NANOS_PER_SEC;
+        if new_tokens == 0 {
+            return;
+        }
+
+        let headroom = u128::from(self.burst - self.tokens) + u128::from(self.debt);
+        if new_tokens > headroom {
+            self.tokens = self.burst;
+            self.debt = 0;
+            self.last_refill = now;
+        } else {
+            let repaid = (new_tokens as u64).min(self.debt);
+            self.debt -= repaid;
+            self.tokens += new_tokens as u64 - repaid;
+            let credited = new_tokens * NANOS_PER_SEC / u128::from(self.rate);
+            self.last_refill += Duration::from_nanos(credited as u64);
+        }
+    }
+
+    /// Refills the bucket, then removes `amount` tokens if they are all available.
+    pub fn try_consume(&mut self, now: Instant, amount: u64) -> bool {
+        self.refill(now);
+        if self.tokens >= amount {
+            self.tokens -= amount;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Refills the bucket, then removes `amount` tokens even if there are fewer, going into debt
+    /// for the rest. For charging traffic that was let through regardless of this bucket.
+    pub fn drain(&mut self, now: Instant, amount: u64) {
+        self.refill(now);
+        if self.tokens >= amount {
+            self.tokens -= amount;
+        } else {
+            self.debt += amount - self.tokens;
+            self.tokens = 0;
+        }
+    }
+
+    /// How long from `now` until `amount` tokens will be in the bucket. Amounts larger than
+    /// `burst` are treated as `burst`, since the bucket can never hold more than that.
+    pub fn time_until(&self, now: Instant, amount: u64) -> Duration {
+        let amount = amount.min(self.burst);
+        if self.tokens >= amount {
+            return Duration::from_secs(0);
+        }
+
+        let missing = u128::from(amount - self.tokens) + u128::from(self.debt);
+        // Round up, so that waking after this duration guarantees the tokens have accrued.
+        let needed_nanos = (missing * NANOS_PER_SEC).div_ceil(u128::from(self.rate));
+        let ready_at = self.last_refill + Duration::from_nanos(needed_nanos as u64);
+        if ready_at <= now {
+            Duration::from_secs(0)
+        } else {
+            ready_at - now
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic]
+    fn panics_with_zero_rate() {
+        TokenBucket::new(0, 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_with_zero_burst() {
+        TokenBucket::new(1, 0);
+    }
+
+    #[test]
+    fn starts_full_and_drains() {
+        let mut bucket = TokenBucket::new(10, 3);
+        let now = bucket.last_refill;
+
+        assert!(bucket.try_consume(now, 1));
+        assert!(bucket.try_consume(now, 2));
+        assert!(!bucket.try_consume(now, 1));
+        assert_eq!(bucket.tokens(), 0);
+    }
+
+    #[test]
+    fn refills_at_rate() {
+        let mut bucket = TokenBucket::new(100, 10);
+        let start = bucket.last_refill;
+        assert!(bucket.try_consume(start, 10));
+
+        bucket.refill(start + Duration::from_millis(55));
+        assert_eq!(bucket.tokens(), 5);
+
+        // The leftover 5ms is not lost, it counts towards the next token.
+        bucket.refill(start + Duration::from_millis(60));
+        assert_eq!(bucket.tokens(), 6);
+    }
+
+    #[test]
+    fn long_gap_accumulates_up_to_burst() {
+        let mut bucket = TokenBucket::new(100, 10);
+        let start = bucket.last_refill;
+        assert!(bucket.try_consume(start, 10));
+
+        bucket.refill(start + Duration::from_secs(60));
+        assert_eq!(bucket.tokens(), 10);
+    }
+
+    #[test]
+    fn filling_to_burst_keeps_remainder() {
+        let mut bucket = TokenBucket::new(100, 1);
+        let start = bucket.last_refill;
+        assert!(bucket.try_consume(start, 1));
+
+        // Waking late fills the bucket, but the late 5ms still counts towards the next token.
+        assert!(bucket.try_consume(start + Duration::from_millis(15), 1));
+        assert!(bucket.try_consume(start + Duration::from_millis(20), 1));
+    }
+
+    #[test]
+    fn drain_goes_into_debt() {
+        let mut bucket = TokenBucket::new(100, 5);
+        let start = bucket.last_refill;
+
+        bucket.drain(start, 2);
+        assert_eq!(bucket.tokens(), 3);
+        bucket.drain(start, 10);
+        assert_eq!(bucket.tokens(), 0);
+        assert_eq!(bucket.time_until(start, 1), Duration::from_millis(80));
+
+        // The 7 tokens owed are repaid before any become available.
+        bucket.refill(start + Duration::from_millis(90));
+        assert_eq!(bucket.tokens(), 2);
+    }
+
+    #[test]
+    fn time_until_tokens_available() {
+        let mut bucket = TokenBucket::new(100, 10);
+        let start = bucket.last_refill;
+        assert_eq!(bucket.time_until(start, 1), Duration::from_secs(0));
+
+        assert!(bucket.try_consume(start, 10));
+        assert_eq!(bucket.time_until(start, 1), Duration::from_millis(10));
+        assert_eq!(bucket.time_until(start, 5), Duration::from_millis(50));
+        assert_eq!(bucket.time_until(start, 50), Duration::from_millis(100));
+        assert_eq!(
+            bucket.time_until(start + Duration::from_millis(4), 1),
+            Duration::from_millis(6)
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use crossbeam::queue::ArrayQueue;
+use route_rs_packets::PacketData;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+
+struct Buffers {
+    free: ArrayQueue<Vec<u8>>,
+    buffer_size: usize,
+    misses: AtomicU64,
+}
+
+/// A pool of fixed size packet buffers, allocated up front and recycled, so that links receiving
+/// packets at high rates don't call the allocator for each one. A buffer is handed back to the
+/// pool when it's dropped, or, once made into `PacketData`, when that data and every clone and
+/// slice of it are dropped, such as after the packet is transmitted or dropped by a link.
+///
+/// When the pool is empty, `alloc` falls back to the allocator, and the pool keeps the extra
+/// buffer when it's returned if it has room.
+#[derive(Clone)]
+pub struct PacketPool {
+    buffers: Arc<Buffers>,
+}
+
+impl PacketPool {
+    /// Makes a pool of `capacity` buffers, each `buffer_size` bytes, such as the MTU of the
+    /// interface plus its link layer header.
+    pub fn new(capacity: usize, buffer_size: usize) -> Self {
+        assert!(
+            capacity > 0,
+            "PacketPool capacity: {}, must be > 0",
+            capacity
+        );
+
+        let free = ArrayQueue::new(capacity);
+        for _ in 0..capacity {
+            free.push(vec![0; buffer_size]).unwrap();
+        }
+        PacketPool {
+            buffers: Arc::new(Buffers {
+                free,
+                buffer_size,
+                misses: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Takes a buffer from the pool, `buffer_size` bytes long. The contents are whatever the last
+    /// packet in it left.
+    pub fn alloc(&self) -> PoolBuffer {
+        let data = self.buffers.free.pop().unwrap_or_else(|_| {
+            self.buffers.misses.fetch_add(1, Ordering::Relaxed);
+            vec![0; self.buffers.buffer_size]
+        });
+        PoolBuffer {
+            len: data.len(),
+            data,
+            pool: Arc::downgrade(&self.buffers),
+        }
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffers.buffer_size
+    }
+
+    /// The number of buffers in the pool, waiting to be allocated.
+    pub fn available(&self) -> usize {
+        self.buffers.free.len()
+    }
+
+    /// The number of times `alloc` found the pool empty and allocated a new buffer.
+    pub fn misses(&self) -> u64 {
+        self.buffers.misses.load(Ordering::Relaxed)
+    }
+}
+
+/// A buffer from a `PacketPool`, which returns to the pool when dropped. Receive a packet into it,
+/// `truncate` it to the packet's length, and make it `PacketData` with `freeze`.
+pub struct PoolBuffer {
+    data: Vec<u8>,
+    len: usize,
+    pool: Weak<Buffers>,
+}
+
+impl PoolBuffer {
+    /// Shortens the buffer to `len` bytes, which must be at most the buffer size.
+    pub fn truncate(&mut self, len: usize) {
+        assert!(
+            len <= self.data.len(),
+            "PoolBuffer len: {}, must be <= {}",
+            len,
+            self.data.len()
+        );
+        self.len = len;
+    }
+
+    /// Makes the buffer into `PacketData`, without copying it. The buffer returns to the pool once
+    /// the data, and every clone and slice of it, are dropped. Changing the data copies it out of
+    /// the buffer, returning the buffer early.
+    pub fn freeze(self) -> PacketData {
+        PacketData::from_owner(self)
+    }
+}
+
+impl Deref for PoolBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+impl DerefMut for PoolBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.data[..self.len]
+    }
+}
+
+impl AsRef<[u8]> for PoolBuffer {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Drop for PoolBuffer {
+    fn drop(&mut self) {
+        if let Some(buffers) = self.pool.upgrade() {
+            // A full pool just lets the buffer go.
+            let _ = buffers.free.push(std::mem::take(&mut self.data));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recycles_buffers() {
+        let pool = PacketPool::new(2, 1514);
+        let mut buffer = pool.alloc();
+        assert_eq!(buffer.len(), 1514);
+        assert_eq!(pool.available(), 1);
+
+        buffer[..3].copy_from_slice(&[1, 2, 3]);
+        buffer.truncate(3);
+        let ptr = buffer.as_ptr();
+        let data = buffer.freeze();
+        assert_eq!(data, [1, 2, 3]);
+        assert_eq!(data.as_ptr(), ptr);
+
+        // The buffer returns once every view of it is gone.
+        let header = data.slice(..1);
+        drop(data);
+        assert_eq!(pool.available(), 1);
+        drop(header);
+        assert_eq!(pool.available(), 2);
+
+        let buffers: Vec<_> = (0..2).map(|_| pool.alloc()).collect();
+        assert!(buffers.iter().any(|buffer| buffer.as_ptr() == ptr));
+        assert_eq!(pool.misses(), 0);
+    }
+
+    #[test]
+    fn allocates_when_empty() {
+        let pool = PacketPool::new(1, 64);
+        let first = pool.alloc();
+        let second = pool.alloc();
+        assert_eq!(pool.misses(), 1);
+        assert_eq!(second.len(), 64);
+
+        drop(first);
+        // The pool is full again, so the extra buffer is freed.
+        drop(second);
+        assert_eq!(pool.available(), 1);
+    }
+
+    #[test]
+    fn changed_data_returns_buffer() {
+        let pool = PacketPool::new(1, 4);
+        let mut data = pool.alloc().freeze();
+        assert_eq!(pool.available(), 0);
+        data[0] = 9;
+        assert_eq!(pool.available(), 1);
+        assert_eq!(data, [9, 0, 0, 0]);
+    }
+}
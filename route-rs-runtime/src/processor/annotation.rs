@@ -0,0 +1,247 @@
+use crate::classifier::{Classifier, TryClassifier};
+use crate::processor::{Processor, TryProcessor};
+use std::marker::PhantomData;
+
+/// A packet carrying metadata alongside it through the router, such as the interface it came in
+/// on, when it was received, or the verdict of an earlier classifier. Links pass annotated
+/// packets like any other, and `OnPacket` runs processors and classifiers written for the bare
+/// packet on them.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Annotated<P, M> {
+    pub packet: P,
+    pub metadata: M,
+}
+
+impl<P, M> Annotated<P, M> {
+    pub fn new(packet: P, metadata: M) -> Self {
+        Annotated { packet, metadata }
+    }
+
+    /// Transforms the packet, keeping the metadata.
+    pub fn map_packet<Q>(self, f: impl FnOnce(P) -> Q) -> Annotated<Q, M> {
+        Annotated::new(f(self.packet), self.metadata)
+    }
+
+    /// Transforms the metadata, keeping the packet.
+    pub fn map_metadata<N>(self, f: impl FnOnce(M) -> N) -> Annotated<P, N> {
+        Annotated::new(self.packet, f(self.metadata))
+    }
+
+    pub fn into_parts(self) -> (P, M) {
+        (self.packet, self.metadata)
+    }
+}
+
+/// Attaches metadata to each packet, computed from the packet by a closure. For the same
+/// metadata on every packet, such as the ingress interface, have the closure return a copy.
+pub struct Annotate<P, M, F> {
+    annotate: F,
+    phantom: PhantomData<(P, M)>,
+}
+
+impl<P, M, F> Annotate<P, M, F>
+where
+    F: FnMut(&P) -> M,
+{
+    pub fn new(annotate: F) -> Self {
+        Annotate {
+            annotate,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<P, M, F> Processor for Annotate<P, M, F>
+where
+    P: Send + Clone,
+    M: Send + Clone,
+    F: FnMut(&P) -> M,
+{
+    type Input = P;
+    type Output = Annotated<P, M>;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        let metadata = (self.annotate)(&packet);
+        Some(Annotated::new(packet, metadata))
+    }
+}
+
+/// Replaces the metadata of each packet with what a closure makes of the packet and its current
+/// metadata, for instance adding a verdict to what's already known.
+pub struct MapAnnotation<P, M, N, F> {
+    map: F,
+    phantom: PhantomData<(P, M, N)>,
+}
+
+impl<P, M, N, F> MapAnnotation<P, M, N, F>
+where
+    F: FnMut(&P, M) -> N,
+{
+    pub fn new(map: F) -> Self {
+        MapAnnotation {
+            map,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<P, M, N, F> Processor for MapAnnotation<P, M, N, F>
+where
+    P: Send + Clone,
+    M: Send + Clone,
+    N: Send + Clone,
+    F: FnMut(&P, M) -> N,
+{
+    type Input = Annotated<P, M>;
+    type Output = Annotated<P, N>;
+
+    fn process(&mut self, annotated: Self::Input) -> Option<Self::Output> {
+        let (packet, metadata) = annotated.into_parts();
+        let metadata = (self.map)(&packet, metadata);
+        Some(Annotated::new(packet, metadata))
+    }
+}
+
+/// Drops the metadata of each packet, leaving the bare packet, for instance before handing it to
+/// an interface to send.
+#[derive(Default)]
+pub struct StripAnnotation<P, M> {
+    phantom: PhantomData<(P, M)>,
+}
+
+impl<P, M> StripAnnotation<P, M> {
+    pub fn new() -> Self {
+        StripAnnotation {
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<P: Send + Clone, M: Send + Clone> Processor for StripAnnotation<P, M> {
+    type Input = Annotated<P, M>;
+    type Output = P;
+
+    fn process(&mut self, annotated: Self::Input) -> Option<Self::Output> {
+        Some(annotated.packet)
+    }
+}
+
+/// Runs a processor or classifier written for bare packets on annotated ones. Processors
+/// transform the packet and the metadata comes along unchanged; classifiers see just the packet.
+/// Metadata of a packet a `TryProcessor` fails on is dropped with it.
+pub struct OnPacket<T, M> {
+    inner: T,
+    phantom: PhantomData<M>,
+}
+
+impl<T, M> OnPacket<T, M> {
+    pub fn new(inner: T) -> Self {
+        OnPacket {
+            inner,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<T: Processor, M: Send + Clone> Processor for OnPacket<T, M> {
+    type Input = Annotated<T::Input, M>;
+    type Output = Annotated<T::Output, M>;
+
+    fn process(&mut self, annotated: Self::Input) -> Option<Self::Output> {
+        let (packet, metadata) = annotated.into_parts();
+        let packet = self.inner.process(packet)?;
+        Some(Annotated::new(packet, metadata))
+    }
+}
+
+impl<T: TryProcessor, M: Send + Clone> TryProcessor for OnPacket<T, M> {
+    type Input = Annotated<T::Input, M>;
+    type Output = Annotated<T::Output, M>;
+    type Error = T::Error;
+
+    fn try_process(&mut self, annotated: Self::Input) -> Result<Option<Self::Output>, T::Error> {
+        let (packet, metadata) = annotated.into_parts();
+        Ok(self
+            .inner
+            .try_process(packet)?
+            .map(|packet| Annotated::new(packet, metadata)))
+    }
+}
+
+impl<T: Classifier, M: Send + Clone> Classifier for OnPacket<T, M> {
+    type Packet = Annotated<T::Packet, M>;
+    type Class = T::Class;
+
+    fn classify(&self, annotated: &Self::Packet) -> Self::Class {
+        self.inner.classify(&annotated.packet)
+    }
+}
+
+impl<T: TryClassifier, M: Send + Clone> TryClassifier for OnPacket<T, M> {
+    type Packet = Annotated<T::Packet, M>;
+    type Class = T::Class;
+    type Reason = T::Reason;
+
+    fn try_classify(&self, annotated: &Self::Packet) -> Result<Self::Class, Self::Reason> {
+        self.inner.try_classify(&annotated.packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classifier::Even;
+    use crate::link::primitive::ProcessLink;
+    use crate::link::{LinkBuilder, ProcessLinkBuilder};
+    use crate::processor::Identity;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum Interface {
+        Lan,
+        Wan,
+    }
+
+    #[test]
+    fn annotate_map_strip() {
+        let mut annotate = Annotate::new(|_: &i32| Interface::Lan);
+        let packet = annotate.process(7).unwrap();
+        assert_eq!(packet, Annotated::new(7, Interface::Lan));
+
+        let mut map = MapAnnotation::new(|packet: &i32, interface| (interface, packet % 2 == 0));
+        let packet = map.process(packet).unwrap();
+        assert_eq!(packet.metadata, (Interface::Lan, false));
+
+        assert_eq!(StripAnnotation::new().process(packet), Some(7));
+    }
+
+    #[test]
+    fn runs_bare_processors_and_classifiers() {
+        let mut double = OnPacket::new(Identity::new().map(|packet: i32| packet * 2));
+        let packet = double.process(Annotated::new(3, Interface::Wan)).unwrap();
+        assert_eq!(packet, Annotated::new(6, Interface::Wan));
+
+        let even = OnPacket::new(Even::new());
+        assert!(even.classify(&packet));
+    }
+
+    #[test]
+    fn annotated_packets_flow_through_links() {
+        let packets = vec![
+            Annotated::new(1, Interface::Lan),
+            Annotated::new(2, Interface::Wan),
+        ];
+
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = ProcessLink::new()
+                .ingressor(immediate_stream(packets.clone()))
+                .processor(OnPacket::new(Identity::new()))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], packets);
+    }
+}
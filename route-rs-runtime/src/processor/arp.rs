@@ -0,0 +1,577 @@
+use crate::classifier::Classifier;
+use crate::link::primitive::{ClassifyLink, ProcessLink, UnbatchLink};
+use crate::link::{Link, LinkBuilder, PacketStream, ProcessLinkBuilder};
+use crate::processor::Processor;
+use route_rs_packets::{
+    ArpFrame, ArpHardwareType, ArpOp, EthernetFrame, MacAddr, ARP_ETHER_TYPE, IPV4_ETHER_TYPE,
+};
+use std::collections::{HashMap, VecDeque};
+use std::convert::{TryFrom, TryInto};
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const BROADCAST_MAC: MacAddr = MacAddr {
+    bytes: [0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+};
+
+struct ArpEntry {
+    /// None while the address is being requested.
+    mac: Option<MacAddr>,
+    /// When the entry was last confirmed, or last requested.
+    updated: Instant,
+    /// Requests sent while the address is unresolved.
+    requests: u32,
+    /// Packets waiting for the address to be resolved.
+    pending: VecDeque<EthernetFrame>,
+}
+
+/// What to do with a packet for an address.
+#[derive(Debug, PartialEq)]
+enum ArpResolution {
+    Send(MacAddr),
+    /// The packet was queued, and a request for the address should be sent.
+    Request,
+    /// The packet was queued, or dropped, while the address is requested.
+    Wait,
+}
+
+/// The ARP cache shared by an `ArpResponder` and `ArpResolver`, mapping IPv4 addresses on the
+/// link to their MAC addresses. Entries age out after `timeout`, and are requested afresh. While
+/// an address is being requested, up to `max_pending` packets for it are queued, to be sent
+/// when it's resolved. Cloning it gives another handle to the same cache.
+#[derive(Clone)]
+pub struct ArpCache {
+    timeout: Duration,
+    retrans_timer: Duration,
+    max_requests: u32,
+    max_pending: usize,
+    entries: Arc<Mutex<HashMap<Ipv4Addr, ArpEntry>>>,
+}
+
+impl Default for ArpCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ArpCache {
+    pub fn new() -> Self {
+        ArpCache {
+            timeout: Duration::from_secs(60),
+            retrans_timer: Duration::from_secs(1),
+            max_requests: 3,
+            max_pending: 3,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// How long an entry is used after it's confirmed, default 60 seconds.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        ArpCache { timeout, ..self }
+    }
+
+    /// How long to wait between requests, default 1 second.
+    pub fn retrans_timer(self, retrans_timer: Duration) -> Self {
+        ArpCache {
+            retrans_timer,
+            ..self
+        }
+    }
+
+    /// How many requests go unanswered before an address is given up on, and the packets
+    /// waiting for it dropped, default 3.
+    pub fn max_requests(self, max_requests: u32) -> Self {
+        assert!(
+            max_requests > 0,
+            "ArpCache max_requests: {}, must be > 0",
+            max_requests
+        );
+
+        ArpCache {
+            max_requests,
+            ..self
+        }
+    }
+
+    /// How many packets may wait for each address, default 3. Later packets are dropped.
+    pub fn max_pending(self, max_pending: usize) -> Self {
+        ArpCache {
+            max_pending,
+            ..self
+        }
+    }
+
+    /// Returns the MAC address of `addr`, if it's resolved and hasn't aged out.
+    pub fn lookup(&self, addr: &Ipv4Addr) -> Option<MacAddr> {
+        self.lookup_at(addr, Instant::now())
+    }
+
+    fn lookup_at(&self, addr: &Ipv4Addr, now: Instant) -> Option<MacAddr> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(addr)?;
+        if now.duration_since(entry.updated) >= self.timeout {
+            return None;
+        }
+        entry.mac
+    }
+
+    /// Records that `addr` is at `mac`. Returns the packets that were waiting for it.
+    pub fn insert(&self, addr: Ipv4Addr, mac: MacAddr) -> Vec<EthernetFrame> {
+        self.insert_at(addr, mac, Instant::now())
+    }
+
+    fn insert_at(&self, addr: Ipv4Addr, mac: MacAddr, now: Instant) -> Vec<EthernetFrame> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(addr).or_insert_with(|| ArpEntry {
+            mac: None,
+            updated: now,
+            requests: 0,
+            pending: VecDeque::new(),
+        });
+        entry.mac = Some(mac);
+        entry.updated = now;
+        entry.requests = 0;
+        entry.pending.drain(..).collect()
+    }
+
+    /// Updates `addr` to `mac` if it's in the cache, as the merge step of RFC 826 does for
+    /// every ARP packet. Returns whether it was, and the packets that were waiting for it.
+    fn update_at(&self, addr: Ipv4Addr, mac: MacAddr, now: Instant) -> Option<Vec<EthernetFrame>> {
+        if !self.entries.lock().unwrap().contains_key(&addr) {
+            return None;
+        }
+        Some(self.insert_at(addr, mac, now))
+    }
+
+    pub fn remove(&self, addr: &Ipv4Addr) -> Option<MacAddr> {
+        self.entries.lock().unwrap().remove(addr)?.mac
+    }
+
+    /// Finds the MAC address to send `frame` to `addr` with, or queues it while `addr` is
+    /// requested.
+    fn resolve_at(&self, addr: Ipv4Addr, frame: EthernetFrame, now: Instant) -> ArpResolution {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(addr).or_insert_with(|| ArpEntry {
+            mac: None,
+            updated: now,
+            requests: 0,
+            pending: VecDeque::new(),
+        });
+        let elapsed = now.duration_since(entry.updated);
+
+        if let Some(mac) = entry.mac {
+            if elapsed < self.timeout {
+                return ArpResolution::Send(mac);
+            }
+            // The entry aged out; request it again.
+            entry.mac = None;
+            entry.requests = 0;
+        }
+
+        if entry.pending.len() < self.max_pending {
+            entry.pending.push_back(frame);
+        }
+        if entry.requests == 0 || elapsed >= self.retrans_timer {
+            if entry.requests >= self.max_requests {
+                // Nobody answered, so give up on the address, and the packets waiting for it.
+                entries.remove(&addr);
+                return ArpResolution::Wait;
+            }
+            entry.requests += 1;
+            entry.updated = now;
+            return ArpResolution::Request;
+        }
+        ArpResolution::Wait
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Builds an ARP packet of `op`, from the `sender` address and MAC address to the `target` ones,
+/// sent to `dest_mac`.
+fn arp_frame(
+    op: ArpOp,
+    sender: (Ipv4Addr, MacAddr),
+    target: (Ipv4Addr, MacAddr),
+    dest_mac: MacAddr,
+) -> EthernetFrame {
+    let mut arp = ArpFrame::new(6, 4);
+    arp.set_hardware_type(ArpHardwareType::Ethernet as u16)
+        .set_protocol_type(IPV4_ETHER_TYPE)
+        .set_opcode(op as u16)
+        .set_sender_hardware_addr(sender.1)
+        .set_sender_protocol_addr(sender.0.into())
+        .set_target_hardware_addr(target.1)
+        .set_target_protocol_addr(target.0.into());
+
+    let mut frame = arp.frame();
+    frame.set_src_mac(sender.1);
+    frame.set_dest_mac(dest_mac);
+    frame
+}
+
+/// A gratuitous ARP request, announcing that `addr` is at `mac`, so neighbors update their
+/// caches. Send it when an interface comes up, or its address or MAC address changes.
+pub fn gratuitous_arp(addr: Ipv4Addr, mac: MacAddr) -> EthernetFrame {
+    arp_frame(
+        ArpOp::Request,
+        (addr, mac),
+        (addr, MacAddr::new([0; 6])),
+        BROADCAST_MAC,
+    )
+}
+
+/// Parses an ARP packet for IPv4 over Ethernet, returning it with its sender and target.
+fn ipv4_arp(frame: EthernetFrame) -> Option<(ArpFrame, (Ipv4Addr, MacAddr), Ipv4Addr)> {
+    let arp = ArpFrame::try_from(frame).ok()?;
+    if arp.hardware_type() != ArpHardwareType::Ethernet as u16
+        || arp.protocol_type() != IPV4_ETHER_TYPE
+        || arp.hardware_addr_len() != 6
+        || arp.protocol_addr_len() != 4
+    {
+        return None;
+    }
+    let sender_mac = MacAddr::new(arp.sender_hardware_addr().try_into().unwrap());
+    let sender = <[u8; 4]>::try_from(arp.sender_protocol_addr()).unwrap();
+    let target = <[u8; 4]>::try_from(arp.target_protocol_addr()).unwrap();
+    Some((arp, (sender.into(), sender_mac), target.into()))
+}
+
+/// Classifies frames by whether they're ARP.
+#[derive(Default)]
+pub struct Arp {}
+
+impl Arp {
+    pub fn new() -> Self {
+        Arp {}
+    }
+}
+
+impl Classifier for Arp {
+    type Packet = EthernetFrame;
+    type Class = bool;
+
+    fn classify(&self, frame: &Self::Packet) -> Self::Class {
+        frame.ether_type() == ARP_ETHER_TYPE
+    }
+}
+
+/// Answers ARP requests for `addresses`, the router's own on the link, with replies from `mac`,
+/// and keeps `cache` up to date from the ARP packets it sees, per RFC 826. Requesters are added
+/// to the cache, since they're likely to be sent to next; other senders only update the
+/// addresses already in it. The replies are output with any packets that were waiting for the
+/// senders' addresses. Anything that isn't ARP is dropped.
+pub struct ArpResponder {
+    cache: ArpCache,
+    addresses: Vec<Ipv4Addr>,
+    mac: MacAddr,
+}
+
+impl ArpResponder {
+    pub fn new(cache: ArpCache, addresses: Vec<Ipv4Addr>, mac: MacAddr) -> Self {
+        ArpResponder {
+            cache,
+            addresses,
+            mac,
+        }
+    }
+
+    fn process_at(&mut self, frame: EthernetFrame, now: Instant) -> Option<Vec<EthernetFrame>> {
+        let (arp, (sender, sender_mac), target) = ipv4_arp(frame)?;
+        // Probes, sent before taking an address, have no sender address to learn.
+        if sender.is_unspecified() {
+            if arp.opcode() == ArpOp::Request as u16 && self.addresses.contains(&target) {
+                return Some(vec![self.reply(target, sender, sender_mac)]);
+            }
+            return None;
+        }
+
+        let for_us = self.addresses.contains(&target);
+        let mut released = match self.cache.update_at(sender, sender_mac, now) {
+            Some(released) => released,
+            None if for_us => self.cache.insert_at(sender, sender_mac, now),
+            None => vec![],
+        };
+        for packet in released.iter_mut() {
+            packet.set_src_mac(self.mac);
+            packet.set_dest_mac(sender_mac);
+        }
+
+        if for_us && arp.opcode() == ArpOp::Request as u16 {
+            released.insert(0, self.reply(target, sender, sender_mac));
+        }
+        Some(released)
+    }
+
+    fn reply(&self, addr: Ipv4Addr, requester: Ipv4Addr, requester_mac: MacAddr) -> EthernetFrame {
+        arp_frame(
+            ArpOp::Reply,
+            (addr, self.mac),
+            (requester, requester_mac),
+            requester_mac,
+        )
+    }
+}
+
+impl Processor for ArpResponder {
+    type Input = EthernetFrame;
+    type Output = Vec<EthernetFrame>;
+
+    fn process(&mut self, frame: Self::Input) -> Option<Self::Output> {
+        self.process_at(frame, Instant::now())
+    }
+}
+
+/// Addresses IPv4 frames to their next hop, from `addr` and `mac`, the router's own on the link,
+/// by looking it up in `cache`. The next hop is the packet's destination, or `gateway` if set.
+/// Packets for unresolved next hops wait in the cache, and an ARP request for the next hop is
+/// sent in their place; the `ArpResponder` sends them on once it's answered. Frames that aren't
+/// IPv4 are dropped.
+pub struct ArpResolver {
+    cache: ArpCache,
+    addr: Ipv4Addr,
+    mac: MacAddr,
+    gateway: Option<Ipv4Addr>,
+}
+
+impl ArpResolver {
+    pub fn new(cache: ArpCache, addr: Ipv4Addr, mac: MacAddr) -> Self {
+        ArpResolver {
+            cache,
+            addr,
+            mac,
+            gateway: None,
+        }
+    }
+
+    /// Sends every packet through `gateway`, rather than straight to its destination.
+    pub fn gateway(self, gateway: Ipv4Addr) -> Self {
+        ArpResolver {
+            gateway: Some(gateway),
+            ..self
+        }
+    }
+
+    fn process_at(&mut self, mut frame: EthernetFrame, now: Instant) -> Option<EthernetFrame> {
+        if frame.ether_type() != IPV4_ETHER_TYPE {
+            return None;
+        }
+        let next_hop = match self.gateway {
+            Some(gateway) => gateway,
+            None => {
+                let ip = frame.payload_offset;
+                let dst = frame.data.get(ip + 16..ip + 20)?;
+                Ipv4Addr::from(<[u8; 4]>::try_from(dst).unwrap())
+            }
+        };
+
+        frame.set_src_mac(self.mac);
+        match self.cache.resolve_at(next_hop, frame.clone(), now) {
+            ArpResolution::Send(mac) => {
+                frame.set_dest_mac(mac);
+                Some(frame)
+            }
+            ArpResolution::Request => Some(arp_frame(
+                ArpOp::Request,
+                (self.addr, self.mac),
+                (next_hop, MacAddr::new([0; 6])),
+                BROADCAST_MAC,
+            )),
+            ArpResolution::Wait => None,
+        }
+    }
+}
+
+impl Processor for ArpResolver {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, frame: Self::Input) -> Option<Self::Output> {
+        self.process_at(frame, Instant::now())
+    }
+}
+
+/// Handles the ARP in `stream` with `responder`. Other frames pass through to the first
+/// egressor, and the replies, and packets released by them, are sent out the second.
+pub fn arp_link(
+    stream: PacketStream<EthernetFrame>,
+    responder: ArpResponder,
+) -> Link<EthernetFrame> {
+    let (mut runnables, mut egressors) = ClassifyLink::new()
+        .ingressor(stream)
+        .num_egressors(2)
+        .classifier(Arp::new())
+        .dispatcher(Box::new(|is_arp| if is_arp { 1 } else { 0 }))
+        .build_link();
+
+    let (process_runnables, process_egressors) = ProcessLink::new()
+        .ingressor(egressors.pop().unwrap())
+        .processor(responder)
+        .build_link();
+    let (unbatch_runnables, unbatch_egressors) = UnbatchLink::new()
+        .ingressors(process_egressors)
+        .build_link();
+    runnables.extend(process_runnables);
+    runnables.extend(unbatch_runnables);
+    egressors.extend(unbatch_egressors);
+    (runnables, egressors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use route_rs_packets::Ipv4Packet;
+
+    const ROUTER: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 1);
+    const HOST: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 20);
+    const OTHER: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 30);
+    const ROUTER_MAC: MacAddr = MacAddr {
+        bytes: [2, 0, 0, 0, 0, 1],
+    };
+    const HOST_MAC: MacAddr = MacAddr {
+        bytes: [2, 0, 0, 0, 0, 20],
+    };
+
+    fn ipv4_frame(dst: Ipv4Addr) -> EthernetFrame {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_ttl(64);
+        packet.set_protocol(17);
+        packet.set_src_addr(ROUTER);
+        packet.set_dest_addr(dst);
+        packet.set_payload(&[1, 2, 3, 4]);
+        packet.set_checksum();
+        EthernetFrame::encap_ipv4(packet)
+    }
+
+    fn request(target: Ipv4Addr) -> EthernetFrame {
+        arp_frame(
+            ArpOp::Request,
+            (HOST, HOST_MAC),
+            (target, MacAddr::new([0; 6])),
+            BROADCAST_MAC,
+        )
+    }
+
+    #[test]
+    fn answers_requests() {
+        let cache = ArpCache::new();
+        let mut responder = ArpResponder::new(cache.clone(), vec![ROUTER], ROUTER_MAC);
+
+        let replies = responder.process(request(ROUTER)).unwrap();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].dest_mac(), HOST_MAC);
+        let (reply, sender, target) = ipv4_arp(replies[0].clone()).unwrap();
+        assert_eq!(reply.opcode(), ArpOp::Reply as u16);
+        assert_eq!(sender, (ROUTER, ROUTER_MAC));
+        assert_eq!(target, HOST);
+        assert_eq!(reply.target_hardware_addr(), HOST_MAC.bytes);
+        assert_eq!(cache.lookup(&HOST), Some(HOST_MAC));
+
+        // Requests for other hosts aren't answered, nor learned from.
+        let mut other = ArpResponder::new(ArpCache::new(), vec![OTHER], ROUTER_MAC);
+        assert_eq!(other.process(request(ROUTER)), Some(vec![]));
+        assert!(other.cache.is_empty());
+        assert_eq!(responder.process(ipv4_frame(HOST)), None);
+    }
+
+    #[test]
+    fn resolves_and_releases_pending_packets() {
+        let cache = ArpCache::new().max_pending(2);
+        let mut responder = ArpResponder::new(cache.clone(), vec![ROUTER], ROUTER_MAC);
+        let mut resolver = ArpResolver::new(cache.clone(), ROUTER, ROUTER_MAC);
+        let now = Instant::now();
+
+        let request = resolver.process_at(ipv4_frame(HOST), now).unwrap();
+        assert_eq!(request.dest_mac(), BROADCAST_MAC);
+        let (arp, sender, target) = ipv4_arp(request).unwrap();
+        assert_eq!(arp.opcode(), ArpOp::Request as u16);
+        assert_eq!(sender, (ROUTER, ROUTER_MAC));
+        assert_eq!(target, HOST);
+
+        assert_eq!(resolver.process_at(ipv4_frame(HOST), now), None);
+        assert_eq!(resolver.process_at(ipv4_frame(HOST), now), None);
+
+        let reply = arp_frame(
+            ArpOp::Reply,
+            (HOST, HOST_MAC),
+            (ROUTER, ROUTER_MAC),
+            ROUTER_MAC,
+        );
+        let released = responder.process_at(reply, now).unwrap();
+        assert_eq!(released.len(), 2);
+        for packet in released {
+            assert_eq!(packet.src_mac(), ROUTER_MAC);
+            assert_eq!(packet.dest_mac(), HOST_MAC);
+            assert_eq!(packet.payload(), ipv4_frame(HOST).payload());
+        }
+
+        let sent = resolver.process_at(ipv4_frame(HOST), now).unwrap();
+        assert_eq!(sent.dest_mac(), HOST_MAC);
+
+        // Once the entry ages out, it's requested again.
+        let later = now + Duration::from_secs(60);
+        assert_eq!(cache.lookup_at(&HOST, later), None);
+        let request = resolver.process_at(ipv4_frame(HOST), later).unwrap();
+        assert_eq!(request.ether_type(), ARP_ETHER_TYPE);
+    }
+
+    #[test]
+    fn gives_up_on_silent_hosts() {
+        let cache = ArpCache::new().max_requests(2);
+        let mut resolver = ArpResolver::new(cache.clone(), ROUTER, ROUTER_MAC).gateway(HOST);
+        let now = Instant::now();
+        let second = Duration::from_secs(1);
+
+        assert!(resolver.process_at(ipv4_frame(OTHER), now).is_some());
+        assert!(resolver
+            .process_at(ipv4_frame(OTHER), now + second)
+            .is_some());
+        assert_eq!(
+            resolver.process_at(ipv4_frame(OTHER), now + second * 2),
+            None
+        );
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn gratuitous_arp_updates_caches() {
+        let cache = ArpCache::new();
+        cache.insert(ROUTER, MacAddr::new([2, 0, 0, 0, 0, 99]));
+        let mut responder = ArpResponder::new(cache.clone(), vec![HOST], HOST_MAC);
+
+        let announcement = gratuitous_arp(ROUTER, ROUTER_MAC);
+        assert_eq!(announcement.dest_mac(), BROADCAST_MAC);
+        assert_eq!(responder.process(announcement), Some(vec![]));
+        assert_eq!(cache.lookup(&ROUTER), Some(ROUTER_MAC));
+
+        // Announcements only update hosts that are already cached.
+        let announcement = gratuitous_arp(OTHER, HOST_MAC);
+        responder.process(announcement);
+        assert_eq!(cache.lookup(&OTHER), None);
+    }
+
+    #[test]
+    fn arp_link_splits_arp() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let cache = ArpCache::new();
+            let responder = ArpResponder::new(cache, vec![ROUTER], ROUTER_MAC);
+            let packets = vec![request(ROUTER), ipv4_frame(HOST), request(OTHER)];
+            let link = arp_link(immediate_stream(packets), responder);
+            run_link(link).await
+        });
+
+        assert_eq!(results[0], vec![ipv4_frame(HOST)]);
+        assert_eq!(results[1].len(), 1);
+        assert_eq!(results[1][0].dest_mac(), HOST_MAC);
+    }
+}
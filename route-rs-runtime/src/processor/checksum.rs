@@ -0,0 +1,325 @@
+use crate::processor::Processor;
+use route_rs_packets::{pseudo_header_checksum, Ipv4Packet, TcpSegment, UdpSegment};
+use std::convert::TryInto;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Returns the source and destination addresses of the IPv4 or IPv6 header at `layer3_offset`,
+/// which go into the pseudo-header, if the header is all there.
+fn addresses(data: &[u8], layer3_offset: usize) -> Option<(IpAddr, IpAddr)> {
+    match data.get(layer3_offset)? >> 4 {
+        4 => {
+            let addresses = data.get(layer3_offset + 12..layer3_offset + 20)?;
+            let src: [u8; 4] = addresses[..4].try_into().unwrap();
+            let dest: [u8; 4] = addresses[4..].try_into().unwrap();
+            Some((Ipv4Addr::from(src).into(), Ipv4Addr::from(dest).into()))
+        }
+        6 => {
+            let addresses = data.get(layer3_offset + 8..layer3_offset + 40)?;
+            let src: [u8; 16] = addresses[..16].try_into().unwrap();
+            let dest: [u8; 16] = addresses[16..].try_into().unwrap();
+            Some((Ipv6Addr::from(src).into(), Ipv6Addr::from(dest).into()))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the checksum over the pseudo-header and the `length` bytes of the TCP or UDP segment
+/// at `layer4_offset`, carried by the IP header at `layer3_offset`. With the checksum field
+/// counted, a valid segment comes to zero, and with it zeroed, to the checksum.
+fn layer4_checksum(
+    data: &[u8],
+    layer3_offset: usize,
+    layer4_offset: usize,
+    length: usize,
+    protocol: u8,
+) -> Option<u16> {
+    let (src, dest) = addresses(data, layer3_offset)?;
+    let segment = data.get(layer4_offset..layer4_offset + length)?;
+    Some(pseudo_header_checksum(src, dest, protocol, segment))
+}
+
+/// Recomputes the header checksum of IPv4 packets, for packets modified earlier in the graph.
+/// Set to `validate`, it instead drops packets with the wrong checksum.
+///
+/// The setters of the packet types already update the checksums covering the fields they change
+/// incrementally, as NAT and TTL decrements do, so these processors are the full recompute, for
+/// packets changed in ways the setters can't follow, such as a rewritten payload.
+#[derive(Default)]
+pub struct Ipv4Checksum {
+    validate: bool,
+}
+
+impl Ipv4Checksum {
+    pub fn new() -> Self {
+        Ipv4Checksum { validate: false }
+    }
+
+    /// Checks, rather than sets, the checksum, dropping packets where it is wrong.
+    pub fn validate(self) -> Self {
+        Ipv4Checksum { validate: true }
+    }
+}
+
+impl Processor for Ipv4Checksum {
+    type Input = Ipv4Packet;
+    type Output = Ipv4Packet;
+
+    fn process(&mut self, mut packet: Self::Input) -> Option<Self::Output> {
+        if self.validate {
+            if packet.validate_checksum() {
+                Some(packet)
+            } else {
+                None
+            }
+        } else {
+            packet.set_checksum();
+            Some(packet)
+        }
+    }
+}
+
+/// Recomputes the checksum of TCP segments, over the segment and the pseudo-header of the IPv4
+/// or IPv6 packet carrying it. Set to `validate`, it instead drops segments with the wrong
+/// checksum. Segments without an IP header have no pseudo-header, so are dropped.
+#[derive(Default)]
+pub struct TcpChecksum {
+    validate: bool,
+}
+
+impl TcpChecksum {
+    pub fn new() -> Self {
+        TcpChecksum { validate: false }
+    }
+
+    /// Checks, rather than sets, the checksum, dropping segments where it is wrong.
+    pub fn validate(self) -> Self {
+        TcpChecksum { validate: true }
+    }
+}
+
+impl Processor for TcpChecksum {
+    type Input = TcpSegment;
+    type Output = TcpSegment;
+
+    fn process(&mut self, mut segment: Self::Input) -> Option<Self::Output> {
+        let layer3_offset = segment.layer3_offset?;
+        let length = segment.data.len().checked_sub(segment.layer4_offset)?;
+
+        if self.validate {
+            match layer4_checksum(
+                &segment.data,
+                layer3_offset,
+                segment.layer4_offset,
+                length,
+                6,
+            )? {
+                0 => Some(segment),
+                _ => None,
+            }
+        } else {
+            segment.set_checksum(0);
+            let checksum = layer4_checksum(
+                &segment.data,
+                layer3_offset,
+                segment.layer4_offset,
+                length,
+                6,
+            )?;
+            segment.set_checksum(checksum);
+            Some(segment)
+        }
+    }
+}
+
+/// Recomputes the checksum of UDP segments, over the segment and the pseudo-header of the IPv4
+/// or IPv6 packet carrying it. Set to `validate`, it instead drops segments with the wrong
+/// checksum. Over IPv4, a checksum of zero means none was computed, so such segments are valid.
+/// Segments without an IP header have no pseudo-header, so are dropped.
+#[derive(Default)]
+pub struct UdpChecksum {
+    validate: bool,
+}
+
+impl UdpChecksum {
+    pub fn new() -> Self {
+        UdpChecksum { validate: false }
+    }
+
+    /// Checks, rather than sets, the checksum, dropping segments where it is wrong.
+    pub fn validate(self) -> Self {
+        UdpChecksum { validate: true }
+    }
+}
+
+impl Processor for UdpChecksum {
+    type Input = UdpSegment;
+    type Output = UdpSegment;
+
+    fn process(&mut self, mut segment: Self::Input) -> Option<Self::Output> {
+        let layer3_offset = segment.layer3_offset?;
+        // A length field shorter than the header, or longer than the segment, can't be checked.
+        let length = segment.length() as usize;
+        let available = segment.data.len().checked_sub(segment.layer4_offset)?;
+        if length < 8 || length > available {
+            return None;
+        }
+        let over_ipv4 = segment.data[layer3_offset] >> 4 == 4;
+
+        if self.validate {
+            if over_ipv4 && segment.checksum() == 0 {
+                return Some(segment);
+            }
+            match layer4_checksum(
+                &segment.data,
+                layer3_offset,
+                segment.layer4_offset,
+                length,
+                17,
+            )? {
+                0 => Some(segment),
+                _ => None,
+            }
+        } else {
+            segment.set_checksum(0);
+            let checksum = layer4_checksum(
+                &segment.data,
+                layer3_offset,
+                segment.layer4_offset,
+                length,
+                17,
+            )?;
+            // A computed checksum of zero is sent as all ones, since zero means none.
+            segment.set_checksum(match checksum {
+                0 => 0xFFFF,
+                checksum => checksum,
+            });
+            Some(segment)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use route_rs_packets::{internet_checksum, update_u32, Ipv6Packet};
+    use std::convert::TryFrom;
+    use std::net::Ipv4Addr;
+
+    fn ipv4_packet(protocol: u8, payload: &[u8]) -> Ipv4Packet {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_ttl(64);
+        packet.set_protocol(protocol);
+        packet.set_src_addr(Ipv4Addr::new(192, 168, 0, 1));
+        packet.set_dest_addr(Ipv4Addr::new(192, 168, 0, 199));
+        packet.set_payload(payload);
+        packet
+    }
+
+    /// A UDP segment from port 1000 to 2000, with a 3 byte payload.
+    const UDP_SEGMENT: [u8; 11] = [0x03, 0xe8, 0x07, 0xd0, 0, 11, 0, 0, 1, 2, 3];
+
+    #[test]
+    fn internet_checksum_example() {
+        // A commonly used example header, which checksums to 0xB861.
+        let header = [
+            0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 0xc0, 0xa8,
+            0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7,
+        ];
+        assert_eq!(internet_checksum(&[&header]), 0xb861);
+    }
+
+    #[test]
+    fn update_matches_recompute() {
+        let mut header = [
+            0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0xb8, 0x61, 0xc0, 0xa8,
+            0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7,
+        ];
+        header[16..20].copy_from_slice(&[10, 0, 0, 1]);
+        let updated = update_u32(0xb861, 0xc0a8_00c7, 0x0a00_0001);
+
+        header[10..12].copy_from_slice(&[0, 0]);
+        assert_eq!(updated, internet_checksum(&[&header]));
+    }
+
+    #[test]
+    fn ipv4_checksum() {
+        let packet = ipv4_packet(17, &[]);
+        assert!(Ipv4Checksum::new()
+            .validate()
+            .process(packet.clone())
+            .is_none());
+
+        let packet = Ipv4Checksum::new().process(packet).unwrap();
+        assert!(Ipv4Checksum::new().validate().process(packet).is_some());
+    }
+
+    #[test]
+    fn udp_checksum_over_ipv4() {
+        let segment = UdpSegment::try_from(ipv4_packet(17, &UDP_SEGMENT)).unwrap();
+        // Zero means no checksum was computed.
+        assert!(UdpChecksum::new()
+            .validate()
+            .process(segment.clone())
+            .is_some());
+
+        let mut segment = UdpChecksum::new().process(segment).unwrap();
+        assert_ne!(segment.checksum(), 0);
+        assert!(UdpChecksum::new()
+            .validate()
+            .process(segment.clone())
+            .is_some());
+
+        segment.set_payload(&[1, 2, 4]);
+        assert!(UdpChecksum::new().validate().process(segment).is_none());
+    }
+
+    #[test]
+    fn udp_checksum_over_ipv6() {
+        let mut packet = Ipv6Packet::empty();
+        packet.set_next_header(17);
+        packet.set_src_addr("2001:db8::1".parse().unwrap());
+        packet.set_dest_addr("2001:db8::2".parse().unwrap());
+        packet.set_payload(&UDP_SEGMENT);
+        let segment = UdpSegment::try_from(packet).unwrap();
+        assert!(UdpChecksum::new()
+            .validate()
+            .process(segment.clone())
+            .is_none());
+
+        let segment = UdpChecksum::new().process(segment).unwrap();
+        assert!(UdpChecksum::new().validate().process(segment).is_some());
+    }
+
+    #[test]
+    fn tcp_checksum() {
+        let mut tcp = vec![0; 20];
+        tcp[12] = 5 << 4;
+        tcp.extend_from_slice(&[0xde, 0xad, 0xbe]);
+        let segment = TcpSegment::try_from(ipv4_packet(6, &tcp)).unwrap();
+        assert!(TcpChecksum::new()
+            .validate()
+            .process(segment.clone())
+            .is_none());
+
+        let segment = TcpChecksum::new().process(segment).unwrap();
+        assert!(TcpChecksum::new().validate().process(segment).is_some());
+    }
+
+    #[test]
+    fn udp_checksum_bad_length() {
+        let segment = UdpSegment::try_from(ipv4_packet(17, &UDP_SEGMENT)).unwrap();
+        for length in [4, 12].iter() {
+            let mut segment = segment.clone();
+            segment.data[segment.layer4_offset + 4..segment.layer4_offset + 6]
+                .copy_from_slice(&(*length as u16).to_be_bytes());
+            assert!(UdpChecksum::new().process(segment.clone()).is_none());
+            assert!(UdpChecksum::new().validate().process(segment).is_none());
+        }
+    }
+
+    #[test]
+    fn drops_segments_without_ip_header() {
+        assert!(UdpChecksum::new().process(UdpSegment::empty()).is_none());
+        assert!(TcpChecksum::new().process(TcpSegment::empty()).is_none());
+    }
+}
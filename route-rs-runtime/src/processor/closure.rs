@@ -0,0 +1,84 @@
+use crate::processor::Processor;
+use std::marker::PhantomData;
+
+/// Processor that runs packets through a closure, for one-off transforms that don't merit their
+/// own processor. Returning `None` drops the packet.
+pub struct ClosureProcessor<Input, Output, F> {
+    f: F,
+    phantom: PhantomData<fn(Input) -> Output>,
+}
+
+impl<Input, Output, F> ClosureProcessor<Input, Output, F>
+where
+    Input: Send + Clone,
+    Output: Send + Clone,
+    F: FnMut(Input) -> Option<Output>,
+{
+    pub fn new(f: F) -> Self {
+        ClosureProcessor {
+            f,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<Input, Output, F> From<F> for ClosureProcessor<Input, Output, F>
+where
+    Input: Send + Clone,
+    Output: Send + Clone,
+    F: FnMut(Input) -> Option<Output>,
+{
+    fn from(f: F) -> Self {
+        ClosureProcessor::new(f)
+    }
+}
+
+impl<Input, Output, F> Processor for ClosureProcessor<Input, Output, F>
+where
+    Input: Send + Clone,
+    Output: Send + Clone,
+    F: FnMut(Input) -> Option<Output>,
+{
+    type Input = Input;
+    type Output = Output;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        (self.f)(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::primitive::ProcessLink;
+    use crate::link::{LinkBuilder, ProcessLinkBuilder};
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+
+    #[test]
+    fn runs_closure_in_process_link() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let link = ProcessLink::new()
+                .ingressor(immediate_stream(vec![1, 2, 3, 4]))
+                .processor(ClosureProcessor::new(|packet: i32| {
+                    if packet % 2 == 0 {
+                        Some(packet.to_string())
+                    } else {
+                        None
+                    }
+                }))
+                .build_link();
+
+            run_link(link).await
+        });
+        assert_eq!(results[0], vec!["2", "4"]);
+    }
+
+    #[test]
+    fn from_closure() {
+        let mut processor: ClosureProcessor<u8, u16, _> =
+            (|packet: u8| Some(u16::from(packet) * 2)).into();
+        assert_eq!(processor.process(200), Some(400));
+    }
+}
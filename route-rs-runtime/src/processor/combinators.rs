@@ -0,0 +1,171 @@
+use crate::processor::Processor;
+
+/// Processor that runs packets through `first`, then through `second`. Made with
+/// `Processor::chain`.
+pub struct Chain<A: Processor, B: Processor<Input = A::Output>> {
+    first: A,
+    second: B,
+}
+
+impl<A: Processor, B: Processor<Input = A::Output>> Chain<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        Chain { first, second }
+    }
+}
+
+impl<A: Processor, B: Processor<Input = A::Output>> Processor for Chain<A, B> {
+    type Input = A::Input;
+    type Output = B::Output;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        self.first
+            .process(packet)
+            .and_then(|packet| self.second.process(packet))
+    }
+
+    fn process_batch(&mut self, packets: &mut Vec<Self::Input>) -> Vec<Self::Output> {
+        let mut processed = self.first.process_batch(packets);
+        self.second.process_batch(&mut processed)
+    }
+}
+
+/// Processor that transforms the output of `processor` with a closure. Made with
+/// `Processor::map`.
+pub struct Map<P: Processor, F> {
+    processor: P,
+    f: F,
+}
+
+impl<P: Processor, F> Map<P, F> {
+    pub fn new(processor: P, f: F) -> Self {
+        Map { processor, f }
+    }
+}
+
+impl<P, F, Output> Processor for Map<P, F>
+where
+    P: Processor,
+    F: FnMut(P::Output) -> Output,
+    Output: Send + Clone,
+{
+    type Input = P::Input;
+    type Output = Output;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        self.processor.process(packet).map(&mut self.f)
+    }
+
+    fn process_batch(&mut self, packets: &mut Vec<Self::Input>) -> Vec<Self::Output> {
+        self.processor
+            .process_batch(packets)
+            .into_iter()
+            .map(&mut self.f)
+            .collect()
+    }
+}
+
+/// Processor that drops the output of `processor` for which a predicate returns false. Made
+/// with `Processor::filter`.
+pub struct Filter<P: Processor, F> {
+    processor: P,
+    predicate: F,
+}
+
+impl<P: Processor, F> Filter<P, F> {
+    pub fn new(processor: P, predicate: F) -> Self {
+        Filter {
+            processor,
+            predicate,
+        }
+    }
+}
+
+impl<P, F> Processor for Filter<P, F>
+where
+    P: Processor,
+    F: FnMut(&P::Output) -> bool,
+{
+    type Input = P::Input;
+    type Output = P::Output;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        self.processor.process(packet).filter(&mut self.predicate)
+    }
+
+    fn process_batch(&mut self, packets: &mut Vec<Self::Input>) -> Vec<Self::Output> {
+        let mut processed = self.processor.process_batch(packets);
+        processed.retain(|packet| (self.predicate)(packet));
+        processed
+    }
+}
+
+/// Processor that calls a closure with each packet `processor` outputs, for instance to log or
+/// count them. Made with `Processor::inspect`.
+pub struct Inspect<P: Processor, F> {
+    processor: P,
+    f: F,
+}
+
+impl<P: Processor, F> Inspect<P, F> {
+    pub fn new(processor: P, f: F) -> Self {
+        Inspect { processor, f }
+    }
+}
+
+impl<P, F> Processor for Inspect<P, F>
+where
+    P: Processor,
+    F: FnMut(&P::Output),
+{
+    type Input = P::Input;
+    type Output = P::Output;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        let packet = self.processor.process(packet)?;
+        (self.f)(&packet);
+        Some(packet)
+    }
+
+    fn process_batch(&mut self, packets: &mut Vec<Self::Input>) -> Vec<Self::Output> {
+        let processed = self.processor.process_batch(packets);
+        processed.iter().for_each(&mut self.f);
+        processed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::processor::{Identity, Processor, TransformFrom};
+
+    #[test]
+    fn chain() {
+        let mut processor = Identity::<char>::new().chain(TransformFrom::<char, u32>::new());
+        assert_eq!(processor.process('a'), Some(97));
+    }
+
+    #[test]
+    fn map_filter_inspect() {
+        let mut seen = vec![];
+        let results: Vec<i32> = {
+            let mut processor = Identity::<i32>::new()
+                .map(|packet| packet * 10)
+                .filter(|packet| *packet != 20)
+                .inspect(|packet| seen.push(*packet));
+            (0..4).filter_map(|p| processor.process(p)).collect()
+        };
+        assert_eq!(results, vec![0, 10, 30]);
+        assert_eq!(seen, vec![0, 10, 30]);
+    }
+
+    #[test]
+    fn combinators_process_batches() {
+        let mut processor = Identity::<i32>::new()
+            .filter(|packet| packet % 2 == 0)
+            .chain(Identity::new())
+            .map(|packet| packet + 1);
+
+        let mut packets = vec![0, 1, 2, 3, 4];
+        assert_eq!(processor.process_batch(&mut packets), vec![1, 3, 5]);
+        assert!(packets.is_empty());
+    }
+}
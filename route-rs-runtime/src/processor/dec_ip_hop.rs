@@ -60,12 +60,14 @@ mod tests {
     #[test]
     fn test_dec_ipv4_hop_limit() {
         let init_ttl = 64;
-        let mac_data: Vec<u8> = vec![0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0, 0];
+        let mac_data: Vec<u8> = vec![
+            0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0x08, 0x00,
+        ];
         let ip_data: Vec<u8> = vec![
             0x45, 0, 0, 20, 0, 0, 0, 0, 64, 17, 0, 0, 192, 178, 128, 0, 10, 0, 0, 1,
         ];
 
-        let mut frame = EthernetFrame::from_buffer(mac_data, 0).unwrap();
+        let mut frame = EthernetFrame::from_buffer(mac_data.into(), 0).unwrap();
         frame.set_payload(&ip_data);
 
         let mut packet = Ipv4Packet::try_from(frame).unwrap();
@@ -81,12 +83,14 @@ mod tests {
     #[test]
     fn test_dec_ipv4_hop_limit_expired() {
         let init_ttl = 0;
-        let mac_data: Vec<u8> = vec![0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0, 0];
+        let mac_data: Vec<u8> = vec![
+            0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0x08, 0x00,
+        ];
         let ip_data: Vec<u8> = vec![
             0x45, 0, 0, 20, 0, 0, 0, 0, 64, 17, 0, 0, 192, 178, 128, 0, 10, 0, 0, 1,
         ];
 
-        let mut frame = EthernetFrame::from_buffer(mac_data, 0).unwrap();
+        let mut frame = EthernetFrame::from_buffer(mac_data.into(), 0).unwrap();
         frame.set_payload(&ip_data);
 
         let mut packet = Ipv4Packet::try_from(frame).unwrap();
@@ -102,14 +106,16 @@ mod tests {
     #[test]
     fn test_dec_ipv6_hop_limit() {
         let init_ttl = 64;
-        let mac_data: Vec<u8> = vec![0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0, 0];
+        let mac_data: Vec<u8> = vec![
+            0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0x86, 0xDD,
+        ];
         let ip_data: Vec<u8> = vec![
             0x60, 0, 0, 0, 0, 4, 17, 64, 0xde, 0xad, 0xbe, 0xef, 0xde, 0xad, 0xbe, 0xef, 0xde,
             0xad, 0xbe, 0xef, 0xde, 0xad, 0xbe, 0xef, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13,
             14, 15, 0xa, 0xb, 0xc, 0xd,
         ];
 
-        let mut frame = EthernetFrame::from_buffer(mac_data, 0).unwrap();
+        let mut frame = EthernetFrame::from_buffer(mac_data.into(), 0).unwrap();
         frame.set_payload(&ip_data);
 
         let mut packet = Ipv6Packet::try_from(frame).unwrap();
@@ -125,14 +131,16 @@ mod tests {
     #[test]
     fn test_dec_ipv6_hop_limit_expired() {
         let init_ttl = 0;
-        let mac_data: Vec<u8> = vec![0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0, 0];
+        let mac_data: Vec<u8> = vec![
+            0xde, 0xad, 0xbe, 0xef, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 0x86, 0xDD,
+        ];
         let ip_data: Vec<u8> = vec![
             0x60, 0, 0, 0, 0, 4, 17, 64, 0xde, 0xad, 0xbe, 0xef, 0xde, 0xad, 0xbe, 0xef, 0xde,
             0xad, 0xbe, 0xef, 0xde, 0xad, 0xbe, 0xef, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13,
             14, 15, 0xa, 0xb, 0xc, 0xd,
         ];
 
-        let mut frame = EthernetFrame::from_buffer(mac_data, 0).unwrap();
+        let mut frame = EthernetFrame::from_buffer(mac_data.into(), 0).unwrap();
         frame.set_payload(&ip_data);
 
         let mut packet = Ipv6Packet::try_from(frame).unwrap();
@@ -0,0 +1,236 @@
+use crate::processor::TryProcessor;
+use route_rs_packets::{internet_checksum, IpProtocol, Ipv4Packet, Ipv6Packet};
+use std::marker::PhantomData;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// TTL, or hop limit, of the ICMP messages we send.
+const ICMP_TTL: u8 = 64;
+const ICMP_TIME_EXCEEDED: u8 = 11;
+const ICMPV6_TIME_EXCEEDED: u8 = 3;
+/// ICMPv6 errors carry as much of the expired packet as fits in the minimum IPv6 MTU.
+const ICMPV6_MAX_QUOTE: usize = 1280 - 40 - 8;
+
+/// Decrements the TTL of an IPv4 packet, or the hop limit of an IPv6 packet, updating the IPv4
+/// header checksum incrementally. Packets that arrive with a TTL of 0 or 1 would expire here, so rather than
+/// being forwarded, they fail with the ICMP Time Exceeded message, addressed back to their
+/// source, from `source`, the address of the router. Run in a TryProcessLink, the messages come
+/// out of its error egressor. As RFC 1812 requires, we stay quiet about expired ICMP errors and
+/// IPv4 fragments other than the first, dropping them.
+pub struct DecrementTtl<Packet> {
+    source: IpAddr,
+    phantom: PhantomData<Packet>,
+}
+
+impl DecrementTtl<Ipv4Packet> {
+    pub fn new(source: Ipv4Addr) -> Self {
+        DecrementTtl {
+            source: IpAddr::V4(source),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl DecrementTtl<Ipv6Packet> {
+    pub fn new(source: Ipv6Addr) -> Self {
+        DecrementTtl {
+            source: IpAddr::V6(source),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl TryProcessor for DecrementTtl<Ipv4Packet> {
+    type Input = Ipv4Packet;
+    type Output = Ipv4Packet;
+    type Error = Ipv4Packet;
+
+    fn try_process(&mut self, mut packet: Ipv4Packet) -> Result<Option<Ipv4Packet>, Ipv4Packet> {
+        if packet.ttl() > 1 {
+            packet.set_ttl(packet.ttl() - 1);
+            return Ok(Some(packet));
+        }
+
+        let is_icmp_error = packet.protocol() == IpProtocol::ICMP
+            && match packet.payload().first() {
+                // Echo and timestamp requests and replies are the only queries.
+                Some(icmp_type) => ![0, 8, 13, 14].contains(icmp_type),
+                None => false,
+            };
+        if is_icmp_error || packet.fragment_offset() != 0 {
+            return Ok(None);
+        }
+
+        let source = match self.source {
+            IpAddr::V4(source) => source,
+            IpAddr::V6(_) => unreachable!("DecrementTtl<Ipv4Packet> has an IPv4 source"),
+        };
+        // The expired packet's header, and the first 8 bytes of its payload.
+        let quote_end = (packet.payload_offset + 8).min(packet.data.len());
+        let quote = &packet.data[packet.layer3_offset..quote_end];
+
+        let mut icmp = vec![ICMP_TIME_EXCEEDED, 0, 0, 0, 0, 0, 0, 0];
+        icmp.extend_from_slice(quote);
+        let checksum = internet_checksum(&[&icmp]);
+        icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+        let mut time_exceeded = Ipv4Packet::empty();
+        time_exceeded.set_ttl(ICMP_TTL);
+        time_exceeded.set_protocol(1);
+        time_exceeded.set_src_addr(source);
+        time_exceeded.set_dest_addr(packet.src_addr());
+        time_exceeded.set_payload(&icmp);
+        time_exceeded.set_checksum();
+        Err(time_exceeded)
+    }
+}
+
+impl TryProcessor for DecrementTtl<Ipv6Packet> {
+    type Input = Ipv6Packet;
+    type Output = Ipv6Packet;
+    type Error = Ipv6Packet;
+
+    fn try_process(&mut self, mut packet: Ipv6Packet) -> Result<Option<Ipv6Packet>, Ipv6Packet> {
+        if packet.hop_limit() > 1 {
+            packet.set_hop_limit(packet.hop_limit() - 1);
+            return Ok(Some(packet));
+        }
+
+        // ICMPv6 error messages have types below 128.
+        let is_icmp_error = packet.next_header() == IpProtocol::IPv6_ICMP
+            && match packet.data.get(packet.layer3_offset + 40) {
+                Some(icmp_type) => *icmp_type < 128,
+                None => false,
+            };
+        if is_icmp_error {
+            return Ok(None);
+        }
+
+        let source = match self.source {
+            IpAddr::V6(source) => source,
+            IpAddr::V4(_) => unreachable!("DecrementTtl<Ipv6Packet> has an IPv6 source"),
+        };
+        let destination = packet.src_addr();
+        let quote_end = (packet.layer3_offset + ICMPV6_MAX_QUOTE).min(packet.data.len());
+        let quote = &packet.data[packet.layer3_offset..quote_end];
+
+        let mut icmp = vec![ICMPV6_TIME_EXCEEDED, 0, 0, 0, 0, 0, 0, 0];
+        icmp.extend_from_slice(quote);
+        // The checksum covers a pseudo-header of the addresses, length and next header.
+        let mut checksummed = Vec::with_capacity(40 + icmp.len());
+        checksummed.extend_from_slice(&source.octets());
+        checksummed.extend_from_slice(&destination.octets());
+        checksummed.extend_from_slice(&(icmp.len() as u32).to_be_bytes());
+        checksummed.extend_from_slice(&[0, 0, 0, 58]);
+        checksummed.extend_from_slice(&icmp);
+        let checksum = internet_checksum(&[&checksummed]);
+        icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+        let mut time_exceeded = Ipv6Packet::empty();
+        time_exceeded.set_hop_limit(ICMP_TTL);
+        time_exceeded.set_next_header(58);
+        time_exceeded.set_src_addr(source);
+        time_exceeded.set_dest_addr(destination);
+        time_exceeded.set_payload(&icmp);
+        Err(time_exceeded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ipv4_packet(ttl: u8, protocol: u8, payload: &[u8]) -> Ipv4Packet {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_ttl(ttl);
+        packet.set_protocol(protocol);
+        packet.set_src_addr(Ipv4Addr::new(10, 0, 0, 1));
+        packet.set_dest_addr(Ipv4Addr::new(192, 168, 0, 1));
+        packet.set_payload(payload);
+        packet.set_checksum();
+        packet
+    }
+
+    fn ipv6_packet(hop_limit: u8, next_header: u8, payload: &[u8]) -> Ipv6Packet {
+        let mut packet = Ipv6Packet::empty();
+        packet.set_hop_limit(hop_limit);
+        packet.set_next_header(next_header);
+        packet.set_src_addr("2001:db8::1".parse().unwrap());
+        packet.set_dest_addr("2001:db8::2".parse().unwrap());
+        packet.set_payload(payload);
+        packet
+    }
+
+    #[test]
+    fn decrements_ipv4_ttl() {
+        let mut processor = DecrementTtl::<Ipv4Packet>::new(Ipv4Addr::new(10, 0, 0, 254));
+        let mut packet = processor
+            .try_process(ipv4_packet(64, 17, &[1; 12]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(packet.ttl(), 63);
+        assert!(packet.validate_checksum());
+    }
+
+    #[test]
+    fn ipv4_time_exceeded() {
+        let router = Ipv4Addr::new(10, 0, 0, 254);
+        let mut processor = DecrementTtl::<Ipv4Packet>::new(router);
+        let expired = ipv4_packet(1, 17, &[1; 12]);
+
+        let mut time_exceeded = processor.try_process(expired.clone()).unwrap_err();
+        assert!(time_exceeded.validate_checksum());
+        assert_eq!(time_exceeded.protocol(), IpProtocol::ICMP);
+        assert_eq!(time_exceeded.src_addr(), router);
+        assert_eq!(time_exceeded.dest_addr(), expired.src_addr());
+
+        let icmp = time_exceeded.payload();
+        assert_eq!(icmp[0..2], [ICMP_TIME_EXCEEDED, 0]);
+        assert_eq!(internet_checksum(&[&icmp]), 0);
+        assert_eq!(icmp[8..], expired.data[..28]);
+    }
+
+    #[test]
+    fn quiet_about_expired_icmp_errors() {
+        let mut processor = DecrementTtl::<Ipv4Packet>::new(Ipv4Addr::new(10, 0, 0, 254));
+        let unreachable = ipv4_packet(0, 1, &[3, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(processor.try_process(unreachable), Ok(None));
+
+        let echo_request = ipv4_packet(1, 1, &[8, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(processor.try_process(echo_request).is_err());
+    }
+
+    #[test]
+    fn decrements_ipv6_hop_limit() {
+        let mut processor = DecrementTtl::<Ipv6Packet>::new("2001:db8::fe".parse().unwrap());
+        let packet = processor
+            .try_process(ipv6_packet(64, 17, &[1; 12]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(packet.hop_limit(), 63);
+    }
+
+    #[test]
+    fn ipv6_time_exceeded() {
+        let router: Ipv6Addr = "2001:db8::fe".parse().unwrap();
+        let mut processor = DecrementTtl::<Ipv6Packet>::new(router);
+        let expired = ipv6_packet(1, 17, &[1; 2000]);
+
+        let time_exceeded = processor.try_process(expired.clone()).unwrap_err();
+        assert_eq!(time_exceeded.next_header(), IpProtocol::IPv6_ICMP);
+        assert_eq!(time_exceeded.src_addr(), router);
+        assert_eq!(time_exceeded.dest_addr(), expired.src_addr());
+        assert_eq!(time_exceeded.data.len(), 1280);
+
+        let icmp = time_exceeded.payload();
+        assert_eq!(icmp[0..2], [ICMPV6_TIME_EXCEEDED, 0]);
+        assert_eq!(icmp[8..], expired.data[..ICMPV6_MAX_QUOTE]);
+
+        let mut pseudo_header = vec![];
+        pseudo_header.extend_from_slice(&router.octets());
+        pseudo_header.extend_from_slice(&expired.src_addr().octets());
+        pseudo_header.extend_from_slice(&(icmp.len() as u32).to_be_bytes());
+        pseudo_header.extend_from_slice(&[0, 0, 0, 58]);
+        pseudo_header.extend_from_slice(&icmp);
+        assert_eq!(internet_checksum(&[&pseudo_header]), 0);
+    }
+}
@@ -0,0 +1,641 @@
+use crate::classifier::Classifier;
+use crate::link::primitive::{ClassifyLink, JoinLink, ProcessLink};
+use crate::link::{Link, LinkBuilder, PacketStream, ProcessLinkBuilder, TokioRunnable};
+use crate::processor::{dhcp_frame, dhcp_message, Processor, BROADCAST_MAC};
+use futures::channel::mpsc;
+use route_rs_packets::*;
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The options the client asks servers for.
+const PARAMETER_REQUEST_LIST: [u8; 6] = [
+    DHCP_OPTION_SUBNET_MASK,
+    DHCP_OPTION_ROUTER,
+    DHCP_OPTION_DNS_SERVER,
+    DHCP_OPTION_LEASE_TIME,
+    DHCP_OPTION_RENEWAL_TIME,
+    DHCP_OPTION_REBINDING_TIME,
+];
+
+/// The address, and the rest of the configuration, a DHCP client was given for its interface.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DhcpConfig {
+    pub addr: Ipv4Addr,
+    pub subnet_mask: Option<Ipv4Addr>,
+    pub gateway: Option<Ipv4Addr>,
+    pub dns_servers: Vec<Ipv4Addr>,
+    /// The server that gave the lease.
+    pub server: Ipv4Addr,
+    pub lease_time: Duration,
+}
+
+/// Holds the configuration a `DhcpClient` acquired, while its lease lasts, for other processors
+/// to read. Clones share the configuration.
+#[derive(Clone)]
+pub struct DhcpConfigStore {
+    config: Arc<Mutex<Option<DhcpConfig>>>,
+}
+
+impl DhcpConfigStore {
+    pub fn new() -> Self {
+        DhcpConfigStore {
+            config: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Returns the configuration, if the client has a lease.
+    pub fn get(&self) -> Option<DhcpConfig> {
+        self.config.lock().unwrap().clone()
+    }
+
+    fn set(&self, config: Option<DhcpConfig>) {
+        *self.config.lock().unwrap() = config;
+    }
+}
+
+impl Default for DhcpConfigStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Where the client is in acquiring its lease, per section 4.4 of RFC 2131.
+#[derive(Clone, Debug)]
+enum ClientState {
+    Init,
+    Selecting {
+        sent: Instant,
+    },
+    Requesting {
+        addr: Ipv4Addr,
+        server: Ipv4Addr,
+        sent: Instant,
+        attempts: usize,
+    },
+    Bound(Lease),
+    Renewing {
+        lease: Lease,
+        sent: Instant,
+    },
+    Rebinding {
+        lease: Lease,
+        sent: Instant,
+    },
+}
+
+#[derive(Clone, Debug)]
+struct Lease {
+    config: DhcpConfig,
+    server_mac: MacAddr,
+    acquired: Instant,
+    renewal_time: Duration,
+    rebinding_time: Duration,
+}
+
+struct ClientInner {
+    mac: MacAddr,
+    retrans_timer: Duration,
+    max_requests: usize,
+    store: DhcpConfigStore,
+    xid: u32,
+    state: ClientState,
+}
+
+impl ClientInner {
+    fn message(&self, message_type: DhcpMessageType, ciaddr: Ipv4Addr) -> DhcpMessage {
+        let mut message = DhcpMessage::empty();
+        message.set_op(BOOTREQUEST);
+        message.set_xid(self.xid);
+        message.set_ciaddr(ciaddr);
+        message.set_chaddr(self.mac);
+        message.set_options(&[
+            (DHCP_OPTION_MESSAGE_TYPE, &[message_type as u8]),
+            (DHCP_OPTION_PARAMETER_REQUEST_LIST, &PARAMETER_REQUEST_LIST),
+        ]);
+        message
+    }
+
+    fn broadcast(&self, message: &DhcpMessage) -> EthernetFrame {
+        dhcp_frame(
+            (self.mac, message.ciaddr(), DHCP_CLIENT_PORT),
+            (BROADCAST_MAC, Ipv4Addr::BROADCAST, DHCP_SERVER_PORT),
+            message,
+        )
+    }
+
+    /// Starts a new transaction, to acquire a new lease.
+    fn start(&mut self, now: Instant) -> EthernetFrame {
+        self.xid = rand::random();
+        self.discover(now)
+    }
+
+    fn discover(&mut self, now: Instant) -> EthernetFrame {
+        self.state = ClientState::Selecting { sent: now };
+        let message = self.message(DhcpMessageType::Discover, Ipv4Addr::UNSPECIFIED);
+        self.broadcast(&message)
+    }
+
+    /// A request for `addr`, offered by `server`.
+    fn request(&self, addr: Ipv4Addr, server: Ipv4Addr) -> EthernetFrame {
+        let mut message = self.message(DhcpMessageType::Request, Ipv4Addr::UNSPECIFIED);
+        let mut options = message
+            .options()
+            .into_iter()
+            .map(|(code, data)| (code, data.to_vec()))
+            .collect::<Vec<_>>();
+        options.push((DHCP_OPTION_REQUESTED_ADDR, addr.octets().to_vec()));
+        options.push((DHCP_OPTION_SERVER_ID, server.octets().to_vec()));
+        let options = options
+            .iter()
+            .map(|(code, data)| (*code, &data[..]))
+            .collect::<Vec<_>>();
+        message.set_options(&options);
+        self.broadcast(&message)
+    }
+
+    /// A request to extend `lease`, from the server that gave it while renewing, or from any
+    /// server while rebinding.
+    fn extend(&self, lease: &Lease, rebinding: bool) -> EthernetFrame {
+        let message = self.message(DhcpMessageType::Request, lease.config.addr);
+        if rebinding {
+            self.broadcast(&message)
+        } else {
+            dhcp_frame(
+                (self.mac, lease.config.addr, DHCP_CLIENT_PORT),
+                (lease.server_mac, lease.config.server, DHCP_SERVER_PORT),
+                &message,
+            )
+        }
+    }
+
+    /// Sends whatever is due at `now`: the first discover, retransmissions, and requests to
+    /// extend the lease once it's time to renew or rebind.
+    fn poll_at(&mut self, now: Instant) -> Option<EthernetFrame> {
+        let retransmit = |sent: Instant| now >= sent + self.retrans_timer;
+        match self.state.clone() {
+            ClientState::Init => Some(self.start(now)),
+            ClientState::Selecting { sent } if retransmit(sent) => Some(self.discover(now)),
+            ClientState::Requesting {
+                addr,
+                server,
+                sent,
+                attempts,
+            } if retransmit(sent) => {
+                if attempts >= self.max_requests {
+                    return Some(self.start(now));
+                }
+                self.state = ClientState::Requesting {
+                    addr,
+                    server,
+                    sent: now,
+                    attempts: attempts + 1,
+                };
+                Some(self.request(addr, server))
+            }
+            ClientState::Bound(lease) if now >= lease.acquired + lease.renewal_time => {
+                let frame = self.extend(&lease, false);
+                self.state = ClientState::Renewing { lease, sent: now };
+                Some(frame)
+            }
+            ClientState::Renewing { lease, sent } => {
+                if now >= lease.acquired + lease.rebinding_time {
+                    let frame = self.extend(&lease, true);
+                    self.state = ClientState::Rebinding { lease, sent: now };
+                    Some(frame)
+                } else if retransmit(sent) {
+                    let frame = self.extend(&lease, false);
+                    self.state = ClientState::Renewing { lease, sent: now };
+                    Some(frame)
+                } else {
+                    None
+                }
+            }
+            ClientState::Rebinding { lease, sent } => {
+                if now >= lease.acquired + lease.config.lease_time {
+                    self.store.set(None);
+                    Some(self.start(now))
+                } else if retransmit(sent) {
+                    let frame = self.extend(&lease, true);
+                    self.state = ClientState::Rebinding { lease, sent: now };
+                    Some(frame)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn process_at(&mut self, frame: EthernetFrame, now: Instant) -> Option<EthernetFrame> {
+        let reply = dhcp_message(&frame, DHCP_CLIENT_PORT)?;
+        if reply.op() != BOOTREPLY || reply.xid() != self.xid || reply.chaddr() != self.mac {
+            return None;
+        }
+
+        let requesting = matches!(
+            self.state,
+            ClientState::Requesting { .. }
+                | ClientState::Renewing { .. }
+                | ClientState::Rebinding { .. }
+        );
+        match reply.message_type()? {
+            DhcpMessageType::Offer => {
+                if let ClientState::Selecting { .. } = self.state {
+                    let addr = reply.yiaddr();
+                    let server = reply.server_id()?;
+                    self.state = ClientState::Requesting {
+                        addr,
+                        server,
+                        sent: now,
+                        attempts: 1,
+                    };
+                    return Some(self.request(addr, server));
+                }
+                None
+            }
+            DhcpMessageType::Ack if requesting => {
+                let lease = lease(&reply, frame.src_mac(), now)?;
+                self.store.set(Some(lease.config.clone()));
+                self.state = ClientState::Bound(lease);
+                None
+            }
+            DhcpMessageType::Nak if requesting => {
+                self.store.set(None);
+                Some(self.start(now))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The lease an ack from `server_mac` gives, if it says how long it lasts.
+fn lease(ack: &DhcpMessage, server_mac: MacAddr, now: Instant) -> Option<Lease> {
+    let lease_time = ack.lease_time()?;
+
+    Some(Lease {
+        config: DhcpConfig {
+            addr: ack.yiaddr(),
+            subnet_mask: ack.subnet_mask(),
+            gateway: ack.routers().first().copied(),
+            dns_servers: ack.dns_servers(),
+            server: ack.server_id()?,
+            lease_time,
+        },
+        server_mac,
+        acquired: now,
+        renewal_time: ack.renewal_time().unwrap_or(lease_time / 2),
+        rebinding_time: ack.rebinding_time().unwrap_or(lease_time / 8 * 7),
+    })
+}
+
+/// A DHCP client for the router's WAN interface, whose address is `mac`. Acquires a lease, and
+/// keeps it renewed, publishing the address, gateway and DNS servers it was given to its
+/// `config_store` while the lease lasts.
+///
+/// As a processor it takes the servers' replies, answering offers with requests. Its `timer`
+/// sends everything else: the discovers, retransmissions after `retrans_timer`, and requests to
+/// renew the lease. If a request goes unanswered `max_requests` times, or is nakked, or the lease
+/// runs out, the client starts over. Clones share the client's state.
+#[derive(Clone)]
+pub struct DhcpClient {
+    inner: Arc<Mutex<ClientInner>>,
+    timer_interval: Duration,
+}
+
+impl DhcpClient {
+    pub fn new(mac: MacAddr) -> Self {
+        DhcpClient {
+            inner: Arc::new(Mutex::new(ClientInner {
+                mac,
+                retrans_timer: Duration::from_secs(4),
+                max_requests: 4,
+                store: DhcpConfigStore::new(),
+                xid: 0,
+                state: ClientState::Init,
+            })),
+            timer_interval: Duration::from_secs(1),
+        }
+    }
+
+    /// Changes retrans_timer, how long to wait for a reply before asking again, default value
+    /// is 4 seconds.
+    pub fn retrans_timer(self, retrans_timer: Duration) -> Self {
+        assert!(
+            retrans_timer > Duration::from_secs(0),
+            "DhcpClient retrans_timer: {:?}, must be > 0",
+            retrans_timer
+        );
+
+        self.inner.lock().unwrap().retrans_timer = retrans_timer;
+        self
+    }
+
+    /// Changes max_requests, how many times to request an offered address before starting
+    /// over, default value is 4.
+    pub fn max_requests(self, max_requests: usize) -> Self {
+        assert!(
+            max_requests > 0,
+            "DhcpClient max_requests: {}, must be > 0",
+            max_requests
+        );
+
+        self.inner.lock().unwrap().max_requests = max_requests;
+        self
+    }
+
+    /// Publishes the configuration to `store`, rather than one of the client's own.
+    pub fn config_store(self, store: DhcpConfigStore) -> Self {
+        self.inner.lock().unwrap().store = store;
+        self
+    }
+
+    /// Changes timer_interval, how often the timer checks whether anything is due, default
+    /// value is a second.
+    pub fn timer_interval(self, timer_interval: Duration) -> Self {
+        assert!(
+            timer_interval > Duration::from_secs(0),
+            "DhcpClient timer_interval: {:?}, must be > 0",
+            timer_interval
+        );
+
+        DhcpClient {
+            timer_interval,
+            ..self
+        }
+    }
+
+    /// Returns the store the configuration is published to.
+    pub fn store(&self) -> DhcpConfigStore {
+        self.inner.lock().unwrap().store.clone()
+    }
+
+    /// Returns the runnable that keeps the client's timers, and the stream of the messages it
+    /// sends. The runnable stops once the client, and its clones, are dropped.
+    pub fn timer(&self) -> (TokioRunnable, PacketStream<EthernetFrame>) {
+        let inner = Arc::downgrade(&self.inner);
+        let timer_interval = self.timer_interval;
+        let (to_stream, from_timer) = mpsc::unbounded();
+
+        let timer = async move {
+            let mut ticks = tokio::time::interval(timer_interval);
+            loop {
+                ticks.tick().await;
+                let inner = match inner.upgrade() {
+                    Some(inner) => inner,
+                    None => break,
+                };
+                let due = inner.lock().unwrap().poll_at(Instant::now());
+                if let Some(frame) = due {
+                    if to_stream.unbounded_send(frame).is_err() {
+                        break;
+                    }
+                }
+            }
+        };
+        (Box::new(Box::pin(timer)), Box::new(from_timer))
+    }
+}
+
+impl Processor for DhcpClient {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, frame: Self::Input) -> Option<Self::Output> {
+        self.inner.lock().unwrap().process_at(frame, Instant::now())
+    }
+}
+
+/// Classifies frames by whether they're DHCP messages to the client port.
+#[derive(Default)]
+pub struct ToDhcpClient {}
+
+impl ToDhcpClient {
+    pub fn new() -> Self {
+        ToDhcpClient {}
+    }
+}
+
+impl Classifier for ToDhcpClient {
+    type Packet = EthernetFrame;
+    type Class = bool;
+
+    fn classify(&self, frame: &Self::Packet) -> Self::Class {
+        dhcp_message(frame, DHCP_CLIENT_PORT).is_some()
+    }
+}
+
+/// Runs `client` on the frames in `stream`, from the WAN. Other frames pass through to the first
+/// egressor, and the messages the client sends, to be sent out the WAN, are on the second.
+pub fn dhcp_client_link(
+    stream: PacketStream<EthernetFrame>,
+    client: DhcpClient,
+) -> Link<EthernetFrame> {
+    let (mut runnables, mut egressors) = ClassifyLink::new()
+        .ingressor(stream)
+        .num_egressors(2)
+        .classifier(ToDhcpClient::new())
+        .dispatcher(Box::new(|is_dhcp| if is_dhcp { 1 } else { 0 }))
+        .build_link();
+
+    let (timer, timer_messages) = client.timer();
+    let (reply_runnables, mut reply_egressors) = ProcessLink::new()
+        .ingressor(egressors.pop().unwrap())
+        .processor(client)
+        .build_link();
+    reply_egressors.push(timer_messages);
+    let (join_runnables, join_egressors) = JoinLink::new().ingressors(reply_egressors).build_link();
+
+    runnables.push(timer);
+    runnables.extend(reply_runnables);
+    runnables.extend(join_runnables);
+    egressors.extend(join_egressors);
+    (runnables, egressors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::DhcpServer;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::PacketIntervalGenerator;
+    use std::convert::TryFrom;
+
+    const SERVER: Ipv4Addr = Ipv4Addr::new(203, 0, 113, 1);
+    const SERVER_MAC: MacAddr = MacAddr {
+        bytes: [2, 0, 0, 0, 0, 1],
+    };
+    const CLIENT_MAC: MacAddr = MacAddr {
+        bytes: [2, 0, 0, 0, 0, 20],
+    };
+
+    fn server() -> DhcpServer {
+        DhcpServer::new(
+            SERVER,
+            SERVER_MAC,
+            Ipv4Addr::new(203, 0, 113, 10)..=Ipv4Addr::new(203, 0, 113, 20),
+        )
+        .gateway(SERVER)
+        .dns_servers(vec![Ipv4Addr::new(1, 1, 1, 1)])
+        .lease_time(Duration::from_secs(800))
+    }
+
+    fn message_type(frame: &EthernetFrame) -> Option<DhcpMessageType> {
+        dhcp_message(frame, DHCP_SERVER_PORT)?.message_type()
+    }
+
+    /// Runs the client against `server` until it's bound, returning when it was.
+    fn bind(client: &mut ClientInner, server: &mut DhcpServer) -> Instant {
+        let now = Instant::now();
+        let discover = client.poll_at(now).unwrap();
+        assert_eq!(message_type(&discover), Some(DhcpMessageType::Discover));
+        let offer = server.process(discover).unwrap();
+        let request = client.process_at(offer, now).unwrap();
+        assert_eq!(message_type(&request), Some(DhcpMessageType::Request));
+        let ack = server.process(request).unwrap();
+        assert_eq!(client.process_at(ack, now), None);
+        now
+    }
+
+    fn client() -> DhcpClient {
+        DhcpClient::new(CLIENT_MAC).retrans_timer(Duration::from_secs(4))
+    }
+
+    #[test]
+    fn acquires_a_lease() {
+        let client = client();
+        let store = client.store();
+        let mut server = server();
+        bind(&mut client.inner.lock().unwrap(), &mut server);
+
+        assert_eq!(
+            store.get(),
+            Some(DhcpConfig {
+                addr: Ipv4Addr::new(203, 0, 113, 10),
+                subnet_mask: Some(Ipv4Addr::new(255, 255, 255, 0)),
+                gateway: Some(SERVER),
+                dns_servers: vec![Ipv4Addr::new(1, 1, 1, 1)],
+                server: SERVER,
+                lease_time: Duration::from_secs(800),
+            })
+        );
+    }
+
+    #[test]
+    fn renews_and_rebinds() {
+        let client = client();
+        let store = client.store();
+        let mut server = server();
+        let mut inner = client.inner.lock().unwrap();
+        let acquired = bind(&mut inner, &mut server);
+        let addr = store.get().unwrap().addr;
+
+        assert_eq!(inner.poll_at(acquired + Duration::from_secs(399)), None);
+
+        // At T1, half the lease, the client asks the server that gave it to extend it.
+        let renew = inner.poll_at(acquired + Duration::from_secs(400)).unwrap();
+        assert_eq!(renew.dest_mac(), SERVER_MAC);
+        let request = dhcp_message(&renew, DHCP_SERVER_PORT).unwrap();
+        assert_eq!(request.message_type(), Some(DhcpMessageType::Request));
+        assert_eq!(request.ciaddr(), addr);
+        assert_eq!(Ipv4Packet::try_from(renew).unwrap().dest_addr(), SERVER);
+
+        // At T2, seven eighths of it, it asks any server.
+        let rebind = inner.poll_at(acquired + Duration::from_secs(700)).unwrap();
+        assert_eq!(rebind.dest_mac(), BROADCAST_MAC);
+        let renewed = acquired + Duration::from_secs(701);
+        let ack = server.process(rebind).unwrap();
+        assert_eq!(inner.process_at(ack, renewed), None);
+        assert_eq!(store.get().unwrap().addr, addr);
+        assert_eq!(inner.poll_at(renewed + Duration::from_secs(399)), None);
+
+        // Once the lease runs out, the configuration is withdrawn, and the client starts over.
+        inner.poll_at(renewed + Duration::from_secs(400)).unwrap();
+        inner.poll_at(renewed + Duration::from_secs(700)).unwrap();
+        let discover = inner.poll_at(renewed + Duration::from_secs(800)).unwrap();
+        assert_eq!(message_type(&discover), Some(DhcpMessageType::Discover));
+        assert_eq!(store.get(), None);
+    }
+
+    #[test]
+    fn retransmits_and_starts_over() {
+        let client = client().max_requests(2);
+        let mut server = server();
+        let mut inner = client.inner.lock().unwrap();
+        let now = Instant::now();
+
+        let discover = inner.poll_at(now).unwrap();
+        assert_eq!(inner.poll_at(now + Duration::from_secs(3)), None);
+        assert_eq!(
+            inner.poll_at(now + Duration::from_secs(4)),
+            Some(discover.clone())
+        );
+
+        let offer = server.process(discover).unwrap();
+        let now = now + Duration::from_secs(5);
+        inner.process_at(offer, now).unwrap();
+        let request = inner.poll_at(now + Duration::from_secs(4)).unwrap();
+        assert_eq!(message_type(&request), Some(DhcpMessageType::Request));
+        let discover = inner.poll_at(now + Duration::from_secs(8)).unwrap();
+        assert_eq!(message_type(&discover), Some(DhcpMessageType::Discover));
+    }
+
+    #[test]
+    fn starts_over_when_nakked() {
+        let client = client();
+        let mut inner = client.inner.lock().unwrap();
+        let now = Instant::now();
+        let discover = inner.poll_at(now).unwrap();
+        let offer = server().process(discover).unwrap();
+        let request = inner.process_at(offer, now).unwrap();
+
+        // Another server, that doesn't know of the offer, naks the request.
+        let mut other = DhcpServer::new(
+            SERVER,
+            SERVER_MAC,
+            Ipv4Addr::new(203, 0, 113, 30)..=Ipv4Addr::new(203, 0, 113, 30),
+        );
+        let nak = other.process(request).unwrap();
+        let discover = inner.process_at(nak, now).unwrap();
+        assert_eq!(message_type(&discover), Some(DhcpMessageType::Discover));
+        assert_eq!(inner.store.get(), None);
+    }
+
+    #[test]
+    fn ignores_other_transactions() {
+        let client = client();
+        let mut inner = client.inner.lock().unwrap();
+        let discover = inner.poll_at(Instant::now()).unwrap();
+        let mut offer =
+            dhcp_message(&server().process(discover).unwrap(), DHCP_CLIENT_PORT).unwrap();
+        offer.set_xid(offer.xid().wrapping_add(1));
+        let offer = dhcp_frame(
+            (SERVER_MAC, SERVER, DHCP_SERVER_PORT),
+            (CLIENT_MAC, Ipv4Addr::BROADCAST, DHCP_CLIENT_PORT),
+            &offer,
+        );
+        assert_eq!(inner.process_at(offer, Instant::now()), None);
+    }
+
+    #[test]
+    fn dhcp_client_link_sends_discover() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let other = EthernetFrame::encap_ipv4(Ipv4Packet::empty());
+            let packets = PacketIntervalGenerator::new(
+                Duration::from_millis(10),
+                vec![other.clone(), other.clone(), other].into_iter(),
+            );
+            let client = DhcpClient::new(CLIENT_MAC).timer_interval(Duration::from_millis(100));
+            let link = dhcp_client_link(Box::new(packets), client);
+            run_link(link).await
+        });
+
+        assert_eq!(results[0].len(), 3);
+        assert_eq!(results[1].len(), 1);
+        assert_eq!(
+            message_type(&results[1][0]),
+            Some(DhcpMessageType::Discover)
+        );
+    }
+}
@@ -0,0 +1,601 @@
+use crate::classifier::Classifier;
+use crate::link::primitive::{ClassifyLink, ProcessLink};
+use crate::link::{Link, LinkBuilder, PacketStream, ProcessLinkBuilder};
+use crate::processor::Processor;
+use route_rs_packets::*;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::net::Ipv4Addr;
+use std::ops::RangeInclusive;
+use std::time::{Duration, SystemTime};
+
+pub(crate) const BROADCAST_MAC: MacAddr = MacAddr {
+    bytes: [0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+};
+
+/// Wraps a DHCP message in UDP, IPv4 and Ethernet headers. The UDP checksum is left 0, for none.
+pub(crate) fn dhcp_frame(
+    src: (MacAddr, Ipv4Addr, u16),
+    dst: (MacAddr, Ipv4Addr, u16),
+    message: &DhcpMessage,
+) -> EthernetFrame {
+    let mut segment = UdpSegment::empty();
+    segment.set_src_port(src.2);
+    segment.set_dest_port(dst.2);
+    segment.set_payload(&message.data);
+    let length = segment.data.len() as u16;
+    segment.data[4..6].copy_from_slice(&length.to_be_bytes());
+
+    let mut packet = Ipv4Packet::encap_udp(segment);
+    packet.set_ttl(64);
+    packet.set_src_addr(src.1);
+    packet.set_dest_addr(dst.1);
+    packet.set_checksum();
+
+    let mut frame = EthernetFrame::encap_ipv4(packet);
+    frame.set_src_mac(src.0);
+    frame.set_dest_mac(dst.0);
+    frame
+}
+
+/// Parses the DHCP message in `frame`, if it's one to `port`.
+pub(crate) fn dhcp_message(frame: &EthernetFrame, port: u16) -> Option<DhcpMessage> {
+    if frame.ether_type() != IPV4_ETHER_TYPE {
+        return None;
+    }
+    let packet = Ipv4Packet::try_from(frame.clone()).ok()?;
+    if packet.protocol() != IpProtocol::UDP {
+        return None;
+    }
+    let segment = UdpSegment::try_from(packet).ok()?;
+    if segment.dest_port() != port {
+        return None;
+    }
+    DhcpMessage::try_from(segment).ok()
+}
+
+/// An address leased to a client, until `expires`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DhcpLease {
+    pub addr: Ipv4Addr,
+    pub mac: MacAddr,
+    pub expires: SystemTime,
+}
+
+/// Where a `DhcpServer` keeps its leases, so they survive restarts. The server loads the leases
+/// when it's given the store, and saves or removes each as it's granted or released.
+pub trait DhcpLeaseStore: Send {
+    fn load(&mut self) -> Vec<DhcpLease>;
+
+    fn save(&mut self, lease: &DhcpLease);
+
+    fn remove(&mut self, lease: &DhcpLease);
+}
+
+/// A DHCP server for the LAN, leasing addresses from `pool` to clients, from `addr` and `mac`,
+/// the router's own on the LAN. Leases come with the `subnet_mask`, and the `gateway` and
+/// `dns_servers` if set, and last `lease_time`. Clients keep their address while their lease
+/// lasts, and get it again once it's expired if no one else has taken it.
+///
+/// Discovers are answered with offers, and requests with acks, or naks for addresses the server
+/// can't give; releases free the address. Replies go through the relay agent the request came
+/// through, if any. Anything else is dropped.
+pub struct DhcpServer {
+    addr: Ipv4Addr,
+    mac: MacAddr,
+    pool: RangeInclusive<u32>,
+    subnet_mask: Ipv4Addr,
+    gateway: Option<Ipv4Addr>,
+    dns_servers: Vec<Ipv4Addr>,
+    lease_time: Duration,
+    leases: HashMap<Ipv4Addr, DhcpLease>,
+    store: Option<Box<dyn DhcpLeaseStore>>,
+}
+
+impl DhcpServer {
+    pub fn new(addr: Ipv4Addr, mac: MacAddr, pool: RangeInclusive<Ipv4Addr>) -> Self {
+        assert!(
+            pool.start() <= pool.end(),
+            "DhcpServer pool: {:?}, must not be empty",
+            pool
+        );
+
+        DhcpServer {
+            addr,
+            mac,
+            pool: u32::from(*pool.start())..=u32::from(*pool.end()),
+            subnet_mask: Ipv4Addr::new(255, 255, 255, 0),
+            gateway: None,
+            dns_servers: vec![],
+            lease_time: Duration::from_secs(24 * 60 * 60),
+            leases: HashMap::new(),
+            store: None,
+        }
+    }
+
+    /// Changes subnet_mask, default value is 255.255.255.0.
+    pub fn subnet_mask(self, subnet_mask: Ipv4Addr) -> Self {
+        DhcpServer {
+            subnet_mask,
+            ..self
+        }
+    }
+
+    pub fn gateway(self, gateway: Ipv4Addr) -> Self {
+        DhcpServer {
+            gateway: Some(gateway),
+            ..self
+        }
+    }
+
+    pub fn dns_servers(self, dns_servers: Vec<Ipv4Addr>) -> Self {
+        DhcpServer {
+            dns_servers,
+            ..self
+        }
+    }
+
+    /// Changes lease_time, default value is a day.
+    pub fn lease_time(self, lease_time: Duration) -> Self {
+        assert!(
+            lease_time.as_secs() > 0,
+            "DhcpServer lease_time: {:?}, must be at least a second",
+            lease_time
+        );
+
+        DhcpServer { lease_time, ..self }
+    }
+
+    /// Keeps leases in `store`, starting with the ones it has.
+    pub fn lease_store(self, mut store: Box<dyn DhcpLeaseStore>) -> Self {
+        let leases = store
+            .load()
+            .into_iter()
+            .map(|lease| (lease.addr, lease))
+            .collect();
+        DhcpServer {
+            leases,
+            store: Some(store),
+            ..self
+        }
+    }
+
+    /// Returns the lease of `addr`, if it's leased.
+    pub fn lease(&self, addr: &Ipv4Addr) -> Option<DhcpLease> {
+        self.leases.get(addr).copied()
+    }
+
+    /// Whether `mac` may have `addr`.
+    fn available(&self, addr: Ipv4Addr, mac: MacAddr, now: SystemTime) -> bool {
+        if !self.pool.contains(&u32::from(addr)) || addr == self.addr {
+            return false;
+        }
+        match self.leases.get(&addr) {
+            Some(lease) => lease.mac == mac || lease.expires <= now,
+            None => true,
+        }
+    }
+
+    /// Picks the address to offer `mac`: the one it has, the one it asked for, or the first free
+    /// one in the pool.
+    fn choose(
+        &self,
+        mac: MacAddr,
+        requested: Option<Ipv4Addr>,
+        now: SystemTime,
+    ) -> Option<Ipv4Addr> {
+        let leased = self
+            .leases
+            .values()
+            .find(|lease| lease.mac == mac && lease.expires > now)
+            .map(|lease| lease.addr);
+        leased
+            .or_else(|| requested.filter(|addr| self.available(*addr, mac, now)))
+            .or_else(|| {
+                self.pool
+                    .clone()
+                    .map(Ipv4Addr::from)
+                    .find(|addr| self.available(*addr, mac, now))
+            })
+    }
+
+    fn grant(&mut self, addr: Ipv4Addr, mac: MacAddr, now: SystemTime) {
+        // A client only holds one lease.
+        let previous: Vec<Ipv4Addr> = self
+            .leases
+            .values()
+            .filter(|lease| lease.mac == mac && lease.addr != addr)
+            .map(|lease| lease.addr)
+            .collect();
+        for addr in previous {
+            self.release(addr);
+        }
+
+        let lease = DhcpLease {
+            addr,
+            mac,
+            expires: now + self.lease_time,
+        };
+        self.leases.insert(addr, lease);
+        if let Some(store) = &mut self.store {
+            store.save(&lease);
+        }
+    }
+
+    fn release(&mut self, addr: Ipv4Addr) {
+        if let Some(lease) = self.leases.remove(&addr) {
+            if let Some(store) = &mut self.store {
+                store.remove(&lease);
+            }
+        }
+    }
+
+    fn reply(
+        &self,
+        request: &DhcpMessage,
+        request_src_mac: MacAddr,
+        message_type: DhcpMessageType,
+        addr: Ipv4Addr,
+    ) -> EthernetFrame {
+        let mut reply = DhcpReplyBuilder::new(request, message_type)
+            .yiaddr(addr)
+            .server_id(self.addr);
+        if message_type != DhcpMessageType::Nak {
+            let lease_time = self.lease_time.as_secs();
+            reply = reply
+                .lease_time(self.lease_time)
+                .renewal_time(Duration::from_secs(lease_time / 2))
+                .rebinding_time(Duration::from_secs(lease_time / 8 * 7))
+                .subnet_mask(self.subnet_mask);
+            if let Some(gateway) = self.gateway {
+                reply = reply.routers(&[gateway]);
+            }
+            reply = reply.dns_servers(&self.dns_servers);
+        }
+        let reply = reply.build();
+
+        // Per section 4.1 of RFC 2131.
+        let dst = if !request.giaddr().is_unspecified() {
+            (request_src_mac, request.giaddr(), DHCP_SERVER_PORT)
+        } else if message_type == DhcpMessageType::Nak || request.broadcast() {
+            (BROADCAST_MAC, Ipv4Addr::BROADCAST, DHCP_CLIENT_PORT)
+        } else if !request.ciaddr().is_unspecified() {
+            (request.chaddr(), request.ciaddr(), DHCP_CLIENT_PORT)
+        } else {
+            (request.chaddr(), addr, DHCP_CLIENT_PORT)
+        };
+        dhcp_frame((self.mac, self.addr, DHCP_SERVER_PORT), dst, &reply)
+    }
+
+    fn process_at(&mut self, frame: EthernetFrame, now: SystemTime) -> Option<EthernetFrame> {
+        let request = dhcp_message(&frame, DHCP_SERVER_PORT)?;
+        if request.op() != BOOTREQUEST {
+            return None;
+        }
+        let mac = request.chaddr();
+        let requested = request.requested_addr();
+
+        match request.message_type()? {
+            DhcpMessageType::Discover => {
+                let addr = self.choose(mac, requested, now)?;
+                Some(self.reply(&request, frame.src_mac(), DhcpMessageType::Offer, addr))
+            }
+            DhcpMessageType::Request => {
+                if let Some(server_id) = request.server_id() {
+                    if server_id != self.addr {
+                        // The client took another server's offer.
+                        return None;
+                    }
+                }
+                let addr = requested
+                    .or_else(|| Some(request.ciaddr()).filter(|addr| !addr.is_unspecified()));
+                match addr {
+                    Some(addr) if self.available(addr, mac, now) => {
+                        self.grant(addr, mac, now);
+                        Some(self.reply(&request, frame.src_mac(), DhcpMessageType::Ack, addr))
+                    }
+                    _ => Some(self.reply(
+                        &request,
+                        frame.src_mac(),
+                        DhcpMessageType::Nak,
+                        Ipv4Addr::UNSPECIFIED,
+                    )),
+                }
+            }
+            DhcpMessageType::Release => {
+                let addr = request.ciaddr();
+                if self.leases.get(&addr).map(|lease| lease.mac) == Some(mac) {
+                    self.release(addr);
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Processor for DhcpServer {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, frame: Self::Input) -> Option<Self::Output> {
+        self.process_at(frame, SystemTime::now())
+    }
+}
+
+/// Classifies frames by whether they're DHCP messages to the server port.
+#[derive(Default)]
+pub struct ToDhcpServer {}
+
+impl ToDhcpServer {
+    pub fn new() -> Self {
+        ToDhcpServer {}
+    }
+}
+
+impl Classifier for ToDhcpServer {
+    type Packet = EthernetFrame;
+    type Class = bool;
+
+    fn classify(&self, frame: &Self::Packet) -> Self::Class {
+        dhcp_message(frame, DHCP_SERVER_PORT).is_some()
+    }
+}
+
+/// Serves the DHCP in `stream` with `server`. Other frames pass through to the first egressor,
+/// and the server's replies are sent out the second.
+pub fn dhcp_server_link(
+    stream: PacketStream<EthernetFrame>,
+    server: DhcpServer,
+) -> Link<EthernetFrame> {
+    let (mut runnables, mut egressors) = ClassifyLink::new()
+        .ingressor(stream)
+        .num_egressors(2)
+        .classifier(ToDhcpServer::new())
+        .dispatcher(Box::new(|is_dhcp| if is_dhcp { 1 } else { 0 }))
+        .build_link();
+
+    let (reply_runnables, reply_egressors) = ProcessLink::new()
+        .ingressor(egressors.pop().unwrap())
+        .processor(server)
+        .build_link();
+    runnables.extend(reply_runnables);
+    egressors.extend(reply_egressors);
+    (runnables, egressors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use std::sync::{Arc, Mutex};
+
+    const ROUTER: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 1);
+    const ROUTER_MAC: MacAddr = MacAddr {
+        bytes: [2, 0, 0, 0, 0, 1],
+    };
+    const CLIENT_MAC: MacAddr = MacAddr {
+        bytes: [2, 0, 0, 0, 0, 20],
+    };
+    const OTHER_MAC: MacAddr = MacAddr {
+        bytes: [2, 0, 0, 0, 0, 30],
+    };
+
+    fn server() -> DhcpServer {
+        DhcpServer::new(
+            ROUTER,
+            ROUTER_MAC,
+            Ipv4Addr::new(192, 168, 1, 100)..=Ipv4Addr::new(192, 168, 1, 101),
+        )
+        .gateway(ROUTER)
+        .dns_servers(vec![Ipv4Addr::new(1, 1, 1, 1), Ipv4Addr::new(9, 9, 9, 9)])
+        .lease_time(Duration::from_secs(3600))
+    }
+
+    fn client_message(
+        mac: MacAddr,
+        message_type: DhcpMessageType,
+        options: &[(u8, &[u8])],
+    ) -> EthernetFrame {
+        let mut message = DhcpMessage::empty();
+        message.set_op(BOOTREQUEST);
+        message.set_xid(42);
+        message.set_chaddr(mac);
+        let message_type = [message_type as u8];
+        let mut all_options = vec![(DHCP_OPTION_MESSAGE_TYPE, &message_type[..])];
+        all_options.extend_from_slice(options);
+        message.set_options(&all_options);
+        dhcp_frame(
+            (mac, Ipv4Addr::UNSPECIFIED, DHCP_CLIENT_PORT),
+            (BROADCAST_MAC, Ipv4Addr::BROADCAST, DHCP_SERVER_PORT),
+            &message,
+        )
+    }
+
+    fn request(mac: MacAddr, addr: Ipv4Addr) -> EthernetFrame {
+        client_message(
+            mac,
+            DhcpMessageType::Request,
+            &[
+                (DHCP_OPTION_REQUESTED_ADDR, &addr.octets()),
+                (DHCP_OPTION_SERVER_ID, &ROUTER.octets()),
+            ],
+        )
+    }
+
+    #[test]
+    fn discover_offer_request_ack() {
+        let mut server = server();
+        let now = SystemTime::now();
+
+        let offer = server
+            .process_at(
+                client_message(CLIENT_MAC, DhcpMessageType::Discover, &[]),
+                now,
+            )
+            .unwrap();
+        assert_eq!(offer.dest_mac(), CLIENT_MAC);
+        let message = dhcp_message(&offer, DHCP_CLIENT_PORT).unwrap();
+        assert_eq!(message.op(), BOOTREPLY);
+        assert_eq!(message.xid(), 42);
+        assert_eq!(message.message_type(), Some(DhcpMessageType::Offer));
+        let offered = message.yiaddr();
+        assert_eq!(offered, Ipv4Addr::new(192, 168, 1, 100));
+        assert_eq!(message.addr_option(DHCP_OPTION_ROUTER), Some(ROUTER));
+        assert_eq!(
+            message.option(DHCP_OPTION_DNS_SERVER),
+            Some(&[1, 1, 1, 1, 9, 9, 9, 9][..])
+        );
+        assert_eq!(
+            message.option(DHCP_OPTION_LEASE_TIME),
+            Some(&3600u32.to_be_bytes()[..])
+        );
+        assert_eq!(Ipv4Packet::try_from(offer).unwrap().dest_addr(), offered);
+
+        let ack = server
+            .process_at(request(CLIENT_MAC, offered), now)
+            .unwrap();
+        let message = dhcp_message(&ack, DHCP_CLIENT_PORT).unwrap();
+        assert_eq!(message.message_type(), Some(DhcpMessageType::Ack));
+        assert_eq!(message.yiaddr(), offered);
+        assert_eq!(server.lease(&offered).unwrap().mac, CLIENT_MAC);
+
+        // Another client gets the next address, and can't take the leased one.
+        let offer = server
+            .process_at(
+                client_message(OTHER_MAC, DhcpMessageType::Discover, &[]),
+                now,
+            )
+            .unwrap();
+        let message = dhcp_message(&offer, DHCP_CLIENT_PORT).unwrap();
+        assert_eq!(message.yiaddr(), Ipv4Addr::new(192, 168, 1, 101));
+        let nak = server.process_at(request(OTHER_MAC, offered), now).unwrap();
+        assert_eq!(nak.dest_mac(), BROADCAST_MAC);
+        let message = dhcp_message(&nak, DHCP_CLIENT_PORT).unwrap();
+        assert_eq!(message.message_type(), Some(DhcpMessageType::Nak));
+
+        // Until the lease expires.
+        let later = now + Duration::from_secs(3600);
+        let ack = server
+            .process_at(request(OTHER_MAC, offered), later)
+            .unwrap();
+        let message = dhcp_message(&ack, DHCP_CLIENT_PORT).unwrap();
+        assert_eq!(message.message_type(), Some(DhcpMessageType::Ack));
+    }
+
+    #[test]
+    fn pool_exhaustion_and_release() {
+        let mut server = DhcpServer::new(
+            ROUTER,
+            ROUTER_MAC,
+            Ipv4Addr::new(192, 168, 1, 100)..=Ipv4Addr::new(192, 168, 1, 100),
+        );
+        let now = SystemTime::now();
+        let addr = Ipv4Addr::new(192, 168, 1, 100);
+
+        assert!(server.process_at(request(CLIENT_MAC, addr), now).is_some());
+        assert_eq!(
+            server.process_at(
+                client_message(OTHER_MAC, DhcpMessageType::Discover, &[]),
+                now
+            ),
+            None
+        );
+
+        let mut release = DhcpMessage::empty();
+        release.set_op(BOOTREQUEST);
+        release.set_ciaddr(addr);
+        release.set_chaddr(CLIENT_MAC);
+        release.set_options(&[(DHCP_OPTION_MESSAGE_TYPE, &[DhcpMessageType::Release as u8])]);
+        let release = dhcp_frame(
+            (CLIENT_MAC, addr, DHCP_CLIENT_PORT),
+            (ROUTER_MAC, ROUTER, DHCP_SERVER_PORT),
+            &release,
+        );
+        assert_eq!(server.process_at(release, now), None);
+        assert_eq!(server.lease(&addr), None);
+        assert!(server
+            .process_at(
+                client_message(OTHER_MAC, DhcpMessageType::Discover, &[]),
+                now
+            )
+            .is_some());
+    }
+
+    #[derive(Clone, Default)]
+    struct MemoryStore {
+        leases: Arc<Mutex<Vec<DhcpLease>>>,
+    }
+
+    impl DhcpLeaseStore for MemoryStore {
+        fn load(&mut self) -> Vec<DhcpLease> {
+            self.leases.lock().unwrap().clone()
+        }
+
+        fn save(&mut self, lease: &DhcpLease) {
+            self.remove(lease);
+            self.leases.lock().unwrap().push(*lease);
+        }
+
+        fn remove(&mut self, lease: &DhcpLease) {
+            self.leases
+                .lock()
+                .unwrap()
+                .retain(|stored| stored.addr != lease.addr);
+        }
+    }
+
+    #[test]
+    fn leases_persist() {
+        let store = MemoryStore::default();
+        let addr = Ipv4Addr::new(192, 168, 1, 101);
+        let mut server = server().lease_store(Box::new(store.clone()));
+        server.process(request(CLIENT_MAC, addr)).unwrap();
+        assert_eq!(store.leases.lock().unwrap().len(), 1);
+
+        // A restarted server remembers the lease, and offers the client the same address.
+        let mut restarted = self::server().lease_store(Box::new(store));
+        assert_eq!(restarted.lease(&addr).unwrap().mac, CLIENT_MAC);
+        let offer = restarted
+            .process(client_message(CLIENT_MAC, DhcpMessageType::Discover, &[]))
+            .unwrap();
+        assert_eq!(
+            dhcp_message(&offer, DHCP_CLIENT_PORT).unwrap().yiaddr(),
+            addr
+        );
+    }
+
+    #[test]
+    fn ignores_other_servers() {
+        let mut server = server();
+        let other_server = Ipv4Addr::new(192, 168, 1, 2);
+        let frame = client_message(
+            CLIENT_MAC,
+            DhcpMessageType::Request,
+            &[
+                (DHCP_OPTION_REQUESTED_ADDR, &[192, 168, 1, 100]),
+                (DHCP_OPTION_SERVER_ID, &other_server.octets()),
+            ],
+        );
+        assert_eq!(server.process(frame), None);
+    }
+
+    #[test]
+    fn dhcp_server_link_splits_dhcp() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let other = EthernetFrame::encap_ipv4(Ipv4Packet::empty());
+            let packets = vec![
+                client_message(CLIENT_MAC, DhcpMessageType::Discover, &[]),
+                other,
+            ];
+            let link = dhcp_server_link(immediate_stream(packets), server());
+            run_link(link).await
+        });
+
+        assert_eq!(results[0].len(), 1);
+        assert_eq!(results[1].len(), 1);
+        assert_eq!(results[1][0].src_mac(), ROUTER_MAC);
+    }
+}
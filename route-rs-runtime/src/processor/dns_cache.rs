@@ -0,0 +1,544 @@
+use crate::processor::Processor;
+use route_rs_packets::{internet_checksum, EthernetFrame, IPV4_ETHER_TYPE};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const DNS_PORT: u16 = 53;
+const UDP_PROTOCOL: u8 = 17;
+const DNS_HEADER_LEN: usize = 12;
+
+/// TTL of the answers we send.
+const ANSWER_TTL: u8 = 64;
+
+/// Records of type OPT carry EDNS flags, not a TTL, in their TTL field.
+const OPT_TYPE: u16 = 41;
+const IN_CLASS: u16 = 1;
+
+/// How long a forwarded query waits for its response, before a response to it isn't cached.
+const PENDING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The question a DNS message asks: its name, lowercased, and its type.
+type Question = (String, u16);
+
+/// Where the DNS message in an IPv4 frame is: the offset of its UDP header, and of the end of
+/// the message. Fragments aren't recognised.
+fn dns_message(frame: &EthernetFrame) -> Option<(usize, usize)> {
+    if frame.ether_type() != IPV4_ETHER_TYPE {
+        return None;
+    }
+    let ip = frame.payload_offset;
+    let data = &frame.data;
+    let header = data.get(ip..ip + 20)?;
+    let more_fragments = header[6] & 0x20 != 0;
+    let fragment_offset = u16::from_be_bytes([header[6] & 0x1F, header[7]]);
+    if header[9] != UDP_PROTOCOL || more_fragments || fragment_offset != 0 {
+        return None;
+    }
+    let udp = ip + usize::from(header[0] & 0x0F) * 4;
+    let end = ip + usize::from(u16::from_be_bytes([header[2], header[3]]));
+    if end > data.len() || udp + 8 + DNS_HEADER_LEN > end {
+        return None;
+    }
+    Some((udp, end))
+}
+
+fn read_u16(message: &[u8], offset: usize) -> Option<u16> {
+    let bytes = message.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// Reads the name at `offset`, following compression pointers, returning it lowercased and
+/// dotted, and the offset just past it.
+fn read_name(message: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels = vec![];
+    let mut end = None;
+    // Each pointer must point backwards, so a name can't loop.
+    let mut limit = offset;
+    loop {
+        let len = *message.get(offset)?;
+        match len {
+            0 => break,
+            len if len & 0xC0 == 0xC0 => {
+                let pointer = usize::from(read_u16(message, offset)? & 0x3FFF);
+                if pointer >= limit {
+                    return None;
+                }
+                end = end.or(Some(offset + 2));
+                limit = pointer;
+                offset = pointer;
+            }
+            len if len & 0xC0 == 0 => {
+                let label = message.get(offset + 1..offset + 1 + usize::from(len))?;
+                labels.push(String::from_utf8_lossy(label).to_lowercase());
+                offset += 1 + usize::from(len);
+            }
+            _ => return None,
+        }
+    }
+    Some((labels.join("."), end.unwrap_or(offset + 1)))
+}
+
+/// The question of `message`, if it asks exactly one, of class IN, and the offset just past it.
+fn question(message: &[u8]) -> Option<(Question, usize)> {
+    if read_u16(message, 4)? != 1 {
+        return None;
+    }
+    let (name, offset) = read_name(message, DNS_HEADER_LEN)?;
+    let qtype = read_u16(message, offset)?;
+    if read_u16(message, offset + 2)? != IN_CLASS {
+        return None;
+    }
+    Some(((name, qtype), offset + 4))
+}
+
+/// The offsets of the TTLs of the records in `response`, leaving out OPT records, and the least
+/// TTL of its answers.
+fn ttls(response: &[u8], mut offset: usize) -> Option<(Vec<usize>, u32)> {
+    let answers = usize::from(read_u16(response, 6)?);
+    let records =
+        answers + usize::from(read_u16(response, 8)?) + usize::from(read_u16(response, 10)?);
+    let mut offsets = vec![];
+    let mut least = None;
+    for record in 0..records {
+        let (_, after_name) = read_name(response, offset)?;
+        let rtype = read_u16(response, after_name)?;
+        let ttl = after_name + 4;
+        let rdlen = usize::from(read_u16(response, ttl + 4)?);
+        offset = ttl + 6 + rdlen;
+        if offset > response.len() {
+            return None;
+        }
+        if rtype == OPT_TYPE {
+            continue;
+        }
+        offsets.push(ttl);
+        if record < answers {
+            let ttl = u32::from_be_bytes(response[ttl..ttl + 4].try_into().ok()?);
+            least = Some(least.map_or(ttl, |least: u32| least.min(ttl)));
+        }
+    }
+    Some((offsets, least?))
+}
+
+/// The answer to the query in `frame`, at `udp`, carrying `response`: the query with its
+/// addresses and ports swapped, and the response as its message.
+fn answer(frame: &EthernetFrame, udp: usize, response: &[u8]) -> Option<EthernetFrame> {
+    let ip = frame.payload_offset;
+    let mut data = frame.data[..udp + 8].to_vec();
+    data.extend_from_slice(response);
+
+    let mac = frame.layer2_offset;
+    let src_mac: [u8; 6] = data[mac + 6..mac + 12].try_into().unwrap();
+    data.copy_within(mac..mac + 6, mac + 6);
+    data[mac..mac + 6].copy_from_slice(&src_mac);
+
+    let src_addr: [u8; 4] = data[ip + 12..ip + 16].try_into().unwrap();
+    data.copy_within(ip + 16..ip + 20, ip + 12);
+    data[ip + 16..ip + 20].copy_from_slice(&src_addr);
+    let total_len = (data.len() - ip) as u16;
+    data[ip + 2..ip + 4].copy_from_slice(&total_len.to_be_bytes());
+    data[ip + 8] = ANSWER_TTL;
+    data[ip + 10..ip + 12].copy_from_slice(&[0, 0]);
+    let checksum = internet_checksum(&[&data[ip..udp]]);
+    data[ip + 10..ip + 12].copy_from_slice(&checksum.to_be_bytes());
+
+    let src_port: [u8; 2] = data[udp..udp + 2].try_into().unwrap();
+    data.copy_within(udp + 2..udp + 4, udp);
+    data[udp + 2..udp + 4].copy_from_slice(&src_port);
+    let udp_len = (8 + response.len()) as u16;
+    data[udp + 4..udp + 6].copy_from_slice(&udp_len.to_be_bytes());
+    // No checksum, which UDP over IPv4 allows.
+    data[udp + 6..udp + 8].copy_from_slice(&[0, 0]);
+
+    EthernetFrame::from_buffer(data.into(), frame.layer2_offset).ok()
+}
+
+/// Hit and miss counters of a DnsCache.
+#[derive(Default)]
+pub struct DnsCacheStats {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl DnsCacheStats {
+    /// Queries answered from the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Queries forwarded, as the cache had no answer.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Sets both counters back to zero.
+    pub fn reset(&self) {
+        self.hits.store(0, Ordering::Relaxed);
+        self.misses.store(0, Ordering::Relaxed);
+    }
+}
+
+struct CacheEntry {
+    response: Vec<u8>,
+    ttl_offsets: Vec<usize>,
+    stored: Instant,
+    expires: Instant,
+    used: u64,
+}
+
+/// Caches DNS responses, by the name and type they answer, for as long as the least TTL of
+/// their answers. Queries the cache can answer are turned into their answer, addressed back to
+/// the client, with the TTLs counted down by the time the response was cached for. Other
+/// queries are forwarded, and their responses cached on the way back. Only responses to queries
+/// that were forwarded are cached, and only successful ones with answers.
+///
+/// Holds at most `capacity` responses, evicting the least recently used. Works on DNS over UDP
+/// and IPv4; anything else passes through untouched. Its hit and miss counters are read through
+/// an `Arc<DnsCacheStats>` taken from `stats`.
+pub struct DnsCache {
+    capacity: usize,
+    entries: HashMap<Question, CacheEntry>,
+    recency: BTreeMap<u64, Question>,
+    clock: u64,
+    pending: HashMap<(Question, u16), Instant>,
+    stats: Arc<DnsCacheStats>,
+}
+
+impl DnsCache {
+    pub fn new() -> Self {
+        DnsCache {
+            capacity: 1024,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            pending: HashMap::new(),
+            stats: Arc::new(DnsCacheStats::default()),
+        }
+    }
+
+    /// Changes capacity, the most responses cached, default value is 1024.
+    pub fn capacity(self, capacity: usize) -> Self {
+        assert!(capacity > 0, "DnsCache capacity: {}, must be > 0", capacity);
+
+        DnsCache { capacity, ..self }
+    }
+
+    /// Returns the cache's counters.
+    pub fn stats(&self) -> Arc<DnsCacheStats> {
+        Arc::clone(&self.stats)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The cached response to `question`, with its TTLs counted down, if it hasn't expired.
+    fn lookup(&mut self, question: &Question, id: u16, now: Instant) -> Option<Vec<u8>> {
+        let entry = self.entries.get_mut(question)?;
+        if entry.expires <= now {
+            let used = entry.used;
+            self.entries.remove(question);
+            self.recency.remove(&used);
+            return None;
+        }
+
+        self.recency.remove(&entry.used);
+        self.clock += 1;
+        entry.used = self.clock;
+        self.recency.insert(entry.used, question.clone());
+
+        let mut response = entry.response.clone();
+        response[0..2].copy_from_slice(&id.to_be_bytes());
+        let elapsed = now.duration_since(entry.stored).as_secs() as u32;
+        for &offset in &entry.ttl_offsets {
+            let ttl = u32::from_be_bytes(response[offset..offset + 4].try_into().unwrap());
+            response[offset..offset + 4]
+                .copy_from_slice(&ttl.saturating_sub(elapsed).to_be_bytes());
+        }
+        Some(response)
+    }
+
+    /// Caches `response` to `question`, whose records start at `records`.
+    fn store(&mut self, question: Question, response: &[u8], records: usize, now: Instant) {
+        let (ttl_offsets, ttl) = match ttls(response, records) {
+            Some(ttls) => ttls,
+            None => return,
+        };
+        if ttl == 0 {
+            return;
+        }
+        if let Some(entry) = self.entries.remove(&question) {
+            self.recency.remove(&entry.used);
+        }
+        if self.entries.len() >= self.capacity {
+            let oldest = *self.recency.keys().next().unwrap();
+            let evicted = self.recency.remove(&oldest).unwrap();
+            self.entries.remove(&evicted);
+        }
+
+        self.clock += 1;
+        self.recency.insert(self.clock, question.clone());
+        self.entries.insert(
+            question,
+            CacheEntry {
+                response: response.to_vec(),
+                ttl_offsets,
+                stored: now,
+                expires: now + Duration::from_secs(u64::from(ttl)),
+                used: self.clock,
+            },
+        );
+    }
+
+    fn process_at(&mut self, frame: EthernetFrame, now: Instant) -> Option<EthernetFrame> {
+        let (udp, end) = match dns_message(&frame) {
+            Some(message) => message,
+            None => return Some(frame),
+        };
+        let src_port = u16::from_be_bytes([frame.data[udp], frame.data[udp + 1]]);
+        let dest_port = u16::from_be_bytes([frame.data[udp + 2], frame.data[udp + 3]]);
+        let message = &frame.data[udp + 8..end];
+        let id = u16::from_be_bytes([message[0], message[1]]);
+        let is_response = message[2] & 0x80 != 0;
+        let opcode = (message[2] >> 3) & 0x0F;
+        let truncated = message[2] & 0x02 != 0;
+        let rcode = message[3] & 0x0F;
+        let (question, records) = match question(message) {
+            Some(question) if opcode == 0 => question,
+            _ => return Some(frame),
+        };
+
+        if dest_port == DNS_PORT && !is_response {
+            if let Some(mut response) = self.lookup(&question, id, now) {
+                self.stats.hits.fetch_add(1, Ordering::Relaxed);
+                // The question is echoed as it was asked, as resolvers check its case.
+                response[DNS_HEADER_LEN..records]
+                    .copy_from_slice(&message[DNS_HEADER_LEN..records]);
+                return answer(&frame, udp, &response);
+            }
+            self.stats.misses.fetch_add(1, Ordering::Relaxed);
+            if self.pending.len() >= self.capacity {
+                self.pending.retain(|_, sent| now < *sent + PENDING_TIMEOUT);
+            }
+            if self.pending.len() < self.capacity {
+                self.pending.insert((question, id), now);
+            }
+        } else if src_port == DNS_PORT && is_response {
+            let sent = self.pending.remove(&(question.clone(), id));
+            let solicited = matches!(sent, Some(sent) if now < sent + PENDING_TIMEOUT);
+            if solicited && !truncated && rcode == 0 {
+                let response = message.to_vec();
+                self.store(question, &response, records, now);
+            }
+        }
+        Some(frame)
+    }
+}
+
+impl Default for DnsCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Processor for DnsCache {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, frame: Self::Input) -> Option<Self::Output> {
+        self.process_at(frame, Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use route_rs_packets::{Ipv4Packet, MacAddr, UdpSegment};
+    use std::convert::TryFrom;
+    use std::net::Ipv4Addr;
+
+    const CLIENT: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+    const RESOLVER: Ipv4Addr = Ipv4Addr::new(1, 1, 1, 1);
+    const CLIENT_MAC: MacAddr = MacAddr {
+        bytes: [2, 0, 0, 0, 0, 2],
+    };
+    const ROUTER_MAC: MacAddr = MacAddr {
+        bytes: [2, 0, 0, 0, 0, 1],
+    };
+    const A: u16 = 1;
+
+    fn udp(src: (Ipv4Addr, u16), dst: (Ipv4Addr, u16), payload: &[u8]) -> EthernetFrame {
+        let mut segment = UdpSegment::empty();
+        segment.set_src_port(src.1);
+        segment.set_dest_port(dst.1);
+        segment.set_payload(payload);
+        let length = segment.data.len() as u16;
+        segment.data[4..6].copy_from_slice(&length.to_be_bytes());
+        let mut packet = Ipv4Packet::encap_udp(segment);
+        packet.set_src_addr(src.0);
+        packet.set_dest_addr(dst.0);
+        packet.set_ttl(64);
+        packet.set_checksum();
+        let mut frame = EthernetFrame::encap_ipv4(packet);
+        frame.set_src_mac(CLIENT_MAC);
+        frame.set_dest_mac(ROUTER_MAC);
+        frame
+    }
+
+    fn dns_query(id: u16, name: &str) -> Vec<u8> {
+        let mut message = id.to_be_bytes().to_vec();
+        message.extend_from_slice(&[0x01, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+        for label in name.split('.') {
+            message.push(label.len() as u8);
+            message.extend_from_slice(label.as_bytes());
+        }
+        message.push(0);
+        message.extend_from_slice(&A.to_be_bytes());
+        message.extend_from_slice(&IN_CLASS.to_be_bytes());
+        message
+    }
+
+    /// A response to the query, with an answer of `addr`, its name compressed to point at the
+    /// question, and an OPT record.
+    fn dns_response(id: u16, name: &str, ttl: u32, addr: Ipv4Addr) -> Vec<u8> {
+        let mut message = dns_query(id, name);
+        message[2] = 0x81;
+        message[3] = 0x80;
+        message[7] = 1;
+        message[11] = 1;
+        message.extend_from_slice(&[0xC0, 12, 0, 1, 0, 1]);
+        message.extend_from_slice(&ttl.to_be_bytes());
+        message.extend_from_slice(&[0, 4]);
+        message.extend_from_slice(&addr.octets());
+        message.extend_from_slice(&[0, 0, 41, 0x10, 0, 0, 0, 0, 0, 0, 0]);
+        message
+    }
+
+    fn query(id: u16, name: &str) -> EthernetFrame {
+        udp((CLIENT, 5353), (RESOLVER, DNS_PORT), &dns_query(id, name))
+    }
+
+    fn response(id: u16, name: &str, ttl: u32) -> EthernetFrame {
+        let addr = Ipv4Addr::new(93, 184, 216, 34);
+        udp(
+            (RESOLVER, DNS_PORT),
+            (CLIENT, 5353),
+            &dns_response(id, name, ttl, addr),
+        )
+    }
+
+    #[test]
+    fn answers_repeated_queries() {
+        let mut cache = DnsCache::new();
+        let stats = cache.stats();
+        let now = Instant::now();
+
+        assert_eq!(
+            cache.process_at(query(1, "example.com"), now),
+            Some(query(1, "example.com"))
+        );
+        assert_eq!(stats.misses(), 1);
+        cache
+            .process_at(response(1, "example.com", 300), now)
+            .unwrap();
+        assert_eq!(cache.len(), 1);
+
+        // Names are matched without regard to case.
+        let later = now + Duration::from_secs(100);
+        let answer = cache.process_at(query(2, "Example.COM"), later).unwrap();
+        assert_eq!(stats.hits(), 1);
+        assert_eq!(answer.dest_mac(), CLIENT_MAC);
+        assert_eq!(answer.src_mac(), ROUTER_MAC);
+
+        let mut packet = Ipv4Packet::try_from(answer).unwrap();
+        assert_eq!(packet.src_addr(), RESOLVER);
+        assert_eq!(packet.dest_addr(), CLIENT);
+        assert!(packet.validate_checksum());
+        let segment = UdpSegment::try_from(packet).unwrap();
+        assert_eq!(segment.src_port(), DNS_PORT);
+        assert_eq!(segment.dest_port(), 5353);
+        let expected = dns_response(2, "Example.COM", 200, Ipv4Addr::new(93, 184, 216, 34));
+        assert_eq!(usize::from(segment.length()), 8 + expected.len());
+        assert_eq!(segment.payload().as_ref(), &expected[..]);
+    }
+
+    #[test]
+    fn expires_with_ttl() {
+        let mut cache = DnsCache::new();
+        let stats = cache.stats();
+        let now = Instant::now();
+        cache.process_at(query(1, "example.com"), now);
+        cache.process_at(response(1, "example.com", 60), now);
+
+        let expired = now + Duration::from_secs(60);
+        assert_eq!(
+            cache.process_at(query(2, "example.com"), expired),
+            Some(query(2, "example.com"))
+        );
+        assert_eq!(stats.hits(), 0);
+        assert_eq!(stats.misses(), 2);
+        assert!(cache.is_empty());
+
+        stats.reset();
+        assert_eq!(stats.misses(), 0);
+    }
+
+    #[test]
+    fn ignores_unsolicited_responses() {
+        let mut cache = DnsCache::new();
+        let now = Instant::now();
+        assert!(cache
+            .process_at(response(1, "example.com", 300), now)
+            .is_some());
+        assert!(cache.is_empty());
+
+        // Nor a response with another ID.
+        cache.process_at(query(1, "example.com"), now);
+        cache.process_at(response(2, "example.com", 300), now);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = DnsCache::new().capacity(2);
+        let stats = cache.stats();
+        let now = Instant::now();
+        for (id, name) in [(1, "a.com"), (2, "b.com"), (3, "a.com"), (4, "c.com")].iter() {
+            let id = *id;
+            if let Some(frame) = cache.process_at(query(id, name), now) {
+                if frame == query(id, name) {
+                    cache.process_at(response(id, name, 300), now);
+                }
+            }
+        }
+        assert_eq!(stats.hits(), 1);
+        assert_eq!(cache.len(), 2);
+
+        assert_ne!(
+            cache.process_at(query(5, "a.com"), now),
+            Some(query(5, "a.com"))
+        );
+        assert_ne!(
+            cache.process_at(query(6, "c.com"), now),
+            Some(query(6, "c.com"))
+        );
+        assert_eq!(
+            cache.process_at(query(7, "b.com"), now),
+            Some(query(7, "b.com"))
+        );
+    }
+
+    #[test]
+    fn passes_other_frames() {
+        let mut cache = DnsCache::new();
+        let frame = udp((CLIENT, 5353), (RESOLVER, 80), b"GET /");
+        assert_eq!(cache.process(frame.clone()), Some(frame));
+    }
+}
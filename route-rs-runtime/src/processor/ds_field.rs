@@ -0,0 +1,190 @@
+use crate::classifier::Classifier;
+use crate::processor::Processor;
+use route_rs_packets::{update_u16, Ipv4Packet, Ipv6Packet};
+use std::marker::PhantomData;
+
+/// Packets with a Differentiated Services field, the IPv4 Type of Service or IPv6 Traffic Class
+/// byte, holding a 6 bit DSCP and 2 bit ECN codepoint.
+pub trait DsField {
+    fn ds_field(&self) -> u8;
+
+    /// Sets the field, updating any header checksum covering it.
+    fn set_ds_field(&mut self, ds_field: u8);
+}
+
+impl DsField for Ipv4Packet {
+    fn ds_field(&self) -> u8 {
+        self.data[self.layer3_offset + 1]
+    }
+
+    fn set_ds_field(&mut self, ds_field: u8) {
+        // The field shares its 16 bit word of the header with the version and header length.
+        let old_word = u16::from_be_bytes([self.data[self.layer3_offset], self.ds_field()]);
+        let new_word = u16::from_be_bytes([self.data[self.layer3_offset], ds_field]);
+        let checksum = update_u16(self.checksum(), old_word, new_word);
+        self.data[self.layer3_offset + 1] = ds_field;
+        self.data[self.layer3_offset + 10..self.layer3_offset + 12]
+            .copy_from_slice(&checksum.to_be_bytes());
+    }
+}
+
+impl DsField for Ipv6Packet {
+    fn ds_field(&self) -> u8 {
+        self.traffic_class()
+    }
+
+    fn set_ds_field(&mut self, ds_field: u8) {
+        self.set_traffic_class(ds_field);
+    }
+}
+
+/// ECN codepoints, per RFC 3168.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Ecn {
+    /// The sender doesn't support ECN.
+    NotEct = 0,
+    Ect1 = 1,
+    Ect0 = 2,
+    /// Congestion Experienced, set by a router instead of dropping an ECN capable packet.
+    Ce = 3,
+}
+
+/// Sets the ECN codepoint of IPv4 or IPv6 packets to `ecn`, for instance to clear it with
+/// NotEct before packets leave for a network that mishandles ECN, or to mark congestion with Ce.
+/// Only packets whose sender supports ECN can be marked Ce; others pass unchanged.
+pub struct EcnMark<Packet> {
+    ecn: Ecn,
+    phantom: PhantomData<Packet>,
+}
+
+impl<Packet> EcnMark<Packet> {
+    pub fn new(ecn: Ecn) -> Self {
+        EcnMark {
+            ecn,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<Packet: DsField + Send + Clone> Processor for EcnMark<Packet> {
+    type Input = Packet;
+    type Output = Packet;
+
+    fn process(&mut self, mut packet: Self::Input) -> Option<Self::Output> {
+        let ds_field = packet.ds_field();
+        let ecn_capable = ds_field & 0x03 != Ecn::NotEct as u8;
+        if self.ecn != Ecn::Ce || ecn_capable {
+            packet.set_ds_field((ds_field & 0xFC) | self.ecn as u8);
+        }
+        Some(packet)
+    }
+}
+
+/// Rewrites the DSCP of IPv4 or IPv6 packets by their class, as found by `classifier`, and mapped
+/// to a codepoint by `mapper`, for instance to mark voice traffic EF (46) for WMM. Packets the
+/// mapper gives no codepoint keep theirs. The ECN codepoint is left unchanged.
+pub struct DscpMark<C: Classifier> {
+    classifier: C,
+    mapper: Box<dyn Fn(C::Class) -> Option<u8> + Send + Sync + 'static>,
+}
+
+impl<C: Classifier> DscpMark<C> {
+    pub fn new(
+        classifier: C,
+        mapper: Box<dyn Fn(C::Class) -> Option<u8> + Send + Sync + 'static>,
+    ) -> Self {
+        DscpMark { classifier, mapper }
+    }
+}
+
+impl<C: Classifier> Processor for DscpMark<C>
+where
+    C::Packet: DsField,
+{
+    type Input = C::Packet;
+    type Output = C::Packet;
+
+    fn process(&mut self, mut packet: Self::Input) -> Option<Self::Output> {
+        let class = self.classifier.classify(&packet);
+        if let Some(dscp) = (self.mapper)(class) {
+            assert!(dscp < 64, "DscpMark dscp: {}, must be < 64", dscp);
+
+            let ds_field = packet.ds_field();
+            packet.set_ds_field((dscp << 2) | (ds_field & 0x03));
+        }
+        Some(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn ipv4_packet(ds_field: u8, protocol: u8) -> Ipv4Packet {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_ttl(64);
+        packet.set_protocol(protocol);
+        packet.set_src_addr(Ipv4Addr::new(10, 0, 0, 1));
+        packet.set_dest_addr(Ipv4Addr::new(192, 168, 0, 1));
+        packet.data[1] = ds_field;
+        packet.set_checksum();
+        packet
+    }
+
+    struct ByProtocol {}
+
+    impl Classifier for ByProtocol {
+        type Packet = Ipv4Packet;
+        type Class = u8;
+
+        fn classify(&self, packet: &Self::Packet) -> Self::Class {
+            packet.data[packet.layer3_offset + 9]
+        }
+    }
+
+    #[test]
+    fn marks_ce_only_when_ecn_capable() {
+        let mut processor = EcnMark::new(Ecn::Ce);
+
+        let mut marked = processor.process(ipv4_packet(0xB8 | 2, 17)).unwrap();
+        assert_eq!(marked.ecn(), 3);
+        assert_eq!(marked.dscp(), 46);
+        assert!(marked.validate_checksum());
+
+        let unmarked = processor.process(ipv4_packet(0xB8, 17)).unwrap();
+        assert_eq!(unmarked.ecn(), 0);
+    }
+
+    #[test]
+    fn clears_ecn() {
+        let mut packet = Ipv6Packet::empty();
+        packet.set_traffic_class(0xB8 | 1);
+        let packet = EcnMark::new(Ecn::NotEct).process(packet).unwrap();
+        assert_eq!(packet.traffic_class(), 0xB8);
+    }
+
+    #[test]
+    fn dscp_by_class() {
+        let mut processor = DscpMark::new(
+            ByProtocol {},
+            Box::new(|protocol| match protocol {
+                17 => Some(46),
+                6 => Some(10),
+                _ => None,
+            }),
+        );
+
+        let mut udp = processor.process(ipv4_packet(1, 17)).unwrap();
+        assert_eq!(udp.dscp(), 46);
+        assert_eq!(udp.ecn(), 1);
+        assert!(udp.validate_checksum());
+
+        let mut tcp = processor.process(ipv4_packet(0, 6)).unwrap();
+        assert_eq!(tcp.dscp(), 10);
+        assert!(tcp.validate_checksum());
+
+        let icmp = processor.process(ipv4_packet(0x20, 1)).unwrap();
+        assert_eq!(icmp.dscp(), 8);
+    }
+}
@@ -0,0 +1,483 @@
+use crate::processor::{Processor, ReplayWindow};
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes128Gcm, Aes256Gcm, Key, Nonce};
+use route_rs_packets::{EthernetFrame, Ipv4Packet, MacAddr, IPV4_ETHER_TYPE, IPV6_ETHER_TYPE};
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+
+/// The IP protocol number of ESP.
+pub const ESP_PROTOCOL: u8 = 50;
+/// TTL of the outer packets we send.
+const OUTER_TTL: u8 = 64;
+
+/// Next header values of the trailer: the inner packet's IP version, or a dummy packet, sent
+/// only to hide the traffic's pattern.
+const IPV4_NEXT_HEADER: u8 = 4;
+const IPV6_NEXT_HEADER: u8 = 41;
+const NO_NEXT_HEADER: u8 = 59;
+
+/// The SPI and sequence number that start every ESP packet.
+const ESP_HEADER_LEN: usize = 8;
+
+/// A cipher suite of ESP, encrypting and authenticating the payloads of packets. Implemented by
+/// AesGcmCipher, and may be implemented for others.
+pub trait EspCipher: Send {
+    /// How many bytes of the payload the encrypted data must be a multiple of. ESP needs at
+    /// least 4.
+    fn block_size(&self) -> usize {
+        4
+    }
+
+    /// Encrypts `plaintext`, the payload of the packet numbered `seq`, authenticating `aad`
+    /// along with it. Returns the payload as sent: the IV, the ciphertext and the ICV.
+    fn seal(&self, seq: u64, aad: &[u8], plaintext: &[u8]) -> Option<Vec<u8>>;
+
+    /// Decrypts the payload `payload` as sent, if it and `aad` are authentic.
+    fn open(&self, aad: &[u8], payload: &[u8]) -> Option<Vec<u8>>;
+}
+
+enum GcmKey {
+    Aes128(Box<Aes128Gcm>),
+    Aes256(Box<Aes256Gcm>),
+}
+
+/// AES in Galois/Counter Mode, with a 16 byte ICV, per RFC 4106. Each SA's keying material is the
+/// key, followed by the 4 byte salt of the nonces. The IV sent with each packet is its sequence
+/// number, which is never repeated.
+pub struct AesGcmCipher {
+    key: GcmKey,
+    salt: [u8; 4],
+}
+
+impl AesGcmCipher {
+    pub fn aes128(key: [u8; 16], salt: [u8; 4]) -> Self {
+        AesGcmCipher {
+            key: GcmKey::Aes128(Box::new(Aes128Gcm::new(Key::<Aes128Gcm>::from_slice(&key)))),
+            salt,
+        }
+    }
+
+    pub fn aes256(key: [u8; 32], salt: [u8; 4]) -> Self {
+        AesGcmCipher {
+            key: GcmKey::Aes256(Box::new(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)))),
+            salt,
+        }
+    }
+
+    fn nonce(&self, iv: &[u8]) -> [u8; 12] {
+        let mut nonce = [0; 12];
+        nonce[..4].copy_from_slice(&self.salt);
+        nonce[4..].copy_from_slice(iv);
+        nonce
+    }
+}
+
+const GCM_IV_LEN: usize = 8;
+
+impl EspCipher for AesGcmCipher {
+    fn seal(&self, seq: u64, aad: &[u8], plaintext: &[u8]) -> Option<Vec<u8>> {
+        let iv = seq.to_be_bytes();
+        let nonce = self.nonce(&iv);
+        let payload = Payload {
+            msg: plaintext,
+            aad,
+        };
+        let ciphertext = match &self.key {
+            GcmKey::Aes128(key) => key.encrypt(Nonce::from_slice(&nonce), payload),
+            GcmKey::Aes256(key) => key.encrypt(Nonce::from_slice(&nonce), payload),
+        }
+        .ok()?;
+
+        let mut sealed = iv.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Some(sealed)
+    }
+
+    fn open(&self, aad: &[u8], payload: &[u8]) -> Option<Vec<u8>> {
+        let nonce = self.nonce(payload.get(..GCM_IV_LEN)?);
+        let payload = Payload {
+            msg: &payload[GCM_IV_LEN..],
+            aad,
+        };
+        match &self.key {
+            GcmKey::Aes128(key) => key.decrypt(Nonce::from_slice(&nonce), payload),
+            GcmKey::Aes256(key) => key.decrypt(Nonce::from_slice(&nonce), payload),
+        }
+        .ok()
+    }
+}
+
+/// A security association: one direction of a tunnel between `src` and `dst`, identified by its
+/// SPI, with the cipher and keys negotiated for it, by IKE or by hand. Outbound, it numbers the
+/// packets sent; inbound, it keeps the window of those received.
+pub struct EspSa {
+    spi: u32,
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    cipher: Box<dyn EspCipher>,
+    seq: u32,
+    replay: ReplayWindow,
+}
+
+impl EspSa {
+    pub fn new(spi: u32, src: Ipv4Addr, dst: Ipv4Addr, cipher: Box<dyn EspCipher>) -> Self {
+        EspSa {
+            spi,
+            src,
+            dst,
+            cipher,
+            seq: 0,
+            replay: ReplayWindow::new(),
+        }
+    }
+}
+
+/// The security associations of the router, by SPI, shared by the EspEncap and EspDecap of its
+/// tunnels. Clones share the associations.
+#[derive(Clone)]
+pub struct EspSaTable {
+    sas: Arc<Mutex<HashMap<u32, EspSa>>>,
+}
+
+impl EspSaTable {
+    pub fn new() -> Self {
+        EspSaTable {
+            sas: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Adds `sa`, replacing any with the same SPI, as when rekeying.
+    pub fn insert(&self, sa: EspSa) {
+        self.sas.lock().unwrap().insert(sa.spi, sa);
+    }
+
+    /// Removes the SA with `spi`, returning whether there was one.
+    pub fn remove(&self, spi: u32) -> bool {
+        self.sas.lock().unwrap().remove(&spi).is_some()
+    }
+
+    /// How many packets have been sent with the SA with `spi`, if there is one.
+    pub fn sent(&self, spi: u32) -> Option<u32> {
+        self.sas.lock().unwrap().get(&spi).map(|sa| sa.seq)
+    }
+
+    pub fn len(&self) -> usize {
+        self.sas.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sas.lock().unwrap().is_empty()
+    }
+}
+
+impl Default for EspSaTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Encapsulates IP packets in ESP, in tunnel mode, with the outbound SA with `spi`, from its
+/// source to its destination. The outer frame's MAC addresses are zero unless set. Frames that
+/// aren't IPv4 or IPv6 are dropped, as is everything while there's no SA with `spi`, or once
+/// its sequence numbers have run out, until it's replaced.
+pub struct EspEncap {
+    sas: EspSaTable,
+    spi: u32,
+    src_mac: MacAddr,
+    dst_mac: MacAddr,
+}
+
+impl EspEncap {
+    pub fn new(sas: EspSaTable, spi: u32) -> Self {
+        EspEncap {
+            sas,
+            spi,
+            src_mac: MacAddr::new([0; 6]),
+            dst_mac: MacAddr::new([0; 6]),
+        }
+    }
+
+    pub fn src_mac(self, src_mac: MacAddr) -> Self {
+        EspEncap { src_mac, ..self }
+    }
+
+    pub fn dst_mac(self, dst_mac: MacAddr) -> Self {
+        EspEncap { dst_mac, ..self }
+    }
+}
+
+impl Processor for EspEncap {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, frame: Self::Input) -> Option<Self::Output> {
+        let inner = &frame.data[frame.payload_offset..];
+        let (len, next_header) = match frame.ether_type() {
+            IPV4_ETHER_TYPE => {
+                let len = u16::from_be_bytes(inner.get(2..4)?.try_into().unwrap());
+                (usize::from(len), IPV4_NEXT_HEADER)
+            }
+            IPV6_ETHER_TYPE => {
+                let len = u16::from_be_bytes(inner.get(4..6)?.try_into().unwrap());
+                (40 + usize::from(len), IPV6_NEXT_HEADER)
+            }
+            _ => return None,
+        };
+        let inner = inner.get(..len)?;
+
+        let mut sas = self.sas.sas.lock().unwrap();
+        let sa = sas.get_mut(&self.spi)?;
+        // Without extended sequence numbers, they may not cycle.
+        sa.seq = sa.seq.checked_add(1)?;
+
+        // The trailer's padding, 1, 2, 3 and on, aligns the pad length and next header to the
+        // end of a block.
+        let block_size = sa.cipher.block_size();
+        let pad_len = (block_size - (inner.len() + 2) % block_size) % block_size;
+        let mut plaintext = Vec::with_capacity(inner.len() + pad_len + 2);
+        plaintext.extend_from_slice(inner);
+        plaintext.extend(1..=pad_len as u8);
+        plaintext.push(pad_len as u8);
+        plaintext.push(next_header);
+
+        let mut esp = sa.spi.to_be_bytes().to_vec();
+        esp.extend_from_slice(&sa.seq.to_be_bytes());
+        let sealed = sa.cipher.seal(u64::from(sa.seq), &esp, &plaintext)?;
+        esp.extend_from_slice(&sealed);
+
+        let mut packet = Ipv4Packet::empty();
+        packet.set_ttl(OUTER_TTL);
+        packet.set_protocol(ESP_PROTOCOL);
+        packet.set_src_addr(sa.src);
+        packet.set_dest_addr(sa.dst);
+        packet.set_payload(&esp);
+        packet.set_checksum();
+
+        let mut outer = EthernetFrame::encap_ipv4(packet);
+        outer.set_src_mac(self.src_mac);
+        outer.set_dest_mac(self.dst_mac);
+        Some(outer)
+    }
+}
+
+/// Decapsulates IP packets from ESP in tunnel mode, with the inbound SA the SPI of each packet
+/// names, into frames with zero MAC addresses. Packets for no SA, or to another address than the
+/// SA's destination, or that aren't authentic, or are replays, are dropped, as are dummy
+/// packets.
+pub struct EspDecap {
+    sas: EspSaTable,
+}
+
+impl EspDecap {
+    pub fn new(sas: EspSaTable) -> Self {
+        EspDecap { sas }
+    }
+}
+
+impl Processor for EspDecap {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, frame: Self::Input) -> Option<Self::Output> {
+        if frame.ether_type() != IPV4_ETHER_TYPE {
+            return None;
+        }
+        let packet = Ipv4Packet::try_from(frame).ok()?;
+        if packet.data[packet.layer3_offset + 9] != ESP_PROTOCOL || packet.fragment_offset() != 0 {
+            return None;
+        }
+        let esp = &packet.data[packet.payload_offset..];
+        if esp.len() < ESP_HEADER_LEN {
+            return None;
+        }
+        let spi = u32::from_be_bytes(esp[0..4].try_into().unwrap());
+        let seq = u32::from_be_bytes(esp[4..8].try_into().unwrap());
+
+        let mut sas = self.sas.sas.lock().unwrap();
+        let sa = sas.get_mut(&spi)?;
+        if packet.dest_addr() != sa.dst || seq == 0 || !sa.replay.check(u64::from(seq)) {
+            return None;
+        }
+        let plaintext = sa
+            .cipher
+            .open(&esp[..ESP_HEADER_LEN], &esp[ESP_HEADER_LEN..])?;
+        if !sa.replay.accept(u64::from(seq)) {
+            return None;
+        }
+        drop(sas);
+
+        let (&next_header, trailer) = plaintext.split_last()?;
+        let (&pad_len, padded) = trailer.split_last()?;
+        let inner_len = padded.len().checked_sub(usize::from(pad_len))?;
+        let (inner, padding) = padded.split_at(inner_len);
+        if !padding
+            .iter()
+            .zip(1..)
+            .all(|(pad, expected)| *pad == expected)
+        {
+            return None;
+        }
+        let ether_type = match next_header {
+            IPV4_NEXT_HEADER => IPV4_ETHER_TYPE,
+            IPV6_NEXT_HEADER => IPV6_ETHER_TYPE,
+            // Dummy packets are dropped, as is anything but a tunnelled IP packet.
+            NO_NEXT_HEADER => return None,
+            _ => return None,
+        };
+
+        let mut data = vec![0; 14];
+        data[12..14].copy_from_slice(&ether_type.to_be_bytes());
+        data.extend_from_slice(inner);
+        EthernetFrame::from_buffer(data.into(), 0).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use route_rs_packets::Ipv6Packet;
+    use std::net::Ipv6Addr;
+
+    const LOCAL: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 1);
+    const PEER: Ipv4Addr = Ipv4Addr::new(203, 0, 113, 7);
+    const KEY: [u8; 16] = [0x42; 16];
+    const SALT: [u8; 4] = [1, 2, 3, 4];
+
+    /// A tunnel's SAs, as seen by us and by the peer.
+    fn tables() -> (EspSaTable, EspSaTable) {
+        let ours = EspSaTable::new();
+        ours.insert(EspSa::new(
+            0x100,
+            LOCAL,
+            PEER,
+            Box::new(AesGcmCipher::aes128(KEY, SALT)),
+        ));
+        let theirs = EspSaTable::new();
+        theirs.insert(EspSa::new(
+            0x100,
+            LOCAL,
+            PEER,
+            Box::new(AesGcmCipher::aes128(KEY, SALT)),
+        ));
+        (ours, theirs)
+    }
+
+    fn inner_frame() -> EthernetFrame {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_src_addr(Ipv4Addr::new(10, 1, 0, 2));
+        packet.set_dest_addr(Ipv4Addr::new(10, 2, 0, 2));
+        packet.set_payload(&[1, 2, 3, 4, 5]);
+        packet.set_checksum();
+        EthernetFrame::encap_ipv4(packet)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (ours, theirs) = tables();
+        let mut encap = EspEncap::new(ours.clone(), 0x100);
+        let mut decap = EspDecap::new(theirs);
+
+        let outer = encap.process(inner_frame()).unwrap();
+        let mut packet = Ipv4Packet::try_from(outer.clone()).unwrap();
+        assert!(packet.validate_checksum());
+        assert_eq!(
+            packet.protocol(),
+            route_rs_packets::IpProtocol::from(ESP_PROTOCOL)
+        );
+        assert_eq!(packet.src_addr(), LOCAL);
+        assert_eq!(packet.dest_addr(), PEER);
+
+        // The packet is the SPI and sequence number, then the IV, here the sequence number, and
+        // the sealed payload: the inner packet, padding, pad length and next header.
+        let esp = packet.payload();
+        assert_eq!(esp[..16], [0, 0, 1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1]);
+        let cipher = Aes128Gcm::new(Key::<Aes128Gcm>::from_slice(&KEY));
+        let plaintext = cipher
+            .decrypt(
+                Nonce::from_slice(&[1, 2, 3, 4, 0, 0, 0, 0, 0, 0, 0, 1]),
+                Payload {
+                    msg: &esp[16..],
+                    aad: &esp[..8],
+                },
+            )
+            .unwrap();
+        let inner = &inner_frame().data[14..];
+        assert_eq!(plaintext.len() % 4, 0);
+        assert_eq!(&plaintext[..inner.len()], inner);
+        // 25 bytes, padded by one to end on a 4 byte boundary.
+        assert_eq!(plaintext[inner.len()..], [1, 1, IPV4_NEXT_HEADER]);
+
+        assert_eq!(decap.process(outer), Some(inner_frame()));
+        assert_eq!(ours.sent(0x100), Some(1));
+    }
+
+    #[test]
+    fn carries_ipv6_with_aes256() {
+        let key = [7; 32];
+        let ours = EspSaTable::new();
+        ours.insert(EspSa::new(
+            9,
+            LOCAL,
+            PEER,
+            Box::new(AesGcmCipher::aes256(key, SALT)),
+        ));
+        let theirs = EspSaTable::new();
+        theirs.insert(EspSa::new(
+            9,
+            LOCAL,
+            PEER,
+            Box::new(AesGcmCipher::aes256(key, SALT)),
+        ));
+
+        let mut packet = Ipv6Packet::empty();
+        packet.set_src_addr(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 2));
+        packet.set_payload(&[9; 21]);
+        let inner = EthernetFrame::encap_ipv6(packet);
+
+        let outer = EspEncap::new(ours, 9).process(inner.clone()).unwrap();
+        assert_eq!(EspDecap::new(theirs).process(outer), Some(inner));
+    }
+
+    #[test]
+    fn rejects_replays_and_forgeries() {
+        let (ours, theirs) = tables();
+        let mut encap = EspEncap::new(ours, 0x100);
+        let mut decap = EspDecap::new(theirs);
+
+        let first = encap.process(inner_frame()).unwrap();
+        let second = encap.process(inner_frame()).unwrap();
+        assert!(decap.process(second.clone()).is_some());
+        assert_eq!(decap.process(second), None);
+        let mut forged = first.clone();
+        let last = forged.data.len() - 1;
+        forged.data[last] ^= 1;
+        assert_eq!(decap.process(forged), None);
+        // A forgery doesn't use up its sequence number.
+        assert!(decap.process(first).is_some());
+    }
+
+    #[test]
+    fn drops_without_sa() {
+        let (ours, theirs) = tables();
+        assert_eq!(
+            EspEncap::new(ours.clone(), 0x200).process(inner_frame()),
+            None
+        );
+
+        let outer = EspEncap::new(ours, 0x100).process(inner_frame()).unwrap();
+        assert!(theirs.remove(0x100));
+        assert!(theirs.is_empty());
+        assert_eq!(EspDecap::new(theirs).process(outer), None);
+    }
+
+    #[test]
+    fn sequence_numbers_run_out() {
+        let (ours, _) = tables();
+        ours.sas.lock().unwrap().get_mut(&0x100).unwrap().seq = u32::MAX - 1;
+        let mut encap = EspEncap::new(ours, 0x100);
+        assert!(encap.process(inner_frame()).is_some());
+        assert_eq!(encap.process(inner_frame()), None);
+    }
+}
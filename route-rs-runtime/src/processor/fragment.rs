@@ -0,0 +1,191 @@
+use crate::processor::Processor;
+use route_rs_packets::Ipv4Packet;
+
+/// The smallest MTU every IPv4 link must support, per RFC 791.
+const MIN_MTU: usize = 68;
+
+/// Fragments IPv4 packets longer than `mtu` bytes, outputting the fragments in order, or the
+/// packet alone if it fits. Run before an UnbatchLink to send the fragments on as separate
+/// packets. Every fragment gets the header of the original, with its checksum recomputed, but
+/// fragments after the first only keep the options whose copied flag is set. Packets that are
+/// already fragments are fragmented further, keeping their offset and More Fragments flag.
+/// Oversized packets with Don't Fragment set are dropped.
+pub struct Fragment {
+    mtu: usize,
+}
+
+impl Fragment {
+    pub fn new(mtu: usize) -> Self {
+        assert!(
+            mtu >= MIN_MTU,
+            "Fragment mtu: {}, must be >= {}",
+            mtu,
+            MIN_MTU
+        );
+
+        Fragment { mtu }
+    }
+}
+
+impl Processor for Fragment {
+    type Input = Ipv4Packet;
+    type Output = Vec<Ipv4Packet>;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        if usize::from(packet.total_len()) <= self.mtu {
+            return Some(vec![packet]);
+        }
+        let (dont_fragment, more_fragments) = packet.flags();
+        if dont_fragment {
+            return None;
+        }
+
+        let layer3_offset = packet.layer3_offset;
+        let header = &packet.data[layer3_offset..packet.payload_offset];
+        let payload = &packet.data[packet.payload_offset..];
+        let copied_header = copied_header(header);
+
+        let mut fragments = vec![];
+        let mut offset = 0;
+        while offset < payload.len() {
+            let header = if offset == 0 { header } else { &copied_header };
+            // Offsets count 8 byte units, so every fragment but the last carries a multiple of 8.
+            let max_len = (self.mtu - header.len()) / 8 * 8;
+            let end = (offset + max_len).min(payload.len());
+
+            let mut data = Vec::with_capacity(layer3_offset + header.len() + end - offset);
+            data.extend_from_slice(&packet.data[..layer3_offset]);
+            data.extend_from_slice(header);
+            data.extend_from_slice(&payload[offset..end]);
+            let total_len = (header.len() + end - offset) as u16;
+            data[layer3_offset] = 0x40 | (header.len() / 4) as u8;
+            data[layer3_offset + 2..layer3_offset + 4].copy_from_slice(&total_len.to_be_bytes());
+
+            let mut fragment =
+                Ipv4Packet::from_buffer(data.into(), packet.layer2_offset, layer3_offset)
+                    .expect("Fragment built an invalid IPv4 packet");
+            fragment.set_fragment_offset(packet.fragment_offset() + (offset / 8) as u16);
+            fragment.set_flags(false, end < payload.len() || more_fragments);
+            fragment.set_checksum();
+            fragments.push(fragment);
+            offset = end;
+        }
+        Some(fragments)
+    }
+}
+
+/// Returns `header` with only the options that are copied into every fragment, padded to a
+/// multiple of 4 bytes.
+fn copied_header(header: &[u8]) -> Vec<u8> {
+    let mut copied = header[..20].to_vec();
+    let options = &header[20..];
+    let mut i = 0;
+    while i < options.len() {
+        let length = match options[i] {
+            // End of options list.
+            0 => break,
+            // No operation.
+            1 => 1,
+            _ => match options.get(i + 1) {
+                Some(length) if *length >= 2 => usize::from(*length),
+                _ => break,
+            },
+        };
+        let end = (i + length).min(options.len());
+        if options[i] & 0x80 != 0 {
+            copied.extend_from_slice(&options[i..end]);
+        }
+        i = end;
+    }
+    let padding = (4 - copied.len() % 4) % 4;
+    copied.resize(copied.len() + padding, 0);
+    copied
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn ipv4_packet(payload: &[u8]) -> Ipv4Packet {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_ttl(64);
+        packet.set_protocol(17);
+        packet.set_identification(0x1234);
+        packet.set_src_addr(Ipv4Addr::new(10, 0, 0, 1));
+        packet.set_dest_addr(Ipv4Addr::new(192, 168, 0, 1));
+        packet.set_payload(payload);
+        packet.set_checksum();
+        packet
+    }
+
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn fits() {
+        let packet = ipv4_packet(&payload(100));
+        assert_eq!(
+            Fragment::new(120).process(packet.clone()),
+            Some(vec![packet])
+        );
+    }
+
+    #[test]
+    fn fragments() {
+        let payload = payload(1000);
+        let fragments = Fragment::new(500).process(ipv4_packet(&payload)).unwrap();
+
+        let lens: Vec<u16> = fragments.iter().map(|f| f.total_len()).collect();
+        assert_eq!(lens, vec![500, 500, 60]);
+        let offsets: Vec<u16> = fragments.iter().map(|f| f.fragment_offset()).collect();
+        assert_eq!(offsets, vec![0, 60, 120]);
+        let flags: Vec<(bool, bool)> = fragments.iter().map(|f| f.flags()).collect();
+        assert_eq!(flags, vec![(false, true), (false, true), (false, false)]);
+
+        let mut reassembled = vec![];
+        for mut fragment in fragments {
+            assert!(fragment.validate_checksum());
+            assert_eq!(fragment.indentification(), 0x1234);
+            reassembled.extend_from_slice(&fragment.payload());
+        }
+        assert_eq!(reassembled, payload);
+    }
+
+    #[test]
+    fn fragments_fragments() {
+        let mut packet = ipv4_packet(&payload(200));
+        packet.set_fragment_offset(10);
+        packet.set_flags(false, true);
+
+        let fragments = Fragment::new(124).process(packet).unwrap();
+        let offsets: Vec<u16> = fragments.iter().map(|f| f.fragment_offset()).collect();
+        assert_eq!(offsets, vec![10, 23]);
+        assert!(fragments.iter().all(|f| f.flags() == (false, true)));
+    }
+
+    #[test]
+    fn copies_options() {
+        let mut packet = ipv4_packet(&[]);
+        // Router Alert is copied, Record Route is not.
+        packet.set_options(&[0x94, 4, 0, 0, 0x07, 7, 4, 0, 0, 0, 0, 0]);
+        packet.set_payload(&payload(200));
+
+        let fragments = Fragment::new(100).process(packet).unwrap();
+        assert_eq!(fragments[0].ihl(), 8);
+        for fragment in &fragments[1..] {
+            assert_eq!(fragment.ihl(), 6);
+            assert_eq!(fragment.options().unwrap().as_ref(), &[0x94, 4, 0, 0][..]);
+        }
+        let payload_len: usize = fragments.iter().map(|f| f.payload().len()).sum();
+        assert_eq!(payload_len, 200);
+    }
+
+    #[test]
+    fn drops_dont_fragment() {
+        let mut packet = ipv4_packet(&payload(1000));
+        packet.set_flags(true, false);
+        assert_eq!(Fragment::new(500).process(packet), None);
+    }
+}
@@ -0,0 +1,269 @@
+use crate::processor::Processor;
+use route_rs_packets::{
+    GrePacket, Ipv4Packet, Ipv6Packet, PacketData, IPV4_ETHER_TYPE, IPV6_ETHER_TYPE,
+};
+use std::convert::TryFrom;
+use std::marker::PhantomData;
+use std::net::Ipv4Addr;
+
+/// TTL of the delivery packets we send.
+const DELIVERY_TTL: u8 = 64;
+const GRE_PROTOCOL: u8 = 47;
+
+/// Encapsulates IPv4 or IPv6 packets in GRE, in an IPv4 delivery packet from `src` to `dst`,
+/// the local and remote ends of the tunnel. The GRE header may carry a `key`, identifying the
+/// tunnel to the remote end, `sequence_numbers`, counting up from 0, and a `checksum`.
+pub struct GreEncap<Inner> {
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    key: Option<u32>,
+    next_sequence_number: Option<u32>,
+    checksum: bool,
+    phantom: PhantomData<Inner>,
+}
+
+impl<Inner> GreEncap<Inner> {
+    pub fn new(src: Ipv4Addr, dst: Ipv4Addr) -> Self {
+        GreEncap {
+            src,
+            dst,
+            key: None,
+            next_sequence_number: None,
+            checksum: false,
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn key(self, key: u32) -> Self {
+        GreEncap {
+            key: Some(key),
+            ..self
+        }
+    }
+
+    pub fn sequence_numbers(self) -> Self {
+        GreEncap {
+            next_sequence_number: Some(0),
+            ..self
+        }
+    }
+
+    pub fn checksum(self) -> Self {
+        GreEncap {
+            checksum: true,
+            ..self
+        }
+    }
+
+    fn encapsulate(&mut self, protocol_type: u16, inner: &[u8]) -> Ipv4Packet {
+        let mut gre = GrePacket::empty();
+        gre.set_protocol_type(protocol_type);
+        gre.set_key(self.key);
+        gre.set_sequence_number(self.next_sequence_number);
+        gre.set_payload(inner);
+        if self.checksum {
+            gre.set_checksum();
+        }
+        if let Some(sequence_number) = self.next_sequence_number {
+            self.next_sequence_number = Some(sequence_number.wrapping_add(1));
+        }
+
+        let mut delivery = Ipv4Packet::empty();
+        delivery.set_ttl(DELIVERY_TTL);
+        delivery.set_protocol(GRE_PROTOCOL);
+        delivery.set_src_addr(self.src);
+        delivery.set_dest_addr(self.dst);
+        delivery.set_payload(&gre.data);
+        delivery.set_checksum();
+        delivery
+    }
+}
+
+impl Processor for GreEncap<Ipv4Packet> {
+    type Input = Ipv4Packet;
+    type Output = Ipv4Packet;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        Some(self.encapsulate(IPV4_ETHER_TYPE, &packet.data[packet.layer3_offset..]))
+    }
+}
+
+impl Processor for GreEncap<Ipv6Packet> {
+    type Input = Ipv6Packet;
+    type Output = Ipv4Packet;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        Some(self.encapsulate(IPV6_ETHER_TYPE, &packet.data[packet.layer3_offset..]))
+    }
+}
+
+/// Decapsulates IPv4 or IPv6 packets from GRE delivery packets, for the end of a tunnel.
+/// Packets that aren't GRE, carry another protocol, have the wrong checksum, or, if a `key` is
+/// set, another key, are dropped. With `sequence_numbers`, packets without one, or that arrive
+/// out of order, are dropped too.
+pub struct GreDecap<Inner> {
+    key: Option<u32>,
+    sequence_numbers: bool,
+    last_sequence_number: Option<u32>,
+    phantom: PhantomData<Inner>,
+}
+
+impl<Inner> Default for GreDecap<Inner> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Inner> GreDecap<Inner> {
+    pub fn new() -> Self {
+        GreDecap {
+            key: None,
+            sequence_numbers: false,
+            last_sequence_number: None,
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn key(self, key: u32) -> Self {
+        GreDecap {
+            key: Some(key),
+            ..self
+        }
+    }
+
+    pub fn sequence_numbers(self) -> Self {
+        GreDecap {
+            sequence_numbers: true,
+            ..self
+        }
+    }
+
+    /// Returns the payload of the delivery packet, if it is one for this tunnel end.
+    fn decapsulate(&mut self, delivery: Ipv4Packet, protocol_type: u16) -> Option<PacketData> {
+        let mut gre = GrePacket::try_from(delivery).ok()?;
+        if gre.protocol_type() != protocol_type || !gre.validate_checksum() {
+            return None;
+        }
+        if self.key.is_some() && gre.key() != self.key {
+            return None;
+        }
+        if self.sequence_numbers {
+            let sequence_number = gre.sequence_number()?;
+            if let Some(last) = self.last_sequence_number {
+                // Later sequence numbers are less than 2^31 ahead, with wrapping, per RFC 1982.
+                let ahead = sequence_number.wrapping_sub(last);
+                if ahead == 0 || ahead >= 1 << 31 {
+                    return None;
+                }
+            }
+            self.last_sequence_number = Some(sequence_number);
+        }
+        Some(gre.data.split_off(gre.payload_offset))
+    }
+}
+
+impl Processor for GreDecap<Ipv4Packet> {
+    type Input = Ipv4Packet;
+    type Output = Ipv4Packet;
+
+    fn process(&mut self, delivery: Self::Input) -> Option<Self::Output> {
+        let inner = self.decapsulate(delivery, IPV4_ETHER_TYPE)?;
+        Ipv4Packet::from_buffer(inner, None, 0).ok()
+    }
+}
+
+impl Processor for GreDecap<Ipv6Packet> {
+    type Input = Ipv4Packet;
+    type Output = Ipv6Packet;
+
+    fn process(&mut self, delivery: Self::Input) -> Option<Self::Output> {
+        let inner = self.decapsulate(delivery, IPV6_ETHER_TYPE)?;
+        Ipv6Packet::from_buffer(inner, None, 0).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCAL: Ipv4Addr = Ipv4Addr::new(203, 0, 113, 1);
+    const REMOTE: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 2);
+
+    fn inner() -> Ipv4Packet {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_ttl(64);
+        packet.set_protocol(17);
+        packet.set_src_addr(Ipv4Addr::new(10, 0, 0, 1));
+        packet.set_dest_addr(Ipv4Addr::new(10, 0, 1, 1));
+        packet.set_payload(&[1, 2, 3, 4]);
+        packet.set_checksum();
+        packet
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut encap = GreEncap::<Ipv4Packet>::new(LOCAL, REMOTE)
+            .key(7)
+            .sequence_numbers()
+            .checksum();
+        let mut delivery = encap.process(inner()).unwrap();
+        assert!(delivery.validate_checksum());
+        assert_eq!(delivery.src_addr(), LOCAL);
+        assert_eq!(delivery.dest_addr(), REMOTE);
+
+        let gre = GrePacket::try_from(delivery.clone()).unwrap();
+        assert_eq!(gre.key(), Some(7));
+        assert_eq!(gre.sequence_number(), Some(0));
+        assert!(gre.checksum().is_some());
+
+        let mut decap = GreDecap::<Ipv4Packet>::new().key(7).sequence_numbers();
+        assert_eq!(decap.process(delivery), Some(inner()));
+
+        let next = encap.process(inner()).unwrap();
+        let gre = GrePacket::try_from(next.clone()).unwrap();
+        assert_eq!(gre.sequence_number(), Some(1));
+        assert_eq!(decap.process(next.clone()), Some(inner()));
+        // A replayed packet is out of order.
+        assert_eq!(decap.process(next), None);
+    }
+
+    #[test]
+    fn drops_other_tunnels() {
+        let delivery = GreEncap::<Ipv4Packet>::new(LOCAL, REMOTE)
+            .key(7)
+            .process(inner())
+            .unwrap();
+        assert_eq!(
+            GreDecap::<Ipv4Packet>::new()
+                .key(8)
+                .process(delivery.clone()),
+            None
+        );
+        assert_eq!(
+            GreDecap::<Ipv6Packet>::new().process(delivery.clone()),
+            None
+        );
+        assert_eq!(
+            GreDecap::<Ipv4Packet>::new()
+                .sequence_numbers()
+                .process(delivery),
+            None
+        );
+        assert_eq!(GreDecap::<Ipv4Packet>::new().process(inner()), None);
+    }
+
+    #[test]
+    fn carries_ipv6() {
+        let mut packet = Ipv6Packet::empty();
+        packet.set_hop_limit(64);
+        packet.set_payload(&[9; 10]);
+
+        let delivery = GreEncap::<Ipv6Packet>::new(LOCAL, REMOTE)
+            .process(packet.clone())
+            .unwrap();
+        assert_eq!(
+            GreDecap::<Ipv6Packet>::new().process(delivery),
+            Some(packet)
+        );
+    }
+}
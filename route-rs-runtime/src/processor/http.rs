@@ -0,0 +1,177 @@
+use crate::classifier::tcp_payload;
+use crate::processor::Processor;
+use route_rs_packets::EthernetFrame;
+
+/// Methods a plaintext HTTP request may start with.
+const HTTP_METHODS: [&str; 9] = [
+    "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
+];
+
+/// The start of a plaintext HTTP request: its method, and the host it's for, if it names one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpRequest {
+    pub method: String,
+    pub host: Option<String>,
+}
+
+/// An Ethernet frame, tagged with the HTTP request it starts, if it starts one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HttpFrame {
+    pub request: Option<HttpRequest>,
+    pub frame: EthernetFrame,
+}
+
+/// The host of an authority, `host[:port]`, lowercased, without the port or a trailing dot.
+fn authority_host(authority: &str) -> Option<String> {
+    let host = if authority.starts_with('[') {
+        // An IPv6 literal, which has colons of its own.
+        &authority[..=authority.find(']')?]
+    } else {
+        authority.split(':').next().unwrap()
+    };
+    let host = host.trim_end_matches('.');
+    if host.is_empty() {
+        return None;
+    }
+    Some(host.to_ascii_lowercase())
+}
+
+/// Returns the method and host of the HTTP request `payload`, a TCP payload, starts with, if it
+/// starts with one. The host is taken from an absolute request target, as proxies are sent, or
+/// else from the Host header, if it's in `payload`.
+pub fn http_request(payload: &[u8]) -> Option<HttpRequest> {
+    // Headers are ASCII, so anything else ends what we look at.
+    let text = match std::str::from_utf8(payload) {
+        Ok(text) => text,
+        Err(error) => std::str::from_utf8(&payload[..error.valid_up_to()]).unwrap(),
+    };
+    let mut lines = text.split("\r\n");
+
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next()?;
+    if !HTTP_METHODS.contains(&method) {
+        return None;
+    }
+    let target = request_line.next()?;
+    if !request_line.next()?.starts_with("HTTP/") {
+        return None;
+    }
+
+    let target_host = if method == "CONNECT" {
+        authority_host(target)
+    } else {
+        let lowercase = target.to_ascii_lowercase();
+        ["http://", "https://"]
+            .iter()
+            .find(|scheme| lowercase.starts_with(*scheme))
+            .and_then(|scheme| authority_host(target[scheme.len()..].split('/').next().unwrap()))
+    };
+    let host = target_host.or_else(|| {
+        lines
+            .take_while(|line| !line.is_empty())
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.eq_ignore_ascii_case("host"))
+            .and_then(|(_, value)| authority_host(value.trim()))
+    });
+
+    Some(HttpRequest {
+        method: method.to_string(),
+        host,
+    })
+}
+
+/// Tags frames with the plaintext HTTP request they start, if any, parsing only its request line
+/// and headers, for downstream policy such as an HttpHost classifier. Frames that don't start a
+/// request, including all but the first segment of each, are tagged with None.
+#[derive(Default)]
+pub struct HttpParse;
+
+impl HttpParse {
+    pub fn new() -> Self {
+        HttpParse
+    }
+}
+
+impl Processor for HttpParse {
+    type Input = EthernetFrame;
+    type Output = HttpFrame;
+
+    fn process(&mut self, frame: Self::Input) -> Option<Self::Output> {
+        let request = tcp_payload(&frame).and_then(http_request);
+        Some(HttpFrame { request, frame })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use route_rs_packets::{Ipv4Packet, TcpSegment};
+
+    fn request(method: &str, host: Option<&str>) -> Option<HttpRequest> {
+        Some(HttpRequest {
+            method: method.to_string(),
+            host: host.map(str::to_string),
+        })
+    }
+
+    #[test]
+    fn parses_method_and_host() {
+        assert_eq!(
+            http_request(b"GET /index.html HTTP/1.1\r\nUser-Agent: test\r\nHOST: Www.Example.com.:8080\r\n\r\n"),
+            request("GET", Some("www.example.com"))
+        );
+        assert_eq!(
+            http_request(b"POST /form HTTP/1.0\r\nHost: [2001:db8::1]:80\r\n"),
+            request("POST", Some("[2001:db8::1]"))
+        );
+        // Without a Host header, as in HTTP/1.0, or with it in a later segment.
+        assert_eq!(
+            http_request(b"GET / HTTP/1.0\r\n\r\nHost: body.example.com\r\n"),
+            request("GET", None)
+        );
+        assert_eq!(
+            http_request(b"HEAD / HTTP/1.1\r\nAcc"),
+            request("HEAD", None)
+        );
+    }
+
+    #[test]
+    fn prefers_absolute_targets() {
+        assert_eq!(
+            http_request(b"GET http://proxied.example.com/a HTTP/1.1\r\nHost: other\r\n\r\n"),
+            request("GET", Some("proxied.example.com"))
+        );
+        assert_eq!(
+            http_request(b"CONNECT tunnel.example.com:443 HTTP/1.1\r\n\r\n"),
+            request("CONNECT", Some("tunnel.example.com"))
+        );
+    }
+
+    #[test]
+    fn ignores_other_payloads() {
+        assert_eq!(http_request(b""), None);
+        assert_eq!(http_request(b"HTTP/1.1 200 OK\r\nHost: x\r\n"), None);
+        assert_eq!(http_request(b"get / HTTP/1.1\r\n"), None);
+        assert_eq!(http_request(b"GET /\r\n"), None);
+        assert_eq!(http_request(&[0x16, 0x03, 0x01, 0x00]), None);
+    }
+
+    #[test]
+    fn tags_frames() {
+        let mut segment = TcpSegment::empty();
+        segment.set_payload(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n");
+        let mut packet = Ipv4Packet::empty();
+        packet.set_protocol(6);
+        packet.set_payload(&segment.data[segment.layer4_offset..]);
+        let frame = EthernetFrame::encap_ipv4(packet);
+
+        let mut parse = HttpParse::new();
+        let tagged = parse.process(frame.clone()).unwrap();
+        assert_eq!(tagged.request, request("GET", Some("example.com")));
+        assert_eq!(tagged.frame, frame);
+
+        let mut frame = frame;
+        frame.set_ether_type(0x0806);
+        assert_eq!(parse.process(frame).unwrap().request, None);
+    }
+}
@@ -0,0 +1,260 @@
+use crate::classifier::Classifier;
+use crate::link::primitive::{ClassifyLink, ProcessLink};
+use crate::link::{Link, LinkBuilder, PacketStream, ProcessLinkBuilder};
+use crate::processor::Processor;
+use route_rs_packets::{update_u16, EthernetFrame, IPV4_ETHER_TYPE, IPV6_ETHER_TYPE};
+use std::convert::TryInto;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// TTL, or hop limit, of the replies we send.
+const REPLY_TTL: u8 = 64;
+
+/// ICMP and ICMPv6 protocol numbers, and their echo message types.
+const ICMP_PROTOCOL: u8 = 1;
+const ICMPV6_NEXT_HEADER: u8 = 58;
+const ECHO_REQUEST: u8 = 8;
+const ECHO_REPLY: u8 = 0;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+/// Where the IP header of the frame is, and where its ICMP message is, if the frame is an echo
+/// request to one of `addresses`. Fragmented requests, and IPv6 requests behind extension
+/// headers, aren't recognised.
+fn echo_request(frame: &EthernetFrame, addresses: &[IpAddr]) -> Option<(usize, usize)> {
+    let ip = frame.payload_offset;
+    let data = &frame.data;
+    let (dst, icmp, request) = match frame.ether_type() {
+        IPV4_ETHER_TYPE => {
+            let header = data.get(ip..ip + 20)?;
+            let more_fragments = header[6] & 0x20 != 0;
+            let fragment_offset = u16::from_be_bytes([header[6] & 0x1F, header[7]]);
+            if header[9] != ICMP_PROTOCOL || more_fragments || fragment_offset != 0 {
+                return None;
+            }
+            let dst: [u8; 4] = header[16..20].try_into().unwrap();
+            let ihl = usize::from(header[0] & 0x0F) * 4;
+            (IpAddr::from(Ipv4Addr::from(dst)), ip + ihl, ECHO_REQUEST)
+        }
+        IPV6_ETHER_TYPE => {
+            let header = data.get(ip..ip + 40)?;
+            if header[6] != ICMPV6_NEXT_HEADER {
+                return None;
+            }
+            let dst: [u8; 16] = header[24..40].try_into().unwrap();
+            (
+                IpAddr::from(Ipv6Addr::from(dst)),
+                ip + 40,
+                ICMPV6_ECHO_REQUEST,
+            )
+        }
+        _ => return None,
+    };
+
+    // Echo messages have a type, code, checksum, identifier and sequence number.
+    if data.len() < icmp + 8 || data[icmp] != request || data[icmp + 1] != 0 {
+        return None;
+    }
+    if !addresses.contains(&dst) {
+        return None;
+    }
+    Some((ip, icmp))
+}
+
+/// Classifies frames by whether they're ICMP or ICMPv6 echo requests to one of `addresses`, the
+/// router's own.
+pub struct IcmpEchoRequest {
+    addresses: Vec<IpAddr>,
+}
+
+impl IcmpEchoRequest {
+    pub fn new(addresses: Vec<IpAddr>) -> Self {
+        IcmpEchoRequest { addresses }
+    }
+}
+
+impl Classifier for IcmpEchoRequest {
+    type Packet = EthernetFrame;
+    type Class = bool;
+
+    fn classify(&self, frame: &Self::Packet) -> Self::Class {
+        echo_request(frame, &self.addresses).is_some()
+    }
+}
+
+/// Answers ICMP and ICMPv6 echo requests to one of `addresses`, the router's own, turning each
+/// into its echo reply: the MAC and IP addresses are swapped, the TTL reset, and the checksums
+/// updated. Anything else is dropped.
+pub struct IcmpEchoResponder {
+    addresses: Vec<IpAddr>,
+}
+
+impl IcmpEchoResponder {
+    pub fn new(addresses: Vec<IpAddr>) -> Self {
+        IcmpEchoResponder { addresses }
+    }
+}
+
+impl Processor for IcmpEchoResponder {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, mut frame: Self::Input) -> Option<Self::Output> {
+        let (ip, icmp) = echo_request(&frame, &self.addresses)?;
+
+        let src_mac = frame.src_mac();
+        let dest_mac = frame.dest_mac();
+        frame.set_src_mac(dest_mac);
+        frame.set_dest_mac(src_mac);
+
+        let data = &mut frame.data;
+        let reply = if data[ip] >> 4 == 4 {
+            let (src, dst) = (ip + 12, ip + 16);
+            let src_addr: [u8; 4] = data[src..src + 4].try_into().unwrap();
+            data.copy_within(dst..dst + 4, src);
+            data[dst..dst + 4].copy_from_slice(&src_addr);
+
+            // Swapping the addresses leaves the header checksum as it was, only the TTL changes.
+            // It shares its 16 bit word of the header with the protocol.
+            let old_word = u16::from_be_bytes([data[ip + 8], data[ip + 9]]);
+            let new_word = u16::from_be_bytes([REPLY_TTL, data[ip + 9]]);
+            let checksum = u16::from_be_bytes([data[ip + 10], data[ip + 11]]);
+            let checksum = update_u16(checksum, old_word, new_word);
+            data[ip + 8] = REPLY_TTL;
+            data[ip + 10..ip + 12].copy_from_slice(&checksum.to_be_bytes());
+            ECHO_REPLY
+        } else {
+            let (src, dst) = (ip + 8, ip + 24);
+            let src_addr: [u8; 16] = data[src..src + 16].try_into().unwrap();
+            data.copy_within(dst..dst + 16, src);
+            data[dst..dst + 16].copy_from_slice(&src_addr);
+            data[ip + 7] = REPLY_TTL;
+            ICMPV6_ECHO_REPLY
+        };
+
+        // The ICMPv6 checksum covers the addresses too, but their sum is the same once swapped, so
+        // only the type changes. It shares its 16 bit word of the message with the code.
+        let old_word = u16::from_be_bytes([data[icmp], data[icmp + 1]]);
+        let new_word = u16::from_be_bytes([reply, data[icmp + 1]]);
+        let checksum = u16::from_be_bytes([data[icmp + 2], data[icmp + 3]]);
+        let checksum = update_u16(checksum, old_word, new_word);
+        data[icmp] = reply;
+        data[icmp + 2..icmp + 4].copy_from_slice(&checksum.to_be_bytes());
+        Some(frame)
+    }
+}
+
+/// Answers echo requests in `stream` to one of `addresses`, the router's own. Other frames pass
+/// through to the first egressor, and the replies are sent out the second.
+pub fn icmp_echo_link(
+    stream: PacketStream<EthernetFrame>,
+    addresses: Vec<IpAddr>,
+) -> Link<EthernetFrame> {
+    let (mut runnables, mut egressors) = ClassifyLink::new()
+        .ingressor(stream)
+        .num_egressors(2)
+        .classifier(IcmpEchoRequest::new(addresses.clone()))
+        .dispatcher(Box::new(|is_request| if is_request { 1 } else { 0 }))
+        .build_link();
+
+    let (reply_runnables, reply_egressors) = ProcessLink::new()
+        .ingressor(egressors.pop().unwrap())
+        .processor(IcmpEchoResponder::new(addresses))
+        .build_link();
+    runnables.extend(reply_runnables);
+    egressors.extend(reply_egressors);
+    (runnables, egressors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use route_rs_packets::{internet_checksum, Ipv4Packet, Ipv6Packet, MacAddr};
+    use std::convert::TryFrom;
+
+    const ROUTER: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 1);
+    const HOST: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 20);
+    const ROUTER_MAC: MacAddr = MacAddr {
+        bytes: [2, 0, 0, 0, 0, 1],
+    };
+    const HOST_MAC: MacAddr = MacAddr {
+        bytes: [2, 0, 0, 0, 0, 20],
+    };
+
+    fn echo(icmp_type: u8) -> Vec<u8> {
+        let mut message = vec![icmp_type, 0, 0, 0, 0x12, 0x34, 0, 1, 0xde, 0xad];
+        let checksum = internet_checksum(&[&message]);
+        message[2..4].copy_from_slice(&checksum.to_be_bytes());
+        message
+    }
+
+    fn ping(dst: Ipv4Addr) -> EthernetFrame {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_ttl(60);
+        packet.set_protocol(ICMP_PROTOCOL);
+        packet.set_src_addr(HOST);
+        packet.set_dest_addr(dst);
+        packet.set_payload(&echo(ECHO_REQUEST));
+        packet.set_checksum();
+        let mut frame = EthernetFrame::encap_ipv4(packet);
+        frame.set_src_mac(HOST_MAC);
+        frame.set_dest_mac(ROUTER_MAC);
+        frame
+    }
+
+    #[test]
+    fn replies_to_ipv4_echo() {
+        let mut responder = IcmpEchoResponder::new(vec![ROUTER.into()]);
+        let reply = responder.process(ping(ROUTER)).unwrap();
+        assert_eq!(reply.src_mac(), ROUTER_MAC);
+        assert_eq!(reply.dest_mac(), HOST_MAC);
+
+        let mut packet = Ipv4Packet::try_from(reply).unwrap();
+        assert_eq!(packet.src_addr(), ROUTER);
+        assert_eq!(packet.dest_addr(), HOST);
+        assert_eq!(packet.ttl(), REPLY_TTL);
+        assert!(packet.validate_checksum());
+        assert_eq!(packet.payload().as_ref(), &echo(ECHO_REPLY)[..]);
+
+        assert_eq!(responder.process(ping(HOST)), None);
+    }
+
+    #[test]
+    fn replies_to_ipv6_echo() {
+        let router = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        let host = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 20);
+
+        let mut packet = Ipv6Packet::empty();
+        packet.set_next_header(ICMPV6_NEXT_HEADER);
+        packet.set_hop_limit(255);
+        packet.set_src_addr(host);
+        packet.set_dest_addr(router);
+        packet.set_payload(&echo(ICMPV6_ECHO_REQUEST));
+        let request = EthernetFrame::encap_ipv6(packet);
+
+        let reply = IcmpEchoResponder::new(vec![router.into()])
+            .process(request)
+            .unwrap();
+        let packet = Ipv6Packet::try_from(reply).unwrap();
+        assert_eq!(packet.src_addr(), router);
+        assert_eq!(packet.dest_addr(), host);
+        assert_eq!(packet.hop_limit(), REPLY_TTL);
+        // The test message is summed without the pseudo header, but updates keep it matching.
+        assert_eq!(packet.payload().as_ref(), &echo(ICMPV6_ECHO_REPLY)[..]);
+    }
+
+    #[test]
+    fn icmp_echo_link_splits_replies() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let packets = vec![ping(ROUTER), ping(HOST), ping(ROUTER)];
+            let link = icmp_echo_link(immediate_stream(packets), vec![ROUTER.into()]);
+            run_link(link).await
+        });
+
+        assert_eq!(results[0], vec![ping(HOST)]);
+        assert_eq!(results[1].len(), 2);
+        assert!(results[1].iter().all(|reply| reply.dest_mac() == HOST_MAC));
+    }
+}
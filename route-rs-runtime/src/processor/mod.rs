@@ -7,6 +7,8 @@
 //! While there are many provided processors that can be used to implement a router, users of route-rs that need specifc functionality
 //! in their router most likely will implement their own custom processors, conforming to the laid out processor standard.
 
+use futures::Future;
+
 mod identity;
 pub use self::identity::*;
 
@@ -19,15 +21,166 @@ pub use self::drop::*;
 mod dec_ip_hop;
 pub use self::dec_ip_hop::*;
 
+mod decrement_ttl;
+pub use self::decrement_ttl::*;
+
+mod checksum;
+pub use self::checksum::*;
+
+mod replay_window;
+pub(crate) use self::replay_window::*;
+
+mod esp;
+pub use self::esp::*;
+
+mod fragment;
+pub use self::fragment::*;
+
+mod mtu_check;
+pub use self::mtu_check::*;
+
+mod mss_clamp;
+pub use self::mss_clamp::*;
+
+mod ds_field;
+pub use self::ds_field::*;
+
+mod gre;
+pub use self::gre::*;
+
+mod vxlan;
+pub use self::vxlan::*;
+
+mod mpls;
+pub use self::mpls::*;
+
+mod icmp_echo;
+pub use self::icmp_echo::*;
+
+mod ndp;
+pub use self::ndp::*;
+
+mod arp;
+pub use self::arp::*;
+
+mod dhcp_client;
+pub use self::dhcp_client::*;
+
+mod dhcp_server;
+pub use self::dhcp_server::*;
+
+mod dns_cache;
+pub use self::dns_cache::*;
+
+mod http;
+pub use self::http::*;
+
+mod vlan;
+pub use self::vlan::*;
+
+mod wireguard;
+pub use self::wireguard::*;
+
+mod nat44;
+pub use self::nat44::*;
+
 mod log;
 pub use self::log::*;
 
 mod file_log;
 pub use self::file_log::*;
 
+mod stage_latency;
+pub use self::stage_latency::*;
+
+mod sequencer;
+pub use self::sequencer::*;
+
+mod annotation;
+pub use self::annotation::*;
+
+mod trace;
+pub use self::trace::*;
+
+mod combinators;
+pub use self::combinators::*;
+
+mod closure;
+pub use self::closure::*;
+
 pub trait Processor {
     type Input: Send + Clone;
     type Output: Send + Clone;
 
     fn process(&mut self, packet: Self::Input) -> Option<Self::Output>;
+
+    /// Processes every packet in `packets`, leaving it empty so the link can reuse it for the
+    /// next batch. Links call this, rather than `process`, with whatever packets their input has
+    /// ready, so a processor may override it to work on many packets at once, for instance
+    /// updating checksums in bulk. The default processes each packet in turn.
+    fn process_batch(&mut self, packets: &mut Vec<Self::Input>) -> Vec<Self::Output> {
+        packets
+            .drain(..)
+            .filter_map(|packet| self.process(packet))
+            .collect()
+    }
+
+    /// Runs the packets this processor outputs through `next`, as one processor.
+    fn chain<Next>(self, next: Next) -> Chain<Self, Next>
+    where
+        Self: Sized,
+        Next: Processor<Input = Self::Output>,
+    {
+        Chain::new(self, next)
+    }
+
+    /// Transforms the packets this processor outputs with `f`.
+    fn map<Output, F>(self, f: F) -> Map<Self, F>
+    where
+        Self: Sized,
+        Output: Send + Clone,
+        F: FnMut(Self::Output) -> Output,
+    {
+        Map::new(self, f)
+    }
+
+    /// Drops the packets this processor outputs for which `predicate` returns false.
+    fn filter<F>(self, predicate: F) -> Filter<Self, F>
+    where
+        Self: Sized,
+        F: FnMut(&Self::Output) -> bool,
+    {
+        Filter::new(self, predicate)
+    }
+
+    /// Calls `f` with each packet this processor outputs, before passing it on.
+    fn inspect<F>(self, f: F) -> Inspect<Self, F>
+    where
+        Self: Sized,
+        F: FnMut(&Self::Output),
+    {
+        Inspect::new(self, f)
+    }
+}
+
+/// Like Processor, but processing a packet may await, for instance on a lookup against a remote
+/// store. `process` returns a future that owns everything it needs, so several packets may be
+/// in flight at once; an AsyncProcessLink drives them.
+pub trait AsyncProcessor {
+    type Input: Send + Clone;
+    type Output: Send + Clone;
+    type Future: Future<Output = Option<Self::Output>> + Send + 'static;
+
+    fn process(&mut self, packet: Self::Input) -> Self::Future;
+}
+
+/// Like Processor, but processing may fail, for instance when parsing a malformed packet. A
+/// TryProcessLink sends the input that failed, and the error, to its error egressor, rather than
+/// the failure being a silent drop. Returning `Ok(None)` still drops the packet quietly.
+pub trait TryProcessor {
+    type Input: Send + Clone;
+    type Output: Send + Clone;
+    type Error: Send;
+
+    fn try_process(&mut self, packet: Self::Input) -> Result<Option<Self::Output>, Self::Error>;
 }
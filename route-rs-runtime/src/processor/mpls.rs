@@ -0,0 +1,238 @@
+use crate::processor::Processor;
+use route_rs_packets::{update_u16, EthernetFrame, MplsLabel, IPV4_ETHER_TYPE, IPV6_ETHER_TYPE};
+
+/// Where the TTL, or hop limit, is in the IPv4 and IPv6 headers.
+const IPV4_TTL_OFFSET: usize = 8;
+const IPV6_HOP_LIMIT_OFFSET: usize = 7;
+const IPV4_CHECKSUM_OFFSET: usize = 10;
+
+/// Returns the TTL of the IP packet carried by an unlabelled frame, if it carries one.
+fn ip_ttl(frame: &EthernetFrame) -> Option<u8> {
+    let offset = match frame.ether_type() {
+        IPV4_ETHER_TYPE => IPV4_TTL_OFFSET,
+        IPV6_ETHER_TYPE => IPV6_HOP_LIMIT_OFFSET,
+        _ => return None,
+    };
+    frame.data.get(frame.payload_offset + offset).copied()
+}
+
+/// Sets the TTL of the IP packet carried by an unlabelled frame, updating the IPv4 checksum.
+fn set_ip_ttl(frame: &mut EthernetFrame, ttl: u8) {
+    let ip = frame.payload_offset;
+    match frame.ether_type() {
+        IPV4_ETHER_TYPE => {
+            // The TTL shares its 16 bit word of the header with the protocol.
+            let protocol = frame.data[ip + 9];
+            let old_word = u16::from_be_bytes([frame.data[ip + IPV4_TTL_OFFSET], protocol]);
+            let new_word = u16::from_be_bytes([ttl, protocol]);
+            let checksum_offset = ip + IPV4_CHECKSUM_OFFSET;
+            let checksum =
+                u16::from_be_bytes([frame.data[checksum_offset], frame.data[checksum_offset + 1]]);
+            let checksum = update_u16(checksum, old_word, new_word);
+            frame.data[ip + IPV4_TTL_OFFSET] = ttl;
+            frame.data[checksum_offset..checksum_offset + 2]
+                .copy_from_slice(&checksum.to_be_bytes());
+        }
+        IPV6_ETHER_TYPE => frame.data[ip + IPV6_HOP_LIMIT_OFFSET] = ttl,
+        _ => (),
+    }
+}
+
+/// Pushes `label` onto the label stack of frames, as they enter a label switched path. The new
+/// entry's TTL is copied from the top label, or the TTL of the IP packet of unlabelled frames,
+/// unless a fixed one is set with `ttl`. Frames carrying neither get a TTL of 255.
+pub struct MplsPush {
+    label: u32,
+    tc: u8,
+    ttl: Option<u8>,
+}
+
+impl MplsPush {
+    pub fn new(label: u32) -> Self {
+        assert!(label < 1 << 20, "MplsPush label: {}, must be < 2^20", label);
+
+        MplsPush {
+            label,
+            tc: 0,
+            ttl: None,
+        }
+    }
+
+    pub fn tc(self, tc: u8) -> Self {
+        assert!(tc < 8, "MplsPush tc: {}, must be < 8", tc);
+
+        MplsPush { tc, ..self }
+    }
+
+    pub fn ttl(self, ttl: u8) -> Self {
+        MplsPush {
+            ttl: Some(ttl),
+            ..self
+        }
+    }
+}
+
+impl Processor for MplsPush {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, mut frame: Self::Input) -> Option<Self::Output> {
+        let ttl = self.ttl.unwrap_or_else(|| match frame.mpls_label() {
+            Some(top) => top.ttl,
+            None => ip_ttl(&frame).unwrap_or(255),
+        });
+        frame.push_mpls_label(MplsLabel {
+            label: self.label,
+            tc: self.tc,
+            bos: false,
+            ttl,
+        });
+        Some(frame)
+    }
+}
+
+/// Swaps the top label of frames for `label`, decrementing its TTL, as a label switching router
+/// forwards them. Frames that aren't MPLS, or whose TTL expires, are dropped.
+pub struct MplsSwap {
+    label: u32,
+}
+
+impl MplsSwap {
+    pub fn new(label: u32) -> Self {
+        assert!(label < 1 << 20, "MplsSwap label: {}, must be < 2^20", label);
+
+        MplsSwap { label }
+    }
+}
+
+impl Processor for MplsSwap {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, mut frame: Self::Input) -> Option<Self::Output> {
+        let top = frame.mpls_label()?;
+        if top.ttl <= 1 {
+            return None;
+        }
+        frame
+            .set_mpls_label(MplsLabel {
+                label: self.label,
+                ttl: top.ttl - 1,
+                ..top
+            })
+            .ok()?;
+        Some(frame)
+    }
+}
+
+/// Pops the top label of frames, as they leave a label switched path. The TTL is decremented
+/// and propagated to the label beneath, or the IP packet of the bottom label, if lower than
+/// theirs, so hops in the path still count toward the packet's TTL. Frames that aren't MPLS, whose
+/// TTL expires, or whose bottom label carries something other than IP, are dropped.
+#[derive(Default)]
+pub struct MplsPop {}
+
+impl MplsPop {
+    pub fn new() -> Self {
+        MplsPop {}
+    }
+}
+
+impl Processor for MplsPop {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, mut frame: Self::Input) -> Option<Self::Output> {
+        let popped = frame.pop_mpls_label()?;
+        if popped.ttl <= 1 {
+            return None;
+        }
+        let ttl = popped.ttl - 1;
+
+        match frame.mpls_label() {
+            Some(next) if next.ttl > ttl => {
+                frame.set_mpls_label(MplsLabel { ttl, ..next }).ok()?;
+            }
+            Some(_) => (),
+            None => {
+                if ip_ttl(&frame)? > ttl {
+                    set_ip_ttl(&mut frame, ttl);
+                }
+            }
+        }
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use route_rs_packets::Ipv4Packet;
+    use std::convert::TryFrom;
+    use std::net::Ipv4Addr;
+
+    fn frame(ttl: u8) -> EthernetFrame {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_ttl(ttl);
+        packet.set_protocol(17);
+        packet.set_src_addr(Ipv4Addr::new(10, 0, 0, 1));
+        packet.set_dest_addr(Ipv4Addr::new(10, 0, 1, 1));
+        packet.set_payload(&[1, 2, 3, 4]);
+        packet.set_checksum();
+        EthernetFrame::encap_ipv4(packet)
+    }
+
+    #[test]
+    fn push_copies_ttl() {
+        let labelled = MplsPush::new(100).tc(5).process(frame(64)).unwrap();
+        let top = labelled.mpls_label().unwrap();
+        assert_eq!((top.label, top.tc, top.bos, top.ttl), (100, 5, true, 64));
+
+        let labelled = MplsPush::new(200).process(labelled).unwrap();
+        let labels = labelled.mpls_labels();
+        assert_eq!(
+            (labels[0].label, labels[0].bos, labels[0].ttl),
+            (200, false, 64)
+        );
+
+        let labelled = MplsPush::new(300).ttl(255).process(frame(64)).unwrap();
+        assert_eq!(labelled.mpls_label().unwrap().ttl, 255);
+    }
+
+    #[test]
+    fn swap_decrements_ttl() {
+        let labelled = MplsPush::new(100).tc(3).process(frame(64)).unwrap();
+        let swapped = MplsSwap::new(200).process(labelled).unwrap();
+        let top = swapped.mpls_label().unwrap();
+        assert_eq!((top.label, top.tc, top.bos, top.ttl), (200, 3, true, 63));
+
+        let expiring = MplsPush::new(100).ttl(1).process(frame(64)).unwrap();
+        assert_eq!(MplsSwap::new(200).process(expiring), None);
+        assert_eq!(MplsSwap::new(200).process(frame(64)), None);
+    }
+
+    #[test]
+    fn pop_propagates_ttl() {
+        let labelled = MplsPush::new(100).ttl(10).process(frame(64)).unwrap();
+        let popped = MplsPop::new().process(labelled).unwrap();
+        assert_eq!(popped.mpls_labels(), vec![]);
+        let mut packet = Ipv4Packet::try_from(popped).unwrap();
+        assert_eq!(packet.ttl(), 9);
+        assert!(packet.validate_checksum());
+
+        // The TTL only ever goes down.
+        let labelled = MplsPush::new(100).ttl(255).process(frame(64)).unwrap();
+        let popped = MplsPop::new().process(labelled).unwrap();
+        assert_eq!(Ipv4Packet::try_from(popped).unwrap().ttl(), 64);
+
+        let labelled = MplsPush::new(100).process(frame(64)).unwrap();
+        let labelled = MplsPush::new(200).ttl(20).process(labelled).unwrap();
+        let popped = MplsPop::new().process(labelled).unwrap();
+        let top = popped.mpls_label().unwrap();
+        assert_eq!((top.label, top.ttl), (100, 19));
+
+        let expiring = MplsPush::new(100).ttl(1).process(frame(64)).unwrap();
+        assert_eq!(MplsPop::new().process(expiring), None);
+        assert_eq!(MplsPop::new().process(frame(64)), None);
+    }
+}
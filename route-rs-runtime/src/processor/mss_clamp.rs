@@ -0,0 +1,203 @@
+use crate::processor::Processor;
+use route_rs_packets::{update_u16, update_u32, TcpSegment};
+use std::convert::TryInto;
+
+const TCP_SYN: u8 = 0x02;
+const MSS_OPTION: u8 = 2;
+const END_OF_OPTIONS: u8 = 0;
+const NO_OPERATION: u8 = 1;
+
+/// Where the clamped MSS comes from.
+enum Limit {
+    Mss(u16),
+    /// Derived from the egress MTU, less the IP and TCP headers.
+    Mtu(u16),
+}
+
+/// Lowers the Maximum Segment Size option of TCP SYN and SYN ACK segments to a limit, updating
+/// the TCP checksum, so connections through a link with a smaller MTU than their ends', like a
+/// PPPoE or tunnel link, never send segments too large for it. Segments with a smaller MSS, or
+/// none, are left alone, as are all other segments.
+pub struct MssClamp {
+    limit: Limit,
+}
+
+impl MssClamp {
+    /// Clamps the MSS to `mss`.
+    pub fn new(mss: u16) -> Self {
+        assert!(mss > 0, "MssClamp mss: {}, must be > 0", mss);
+
+        MssClamp {
+            limit: Limit::Mss(mss),
+        }
+    }
+
+    /// Clamps the MSS to what fits in `mtu`, without IP or TCP options: `mtu` less 40 bytes over
+    /// IPv4, or 60 over IPv6.
+    pub fn from_mtu(mtu: u16) -> Self {
+        assert!(mtu > 60, "MssClamp mtu: {}, must be > 60", mtu);
+
+        MssClamp {
+            limit: Limit::Mtu(mtu),
+        }
+    }
+
+    fn mss(&self, segment: &TcpSegment) -> u16 {
+        match self.limit {
+            Limit::Mss(mss) => mss,
+            Limit::Mtu(mtu) => {
+                let ipv6 = match segment.layer3_offset {
+                    Some(layer3_offset) => segment.data[layer3_offset] >> 4 == 6,
+                    None => false,
+                };
+                if ipv6 {
+                    mtu - 60
+                } else {
+                    mtu - 40
+                }
+            }
+        }
+    }
+}
+
+/// Returns where the value of the MSS option is, among the options of the segment.
+fn find_mss_option(segment: &TcpSegment) -> Option<usize> {
+    let options_end = segment.layer4_offset + usize::from(segment.data_offset()) * 4;
+    let mut i = segment.layer4_offset + 20;
+    while i < options_end.min(segment.data.len()) {
+        match segment.data[i] {
+            END_OF_OPTIONS => return None,
+            NO_OPERATION => i += 1,
+            kind => {
+                let length = usize::from(*segment.data.get(i + 1)?);
+                if length < 2 {
+                    return None;
+                }
+                if kind == MSS_OPTION && length == 4 && i + 4 <= options_end {
+                    return Some(i + 2);
+                }
+                i += length;
+            }
+        }
+    }
+    None
+}
+
+impl Processor for MssClamp {
+    type Input = TcpSegment;
+    type Output = TcpSegment;
+
+    fn process(&mut self, mut segment: Self::Input) -> Option<Self::Output> {
+        if segment.data[segment.layer4_offset + 13] & TCP_SYN == 0 {
+            return Some(segment);
+        }
+        let at = match find_mss_option(&segment) {
+            Some(at) => at,
+            None => return Some(segment),
+        };
+        let mss = self.mss(&segment);
+        if u16::from_be_bytes([segment.data[at], segment.data[at + 1]]) <= mss {
+            return Some(segment);
+        }
+
+        // The checksum is updated a 16 bit word at a time, and options needn't be word aligned, in
+        // which case the MSS straddles two words.
+        let checksum = if (at - segment.layer4_offset) % 2 == 0 {
+            let old = u16::from_be_bytes([segment.data[at], segment.data[at + 1]]);
+            update_u16(segment.checksum(), old, mss)
+        } else {
+            let words = |data: &[u8]| u32::from_be_bytes(data[at - 1..at + 3].try_into().unwrap());
+            let old = words(&segment.data);
+            segment.data[at..at + 2].copy_from_slice(&mss.to_be_bytes());
+            update_u32(segment.checksum(), old, words(&segment.data))
+        };
+        segment.data[at..at + 2].copy_from_slice(&mss.to_be_bytes());
+        segment.set_checksum(checksum);
+        Some(segment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::TcpChecksum;
+    use route_rs_packets::{Ipv4Packet, Ipv6Packet};
+    use std::convert::TryFrom;
+    use std::net::Ipv4Addr;
+
+    fn syn(flags: u8, options: &[u8]) -> Vec<u8> {
+        let mut tcp = vec![0; 20];
+        tcp[12] = (((20 + options.len()) / 4) << 4) as u8;
+        tcp[13] = flags;
+        tcp.extend_from_slice(options);
+        tcp
+    }
+
+    fn ipv4_segment(tcp: &[u8]) -> TcpSegment {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_ttl(64);
+        packet.set_protocol(6);
+        packet.set_src_addr(Ipv4Addr::new(192, 168, 1, 10));
+        packet.set_dest_addr(Ipv4Addr::new(198, 51, 100, 7));
+        packet.set_payload(tcp);
+        let segment = TcpSegment::try_from(packet).unwrap();
+        TcpChecksum::new().process(segment).unwrap()
+    }
+
+    fn mss(segment: &TcpSegment) -> u16 {
+        let at = find_mss_option(segment).unwrap();
+        u16::from_be_bytes([segment.data[at], segment.data[at + 1]])
+    }
+
+    fn valid(segment: &TcpSegment) -> bool {
+        TcpChecksum::new()
+            .validate()
+            .process(segment.clone())
+            .is_some()
+    }
+
+    #[test]
+    fn clamps_syn() {
+        let segment = ipv4_segment(&syn(TCP_SYN, &[2, 4, 0x05, 0xb4]));
+        let clamped = MssClamp::new(1412).process(segment).unwrap();
+        assert_eq!(mss(&clamped), 1412);
+        assert!(valid(&clamped));
+    }
+
+    #[test]
+    fn clamps_unaligned_option() {
+        // A No-Operation before the MSS option puts its value on an odd offset.
+        let segment = ipv4_segment(&syn(TCP_SYN | 0x10, &[1, 2, 4, 0x05, 0xb4, 1, 1, 0]));
+        let clamped = MssClamp::from_mtu(1492).process(segment).unwrap();
+        assert_eq!(mss(&clamped), 1452);
+        assert!(valid(&clamped));
+    }
+
+    #[test]
+    fn from_mtu_over_ipv6() {
+        let mut packet = Ipv6Packet::empty();
+        packet.set_next_header(6);
+        packet.set_src_addr("2001:db8::1".parse().unwrap());
+        packet.set_dest_addr("2001:db8::2".parse().unwrap());
+        packet.set_payload(&syn(TCP_SYN, &[2, 4, 0x05, 0xa0]));
+        let segment = TcpSegment::try_from(packet).unwrap();
+
+        let clamped = MssClamp::from_mtu(1280).process(segment).unwrap();
+        assert_eq!(mss(&clamped), 1220);
+    }
+
+    #[test]
+    fn leaves_others_alone() {
+        let small = ipv4_segment(&syn(TCP_SYN, &[2, 4, 0x02, 0x00]));
+        assert_eq!(MssClamp::new(1412).process(small.clone()), Some(small));
+
+        let no_option = ipv4_segment(&syn(TCP_SYN, &[]));
+        assert_eq!(
+            MssClamp::new(1412).process(no_option.clone()),
+            Some(no_option)
+        );
+
+        let not_syn = ipv4_segment(&syn(0x10, &[2, 4, 0x05, 0xb4]));
+        assert_eq!(MssClamp::new(1412).process(not_syn.clone()), Some(not_syn));
+    }
+}
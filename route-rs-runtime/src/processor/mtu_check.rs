@@ -0,0 +1,211 @@
+use crate::processor::TryProcessor;
+use route_rs_packets::{IcmpMessage, Icmpv6Message, IpProtocol, Ipv4Packet, Ipv6Packet};
+use std::marker::PhantomData;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// TTL, or hop limit, of the ICMP messages we send.
+const ICMP_TTL: u8 = 64;
+/// The Destination Unreachable code for Fragmentation Needed and Don't Fragment was Set.
+const ICMP_FRAGMENTATION_NEEDED: u8 = 4;
+/// The smallest MTU every IPv4 link must support, per RFC 791.
+const MIN_IPV4_MTU: usize = 68;
+/// The smallest MTU every IPv6 link must support, per RFC 8200.
+const MIN_IPV6_MTU: usize = 1280;
+
+/// Checks IP packets fit in `mtu`, the MTU of the link they're about to leave on, such as
+/// `ETHERNET_MTU`, or 9000 or so for jumbo frames. Packets that fit pass, as do oversized IPv4
+/// packets without Don't Fragment set, for a `Fragment` after this to break up. Oversized IPv4
+/// packets with Don't Fragment set fail with ICMP Fragmentation Needed, and oversized IPv6
+/// packets, which routers never fragment, with ICMPv6 Packet Too Big, either carrying the MTU
+/// and addressed back to the packet's source from `source`, the address of the router, so it
+/// can discover the path MTU. Run in a TryProcessLink, the messages come out of its error
+/// egressor. As with `DecrementTtl`, oversized ICMP errors are dropped without a word.
+pub struct MtuCheck<Packet> {
+    mtu: usize,
+    source: IpAddr,
+    phantom: PhantomData<Packet>,
+}
+
+impl MtuCheck<Ipv4Packet> {
+    pub fn new(mtu: usize, source: Ipv4Addr) -> Self {
+        assert!(
+            mtu >= MIN_IPV4_MTU,
+            "MtuCheck mtu: {}, must be >= {}",
+            mtu,
+            MIN_IPV4_MTU
+        );
+
+        MtuCheck {
+            mtu,
+            source: IpAddr::V4(source),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl MtuCheck<Ipv6Packet> {
+    pub fn new(mtu: usize, source: Ipv6Addr) -> Self {
+        assert!(
+            mtu >= MIN_IPV6_MTU,
+            "MtuCheck mtu: {}, must be >= {}",
+            mtu,
+            MIN_IPV6_MTU
+        );
+
+        MtuCheck {
+            mtu,
+            source: IpAddr::V6(source),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl TryProcessor for MtuCheck<Ipv4Packet> {
+    type Input = Ipv4Packet;
+    type Output = Ipv4Packet;
+    type Error = Ipv4Packet;
+
+    fn try_process(&mut self, packet: Ipv4Packet) -> Result<Option<Ipv4Packet>, Ipv4Packet> {
+        let (dont_fragment, _) = packet.flags();
+        if usize::from(packet.total_len()) <= self.mtu || !dont_fragment {
+            return Ok(Some(packet));
+        }
+
+        let is_icmp_error = packet.protocol() == IpProtocol::ICMP
+            && match packet.payload().first() {
+                // Echo and timestamp requests and replies are the only queries.
+                Some(icmp_type) => ![0, 8, 13, 14].contains(icmp_type),
+                None => false,
+            };
+        if is_icmp_error {
+            return Ok(None);
+        }
+
+        let source = match self.source {
+            IpAddr::V4(source) => source,
+            IpAddr::V6(_) => unreachable!("MtuCheck<Ipv4Packet> has an IPv4 source"),
+        };
+        let message = IcmpMessage::DestinationUnreachable {
+            code: ICMP_FRAGMENTATION_NEEDED,
+            next_hop_mtu: self.mtu.min(usize::from(u16::MAX)) as u16,
+            invoking_packet: &packet.data[packet.layer3_offset..],
+        };
+        Err(Ipv4Packet::builder()
+            .ttl(ICMP_TTL)
+            .src(source)
+            .dst(packet.src_addr())
+            .icmp(&message)
+            .build())
+    }
+}
+
+impl TryProcessor for MtuCheck<Ipv6Packet> {
+    type Input = Ipv6Packet;
+    type Output = Ipv6Packet;
+    type Error = Ipv6Packet;
+
+    fn try_process(&mut self, packet: Ipv6Packet) -> Result<Option<Ipv6Packet>, Ipv6Packet> {
+        if packet.data.len() - packet.layer3_offset <= self.mtu {
+            return Ok(Some(packet));
+        }
+
+        // ICMPv6 error messages have types below 128.
+        let is_icmp_error = packet.next_header() == IpProtocol::IPv6_ICMP
+            && match packet.data.get(packet.layer3_offset + 40) {
+                Some(icmp_type) => *icmp_type < 128,
+                None => false,
+            };
+        if is_icmp_error {
+            return Ok(None);
+        }
+
+        let source = match self.source {
+            IpAddr::V6(source) => source,
+            IpAddr::V4(_) => unreachable!("MtuCheck<Ipv6Packet> has an IPv6 source"),
+        };
+        let message = Icmpv6Message::PacketTooBig {
+            mtu: self.mtu as u32,
+            invoking_packet: &packet.data[packet.layer3_offset..],
+        };
+        Err(Ipv6Packet::builder()
+            .hop_limit(ICMP_TTL)
+            .src(source)
+            .dst(packet.src_addr())
+            .icmpv6(&message)
+            .build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use route_rs_packets::{IcmpPacket, Icmpv6Packet, ETHERNET_MTU};
+    use std::convert::TryFrom;
+
+    const ROUTER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 254);
+    const HOST: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+
+    fn ipv4_packet(payload_len: usize, dont_fragment: bool) -> Ipv4Packet {
+        let builder = Ipv4Packet::builder()
+            .src(HOST)
+            .dst(Ipv4Addr::new(192, 168, 0, 1));
+        let builder = if dont_fragment {
+            builder.dont_fragment()
+        } else {
+            builder
+        };
+        builder.payload(17, &vec![0; payload_len]).build()
+    }
+
+    #[test]
+    fn ipv4() {
+        let mut check = MtuCheck::<Ipv4Packet>::new(ETHERNET_MTU, ROUTER);
+        let fits = ipv4_packet(ETHERNET_MTU - 20, true);
+        assert_eq!(check.try_process(fits.clone()), Ok(Some(fits)));
+
+        // Left for Fragment.
+        let jumbo = ipv4_packet(8000, false);
+        assert_eq!(check.try_process(jumbo.clone()), Ok(Some(jumbo)));
+
+        let error = check.try_process(ipv4_packet(8000, true)).unwrap_err();
+        assert_eq!((error.src_addr(), error.dest_addr()), (ROUTER, HOST));
+        let icmp = IcmpPacket::try_from(error).unwrap();
+        match icmp.message() {
+            IcmpMessage::DestinationUnreachable {
+                code, next_hop_mtu, ..
+            } => assert_eq!((code, next_hop_mtu), (4, 1500)),
+            message => panic!("Unexpected message: {:?}", message),
+        }
+
+        // Jumbo frames fit on a link with a jumbo MTU.
+        let mut check = MtuCheck::<Ipv4Packet>::new(9000, ROUTER);
+        assert!(check
+            .try_process(ipv4_packet(8000, true))
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn ipv6() {
+        let router: Ipv6Addr = "2001:db8::fe".parse().unwrap();
+        let host: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let packet = Ipv6Packet::builder()
+            .src(host)
+            .dst("2001:db8::2".parse().unwrap())
+            .payload(17, &[0; 8000])
+            .build();
+
+        let mut check = MtuCheck::<Ipv6Packet>::new(9000, router);
+        assert_eq!(check.try_process(packet.clone()), Ok(Some(packet.clone())));
+
+        let mut check = MtuCheck::<Ipv6Packet>::new(ETHERNET_MTU, router);
+        let error = check.try_process(packet).unwrap_err();
+        assert_eq!(error.dest_addr(), host);
+        let icmp = Icmpv6Packet::try_from(error).unwrap();
+        assert!(icmp.validate_checksum(router, host));
+        match icmp.message() {
+            Icmpv6Message::PacketTooBig { mtu, .. } => assert_eq!(mtu, 1500),
+            message => panic!("Unexpected message: {:?}", message),
+        }
+    }
+}
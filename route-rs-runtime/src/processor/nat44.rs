@@ -0,0 +1,640 @@
+use crate::processor::Processor;
+use route_rs_packets::{update_u16, IpProtocol, Ipv4Packet};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::net::Ipv4Addr;
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+
+/// The protocols NAT44 can translate. TCP and UDP are translated by port, and ICMP echo by its
+/// identifier.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum NatProtocol {
+    Tcp,
+    Udp,
+    Icmp,
+}
+
+impl NatProtocol {
+    /// Offset into the layer 4 header of the checksum.
+    fn checksum_offset(self) -> usize {
+        match self {
+            NatProtocol::Tcp => 16,
+            NatProtocol::Udp => 6,
+            NatProtocol::Icmp => 2,
+        }
+    }
+}
+
+/// An address and port, or ICMP identifier, on one side of the NAT.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NatEndpoint {
+    pub addr: Ipv4Addr,
+    pub port: u16,
+}
+
+impl NatEndpoint {
+    pub fn new(addr: Ipv4Addr, port: u16) -> Self {
+        NatEndpoint { addr, port }
+    }
+}
+
+struct NatMapping {
+    internal: NatEndpoint,
+    last_used: Instant,
+    /// Static mappings are port forwards, which never expire.
+    is_static: bool,
+}
+
+struct NatState {
+    /// Maps an internal endpoint to its external port.
+    outbound: HashMap<(NatProtocol, NatEndpoint), u16>,
+    /// Maps an external port to the mapping using it.
+    inbound: HashMap<(NatProtocol, u16), NatMapping>,
+    /// Where to start looking for a free port, so ports are reused as late as possible.
+    next_port: u16,
+}
+
+/// The translation table shared by a `Snat` and `Dnat`, mapping internal endpoints to ports of
+/// the external address. Mappings are made by `Snat` as internal hosts send traffic out, and
+/// are independent of the destination, so any external host may reply through them. Mappings
+/// expire when unused for the timeout of their protocol, except port forwards, which are added
+/// with `add_port_forward`. Cloning it gives another handle to the same table.
+#[derive(Clone)]
+pub struct NatTable {
+    external_addr: Ipv4Addr,
+    ports: RangeInclusive<u16>,
+    tcp_timeout: Duration,
+    udp_timeout: Duration,
+    icmp_timeout: Duration,
+    state: Arc<Mutex<NatState>>,
+}
+
+impl NatTable {
+    /// A table translating to `external_addr`, the address of the router's external interface.
+    pub fn new(external_addr: Ipv4Addr) -> Self {
+        let ports = 1024..=65535;
+        NatTable {
+            external_addr,
+            tcp_timeout: Duration::from_secs(7440),
+            udp_timeout: Duration::from_secs(300),
+            icmp_timeout: Duration::from_secs(60),
+            state: Arc::new(Mutex::new(NatState {
+                outbound: HashMap::new(),
+                inbound: HashMap::new(),
+                next_port: *ports.start(),
+            })),
+            ports,
+        }
+    }
+
+    /// Changes the external ports, or ICMP identifiers, mappings are made with, default 1024 to
+    /// 65535. Port forwards may use any port.
+    pub fn ports(self, ports: RangeInclusive<u16>) -> Self {
+        assert!(
+            !ports.is_empty(),
+            "NatTable ports: {:?}, must not be empty",
+            ports
+        );
+
+        self.state.lock().unwrap().next_port = *ports.start();
+        NatTable { ports, ..self }
+    }
+
+    /// Changes how long an unused TCP mapping lasts, default 2 hours and 4 minutes, per RFC 5382.
+    pub fn tcp_timeout(self, tcp_timeout: Duration) -> Self {
+        NatTable {
+            tcp_timeout,
+            ..self
+        }
+    }
+
+    /// Changes how long an unused UDP mapping lasts, default 5 minutes, per RFC 4787.
+    pub fn udp_timeout(self, udp_timeout: Duration) -> Self {
+        NatTable {
+            udp_timeout,
+            ..self
+        }
+    }
+
+    /// Changes how long an unused ICMP echo mapping lasts, default 60 seconds, per RFC 5508.
+    pub fn icmp_timeout(self, icmp_timeout: Duration) -> Self {
+        NatTable {
+            icmp_timeout,
+            ..self
+        }
+    }
+
+    pub fn external_addr(&self) -> Ipv4Addr {
+        self.external_addr
+    }
+
+    /// Forwards traffic to `external_port` on to `internal`, and translates `internal`'s traffic
+    /// out from `external_port`. Replaces any mapping using either.
+    pub fn add_port_forward(
+        &self,
+        protocol: NatProtocol,
+        external_port: u16,
+        internal: NatEndpoint,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(old_port) = state.outbound.remove(&(protocol, internal)) {
+            state.inbound.remove(&(protocol, old_port));
+        }
+        if let Some(old) = state.inbound.remove(&(protocol, external_port)) {
+            state.outbound.remove(&(protocol, old.internal));
+        }
+        state.outbound.insert((protocol, internal), external_port);
+        state.inbound.insert(
+            (protocol, external_port),
+            NatMapping {
+                internal,
+                last_used: Instant::now(),
+                is_static: true,
+            },
+        );
+    }
+
+    /// Removes the port forward on `external_port`, returning where it forwarded to.
+    pub fn remove_port_forward(
+        &self,
+        protocol: NatProtocol,
+        external_port: u16,
+    ) -> Option<NatEndpoint> {
+        let mut state = self.state.lock().unwrap();
+        match state.inbound.get(&(protocol, external_port)) {
+            Some(mapping) if mapping.is_static => {}
+            _ => return None,
+        }
+        let mapping = state.inbound.remove(&(protocol, external_port))?;
+        state.outbound.remove(&(protocol, mapping.internal));
+        Some(mapping.internal)
+    }
+
+    /// Returns the number of mappings, including any expired ones not yet cleaned up.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().inbound.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn timeout(&self, protocol: NatProtocol) -> Duration {
+        match protocol {
+            NatProtocol::Tcp => self.tcp_timeout,
+            NatProtocol::Udp => self.udp_timeout,
+            NatProtocol::Icmp => self.icmp_timeout,
+        }
+    }
+
+    fn is_expired(&self, protocol: NatProtocol, mapping: &NatMapping, now: Instant) -> bool {
+        !mapping.is_static && now.duration_since(mapping.last_used) >= self.timeout(protocol)
+    }
+
+    /// Returns the external port for `internal`, making a mapping if it has none.
+    fn map_outbound(
+        &self,
+        protocol: NatProtocol,
+        internal: NatEndpoint,
+        now: Instant,
+    ) -> Option<u16> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(port) = state.outbound.get(&(protocol, internal)).cloned() {
+            let mapping = state.inbound.get_mut(&(protocol, port)).unwrap();
+            if !self.is_expired(protocol, mapping, now) {
+                mapping.last_used = now;
+                return Some(port);
+            }
+            state.outbound.remove(&(protocol, internal));
+            state.inbound.remove(&(protocol, port));
+        }
+
+        let port = self.allocate_port(&mut state, protocol, now)?;
+        state.outbound.insert((protocol, internal), port);
+        state.inbound.insert(
+            (protocol, port),
+            NatMapping {
+                internal,
+                last_used: now,
+                is_static: false,
+            },
+        );
+        Some(port)
+    }
+
+    /// Finds a port with no mapping, or an expired one, which is removed.
+    fn allocate_port(
+        &self,
+        state: &mut NatState,
+        protocol: NatProtocol,
+        now: Instant,
+    ) -> Option<u16> {
+        let (first, last) = (*self.ports.start(), *self.ports.end());
+        let count = u32::from(last - first) + 1;
+        for i in 0..count {
+            let offset = (u32::from(state.next_port - first) + i) % count;
+            let port = first + offset as u16;
+            let free = match state.inbound.get(&(protocol, port)) {
+                None => true,
+                Some(mapping) => self.is_expired(protocol, mapping, now),
+            };
+            if free {
+                if let Some(expired) = state.inbound.remove(&(protocol, port)) {
+                    state.outbound.remove(&(protocol, expired.internal));
+                }
+                state.next_port = if port == last { first } else { port + 1 };
+                return Some(port);
+            }
+        }
+        None
+    }
+
+    /// Returns the internal endpoint traffic to `external_port` is translated to, if mapped.
+    fn map_inbound(
+        &self,
+        protocol: NatProtocol,
+        external_port: u16,
+        now: Instant,
+    ) -> Option<NatEndpoint> {
+        let mut state = self.state.lock().unwrap();
+        let mapping = state.inbound.get_mut(&(protocol, external_port))?;
+        if self.is_expired(protocol, mapping, now) {
+            let internal = mapping.internal;
+            state.inbound.remove(&(protocol, external_port));
+            state.outbound.remove(&(protocol, internal));
+            return None;
+        }
+        mapping.last_used = now;
+        Some(mapping.internal)
+    }
+}
+
+/// Which of a packet's endpoints to translate.
+#[derive(Clone, Copy)]
+enum Direction {
+    Source,
+    Destination,
+}
+
+/// Finds the protocol of a packet we can translate, and the offset of its port, or ICMP
+/// identifier, on the `direction` side. Non-first fragments have no layer 4 header, so can't be
+/// translated.
+fn translatable(packet: &Ipv4Packet, direction: Direction) -> Option<(NatProtocol, usize)> {
+    if packet.fragment_offset() != 0 {
+        return None;
+    }
+    let layer4 = packet.payload_offset;
+    let layer4_len = packet.data.len().checked_sub(layer4)?;
+    let port_offset = match direction {
+        Direction::Source => 0,
+        Direction::Destination => 2,
+    };
+    match packet.protocol() {
+        IpProtocol::TCP if layer4_len >= 20 => Some((NatProtocol::Tcp, layer4 + port_offset)),
+        IpProtocol::UDP if layer4_len >= 8 => Some((NatProtocol::Udp, layer4 + port_offset)),
+        IpProtocol::ICMP if layer4_len >= 8 => {
+            let echo_type = match direction {
+                Direction::Source => ICMP_ECHO_REQUEST,
+                Direction::Destination => ICMP_ECHO_REPLY,
+            };
+            if packet.data[layer4] == echo_type {
+                Some((NatProtocol::Icmp, layer4 + 4))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+fn read_port(packet: &Ipv4Packet, port_at: usize) -> u16 {
+    u16::from_be_bytes(packet.data[port_at..port_at + 2].try_into().unwrap())
+}
+
+/// Rewrites the `direction` endpoint of the packet to `to`, updating the IPv4 and layer 4
+/// checksums incrementally. The address setters update the checksums covering the address, so
+/// only the port's is left.
+fn translate(
+    packet: &mut Ipv4Packet,
+    protocol: NatProtocol,
+    direction: Direction,
+    port_at: usize,
+    to: NatEndpoint,
+) {
+    match direction {
+        Direction::Source => packet.set_src_addr(to.addr),
+        Direction::Destination => packet.set_dest_addr(to.addr),
+    }
+
+    let checksum_at = packet.payload_offset + protocol.checksum_offset();
+    let old_port = read_port(packet, port_at);
+    // A UDP checksum of zero means there is none.
+    if protocol != NatProtocol::Udp || read_port(packet, checksum_at) != 0 {
+        let checksum = match update_u16(read_port(packet, checksum_at), old_port, to.port) {
+            0 if protocol == NatProtocol::Udp => 0xFFFF,
+            checksum => checksum,
+        };
+        packet.data[checksum_at..checksum_at + 2].copy_from_slice(&checksum.to_be_bytes());
+    }
+    packet.data[port_at..port_at + 2].copy_from_slice(&to.port.to_be_bytes());
+}
+
+/// Source NAT, for traffic leaving through the external interface. Translates the source of TCP,
+/// UDP and ICMP echo requests to the table's external address, mapping each internal endpoint to
+/// an external port. Packets that can't be translated, of other protocols, or when no ports are
+/// free, are dropped.
+pub struct Snat {
+    table: NatTable,
+}
+
+impl Snat {
+    pub fn new(table: NatTable) -> Self {
+        Snat { table }
+    }
+}
+
+impl Processor for Snat {
+    type Input = Ipv4Packet;
+    type Output = Ipv4Packet;
+
+    fn process(&mut self, mut packet: Self::Input) -> Option<Self::Output> {
+        let (protocol, port_at) = translatable(&packet, Direction::Source)?;
+        let internal = NatEndpoint::new(packet.src_addr(), read_port(&packet, port_at));
+        let external_port = self
+            .table
+            .map_outbound(protocol, internal, Instant::now())?;
+
+        let external = NatEndpoint::new(self.table.external_addr, external_port);
+        translate(&mut packet, protocol, Direction::Source, port_at, external);
+        Some(packet)
+    }
+}
+
+/// Destination NAT, for traffic arriving on the external interface. Translates the destination
+/// of TCP, UDP and ICMP echo replies to the internal endpoint mapped to its port, either by
+/// `Snat` or as a port forward. Packets with no mapping are dropped.
+pub struct Dnat {
+    table: NatTable,
+}
+
+impl Dnat {
+    pub fn new(table: NatTable) -> Self {
+        Dnat { table }
+    }
+}
+
+impl Processor for Dnat {
+    type Input = Ipv4Packet;
+    type Output = Ipv4Packet;
+
+    fn process(&mut self, mut packet: Self::Input) -> Option<Self::Output> {
+        if packet.dest_addr() != self.table.external_addr {
+            return None;
+        }
+        let (protocol, port_at) = translatable(&packet, Direction::Destination)?;
+        let internal =
+            self.table
+                .map_inbound(protocol, read_port(&packet, port_at), Instant::now())?;
+
+        translate(
+            &mut packet,
+            protocol,
+            Direction::Destination,
+            port_at,
+            internal,
+        );
+        Some(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{Ipv4Checksum, Processor, TcpChecksum, UdpChecksum};
+    use route_rs_packets::{internet_checksum, TcpSegment, UdpSegment};
+    use std::convert::TryFrom;
+    use std::thread::sleep;
+
+    const EXTERNAL: Ipv4Addr = Ipv4Addr::new(203, 0, 113, 1);
+    const HOST: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 10);
+    const SERVER: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 7);
+
+    /// A packet with valid checksums, from `src`:`src_port` to `dest`:`dest_port`.
+    fn packet(protocol: u8, src: NatEndpoint, dest: NatEndpoint) -> Ipv4Packet {
+        let mut layer4 = match protocol {
+            6 => {
+                let mut tcp = vec![0; 20];
+                tcp[12] = 5 << 4;
+                tcp
+            }
+            17 => vec![0, 0, 0, 0, 0, 11, 0, 0],
+            _ => vec![ICMP_ECHO_REQUEST, 0, 0, 0, 0, 0, 0, 1],
+        };
+        layer4.extend_from_slice(&[1, 2, 3]);
+        if protocol == 1 {
+            layer4[4..6].copy_from_slice(&src.port.to_be_bytes());
+        } else {
+            layer4[0..2].copy_from_slice(&src.port.to_be_bytes());
+            layer4[2..4].copy_from_slice(&dest.port.to_be_bytes());
+        }
+
+        let mut packet = Ipv4Packet::empty();
+        packet.set_ttl(64);
+        packet.set_protocol(protocol);
+        packet.set_src_addr(src.addr);
+        packet.set_dest_addr(dest.addr);
+        packet.set_payload(&layer4);
+        packet.set_checksum();
+        match protocol {
+            6 => {
+                let segment = TcpSegment::try_from(packet).unwrap();
+                Ipv4Packet::try_from(TcpChecksum::new().process(segment).unwrap()).unwrap()
+            }
+            17 => {
+                let segment = UdpSegment::try_from(packet).unwrap();
+                Ipv4Packet::try_from(UdpChecksum::new().process(segment).unwrap()).unwrap()
+            }
+            _ => {
+                let checksum = internet_checksum(&[&layer4]);
+                packet.data[22..24].copy_from_slice(&checksum.to_be_bytes());
+                packet
+            }
+        }
+    }
+
+    fn reply(packet: &Ipv4Packet, protocol: u8) -> Ipv4Packet {
+        let src = NatEndpoint::new(packet.src_addr(), read_port(packet, 20));
+        let dest = NatEndpoint::new(packet.dest_addr(), read_port(packet, 22));
+        if protocol == 1 {
+            let id = NatEndpoint::new(packet.src_addr(), read_port(packet, 24));
+            let mut reply = self::packet(1, NatEndpoint::new(dest.addr, 0), id);
+            reply.data[20] = ICMP_ECHO_REPLY;
+            reply.data[24..26].copy_from_slice(&id.port.to_be_bytes());
+            reply.data[22..24].copy_from_slice(&[0, 0]);
+            let checksum = internet_checksum(&[&reply.data[20..]]);
+            reply.data[22..24].copy_from_slice(&checksum.to_be_bytes());
+            return reply;
+        }
+        self::packet(protocol, dest, src)
+    }
+
+    fn assert_valid(packet: Ipv4Packet) {
+        assert!(Ipv4Checksum::new()
+            .validate()
+            .process(packet.clone())
+            .is_some());
+        match packet.protocol() {
+            IpProtocol::TCP => {
+                let segment = TcpSegment::try_from(packet).unwrap();
+                assert!(TcpChecksum::new().validate().process(segment).is_some());
+            }
+            IpProtocol::UDP => {
+                let segment = UdpSegment::try_from(packet).unwrap();
+                assert!(UdpChecksum::new().validate().process(segment).is_some());
+            }
+            _ => assert_eq!(internet_checksum(&[&packet.data[20..]]), 0),
+        }
+    }
+
+    #[test]
+    fn translates_both_ways() {
+        let table = NatTable::new(EXTERNAL).ports(5000..=5010);
+        let mut snat = Snat::new(table.clone());
+        let mut dnat = Dnat::new(table.clone());
+
+        for protocol in [6, 17, 1].iter().cloned() {
+            let outbound = packet(
+                protocol,
+                NatEndpoint::new(HOST, 40000),
+                NatEndpoint::new(SERVER, 80),
+            );
+            let translated = snat.process(outbound.clone()).unwrap();
+            assert_eq!(translated.src_addr(), EXTERNAL);
+            assert_eq!(translated.dest_addr(), SERVER);
+            assert_valid(translated.clone());
+
+            let inbound = dnat.process(reply(&translated, protocol)).unwrap();
+            assert_eq!(inbound.dest_addr(), HOST);
+            assert_eq!(inbound.src_addr(), SERVER);
+            assert_valid(inbound.clone());
+            if protocol == 1 {
+                assert_eq!(read_port(&inbound, 24), 40000);
+            } else {
+                assert_eq!(read_port(&inbound, 22), 40000);
+            }
+        }
+        assert_eq!(table.len(), 3);
+    }
+
+    #[test]
+    fn reuses_mappings() {
+        let table = NatTable::new(EXTERNAL).ports(5000..=5010);
+        let mut snat = Snat::new(table);
+        let from = |port| {
+            packet(
+                17,
+                NatEndpoint::new(HOST, port),
+                NatEndpoint::new(SERVER, 53),
+            )
+        };
+
+        let first = snat.process(from(1000)).unwrap();
+        let second = snat.process(from(1001)).unwrap();
+        let again = snat.process(from(1000)).unwrap();
+        assert_eq!(read_port(&first, 20), 5000);
+        assert_eq!(read_port(&second, 20), 5001);
+        assert_eq!(read_port(&again, 20), 5000);
+    }
+
+    #[test]
+    fn drops_when_out_of_ports() {
+        let table = NatTable::new(EXTERNAL).ports(5000..=5001);
+        let mut snat = Snat::new(table);
+        let from = |port| {
+            packet(
+                6,
+                NatEndpoint::new(HOST, port),
+                NatEndpoint::new(SERVER, 80),
+            )
+        };
+
+        assert!(snat.process(from(1)).is_some());
+        assert!(snat.process(from(2)).is_some());
+        assert!(snat.process(from(3)).is_none());
+    }
+
+    #[test]
+    fn mappings_expire() {
+        let table = NatTable::new(EXTERNAL)
+            .ports(5000..=5000)
+            .udp_timeout(Duration::from_millis(20));
+        let mut snat = Snat::new(table.clone());
+        let mut dnat = Dnat::new(table);
+        let from = |port| {
+            packet(
+                17,
+                NatEndpoint::new(HOST, port),
+                NatEndpoint::new(SERVER, 53),
+            )
+        };
+
+        let translated = snat.process(from(1000)).unwrap();
+        assert!(snat.process(from(1001)).is_none());
+
+        sleep(Duration::from_millis(30));
+        assert!(dnat.process(reply(&translated, 17)).is_none());
+        assert_eq!(read_port(&snat.process(from(1001)).unwrap(), 20), 5000);
+    }
+
+    #[test]
+    fn port_forwards() {
+        let table = NatTable::new(EXTERNAL).tcp_timeout(Duration::from_millis(0));
+        let web_server = NatEndpoint::new(HOST, 8080);
+        table.add_port_forward(NatProtocol::Tcp, 80, web_server);
+        let mut dnat = Dnat::new(table.clone());
+        let mut snat = Snat::new(table.clone());
+
+        let inbound = packet(
+            6,
+            NatEndpoint::new(SERVER, 50000),
+            NatEndpoint::new(EXTERNAL, 80),
+        );
+        let forwarded = dnat.process(inbound.clone()).unwrap();
+        assert_eq!(forwarded.dest_addr(), HOST);
+        assert_eq!(read_port(&forwarded, 22), 8080);
+        assert_valid(forwarded.clone());
+
+        let response = snat.process(reply(&forwarded, 6)).unwrap();
+        assert_eq!(response.src_addr(), EXTERNAL);
+        assert_eq!(read_port(&response, 20), 80);
+
+        assert_eq!(
+            table.remove_port_forward(NatProtocol::Tcp, 80),
+            Some(web_server)
+        );
+        assert!(dnat.process(inbound).is_none());
+    }
+
+    #[test]
+    fn drops_unsolicited_and_untranslatable() {
+        let table = NatTable::new(EXTERNAL);
+        let mut dnat = Dnat::new(table.clone());
+        let mut snat = Snat::new(table);
+
+        let unsolicited = packet(
+            17,
+            NatEndpoint::new(SERVER, 53),
+            NatEndpoint::new(EXTERNAL, 6000),
+        );
+        assert!(dnat.process(unsolicited).is_none());
+
+        let mut gre = packet(17, NatEndpoint::new(HOST, 1), NatEndpoint::new(SERVER, 2));
+        gre.set_protocol(47);
+        assert!(snat.process(gre).is_none());
+    }
+}
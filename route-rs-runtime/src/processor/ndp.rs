@@ -0,0 +1,776 @@
+use crate::classifier::Classifier;
+use crate::link::primitive::{ClassifyLink, ProcessLink};
+use crate::link::{Link, LinkBuilder, PacketStream, ProcessLinkBuilder};
+use crate::processor::Processor;
+use route_rs_packets::{
+    EthernetFrame, Icmpv6Message, Icmpv6Packet, Ipv6Packet, MacAddr, NdpOption,
+    ICMPV6_NEIGHBOR_ADVERTISEMENT, IPV6_ETHER_TYPE,
+};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::net::Ipv6Addr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const ICMPV6_NEXT_HEADER: u8 = 58;
+/// Neighbor Discovery messages must arrive with this hop limit, proving they weren't forwarded.
+const ND_HOP_LIMIT: u8 = 255;
+
+/// The reachability states of a neighbor, per RFC 4861.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum NeighborState {
+    /// Solicited, but not yet answered.
+    Incomplete,
+    /// Confirmed reachable within the reachable time.
+    Reachable,
+    /// Not confirmed recently; it's used, but confirmed when it is.
+    Stale,
+    /// Used while stale, waiting a little for upper layers to confirm it before probing.
+    Delay,
+    /// Being probed with unicast solicitations.
+    Probe,
+}
+
+struct Neighbor {
+    mac: Option<MacAddr>,
+    state: NeighborState,
+    /// When the neighbor last changed state, or was last solicited.
+    updated: Instant,
+    /// Solicitations sent in the Incomplete or Probe state.
+    solicitations: u32,
+}
+
+/// What to do with a packet for a neighbor.
+#[derive(Debug, PartialEq)]
+enum Resolution {
+    Send(MacAddr),
+    /// Send the packet, and a unicast solicitation to confirm the neighbor.
+    SendAndProbe(MacAddr),
+    /// The neighbor is unknown; send a multicast solicitation for it.
+    Solicit,
+    /// The neighbor is being solicited.
+    Wait,
+}
+
+/// The neighbor cache shared by an `NdpResponder` and `NdpResolver`, mapping neighbors' IPv6
+/// addresses to their link-layer addresses, and tracking whether they're reachable. Entries are
+/// confirmed by Neighbor Advertisements, and probed once `reachable_time` passes without one.
+/// Cloning it gives another handle to the same cache.
+#[derive(Clone)]
+pub struct NeighborCache {
+    reachable_time: Duration,
+    delay_first_probe_time: Duration,
+    retrans_timer: Duration,
+    max_solicitations: u32,
+    neighbors: Arc<Mutex<HashMap<Ipv6Addr, Neighbor>>>,
+}
+
+impl Default for NeighborCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NeighborCache {
+    /// A cache with the timers RFC 4861 suggests.
+    pub fn new() -> Self {
+        NeighborCache {
+            reachable_time: Duration::from_secs(30),
+            delay_first_probe_time: Duration::from_secs(5),
+            retrans_timer: Duration::from_secs(1),
+            max_solicitations: 3,
+            neighbors: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// How long a neighbor is reachable after it's confirmed, default 30 seconds.
+    pub fn reachable_time(self, reachable_time: Duration) -> Self {
+        NeighborCache {
+            reachable_time,
+            ..self
+        }
+    }
+
+    /// How long a stale neighbor is used before it's probed, default 5 seconds.
+    pub fn delay_first_probe_time(self, delay_first_probe_time: Duration) -> Self {
+        NeighborCache {
+            delay_first_probe_time,
+            ..self
+        }
+    }
+
+    /// How long to wait between solicitations, default 1 second.
+    pub fn retrans_timer(self, retrans_timer: Duration) -> Self {
+        NeighborCache {
+            retrans_timer,
+            ..self
+        }
+    }
+
+    /// How many solicitations go unanswered before a neighbor is forgotten, default 3.
+    pub fn max_solicitations(self, max_solicitations: u32) -> Self {
+        assert!(
+            max_solicitations > 0,
+            "NeighborCache max_solicitations: {}, must be > 0",
+            max_solicitations
+        );
+
+        NeighborCache {
+            max_solicitations,
+            ..self
+        }
+    }
+
+    /// Returns the state of the neighbor, if it's in the cache.
+    pub fn state(&self, addr: &Ipv6Addr) -> Option<NeighborState> {
+        self.state_at(addr, Instant::now())
+    }
+
+    fn state_at(&self, addr: &Ipv6Addr, now: Instant) -> Option<NeighborState> {
+        let neighbors = self.neighbors.lock().unwrap();
+        let neighbor = neighbors.get(addr)?;
+        if neighbor.state == NeighborState::Reachable
+            && now.duration_since(neighbor.updated) >= self.reachable_time
+        {
+            return Some(NeighborState::Stale);
+        }
+        Some(neighbor.state)
+    }
+
+    /// Returns the link-layer address of the neighbor, if it's known.
+    pub fn link_layer_addr(&self, addr: &Ipv6Addr) -> Option<MacAddr> {
+        self.neighbors.lock().unwrap().get(addr)?.mac
+    }
+
+    /// Records that `addr` is at `mac`, as a Neighbor Solicitation from it says, or an
+    /// unsolicited advertisement. The neighbor is Stale, unless it was already known at `mac`.
+    pub fn learn(&self, addr: Ipv6Addr, mac: MacAddr) {
+        self.learn_at(addr, mac, Instant::now())
+    }
+
+    fn learn_at(&self, addr: Ipv6Addr, mac: MacAddr, now: Instant) {
+        let mut neighbors = self.neighbors.lock().unwrap();
+        match neighbors.get_mut(&addr) {
+            Some(neighbor) if neighbor.mac == Some(mac) => (),
+            Some(neighbor) => {
+                neighbor.mac = Some(mac);
+                neighbor.state = NeighborState::Stale;
+                neighbor.updated = now;
+            }
+            None => {
+                neighbors.insert(
+                    addr,
+                    Neighbor {
+                        mac: Some(mac),
+                        state: NeighborState::Stale,
+                        updated: now,
+                        solicitations: 0,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Updates the neighbor from a Neighbor Advertisement, per section 7.2.5 of RFC 4861.
+    /// Advertisements for neighbors that aren't in the cache are ignored.
+    fn advertised_at(
+        &self,
+        addr: Ipv6Addr,
+        mac: Option<MacAddr>,
+        solicited: bool,
+        override_flag: bool,
+        now: Instant,
+    ) {
+        let mut neighbors = self.neighbors.lock().unwrap();
+        let neighbor = match neighbors.get_mut(&addr) {
+            Some(neighbor) => neighbor,
+            None => return,
+        };
+
+        if neighbor.state == NeighborState::Incomplete {
+            if let Some(mac) = mac {
+                neighbor.mac = Some(mac);
+                neighbor.state = if solicited {
+                    NeighborState::Reachable
+                } else {
+                    NeighborState::Stale
+                };
+                neighbor.updated = now;
+            }
+            return;
+        }
+
+        let changed = mac.is_some() && mac != neighbor.mac;
+        if changed && !override_flag {
+            // Keep the address we have, but stop trusting it.
+            if neighbor.state == NeighborState::Reachable {
+                neighbor.state = NeighborState::Stale;
+                neighbor.updated = now;
+            }
+            return;
+        }
+        if changed {
+            neighbor.mac = mac;
+        }
+        if solicited {
+            neighbor.state = NeighborState::Reachable;
+            neighbor.updated = now;
+        } else if changed {
+            neighbor.state = NeighborState::Stale;
+            neighbor.updated = now;
+        }
+    }
+
+    /// Steps the neighbor's state machine for a packet that's to be sent to it.
+    fn resolve_at(&self, addr: Ipv6Addr, now: Instant) -> Resolution {
+        let mut neighbors = self.neighbors.lock().unwrap();
+        let neighbor = match neighbors.get_mut(&addr) {
+            Some(neighbor) => neighbor,
+            None => {
+                neighbors.insert(
+                    addr,
+                    Neighbor {
+                        mac: None,
+                        state: NeighborState::Incomplete,
+                        updated: now,
+                        solicitations: 1,
+                    },
+                );
+                return Resolution::Solicit;
+            }
+        };
+        let elapsed = now.duration_since(neighbor.updated);
+
+        match neighbor.state {
+            NeighborState::Incomplete if elapsed < self.retrans_timer => Resolution::Wait,
+            NeighborState::Incomplete if neighbor.solicitations < self.max_solicitations => {
+                neighbor.solicitations += 1;
+                neighbor.updated = now;
+                Resolution::Solicit
+            }
+            NeighborState::Incomplete => {
+                // The neighbor never answered. Forget it, so the next packet starts again.
+                neighbors.remove(&addr);
+                Resolution::Wait
+            }
+            NeighborState::Reachable if elapsed < self.reachable_time => {
+                Resolution::Send(neighbor.mac.unwrap())
+            }
+            NeighborState::Reachable | NeighborState::Stale => {
+                neighbor.state = NeighborState::Delay;
+                neighbor.updated = now;
+                Resolution::Send(neighbor.mac.unwrap())
+            }
+            NeighborState::Delay if elapsed < self.delay_first_probe_time => {
+                Resolution::Send(neighbor.mac.unwrap())
+            }
+            NeighborState::Delay => {
+                neighbor.state = NeighborState::Probe;
+                neighbor.updated = now;
+                neighbor.solicitations = 1;
+                Resolution::SendAndProbe(neighbor.mac.unwrap())
+            }
+            NeighborState::Probe if elapsed < self.retrans_timer => {
+                Resolution::Send(neighbor.mac.unwrap())
+            }
+            NeighborState::Probe if neighbor.solicitations < self.max_solicitations => {
+                neighbor.solicitations += 1;
+                neighbor.updated = now;
+                Resolution::SendAndProbe(neighbor.mac.unwrap())
+            }
+            NeighborState::Probe => {
+                // The neighbor has gone away; look for it afresh.
+                *neighbor = Neighbor {
+                    mac: None,
+                    state: NeighborState::Incomplete,
+                    updated: now,
+                    solicitations: 1,
+                };
+                Resolution::Solicit
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.neighbors.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A Neighbor Discovery message, as found in a frame.
+struct NdpMessage {
+    src_mac: MacAddr,
+    src: Ipv6Addr,
+    kind: u8,
+    /// The flags of advertisements: sent in answer to a solicitation, and to override the cached
+    /// link-layer address.
+    solicited: bool,
+    override_entry: bool,
+    target: Ipv6Addr,
+    /// The link-layer address option: the source's of solicitations, the target's of
+    /// advertisements.
+    link_layer_addr: Option<MacAddr>,
+}
+
+/// Parses the Neighbor Solicitation or Advertisement in `frame`, if it's a valid one.
+fn ndp_message(frame: &EthernetFrame) -> Option<NdpMessage> {
+    let packet = Ipv6Packet::try_from(frame.clone()).ok()?;
+    if packet.hop_limit() != ND_HOP_LIMIT {
+        return None;
+    }
+    let src = packet.src_addr();
+    let dst = packet.dest_addr();
+    let icmp = Icmpv6Packet::try_from(packet).ok()?;
+    if icmp.code() != 0 || !icmp.validate_checksum(src, dst) {
+        return None;
+    }
+
+    let link_layer_addr = |options: &[NdpOption], solicitation: bool| {
+        options.iter().find_map(|option| match option {
+            NdpOption::SourceLinkLayerAddr(mac) if solicitation => Some(*mac),
+            NdpOption::TargetLinkLayerAddr(mac) if !solicitation => Some(*mac),
+            _ => None,
+        })
+    };
+    let (solicited, override_entry, target, link_layer_addr) = match icmp.message() {
+        Icmpv6Message::NeighborSolicitation { target, options } => {
+            (false, false, target, link_layer_addr(&options, true))
+        }
+        Icmpv6Message::NeighborAdvertisement {
+            solicited,
+            override_entry,
+            target,
+            options,
+            ..
+        } => (
+            solicited,
+            override_entry,
+            target,
+            link_layer_addr(&options, false),
+        ),
+        _ => return None,
+    };
+
+    Some(NdpMessage {
+        src_mac: frame.src_mac(),
+        src,
+        kind: icmp.icmp_type(),
+        solicited,
+        override_entry,
+        target,
+        link_layer_addr,
+    })
+}
+
+/// Builds a frame carrying the Neighbor Discovery `message`, setting its checksum.
+fn ndp_frame(
+    src_mac: MacAddr,
+    dst_mac: MacAddr,
+    src: Ipv6Addr,
+    dst: Ipv6Addr,
+    message: &Icmpv6Message,
+) -> EthernetFrame {
+    let icmp = Icmpv6Packet::new(src, dst, message);
+
+    let mut packet = Ipv6Packet::empty();
+    packet.set_next_header(ICMPV6_NEXT_HEADER);
+    packet.set_hop_limit(ND_HOP_LIMIT);
+    packet.set_src_addr(src);
+    packet.set_dest_addr(dst);
+    packet.set_payload(&icmp.data);
+
+    let mut frame = EthernetFrame::encap_ipv6(packet);
+    frame.set_src_mac(src_mac);
+    frame.set_dest_mac(dst_mac);
+    frame
+}
+
+/// A router's advertisement of `mac` for `target`, overriding any cached address.
+fn advertisement(target: Ipv6Addr, solicited: bool, mac: MacAddr) -> Icmpv6Message<'static> {
+    Icmpv6Message::NeighborAdvertisement {
+        router: true,
+        solicited,
+        override_entry: true,
+        target,
+        options: vec![NdpOption::TargetLinkLayerAddr(mac)],
+    }
+}
+
+/// The solicited-node multicast address of `addr`, and the MAC address it's sent to.
+fn solicited_node(addr: Ipv6Addr) -> (Ipv6Addr, MacAddr) {
+    let octets = addr.octets();
+    let mut multicast = [0; 16];
+    multicast[..2].copy_from_slice(&[0xff, 0x02]);
+    multicast[11..].copy_from_slice(&[0x01, 0xff, octets[13], octets[14], octets[15]]);
+    (
+        Ipv6Addr::from(multicast),
+        MacAddr::new([0x33, 0x33, 0xff, octets[13], octets[14], octets[15]]),
+    )
+}
+
+/// The all-nodes multicast address, and the MAC address it's sent to.
+const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+const ALL_NODES_MAC: MacAddr = MacAddr {
+    bytes: [0x33, 0x33, 0, 0, 0, 1],
+};
+
+/// Classifies frames by whether they're Neighbor Solicitations or Advertisements.
+#[derive(Default)]
+pub struct Ndp {}
+
+impl Ndp {
+    pub fn new() -> Self {
+        Ndp {}
+    }
+}
+
+impl Classifier for Ndp {
+    type Packet = EthernetFrame;
+    type Class = bool;
+
+    fn classify(&self, frame: &Self::Packet) -> Self::Class {
+        ndp_message(frame).is_some()
+    }
+}
+
+/// Answers Neighbor Solicitations for `addresses`, the router's own on the link, with
+/// advertisements of `mac`, and learns the neighbors that send them into `cache`. Neighbor
+/// Advertisements update the cache, and are dropped, as is anything else.
+pub struct NdpResponder {
+    cache: NeighborCache,
+    addresses: Vec<Ipv6Addr>,
+    mac: MacAddr,
+}
+
+impl NdpResponder {
+    pub fn new(cache: NeighborCache, addresses: Vec<Ipv6Addr>, mac: MacAddr) -> Self {
+        NdpResponder {
+            cache,
+            addresses,
+            mac,
+        }
+    }
+
+    fn process_at(&mut self, frame: EthernetFrame, now: Instant) -> Option<EthernetFrame> {
+        let message = ndp_message(&frame)?;
+
+        if message.kind == ICMPV6_NEIGHBOR_ADVERTISEMENT {
+            self.cache.advertised_at(
+                message.target,
+                message.link_layer_addr,
+                message.solicited,
+                message.override_entry,
+                now,
+            );
+            return None;
+        }
+
+        if !self.addresses.contains(&message.target) {
+            return None;
+        }
+        // Solicitations from the unspecified address are duplicate address detection; the
+        // answer goes to all nodes, and isn't a solicited one.
+        if message.src.is_unspecified() {
+            return Some(ndp_frame(
+                self.mac,
+                ALL_NODES_MAC,
+                message.target,
+                ALL_NODES,
+                &advertisement(message.target, false, self.mac),
+            ));
+        }
+        if let Some(mac) = message.link_layer_addr {
+            self.cache.learn_at(message.src, mac, now);
+        }
+        Some(ndp_frame(
+            self.mac,
+            message.link_layer_addr.unwrap_or(message.src_mac),
+            message.target,
+            message.src,
+            &advertisement(message.target, true, self.mac),
+        ))
+    }
+}
+
+impl Processor for NdpResponder {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, frame: Self::Input) -> Option<Self::Output> {
+        self.process_at(frame, Instant::now())
+    }
+}
+
+/// Addresses IPv6 frames to their next hop, from `addr` and `mac`, the router's own on the link,
+/// by looking it up in `cache`. The next hop is the packet's destination, or `gateway` if set.
+/// Packets for unknown next hops are dropped, and a Neighbor Solicitation for the next hop sent
+/// in their place; solicitations to confirm stale next hops are sent alongside the packet.
+/// Frames that aren't IPv6 are dropped.
+pub struct NdpResolver {
+    cache: NeighborCache,
+    addr: Ipv6Addr,
+    mac: MacAddr,
+    gateway: Option<Ipv6Addr>,
+}
+
+impl NdpResolver {
+    pub fn new(cache: NeighborCache, addr: Ipv6Addr, mac: MacAddr) -> Self {
+        NdpResolver {
+            cache,
+            addr,
+            mac,
+            gateway: None,
+        }
+    }
+
+    /// Sends every packet through `gateway`, rather than straight to its destination.
+    pub fn gateway(self, gateway: Ipv6Addr) -> Self {
+        NdpResolver {
+            gateway: Some(gateway),
+            ..self
+        }
+    }
+
+    fn solicitation(&self, target: Ipv6Addr, unicast: Option<MacAddr>) -> EthernetFrame {
+        let (dst, dst_mac) = match unicast {
+            Some(mac) => (target, mac),
+            None => solicited_node(target),
+        };
+        ndp_frame(
+            self.mac,
+            dst_mac,
+            self.addr,
+            dst,
+            &Icmpv6Message::NeighborSolicitation {
+                target,
+                options: vec![NdpOption::SourceLinkLayerAddr(self.mac)],
+            },
+        )
+    }
+
+    fn process_at(&mut self, mut frame: EthernetFrame, now: Instant) -> Option<Vec<EthernetFrame>> {
+        if frame.ether_type() != IPV6_ETHER_TYPE {
+            return None;
+        }
+        let next_hop = match self.gateway {
+            Some(gateway) => gateway,
+            None => {
+                let ip = frame.payload_offset;
+                let dst = frame.data.get(ip + 24..ip + 40)?;
+                Ipv6Addr::from(<[u8; 16]>::try_from(dst).unwrap())
+            }
+        };
+
+        match self.cache.resolve_at(next_hop, now) {
+            Resolution::Send(mac) => {
+                frame.set_src_mac(self.mac);
+                frame.set_dest_mac(mac);
+                Some(vec![frame])
+            }
+            Resolution::SendAndProbe(mac) => {
+                frame.set_src_mac(self.mac);
+                frame.set_dest_mac(mac);
+                Some(vec![frame, self.solicitation(next_hop, Some(mac))])
+            }
+            Resolution::Solicit => Some(vec![self.solicitation(next_hop, None)]),
+            Resolution::Wait => None,
+        }
+    }
+}
+
+impl Processor for NdpResolver {
+    type Input = EthernetFrame;
+    type Output = Vec<EthernetFrame>;
+
+    fn process(&mut self, frame: Self::Input) -> Option<Self::Output> {
+        self.process_at(frame, Instant::now())
+    }
+}
+
+/// Answers Neighbor Discovery in `stream` with `responder`. Other frames pass through to the
+/// first egressor, and the advertisements are sent out the second.
+pub fn ndp_link(
+    stream: PacketStream<EthernetFrame>,
+    responder: NdpResponder,
+) -> Link<EthernetFrame> {
+    let (mut runnables, mut egressors) = ClassifyLink::new()
+        .ingressor(stream)
+        .num_egressors(2)
+        .classifier(Ndp::new())
+        .dispatcher(Box::new(|is_ndp| if is_ndp { 1 } else { 0 }))
+        .build_link();
+
+    let (reply_runnables, reply_egressors) = ProcessLink::new()
+        .ingressor(egressors.pop().unwrap())
+        .processor(responder)
+        .build_link();
+    runnables.extend(reply_runnables);
+    egressors.extend(reply_egressors);
+    (runnables, egressors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use route_rs_packets::ICMPV6_NEIGHBOR_SOLICITATION;
+
+    const ROUTER: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+    const HOST: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0xab, 0xcdef);
+    const ROUTER_MAC: MacAddr = MacAddr {
+        bytes: [2, 0, 0, 0, 0, 1],
+    };
+    const HOST_MAC: MacAddr = MacAddr {
+        bytes: [2, 0, 0, 0, 0, 2],
+    };
+
+    fn ipv6_frame(dst: Ipv6Addr) -> EthernetFrame {
+        let mut packet = Ipv6Packet::empty();
+        packet.set_next_header(17);
+        packet.set_hop_limit(64);
+        packet.set_src_addr(ROUTER);
+        packet.set_dest_addr(dst);
+        packet.set_payload(&[1, 2, 3, 4]);
+        EthernetFrame::encap_ipv6(packet)
+    }
+
+    fn host_advertisement(solicited: bool, mac: MacAddr) -> EthernetFrame {
+        let message = Icmpv6Message::NeighborAdvertisement {
+            router: false,
+            solicited,
+            override_entry: true,
+            target: HOST,
+            options: vec![NdpOption::TargetLinkLayerAddr(mac)],
+        };
+        ndp_frame(mac, ROUTER_MAC, HOST, ROUTER, &message)
+    }
+
+    #[test]
+    fn answers_solicitations() {
+        let cache = NeighborCache::new();
+        let mut responder = NdpResponder::new(cache.clone(), vec![ROUTER], ROUTER_MAC);
+        let solicitation = ndp_frame(
+            HOST_MAC,
+            solicited_node(ROUTER).1,
+            HOST,
+            solicited_node(ROUTER).0,
+            &Icmpv6Message::NeighborSolicitation {
+                target: ROUTER,
+                options: vec![NdpOption::SourceLinkLayerAddr(HOST_MAC)],
+            },
+        );
+
+        let reply = responder.process(solicitation.clone()).unwrap();
+        assert_eq!(reply.dest_mac(), HOST_MAC);
+        let message = ndp_message(&reply).unwrap();
+        assert_eq!(message.kind, ICMPV6_NEIGHBOR_ADVERTISEMENT);
+        assert_eq!(message.src, ROUTER);
+        assert_eq!(message.target, ROUTER);
+        assert!(message.solicited && message.override_entry);
+        assert_eq!(message.link_layer_addr, Some(ROUTER_MAC));
+        assert_eq!(Ipv6Packet::try_from(reply).unwrap().dest_addr(), HOST);
+
+        assert_eq!(cache.state(&HOST), Some(NeighborState::Stale));
+        assert_eq!(cache.link_layer_addr(&HOST), Some(HOST_MAC));
+
+        // Solicitations for other nodes, or that were forwarded, aren't answered.
+        let mut other = NdpResponder::new(NeighborCache::new(), vec![HOST], HOST_MAC);
+        assert_eq!(other.process(solicitation.clone()), None);
+        let mut forwarded = solicitation;
+        forwarded.data[14 + 7] = 254;
+        assert_eq!(responder.process(forwarded), None);
+    }
+
+    #[test]
+    fn resolves_next_hops() {
+        let cache = NeighborCache::new();
+        let mut responder = NdpResponder::new(cache.clone(), vec![ROUTER], ROUTER_MAC);
+        let mut resolver = NdpResolver::new(cache.clone(), ROUTER, ROUTER_MAC);
+        let now = Instant::now();
+
+        let solicitation = resolver.process_at(ipv6_frame(HOST), now).unwrap();
+        assert_eq!(solicitation.len(), 1);
+        assert_eq!(
+            solicitation[0].dest_mac(),
+            MacAddr::new([0x33, 0x33, 0xff, 0xab, 0xcd, 0xef])
+        );
+        let message = ndp_message(&solicitation[0]).unwrap();
+        assert_eq!(message.kind, ICMPV6_NEIGHBOR_SOLICITATION);
+        assert_eq!(message.target, HOST);
+        assert_eq!(message.link_layer_addr, Some(ROUTER_MAC));
+        assert_eq!(cache.state(&HOST), Some(NeighborState::Incomplete));
+
+        // Packets wait while the solicitation is outstanding.
+        assert_eq!(resolver.process_at(ipv6_frame(HOST), now), None);
+
+        let answer = host_advertisement(true, HOST_MAC);
+        assert_eq!(responder.process_at(answer, now), None);
+        assert_eq!(cache.state_at(&HOST, now), Some(NeighborState::Reachable));
+
+        let sent = resolver.process_at(ipv6_frame(HOST), now).unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].src_mac(), ROUTER_MAC);
+        assert_eq!(sent[0].dest_mac(), HOST_MAC);
+    }
+
+    #[test]
+    fn gives_up_on_silent_neighbors() {
+        let cache = NeighborCache::new().max_solicitations(2);
+        let mut resolver = NdpResolver::new(cache.clone(), ROUTER, ROUTER_MAC);
+        let now = Instant::now();
+        let second = Duration::from_secs(1);
+
+        assert!(resolver.process_at(ipv6_frame(HOST), now).is_some());
+        assert!(resolver
+            .process_at(ipv6_frame(HOST), now + second)
+            .is_some());
+        assert_eq!(
+            resolver.process_at(ipv6_frame(HOST), now + second * 2),
+            None
+        );
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn reachability_state_machine() {
+        let cache = NeighborCache::new();
+        let now = Instant::now();
+        let second = Duration::from_secs(1);
+
+        cache.resolve_at(HOST, now);
+        cache.advertised_at(HOST, Some(HOST_MAC), true, true, now);
+        assert_eq!(cache.state_at(&HOST, now), Some(NeighborState::Reachable));
+        assert_eq!(
+            cache.resolve_at(HOST, now + second * 29),
+            Resolution::Send(HOST_MAC)
+        );
+
+        // Once the reachable time passes, the neighbor is used while waiting to be confirmed.
+        let later = now + second * 30;
+        assert_eq!(cache.state_at(&HOST, later), Some(NeighborState::Stale));
+        assert_eq!(cache.resolve_at(HOST, later), Resolution::Send(HOST_MAC));
+        assert_eq!(cache.state_at(&HOST, later), Some(NeighborState::Delay));
+
+        // If no confirmation comes, it's probed.
+        let later = later + second * 5;
+        assert_eq!(
+            cache.resolve_at(HOST, later),
+            Resolution::SendAndProbe(HOST_MAC)
+        );
+        assert_eq!(cache.state_at(&HOST, later), Some(NeighborState::Probe));
+        assert_eq!(cache.resolve_at(HOST, later), Resolution::Send(HOST_MAC));
+
+        cache.advertised_at(HOST, Some(HOST_MAC), true, false, later);
+        assert_eq!(cache.state_at(&HOST, later), Some(NeighborState::Reachable));
+
+        // Without the override flag, a different address only makes the entry suspect.
+        let other_mac = MacAddr::new([2, 0, 0, 0, 0, 3]);
+        cache.advertised_at(HOST, Some(other_mac), false, false, later);
+        assert_eq!(cache.state_at(&HOST, later), Some(NeighborState::Stale));
+        assert_eq!(cache.link_layer_addr(&HOST), Some(HOST_MAC));
+        cache.advertised_at(HOST, Some(other_mac), false, true, later);
+        assert_eq!(cache.link_layer_addr(&HOST), Some(other_mac));
+    }
+}
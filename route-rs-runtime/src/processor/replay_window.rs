@@ -0,0 +1,78 @@
+/// Counters further than this behind the greatest seen are rejected as replays.
+const REPLAY_WINDOW_WORDS: usize = 32;
+const REPLAY_WINDOW: u64 = (REPLAY_WINDOW_WORDS as u64 - 1) * 64;
+
+/// The counters received, to reject replays, per RFC 6479. The bitmap is a ring of words, of
+/// which the one holding the greatest counter is the newest.
+pub(crate) struct ReplayWindow {
+    greatest: u64,
+    bitmap: [u64; REPLAY_WINDOW_WORDS],
+}
+
+impl ReplayWindow {
+    pub(crate) fn new() -> Self {
+        ReplayWindow {
+            greatest: 0,
+            bitmap: [0; REPLAY_WINDOW_WORDS],
+        }
+    }
+
+    fn bit(counter: u64) -> (usize, u64) {
+        (
+            (counter / 64) as usize % REPLAY_WINDOW_WORDS,
+            1 << (counter % 64),
+        )
+    }
+
+    /// Whether `counter` may be accepted, if its message is authentic.
+    pub(crate) fn check(&self, counter: u64) -> bool {
+        if counter > self.greatest {
+            return true;
+        }
+        if self.greatest - counter >= REPLAY_WINDOW {
+            return false;
+        }
+        let (word, bit) = Self::bit(counter);
+        self.bitmap[word] & bit == 0
+    }
+
+    /// Records `counter` as received, if it may be accepted.
+    pub(crate) fn accept(&mut self, counter: u64) -> bool {
+        if !self.check(counter) {
+            return false;
+        }
+        if counter > self.greatest {
+            let current = self.greatest / 64;
+            let advance = (counter / 64 - current).min(REPLAY_WINDOW_WORDS as u64);
+            for word in 1..=advance {
+                self.bitmap[((current + word) as usize) % REPLAY_WINDOW_WORDS] = 0;
+            }
+            self.greatest = counter;
+        }
+        let (word, bit) = Self::bit(counter);
+        self.bitmap[word] |= bit;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_window() {
+        let mut window = ReplayWindow::new();
+        assert!(window.accept(0));
+        assert!(!window.accept(0));
+        assert!(window.accept(5000));
+        assert!(window.accept(4999));
+        assert!(window.accept(5000 - REPLAY_WINDOW + 1));
+        assert!(!window.accept(5000 - REPLAY_WINDOW));
+        assert!(!window.accept(4999));
+
+        // Jumping further than the window clears it all.
+        assert!(window.accept(100_000));
+        assert!(window.accept(100_000 - 64));
+        assert!(!window.check(100_000));
+    }
+}
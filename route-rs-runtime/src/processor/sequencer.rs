@@ -0,0 +1,54 @@
+use crate::processor::Processor;
+use std::marker::PhantomData;
+
+/// A packet tagged with its position in the stream it came from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sequenced<P> {
+    pub sequence: u64,
+    pub packet: P,
+}
+
+/// Tags each packet with a monotonically increasing sequence number, starting at 0. Place this
+/// before fanning packets out across parallel pipelines, and a `ReorderLink` where they are joined
+/// back together to restore the original order.
+#[derive(Default)]
+pub struct Sequencer<P: Send + Clone> {
+    next_sequence: u64,
+    phantom: PhantomData<P>,
+}
+
+impl<P: Send + Clone> Sequencer<P> {
+    pub fn new() -> Self {
+        Sequencer {
+            next_sequence: 0,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<P: Send + Clone> Processor for Sequencer<P> {
+    type Input = P;
+    type Output = Sequenced<P>;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        Some(Sequenced { sequence, packet })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_packets_in_order() {
+        let mut sequencer = Sequencer::new();
+
+        let sequences: Vec<u64> = vec!['a', 'b', 'c']
+            .into_iter()
+            .map(|packet| sequencer.process(packet).unwrap().sequence)
+            .collect();
+        assert_eq!(sequences, vec![0, 1, 2]);
+    }
+}
@@ -0,0 +1,292 @@
+use crate::processor::{Annotated, Processor};
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+
+/// A packet stamped with the instant it was received, along with the latency recorded by
+/// each `StageLatency` it has passed through since.
+#[derive(Clone, Debug)]
+pub struct Timestamped<P> {
+    pub packet: P,
+    pub received: Instant,
+    /// When the packet must leave the router by, if it's worth nothing late, such as real time
+    /// media.
+    pub deadline: Option<Instant>,
+    /// Time elapsed since `received`, as observed by each stage in order.
+    pub stage_latencies: Vec<Duration>,
+}
+
+impl<P> Timestamped<P> {
+    pub fn new(packet: P) -> Self {
+        Timestamped::received_at(packet, Instant::now())
+    }
+
+    /// Stamps the packet with when it was received, such as the time the interface or its
+    /// driver recorded, which is closer to the packet's arrival than any time taken later.
+    pub fn received_at(packet: P, received: Instant) -> Self {
+        Timestamped {
+            packet,
+            received,
+            deadline: None,
+            stage_latencies: vec![],
+        }
+    }
+
+    pub fn with_deadline(self, deadline: Instant) -> Self {
+        Timestamped {
+            deadline: Some(deadline),
+            ..self
+        }
+    }
+
+    /// Whether the packet has missed its deadline, if it has one, by `now`.
+    pub fn is_late(&self, now: Instant) -> bool {
+        matches!(self.deadline, Some(deadline) if now > deadline)
+    }
+
+    /// Latency added by each stage, rather than the running total.
+    pub fn stage_deltas(&self) -> Vec<Duration> {
+        let mut previous = Duration::from_secs(0);
+        self.stage_latencies
+            .iter()
+            .map(|latency| {
+                let delta = *latency - previous;
+                previous = *latency;
+                delta
+            })
+            .collect()
+    }
+}
+
+/// Packets that carry when they were received, so links that measure or act on delay, like
+/// `CoDelQueueLink`, can use the time the packet reached the router, rather than the time it
+/// reached them.
+pub trait ReceiveTime {
+    fn received(&self) -> Option<Instant>;
+
+    /// When the packet must leave the router by, if it has a deadline.
+    fn deadline(&self) -> Option<Instant> {
+        None
+    }
+}
+
+impl<P> ReceiveTime for Timestamped<P> {
+    fn received(&self) -> Option<Instant> {
+        Some(self.received)
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+}
+
+/// Annotated packets carry their receive time in their metadata, which may be the `Instant`
+/// itself.
+impl<P, M: ReceiveTime> ReceiveTime for Annotated<P, M> {
+    fn received(&self) -> Option<Instant> {
+        self.metadata.received()
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.metadata.deadline()
+    }
+}
+
+impl ReceiveTime for Instant {
+    fn received(&self) -> Option<Instant> {
+        Some(*self)
+    }
+}
+
+/// Reads the receive time and deadline a packet already carries.
+type ReceiveTimeFn<P> = fn(&P) -> (Option<Instant>, Option<Instant>);
+
+/// Stamps packets with their receive time. Place this as close to the ingress of the
+/// router as possible, or use `from_receive_time` to keep a time the packet already carries.
+pub struct Timestamp<P: Send + Clone> {
+    receive_time: Option<ReceiveTimeFn<P>>,
+    budget: Option<Duration>,
+    phantom: PhantomData<P>,
+}
+
+impl<P: Send + Clone> Default for Timestamp<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: Send + Clone> Timestamp<P> {
+    pub fn new() -> Self {
+        Timestamp {
+            receive_time: None,
+            budget: None,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Changes budget, how long packets may take through the router, setting the deadline of
+    /// those without one, default is none.
+    pub fn budget(self, budget: Duration) -> Self {
+        Timestamp {
+            budget: Some(budget),
+            ..self
+        }
+    }
+}
+
+impl<P: ReceiveTime + Send + Clone> Timestamp<P> {
+    /// Stamps packets with the receive time and deadline they already carry, falling back to now
+    /// for packets without one.
+    pub fn from_receive_time() -> Self {
+        Timestamp {
+            receive_time: Some(receive_time::<P>),
+            ..Self::new()
+        }
+    }
+}
+
+fn receive_time<P: ReceiveTime>(packet: &P) -> (Option<Instant>, Option<Instant>) {
+    (packet.received(), packet.deadline())
+}
+
+impl<P: Send + Clone> Processor for Timestamp<P> {
+    type Input = P;
+    type Output = Timestamped<P>;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        let (received, deadline) = match self.receive_time {
+            Some(receive_time) => receive_time(&packet),
+            None => (None, None),
+        };
+        let received = received.unwrap_or_else(Instant::now);
+        let mut packet = Timestamped::received_at(packet, received);
+        packet.deadline = deadline.or_else(|| self.budget.map(|budget| received + budget));
+        Some(packet)
+    }
+}
+
+/// Drops packets that have missed their deadline, rather than spend any more of the router's
+/// time, or the link's, on them. Place it before a bottleneck, like a slow egress queue.
+#[derive(Default)]
+pub struct DropLate<P: Send + Clone> {
+    phantom: PhantomData<P>,
+}
+
+impl<P: Send + Clone> DropLate<P> {
+    pub fn new() -> Self {
+        DropLate {
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<P: Send + Clone> Processor for DropLate<P> {
+    type Input = Timestamped<P>;
+    type Output = Timestamped<P>;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        if packet.is_late(Instant::now()) {
+            None
+        } else {
+            Some(packet)
+        }
+    }
+}
+
+/// Records how long it has been since the packet was received, appending it to the
+/// packet's `stage_latencies`. Chaining several gives a latency profile of the pipeline.
+#[derive(Default)]
+pub struct StageLatency<P: Send + Clone> {
+    phantom: PhantomData<P>,
+}
+
+impl<P: Send + Clone> StageLatency<P> {
+    pub fn new() -> Self {
+        StageLatency {
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<P: Send + Clone> Processor for StageLatency<P> {
+    type Input = Timestamped<P>;
+    type Output = Timestamped<P>;
+
+    fn process(&mut self, mut packet: Self::Input) -> Option<Self::Output> {
+        let latency = packet.received.elapsed();
+        packet.stage_latencies.push(latency);
+        Some(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+
+    /// Stand-in for a stage that takes a while to do its work.
+    struct Delay {
+        duration: Duration,
+    }
+
+    impl Processor for Delay {
+        type Input = Timestamped<i32>;
+        type Output = Timestamped<i32>;
+
+        fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+            sleep(self.duration);
+            Some(packet)
+        }
+    }
+
+    #[test]
+    fn stamps_packets() {
+        let before = Instant::now();
+        let packet = Timestamp::new().process(42).unwrap();
+
+        assert_eq!(packet.packet, 42);
+        assert!(packet.received >= before);
+        assert!(packet.stage_latencies.is_empty());
+    }
+
+    #[test]
+    fn keeps_receive_time_and_drops_late_packets() {
+        let received = Instant::now() - Duration::from_millis(50);
+        let mut timestamp = Timestamp::from_receive_time().budget(Duration::from_millis(20));
+        let packet = timestamp.process(Annotated::new(7, received)).unwrap();
+        assert_eq!(packet.received, received);
+        assert_eq!(packet.deadline, Some(received + Duration::from_millis(20)));
+        assert!(DropLate::new().process(packet).is_none());
+
+        let on_time = Timestamp::new()
+            .budget(Duration::from_secs(60))
+            .process(7)
+            .unwrap();
+        assert!(on_time.received > received);
+        assert!(DropLate::new().process(on_time).is_some());
+    }
+
+    #[test]
+    fn latency_increases_through_delaying_stages() {
+        let mut delay = Delay {
+            duration: Duration::from_millis(5),
+        };
+        let mut stage = StageLatency::new();
+
+        let mut packet = Timestamp::new().process(0).unwrap();
+        for _ in 0..3 {
+            packet = delay.process(packet).unwrap();
+            packet = stage.process(packet).unwrap();
+        }
+
+        assert_eq!(packet.stage_latencies.len(), 3);
+        assert!(packet.stage_latencies[0] >= Duration::from_millis(5));
+        assert!(packet
+            .stage_latencies
+            .windows(2)
+            .all(|pair| pair[0] < pair[1]));
+        assert!(packet
+            .stage_deltas()
+            .iter()
+            .all(|delta| *delta >= Duration::from_millis(5)));
+    }
+}
@@ -0,0 +1,245 @@
+use crate::processor::{Annotated, Processor};
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The trace flag of a packet marked by `MarkTrace`, that the links it passes through append
+/// their hops to. Copies of the packet, as made by a ForkLink, share the trace. Once the packet
+/// and every copy of it are gone, whether they left the router or were dropped, the trace is
+/// emitted to the packet's `TraceLog`.
+#[derive(Clone)]
+pub struct Trace {
+    state: Arc<TraceState>,
+}
+
+struct TraceState {
+    id: u64,
+    marked: Instant,
+    hops: Mutex<Vec<Hop>>,
+    log: TraceLog,
+}
+
+impl Trace {
+    fn new(log: &TraceLog) -> Self {
+        Trace {
+            state: Arc::new(TraceState {
+                id: log.state.next_id.fetch_add(1, Ordering::Relaxed),
+                marked: Instant::now(),
+                hops: Mutex::new(vec![]),
+                log: log.clone(),
+            }),
+        }
+    }
+
+    /// Identifies the packet among those traced into the same log, in the order they were marked.
+    pub fn id(&self) -> u64 {
+        self.state.id
+    }
+
+    /// Appends a hop to the trace, timestamped now.
+    pub fn record(&self, link: &str, event: HopEvent) {
+        self.state.hops.lock().unwrap().push(Hop {
+            link: link.to_string(),
+            event,
+            at: Instant::now(),
+        });
+    }
+}
+
+impl fmt::Debug for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Trace").field("id", &self.state.id).finish()
+    }
+}
+
+impl Drop for TraceState {
+    fn drop(&mut self) {
+        let trace = PacketTrace {
+            id: self.id,
+            marked: self.marked,
+            hops: mem::take(self.hops.get_mut().unwrap()),
+        };
+        self.log.state.traces.lock().unwrap().push(trace);
+    }
+}
+
+/// A link a traced packet entered or left.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hop {
+    pub link: String,
+    pub event: HopEvent,
+    pub at: Instant,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HopEvent {
+    /// The packet was taken by one of the link's ingressors.
+    Enter,
+    /// The packet was given out by one of the link's egressors.
+    Exit,
+}
+
+/// The path a traced packet took through the router, as emitted once it's gone. A packet
+/// dropped by a link has entered it but not left it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PacketTrace {
+    pub id: u64,
+    /// When the packet was marked.
+    pub marked: Instant,
+    pub hops: Vec<Hop>,
+}
+
+impl PacketTrace {
+    /// The hops as `(link, event, time since the packet was marked)`.
+    pub fn path(&self) -> Vec<(&str, HopEvent, Duration)> {
+        self.hops
+            .iter()
+            .map(|hop| (hop.link.as_str(), hop.event, hop.at - self.marked))
+            .collect()
+    }
+}
+
+/// Prints the trace on one line, such as `trace 3: > classify 12µs, classify > 40µs`, where
+/// `> link` is entering a link and `link >` leaving it.
+impl fmt::Display for PacketTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "trace {}:", self.id)?;
+        for (i, (link, event, elapsed)) in self.path().into_iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            match event {
+                HopEvent::Enter => write!(f, "{} > {} {:?}", separator, link, elapsed)?,
+                HopEvent::Exit => write!(f, "{} {} > {:?}", separator, link, elapsed)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where the traces of packets marked by a `MarkTrace` are emitted, to be taken and inspected,
+/// or printed, while the router runs. Clones share the log.
+#[derive(Clone, Default)]
+pub struct TraceLog {
+    state: Arc<LogState>,
+}
+
+#[derive(Default)]
+struct LogState {
+    next_id: AtomicU64,
+    traces: Mutex<Vec<PacketTrace>>,
+}
+
+impl TraceLog {
+    pub fn new() -> Self {
+        TraceLog::default()
+    }
+
+    /// Takes the traces emitted since the last call, in the order they were emitted.
+    pub fn take(&self) -> Vec<PacketTrace> {
+        mem::take(&mut *self.state.traces.lock().unwrap())
+    }
+}
+
+/// Packets that may carry a trace, so links wrapped in a `TracePoint` can record their hops.
+pub trait Traced {
+    fn trace(&self) -> Option<&Trace>;
+}
+
+impl Traced for Trace {
+    fn trace(&self) -> Option<&Trace> {
+        Some(self)
+    }
+}
+
+impl Traced for Option<Trace> {
+    fn trace(&self) -> Option<&Trace> {
+        self.as_ref()
+    }
+}
+
+/// Annotated packets carry their trace in their metadata, as `MarkTrace` puts it there.
+impl<P, M: Traced> Traced for Annotated<P, M> {
+    fn trace(&self) -> Option<&Trace> {
+        self.metadata.trace()
+    }
+}
+
+/// Marks the packets a predicate picks out for tracing, annotating each packet with a trace
+/// emitted to `log`, or none for packets it leaves alone. Trace as few packets as will do, since
+/// every hop of a traced packet takes a lock.
+pub struct MarkTrace<P, F> {
+    predicate: F,
+    log: TraceLog,
+    phantom: PhantomData<P>,
+}
+
+impl<P, F> MarkTrace<P, F>
+where
+    F: FnMut(&P) -> bool,
+{
+    pub fn new(predicate: F, log: &TraceLog) -> Self {
+        MarkTrace {
+            predicate,
+            log: log.clone(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<P, F> Processor for MarkTrace<P, F>
+where
+    P: Send + Clone,
+    F: FnMut(&P) -> bool,
+{
+    type Input = P;
+    type Output = Annotated<P, Option<Trace>>;
+
+    fn process(&mut self, packet: Self::Input) -> Option<Self::Output> {
+        let trace = if (self.predicate)(&packet) {
+            Some(Trace::new(&self.log))
+        } else {
+            None
+        };
+        Some(Annotated::new(packet, trace))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marks_matching_packets() {
+        let log = TraceLog::new();
+        let mut mark = MarkTrace::new(|packet: &i32| *packet % 2 == 0, &log);
+        let packets: Vec<_> = (0..4).filter_map(|packet| mark.process(packet)).collect();
+        let ids: Vec<Option<u64>> = packets
+            .iter()
+            .map(|packet| packet.trace().map(Trace::id))
+            .collect();
+        assert_eq!(ids, vec![Some(0), None, Some(1), None]);
+
+        packets[2].trace().unwrap().record("queue", HopEvent::Enter);
+        let copy = packets[2].clone();
+        drop(packets);
+        // The untouched first trace is emitted, but a copy of the second packet is still around.
+        assert_eq!(log.take().len(), 1);
+
+        copy.trace().unwrap().record("queue", HopEvent::Exit);
+        drop(copy);
+        let traces = log.take();
+        assert_eq!(traces.len(), 1);
+        let path: Vec<(&str, HopEvent)> = traces[0]
+            .path()
+            .into_iter()
+            .map(|(link, event, _)| (link, event))
+            .collect();
+        assert_eq!(
+            path,
+            vec![("queue", HopEvent::Enter), ("queue", HopEvent::Exit)]
+        );
+        assert!(traces[0].to_string().starts_with("trace 1: > queue "));
+    }
+}
@@ -0,0 +1,151 @@
+use crate::processor::Processor;
+use route_rs_packets::{EthernetFrame, VlanTag};
+
+/// Tags untagged frames with an 802.1Q tag, as they leave through a trunk port. Frames that are
+/// already tagged are dropped.
+pub struct VlanPush {
+    tag: VlanTag,
+}
+
+impl VlanPush {
+    pub fn new(tag: VlanTag) -> Self {
+        VlanPush { tag }
+    }
+}
+
+impl Processor for VlanPush {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, mut frame: Self::Input) -> Option<Self::Output> {
+        match frame.push_vlan_tag(self.tag) {
+            Ok(()) => Some(frame),
+            Err(_) => None,
+        }
+    }
+}
+
+/// Strips the 802.1Q tag from tagged frames, as they arrive from a trunk port. Untagged frames
+/// pass unchanged.
+#[derive(Default)]
+pub struct VlanPop {}
+
+impl VlanPop {
+    pub fn new() -> Self {
+        VlanPop {}
+    }
+}
+
+impl Processor for VlanPop {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, mut frame: Self::Input) -> Option<Self::Output> {
+        frame.pop_vlan_tag();
+        Some(frame)
+    }
+}
+
+/// Moves tagged frames to VLAN `vid`, keeping their priority unless a new one is set with `pcp`.
+/// Untagged frames pass unchanged.
+pub struct VlanRewrite {
+    vid: u16,
+    pcp: Option<u8>,
+}
+
+impl VlanRewrite {
+    pub fn new(vid: u16) -> Self {
+        VlanRewrite { vid, pcp: None }
+    }
+
+    /// Sets the priority code point of rewritten tags as well.
+    pub fn pcp(self, pcp: u8) -> Self {
+        assert!(pcp < 8, "VlanRewrite pcp: {}, must be < 8", pcp);
+
+        VlanRewrite {
+            vid: self.vid,
+            pcp: Some(pcp),
+        }
+    }
+}
+
+impl Processor for VlanRewrite {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, mut frame: Self::Input) -> Option<Self::Output> {
+        if let Some(tag) = frame.vlan_tag() {
+            let tag = VlanTag {
+                pcp: self.pcp.unwrap_or(tag.pcp),
+                dei: tag.dei,
+                vid: self.vid,
+            };
+            frame
+                .set_vlan_tag(tag)
+                .expect("VlanRewrite set_vlan_tag on a tagged frame shouldn't fail");
+        }
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use route_rs_packets::{Ipv4Packet, IPV4_ETHER_TYPE};
+
+    fn untagged_frame() -> EthernetFrame {
+        EthernetFrame::encap_ipv4(Ipv4Packet::empty())
+    }
+
+    #[test]
+    fn push_then_pop() {
+        let tagged = VlanPush::new(VlanTag::new(10))
+            .process(untagged_frame())
+            .unwrap();
+        assert_eq!(tagged.vlan_tag(), Some(VlanTag::new(10)));
+        assert_eq!(tagged.ether_type(), IPV4_ETHER_TYPE);
+
+        assert!(VlanPush::new(VlanTag::new(20))
+            .process(tagged.clone())
+            .is_none());
+
+        let popped = VlanPop::new().process(tagged).unwrap();
+        assert_eq!(popped, untagged_frame());
+        assert_eq!(VlanPop::new().process(popped), Some(untagged_frame()));
+    }
+
+    #[test]
+    fn rewrite() {
+        let mut tagged = untagged_frame();
+        tagged
+            .push_vlan_tag(VlanTag {
+                pcp: 3,
+                dei: true,
+                vid: 10,
+            })
+            .unwrap();
+
+        let rewritten = VlanRewrite::new(20).process(tagged.clone()).unwrap();
+        assert_eq!(
+            rewritten.vlan_tag(),
+            Some(VlanTag {
+                pcp: 3,
+                dei: true,
+                vid: 20
+            })
+        );
+
+        let rewritten = VlanRewrite::new(30).pcp(6).process(tagged).unwrap();
+        assert_eq!(
+            rewritten.vlan_tag(),
+            Some(VlanTag {
+                pcp: 6,
+                dei: true,
+                vid: 30
+            })
+        );
+
+        let untagged = VlanRewrite::new(20).process(untagged_frame()).unwrap();
+        assert_eq!(untagged.vlan_tag(), None);
+    }
+}
@@ -0,0 +1,204 @@
+use crate::processor::Processor;
+pub use route_rs_packets::VXLAN_PORT;
+use route_rs_packets::{
+    EthernetFrame, Ipv4Packet, MacAddr, UdpSegment, VxlanPacket, IPV4_ETHER_TYPE,
+};
+use std::collections::hash_map::DefaultHasher;
+use std::convert::TryFrom;
+use std::hash::Hasher;
+use std::net::Ipv4Addr;
+
+/// TTL of the outer packets we send.
+const OUTER_TTL: u8 = 64;
+
+/// An Ethernet frame, tagged with the VXLAN Network Identifier of the segment it came from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VniFrame {
+    pub vni: u32,
+    pub frame: EthernetFrame,
+}
+
+/// Encapsulates Ethernet frames in VXLAN, on segment `vni`, with outer Ethernet, IPv4 and UDP
+/// headers from `src` to `dst`, the local and remote VTEPs. The outer frame's MAC addresses are
+/// zero unless set. The UDP source port is a hash of the inner frame's Ethernet header, as RFC
+/// 7348 suggests, so routers between the VTEPs can balance segments' flows over their paths.
+pub struct VxlanEncap {
+    vni: u32,
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    src_mac: MacAddr,
+    dst_mac: MacAddr,
+    port: u16,
+}
+
+impl VxlanEncap {
+    pub fn new(vni: u32, src: Ipv4Addr, dst: Ipv4Addr) -> Self {
+        assert!(vni < 1 << 24, "VxlanEncap vni: {}, must be < 2^24", vni);
+
+        VxlanEncap {
+            vni,
+            src,
+            dst,
+            src_mac: MacAddr::new([0; 6]),
+            dst_mac: MacAddr::new([0; 6]),
+            port: VXLAN_PORT,
+        }
+    }
+
+    pub fn src_mac(self, src_mac: MacAddr) -> Self {
+        VxlanEncap { src_mac, ..self }
+    }
+
+    pub fn dst_mac(self, dst_mac: MacAddr) -> Self {
+        VxlanEncap { dst_mac, ..self }
+    }
+
+    /// Sends to UDP port `port` rather than 4789.
+    pub fn port(self, port: u16) -> Self {
+        VxlanEncap { port, ..self }
+    }
+}
+
+impl Processor for VxlanEncap {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, frame: Self::Input) -> Option<Self::Output> {
+        let inner = &frame.data[frame.layer2_offset..];
+
+        let mut hasher = DefaultHasher::new();
+        hasher.write(&inner[..14.min(inner.len())]);
+        // Source ports from the dynamic range, 49152 to 65535.
+        let src_port = (hasher.finish() as u16) | 0xC000;
+
+        let vxlan = VxlanPacket::new(self.vni, &frame);
+        let udp_len = (8 + vxlan.data.len()) as u16;
+        let mut udp = Vec::with_capacity(usize::from(udp_len));
+        udp.extend_from_slice(&src_port.to_be_bytes());
+        udp.extend_from_slice(&self.port.to_be_bytes());
+        udp.extend_from_slice(&udp_len.to_be_bytes());
+        // A zero checksum, meaning none, as RFC 7348 recommends for VXLAN over IPv4.
+        udp.extend_from_slice(&[0, 0]);
+        udp.extend(vxlan.data);
+
+        let mut packet = Ipv4Packet::empty();
+        packet.set_ttl(OUTER_TTL);
+        packet.set_protocol(17);
+        packet.set_src_addr(self.src);
+        packet.set_dest_addr(self.dst);
+        packet.set_payload(&udp);
+        packet.set_checksum();
+
+        let mut outer = EthernetFrame::encap_ipv4(packet);
+        outer.set_src_mac(self.src_mac);
+        outer.set_dest_mac(self.dst_mac);
+        Some(outer)
+    }
+}
+
+/// Decapsulates Ethernet frames from VXLAN, tagging each with the VNI of its segment, so
+/// downstream links can classify them by segment. Frames that aren't VXLAN over IPv4 to UDP
+/// port 4789, or `port` if set, or have no valid VNI, are dropped.
+pub struct VxlanDecap {
+    port: u16,
+}
+
+impl Default for VxlanDecap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VxlanDecap {
+    pub fn new() -> Self {
+        VxlanDecap { port: VXLAN_PORT }
+    }
+
+    /// Accepts VXLAN on UDP port `port` rather than 4789.
+    pub fn port(self, port: u16) -> Self {
+        VxlanDecap { port }
+    }
+}
+
+impl Processor for VxlanDecap {
+    type Input = EthernetFrame;
+    type Output = VniFrame;
+
+    fn process(&mut self, frame: Self::Input) -> Option<Self::Output> {
+        if frame.ether_type() != IPV4_ETHER_TYPE {
+            return None;
+        }
+        let packet = Ipv4Packet::try_from(frame).ok()?;
+        if packet.data[packet.layer3_offset + 9] != 17 || packet.fragment_offset() != 0 {
+            return None;
+        }
+
+        let udp = UdpSegment::try_from(packet).ok()?;
+        if udp.dest_port() != self.port {
+            return None;
+        }
+        let vxlan = VxlanPacket::try_from(udp).ok()?;
+        let vni = vxlan.vni()?;
+
+        let frame = vxlan.inner_frame().ok()?;
+        Some(VniFrame { vni, frame })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCAL: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+    const REMOTE: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 2);
+
+    fn inner_frame() -> EthernetFrame {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_payload(&[1, 2, 3]);
+        let mut frame = EthernetFrame::encap_ipv4(packet);
+        frame.set_src_mac(MacAddr::new([2, 0, 0, 0, 0, 1]));
+        frame.set_dest_mac(MacAddr::new([2, 0, 0, 0, 0, 2]));
+        frame
+    }
+
+    #[test]
+    fn round_trip() {
+        let vtep_mac = MacAddr::new([0xaa, 0, 0, 0, 0, 1]);
+        let outer = VxlanEncap::new(5001, LOCAL, REMOTE)
+            .src_mac(vtep_mac)
+            .process(inner_frame())
+            .unwrap();
+        assert_eq!(outer.src_mac(), vtep_mac);
+        assert_eq!(outer.ether_type(), IPV4_ETHER_TYPE);
+
+        let mut packet = Ipv4Packet::try_from(outer.clone()).unwrap();
+        assert!(packet.validate_checksum());
+        assert_eq!(packet.src_addr(), LOCAL);
+        assert_eq!(packet.dest_addr(), REMOTE);
+        let udp = packet.payload();
+        assert_eq!(u16::from_be_bytes([udp[2], udp[3]]), VXLAN_PORT);
+        assert!(u16::from_be_bytes([udp[0], udp[1]]) >= 49152);
+        assert_eq!(usize::from(u16::from_be_bytes([udp[4], udp[5]])), udp.len());
+
+        let decapsulated = VxlanDecap::new().process(outer).unwrap();
+        assert_eq!(
+            decapsulated,
+            VniFrame {
+                vni: 5001,
+                frame: inner_frame()
+            }
+        );
+    }
+
+    #[test]
+    fn drops_non_vxlan() {
+        assert_eq!(VxlanDecap::new().process(inner_frame()), None);
+
+        let outer = VxlanEncap::new(1, LOCAL, REMOTE)
+            .port(8472)
+            .process(inner_frame())
+            .unwrap();
+        assert_eq!(VxlanDecap::new().process(outer.clone()), None);
+        assert_eq!(VxlanDecap::new().port(8472).process(outer).unwrap().vni, 1);
+    }
+}
@@ -0,0 +1,578 @@
+use crate::link::{PacketStream, TokioRunnable};
+use crate::processor::{Processor, ReplayWindow};
+use chacha20poly1305::aead::Aead;
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use futures::channel::mpsc;
+use route_rs_packets::{EthernetFrame, Ipv4Packet, MacAddr, IPV4_ETHER_TYPE, IPV6_ETHER_TYPE};
+use std::convert::{TryFrom, TryInto};
+use std::net::SocketAddrV4;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The UDP port WireGuard listens on by default.
+pub const WIREGUARD_PORT: u16 = 51820;
+/// TTL of the outer packets we send.
+const OUTER_TTL: u8 = 64;
+
+/// The type of transport data messages, and the length of their header: the type, 3 reserved
+/// bytes, the receiver's index and the counter.
+const TRANSPORT_DATA: u8 = 4;
+const HEADER_LEN: usize = 16;
+const TAG_LEN: usize = 16;
+
+/// Keys must be replaced by a new handshake before a counter reaches this.
+const REJECT_AFTER_MESSAGES: u64 = u64::MAX - (1 << 13);
+/// How long after receiving a packet, with nothing sent since, a keepalive is sent.
+const KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often the keepalive timer checks whether one is due.
+const TIMER_TICK: Duration = Duration::from_millis(100);
+
+/// The transport keys of a WireGuard session, as derived by a handshake done elsewhere, and the
+/// indices each side chose for it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WireGuardKeys {
+    /// The index the peer sends to us with.
+    pub local_index: u32,
+    /// The index we send to the peer with.
+    pub remote_index: u32,
+    pub send_key: [u8; 32],
+    pub recv_key: [u8; 32],
+}
+
+struct SessionState {
+    local_index: u32,
+    remote_index: u32,
+    send_cipher: ChaCha20Poly1305,
+    recv_cipher: ChaCha20Poly1305,
+    send_counter: u64,
+    replay: ReplayWindow,
+    last_sent: Option<Instant>,
+    last_received: Option<Instant>,
+    persistent_keepalive: Option<Duration>,
+}
+
+impl SessionState {
+    fn install(&mut self, keys: WireGuardKeys) {
+        self.local_index = keys.local_index;
+        self.remote_index = keys.remote_index;
+        self.send_cipher = ChaCha20Poly1305::new(Key::from_slice(&keys.send_key));
+        self.recv_cipher = ChaCha20Poly1305::new(Key::from_slice(&keys.recv_key));
+        self.send_counter = 0;
+        self.replay = ReplayWindow::new();
+    }
+
+    /// Encrypts `packet` into a transport data message, or returns None if the keys have been
+    /// used for as many messages as they may be.
+    fn seal(&mut self, packet: &[u8], now: Instant) -> Option<Vec<u8>> {
+        let counter = self.send_counter;
+        if counter >= REJECT_AFTER_MESSAGES {
+            return None;
+        }
+        self.send_counter += 1;
+        self.last_sent = Some(now);
+
+        // Padded to a multiple of 16 bytes, so the length says less of what's inside.
+        let mut plaintext = packet.to_vec();
+        plaintext.resize(packet.len() + (16 - packet.len() % 16) % 16, 0);
+        let ciphertext = self
+            .send_cipher
+            .encrypt(&nonce(counter), &plaintext[..])
+            .ok()?;
+
+        let mut message = vec![TRANSPORT_DATA, 0, 0, 0];
+        message.extend_from_slice(&self.remote_index.to_le_bytes());
+        message.extend_from_slice(&counter.to_le_bytes());
+        message.extend_from_slice(&ciphertext);
+        Some(message)
+    }
+
+    /// Decrypts the transport data message `message`, if it's authentic, for us, and not a
+    /// replay.
+    fn open(&mut self, message: &[u8], now: Instant) -> Option<Vec<u8>> {
+        if message.len() < HEADER_LEN + TAG_LEN || message[0..4] != [TRANSPORT_DATA, 0, 0, 0] {
+            return None;
+        }
+        let receiver = u32::from_le_bytes(message[4..8].try_into().unwrap());
+        let counter = u64::from_le_bytes(message[8..16].try_into().unwrap());
+        if receiver != self.local_index
+            || counter >= REJECT_AFTER_MESSAGES
+            || !self.replay.check(counter)
+        {
+            return None;
+        }
+        let plaintext = self
+            .recv_cipher
+            .decrypt(&nonce(counter), &message[HEADER_LEN..])
+            .ok()?;
+        if !self.replay.accept(counter) {
+            return None;
+        }
+        self.last_received = Some(now);
+        Some(plaintext)
+    }
+
+    /// Whether a keepalive is due: a while after receiving, if nothing has been sent since, or
+    /// every `persistent_keepalive` of sending nothing.
+    fn keepalive_due(&self, now: Instant) -> bool {
+        if let Some(received) = self.last_received {
+            let replied = matches!(self.last_sent, Some(sent) if sent >= received);
+            if !replied && now >= received + KEEPALIVE_TIMEOUT {
+                return true;
+            }
+        }
+        match (self.persistent_keepalive, self.last_sent) {
+            (Some(_), None) => true,
+            (Some(interval), Some(sent)) => now >= sent + interval,
+            (None, _) => false,
+        }
+    }
+}
+
+/// The nonce of the message with `counter`: 4 zero bytes, then the counter, little endian.
+fn nonce(counter: u64) -> Nonce {
+    let mut nonce = [0; 12];
+    nonce[4..].copy_from_slice(&counter.to_le_bytes());
+    *Nonce::from_slice(&nonce)
+}
+
+/// The length of the IPv4 or IPv6 packet at the start of `data`, and its EtherType, if it fits.
+fn ip_packet(data: &[u8]) -> Option<(usize, u16)> {
+    let (len, ether_type) = match data.first()? >> 4 {
+        4 => (
+            usize::from(u16::from_be_bytes([*data.get(2)?, *data.get(3)?])),
+            IPV4_ETHER_TYPE,
+        ),
+        6 => (
+            40 + usize::from(u16::from_be_bytes([*data.get(4)?, *data.get(5)?])),
+            IPV6_ETHER_TYPE,
+        ),
+        _ => return None,
+    };
+    if len > data.len() {
+        return None;
+    }
+    Some((len, ether_type))
+}
+
+/// A WireGuard session with one peer, shared by the WireGuardEncrypt and WireGuardDecrypt of a
+/// tunnel. Holds the transport keys, the counter of the messages sent, and the window of those
+/// received, and when the last of each was. Clones share the session.
+#[derive(Clone)]
+pub struct WireGuardSession {
+    state: Arc<Mutex<SessionState>>,
+}
+
+impl WireGuardSession {
+    pub fn new(keys: WireGuardKeys) -> Self {
+        let send_cipher = ChaCha20Poly1305::new(Key::from_slice(&keys.send_key));
+        let recv_cipher = ChaCha20Poly1305::new(Key::from_slice(&keys.recv_key));
+        WireGuardSession {
+            state: Arc::new(Mutex::new(SessionState {
+                local_index: keys.local_index,
+                remote_index: keys.remote_index,
+                send_cipher,
+                recv_cipher,
+                send_counter: 0,
+                replay: ReplayWindow::new(),
+                last_sent: None,
+                last_received: None,
+                persistent_keepalive: None,
+            })),
+        }
+    }
+
+    /// Sends a keepalive whenever nothing has been sent for `interval`, to keep the mappings of
+    /// NATs between the peers alive. Off by default.
+    pub fn persistent_keepalive(self, interval: Duration) -> Self {
+        assert!(
+            interval > Duration::from_secs(0),
+            "WireGuardSession persistent_keepalive: {:?}, must be > 0",
+            interval
+        );
+
+        self.state.lock().unwrap().persistent_keepalive = Some(interval);
+        self
+    }
+
+    /// Replaces the keys with those of a new handshake. Counters start again from 0.
+    pub fn rekey(&self, keys: WireGuardKeys) {
+        self.state.lock().unwrap().install(keys);
+    }
+
+    /// How many messages have been sent with the current keys.
+    pub fn sent(&self) -> u64 {
+        self.state.lock().unwrap().send_counter
+    }
+}
+
+/// Encrypts IP packets into WireGuard transport data messages to the peer of `session`, sent
+/// from `src` to `dst`, its endpoint, in UDP over IPv4. The outer frame's MAC addresses are zero
+/// unless set. Frames that aren't IPv4 or IPv6 are dropped, as is everything once the keys have
+/// been used for as many messages as they may be, until the session is rekeyed.
+///
+/// Its `keepalives` send the keepalives of the session, the empty messages that tell the peer
+/// the session is alive when there's nothing else to send.
+pub struct WireGuardEncrypt {
+    session: WireGuardSession,
+    endpoints: Endpoints,
+}
+
+impl WireGuardEncrypt {
+    pub fn new(session: WireGuardSession, src: SocketAddrV4, dst: SocketAddrV4) -> Self {
+        WireGuardEncrypt {
+            session,
+            endpoints: Endpoints {
+                src,
+                dst,
+                src_mac: MacAddr::new([0; 6]),
+                dst_mac: MacAddr::new([0; 6]),
+            },
+        }
+    }
+
+    pub fn src_mac(mut self, src_mac: MacAddr) -> Self {
+        self.endpoints.src_mac = src_mac;
+        self
+    }
+
+    pub fn dst_mac(mut self, dst_mac: MacAddr) -> Self {
+        self.endpoints.dst_mac = dst_mac;
+        self
+    }
+
+    fn encrypt_at(&mut self, frame: EthernetFrame, now: Instant) -> Option<EthernetFrame> {
+        let packet = &frame.data[frame.payload_offset..];
+        let (len, _) = ip_packet(packet)?;
+        let message = self
+            .session
+            .state
+            .lock()
+            .unwrap()
+            .seal(&packet[..len], now)?;
+        Some(self.endpoints.outer_frame(&message))
+    }
+
+    /// Returns the runnable that sends keepalives when they're due, and the stream of them. The
+    /// runnable stops once the session, and every clone of it, is dropped.
+    pub fn keepalives(&self) -> (TokioRunnable, PacketStream<EthernetFrame>) {
+        let state = Arc::downgrade(&self.session.state);
+        let endpoints = self.endpoints;
+        let (to_stream, from_timer) = mpsc::unbounded();
+
+        let timer = async move {
+            let mut ticks = tokio::time::interval(TIMER_TICK);
+            loop {
+                ticks.tick().await;
+                let state = match state.upgrade() {
+                    Some(state) => state,
+                    None => break,
+                };
+                let keepalive = endpoints.keepalive_at(&mut state.lock().unwrap(), Instant::now());
+                if let Some(frame) = keepalive {
+                    if to_stream.unbounded_send(frame).is_err() {
+                        break;
+                    }
+                }
+            }
+        };
+        (Box::new(Box::pin(timer)), Box::new(from_timer))
+    }
+}
+
+/// Where a WireGuardEncrypt sends its messages from, and to.
+#[derive(Clone, Copy)]
+struct Endpoints {
+    src: SocketAddrV4,
+    dst: SocketAddrV4,
+    src_mac: MacAddr,
+    dst_mac: MacAddr,
+}
+
+impl Endpoints {
+    /// Wraps `message` in UDP, IPv4 and Ethernet headers, to the peer.
+    fn outer_frame(&self, message: &[u8]) -> EthernetFrame {
+        let udp_len = (8 + message.len()) as u16;
+        let mut udp = Vec::with_capacity(usize::from(udp_len));
+        udp.extend_from_slice(&self.src.port().to_be_bytes());
+        udp.extend_from_slice(&self.dst.port().to_be_bytes());
+        udp.extend_from_slice(&udp_len.to_be_bytes());
+        // A zero checksum, meaning none; the message authenticates itself.
+        udp.extend_from_slice(&[0, 0]);
+        udp.extend_from_slice(message);
+
+        let mut packet = Ipv4Packet::empty();
+        packet.set_ttl(OUTER_TTL);
+        packet.set_protocol(17);
+        packet.set_src_addr(*self.src.ip());
+        packet.set_dest_addr(*self.dst.ip());
+        packet.set_payload(&udp);
+        packet.set_checksum();
+
+        let mut outer = EthernetFrame::encap_ipv4(packet);
+        outer.set_src_mac(self.src_mac);
+        outer.set_dest_mac(self.dst_mac);
+        outer
+    }
+
+    /// Returns a keepalive of the session, if one is due at `now`.
+    fn keepalive_at(&self, state: &mut SessionState, now: Instant) -> Option<EthernetFrame> {
+        if !state.keepalive_due(now) {
+            return None;
+        }
+        let message = state.seal(&[], now)?;
+        Some(self.outer_frame(&message))
+    }
+}
+
+impl Processor for WireGuardEncrypt {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, frame: Self::Input) -> Option<Self::Output> {
+        self.encrypt_at(frame, Instant::now())
+    }
+}
+
+/// Decrypts WireGuard transport data messages from the peer of `session`, arriving in UDP over
+/// IPv4 to port 51820, or `port` if set, into the IP packets they carry, in frames with zero MAC
+/// addresses. Messages that aren't authentic, or for another session, or are replays, are
+/// dropped, as are keepalives once they've been noted.
+pub struct WireGuardDecrypt {
+    session: WireGuardSession,
+    port: u16,
+}
+
+impl WireGuardDecrypt {
+    pub fn new(session: WireGuardSession) -> Self {
+        WireGuardDecrypt {
+            session,
+            port: WIREGUARD_PORT,
+        }
+    }
+
+    /// Accepts messages on UDP port `port` rather than 51820.
+    pub fn port(self, port: u16) -> Self {
+        WireGuardDecrypt { port, ..self }
+    }
+
+    fn decrypt_at(&mut self, frame: EthernetFrame, now: Instant) -> Option<EthernetFrame> {
+        if frame.ether_type() != IPV4_ETHER_TYPE {
+            return None;
+        }
+        let packet = Ipv4Packet::try_from(frame).ok()?;
+        if packet.data[packet.layer3_offset + 9] != 17 || packet.fragment_offset() != 0 {
+            return None;
+        }
+        let udp = &packet.data[packet.payload_offset..];
+        if udp.len() < 8 || u16::from_be_bytes([udp[2], udp[3]]) != self.port {
+            return None;
+        }
+
+        let plaintext = self.session.state.lock().unwrap().open(&udp[8..], now)?;
+        // An empty message is a keepalive, and carries nothing.
+        let (len, ether_type) = ip_packet(&plaintext)?;
+        let mut data = vec![0; 14];
+        data[12..14].copy_from_slice(&ether_type.to_be_bytes());
+        data.extend_from_slice(&plaintext[..len]);
+        EthernetFrame::from_buffer(data.into(), 0).ok()
+    }
+}
+
+impl Processor for WireGuardDecrypt {
+    type Input = EthernetFrame;
+    type Output = EthernetFrame;
+
+    fn process(&mut self, frame: Self::Input) -> Option<Self::Output> {
+        self.decrypt_at(frame, Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::harness::initialize_runtime;
+    use futures::StreamExt;
+    use route_rs_packets::Ipv6Packet;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    const LOCAL: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(198, 51, 100, 1), 51820);
+    const PEER: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(203, 0, 113, 7), 51820);
+
+    fn keys() -> (WireGuardKeys, WireGuardKeys) {
+        let ours = WireGuardKeys {
+            local_index: 1,
+            remote_index: 2,
+            send_key: [0x11; 32],
+            recv_key: [0x22; 32],
+        };
+        let theirs = WireGuardKeys {
+            local_index: 2,
+            remote_index: 1,
+            send_key: [0x22; 32],
+            recv_key: [0x11; 32],
+        };
+        (ours, theirs)
+    }
+
+    fn inner_frame() -> EthernetFrame {
+        let mut packet = Ipv4Packet::empty();
+        packet.set_src_addr(Ipv4Addr::new(10, 8, 0, 2));
+        packet.set_dest_addr(Ipv4Addr::new(10, 8, 0, 1));
+        packet.set_payload(&[1, 2, 3, 4, 5]);
+        packet.set_checksum();
+        EthernetFrame::encap_ipv4(packet)
+    }
+
+    /// The transport data message in an outer frame.
+    fn message(outer: &EthernetFrame) -> Vec<u8> {
+        let packet = Ipv4Packet::try_from(outer.clone()).unwrap();
+        packet.payload()[8..].to_vec()
+    }
+
+    #[test]
+    fn round_trip() {
+        let (ours, theirs) = keys();
+        let mut encrypt = WireGuardEncrypt::new(WireGuardSession::new(ours), LOCAL, PEER)
+            .dst_mac(MacAddr::new([2, 0, 0, 0, 0, 1]));
+        let mut decrypt = WireGuardDecrypt::new(WireGuardSession::new(theirs));
+
+        let outer = encrypt.process(inner_frame()).unwrap();
+        assert_eq!(outer.dest_mac(), MacAddr::new([2, 0, 0, 0, 0, 1]));
+        let packet = Ipv4Packet::try_from(outer.clone()).unwrap();
+        assert_eq!(packet.src_addr(), *LOCAL.ip());
+        assert_eq!(packet.dest_addr(), *PEER.ip());
+
+        // The message is the header, then the padded packet sealed with the counter as nonce.
+        let message = message(&outer);
+        assert_eq!(
+            message[..16],
+            [4, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        let mut padded = inner_frame().data[14..].to_vec();
+        padded.resize(32, 0);
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(&[0x11; 32]));
+        assert_eq!(
+            message[16..],
+            cipher.encrypt(&nonce(0), &padded[..]).unwrap()[..]
+        );
+
+        assert_eq!(decrypt.process(outer), Some(inner_frame()));
+        let second = encrypt.process(inner_frame()).unwrap();
+        assert_eq!(message_counter(&second), 1);
+        assert_eq!(decrypt.process(second), Some(inner_frame()));
+    }
+
+    fn keepalive(encrypt: &WireGuardEncrypt, now: Instant) -> Option<EthernetFrame> {
+        let mut state = encrypt.session.state.lock().unwrap();
+        encrypt.endpoints.keepalive_at(&mut state, now)
+    }
+
+    fn message_counter(outer: &EthernetFrame) -> u64 {
+        u64::from_le_bytes(message(outer)[8..16].try_into().unwrap())
+    }
+
+    #[test]
+    fn carries_ipv6() {
+        let (ours, theirs) = keys();
+        let mut packet = Ipv6Packet::empty();
+        packet.set_src_addr(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 2));
+        packet.set_payload(&[9; 20]);
+        let inner = EthernetFrame::encap_ipv6(packet);
+
+        let outer = WireGuardEncrypt::new(WireGuardSession::new(ours), LOCAL, PEER)
+            .process(inner.clone())
+            .unwrap();
+        let decrypted = WireGuardDecrypt::new(WireGuardSession::new(theirs))
+            .process(outer)
+            .unwrap();
+        assert_eq!(decrypted, inner);
+    }
+
+    #[test]
+    fn rejects_replays_and_forgeries() {
+        let (ours, theirs) = keys();
+        let mut encrypt = WireGuardEncrypt::new(WireGuardSession::new(ours), LOCAL, PEER);
+        let mut decrypt = WireGuardDecrypt::new(WireGuardSession::new(theirs.clone()));
+
+        let first = encrypt.process(inner_frame()).unwrap();
+        let second = encrypt.process(inner_frame()).unwrap();
+        assert!(decrypt.process(second.clone()).is_some());
+        assert_eq!(decrypt.process(second), None);
+        // Reordered messages are still accepted, once.
+        assert!(decrypt.process(first.clone()).is_some());
+        assert_eq!(decrypt.process(first.clone()), None);
+
+        let mut forged = first;
+        let last = forged.data.len() - 1;
+        forged.data[last] ^= 1;
+        let mut fresh = WireGuardDecrypt::new(WireGuardSession::new(theirs));
+        assert_eq!(fresh.process(forged), None);
+
+        // Nor are messages for another session.
+        let mut other = keys().1;
+        other.local_index = 3;
+        let message = encrypt.process(inner_frame()).unwrap();
+        assert_eq!(
+            WireGuardDecrypt::new(WireGuardSession::new(other)).process(message),
+            None
+        );
+    }
+
+    #[test]
+    fn keepalives_when_due() {
+        let (ours, theirs) = keys();
+        let session = WireGuardSession::new(ours);
+        let mut encrypt = WireGuardEncrypt::new(session.clone(), LOCAL, PEER);
+        let mut decrypt = WireGuardDecrypt::new(session);
+        let mut peer = WireGuardEncrypt::new(WireGuardSession::new(theirs), PEER, LOCAL);
+        let now = Instant::now();
+
+        assert_eq!(keepalive(&encrypt, now), None);
+        assert_eq!(
+            decrypt.decrypt_at(peer.process(inner_frame()).unwrap(), now),
+            Some(inner_frame())
+        );
+        assert_eq!(keepalive(&encrypt, now + Duration::from_secs(9)), None);
+        let sent = keepalive(&encrypt, now + KEEPALIVE_TIMEOUT).unwrap();
+        // A keepalive is just the header and the tag.
+        assert_eq!(message(&sent).len(), HEADER_LEN + TAG_LEN);
+        assert_eq!(keepalive(&encrypt, now + Duration::from_secs(20)), None);
+
+        // Sending anything answers a received packet, so no keepalive is needed.
+        let later = now + Duration::from_secs(30);
+        decrypt.decrypt_at(peer.process(inner_frame()).unwrap(), later);
+        encrypt.encrypt_at(inner_frame(), later).unwrap();
+        assert_eq!(keepalive(&encrypt, later + KEEPALIVE_TIMEOUT), None);
+    }
+
+    #[test]
+    fn persistent_keepalives() {
+        let (ours, theirs) = keys();
+        let session = WireGuardSession::new(ours).persistent_keepalive(Duration::from_millis(200));
+        let encrypt = WireGuardEncrypt::new(session.clone(), LOCAL, PEER);
+        let mut decrypt = WireGuardDecrypt::new(WireGuardSession::new(theirs));
+
+        let runtime = initialize_runtime();
+        let keepalives = runtime.block_on(async move {
+            let (timer, keepalives) = encrypt.keepalives();
+            tokio::spawn(timer);
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            drop(encrypt);
+            drop(session);
+            keepalives.collect::<Vec<_>>().await
+        });
+
+        assert!(keepalives.len() >= 2 && keepalives.len() <= 4);
+        for keepalive in keepalives {
+            // Keepalives are authentic, but carry nothing.
+            assert_eq!(decrypt.process(keepalive), None);
+        }
+        assert!(decrypt
+            .session
+            .state
+            .lock()
+            .unwrap()
+            .last_received
+            .is_some());
+    }
+}
@@ -2,3 +2,8 @@
 pub mod test;
 
 pub mod runner;
+
+pub mod shutdown;
+
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
@@ -0,0 +1,286 @@
+use crate::link::registry::LinkRegistry;
+use crate::link::utils::stats::LinkStats;
+use futures::future;
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{header, Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::io;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+/// The content type of the Prometheus text exposition format.
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Serves the metrics of the links in `registry`, as rendered by `render`, to every HTTP GET of
+/// `/metrics` on `listener`, until the listener fails. Spawn it next to the router, for instance
+/// with `tokio::spawn(serve(TcpListener::bind("0.0.0.0:9100").await?, LinkRegistry::global()))`,
+/// and point a Prometheus scrape job at the port.
+pub async fn serve(listener: TcpListener, registry: LinkRegistry) -> io::Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let registry = registry.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |request| {
+                future::ready(Ok::<_, Infallible>(respond(&request, &registry)))
+            });
+            // A scraper that hangs up mid-request is no concern of the router's.
+            let _ = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+    }
+}
+
+fn respond(request: &Request<Incoming>, registry: &LinkRegistry) -> Response<Full<Bytes>> {
+    if request.method() != Method::GET || request.uri().path() != "/metrics" {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Full::default())
+            .unwrap();
+    }
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, CONTENT_TYPE)
+        .body(Full::new(Bytes::from(render(registry))))
+        .unwrap()
+}
+
+/// Renders the metrics of the links in `registry` in the Prometheus text exposition format, each
+/// sample labelled with the name of its link, and packets in and out also with the port they
+/// went through. Links are found by their `LinkStats`; other links have no metrics to render.
+/// Packets in and out are only counted by port once a link is built, so they have no samples for
+/// a link that is not.
+pub fn render(registry: &LinkRegistry) -> String {
+    let mut packets_in = Family::new(
+        "route_rs_link_packets_in_total",
+        "counter",
+        "Packets a link has taken from an ingressor.",
+    );
+    let mut packets_out = Family::new(
+        "route_rs_link_packets_out_total",
+        "counter",
+        "Packets an egressor of a link has given out.",
+    );
+    let mut bytes_out = Family::new(
+        "route_rs_link_bytes_out_total",
+        "counter",
+        "Bytes of the packets the egressors of a link have given out.",
+    );
+    let mut dropped = Family::new(
+        "route_rs_link_dropped_total",
+        "counter",
+        "Packets a link has dropped.",
+    );
+    let mut queue_depth = Family::new(
+        "route_rs_link_queue_depth",
+        "gauge",
+        "Packets waiting in the queues of a link.",
+    );
+    let mut queue_high_water = Family::new(
+        "route_rs_link_queue_high_water",
+        "gauge",
+        "The most packets that have waited in the queues of a link at once.",
+    );
+
+    for name in registry.names() {
+        if let Some(stats) = registry.get::<Arc<LinkStats>>(&name) {
+            for (port, packets) in stats.packets_in_by_port() {
+                packets_in.port_sample(&name, port, packets);
+            }
+            for (port, packets) in stats.packets_out_by_port() {
+                packets_out.port_sample(&name, port, packets);
+            }
+            bytes_out.sample(&name, stats.bytes_out());
+            dropped.sample(&name, stats.dropped());
+            queue_depth.sample(&name, stats.queue_depth());
+            queue_high_water.sample(&name, stats.queue_high_water());
+        }
+    }
+
+    let mut exposition = String::new();
+    for family in [
+        packets_in,
+        packets_out,
+        bytes_out,
+        dropped,
+        queue_depth,
+        queue_high_water,
+    ] {
+        family.render(&mut exposition);
+    }
+    exposition
+}
+
+/// A metric, with its samples in the exposition format.
+struct Family {
+    name: &'static str,
+    kind: &'static str,
+    help: &'static str,
+    samples: String,
+}
+
+impl Family {
+    fn new(name: &'static str, kind: &'static str, help: &'static str) -> Self {
+        Family {
+            name,
+            kind,
+            help,
+            samples: String::new(),
+        }
+    }
+
+    fn sample(&mut self, link: &str, value: u64) {
+        writeln!(
+            self.samples,
+            "{}{{link=\"{}\"}} {}",
+            self.name,
+            escape(link),
+            value
+        )
+        .unwrap();
+    }
+
+    fn port_sample(&mut self, link: &str, port: usize, value: u64) {
+        writeln!(
+            self.samples,
+            "{}{{link=\"{}\",port=\"{}\"}} {}",
+            self.name,
+            escape(link),
+            port,
+            value
+        )
+        .unwrap();
+    }
+
+    /// Appends the metric to `exposition`, unless it has no samples.
+    fn render(self, exposition: &mut String) {
+        if self.samples.is_empty() {
+            return;
+        }
+        writeln!(exposition, "# HELP {} {}", self.name, self.help).unwrap();
+        writeln!(exposition, "# TYPE {} {}", self.name, self.kind).unwrap();
+        exposition.push_str(&self.samples);
+    }
+}
+
+/// Escapes backslashes, quotes and newlines in a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::primitive::{CounterLink, QueueLink};
+    use crate::link::LinkBuilder;
+    use crate::link::ProcessLinkBuilder;
+    use crate::processor::Identity;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crate::utils::test::packet_generators::immediate_stream;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    /// Runs a queue link named `queue "a"` into a counter link named `counter`, registered in the
+    /// returned registry.
+    fn run_router() -> LinkRegistry {
+        let registry = LinkRegistry::new();
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (mut runnables, egressors) = QueueLink::new()
+                .ingressor(immediate_stream(vec![1, 2, 3]))
+                .processor(Identity::new())
+                .name("queue \"a\"")
+                .registry(&registry)
+                .build_link();
+            let (counter_runnables, egressors) = CounterLink::new()
+                .bytes(Box::new(|_| 10))
+                .name("counter")
+                .registry(&registry)
+                .ingressors(egressors)
+                .build_link();
+            runnables.extend(counter_runnables);
+            run_link((runnables, egressors)).await
+        });
+        assert_eq!(results[0], vec![1, 2, 3]);
+        registry
+    }
+
+    #[test]
+    fn renders_registry() {
+        let registry = run_router();
+        // How many packets queue up at once is up to the scheduler.
+        let high_water = registry
+            .get::<Arc<LinkStats>>("queue \"a\"")
+            .unwrap()
+            .queue_high_water();
+        assert!(high_water > 0);
+        assert_eq!(
+            render(&registry),
+            format!(
+                "# HELP route_rs_link_packets_in_total Packets a link has taken from an ingressor.\n\
+             # TYPE route_rs_link_packets_in_total counter\n\
+             route_rs_link_packets_in_total{{link=\"counter\",port=\"0\"}} 3\n\
+             route_rs_link_packets_in_total{{link=\"queue \\\"a\\\"\",port=\"0\"}} 3\n\
+             # HELP route_rs_link_packets_out_total Packets an egressor of a link has given out.\n\
+             # TYPE route_rs_link_packets_out_total counter\n\
+             route_rs_link_packets_out_total{{link=\"counter\",port=\"0\"}} 3\n\
+             route_rs_link_packets_out_total{{link=\"queue \\\"a\\\"\",port=\"0\"}} 3\n\
+             # HELP route_rs_link_bytes_out_total Bytes of the packets the egressors of a link have given out.\n\
+             # TYPE route_rs_link_bytes_out_total counter\n\
+             route_rs_link_bytes_out_total{{link=\"counter\"}} 30\n\
+             route_rs_link_bytes_out_total{{link=\"queue \\\"a\\\"\"}} 0\n\
+             # HELP route_rs_link_dropped_total Packets a link has dropped.\n\
+             # TYPE route_rs_link_dropped_total counter\n\
+             route_rs_link_dropped_total{{link=\"counter\"}} 0\n\
+             route_rs_link_dropped_total{{link=\"queue \\\"a\\\"\"}} 0\n\
+             # HELP route_rs_link_queue_depth Packets waiting in the queues of a link.\n\
+             # TYPE route_rs_link_queue_depth gauge\n\
+             route_rs_link_queue_depth{{link=\"counter\"}} 0\n\
+             route_rs_link_queue_depth{{link=\"queue \\\"a\\\"\"}} 0\n\
+             # HELP route_rs_link_queue_high_water The most packets that have waited in the queues of a link at once.\n\
+             # TYPE route_rs_link_queue_high_water gauge\n\
+             route_rs_link_queue_high_water{{link=\"counter\"}} 0\n\
+             route_rs_link_queue_high_water{{link=\"queue \\\"a\\\"\"}} {}\n",
+                high_water
+            )
+        );
+        assert_eq!(render(&LinkRegistry::new()), "");
+    }
+
+    #[test]
+    fn serves_metrics() {
+        let registry = run_router();
+        let runtime = initialize_runtime();
+        let (metrics, not_found) = runtime.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            tokio::spawn(serve(listener, registry));
+
+            let get = |path: &'static str| async move {
+                let mut stream = TcpStream::connect(address).await.unwrap();
+                let request = format!(
+                    "GET {} HTTP/1.1\r\nHost: router\r\nConnection: close\r\n\r\n",
+                    path
+                );
+                stream.write_all(request.as_bytes()).await.unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).await.unwrap();
+                response
+            };
+            (get("/metrics").await, get("/").await)
+        });
+
+        assert!(metrics.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(metrics.contains("content-type: text/plain; version=0.0.4; charset=utf-8\r\n"));
+        assert!(metrics.contains("route_rs_link_bytes_out_total{link=\"counter\"} 30\n"));
+        assert!(not_found.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::link::{Link, TokioRunnable};
+use crate::utils::shutdown::ShutdownHandle;
 use crate::utils::test::packet_collectors::ExhaustiveCollector;
 use crossbeam::crossbeam_channel;
 use std::fmt::Debug;
@@ -22,39 +23,61 @@ use tokio::task::JoinHandle;
 pub fn runner<OutputPacket: Debug + Send + Clone + 'static>(
     link_builder: fn() -> Link<OutputPacket>,
 ) -> Vec<Vec<OutputPacket>> {
-    let mut runtime = runtime::Builder::new()
-        .threaded_scheduler()
+    let runtime = runtime::Builder::new_multi_thread()
         .enable_all()
         .build()
         .unwrap();
 
+    runtime.block_on(run(link_builder()))
+}
+
+/// Runs the router as `runner` does, but also stops it gracefully on SIGINT, or Ctrl-C.
+/// `link_builder` is handed the `ShutdownHandle` to give the router's input links, which stop
+/// pulling packets at shutdown, so the packets already in the router make it through before this
+/// returns. The handle can be cloned to shut the router down by other means too.
+pub fn graceful_runner<OutputPacket: Debug + Send + Clone + 'static>(
+    link_builder: fn(&ShutdownHandle) -> Link<OutputPacket>,
+) -> Vec<Vec<OutputPacket>> {
+    let runtime = runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let shutdown = ShutdownHandle::new();
     runtime.block_on(async {
-        let (mut runnables, egressors) = link_builder();
-
-        let (mut consumers, receivers): (
-            Vec<TokioRunnable>,
-            Vec<crossbeam_channel::Receiver<OutputPacket>>,
-        ) = egressors
-            .into_iter()
-            .map(|egressor| {
-                let (s, r) = crossbeam_channel::unbounded::<OutputPacket>();
-                // TODO: Do we care about consumer IDs? Are they helpful to debug test examples?
-                let consumer: TokioRunnable = Box::new(ExhaustiveCollector::new(0, egressor, s));
-                (consumer, r)
-            })
-            .unzip();
-
-        runnables.append(&mut consumers);
-
-        let handles: Vec<JoinHandle<()>> = runnables.into_iter().map(tokio::spawn).collect();
-        // 🏃💨💨
-        for handle in handles {
-            handle.await.unwrap();
-        }
-
-        receivers
-            .into_iter()
-            .map(|receiver| receiver.iter().collect())
-            .collect()
+        tokio::spawn(shutdown.on_ctrl_c());
+        run(link_builder(&shutdown)).await
     })
 }
+
+async fn run<OutputPacket: Debug + Send + Clone + 'static>(
+    link: Link<OutputPacket>,
+) -> Vec<Vec<OutputPacket>> {
+    let (mut runnables, egressors) = link;
+
+    let (mut consumers, receivers): (
+        Vec<TokioRunnable>,
+        Vec<crossbeam_channel::Receiver<OutputPacket>>,
+    ) = egressors
+        .into_iter()
+        .map(|egressor| {
+            let (s, r) = crossbeam_channel::unbounded::<OutputPacket>();
+            // TODO: Do we care about consumer IDs? Are they helpful to debug test examples?
+            let consumer: TokioRunnable = Box::new(ExhaustiveCollector::new(0, egressor, s));
+            (consumer, r)
+        })
+        .unzip();
+
+    runnables.append(&mut consumers);
+
+    let handles: Vec<JoinHandle<()>> = runnables.into_iter().map(tokio::spawn).collect();
+    // 🏃💨💨
+    for handle in handles {
+        handle.await.unwrap();
+    }
+
+    receivers
+        .into_iter()
+        .map(|receiver| receiver.iter().collect())
+        .collect()
+}
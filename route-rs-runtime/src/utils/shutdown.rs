@@ -0,0 +1,230 @@
+use crate::link::{PacketStream, TokioRunnable};
+use futures::future::{self, Either};
+use futures::prelude::*;
+use futures::task::{AtomicWaker, Context, Poll};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Shuts a running router down gracefully. Once `shutdown` is called, input streams guarded by
+/// the handle, and `InputChannelLink`s given it, stop pulling packets and end. The end travels
+/// downstream as it does when input runs out: queues forward the packets they hold before ending,
+/// and every runnable completes, so the runtime driving them can return without losing packets
+/// already in the router. Clones of a handle share its signal.
+#[derive(Clone, Default)]
+pub struct ShutdownHandle {
+    state: Arc<ShutdownState>,
+}
+
+#[derive(Default)]
+struct ShutdownState {
+    triggered: AtomicBool,
+    /// The wakers of guarded streams and `Shutdown` futures, woken at shutdown, by the id of
+    /// their `Listener`, which removes its waker when dropped.
+    wakers: Mutex<HashMap<usize, Arc<AtomicWaker>>>,
+    next_id: AtomicUsize,
+}
+
+impl ShutdownHandle {
+    pub fn new() -> Self {
+        ShutdownHandle::default()
+    }
+
+    /// Signals shutdown, waking everything waiting on it. Later calls do nothing.
+    pub fn shutdown(&self) {
+        self.state.triggered.store(true, Ordering::SeqCst);
+        for waker in self.state.wakers.lock().unwrap().values() {
+            waker.wake();
+        }
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.state.triggered.load(Ordering::SeqCst)
+    }
+
+    /// Wraps an input stream of the router so that it ends at shutdown, without pulling any
+    /// more packets from `stream`.
+    pub fn guard<Packet: Send + 'static>(
+        &self,
+        stream: PacketStream<Packet>,
+    ) -> PacketStream<Packet> {
+        Box::new(Guarded {
+            stream,
+            listener: self.listener(),
+        })
+    }
+
+    /// Returns a future that completes at shutdown.
+    pub fn wait(&self) -> Shutdown {
+        Shutdown {
+            listener: self.listener(),
+        }
+    }
+
+    /// Returns a runnable that signals shutdown on SIGINT, or Ctrl-C. It completes at shutdown,
+    /// however that comes, so spawn it alongside the router's runnables rather than joining it
+    /// with them, lest a router that finishes on its own wait for it.
+    pub fn on_ctrl_c(&self) -> TokioRunnable {
+        let handle = self.clone();
+        Box::new(Box::pin(async move {
+            let ctrl_c = Box::pin(tokio::signal::ctrl_c());
+            if let Either::Left((Ok(()), _)) = future::select(ctrl_c, handle.wait()).await {
+                handle.shutdown();
+            }
+        }))
+    }
+
+    fn listener(&self) -> Listener {
+        let id = self.state.next_id.fetch_add(1, Ordering::Relaxed);
+        let waker = Arc::new(AtomicWaker::new());
+        self.state
+            .wakers
+            .lock()
+            .unwrap()
+            .insert(id, Arc::clone(&waker));
+        Listener {
+            state: Arc::clone(&self.state),
+            id,
+            waker,
+        }
+    }
+}
+
+/// One waiter on a `ShutdownHandle`, with its own waker.
+struct Listener {
+    state: Arc<ShutdownState>,
+    id: usize,
+    waker: Arc<AtomicWaker>,
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.state.wakers.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl Listener {
+    /// Ready once shutdown has been signalled, otherwise leaves our waker to be woken when it is.
+    /// We check again after leaving the waker, in case shutdown came in between.
+    fn poll(&self, cx: &mut Context) -> Poll<()> {
+        if self.state.triggered.load(Ordering::SeqCst) {
+            return Poll::Ready(());
+        }
+        self.waker.register(cx.waker());
+        if self.state.triggered.load(Ordering::SeqCst) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Future returned by `ShutdownHandle::wait`.
+pub struct Shutdown {
+    listener: Listener,
+}
+
+impl Future for Shutdown {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        self.listener.poll(cx)
+    }
+}
+
+struct Guarded<Packet> {
+    stream: PacketStream<Packet>,
+    listener: Listener,
+}
+
+impl<Packet> Unpin for Guarded<Packet> {}
+
+impl<Packet> Stream for Guarded<Packet> {
+    type Item = Packet;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if self.listener.poll(cx).is_ready() {
+            return Poll::Ready(None);
+        }
+        Pin::new(&mut self.stream).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::primitive::{InputChannelLink, QueueLink};
+    use crate::link::{LinkBuilder, ProcessLinkBuilder};
+    use crate::processor::Identity;
+    use crate::utils::test::harness::{initialize_runtime, run_link};
+    use crossbeam::crossbeam_channel;
+    use std::time::Duration;
+    use tokio::time::sleep;
+
+    #[test]
+    fn ends_input_and_drains_queues() {
+        let runtime = initialize_runtime();
+        let results = runtime.block_on(async {
+            let (send, recv) = crossbeam_channel::unbounded();
+            let handle = ShutdownHandle::new();
+
+            let (mut runnables, egressors) = InputChannelLink::new()
+                .channel(recv)
+                .shutdown(handle.clone())
+                .build_link();
+            let (mut queue_runnables, egressors) = QueueLink::new()
+                .ingressors(egressors)
+                .processor(Identity::new())
+                .queue_capacity(100)
+                .build_link();
+            runnables.append(&mut queue_runnables);
+
+            for packet in 0..10 {
+                send.send(packet).unwrap();
+            }
+            let shutdown = handle.clone();
+            tokio::spawn(async move {
+                sleep(Duration::from_millis(50)).await;
+                shutdown.shutdown();
+            });
+
+            // The sender is still open, so without shutdown the link would run forever.
+            let results = run_link((runnables, egressors)).await;
+            drop(send);
+            results
+        });
+        assert_eq!(results[0], (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn guards_streams_and_wakes_waiters() {
+        let runtime = initialize_runtime();
+        runtime.block_on(async {
+            let handle = ShutdownHandle::new();
+            let mut stream = handle.guard(Box::new(stream::pending::<i32>()));
+            let waiter = tokio::spawn(handle.wait());
+
+            assert!(futures::poll!(stream.next()).is_pending());
+            handle.shutdown();
+            assert!(handle.is_shutdown());
+            assert_eq!(stream.next().await, None);
+            waiter.await.unwrap();
+        });
+    }
+
+    #[test]
+    fn forgets_dropped_waiters() {
+        let handle = ShutdownHandle::new();
+        let stream = handle.guard(Box::new(stream::empty::<i32>()));
+        for _ in 0..10 {
+            drop(handle.wait());
+        }
+        let waiter = handle.wait();
+        assert_eq!(handle.state.wakers.lock().unwrap().len(), 2);
+
+        drop(stream);
+        drop(waiter);
+        assert!(handle.state.wakers.lock().unwrap().is_empty());
+    }
+}
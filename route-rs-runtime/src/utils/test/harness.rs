@@ -24,8 +24,7 @@ use tokio::runtime;
 /// through Tokio, and extracts the output packets into vectors representing egress streams.
 
 pub fn initialize_runtime() -> runtime::Runtime {
-    runtime::Builder::new()
-        .threaded_scheduler()
+    runtime::Builder::new_multi_thread()
         .enable_all()
         .build()
         .unwrap()
@@ -42,7 +42,7 @@ impl Stream for LinearIntervalGenerator {
     type Item = i32;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
-        ready!(Pin::new(&mut self.interval).poll_next(cx));
+        ready!(self.interval.poll_tick(cx));
         if self.seq_num as usize > self.iterations {
             Poll::Ready(None)
         } else {
@@ -97,7 +97,7 @@ where
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
         let interval_generator = Pin::into_inner(self);
-        ready!(Pin::new(&mut interval_generator.interval).poll_next(cx));
+        ready!(interval_generator.interval.poll_tick(cx));
         match interval_generator.packets.next() {
             Some(packet) => Poll::Ready(Some(packet)),
             None => Poll::Ready(None),